@@ -2,47 +2,146 @@
 rusted_chain - LangChain-style LLM framework in Rust
 """
 
+import atexit
+
 import rusted_chain.rusted_chain as _rust
 from .tool_helpers import tool, ToolAdapter, ensure_tool_wrapper
 create_agent = _rust.create_agent
+AgentBuilder = _rust.AgentBuilder
 AgentResponse = _rust.AgentResponse
 ToolCall = _rust.ToolCall
+ResponseMetadata = _rust.ResponseMetadata
+ToolUsage = _rust.ToolUsage
+Retries = _rust.Retries
+Estimate = _rust.Estimate
+Citation = _rust.Citation
+RunContext = _rust.RunContext
+TextStream = _rust.TextStream
+JsonStream = _rust.JsonStream
+EventStream = _rust.EventStream
+Workflow = _rust.Workflow
+WorkflowResult = _rust.WorkflowResult
+PromptTemplate = _rust.PromptTemplate
+PromptLibrary = _rust.PromptLibrary
+OutputTransform = _rust.OutputTransform
+MessageTransform = _rust.MessageTransform
+HmacSigner = _rust.HmacSigner
+Router = _rust.Router
+END = _rust.END
+Providers = _rust.Providers
+Roles = _rust.Roles
+map_reduce = _rust.map_reduce
+analyze_image = _rust.analyze_image
+classify = _rust.classify
+Classification = _rust.Classification
+moderate = _rust.moderate
+ModerationResult = _rust.ModerationResult
+validate_answer = _rust.validate_answer
+ValidationReport = _rust.ValidationReport
+QuoteCheck = _rust.QuoteCheck
+Video = _rust.Video
+LongRunningJob = _rust.LongRunningJob
+Assistant = _rust.Assistant
+AssistantThread = _rust.AssistantThread
+Session = _rust.Session
+DowngradePolicy = _rust.DowngradePolicy
+KeyPool = _rust.KeyPool
+LoadBalancer = _rust.LoadBalancer
+Tenant = _rust.Tenant
+MemoryPolicy = _rust.MemoryPolicy
+Replay = _rust.Replay
+PiiPolicy = _rust.PiiPolicy
+PromptCompressor = _rust.PromptCompressor
+ToolSerializer = _rust.ToolSerializer
+Artifact = _rust.Artifact
+Delegate = _rust.Delegate
+RecursiveCharacterTextSplitter = _rust.RecursiveCharacterTextSplitter
+TokenTextSplitter = _rust.TokenTextSplitter
+Document = _rust.Document
+Reranker = _rust.Reranker
+RerankResult = _rust.RerankResult
+Embeddings = _rust.Embeddings
+VectorStore = _rust.VectorStore
+parse_partial_json = _rust.parse_partial_json
+token_ids = _rust.token_ids
+estimate = _rust.estimate
+load_runs = _rust.load_runs
+export_finetune_jsonl = _rust.export_finetune_jsonl
+flush_tracking = _rust.flush_tracking
+add_usage_webhook = _rust.add_usage_webhook
+clear_usage_webhooks = _rust.clear_usage_webhooks
+flush_usage_webhooks = _rust.flush_usage_webhooks
+shutdown = _rust.shutdown
+configure_runtime = _rust.configure_runtime
+configure_circuit_breaker = _rust.configure_circuit_breaker
+circuit_breakers = _rust.circuit_breakers
+diagnostics = _rust.diagnostics
+get_metrics = _rust.get_metrics
+metrics_text = _rust.metrics_text
+start_metrics_server = _rust.start_metrics_server
+serve_traces = _rust.serve_traces
+ProviderError = _rust.ProviderError
+RateLimitError = _rust.RateLimitError
+OverloadedError = _rust.OverloadedError
+ContextLengthError = _rust.ContextLengthError
+ContentFilterError = _rust.ContentFilterError
+InvalidRequestError = _rust.InvalidRequestError
+ProviderUnavailableError = _rust.ProviderUnavailableError
+ToolLoopError = _rust.ToolLoopError
+BudgetExceededError = _rust.BudgetExceededError
+
+# Let calls in flight and background experiment-tracking uploads finish before exit.
+atexit.register(shutdown, 10.0)
 
 
 class AgentWrapper:
-    def __init__(self, rust_class, model=None, tools=None, api_key=None):
+    def __init__(self, rust_class, model=None, tools=None, api_key=None, **kwargs):
         if tools:
             tools = [ensure_tool_wrapper(t) for t in tools]
-        self._agent = rust_class(model, tools, api_key)
+        self._agent = rust_class(model, tools, api_key, **kwargs)
 
-    def invoke(self, query: str) -> AgentResponse:
+    def invoke(self, query: str, **kwargs) -> AgentResponse:
 
-        return self._agent.invoke(query)
+        return self._agent.invoke(query, **kwargs)
     
-    def run(self, query: str) -> str:
-        return self._agent.run(query)
+    def run(self, query: str, stream: bool = False, **kwargs):
+        return self._agent.run(query, stream=stream, **kwargs)
         
-    def add_tool(self, tool):
-        return self._agent.add_tool(ensure_tool_wrapper(tool))
+    def add_tool(self, tool, max_concurrency=None, rate_limit_per_min=None, strict=None, cache_ttl=None):
+        return self._agent.add_tool(ensure_tool_wrapper(tool, strict=strict),
+                                    max_concurrency=max_concurrency,
+                                    rate_limit_per_min=rate_limit_per_min,
+                                    cache_ttl=cache_ttl)
 
     def __getattr__(self, name):
         return getattr(self._agent, name)
 
 
 class GeminiModel(AgentWrapper):
-    def __init__(self, model=None, tools=None, api_key=None):
-        super().__init__(_rust.GeminiModel, model, tools, api_key)
+    def __init__(self, model=None, tools=None, api_key=None, **options):
+        super().__init__(_rust.GeminiModel, model, tools, api_key, **options)
 
 
 class OpenAIModel(AgentWrapper):
-    def __init__(self, model=None, tools=None, api_key=None):
-        super().__init__(_rust.OpenAIModel, model, tools, api_key)
+    def __init__(self, model=None, tools=None, api_key=None, user=None, safety_identifier=None,
+                 **options):
+        super().__init__(_rust.OpenAIModel, model, tools, api_key,
+                         user=user, safety_identifier=safety_identifier, **options)
 
 
 class ClaudeModel(AgentWrapper):
-    def __init__(self, model=None, tools=None, api_key=None):
-        super().__init__(_rust.ClaudeModel, model, tools, api_key)
+    def __init__(self, model=None, tools=None, api_key=None, **options):
+        super().__init__(_rust.ClaudeModel, model, tools, api_key, **options)
+
+
+class HuggingFaceModel(AgentWrapper):
+    def __init__(self, model=None, tools=None, api_key=None, endpoint=None, task="chat-completion",
+                 inference_provider=None, **options):
+        super().__init__(_rust.HuggingFaceModel, model, tools, api_key, endpoint=endpoint,
+                         task=task, inference_provider=inference_provider, **options)
 
 
-__all__ = ['GeminiModel', 'OpenAIModel', 'ClaudeModel', 'create_agent', 'AgentResponse', 'ToolCall', 
-           'tool', 'ToolAdapter']
+__all__ = ['GeminiModel', 'OpenAIModel', 'ClaudeModel', 'HuggingFaceModel', 'create_agent', 'AgentBuilder', 'AgentResponse', 'ToolCall', 'ResponseMetadata', 'ToolUsage', 'Retries', 'Citation', 'RunContext',
+           'TextStream', 'JsonStream', 'EventStream', 'Workflow', 'WorkflowResult', 'Router', 'END', 'Providers', 'Roles', 'PromptTemplate', 'PromptLibrary', 'OutputTransform', 'MessageTransform', 'HmacSigner', 'map_reduce', 'analyze_image', 'classify', 'Classification', 'moderate', 'ModerationResult', 'validate_answer', 'ValidationReport', 'QuoteCheck', 'Video', 'LongRunningJob', 'Assistant', 'AssistantThread', 'Session', 'DowngradePolicy', 'MemoryPolicy', 'KeyPool', 'LoadBalancer', 'Tenant', 'Replay', 'PiiPolicy', 'PromptCompressor', 'ToolSerializer', 'Artifact', 'Delegate', 'RecursiveCharacterTextSplitter', 'TokenTextSplitter', 'Document', 'Reranker', 'RerankResult', 'Embeddings', 'VectorStore', 'parse_partial_json', 'token_ids', 'estimate', 'Estimate', 'load_runs', 'export_finetune_jsonl', 'flush_tracking', 'add_usage_webhook', 'clear_usage_webhooks', 'flush_usage_webhooks', 'shutdown', 'configure_runtime', 'configure_circuit_breaker', 'circuit_breakers', 'diagnostics', 'get_metrics', 'metrics_text',
+           'start_metrics_server', 'serve_traces', 'tool', 'ToolAdapter', 'ProviderError', 'RateLimitError', 'OverloadedError',
+           'ContextLengthError', 'ContentFilterError', 'InvalidRequestError', 'ProviderUnavailableError', 'ToolLoopError', 'BudgetExceededError']
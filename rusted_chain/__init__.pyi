@@ -0,0 +1,184 @@
+# Generated by scripts/generate_stubs.py from the Rust sources; do not edit.
+
+from .tool_helpers import ToolAdapter as ToolAdapter, tool as tool
+from .rusted_chain import (
+    GeminiModel as GeminiModel,
+    OpenAIModel as OpenAIModel,
+    ClaudeModel as ClaudeModel,
+    HuggingFaceModel as HuggingFaceModel,
+    create_agent as create_agent,
+    AgentBuilder as AgentBuilder,
+    AgentResponse as AgentResponse,
+    ToolCall as ToolCall,
+    ResponseMetadata as ResponseMetadata,
+    ToolUsage as ToolUsage,
+    Retries as Retries,
+    Citation as Citation,
+    RunContext as RunContext,
+    TextStream as TextStream,
+    JsonStream as JsonStream,
+    EventStream as EventStream,
+    Workflow as Workflow,
+    WorkflowResult as WorkflowResult,
+    Router as Router,
+    END as END,
+    Providers as Providers,
+    Roles as Roles,
+    PromptTemplate as PromptTemplate,
+    PromptLibrary as PromptLibrary,
+    OutputTransform as OutputTransform,
+    MessageTransform as MessageTransform,
+    HmacSigner as HmacSigner,
+    map_reduce as map_reduce,
+    analyze_image as analyze_image,
+    classify as classify,
+    Classification as Classification,
+    moderate as moderate,
+    ModerationResult as ModerationResult,
+    validate_answer as validate_answer,
+    ValidationReport as ValidationReport,
+    QuoteCheck as QuoteCheck,
+    Video as Video,
+    LongRunningJob as LongRunningJob,
+    Assistant as Assistant,
+    AssistantThread as AssistantThread,
+    Session as Session,
+    DowngradePolicy as DowngradePolicy,
+    MemoryPolicy as MemoryPolicy,
+    KeyPool as KeyPool,
+    LoadBalancer as LoadBalancer,
+    Tenant as Tenant,
+    Replay as Replay,
+    PiiPolicy as PiiPolicy,
+    PromptCompressor as PromptCompressor,
+    ToolSerializer as ToolSerializer,
+    Artifact as Artifact,
+    Delegate as Delegate,
+    RecursiveCharacterTextSplitter as RecursiveCharacterTextSplitter,
+    TokenTextSplitter as TokenTextSplitter,
+    Document as Document,
+    Reranker as Reranker,
+    RerankResult as RerankResult,
+    Embeddings as Embeddings,
+    VectorStore as VectorStore,
+    parse_partial_json as parse_partial_json,
+    token_ids as token_ids,
+    estimate as estimate,
+    Estimate as Estimate,
+    load_runs as load_runs,
+    export_finetune_jsonl as export_finetune_jsonl,
+    flush_tracking as flush_tracking,
+    add_usage_webhook as add_usage_webhook,
+    clear_usage_webhooks as clear_usage_webhooks,
+    flush_usage_webhooks as flush_usage_webhooks,
+    shutdown as shutdown,
+    configure_runtime as configure_runtime,
+    configure_circuit_breaker as configure_circuit_breaker,
+    circuit_breakers as circuit_breakers,
+    diagnostics as diagnostics,
+    get_metrics as get_metrics,
+    metrics_text as metrics_text,
+    start_metrics_server as start_metrics_server,
+    serve_traces as serve_traces,
+    ProviderError as ProviderError,
+    RateLimitError as RateLimitError,
+    OverloadedError as OverloadedError,
+    ContextLengthError as ContextLengthError,
+    ContentFilterError as ContentFilterError,
+    InvalidRequestError as InvalidRequestError,
+    ProviderUnavailableError as ProviderUnavailableError,
+    ToolLoopError as ToolLoopError,
+    BudgetExceededError as BudgetExceededError,
+)
+
+__all__ = [
+    "GeminiModel",
+    "OpenAIModel",
+    "ClaudeModel",
+    "HuggingFaceModel",
+    "create_agent",
+    "AgentBuilder",
+    "AgentResponse",
+    "ToolCall",
+    "ResponseMetadata",
+    "ToolUsage",
+    "Retries",
+    "Citation",
+    "RunContext",
+    "TextStream",
+    "JsonStream",
+    "EventStream",
+    "Workflow",
+    "WorkflowResult",
+    "Router",
+    "END",
+    "Providers",
+    "Roles",
+    "PromptTemplate",
+    "PromptLibrary",
+    "OutputTransform",
+    "MessageTransform",
+    "HmacSigner",
+    "map_reduce",
+    "analyze_image",
+    "classify",
+    "Classification",
+    "moderate",
+    "ModerationResult",
+    "validate_answer",
+    "ValidationReport",
+    "QuoteCheck",
+    "Video",
+    "LongRunningJob",
+    "Assistant",
+    "AssistantThread",
+    "Session",
+    "DowngradePolicy",
+    "MemoryPolicy",
+    "KeyPool",
+    "LoadBalancer",
+    "Tenant",
+    "Replay",
+    "PiiPolicy",
+    "PromptCompressor",
+    "ToolSerializer",
+    "Artifact",
+    "Delegate",
+    "RecursiveCharacterTextSplitter",
+    "TokenTextSplitter",
+    "Document",
+    "Reranker",
+    "RerankResult",
+    "Embeddings",
+    "VectorStore",
+    "parse_partial_json",
+    "token_ids",
+    "estimate",
+    "Estimate",
+    "load_runs",
+    "export_finetune_jsonl",
+    "flush_tracking",
+    "add_usage_webhook",
+    "clear_usage_webhooks",
+    "flush_usage_webhooks",
+    "shutdown",
+    "configure_runtime",
+    "configure_circuit_breaker",
+    "circuit_breakers",
+    "diagnostics",
+    "get_metrics",
+    "metrics_text",
+    "start_metrics_server",
+    "serve_traces",
+    "tool",
+    "ToolAdapter",
+    "ProviderError",
+    "RateLimitError",
+    "OverloadedError",
+    "ContextLengthError",
+    "ContentFilterError",
+    "InvalidRequestError",
+    "ProviderUnavailableError",
+    "ToolLoopError",
+    "BudgetExceededError",
+]
@@ -0,0 +1,25 @@
+"""
+Document loaders for PDF, DOCX, HTML, CSV and Markdown files.
+
+Available when rusted_chain is built with the `loaders` feature.
+"""
+
+import rusted_chain.rusted_chain as _rust
+
+try:
+    _loaders = _rust.loaders
+except AttributeError:
+    raise ImportError(
+        "rusted_chain was built without document loaders; "
+        "rebuild with `maturin build --features loaders`"
+    ) from None
+
+load = _loaders.load
+load_pdf = _loaders.load_pdf
+load_docx = _loaders.load_docx
+load_html = _loaders.load_html
+load_csv = _loaders.load_csv
+load_markdown = _loaders.load_markdown
+html_to_text = _loaders.html_to_text
+
+__all__ = ['load', 'load_pdf', 'load_docx', 'load_html', 'load_csv', 'load_markdown', 'html_to_text']
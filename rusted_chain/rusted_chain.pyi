@@ -0,0 +1,1939 @@
+# Generated by scripts/generate_stubs.py from the Rust sources; do not edit.
+
+import os
+import pathlib
+from types import ModuleType
+from typing import Any
+
+
+def create_agent(model: str, tools: list[Any] | None = None, api_key: Any = None, provider: str | None = None, **options: Any) -> Any:
+    ...
+
+
+class AgentBuilder:
+    """Builds an agent one setting at a time:
+    `AgentBuilder().model("gpt-4o").system(...).tool(f).temperature(0.3).build()`.
+    `build()` returns the same model class `create_agent` does. Settings are checked
+    there, so a mistake is reported once, before any request is made; a builder can be
+    built from more than once.
+    """
+    def __init__(self) -> None:
+        ...
+    def model(self, model: str) -> AgentBuilder:
+        """The model to run, e.g. `"gpt-4o"`. The provider is picked from the name unless
+        `provider()` names it.
+        """
+        ...
+    def provider(self, provider: str) -> AgentBuilder:
+        """The provider, as `create_agent(provider=...)` takes it (see `Providers`)."""
+        ...
+    def api_key(self, api_key: Any) -> AgentBuilder:
+        """An API key, `KeyPool` or token callback, instead of the provider's variable."""
+        ...
+    def tool(self, tool: Any, strict: bool | None = None, max_concurrency: int | None = None, rate_limit_per_min: int | None = None, cache_ttl: float | None = None) -> AgentBuilder:
+        """Add a tool: a function, LangChain tool or tool object such as `Delegate`.
+        `strict`, `max_concurrency`, `rate_limit_per_min` and `cache_ttl` are as for
+        `add_tool`.
+        """
+        ...
+    def tools(self, tools: list[Any]) -> AgentBuilder:
+        """Add several tools."""
+        ...
+    def system(self, system: str) -> AgentBuilder:
+        """The system prompt."""
+        ...
+    def instructions(self, instructions: str) -> AgentBuilder:
+        """The agent's task, sent after the system prompt with tool-use guidance."""
+        ...
+    def language(self, language: str) -> AgentBuilder:
+        """The language answers are given in, as an ISO 639-1 code or name."""
+        ...
+    def temperature(self, temperature: float) -> AgentBuilder:
+        """Sampling temperature, from 0 to 2 (to 1 for Claude)."""
+        ...
+    def max_retries(self, max_retries: int) -> AgentBuilder:
+        """How many times a failed provider request is retried."""
+        ...
+    def option(self, name: str, value: Any) -> AgentBuilder:
+        """Set any keyword option `create_agent` takes, e.g. `option("prefill", "{")`."""
+        ...
+    def options(self, **options: Any) -> AgentBuilder:
+        """Set several keyword options at once."""
+        ...
+    def build(self) -> Any:
+        """Check the settings and make the agent. Raises `ValueError` or `TypeError` for a
+        missing model, a tool added twice, or an option that is unknown, out of range or
+        unsupported by the provider.
+        """
+        ...
+    def __repr__(self) -> str:
+        ...
+
+
+class GeminiModel:
+    def __init__(self, model: str | None = None, tools: list[Any] | None = None, api_key: Any = None, **options: Any) -> None:
+        ...
+    def add_tool(self, tool: Any, max_concurrency: int | None = None, rate_limit_per_min: int | None = None, cache_ttl: float | None = None) -> None:
+        """Add a tool, optionally capping how many calls to it run at once and how many
+        start per minute across every run of this model. With `cache_ttl`, results are
+        reused for `cache_ttl` seconds by calls with the same arguments, in any run.
+        """
+        ...
+    def invoke(self, query: str, tags: list[str] | None = None, metadata: dict[str, Any] | None = None, context: Tenant | None = None, tool_model: str | None = None, idempotency_key: str | None = None, dry_run: bool = False) -> Any:
+        """Invoke the model.
+        If tools are provided, this will run the agent loop (execute tools) until a final answer is reached.
+        If no tools are provided, it runs a single-shot completion.
+        `tags` and `metadata` label the run in usage records, audit logs and trackers.
+        `context` is a `Tenant` whose keys, budget and limits apply to this call.
+        `tool_model` overrides the agent's `tool_model` for this call.
+        Calls given the same `idempotency_key` send the same keys and share remembered answers.
+        `dry_run=True` returns the request the call would send, as a dict, without sending it.
+        """
+        ...
+    def invoke_with_files(self, query: str, files: Any, tags: list[str] | None = None, metadata: dict[str, Any] | None = None) -> AgentResponse:
+        """Invoke the model with images attached: file paths, bytes or URLs. Gemini also
+        takes videos, including `Video` objects carrying sampling options. This is a
+        single turn; tools are not offered.
+        """
+        ...
+    def warmup(self, prime: bool = False) -> float:
+        """Connect to the provider ahead of the first real request, so it doesn't pay for
+        DNS, TCP and TLS setup; the connection is kept for later calls. With
+        `prime=True`, a one-token request is sent as well. Returns the seconds taken.
+        """
+        ...
+    def invoke_raw(self, payload: dict[str, Any], path: str | None = None) -> Any:
+        """Send `payload`, a request body as the provider's API takes it, and return the
+        response as a dict, for parameters this library doesn't expose. Streaming isn't
+        supported. The model is filled in if missing, and for Claude `max_tokens` too.
+        `path` is relative to the base URL: `/chat/completions` by default, or
+        `/v1/messages` for Claude. For Gemini it is the model method,
+        `generateContent` by default, always sent over REST. Hugging Face's
+        text-generation task always posts to the generation URL.
+        """
+        ...
+    def tool_usage(self, reset: bool = False) -> dict[str, ToolUsage]:
+        """`ToolUsage` per tool over every run of this agent: calls, failure rate, average
+        latency and result size. `reset=True` starts the counts over.
+        """
+        ...
+    def submit_long_running(self, query: str, tags: list[str] | None = None, metadata: dict[str, Any] | None = None) -> LongRunningJob:
+        """Submit `query` as a long-running request and return a `LongRunningJob` straight
+        away. This is a single turn; tools are not offered. OpenAI runs it as a
+        background response, with no connection held open, which suits slow reasoning
+        models; collect it later with `result()`, here or in another process via
+        `get_long_running(job.id)`. Other providers run it in the background here.
+        """
+        ...
+    def ainvoke(self, query: str, tags: list[str] | None = None, metadata: dict[str, Any] | None = None, context: Tenant | None = None, tool_model: str | None = None, idempotency_key: str | None = None) -> Any:
+        """`invoke` as a coroutine for asyncio. The call runs on a thread of its own, so
+        awaiting it doesn't block the event loop.
+        """
+        ...
+    def arun(self, query: str, tags: list[str] | None = None, metadata: dict[str, Any] | None = None, context: Tenant | None = None, tool_model: str | None = None, idempotency_key: str | None = None) -> Any:
+        """`run` as a coroutine for asyncio, returning the final answer."""
+        ...
+    def stream(self, query: str, tags: list[str] | None = None, metadata: dict[str, Any] | None = None, context: Tenant | None = None, tool_model: str | None = None, idempotency_key: str | None = None) -> TextStream:
+        """Run the agent and return an iterator of text chunks as the final answer is
+        generated; the same as `run(query, stream=True)`.
+        """
+        ...
+    def run(self, query: str, stream: bool = False, tags: list[str] | None = None, metadata: dict[str, Any] | None = None, context: Tenant | None = None, tool_model: str | None = None, idempotency_key: str | None = None, dry_run: bool = False) -> Any:
+        """Run the agent and return the final answer.
+        With `stream=True`, returns an iterator of text chunks instead; tools are still
+        executed between turns and the final answer is streamed as it is generated.
+        """
+        ...
+
+
+class OpenAIModel:
+    """`user` and `safety_identifier` apply to every call, and each call that runs the model
+    can override them for that call only.
+    """
+    def __init__(self, model: str | None = None, tools: list[Any] | None = None, api_key: Any = None, user: str | None = None, safety_identifier: str | None = None, **options: Any) -> None:
+        ...
+    def get_long_running(self, job_id: str) -> LongRunningJob:
+        """Pick up a background response by id, e.g. one submitted by another process."""
+        ...
+    def create_assistant(self, name: str | None = None) -> Assistant:
+        """Create an OpenAI assistant (Assistants API) with this model, system prompt and
+        tools. Conversations with it run on server-side threads.
+        """
+        ...
+    def get_assistant(self, assistant_id: str) -> Assistant:
+        """Use an existing assistant by id. This model's tools are executed when its runs
+        call them, and its system prompt is added to every run.
+        """
+        ...
+    def add_tool(self, tool: Any, max_concurrency: int | None = None, rate_limit_per_min: int | None = None, cache_ttl: float | None = None) -> None:
+        """Add a tool, optionally capping how many calls to it run at once and how many
+        start per minute across every run of this model. With `cache_ttl`, results are
+        reused for `cache_ttl` seconds by calls with the same arguments, in any run.
+        """
+        ...
+    def invoke(self, query: str, user: str | None = None, safety_identifier: str | None = None, tags: list[str] | None = None, metadata: dict[str, Any] | None = None, context: Tenant | None = None, tool_model: str | None = None, idempotency_key: str | None = None, dry_run: bool = False) -> Any:
+        """Invoke the model.
+        If tools are provided, this will run the agent loop (execute tools) until a final answer is reached.
+        If no tools are provided, it runs a single-shot completion.
+        `tags` and `metadata` label the run in usage records, audit logs and trackers.
+        `context` is a `Tenant` whose keys, budget and limits apply to this call.
+        `tool_model` overrides the agent's `tool_model` for this call.
+        Calls given the same `idempotency_key` send the same keys and share remembered answers.
+        `dry_run=True` returns the request the call would send, as a dict, without sending it.
+        """
+        ...
+    def invoke_with_files(self, query: str, files: Any, user: str | None = None, safety_identifier: str | None = None, tags: list[str] | None = None, metadata: dict[str, Any] | None = None) -> AgentResponse:
+        """Invoke the model with images attached: file paths, bytes or URLs. Gemini also
+        takes videos, including `Video` objects carrying sampling options. This is a
+        single turn; tools are not offered.
+        """
+        ...
+    def warmup(self, prime: bool = False) -> float:
+        """Connect to the provider ahead of the first real request, so it doesn't pay for
+        DNS, TCP and TLS setup; the connection is kept for later calls. With
+        `prime=True`, a one-token request is sent as well. Returns the seconds taken.
+        """
+        ...
+    def invoke_raw(self, payload: dict[str, Any], path: str | None = None) -> Any:
+        """Send `payload`, a request body as the provider's API takes it, and return the
+        response as a dict, for parameters this library doesn't expose. Streaming isn't
+        supported. The model is filled in if missing, and for Claude `max_tokens` too.
+        `path` is relative to the base URL: `/chat/completions` by default, or
+        `/v1/messages` for Claude. For Gemini it is the model method,
+        `generateContent` by default, always sent over REST. Hugging Face's
+        text-generation task always posts to the generation URL.
+        """
+        ...
+    def tool_usage(self, reset: bool = False) -> dict[str, ToolUsage]:
+        """`ToolUsage` per tool over every run of this agent: calls, failure rate, average
+        latency and result size. `reset=True` starts the counts over.
+        """
+        ...
+    def submit_long_running(self, query: str, user: str | None = None, safety_identifier: str | None = None, tags: list[str] | None = None, metadata: dict[str, Any] | None = None) -> LongRunningJob:
+        """Submit `query` as a long-running request and return a `LongRunningJob` straight
+        away. This is a single turn; tools are not offered. OpenAI runs it as a
+        background response, with no connection held open, which suits slow reasoning
+        models; collect it later with `result()`, here or in another process via
+        `get_long_running(job.id)`. Other providers run it in the background here.
+        """
+        ...
+    def ainvoke(self, query: str, user: str | None = None, safety_identifier: str | None = None, tags: list[str] | None = None, metadata: dict[str, Any] | None = None, context: Tenant | None = None, tool_model: str | None = None, idempotency_key: str | None = None) -> Any:
+        """`invoke` as a coroutine for asyncio. The call runs on a thread of its own, so
+        awaiting it doesn't block the event loop.
+        """
+        ...
+    def arun(self, query: str, user: str | None = None, safety_identifier: str | None = None, tags: list[str] | None = None, metadata: dict[str, Any] | None = None, context: Tenant | None = None, tool_model: str | None = None, idempotency_key: str | None = None) -> Any:
+        """`run` as a coroutine for asyncio, returning the final answer."""
+        ...
+    def stream(self, query: str, user: str | None = None, safety_identifier: str | None = None, tags: list[str] | None = None, metadata: dict[str, Any] | None = None, context: Tenant | None = None, tool_model: str | None = None, idempotency_key: str | None = None) -> TextStream:
+        """Run the agent and return an iterator of text chunks as the final answer is
+        generated; the same as `run(query, stream=True)`.
+        """
+        ...
+    def run(self, query: str, stream: bool = False, user: str | None = None, safety_identifier: str | None = None, tags: list[str] | None = None, metadata: dict[str, Any] | None = None, context: Tenant | None = None, tool_model: str | None = None, idempotency_key: str | None = None, dry_run: bool = False) -> Any:
+        """Run the agent and return the final answer.
+        With `stream=True`, returns an iterator of text chunks instead; tools are still
+        executed between turns and the final answer is streamed as it is generated.
+        """
+        ...
+
+
+class ClaudeModel:
+    def __init__(self, model: str | None = None, tools: list[Any] | None = None, api_key: Any = None, **options: Any) -> None:
+        ...
+    def add_tool(self, tool: Any, max_concurrency: int | None = None, rate_limit_per_min: int | None = None, cache_ttl: float | None = None) -> None:
+        """Add a tool, optionally capping how many calls to it run at once and how many
+        start per minute across every run of this model. With `cache_ttl`, results are
+        reused for `cache_ttl` seconds by calls with the same arguments, in any run.
+        """
+        ...
+    def invoke(self, query: str, tags: list[str] | None = None, metadata: dict[str, Any] | None = None, context: Tenant | None = None, tool_model: str | None = None, idempotency_key: str | None = None, dry_run: bool = False) -> Any:
+        """Invoke the model.
+        If tools are provided, this will run the agent loop (execute tools) until a final answer is reached.
+        If no tools are provided, it runs a single-shot completion.
+        `tags` and `metadata` label the run in usage records, audit logs and trackers.
+        `context` is a `Tenant` whose keys, budget and limits apply to this call.
+        `tool_model` overrides the agent's `tool_model` for this call.
+        Calls given the same `idempotency_key` send the same keys and share remembered answers.
+        `dry_run=True` returns the request the call would send, as a dict, without sending it.
+        """
+        ...
+    def invoke_with_files(self, query: str, files: Any, tags: list[str] | None = None, metadata: dict[str, Any] | None = None) -> AgentResponse:
+        """Invoke the model with images attached: file paths, bytes or URLs. Gemini also
+        takes videos, including `Video` objects carrying sampling options. This is a
+        single turn; tools are not offered.
+        """
+        ...
+    def warmup(self, prime: bool = False) -> float:
+        """Connect to the provider ahead of the first real request, so it doesn't pay for
+        DNS, TCP and TLS setup; the connection is kept for later calls. With
+        `prime=True`, a one-token request is sent as well. Returns the seconds taken.
+        """
+        ...
+    def invoke_raw(self, payload: dict[str, Any], path: str | None = None) -> Any:
+        """Send `payload`, a request body as the provider's API takes it, and return the
+        response as a dict, for parameters this library doesn't expose. Streaming isn't
+        supported. The model is filled in if missing, and for Claude `max_tokens` too.
+        `path` is relative to the base URL: `/chat/completions` by default, or
+        `/v1/messages` for Claude. For Gemini it is the model method,
+        `generateContent` by default, always sent over REST. Hugging Face's
+        text-generation task always posts to the generation URL.
+        """
+        ...
+    def tool_usage(self, reset: bool = False) -> dict[str, ToolUsage]:
+        """`ToolUsage` per tool over every run of this agent: calls, failure rate, average
+        latency and result size. `reset=True` starts the counts over.
+        """
+        ...
+    def submit_long_running(self, query: str, tags: list[str] | None = None, metadata: dict[str, Any] | None = None) -> LongRunningJob:
+        """Submit `query` as a long-running request and return a `LongRunningJob` straight
+        away. This is a single turn; tools are not offered. OpenAI runs it as a
+        background response, with no connection held open, which suits slow reasoning
+        models; collect it later with `result()`, here or in another process via
+        `get_long_running(job.id)`. Other providers run it in the background here.
+        """
+        ...
+    def ainvoke(self, query: str, tags: list[str] | None = None, metadata: dict[str, Any] | None = None, context: Tenant | None = None, tool_model: str | None = None, idempotency_key: str | None = None) -> Any:
+        """`invoke` as a coroutine for asyncio. The call runs on a thread of its own, so
+        awaiting it doesn't block the event loop.
+        """
+        ...
+    def arun(self, query: str, tags: list[str] | None = None, metadata: dict[str, Any] | None = None, context: Tenant | None = None, tool_model: str | None = None, idempotency_key: str | None = None) -> Any:
+        """`run` as a coroutine for asyncio, returning the final answer."""
+        ...
+    def stream(self, query: str, tags: list[str] | None = None, metadata: dict[str, Any] | None = None, context: Tenant | None = None, tool_model: str | None = None, idempotency_key: str | None = None) -> TextStream:
+        """Run the agent and return an iterator of text chunks as the final answer is
+        generated; the same as `run(query, stream=True)`.
+        """
+        ...
+    def run(self, query: str, stream: bool = False, tags: list[str] | None = None, metadata: dict[str, Any] | None = None, context: Tenant | None = None, tool_model: str | None = None, idempotency_key: str | None = None, dry_run: bool = False) -> Any:
+        """Run the agent and return the final answer.
+        With `stream=True`, returns an iterator of text chunks instead; tools are still
+        executed between turns and the final answer is streamed as it is generated.
+        """
+        ...
+
+
+class HuggingFaceModel:
+    def __init__(self, model: str | None = None, tools: list[Any] | None = None, api_key: Any = None, endpoint: str | None = None, task: str = "chat-completion", inference_provider: str | None = None, **options: Any) -> None:
+        ...
+    def add_tool(self, tool: Any, max_concurrency: int | None = None, rate_limit_per_min: int | None = None, cache_ttl: float | None = None) -> None:
+        """Add a tool, optionally capping how many calls to it run at once and how many
+        start per minute across every run of this model. With `cache_ttl`, results are
+        reused for `cache_ttl` seconds by calls with the same arguments, in any run.
+        """
+        ...
+    def invoke(self, query: str, tags: list[str] | None = None, metadata: dict[str, Any] | None = None, context: Tenant | None = None, tool_model: str | None = None, idempotency_key: str | None = None, dry_run: bool = False) -> Any:
+        """Invoke the model.
+        If tools are provided, this will run the agent loop (execute tools) until a final answer is reached.
+        If no tools are provided, it runs a single-shot completion.
+        `tags` and `metadata` label the run in usage records, audit logs and trackers.
+        `context` is a `Tenant` whose keys, budget and limits apply to this call.
+        `tool_model` overrides the agent's `tool_model` for this call.
+        Calls given the same `idempotency_key` send the same keys and share remembered answers.
+        `dry_run=True` returns the request the call would send, as a dict, without sending it.
+        """
+        ...
+    def invoke_with_files(self, query: str, files: Any, tags: list[str] | None = None, metadata: dict[str, Any] | None = None) -> AgentResponse:
+        """Invoke the model with images attached: file paths, bytes or URLs. Gemini also
+        takes videos, including `Video` objects carrying sampling options. This is a
+        single turn; tools are not offered.
+        """
+        ...
+    def warmup(self, prime: bool = False) -> float:
+        """Connect to the provider ahead of the first real request, so it doesn't pay for
+        DNS, TCP and TLS setup; the connection is kept for later calls. With
+        `prime=True`, a one-token request is sent as well. Returns the seconds taken.
+        """
+        ...
+    def invoke_raw(self, payload: dict[str, Any], path: str | None = None) -> Any:
+        """Send `payload`, a request body as the provider's API takes it, and return the
+        response as a dict, for parameters this library doesn't expose. Streaming isn't
+        supported. The model is filled in if missing, and for Claude `max_tokens` too.
+        `path` is relative to the base URL: `/chat/completions` by default, or
+        `/v1/messages` for Claude. For Gemini it is the model method,
+        `generateContent` by default, always sent over REST. Hugging Face's
+        text-generation task always posts to the generation URL.
+        """
+        ...
+    def tool_usage(self, reset: bool = False) -> dict[str, ToolUsage]:
+        """`ToolUsage` per tool over every run of this agent: calls, failure rate, average
+        latency and result size. `reset=True` starts the counts over.
+        """
+        ...
+    def submit_long_running(self, query: str, tags: list[str] | None = None, metadata: dict[str, Any] | None = None) -> LongRunningJob:
+        """Submit `query` as a long-running request and return a `LongRunningJob` straight
+        away. This is a single turn; tools are not offered. OpenAI runs it as a
+        background response, with no connection held open, which suits slow reasoning
+        models; collect it later with `result()`, here or in another process via
+        `get_long_running(job.id)`. Other providers run it in the background here.
+        """
+        ...
+    def ainvoke(self, query: str, tags: list[str] | None = None, metadata: dict[str, Any] | None = None, context: Tenant | None = None, tool_model: str | None = None, idempotency_key: str | None = None) -> Any:
+        """`invoke` as a coroutine for asyncio. The call runs on a thread of its own, so
+        awaiting it doesn't block the event loop.
+        """
+        ...
+    def arun(self, query: str, tags: list[str] | None = None, metadata: dict[str, Any] | None = None, context: Tenant | None = None, tool_model: str | None = None, idempotency_key: str | None = None) -> Any:
+        """`run` as a coroutine for asyncio, returning the final answer."""
+        ...
+    def stream(self, query: str, tags: list[str] | None = None, metadata: dict[str, Any] | None = None, context: Tenant | None = None, tool_model: str | None = None, idempotency_key: str | None = None) -> TextStream:
+        """Run the agent and return an iterator of text chunks as the final answer is
+        generated; the same as `run(query, stream=True)`.
+        """
+        ...
+    def run(self, query: str, stream: bool = False, tags: list[str] | None = None, metadata: dict[str, Any] | None = None, context: Tenant | None = None, tool_model: str | None = None, idempotency_key: str | None = None, dry_run: bool = False) -> Any:
+        """Run the agent and return the final answer.
+        With `stream=True`, returns an iterator of text chunks instead; tools are still
+        executed between turns and the final answer is streamed as it is generated.
+        """
+        ...
+
+
+class AgentResponse:
+    @property
+    def metadata(self) -> ResponseMetadata:
+        ...
+    @property
+    def is_text(self) -> bool:
+        ...
+    @property
+    def is_tool_call(self) -> bool:
+        ...
+    @property
+    def text(self) -> str:
+        ...
+    def code_blocks(self) -> list[tuple[str | None, str]]:
+        """The fenced code blocks in a text answer as `(language, code)` tuples, in order.
+        The language is lowercased, or `None` for an untagged block.
+        """
+        ...
+    def to_run(self) -> Any:
+        """The complete run as a dict in the `rusted_chain.run/1` schema: messages, tool
+        calls, usage and timings. One per line is what `run_log` writes.
+        """
+        ...
+    def record_feedback(self, score: float, comment: str | None = None) -> None:
+        """Record feedback on the run, such as a user's rating of the answer: a numeric
+        `score` and an optional `comment`. It is written to the agent's `run_log` and
+        `audit_log`, shown with the run in the trace viewer, included in `to_run()`,
+        and sent to the experiment trackers the run was logged to.
+        """
+        ...
+    def to_markdown(self) -> str:
+        """The run's conversation as Markdown: each message under a heading, with tool
+        arguments and results in fenced blocks.
+        """
+        ...
+    def to_html(self) -> str:
+        """The run's conversation as a self-contained HTML page."""
+        ...
+    @property
+    def tool_call(self) -> ToolCall:
+        ...
+    def __repr__(self) -> str:
+        ...
+
+
+class ToolCall:
+    @property
+    def name(self) -> str:
+        ...
+    @property
+    def args(self) -> str:
+        ...
+    def __repr__(self) -> str:
+        ...
+
+
+class ResponseMetadata:
+    """Details about how a response was produced, alongside its content."""
+    @property
+    def detected_language(self) -> str | None:
+        """Best-guess ISO 639-1 code of the query, or `None` if it could not be determined."""
+        ...
+    @property
+    def moderation(self) -> ModerationResult | None:
+        """The moderation verdict on the answer, with `auto_moderate="annotate"`."""
+        ...
+    @property
+    def tool_usage(self) -> dict[str, ToolUsage]:
+        """`ToolUsage` per tool executed during the run."""
+        ...
+    @property
+    def citations(self) -> list[Citation]:
+        """Sources the answer cites, for providers that return them (Perplexity)."""
+        ...
+    @property
+    def retries(self) -> Retries:
+        """How the run's provider requests were retried."""
+        ...
+    @property
+    def artifacts(self) -> list[Artifact]:
+        """Files stored from tool results instead of being sent to the model."""
+        ...
+    def __repr__(self) -> str:
+        ...
+
+
+class ToolUsage:
+    """How often a tool was called and how it fared."""
+    @property
+    def calls(self) -> int:
+        """Executions, failed ones included."""
+        ...
+    @property
+    def failures(self) -> int:
+        """Executions that raised."""
+        ...
+    @property
+    def failure_rate(self) -> float:
+        """`failures / calls`."""
+        ...
+    @property
+    def avg_latency_s(self) -> float:
+        """Mean execution time in seconds."""
+        ...
+    @property
+    def avg_result_bytes(self) -> float:
+        """Mean size of the successful results, serialized as JSON."""
+        ...
+    def __repr__(self) -> str:
+        ...
+
+
+class Estimate:
+    """The estimated prompt size and cost range of a request. The cost is bounded below
+    by the prompt alone and above by the prompt plus `max_output_tokens` of answer.
+    """
+    @property
+    def model(self) -> str:
+        ...
+    @property
+    def prompt_tokens(self) -> int:
+        ...
+    @property
+    def max_output_tokens(self) -> int:
+        ...
+    @property
+    def min_cost_usd(self) -> float | None:
+        """`None` for models without a known price."""
+        ...
+    @property
+    def max_cost_usd(self) -> float | None:
+        ...
+    def __repr__(self) -> str:
+        ...
+
+
+class Retries:
+    """How the provider requests of a run were retried."""
+    @property
+    def attempts(self) -> int:
+        """Requests sent, retries included."""
+        ...
+    @property
+    def retries(self) -> int:
+        """Requests that failed and were sent again."""
+        ...
+    @property
+    def backoff_s(self) -> list[float]:
+        """Seconds waited before each retry."""
+        ...
+    @property
+    def errors(self) -> list[str]:
+        """Error kind of each retried attempt, such as `"rate_limited"`."""
+        ...
+    @property
+    def final_status(self) -> str:
+        """`"ok"`, or the error kind of the last request if it failed for good."""
+        ...
+    def __repr__(self) -> str:
+        ...
+
+
+class Citation:
+    """A source the answer draws on."""
+    @property
+    def url(self) -> str:
+        ...
+    @property
+    def title(self) -> str | None:
+        ...
+    @property
+    def date(self) -> str | None:
+        """Publication date as the provider gives it, e.g. `"2025-03-01"`."""
+        ...
+    @property
+    def snippet(self) -> str | None:
+        ...
+    def __repr__(self) -> str:
+        ...
+    def to_dict(self) -> dict[str, str | None]:
+        ...
+
+
+class RunContext:
+    """Passed as `ctx` to tools whose first parameter is `ctx`, instead of a value chosen
+    by the model. Lives for one run, so tools can share state without globals.
+    """
+    @property
+    def session_id(self) -> str:
+        """Identifier of the run, the same one used in run records and audit logs."""
+        ...
+    @property
+    def iteration(self) -> int:
+        """Agent loop iteration of the current tool call, starting at 0."""
+        ...
+    @property
+    def tool_results(self) -> list[Any]:
+        """Earlier tool calls in this run, as `{"name", "args", "result"}` dicts."""
+        ...
+    @property
+    def metadata(self) -> dict[str, Any]:
+        """Metadata passed to `invoke` or `run`."""
+        ...
+    @property
+    def state(self) -> dict[str, Any]:
+        """Scratch space shared by every tool call in the run."""
+        ...
+    @property
+    def artifacts(self) -> list[Artifact]:
+        """Artifacts stored so far in this run."""
+        ...
+    def __repr__(self) -> str:
+        ...
+
+
+class Video:
+    """A video attachment with sampling options, for `invoke_with_files`. Plain paths,
+    bytes and URLs of video files are accepted too; this adds the options.
+    """
+    @property
+    def fps(self) -> float | None:
+        ...
+    @property
+    def start_offset(self) -> float | None:
+        ...
+    @property
+    def end_offset(self) -> float | None:
+        ...
+    def __init__(self, source: Any, fps: float | None = None, start_offset: float | None = None, end_offset: float | None = None) -> None:
+        """`source` is a file path, bytes or a URL. `fps` sets how many frames per second
+        are sampled; `start_offset` and `end_offset` (seconds) clip the video.
+        """
+        ...
+
+
+class LongRunningJob:
+    """A request submitted with `submit_long_running()`. Poll it with `status()` or
+    `done()`, block on it with `result()`, or stop it with `cancel()`.
+    """
+    @property
+    def id(self) -> str:
+        """The OpenAI response id, or a generated id for other providers."""
+        ...
+    def status(self) -> str:
+        """`queued`, `in_progress`, `completed`, `failed` or `cancelled`."""
+        ...
+    def done(self) -> bool:
+        """Whether the job has finished, successfully or not."""
+        ...
+    def result(self, timeout: float | None = None, poll_interval: float = 2.0) -> str:
+        """Block until the job finishes and return its answer, raising its error if it
+        failed. Polls every `poll_interval` seconds; raises `TimeoutError` if it is
+        still running after `timeout` seconds.
+        """
+        ...
+    def cancel(self) -> None:
+        """Ask the provider to stop the job. Finished jobs are left as they are."""
+        ...
+    def __repr__(self) -> str:
+        ...
+
+
+class Assistant:
+    """An OpenAI assistant: a model, instructions and tool definitions stored by OpenAI.
+    Conversations with it happen on threads.
+    """
+    @property
+    def id(self) -> str:
+        ...
+    @property
+    def model(self) -> str:
+        ...
+    def create_thread(self) -> AssistantThread:
+        """Start a new, empty thread."""
+        ...
+    def get_thread(self, thread_id: str) -> AssistantThread:
+        """Continue an existing thread by id."""
+        ...
+    def delete(self) -> None:
+        """Delete the assistant from OpenAI."""
+        ...
+    def __repr__(self) -> str:
+        ...
+
+
+class AssistantThread:
+    """A conversation with an assistant, kept by OpenAI. Each call adds to it."""
+    @property
+    def id(self) -> str:
+        ...
+    @property
+    def assistant_id(self) -> str:
+        ...
+    def invoke(self, query: str, tags: list[str] | None = None, metadata: dict[str, Any] | None = None) -> AgentResponse:
+        """Add `query` to the thread and run the assistant. Tools passed to the model are
+        executed until the run finishes; a call to any other tool comes back as
+        `AgentResponse.ToolCall`, to be answered with `submit_tool_output`.
+        """
+        ...
+    def run(self, query: str, stream: bool = False, tags: list[str] | None = None, metadata: dict[str, Any] | None = None) -> Any:
+        """Add `query` to the thread and return the final answer, or an iterator of text
+        chunks with `stream=True`.
+        """
+        ...
+    def submit_tool_output(self, output: Any) -> AgentResponse:
+        """Answer the tool call the last run stopped on and let the run continue. Returns
+        the assistant's answer, or its next tool call.
+        """
+        ...
+    def delete(self) -> None:
+        """Delete the thread from OpenAI."""
+        ...
+    def __repr__(self) -> str:
+        ...
+
+
+class Session:
+    """A conversation with a model. Each message is sent with the earlier ones, and the
+    model's tools run as usual in between.
+    """
+    def __init__(self, model: Any, summary_model: Any = None, downgrade: DowngradePolicy | None = None, memory: MemoryPolicy | None = None, migrate: Any = None, token_budget: int | None = None, remember_facts: bool = False) -> None:
+        """`model` answers the messages. `summary_model`, typically a cheaper one, writes
+        titles and summaries; it defaults to `model`. `downgrade`, a `DowngradePolicy`,
+        switches to a cheaper model once the session has spent enough. `memory`, a
+        `MemoryPolicy`, leaves repeated turns and tool results out of what is sent.
+        `migrate(name, args, old_schema, new_schema)` rewrites the arguments of earlier
+        calls to a tool whose schema has changed, or returns `None` to keep them as text.
+        Once the messages since the last reset have used `token_budget` tokens, the
+        summary model condenses the history into a summary and a list of key facts, which
+        are sent in its place from then on. With `remember_facts`, the summary model
+        updates `facts` after each turn, and they are added to the system prompt.
+        """
+        ...
+    @staticmethod
+    def load(source: Any, model: Any, summary_model: Any = None, downgrade: DowngradePolicy | None = None, memory: MemoryPolicy | None = None, migrate: Any = None, token_budget: int | None = None, remember_facts: bool = False) -> Session:
+        """A session saved with `save` or `to_dict`, continued with `model`. Earlier calls
+        to tools whose schema has changed since are migrated, with a warning, before the
+        next message is sent. Usage and model switches start over. `source` may also be
+        a list of chat messages in OpenAI or Gemini format, or a mix of the two.
+        """
+        ...
+    def to_dict(self) -> Any:
+        """The conversation and the versions of the tools it used, for `Session.load`."""
+        ...
+    def save(self, path: str | os.PathLike[str]) -> None:
+        """Write `to_dict()` to `path` as JSON."""
+        ...
+    @property
+    def tool_versions(self) -> dict[str, str]:
+        """The version of each tool the history was made with, a hash of its name and
+        parameters.
+        """
+        ...
+    def invoke(self, message: str, tags: list[str] | None = None, metadata: dict[str, Any] | None = None) -> AgentResponse:
+        """Send `message` with the conversation so far. A text answer is added to the
+        history together with the message and any tool calls made on the way.
+        """
+        ...
+    def run(self, message: str, tags: list[str] | None = None, metadata: dict[str, Any] | None = None) -> str:
+        """Send `message` and return the answer text."""
+        ...
+    @property
+    def history(self) -> list[Any]:
+        """The conversation so far, as `{"role": ..., "content": ...}` dicts. Tool calls
+        appear as `{"role": "assistant", "tool_call": {...}}` followed by a `"tool"`
+        message with the result.
+        """
+        ...
+    def to_messages(self, format: str = "openai") -> Any:
+        """The conversation so far as the messages `format` (`"openai"`, `"gemini"` or
+        `"claude"`) sends, with that provider's roles.
+        """
+        ...
+    def to_markdown(self) -> str:
+        """The conversation so far as Markdown, tool calls and results included."""
+        ...
+    def to_html(self) -> str:
+        """The conversation so far as a self-contained HTML page."""
+        ...
+    def set_model(self, model: Any) -> None:
+        """Continue the conversation with another model: a model object, or a model name
+        such as `"claude-sonnet-4-5"` whose provider is recognised from the name. A name
+        keeps the current model's tools and options. The history, tool calls included,
+        is converted to the new provider's format.
+        """
+        ...
+    @property
+    def model(self) -> str:
+        """The name of the model answering the messages."""
+        ...
+    @property
+    def usage(self) -> Any:
+        """Tokens and estimated cost so far: `{"input_tokens", "output_tokens",
+        "cost_usd"}`. Models without a known price add no cost.
+        """
+        ...
+    @property
+    def model_switch(self) -> Any:
+        """`{"from", "to", "reason"}` once the downgrade policy has switched models,
+        otherwise `None`.
+        """
+        ...
+    @property
+    def carried_summary(self) -> str | None:
+        """The summary of the history dropped for the token budget, or `None` while nothing
+        has been dropped.
+        """
+        ...
+    @property
+    def key_facts(self) -> list[str]:
+        """The key facts kept from the history dropped for the token budget."""
+        ...
+    @property
+    def facts(self) -> dict[str, str]:
+        """What is known about the user and the conversation, by short key. Kept up to date
+        with `remember_facts`, and may be set to seed or correct it.
+        """
+        ...
+    @facts.setter
+    def facts(self, value: dict[str, str]) -> None: ...
+    def clear(self) -> None:
+        """Forget the conversation, including any summary and facts kept from it."""
+        ...
+    def title(self, model: Any = None) -> str:
+        """A short title for the conversation, written by `model` or the summary model."""
+        ...
+    def summary(self, model: Any = None) -> str:
+        """A few sentences summarizing the conversation, written by `model` or the
+        summary model.
+        """
+        ...
+    def __len__(self) -> int:
+        ...
+    def __repr__(self) -> str:
+        ...
+
+
+class KeyPool:
+    """Several API keys for one provider, shared by every model given the pool."""
+    def __init__(self, keys: list[str], rotation: str = "round_robin") -> None:
+        """`rotation` is `"round_robin"` (each request takes the next key) or `"on_429"`
+        (stay on a key until it is rate limited).
+        """
+        ...
+    def usage(self) -> list[dict[str, Any]]:
+        """Per key, in pool order: `{"key", "requests", "rate_limited", "input_tokens",
+        "output_tokens"}`, with the key reduced to its last four characters.
+        """
+        ...
+    def __len__(self) -> int:
+        ...
+    def __repr__(self) -> str:
+        ...
+
+
+class LoadBalancer:
+    """Deployments of one OpenAI-compatible model that requests are spread across, shared
+    by every model given the balancer.
+    """
+    def __init__(self, deployments: list[Any], failure_threshold: int = ..., cooldown: float = ...) -> None:
+        """`deployments` is a list of `{"base_url", "api_key", "weight"}` dicts; `weight`
+        defaults to 1. After `failure_threshold` failed requests in a row a deployment
+        gets no traffic for `cooldown` seconds, then one probe request decides whether
+        it comes back.
+        """
+        ...
+    def health(self) -> list[dict[str, Any]]:
+        """Per deployment, in order: `{"base_url", "weight", "state", "requests",
+        "failures", "consecutive_failures"}`, where `state` is `"closed"`, `"open"` or
+        `"half_open"`.
+        """
+        ...
+    def __len__(self) -> int:
+        ...
+    def __repr__(self) -> str:
+        ...
+
+
+class DowngradePolicy:
+    """When a `Session` moves to a cheaper model: after its estimated cost passes
+    `max_cost_usd` or its tokens pass `max_tokens`, whichever comes first.
+    """
+    @property
+    def to(self) -> Any:
+        """A model object, or a model name resolved like `Session.set_model`."""
+        ...
+    @property
+    def max_cost_usd(self) -> float | None:
+        ...
+    @property
+    def max_tokens(self) -> int | None:
+        ...
+    def __init__(self, to: Any, max_cost_usd: float | None = None, max_tokens: int | None = None) -> None:
+        ...
+    def __repr__(self) -> str:
+        ...
+
+
+class MemoryPolicy:
+    """How a `Session` compresses the history it sends. Once the history has
+    `min_messages` entries, a turn is left out when a later turn has a similar message
+    and a similar answer, and a tool result similar to an earlier one from the same
+    tool is replaced by a pointer to it. The session's own history keeps everything.
+    """
+    @property
+    def similarity(self) -> float:
+        """Cosine similarity from which two texts count as repeats."""
+        ...
+    @property
+    def min_messages(self) -> int:
+        ...
+    def __init__(self, embeddings: Any, similarity: float = 0.95, min_messages: int = 10) -> None:
+        """`embeddings` is an `Embeddings` model or a callable mapping a list of strings to
+        a list of vectors.
+        """
+        ...
+    def __repr__(self) -> str:
+        ...
+
+
+class Tenant:
+    """One customer's credentials, endpoint, budget and limits, passed per call as
+    `invoke(query, context=tenant)`. Spend is tracked on the tenant across every model
+    it is used with.
+    """
+    def __init__(self, name: str, api_keys: dict[str, Any] | None = None, base_urls: dict[str, str] | None = None, max_cost_usd: float | None = None, max_tokens: int | None = None, max_concurrency: int | None = None, rate_limit_per_min: int | None = None) -> None:
+        """`api_keys` maps a provider (`"openai"`, `"anthropic"`, `"gemini"`,
+        `"huggingface"`) to a key, a list of keys, a `KeyPool` or a token callback.
+        `base_urls` may only set `"openai"`, for OpenAI-compatible servers.
+        `max_concurrency` and `rate_limit_per_min` apply to the tenant's runs.
+        """
+        ...
+    @property
+    def name(self) -> str:
+        ...
+    @property
+    def usage(self) -> dict[str, Any]:
+        """`{"runs", "input_tokens", "output_tokens", "cost_usd"}` over every call made
+        with this tenant. The cost only counts models with a known price.
+        """
+        ...
+    def reset_usage(self) -> None:
+        """Forget the tenant's spend, e.g. at the start of a billing period."""
+        ...
+    def __repr__(self) -> str:
+        ...
+
+
+class Replay:
+    """Replays a recorded run step by step. Iterating yields one dict per model reply."""
+    @property
+    def run_id(self) -> str:
+        ...
+    @property
+    def provider(self) -> str:
+        ...
+    @property
+    def model(self) -> str:
+        ...
+    @property
+    def query(self) -> str | None:
+        """The user message that started the run."""
+        ...
+    @property
+    def position(self) -> int:
+        """Index of the next step."""
+        ...
+    def __init__(self, recording: Any, run_id: str | None = None, tools: list[Any] | None = None) -> None:
+        """`recording` is an audit log file (`audit_log="audit.jsonl"`) or a list of the
+        records an `audit_log` callback received, or the same for run exports
+        (`run_log`, `AgentResponse.to_run()`). `run_id` picks the run, by default the
+        last one recorded. Tool calls to one of `tools` are executed again; the others
+        are answered with their recorded output.
+        """
+        ...
+    def step(self) -> dict[str, Any] | None:
+        """Replay the next step, or return `None` after the last one.
+
+        The dict has `index`, `type` (`"text"`, `"tool_call"` or `"error"`) and
+        `latency_ms`, plus `text`, `tool_call` or `error`. A tool call also carries
+        `recorded_result` and `result`: the recorded output, or the output of running
+        the tool again (`source` tells which), with `diverged` set when they differ.
+        """
+        ...
+    def run(self) -> list[Any]:
+        """Replay the remaining steps and return their dicts."""
+        ...
+    def reset(self) -> None:
+        """Start over from the first step, with a fresh `RunContext` for the tools."""
+        ...
+    @property
+    def answer(self) -> str | None:
+        """The recorded final answer, if the run ended with one."""
+        ...
+    def __len__(self) -> int:
+        ...
+    def __iter__(self) -> Replay:
+        ...
+    def __next__(self) -> dict[str, Any] | None:
+        ...
+    def __repr__(self) -> str:
+        ...
+
+
+class PiiPolicy:
+    """Which personal data is kept out of prompts. `entities` picks built-in detectors:
+    `"email"`, `"credit_card"` (Luhn-checked), `"ssn"`, `"phone"` and `"ip_address"`;
+    `patterns` maps further entity names to regexes, applied first. With `restore`,
+    placeholders in the answer are replaced by the original values. Tools always get
+    the original values.
+    """
+    def __init__(self, entities: list[str] | None = None, patterns: dict[str, str] | None = None, restore: bool = True) -> None:
+        ...
+    @property
+    def entities(self) -> list[str]:
+        ...
+    @property
+    def restore(self) -> bool:
+        ...
+    def redact(self, text: str) -> tuple[str, dict[str, str]]:
+        """`text` with the policy applied, and the placeholders mapped to what they
+        replace; for checking a policy against sample data.
+        """
+        ...
+    def __repr__(self) -> str:
+        ...
+
+
+class PromptCompressor:
+    """Shortens long prompts to about `ratio` of their tokens by dropping low-information
+    sentences. Prompts under `min_tokens` are sent as they are. `scorer`, e.g. a small
+    model, replaces the built-in scoring: it is called with the list of sentences and
+    the question, and returns a score per sentence; higher is kept first.
+    """
+    def __init__(self, ratio: float = 0.5, min_tokens: int = 1000, scorer: Any = None) -> None:
+        ...
+    @property
+    def ratio(self) -> float:
+        ...
+    @property
+    def min_tokens(self) -> int:
+        ...
+    def compress(self, text: str) -> str:
+        """`text` as it would be sent."""
+        ...
+    def __repr__(self) -> str:
+        ...
+
+
+class ToolSerializer:
+    """How tool results are turned into JSON. Built in: datetimes, dates and times as
+    ISO 8601 (or Unix timestamps with `datetimes="timestamp"`), timedeltas as seconds,
+    `Decimal` as a number, numpy scalars and arrays as numbers and lists, UUIDs and
+    paths as strings, enums as their value, and dataclasses and pydantic models as
+    objects. Anything else goes to `encoder`, which returns something serializable,
+    like `json.dumps(default=...)`; without one it becomes its `str()`.
+
+    `float_digits` rounds floats; `non_finite` is `"string"` (the default, `"NaN"`) or
+    `"null"`. `ensure_ascii` escapes non-ASCII characters in results sent as JSON text
+    (OpenAI-compatible APIs), as Python's `json.dumps` does by default.
+    """
+    def __init__(self, ensure_ascii: bool = False, float_digits: int | None = None, non_finite: str = "string", datetimes: str = "iso", encoder: Any = None) -> None:
+        ...
+    def dumps(self, value: Any) -> str:
+        """`value` as it would be sent to the model, as JSON text."""
+        ...
+    def __repr__(self) -> str:
+        ...
+
+
+class Artifact:
+    """A file a tool produced during a run."""
+    @property
+    def name(self) -> str:
+        """File name, unique within the run, as the model knows it."""
+        ...
+    @property
+    def path(self) -> pathlib.Path:
+        ...
+    @property
+    def size(self) -> int:
+        """Size in bytes."""
+        ...
+    @property
+    def media_type(self) -> str:
+        ...
+    @property
+    def tool(self) -> str:
+        """The tool that returned it."""
+        ...
+    def read(self) -> bytes:
+        """The artifact's contents."""
+        ...
+    def __repr__(self) -> str:
+        ...
+
+
+class Delegate:
+    """Add to a model's tools to let it fan work out: the model calls `delegate` with a
+    list of tasks, `model` runs each one as a fresh conversation, at most
+    `max_concurrency` at a time, and the answers come back together as the tool result.
+    Calls with more than `max_tasks` tasks run the first `max_tasks`.
+    """
+    def __init__(self, model: Any, max_concurrency: int = 4, max_tasks: int = 8, name: str = "delegate", description: str | None = None) -> None:
+        ...
+    @property
+    def __name__(self) -> str:
+        ...
+    @property
+    def takes_context(self) -> bool:
+        """Asks the agent for the run context, to link sub-agent runs to the run that
+        delegated them.
+        """
+        ...
+    def to_dict(self) -> Any:
+        """The function schema the model sees."""
+        ...
+    def __call__(self, tasks: list[str], ctx: RunContext | None = None) -> Any:
+        """Run `tasks`, returning `{"task", "answer"}` for each, or `{"task", "error"}` for
+        those that failed or were not run.
+        """
+        ...
+    def __repr__(self) -> str:
+        ...
+
+
+class TextStream:
+    """Iterator over streamed text chunks. Chunks end on grapheme cluster boundaries, so an
+    emoji sequence or a letter with combining accents is never split between two.
+    """
+    def __iter__(self) -> TextStream:
+        ...
+    def __next__(self) -> str | None:
+        ...
+    def __aiter__(self) -> TextStream:
+        ...
+    def __anext__(self) -> Any:
+        """The next chunk, awaited without blocking the event loop. Overlapping awaits get
+        chunks in the order they started reading.
+        """
+        ...
+    @property
+    def time_to_first_token(self) -> float | None:
+        """Seconds from the start of the run until the first chunk of text, or `None`
+        before it arrives.
+        """
+        ...
+    @property
+    def tokens_per_second(self) -> float | None:
+        """Output tokens per second of the latest streamed request, measured from its first
+        chunk to its end, or `None` until one has finished.
+        """
+        ...
+    @property
+    def citations(self) -> list[Citation]:
+        """Sources the answer cites, for providers that return them; filled in once the
+        answer has finished streaming.
+        """
+        ...
+    @property
+    def retries(self) -> Retries:
+        """How the run's provider requests were retried; filled in once the stream ends."""
+        ...
+    @property
+    def artifacts(self) -> list[Artifact]:
+        """Files stored from tool results instead of being sent to the model; filled in
+        once the stream ends.
+        """
+        ...
+    def json(self) -> JsonStream:
+        """Treat the streamed text as a JSON document, yielding progressively more complete
+        parsed objects as fields finish.
+        """
+        ...
+    def sse(self, keepalive: float | None = 15.0) -> EventStream:
+        """Format the stream as server-sent events for a streaming HTTP response: each
+        chunk as a `data` event, a `: keep-alive` comment after `keepalive` seconds
+        without text (`None` for none), then an `end` event, or an `error` event holding
+        the message if the run fails.
+        """
+        ...
+    def events(self, keepalive: float | None = 15.0) -> EventStream:
+        """Like `sse`, as dicts to send as WebSocket JSON messages: `{"type": "chunk",
+        "text": ...}`, `{"type": "keepalive"}`, then `{"type": "end"}` or `{"type":
+        "error", "message": ...}`.
+        """
+        ...
+
+
+class JsonStream:
+    """Iterator yielding progressively more complete objects parsed from a text stream."""
+    def __iter__(self) -> JsonStream:
+        ...
+    def __next__(self) -> Any:
+        ...
+
+
+class EventStream:
+    """Iterator over a text stream's chunks as server events, with keep-alives while the
+    model is quiet, e.g. between tool calls.
+    """
+    def __iter__(self) -> EventStream:
+        ...
+    def __next__(self) -> Any:
+        ...
+
+
+class Workflow:
+    """A graph of agents, tools and Python functions executed step by step.
+
+    After each node, its outgoing edges are checked in the order they were added and the
+    first one whose condition passes (or that has none) is followed; a `Router` node
+    jumps straight to the branch it picks. The run ends at `END` or at a node with no
+    matching edge.
+    """
+    def __init__(self, max_steps: int = ...) -> None:
+        ...
+    def add_node(self, name: str, node: Any, prompt: Any = None, transform: Any = None) -> None:
+        """Add a node. `prompt` is a `str.format` template (or a `PromptTemplate`) rendered
+        with the state for model nodes; without it a model receives the previous output (or `state["input"]`).
+        `transform` (an `OutputTransform` or transform name) cleans up string outputs.
+        The first node added is the entry point unless `set_entry` says otherwise.
+        """
+        ...
+    def add_edge(self, source: str, target: str, condition: Any = None) -> None:
+        """Connect two nodes. `condition` is called with the state and the edge is taken
+        only if it returns something truthy.
+        """
+        ...
+    def set_entry(self, name: str) -> None:
+        ...
+    def run(self, input: Any) -> WorkflowResult:
+        """Execute the workflow. `input` is either an initial state dict or a value stored
+        as `state["input"]`.
+        """
+        ...
+
+
+class WorkflowResult:
+    """Result of a workflow run: the final state plus the path taken through the graph."""
+    @property
+    def state(self) -> dict[str, Any]:
+        """The final state dict."""
+        ...
+    @property
+    def output(self) -> Any:
+        """`state["output"]`: the last node's output, or `None`."""
+        ...
+    @property
+    def trace(self) -> list[Any]:
+        """One dict per executed node: `{"node": name, "duration_s": seconds}`, plus
+        `"branch"` for router nodes.
+        """
+        ...
+    def __repr__(self) -> str:
+        ...
+
+
+class Router:
+    """Sends input to one of several named branches, chosen by a Python predicate or by a
+    short classification call to a model.
+    """
+    def __init__(self, branches: Any, model: Any = None, predicate: Any = None, default: str | None = None, race: bool = False) -> None:
+        """`branches` is a list of names or a dict of name -> description. Exactly one of
+        `model` (classifies the input) or `predicate` (returns a branch name) is required.
+        `default` is used when the classifier answers with no known branch. With
+        `race=True`, `model` is a list of models (typically from different providers)
+        that all classify the input concurrently; the first successful answer is used
+        and the slower requests are cancelled.
+        """
+        ...
+    def route(self, input: Any) -> str:
+        """Choose a branch for `input`."""
+        ...
+    @property
+    def branches(self) -> list[str]:
+        ...
+
+
+class PromptTemplate:
+    """A `str.format` template, with the name, version, model hint and parameters it was
+    loaded with.
+    """
+    @property
+    def template(self) -> str:
+        ...
+    @property
+    def name(self) -> str | None:
+        ...
+    @property
+    def version(self) -> str | None:
+        ...
+    @property
+    def model(self) -> str | None:
+        """The model the prompt was written for."""
+        ...
+    def __init__(self, template: str, name: str | None = None, version: str | None = None, model: str | None = None, params: dict[str, Any] | None = None) -> None:
+        ...
+    @property
+    def input_variables(self) -> list[str]:
+        """The placeholders still to be filled, in order of first appearance."""
+        ...
+    @property
+    def params(self) -> Any:
+        """Model parameters from the front matter, e.g. `{"temperature": 0.2}`."""
+        ...
+    @property
+    def metadata(self) -> Any:
+        """The whole front matter."""
+        ...
+    def format(self, **kwargs: Any) -> str:
+        """Render the template with `str.format`. Unused keyword arguments are ignored, so
+        a whole state dict can be passed.
+        """
+        ...
+    def partial(self, **kwargs: Any) -> PromptTemplate:
+        """A copy with some placeholders filled in ahead of time."""
+        ...
+    def __repr__(self) -> str:
+        ...
+
+
+class PromptLibrary:
+    """Named, versioned prompt templates loaded from a directory. `get("summarize@v3")`
+    returns one version; `get("summarize")` the latest.
+    """
+    @property
+    def directory(self) -> str:
+        ...
+    def __init__(self, directory: str) -> None:
+        """Load every `.md`, `.txt` and `.prompt` file under `directory`."""
+        ...
+    def get(self, reference: str) -> PromptTemplate:
+        """The template for `reference`: `"name@version"`, or `"name"` for the latest
+        version.
+        """
+        ...
+    def names(self) -> list[str]:
+        """Prompt names, sorted."""
+        ...
+    def versions(self, name: str) -> list[str]:
+        """The versions of prompt `name`, oldest first."""
+        ...
+    def reload(self) -> None:
+        """Read the directory again, picking up edited, added and removed files."""
+        ...
+    def __contains__(self, reference: str) -> bool:
+        ...
+    def __len__(self) -> int:
+        ...
+    def __repr__(self) -> str:
+        ...
+
+
+class OutputTransform:
+    """A sequence of text clean-up steps, applied in order."""
+    @staticmethod
+    def strip_fences() -> OutputTransform:
+        """Remove Markdown fence lines (```` ```json ````, `~~~`), keeping what they enclose."""
+        ...
+    @staticmethod
+    def normalize_whitespace() -> OutputTransform:
+        """Use `\\n` line endings, drop trailing spaces, collapse runs of blank lines into
+        one, and drop blank lines at either end. Indentation is kept.
+        """
+        ...
+    @staticmethod
+    def first_code_block(language: str | None = None) -> OutputTransform:
+        """Keep only the first fenced code block (in `language`, if given). Text without
+        one passes through unchanged.
+        """
+        ...
+    def __or__(self, other: Any) -> OutputTransform:
+        """Apply this transform, then `other`."""
+        ...
+    def __call__(self, text: str) -> str:
+        ...
+    def __repr__(self) -> str:
+        ...
+
+
+class MessageTransform:
+    """A sequence of message rewrites, applied in order to each request's messages."""
+    @staticmethod
+    def strip_fields(*fields: str) -> MessageTransform:
+        """Remove `fields` from every message and every content part, e.g. `name` for a
+        server that rejects it.
+        """
+        ...
+    @staticmethod
+    def markdown_images() -> MessageTransform:
+        """Turn Markdown images (`![alt](url)`) in user messages into image parts, for
+        OpenAI-compatible providers and Claude. Gemini cannot fetch image URLs, so there
+        they become plain links, `alt (url)`.
+        """
+        ...
+    @staticmethod
+    def split_long(max_chars: int) -> MessageTransform:
+        """Split text longer than `max_chars` characters at paragraph, line or word breaks:
+        into several text parts for Claude and Gemini, and into consecutive messages of
+        the same role for OpenAI-compatible providers. Tool calls and results are left
+        whole.
+        """
+        ...
+    def only(self, *providers: str) -> MessageTransform:
+        """This transform, applied only to requests for `providers` (see `Providers`)."""
+        ...
+    def __or__(self, other: Any) -> MessageTransform:
+        """Apply this transform, then `other`: a `MessageTransform` or a function."""
+        ...
+    def __call__(self, messages: Any, provider: str = "openai") -> Any:
+        """The transformed `messages`, given as the provider's API takes them."""
+        ...
+    def __repr__(self) -> str:
+        ...
+
+
+class HmacSigner:
+    """Signs each request with HMAC-SHA256 over `"{timestamp}.{body}"`, sent as a lowercase
+    hex digest in `header`, with the Unix timestamp in `timestamp_header`.
+    """
+    def __init__(self, secret: str, header: str = "X-Signature", timestamp_header: str = "X-Timestamp") -> None:
+        ...
+    def __repr__(self) -> str:
+        ...
+
+END: str
+
+
+class Providers:
+    """Provider names, as taken by `create_agent(provider=...)` and reported in run
+    records.
+    """
+    OPENAI: str
+    ANTHROPIC: str
+    GEMINI: str
+    HUGGINGFACE: str
+
+
+class Roles:
+    """Message roles, as in `Session.history`. `MODEL` and `FUNCTION` are Gemini's names
+    for `ASSISTANT` and `TOOL`, accepted by `Session.load`.
+    """
+    USER: str
+    ASSISTANT: str
+    TOOL: str
+    SYSTEM: str
+    MODEL: str
+    FUNCTION: str
+
+
+def parse_partial_json(text: str) -> Any:
+    """Parse a partially streamed JSON document into Python objects, or `None` if nothing
+    parseable has arrived yet.
+    """
+    ...
+
+
+def token_ids(text: str, model: str = "gpt-4o-mini") -> list[int]:
+    """The ids OpenAI's `model` tokenizes `text` into, e.g. for `logit_bias`. Models
+    tiktoken doesn't know are assumed to be recent ones using `o200k_base`.
+    """
+    ...
+
+
+def estimate(query_or_messages: Any, model: Any, max_output_tokens: int = 4096) -> Estimate:
+    """Estimate the prompt tokens and cost of sending `query_or_messages`, a query or a list
+    of `{"role", "content"}` dicts, to `model`, a model name or a model object. A model
+    object's system prompt and tools are counted too. Nothing is sent.
+    """
+    ...
+
+
+def map_reduce(documents: Any, map_prompt: str, reduce_prompt: str, model: Any, concurrency: int = 4, chunk_tokens: int = 3000, idempotency_key: str | None = None) -> str:
+    """Summarize (or otherwise process) documents of any length: split them into chunks of
+    about `chunk_tokens` tokens, run `map_prompt` on every chunk with up to `concurrency`
+    requests in flight, then combine the results with `reduce_prompt`, hierarchically
+    until a single answer remains. Prompts receive their input at a `{text}` placeholder.
+    Submitting the same documents again with the same `idempotency_key` reuses the
+    answers already received instead of requesting them twice.
+    """
+    ...
+
+
+def analyze_image(image: Any, schema: Any, model: Any, prompt: str | None = None) -> Any:
+    """Ask `model` about one image (or a list of images) and get the answer back in the
+    shape of `schema`: a JSON schema dict, returned as plain data, or a Pydantic model
+    class, returned as a validated instance. Images are file paths, bytes, `http(s)://`
+    URLs or `data:` URLs; each provider receives them in its own format.
+    """
+    ...
+
+
+def classify(text: str, labels: list[str], model: Any, instructions: str | None = None) -> Classification:
+    """Classify `text` as exactly one of `labels` with `model`, a model class instance.
+    `instructions` says what the labels mean or how to choose between them.
+    """
+    ...
+
+
+class Classification:
+    """The label `classify` picked."""
+    @property
+    def label(self) -> str:
+        """One of the labels given."""
+        ...
+    @property
+    def confidence(self) -> float | None:
+        """How likely `label` is, from 0 to 1, if known."""
+        ...
+    @property
+    def method(self) -> str:
+        """`"logprobs"` when `confidence` is the model's probability of the label's tokens
+        (OpenAI), `"reported"` when it is the model's own estimate.
+        """
+        ...
+    def __repr__(self) -> str:
+        ...
+
+
+def moderate(text: str, api_key: str | None = None, model: str | None = None, keywords: list[str] | None = None, patterns: list[str] | None = None, local_only: bool = False) -> ModerationResult:
+    """Check `text` with OpenAI's moderation endpoint and any local `keywords` (matched
+    case-insensitively) and regex `patterns`. The API key defaults to `OPENAI_API_KEY`;
+    with `local_only=True` only the local rules run.
+    """
+    ...
+
+
+class ModerationResult:
+    """The verdict on one text."""
+    @property
+    def flagged(self) -> bool:
+        """Whether the endpoint or any local rule flagged the text."""
+        ...
+    @property
+    def categories(self) -> list[str]:
+        """Names of the endpoint categories that were flagged, e.g. `"harassment"`."""
+        ...
+    @property
+    def category_scores(self) -> dict[str, float]:
+        """Endpoint score per category, from 0 to 1. Empty for local-only checks."""
+        ...
+    @property
+    def matches(self) -> list[str]:
+        """Local keywords and patterns found in the text."""
+        ...
+    def __repr__(self) -> str:
+        ...
+
+
+def validate_answer(answer: Any, sources: list[Any], schema: Any = None, quote_keys: list[str] | None = None, embeddings: Any = None, threshold: float = 0.85) -> ValidationReport:
+    """Check `answer` (a string or `AgentResponse`) against `schema` (a JSON schema dict or
+    a Pydantic model class) and check that each passage it quotes appears in `sources`
+    (strings or `Document`s). In a JSON answer, the strings under `quote_keys` are quotes
+    in full. Quotes that don't appear verbatim are matched by word overlap, or by cosine
+    similarity with `embeddings` (an `Embeddings` instance or a callable), and count as
+    supported at `threshold` or above.
+    """
+    ...
+
+
+class ValidationReport:
+    """The outcome of `validate_answer`."""
+    @property
+    def valid(self) -> bool:
+        """No schema errors and every quote supported."""
+        ...
+    @property
+    def parsed(self) -> Any:
+        """The answer parsed as JSON, when a schema or quote keys were given and it parsed."""
+        ...
+    @property
+    def schema_errors(self) -> list[str]:
+        """Where the answer breaks the schema, as `"$.path: problem"`."""
+        ...
+    @property
+    def quotes(self) -> list[QuoteCheck]:
+        """One check per quote found in the answer, in order."""
+        ...
+    @property
+    def unsupported(self) -> list[str]:
+        """The quotes no source supports."""
+        ...
+    def __bool__(self) -> bool:
+        ...
+    def __repr__(self) -> str:
+        ...
+
+
+class QuoteCheck:
+    """How one quote in the answer was checked against the sources."""
+    @property
+    def quote(self) -> str:
+        """The quoted text, as it appears in the answer."""
+        ...
+    @property
+    def supported(self) -> bool:
+        """Whether a source backs the quote."""
+        ...
+    @property
+    def source(self) -> int | None:
+        """Index in `sources` of the best match, if any source came close."""
+        ...
+    @property
+    def score(self) -> float:
+        """How close the best match is, from 0 to 1; 1 for a verbatim match."""
+        ...
+    @property
+    def method(self) -> str:
+        """`"exact"`, `"words"` or `"embedding"`: what the score measures."""
+        ...
+    def __repr__(self) -> str:
+        ...
+
+
+class RecursiveCharacterTextSplitter:
+    """Splits text on a list of separators, coarsest first, recursing into pieces that are
+    still larger than `chunk_size`.
+    """
+    @property
+    def chunk_size(self) -> int:
+        ...
+    @property
+    def chunk_overlap(self) -> int:
+        ...
+    @property
+    def separators(self) -> list[str]:
+        ...
+    def __init__(self, chunk_size: int = 1000, chunk_overlap: int = 200, separators: list[str] | None = None, length: str = "chars", keep_separator: str = "end") -> None:
+        """`length` measures chunks in `"chars"` or `"tokens"`; `keep_separator` attaches
+        each separator to the `"end"` of the previous piece or the `"start"` of the next.
+        """
+        ...
+    @staticmethod
+    def from_language(language: str, chunk_size: int = 1000, chunk_overlap: int = 200, length: str = "chars") -> RecursiveCharacterTextSplitter:
+        """A splitter that cuts at the structure of `language`: `"markdown"`, `"python"`,
+        `"rust"`, `"javascript"`, `"typescript"`, `"go"`, `"java"` or `"html"`.
+        """
+        ...
+    def split_text(self, text: str) -> list[str]:
+        ...
+    def split_texts(self, texts: list[str]) -> list[list[str]]:
+        """Split many texts at once, in parallel."""
+        ...
+    def split_documents(self, documents: list[Document]) -> list[Document]:
+        """Split documents into chunk documents that keep their metadata."""
+        ...
+
+
+class TokenTextSplitter:
+    """Splits text into windows of exactly `chunk_size` tokens (fewer for the last one),
+    each starting `chunk_overlap` tokens before the previous one ended.
+    """
+    @property
+    def chunk_size(self) -> int:
+        ...
+    @property
+    def chunk_overlap(self) -> int:
+        ...
+    def __init__(self, chunk_size: int = 512, chunk_overlap: int = 50) -> None:
+        ...
+    def split_text(self, text: str) -> list[str]:
+        ...
+    def split_texts(self, texts: list[str]) -> list[list[str]]:
+        """Split many texts at once, in parallel."""
+        ...
+    def split_documents(self, documents: list[Document]) -> list[Document]:
+        """Split documents into chunk documents that keep their metadata."""
+        ...
+    def count_tokens(self, text: str) -> int:
+        """Number of tokens in `text`."""
+        ...
+
+
+class Document:
+    @property
+    def text(self) -> str:
+        ...
+    @text.setter
+    def text(self, value: str) -> None: ...
+    @property
+    def metadata(self) -> dict[str, Any]:
+        ...
+    @metadata.setter
+    def metadata(self, value: dict[str, Any]) -> None: ...
+    @property
+    def id(self) -> str | None:
+        """Identifier in a vector store, if the document has been stored."""
+        ...
+    @id.setter
+    def id(self, value: str | None) -> None: ...
+    def __init__(self, text: str, metadata: dict[str, Any] | None = None, id: str | None = None) -> None:
+        ...
+    def __repr__(self) -> str:
+        ...
+
+
+class Reranker:
+    """Orders documents by relevance to a query."""
+    def __init__(self, provider: str = "local", model: str | None = None, api_key: str | None = None, max_retries: int | None = None) -> None:
+        """`provider` is `"cohere"`, `"voyage"` or `"local"`. The API key defaults to
+        `COHERE_API_KEY` or `VOYAGE_API_KEY`.
+        """
+        ...
+    def rerank(self, query: str, documents: list[Any], top_n: int | None = None) -> list[RerankResult]:
+        """Rank `documents` (strings or `Document`s) by relevance to `query`, best first,
+        keeping the `top_n` best when given.
+        """
+        ...
+
+
+class RerankResult:
+    """One reranked document: its position in the input, its relevance score and the
+    document itself.
+    """
+    @property
+    def index(self) -> int:
+        ...
+    @property
+    def score(self) -> float:
+        ...
+    @property
+    def document(self) -> Any:
+        ...
+    def __repr__(self) -> str:
+        ...
+
+
+class Embeddings:
+    """An embedding model."""
+    @property
+    def model(self) -> str:
+        ...
+    def __init__(self, provider: str = "openai", model: str | None = None, api_key: str | None = None) -> None:
+        """`provider` is `"openai"` (default model `text-embedding-3-small`) or `"gemini"`
+        (`gemini-embedding-001`). The API key defaults to `OPENAI_API_KEY` or
+        `GEMINI_API_KEY`.
+        """
+        ...
+    def embed(self, texts: list[str]) -> list[list[float]]:
+        """Embed each text, returning one vector per input."""
+        ...
+
+
+class VectorStore:
+    """Embeds and stores documents for similarity search."""
+    def __init__(self, embeddings: Any) -> None:
+        """An in-memory store."""
+        ...
+    @staticmethod
+    def qdrant(embeddings: Any, url: str = "http://localhost:6333", collection: str = "rusted_chain", api_key: str | None = None) -> VectorStore:
+        """A store backed by a Qdrant collection, created on first insert."""
+        ...
+    @staticmethod
+    def pgvector(embeddings: Any, url: str, table: str = "rusted_chain_documents") -> VectorStore:
+        """A store backed by a PostgreSQL table using the pgvector extension, created on
+        first insert. Requires the `pgvector` feature.
+        """
+        ...
+    def add_texts(self, texts: list[str], metadatas: list[dict[str, Any]] | None = None, ids: list[str] | None = None) -> list[str]:
+        """Embed and store texts, returning their ids. Ids are generated when not given."""
+        ...
+    def add_documents(self, documents: list[Document]) -> list[str]:
+        """Embed and store documents, returning their ids. Documents without an id are
+        given one.
+        """
+        ...
+    def similarity_search(self, query: str, k: int = 4, rerank: Any = None, fetch_k: int | None = None, mode: str = "vector") -> list[Document]:
+        """The `k` documents most relevant to `query`. `mode` is `"vector"` (embedding
+        similarity), `"keyword"` (BM25) or `"hybrid"` (both, merged by reciprocal rank
+        fusion). With `rerank` (`True` for the local reranker, or a `Reranker`), the
+        candidates are reordered before the best `k` are returned. Hybrid search and
+        reranking consider `fetch_k` candidates (default `4 * k`).
+        """
+        ...
+    def similarity_search_with_score(self, query: str, k: int = 4, rerank: Any = None, fetch_k: int | None = None, mode: str = "vector") -> list[tuple[Document, float]]:
+        """Like `similarity_search`, paired with each document's score: cosine similarity,
+        BM25 score, fused rank score, or the reranker's relevance score when reranking.
+        """
+        ...
+    def delete(self, ids: list[str]) -> None:
+        """Remove documents by id."""
+        ...
+
+
+def load_runs(source: Any) -> list[Any]:
+    """Read run records from a JSONL file (as written by `run_log`) or a list of dicts
+    (such as `AgentResponse.to_run()` results), checking each against the schema.
+    Feedback recorded in the file is attached to its run as `feedback`. Records from a
+    newer, incompatible version raise `ValueError`.
+    """
+    ...
+
+
+def export_finetune_jsonl(sessions: Any, path: str | os.PathLike[str], format: str = "openai", min_rating: float | None = None, dedupe: bool = True, system: str | None = None) -> int:
+    """Write successful runs as fine-tuning examples to `path`, one JSON object per line,
+    and return how many were written. `sessions` is a run log file, or a list of
+    `Session`s, `AgentResponse`s and run records (as `load_runs` returns). `format` is
+    `"openai"` (chat `messages`) or `"gemini"` (`contents`). Runs that failed, and with
+    `min_rating` runs rated lower or not at all, are left out; with `dedupe`, so are
+    repeats of an earlier example. A run's rating is its latest `record_feedback` score,
+    else a numeric `rating` in its metadata. `system` is added to every example as the system
+    prompt.
+    """
+    ...
+
+
+def flush_tracking(timeout: float = 10.0) -> None:
+    """Wait up to `timeout` seconds for pending run uploads to finish."""
+    ...
+
+
+def add_usage_webhook(target: Any, batch_size: int = 50, flush_interval: float = 5.0, headers: dict[str, str] | None = None, max_retries: int = 3) -> None:
+    """Send a usage event for every provider request to `target`: a URL, which receives
+    each batch as a JSON array in a POST with `headers`, or a callable, which receives
+    it as a list of dicts. Batches hold up to `batch_size` events and are sent at most
+    `flush_interval` seconds after their first event; a failed delivery (an error
+    status, a network error or an exception) is retried `max_retries` times.
+    """
+    ...
+
+
+def clear_usage_webhooks() -> None:
+    """Remove every usage webhook, after sending the events already queued for it."""
+    ...
+
+
+def flush_usage_webhooks(timeout: float = 10.0) -> None:
+    """Send the usage events queued for every webhook now, waiting up to `timeout` seconds
+    for the deliveries (including their retries) to finish.
+    """
+    ...
+
+
+def shutdown(timeout: float | None = None) -> bool:
+    """Stop accepting new calls, wait up to `timeout` seconds (forever if `None`) for
+    calls in flight, pending tracker uploads and queued usage webhook events, then shut
+    the runtime down. Returns whether everything finished in time; if not, the runtime
+    is left running for the calls still going. New calls raise `RuntimeError` either way.
+    """
+    ...
+
+
+def configure_runtime(worker_threads: int | None = None, thread_name: str | None = None, flavor: str = "multi_thread") -> None:
+    """Set how the runtime behind every request is built. Call it before the first
+    request; the runtime starts on first use and cannot be rebuilt. `flavor` is
+    `"multi_thread"` (the default, with `worker_threads` threads, one per core unless
+    set) or `"current_thread"`, which runs everything on the calling threads, for
+    constrained environments such as AWS Lambda. `thread_name` names the worker threads.
+    """
+    ...
+
+
+def configure_circuit_breaker(failure_threshold: int | None = ..., cooldown: float = 30.0) -> None:
+    """Turn on a circuit breaker per provider: after `failure_threshold` failed requests
+    in a row, calls to that provider raise `ProviderUnavailableError` for `cooldown`
+    seconds without sending anything, then one probe request decides whether it
+    closes again. Failures are counted after retries. `failure_threshold=None` turns the
+    breakers off and forgets their state.
+    """
+    ...
+
+
+def circuit_breakers() -> dict[str, Any]:
+    """The provider breakers so far, keyed by provider (or API root): `{"state",
+    "consecutive_failures", "retry_in_s"}`, where `state` is `"closed"`, `"open"` or
+    `"half_open"` and `retry_in_s` is how long an open breaker stays open.
+    """
+    ...
+
+
+def diagnostics(network: bool = True, timeout: float = 5.0) -> Any:
+    """Report on this installation as a dict:
+
+    - `providers`: per provider, the environment variable its key is read from,
+      whether it is set, and with `network=True` whether the endpoint answered within
+      `timeout` seconds. `usable` is true when the key is set and the endpoint was not
+      found unreachable.
+    - `features`: the optional features compiled in.
+    - `runtime`: the tokio runtime's flavor, threads and whether it is taking calls.
+    - `versions`: rusted_chain, Python and langchain-core.
+
+    Probing sends no prompts and uses no tokens; it starts the runtime if it has not
+    started yet, so the `runtime` section is read first.
+    """
+    ...
+
+
+def get_metrics() -> Any:
+    """Snapshot of every metric as a dict of sample lists."""
+    ...
+
+
+def metrics_text() -> str:
+    """All metrics in the Prometheus text exposition format."""
+    ...
+
+
+def start_metrics_server(port: int = 9464, host: str = "127.0.0.1") -> None:
+    """Serve the metrics at `http://host:port/metrics` from a background task."""
+    ...
+
+
+def serve_traces(port: int = 7465, host: str = "127.0.0.1", run_log: str | os.PathLike[str] | None = None) -> None:
+    """Serve a trace viewer at `http://host:port/` from a background task. It lists the
+    runs finished in this process from now on (the last 1000) and, with `run_log`,
+    those in a `run_log` file, read again on every request. `/api/runs` and
+    `/api/runs/<run_id>` serve the same data as JSON.
+    """
+    ...
+
+
+class ProviderError(RuntimeError):
+    ...
+
+
+class RateLimitError(ProviderError):
+    ...
+
+
+class OverloadedError(ProviderError):
+    ...
+
+
+class ContextLengthError(ProviderError):
+    ...
+
+
+class ContentFilterError(ProviderError):
+    ...
+
+
+class InvalidRequestError(ProviderError):
+    ...
+
+
+class ToolLoopError(RuntimeError):
+    ...
+
+
+class BudgetExceededError(RuntimeError):
+    ...
+
+
+testing: ModuleType
+# Built with the `loaders` feature.
+loaders: ModuleType
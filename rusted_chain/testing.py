@@ -0,0 +1,159 @@
+"""
+Golden-transcript helpers for snapshot testing agent behaviour.
+
+A run is serialized with `canonical_json` and compared against a golden file. Set
+`RUSTED_CHAIN_UPDATE_GOLDEN=1` (or pass `update=True`) to rewrite the golden files
+instead, e.g. after an intended prompt change.
+
+`run_dataset` runs an agent over a list of inputs. In watch mode its outputs are checked
+against a stored baseline, so a prompt change that makes answers worse fails in CI.
+"""
+
+import difflib
+import json
+import math
+import os
+
+import rusted_chain.rusted_chain as _rust
+
+canonical_json = _rust.testing.canonical_json
+
+UPDATE_ENV = "RUSTED_CHAIN_UPDATE_GOLDEN"
+
+COMPARISONS = ("exact", "embedding", "judge")
+
+JUDGE_INSTRUCTIONS = (
+    "The text holds a baseline answer and a new answer to the same input. Answer "
+    "`equivalent` if the new answer is as correct and complete as the baseline, and "
+    "`regressed` if it is wrong, missing information, or otherwise worse."
+)
+
+
+def assert_transcript_matches(run_result, golden_file, ignore=(), update=None):
+    """Assert that `run_result` serializes to the contents of `golden_file`.
+
+    `ignore` masks volatile fields: a bare key such as `"duration_s"` at any depth, or a
+    dotted path such as `"trace.*.duration_s"` from the root. A missing golden file is
+    written and the assertion passes, so new snapshots are recorded on the first run.
+    """
+    actual = canonical_json(run_result, list(ignore or ()))
+    if update is None:
+        update = os.environ.get(UPDATE_ENV, "") not in ("", "0")
+
+    if update or not os.path.exists(golden_file):
+        directory = os.path.dirname(golden_file)
+        if directory:
+            os.makedirs(directory, exist_ok=True)
+        with open(golden_file, "w", encoding="utf-8") as f:
+            f.write(actual)
+        return
+
+    with open(golden_file, encoding="utf-8") as f:
+        expected = f.read()
+    if actual != expected:
+        diff = "".join(difflib.unified_diff(
+            expected.splitlines(keepends=True),
+            actual.splitlines(keepends=True),
+            fromfile=golden_file,
+            tofile="actual",
+        ))
+        raise AssertionError(
+            f"transcript does not match {golden_file} "
+            f"(set {UPDATE_ENV}=1 to update it):\n{diff}"
+        )
+
+
+def run_dataset(agent, dataset, watch=None, compare="exact", embeddings=None,
+                judge=None, threshold=0.9, update=None):
+    """Run `agent` on each example of `dataset`, returning a dict of outputs by id.
+
+    An example is an input string, or a dict with an `"input"` and an optional `"id"`;
+    the id defaults to the input. With `watch` (a baseline file path), each output is
+    compared with the baseline's: `"exact"` compares the text, `"embedding"` requires a
+    cosine similarity of at least `threshold` between `embeddings` vectors, and
+    `"judge"` asks the `judge` model whether the new output is worse. Regressions raise
+    an `AssertionError` with a diff for each. A missing baseline file, or examples it
+    doesn't hold yet, are recorded and pass.
+    """
+    if compare not in COMPARISONS:
+        raise ValueError(f"compare must be one of {', '.join(COMPARISONS)}, not {compare!r}")
+    if compare == "embedding" and embeddings is None:
+        raise ValueError('compare="embedding" needs `embeddings`')
+    if compare == "judge" and judge is None:
+        raise ValueError('compare="judge" needs a `judge` model')
+
+    outputs = {}
+    for example in dataset:
+        if isinstance(example, str):
+            example_id, query = example, example
+        else:
+            query = example["input"]
+            example_id = str(example.get("id", query))
+        if example_id in outputs:
+            raise ValueError(f"dataset has two examples with id {example_id!r}")
+        result = agent.run(query)
+        outputs[example_id] = getattr(result, "text", result)
+    if watch is None:
+        return outputs
+
+    if update is None:
+        update = os.environ.get(UPDATE_ENV, "") not in ("", "0")
+    baseline = {}
+    if not update and os.path.exists(watch):
+        with open(watch, encoding="utf-8") as f:
+            baseline = json.load(f)
+
+    regressions = []
+    for example_id, actual in outputs.items():
+        if example_id not in baseline:
+            continue
+        reason = _regression(baseline[example_id], actual, compare, embeddings, judge, threshold)
+        if reason is not None:
+            regressions.append((example_id, reason, baseline[example_id], actual))
+    if regressions:
+        raise AssertionError(
+            f"{len(regressions)} of {len(outputs)} outputs regressed from {watch} "
+            f"(set {UPDATE_ENV}=1 to update it):\n"
+            + "\n".join(_report(*regression) for regression in regressions)
+        )
+
+    if update or set(outputs) - set(baseline):
+        directory = os.path.dirname(watch)
+        if directory:
+            os.makedirs(directory, exist_ok=True)
+        with open(watch, "w", encoding="utf-8") as f:
+            json.dump({**baseline, **outputs}, f, indent=2, sort_keys=True, ensure_ascii=False)
+            f.write("\n")
+    return outputs
+
+
+def _regression(expected, actual, compare, embeddings, judge, threshold):
+    """Why `actual` is a regression from `expected`, or `None` if it isn't."""
+    if expected == actual:
+        return None
+    if compare == "exact":
+        return "output changed"
+    if compare == "embedding":
+        a, b = embeddings.embed([expected, actual])
+        norm = math.sqrt(sum(x * x for x in a)) * math.sqrt(sum(y * y for y in b))
+        similarity = sum(x * y for x, y in zip(a, b)) / norm if norm else 0.0
+        if similarity < threshold:
+            return f"similarity {similarity:.3f} is below {threshold}"
+        return None
+    text = f"Baseline answer:\n{expected}\n\nNew answer:\n{actual}"
+    verdict = _rust.classify(text, ["equivalent", "regressed"], judge, JUDGE_INSTRUCTIONS)
+    return "judged a regression" if verdict.label == "regressed" else None
+
+
+def _report(example_id, reason, expected, actual):
+    diff = "\n".join(difflib.unified_diff(
+        str(expected).splitlines(),
+        str(actual).splitlines(),
+        fromfile="baseline",
+        tofile="actual",
+        lineterm="",
+    ))
+    return f"{example_id!r}: {reason}\n{diff}\n"
+
+
+__all__ = ['assert_transcript_matches', 'canonical_json', 'run_dataset']
@@ -3,6 +3,9 @@ Tool helpers for rusted_chain.
 Leverages LangChain for tool definition and execution.
 """
 
+import functools
+import inspect
+
 from langchain_core.tools import tool as lc_tool
 
 # Re-export the LangChain tool decorator
@@ -14,9 +17,14 @@ class ToolAdapter:
     Converts LangChain/Pydantic schemas to a clean JSON format suitable for LLMs.
     """
     
-    def __init__(self, tool):
+    def __init__(self, tool, context_func=None, strict=None):
         self.tool = tool
         self.__name__ = tool.name
+        # OpenAI strict function calling; None defers to the agent's `strict_tools`.
+        self.strict = strict
+        # Tools taking a RunContext are called directly with `ctx` filled in by the agent.
+        self._context_func = context_func
+        self.takes_context = context_func is not None
         
         # Extract schema using LangChain's built-in methods
         # The fun stuff is here
@@ -40,8 +48,10 @@ class ToolAdapter:
             "parameters": parameters
         }
 
-    def __call__(self, **kwargs):
+    def __call__(self, ctx=None, **kwargs):
         """Execute the underlying LangChain tool."""
+        if self.takes_context:
+            return self._context_func(ctx, **kwargs)
         return self.tool.invoke(kwargs)
     
     def to_dict(self):
@@ -49,10 +59,58 @@ class ToolAdapter:
         return self.schema
 
 
-def ensure_tool_wrapper(obj):
+def _context_parameter(func):
+    """Name of the reserved first parameter receiving the RunContext, if any."""
+    try:
+        params = list(inspect.signature(func).parameters.values())
+    except (TypeError, ValueError):
+        return None
+    if not params:
+        return None
+    first = params[0]
+    annotation = first.annotation
+    if isinstance(annotation, str):
+        is_context = annotation.endswith("RunContext")
+    else:
+        is_context = getattr(annotation, "__name__", None) == "RunContext"
+    if first.name == "ctx" or is_context:
+        return first.name
+    return None
+
+
+def _without_context(func, name):
+    """A stand-in for `func` whose signature hides the context parameter from the schema."""
+    signature = inspect.signature(func)
+
+    def schema_only(**kwargs):
+        raise RuntimeError("context tools are called through ToolAdapter")
+
+    functools.update_wrapper(schema_only, func)
+    del schema_only.__wrapped__
+    schema_only.__signature__ = signature.replace(
+        parameters=[p for p in signature.parameters.values() if p.name != name])
+    schema_only.__annotations__ = {k: v for k, v in func.__annotations__.items() if k != name}
+    return schema_only
+
+
+def ensure_tool_wrapper(obj, strict=None):
     if isinstance(obj, ToolAdapter):
+        if strict is not None:
+            obj.strict = strict
         return obj
-    
+    # Tools that bring their own schema, such as `Delegate`, are used as they are.
+    if callable(obj) and hasattr(obj, "to_dict") and not hasattr(obj, "get_input_schema"):
+        return obj
+    if strict is None:
+        strict = getattr(obj, "strict", None)
+
+    # Functions asking for the run context: build the schema without that parameter.
+    if callable(obj) and not hasattr(obj, "get_input_schema"):
+        context = _context_parameter(obj)
+        if context is not None:
+            return ToolAdapter(lc_tool(_without_context(obj, context)), context_func=obj,
+                               strict=strict)
+
     # If it's a raw function (and not a LangChain tool), convert it
     if callable(obj) and not hasattr(obj, "get_input_schema"):
         # Use LangChain's tool function to wrap it
@@ -60,6 +118,6 @@ def ensure_tool_wrapper(obj):
         
     # Check for LangChain tool interface
     if hasattr(obj, "name") and hasattr(obj, "description") and hasattr(obj, "invoke") and hasattr(obj, "get_input_schema"):
-        return ToolAdapter(obj)
+        return ToolAdapter(obj, strict=strict)
     
     raise ValueError(f"Object {obj} is not a valid tool or callable function")
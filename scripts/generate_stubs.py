@@ -0,0 +1,638 @@
+"""Generate the type stubs for the native module from the Rust sources.
+
+Reads the `#[pyclass]`, `#[pymethods]` and `#[pyfunction]` items registered in
+`src/lib.rs` and writes `rusted_chain/rusted_chain.pyi` and `rusted_chain/__init__.pyi`,
+so IDEs and mypy can check code that uses rusted_chain. Run it after changing the
+Python API; `--check` fails instead of writing when the stubs are out of date.
+
+    python scripts/generate_stubs.py [--check]
+"""
+
+import re
+import sys
+from pathlib import Path
+
+ROOT = Path(__file__).resolve().parent.parent
+SRC = ROOT / "src"
+PACKAGE = ROOT / "rusted_chain"
+
+HEADER = "# Generated by scripts/generate_stubs.py from the Rust sources; do not edit.\n"
+
+# Rust types to their Python equivalents. Types not listed here are either pyclasses,
+# named by their Python name, or accept whatever Python object converts: `Any`.
+SCALARS = {
+    "String": "str",
+    "str": "str",
+    "char": "str",
+    "Cow": "str",
+    "bool": "bool",
+    "f32": "float",
+    "f64": "float",
+    "PyBytes": "bytes",
+    "PyString": "str",
+    "PyDict": "dict[str, Any]",
+    "PyList": "list[Any]",
+    "PyTuple": "tuple[Any, ...]",
+    "PyType": "type",
+    "PyAny": "Any",
+    "PyObject": "Any",
+    "Value": "Any",
+    "Duration": "float",
+    "()": "None",
+}
+INTEGERS = {"i8", "i16", "i32", "i64", "isize", "u8", "u16", "u32", "u64", "usize"}
+WRAPPERS = {"Py", "Bound", "Borrowed", "PyRef", "PyRefMut", "Box", "Arc", "PyResult"}
+EXCEPTION_BASES = {"PyRuntimeError": "RuntimeError", "PyValueError": "ValueError"}
+
+
+def mask(text):
+    """`text` with comments and string and char literals blanked to spaces, so braces
+    and keywords can be found by position. Newlines are kept, and so are offsets."""
+    out = list(text)
+    i, n = 0, len(text)
+
+    def blank(start, end):
+        for j in range(start, end):
+            if out[j] != "\n":
+                out[j] = " "
+
+    while i < n:
+        c = text[i]
+        if text.startswith("//", i):
+            end = text.find("\n", i)
+            end = n if end < 0 else end
+            blank(i, end)
+            i = end
+        elif text.startswith("/*", i):
+            end = text.find("*/", i + 2)
+            end = n if end < 0 else end + 2
+            blank(i, end)
+            i = end
+        elif c == "r" and re.match(r'r#*"', text[i:i + 8]) and not (
+            i and (text[i - 1].isalnum() or text[i - 1] == "_")
+        ):
+            hashes = re.match(r"r(#*)\"", text[i:]).group(1)
+            end = text.find('"' + hashes, i + len(hashes) + 2)
+            end = n if end < 0 else end + 1 + len(hashes)
+            blank(i + 1, end - 1)
+            i = end
+        elif c == '"':
+            j = i + 1
+            while j < n and text[j] != '"':
+                j += 2 if text[j] == "\\" else 1
+            blank(i + 1, j)
+            i = j + 1
+        elif c == "'":
+            # A char literal ('x', '\n', '{'); otherwise a lifetime.
+            literal = re.match(r"'(\\.[^']*|[^\\'])'", text[i:])
+            if literal:
+                blank(i + 1, i + literal.end() - 1)
+                i += literal.end()
+            else:
+                i += 1
+        else:
+            i += 1
+    return "".join(out)
+
+
+def closing(masked, start, pair="{}"):
+    """The offset just past the bracket matching the one at `start`."""
+    depth = 0
+    for i in range(start, len(masked)):
+        if masked[i] == pair[0]:
+            depth += 1
+        elif masked[i] == pair[1]:
+            depth -= 1
+            if depth == 0:
+                return i + 1
+    raise ValueError("unbalanced brackets")
+
+
+def split_top(text, sep=","):
+    """`text` split on `sep` outside brackets."""
+    parts, depth, current = [], 0, ""
+    for c in text:
+        if c in "<([{":
+            depth += 1
+        elif c in ">)]}":
+            depth -= 1
+        if c == sep and depth == 0:
+            parts.append(current.strip())
+            current = ""
+        else:
+            current += c
+    if current.strip():
+        parts.append(current.strip())
+    return parts
+
+
+def preamble(text, offset):
+    """The doc comment and attributes written above the item at `offset`."""
+    lines = text[:offset].rstrip(" ").split("\n")[:-1]
+    docs, attrs = [], []
+    while lines:
+        line = lines[-1].strip()
+        if line.startswith("///"):
+            docs.insert(0, line[4:] if line.startswith("/// ") else line[3:])
+        elif line.startswith("#[") or line.startswith("#!["):
+            attrs.insert(0, line)
+        elif line.startswith("//") and not line.startswith("//!"):
+            pass
+        else:
+            break
+        lines.pop()
+    return "\n".join(docs).strip(), attrs
+
+
+def parse_type(rust):
+    """A Rust type as `(name, [arguments])`, references and lifetimes dropped."""
+    rust = rust.strip()
+    rust = re.sub(r"^&(\s*'\w+)?(\s*mut)?\s*", "", rust)
+    rust = re.sub(r"^(dyn|impl)\s+", "", rust)
+    if rust.startswith("("):
+        inner = rust[1:closing(rust, 0, "()") - 1]
+        return ("tuple", [parse_type(part) for part in split_top(inner)])
+    if rust.startswith("["):
+        inner = rust[1:closing(rust, 0, "[]") - 1]
+        return ("Vec", [parse_type(split_top(inner, ";")[0])])
+    match = re.match(r"([\w:]+)\s*(<(.*)>)?$", rust, re.S)
+    if not match:
+        return ("Any", [])
+    name = match.group(1).split("::")[-1]
+    arguments = [
+        parse_type(argument)
+        for argument in split_top(match.group(3) or "")
+        if not argument.startswith("'")
+    ]
+    return (name, arguments)
+
+
+class Stubs:
+    def __init__(self):
+        self.files = {path: path.read_text() for path in sorted(SRC.rglob("*.rs"))}
+        self.masked = {path: mask(text) for path, text in self.files.items()}
+        self.classes = {}  # Rust name -> class description
+        self.functions = {}  # Rust name -> function description
+        self.constants = {}  # Rust name -> parsed Rust type
+        self.exceptions = []
+        for path in list(self.files):
+            self.scan(path)
+
+    def scan(self, path):
+        text, masked = self.files[path], self.masked[path]
+        macros = self.method_macros(path)
+        masked = self.masked[path]
+        for match in re.finditer(r"#\[pyclass(\(([^\]]*)\))?\]", masked):
+            self.pyclass(path, match)
+        for match in re.finditer(r"#\[pymethods\]", masked):
+            self.pymethods(path, match.end())
+        for match in re.finditer(r"#\[pyfunction\]", masked):
+            item = re.compile(r"\bfn\s").search(masked, match.end())
+            function = self.function(path, item.start())
+            self.functions[function["rust"]] = function
+        for match in re.finditer(r"\bconst\s+(\w+)\s*:\s*([^=]+)=", masked):
+            self.constants[match.group(1)] = parse_type(match.group(2))
+        for match in re.finditer(r"\b(\w+)!\(\s*(\w+)\s*(?:\(([^)]*)\))?\s*\)\s*;", masked):
+            name, model, args = match.groups()
+            if name in macros:
+                self.expand(path, macros[name], model, split_top(args or ""))
+        for match in re.finditer(r"create_exception!\(\s*\w+\s*,\s*(\w+)\s*,\s*(\w+)\s*\)", masked):
+            name, base = match.groups()
+            docs, _ = preamble(text, match.start())
+            self.exceptions.append((name, EXCEPTION_BASES.get(base, base), docs))
+
+    def method_macros(self, path):
+        """The `macro_rules!` in a file that write a `#[pymethods]` block, invoked as
+        `name!(Class)` or `name!(Class(arg, ...))`, by name. Their definitions are blanked
+        from the masked text, so the block isn't read as an `impl` of its own."""
+        text, masked = self.files[path], self.masked[path]
+        arm = re.compile(
+            r"\(\s*\$(\w+):ident\s*\(\s*\$\(\s*\$(\w+):ident\s*\),\*\s*\)\s*\)\s*=>\s*\{"
+        )
+        macros = {}
+        for match in re.finditer(r"macro_rules!\s+(\w+)\s*\{", masked):
+            end = closing(masked, match.end() - 1)
+            found = arm.search(masked, match.end(), end)
+            if not found:
+                continue
+            body_end = closing(masked, found.end() - 1) - 1
+            if "#[pymethods]" not in masked[found.end():body_end]:
+                continue
+            macros[match.group(1)] = (
+                found.group(1),
+                found.group(2),
+                text[found.end():body_end],
+                masked[found.end():body_end],
+            )
+            masked = masked[:match.start()] + re.sub(r"[^\n]", " ", masked[match.start():end]) + masked[end:]
+        self.masked[path] = masked
+        return macros
+
+    def expand(self, path, macro, model, args):
+        """Read the `#[pymethods]` block a method macro writes for `model`."""
+        model_var, arg_var, template, masked = macro
+        out, i = "", 0
+        while i < len(template):
+            if template.startswith("$(", i):
+                end = closing(masked, i + 1, "()")
+                inner = template[i + 2:end - 1]
+                separator = "" if template[end] == "*" else template[end]
+                i = end + 1 + len(separator)
+                out += separator.join(inner.replace("$" + arg_var, arg) for arg in args)
+            else:
+                out += template[i]
+                i += 1
+        out = out.replace("$" + model_var, model)
+        virtual = Path("%s!%s" % (path, model))
+        self.files[virtual], self.masked[virtual] = out, mask(out)
+        self.pymethods(virtual, self.masked[virtual].index("#[pymethods]") + len("#[pymethods]"))
+
+    def pyclass(self, path, match):
+        text, masked = self.files[path], self.masked[path]
+        options = [option.strip() for option in split_top(match.group(2) or "")]
+        item = re.compile(r"\b(struct|enum)\s+(\w+)").search(masked, match.end())
+        rust = item.group(2)
+        name = rust
+        for option in options:
+            renamed = re.match(r'name\s*=\s*"(\w+)"', text[match.start(2):match.end(2)])
+            if option.startswith("name") and renamed:
+                name = renamed.group(1)
+        docs, _ = preamble(text, match.start())
+        body_start = masked.find("{", item.end())
+        semicolon = masked.find(";", item.end())
+        fields, variants = [], []
+        if body_start >= 0 and (semicolon < 0 or body_start < semicolon):
+            body_end = closing(masked, body_start)
+            if item.group(1) == "struct":
+                fields = self.fields(path, body_start + 1, body_end - 1, "get_all" in options)
+            else:
+                variants = self.variants(path, body_start + 1, body_end - 1)
+        self.classes[rust] = {
+            "name": name,
+            "docs": docs,
+            "fields": fields,
+            "variants": variants,
+            "methods": self.classes.get(rust, {}).get("methods", []),
+        }
+
+    def fields(self, path, start, end, get_all):
+        text, masked = self.files[path], self.masked[path]
+        fields = []
+        offset = start
+        for part in split_top(masked[start:end]):
+            at = masked.find(part, offset)
+            offset = at + len(part)
+            declaration = re.search(r"(?:pub(?:\([\w:]+\))?\s+)?(\w+)\s*:\s*(.+)$", part, re.S)
+            if not declaration:
+                continue
+            field_at = at + declaration.start(1)
+            docs, attrs = preamble(text, field_at - (field_at - text.rfind("\n", 0, field_at)) + 1)
+            access = " ".join(attrs)
+            if not (get_all or re.search(r"#\[pyo3\([^)]*\bget\b", access)):
+                continue
+            fields.append(
+                {
+                    "name": declaration.group(1),
+                    "type": parse_type(declaration.group(2)),
+                    "settable": bool(re.search(r"#\[pyo3\([^)]*\bset\b", access)),
+                    "docs": docs,
+                }
+            )
+        return fields
+
+    def variants(self, path, start, end):
+        masked = self.masked[path]
+        variants = []
+        for match in re.finditer(r"(\w+)\s*\{", masked[start:end]):
+            body = start + match.end() - 1
+            if masked[start:body].count("{") != masked[start:body].count("}"):
+                continue
+            variants.append(
+                (match.group(1), self.fields(path, body + 1, closing(masked, body) - 1, True))
+            )
+        return variants
+
+    def pymethods(self, path, offset):
+        masked = self.masked[path]
+        item = re.compile(r"\bimpl\s+(<[^>]*>\s*)?(\w+)[^{]*\{").search(masked, offset)
+        rust = item.group(2)
+        start = item.end() - 1
+        end = closing(masked, start)
+        depth = 0
+        methods = []
+        i = start + 1
+        while i < end - 1:
+            c = masked[i]
+            if c == "{":
+                depth += 1
+            elif c == "}":
+                depth -= 1
+            elif depth == 0:
+                fn = re.match(r"\bfn\s", masked[i:i + 3]) if not masked[i - 1].isalnum() else None
+                const = re.match(r"\bconst\s+(\w+)\s*:\s*([^=]+)=", masked[i:i + 200]) if (
+                    not masked[i - 1].isalnum() and masked.startswith("const", i)
+                ) else None
+                if fn:
+                    methods.append(self.function(path, i, rust))
+                    i = self.body_end(path, i)
+                    continue
+                if const:
+                    docs, attrs = preamble(self.files[path], i)
+                    if any(attr.startswith("#[classattr") for attr in attrs):
+                        methods.append(
+                            {
+                                "kind": "classattr",
+                                "name": const.group(1),
+                                "type": parse_type(const.group(2)),
+                                "docs": docs,
+                            }
+                        )
+            i += 1
+        self.classes.setdefault(rust, {"name": rust, "docs": "", "fields": [], "variants": [],
+                                       "methods": []})
+        self.classes[rust]["methods"].extend(methods)
+
+    def body_end(self, path, offset):
+        """The offset past the body of the function declared at `offset`."""
+        masked = self.masked[path]
+        params = masked.find("(", offset)
+        after = closing(masked, params, "()")
+        body = masked.find("{", after)
+        return closing(masked, body)
+
+    def function(self, path, offset, owner=None):
+        text, masked = self.files[path], self.masked[path]
+        docs, attrs = preamble(text, offset)
+        name = re.match(r"fn\s+(\w+)", masked[offset:]).group(1)
+        params_start = masked.find("(", offset)
+        params_end = closing(masked, params_start, "()")
+        body = masked.find("{", params_end)
+        returns = masked[params_end:body].strip()
+        returns = re.sub(r"\bwhere\b.*$", "", returns, flags=re.S).strip()
+        returns = returns[2:].strip() if returns.startswith("->") else "()"
+        params = []
+        for param in split_top(masked[params_start + 1:params_end - 1]):
+            if re.match(r"(&\s*('\w+\s+)?)?(mut\s+)?self\b", param):
+                continue
+            pattern, _, rust_type = param.partition(":")
+            if not params and parse_type(rust_type)[1][:1] == [("Self", [])]:
+                continue  # `slf: PyRefMut<'_, Self>` is the receiver
+            params.append((re.sub(r"^(mut\s+|r#)", "", pattern.strip()), rust_type.strip()))
+        attributes = " ".join(attrs)
+        signature = re.search(r"signature\s*=\s*\((.*)\)\s*\)\]", attributes)
+        renamed = re.search(r'#\[pyo3\([^\]]*\bname\s*=\s*"(\w+)"', attributes)
+        getter = re.search(r"#\[getter(?:\((\w+)\))?\]", attributes)
+        setter = re.search(r"#\[setter(?:\((\w+)\))?\]", attributes)
+        kind = "method"
+        if "#[new]" in attributes:
+            kind = "new"
+        elif getter:
+            kind = "getter"
+            name = getter.group(1) or name
+        elif setter:
+            kind = "setter"
+            name = setter.group(1) or re.sub(r"^set_", "", name)
+        elif "#[staticmethod]" in attributes:
+            kind = "staticmethod"
+        elif "#[classmethod]" in attributes:
+            kind = "classmethod"
+            params = params[1:]
+        return {
+            "kind": kind,
+            "rust": name,
+            "name": renamed.group(1) if renamed else name,
+            "owner": owner,
+            "docs": docs,
+            "params": params,
+            "signature": signature.group(1) if signature else None,
+            "returns": returns,
+        }
+
+    def python(self, parsed, owner=None, returning=False):
+        """The Python annotation for a parsed Rust type."""
+        name, arguments = parsed
+        if name in ("Python", "Self") and not arguments:
+            return self.classes[owner]["name"] if owner and name == "Self" else "Any"
+        if name in WRAPPERS:
+            if not arguments:
+                return "Any"
+            return self.python(arguments[0], owner, returning)
+        if name == "Option":
+            inner = self.python(arguments[0], owner, returning)
+            return inner if inner in ("Any", "None") or inner.endswith("| None") else inner + " | None"
+        if name in ("Vec", "VecDeque", "HashSet", "BTreeSet", "IndexSet"):
+            container = "set" if name.endswith("Set") else "list"
+            return "%s[%s]" % (container, self.python(arguments[0], owner, returning))
+        if name in ("HashMap", "BTreeMap", "IndexMap"):
+            return "dict[%s, %s]" % tuple(self.python(argument, owner, returning) for argument in arguments[:2])
+        if name == "tuple":
+            if not arguments:
+                return "None"
+            return "tuple[%s]" % ", ".join(self.python(argument, owner, returning) for argument in arguments)
+        if name == "PathBuf" or name == "Path":
+            return "pathlib.Path" if returning else "str | os.PathLike[str]"
+        if name in INTEGERS:
+            return "int"
+        if name in SCALARS:
+            return SCALARS[name]
+        if name in self.classes:
+            return self.classes[name]["name"]
+        return "Any"
+
+    def parameters(self, function, owner):
+        """The Python parameter list of a function, following its pyo3 signature."""
+        parsed = {
+            pattern: parse_type(rust_type)
+            for pattern, rust_type in function["params"]
+            if parse_type(rust_type)[0] != "Python"
+        }
+        types = {pattern: self.python(parsed_type, owner) for pattern, parsed_type in parsed.items()}
+        if function["signature"] is None:
+            return ["%s: %s" % (pattern, annotation) for pattern, annotation in types.items()]
+        params = []
+        for part in split_top(function["signature"]):
+            if part in ("*", "/"):
+                params.append(part)
+                continue
+            pattern, has_default, default = (piece.strip() for piece in part.partition("="))
+            bare = pattern.lstrip("*")
+            if pattern.startswith("*") and not pattern.startswith("**") and bare in parsed:
+                # `*args` collected into a Vec: annotated with the element type.
+                name, arguments = parsed[bare]
+                annotation = self.python(arguments[0], owner) if name == "Vec" else "Any"
+            elif pattern.startswith("*"):
+                annotation = "Any"
+            else:
+                annotation = types.get(bare, "Any")
+            entry = "%s: %s" % (pattern, annotation)
+            if has_default:
+                entry += " = " + python_default(default)
+            params.append(entry)
+        return params
+
+
+def python_default(rust):
+    """A default from a pyo3 signature, as written in Python."""
+    if rust in ("None", "true", "false"):
+        return {"None": "None", "true": "True", "false": "False"}[rust]
+    if re.fullmatch(r"-?\d+(\.\d+)?(_?[iuf]\d+)?", rust):
+        return re.sub(r"_?[iuf]\d+$", "", rust)
+    if re.fullmatch(r'"[^"]*"', rust):
+        return rust
+    return "..."
+
+
+def docstring(docs, indent):
+    if not docs:
+        return []
+    docs = docs.replace("\\", "\\\\").replace('"""', '\\"\\"\\"')
+    lines = docs.split("\n")
+    if len(lines) == 1:
+        return [indent + '"""%s"""' % lines[0]]
+    return [indent + '"""' + lines[0]] + [
+        (indent + line) if line else "" for line in lines[1:]
+    ] + [indent + '"""']
+
+
+def registered(lib):
+    """Names registered on the module, in order: classes, functions, constants and
+    exceptions, as `(kind, rust name, python name)`."""
+    items = []
+    for match in re.finditer(
+        r"add_class::<(?:[\w]+::)*(\w+)>|wrap_pyfunction!\((?:[\w]+::)*(\w+)|"
+        r'm\.add\("(\w+)",\s*(?:py\.get_type::<(\w+)>\(\)|(?:[\w]+::)*(\w+))\)',
+        lib,
+    ):
+        cls, function, name, exception, constant = match.groups()
+        if cls:
+            items.append(("class", cls, None))
+        elif function:
+            items.append(("function", function, None))
+        elif exception:
+            items.append(("exception", exception, name))
+        else:
+            items.append(("constant", constant, name))
+    return items
+
+
+def native(stubs):
+    lib = stubs.files[SRC / "lib.rs"]
+    module = lib[stubs.masked[SRC / "lib.rs"].find("#[pymodule]"):]
+    lines = [HEADER, "import os", "import pathlib", "from types import ModuleType",
+             "from typing import Any", ""]
+    for kind, rust, name in registered(module):
+        if kind == "constant":
+            lines += ["%s: %s" % (name, stubs.python(stubs.constants.get(rust, ("Any", [])))), ""]
+        elif kind == "function":
+            function = stubs.functions[rust]
+            lines += ["", "def %s(%s) -> %s:" % (
+                function["name"],
+                ", ".join(stubs.parameters(function, None)),
+                stubs.python(parse_type(function["returns"]), returning=True),
+            )]
+            lines += docstring(function["docs"], "    ") + ["    ...", ""]
+        elif kind == "exception":
+            base, docs = next((base, docs) for n, base, docs in stubs.exceptions if n == rust)
+            lines += ["", "class %s(%s):" % (name, base)]
+            lines += docstring(docs, "    ") or ["    ..."]
+            lines += [""]
+        else:
+            lines += [""] + pyclass(stubs, rust) + [""]
+    lines += ["", "testing: ModuleType", "# Built with the `loaders` feature.", "loaders: ModuleType", ""]
+    text = "\n".join(lines)
+    return re.sub(r"\n{3,}", "\n\n\n", text).strip("\n") + "\n"
+
+
+def pyclass(stubs, rust):
+    cls = stubs.classes[rust]
+    lines = ["class %s:" % cls["name"]]
+    body = docstring(cls["docs"], "    ")
+    # Fields of an enum's variants read on any instance; pyo3's per-variant classes
+    # are left out, as their names would shadow module-level classes here.
+    named = {method["name"] for method in cls["methods"]}
+    fields = list(cls["fields"])
+    for _, variant in cls["variants"]:
+        for field in variant:
+            if field["name"] not in named:
+                named.add(field["name"])
+                fields.append(field)
+    for field in fields:
+        annotation = stubs.python(field["type"], rust, returning=True)
+        body += property_lines(field["name"], annotation, field["docs"], field["settable"])
+    setters = {method["name"] for method in cls["methods"] if method["kind"] == "setter"}
+    for method in cls["methods"]:
+        kind = method["kind"]
+        if kind == "classattr":
+            body += ["    %s: %s" % (method["name"], stubs.python(method["type"], rust))]
+            continue
+        if kind == "setter":
+            continue
+        returns = stubs.python(parse_type(method["returns"]), rust, returning=True)
+        if kind == "getter":
+            body += property_lines(method["name"], returns, method["docs"], method["name"] in setters)
+            continue
+        params = stubs.parameters(method, rust)
+        if kind == "new":
+            name, returns = "__init__", "None"
+        else:
+            name = method["name"]
+        if kind == "staticmethod":
+            body += ["    @staticmethod"]
+        elif kind == "classmethod":
+            body += ["    @classmethod"]
+            params = ["cls"] + params
+        else:
+            params = ["self"] + params
+        body += ["    def %s(%s) -> %s:" % (name, ", ".join(params), returns)]
+        body += docstring(method["docs"], "        ")
+        body += ["        ..."]
+    return lines + (body or ["    ..."])
+
+
+def property_lines(name, annotation, docs, settable):
+    lines = ["    @property", "    def %s(self) -> %s:" % (name, annotation)]
+    lines += docstring(docs, "        ") + ["        ..."]
+    if settable:
+        lines += ["    @%s.setter" % name, "    def %s(self, value: %s) -> None: ..." % (name, annotation)]
+    return lines
+
+
+def package(stubs):
+    """`__init__.pyi`: the package's exports, typed as the native classes they wrap."""
+    source = (PACKAGE / "__init__.py").read_text()
+    exports = re.findall(r"'(\w+)'", source[source.find("__all__"):])
+    helpers = re.findall(r"^(?:def|class) (\w+)|^(\w+) =", (PACKAGE / "tool_helpers.py").read_text(), re.M)
+    helpers = {name for pair in helpers for name in pair if name}
+    lines = [HEADER]
+    python = sorted(name for name in exports if name in helpers)
+    if python:
+        lines.append("from .tool_helpers import %s" % ", ".join("%s as %s" % (n, n) for n in python))
+    native_names = [name for name in exports if name not in helpers]
+    lines.append("from .rusted_chain import (")
+    lines += ["    %s as %s," % (name, name) for name in native_names]
+    lines += [")", "", "__all__ = ["]
+    lines += ["    %r," % name for name in exports]
+    lines += ["]"]
+    return "\n".join(lines).replace("'", '"') + "\n"
+
+
+def main():
+    stubs = Stubs()
+    outputs = {
+        PACKAGE / "rusted_chain.pyi": native(stubs),
+        PACKAGE / "__init__.pyi": package(stubs),
+    }
+    stale = [path for path, text in outputs.items()
+             if not path.exists() or path.read_text() != text]
+    if "--check" in sys.argv[1:]:
+        for path in stale:
+            print("%s is out of date; run scripts/generate_stubs.py" % path.relative_to(ROOT))
+        return 1 if stale else 0
+    for path in stale:
+        path.write_text(outputs[path])
+        print("wrote %s" % path.relative_to(ROOT))
+    return 0
+
+
+if __name__ == "__main__":
+    sys.exit(main())
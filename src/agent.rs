@@ -0,0 +1,1648 @@
+//! Provider-agnostic agent loop shared by the model classes.
+
+use crate::artifacts::ArtifactStore;
+use crate::citations::Citation;
+use crate::context::RunContext;
+use crate::error::RustedChainError;
+use crate::estimate::Estimate;
+use crate::feedback::Feedback;
+use crate::keys::{self, ApiKey};
+use crate::media::Attachment;
+use crate::moderation::{self, ModerationAction, ModerationResult};
+use crate::options::{AgentOptions, ContextOverflow, ToolLoopAction};
+use crate::pii::Redactor;
+use crate::run::{Labels, RunRecord};
+use crate::serialize::{self, Serializer};
+use crate::session::Entry;
+use crate::stream::{self, TextSink, TextStream};
+use crate::tool_args::{self, Coercion};
+use crate::{
+    audit, circuit, deadline, dry_run, estimate, export, idempotency, language, metrics, prefill,
+    retry, runtime, tool_usage, traces, usage_webhook,
+};
+use crate::{AgentResponse, ResponseMetadata, ToolCall, MAX_TOOL_ITERATIONS, RUNTIME};
+use chrono::{DateTime, Utc};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// A tool invocation requested by the model.
+#[derive(Clone)]
+pub(crate) struct ToolRequest {
+    /// Provider-assigned call id (OpenAI, Claude); Gemini matches results by name.
+    pub(crate) id: Option<String>,
+    pub(crate) name: String,
+    pub(crate) args: Value,
+}
+
+/// What the model produced for one turn.
+#[derive(Clone)]
+pub(crate) enum Turn {
+    Text(String),
+    ToolCall(ToolRequest),
+}
+
+impl Turn {
+    pub(crate) fn into_response(self, metadata: ResponseMetadata) -> AgentResponse {
+        match self {
+            Turn::Text(text) => AgentResponse::Text { text, metadata },
+            Turn::ToolCall(call) => AgentResponse::ToolCall {
+                tool_call: ToolCall {
+                    name: call.name,
+                    args: serde_json::to_string(&call.args).unwrap_or_else(|_| "{}".to_string()),
+                },
+                metadata,
+            },
+        }
+    }
+}
+
+/// Tokens billed for one or more model calls.
+#[derive(Clone, Copy, Default, Serialize)]
+pub(crate) struct Usage {
+    pub(crate) input_tokens: u64,
+    pub(crate) output_tokens: u64,
+}
+
+impl std::ops::AddAssign for Usage {
+    fn add_assign(&mut self, other: Usage) {
+        self.input_tokens += other.input_tokens;
+        self.output_tokens += other.output_tokens;
+    }
+}
+
+/// A provider client that can drive the agent loop with its own message format.
+pub(crate) trait ChatClient: Sync {
+    type Message: Clone + Send + Serialize + 'static;
+
+    /// Provider name used in run records, e.g. `"openai"`.
+    fn provider(&self) -> &'static str;
+
+    /// What the provider circuit breaker of this client's requests is keyed by.
+    fn breaker_key(&self) -> Cow<'_, str> {
+        Cow::Borrowed(self.provider())
+    }
+
+    fn model(&self) -> &str;
+
+    /// This client talking to `model` instead, such as a cheaper `tool_model`.
+    fn for_model(&self, model: &str) -> Self
+    where
+        Self: Sized;
+
+    fn api_key(&self) -> &ApiKey;
+
+    /// The system prompt sent with every request.
+    fn system(&self) -> Option<&str>;
+
+    /// End-user id attached to requests, if the provider supports one.
+    fn end_user(&self) -> Option<&str> {
+        None
+    }
+
+    fn user_message(text: &str) -> Self::Message;
+
+    /// An earlier answer from the model, for replaying conversation history.
+    fn assistant_message(text: &str) -> Self::Message;
+
+    /// An earlier tool call by the model, for replaying conversation history.
+    fn tool_call_message(call: &ToolRequest) -> Self::Message;
+
+    fn tool_result_message(call: &ToolRequest, result: Value) -> Self::Message;
+
+    /// `tool_result_message` with the result already written as JSON `text`, for
+    /// providers that send it as text; the others send `result` as it is.
+    fn tool_result_text_message(call: &ToolRequest, result: Value, _text: String) -> Self::Message {
+        Self::tool_result_message(call, result)
+    }
+
+    /// Sources cited by an answer, for providers that return them.
+    fn citations(_message: &Self::Message) -> Vec<Citation> {
+        Vec::new()
+    }
+
+    /// Whether `message`, a text answer, stopped at the output token limit.
+    fn truncated(_message: &Self::Message) -> bool {
+        false
+    }
+
+    /// This client carrying on `partial`, an answer cut off at the output token limit,
+    /// where the provider continues an answer natively; `None` to ask the model to go on.
+    fn continued(&self, _partial: &str) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        None
+    }
+
+    /// This client with `partial` as the start of its answer, to resume a stream that
+    /// dropped after sending it; `None` if the provider cannot pick an answer up.
+    fn resumed(&self, _partial: &str) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        None
+    }
+
+    /// A tool-free client for `model` with only `system` as instructions, for requests a
+    /// run makes on the side, such as translations; `None` if the provider has none.
+    fn side_client(&self, _model: &str, _system: &str) -> Option<Self>
+    where
+        Self: Sized,
+    {
+        None
+    }
+
+    /// Text the model wrote in the same turn as a tool call, such as what it is about
+    /// to look up, for providers that return both.
+    fn tool_call_text(_message: &Self::Message) -> Option<String> {
+        None
+    }
+
+    async fn exchange(
+        &self,
+        conversation: Vec<Self::Message>,
+    ) -> Result<(Turn, Self::Message, Usage), RustedChainError>;
+
+    /// Like `exchange`, but forwards text to `sink` as it arrives.
+    async fn exchange_stream(
+        &self,
+        conversation: Vec<Self::Message>,
+        sink: &TextSink,
+    ) -> Result<(Turn, Self::Message, Usage), RustedChainError>;
+
+    /// Open a connection to the provider, and with `prime`, send a one-token request.
+    async fn warmup(&self, prime: bool) -> Result<(), RustedChainError>;
+
+    /// A single tool-free turn with `files` attached to `prompt`. With a `schema`
+    /// (describing an object), the answer is JSON text matching it.
+    async fn multimodal(
+        &self,
+        prompt: &str,
+        files: &[Attachment],
+        schema: Option<&Value>,
+    ) -> Result<(String, Usage), RustedChainError>;
+
+    /// Send `payload`, a request body as the provider's API takes it, with the model and
+    /// key filled in, to `path` or the provider's usual endpoint. Returns the response.
+    async fn raw(&self, _payload: Value, _path: Option<&str>) -> Result<Value, RustedChainError> {
+        Err(RustedChainError::InvalidRequest(format!(
+            "{} does not take raw requests",
+            self.provider()
+        )))
+    }
+}
+
+pub(crate) fn wrap_tool_result(value: Value) -> Value {
+    match value {
+        Value::Object(_) => value,
+        other => json!({ "result": other }),
+    }
+}
+
+/// Index the configured Python tools by their `__name__`.
+pub(crate) fn tool_registry(
+    py: Python,
+    tools: &Option<Vec<Py<PyAny>>>,
+) -> HashMap<String, Py<PyAny>> {
+    let mut registry = HashMap::new();
+    for tool in tools.iter().flatten() {
+        if let Ok(name) = tool
+            .bind(py)
+            .getattr("__name__")
+            .and_then(|n| n.extract::<String>())
+        {
+            registry.insert(name, tool.clone_ref(py));
+        }
+    }
+    registry
+}
+
+/// Whether a tool asked for the run context, by setting `takes_context = True`.
+fn takes_context(tool: &Bound<'_, PyAny>) -> bool {
+    tool.getattr("takes_context")
+        .and_then(|flag| flag.is_truthy())
+        .unwrap_or(false)
+}
+
+pub(crate) fn execute_tool(
+    py: Python,
+    tools: &HashMap<String, Py<PyAny>>,
+    ctx: &Py<RunContext>,
+    call: &ToolRequest,
+    serializer: &Serializer,
+    coercion: Coercion,
+) -> PyResult<Value> {
+    let tool_fn = tools
+        .get(&call.name)
+        .ok_or_else(|| RustedChainError::ToolNotFound(call.name.clone()))?
+        .bind(py);
+
+    let kwargs = match tool_args::kwargs(py, tool_fn, &call.args, coercion)? {
+        Ok(kwargs) => kwargs,
+        // The model gets to correct the call rather than the tool failing on it.
+        Err(problems) => {
+            return Ok(json!({
+                "error": format!(
+                    "Invalid arguments for '{}':\n{}\nCall it again with corrected arguments.",
+                    call.name, problems
+                ),
+            }))
+        }
+    };
+    if takes_context(tool_fn) {
+        kwargs.set_item("ctx", ctx)?;
+    }
+    let result = tool_fn.call((), Some(&kwargs))?;
+
+    let artifacts = &ctx.borrow(py).artifacts;
+    if let Some(data) = ArtifactStore::binary(&result) {
+        return artifacts.store_binary(&call.name, &data);
+    }
+    artifacts.store_if_large(&call.name, serializer.to_json(&result)?)
+}
+
+/// `call` without the `null`s OpenAI's strict function calling makes the model send for
+/// optional arguments, so the tool's defaults apply. Under any other schema a `null`
+/// is the model's choice, and goes to the tool.
+fn strict_arguments<C: ChatClient>(
+    py: Python,
+    client: &C,
+    options: &AgentOptions,
+    tools: &HashMap<String, Py<PyAny>>,
+    mut call: ToolRequest,
+) -> ToolRequest {
+    let Some(tool) = tools.get(&call.name).map(|tool| tool.bind(py)) else {
+        return call;
+    };
+    if client.provider() == "openai" && crate::strict_flag(tool, options.strict_tools) {
+        call.args = tool_args::without_unset(tool, &call.args);
+    }
+    call
+}
+
+/// What to do with a tool call once it has been handled.
+enum ToolOutcome {
+    /// Send this result back to the model and keep looping.
+    Result(Value),
+    /// Stop the run, using this text as the answer.
+    Finish(String),
+}
+
+/// Counts identical tool calls (same name and arguments) so a model stuck repeating
+/// itself is caught well before `MAX_TOOL_ITERATIONS`.
+#[derive(Default)]
+struct LoopDetector {
+    seen: HashMap<String, (usize, Value)>,
+}
+
+fn handle_tool_call(
+    py: Python,
+    tools: &HashMap<String, Py<PyAny>>,
+    options: &AgentOptions,
+    detector: &mut LoopDetector,
+    ctx: &Py<RunContext>,
+    run: &mut RunRecord,
+    call: &ToolRequest,
+) -> PyResult<ToolOutcome> {
+    // serde_json keeps object keys sorted, so equal arguments serialize identically.
+    let key = format!("{}:{}", call.name, call.args);
+    if let Some((count, previous)) = detector.seen.get_mut(&key) {
+        *count += 1;
+        if *count >= options.tool_loop_threshold {
+            return match options.on_tool_loop {
+                ToolLoopAction::Nudge => Ok(ToolOutcome::Result(json!({
+                    "error": format!(
+                        "'{}' was already called {} times with these arguments. \
+                         Use the earlier result or answer the user directly.",
+                        call.name,
+                        *count - 1
+                    ),
+                    "previous_result": previous.clone(),
+                }))),
+                ToolLoopAction::Return => Ok(ToolOutcome::Finish(match previous {
+                    Value::String(text) => text.clone(),
+                    other => other.to_string(),
+                })),
+                ToolLoopAction::Raise => Err(RustedChainError::ToolLoop {
+                    name: call.name.clone(),
+                    count: *count,
+                }
+                .into()),
+            };
+        }
+    }
+
+    let cache = options.tool_caches.get(&call.name);
+    let result = match cache.and_then(|cache| cache.get(&call.args)) {
+        Some(result) => result,
+        None => {
+            let result = run_tool(py, tools, options, ctx, run, call)?;
+            if let Some(cache) = cache {
+                cache.insert(&call.args, result.clone());
+            }
+            result
+        }
+    };
+    ctx.borrow(py).record(py, &call.name, &call.args, &result)?;
+    detector
+        .seen
+        .entry(key)
+        .and_modify(|(_, previous)| *previous = result.clone())
+        .or_insert_with(|| (1, result.clone()));
+    Ok(ToolOutcome::Result(result))
+}
+
+/// Execute a tool call within its limits, recording its latency and result size.
+fn run_tool(
+    py: Python,
+    tools: &HashMap<String, Py<PyAny>>,
+    options: &AgentOptions,
+    ctx: &Py<RunContext>,
+    run: &mut RunRecord,
+    call: &ToolRequest,
+) -> PyResult<Value> {
+    let permit = options
+        .tool_limits
+        .get(&call.name)
+        .map(|limiter| py.detach(|| limiter.acquire()));
+    let started = Instant::now();
+    let serializer = options
+        .tool_serializer
+        .as_deref()
+        .unwrap_or(&serialize::DEFAULT);
+    let result = execute_tool(py, tools, ctx, call, serializer, options.coerce_tool_args);
+    drop(permit);
+    let elapsed = started.elapsed();
+    let result_bytes = result.as_ref().ok().map(|value| value.to_string().len());
+    metrics::record_tool(&call.name, elapsed, result_bytes);
+    run.record_tool(&call.name, elapsed, result_bytes);
+    tool_usage::record(&options.tool_usage, &call.name, elapsed, result_bytes);
+    result
+}
+
+/// Where the parts of a conversation that trimming drops whole begin.
+#[derive(Debug, Default, PartialEq)]
+struct Turns {
+    /// Start of each turn of the history sent ahead of the query: a user message and
+    /// everything up to the next one.
+    history: Vec<usize>,
+    /// Index of the current query, which is always kept.
+    query: usize,
+}
+
+impl Turns {
+    /// The turns of `history`, which the query follows.
+    fn of(history: &[Entry]) -> Self {
+        let mut starts: Vec<usize> = history
+            .iter()
+            .enumerate()
+            .filter(|(_, entry)| matches!(entry, Entry::User(_)))
+            .map(|(at, _)| at)
+            .collect();
+        if !history.is_empty() && starts.first() != Some(&0) {
+            starts.insert(0, 0);
+        }
+        Self {
+            history: starts,
+            query: history.len(),
+        }
+    }
+}
+
+/// Drop the older half of the history turns ahead of the current query, or once there
+/// are none, the older half of the tool exchanges that follow it. Every loop iteration
+/// appends an assistant turn plus its tool result, so those are removed in pairs, and
+/// history goes a whole turn at a time; a tool call never loses its result and each
+/// provider's role ordering stays valid.
+fn trim_conversation<T>(conversation: &mut Vec<T>, turns: &mut Turns) -> bool {
+    if !turns.history.is_empty() {
+        let dropped = turns.history.len().div_ceil(2);
+        let end = turns.history.get(dropped).copied().unwrap_or(turns.query);
+        conversation.drain(..end);
+        turns.history = turns.history[dropped..].iter().map(|at| at - end).collect();
+        turns.query -= end;
+        return true;
+    }
+    let exchanges = conversation.len().saturating_sub(turns.query + 1) / 2;
+    if exchanges == 0 {
+        return false;
+    }
+    let first = turns.query + 1;
+    conversation.drain(first..first + exchanges.div_ceil(2) * 2);
+    true
+}
+
+/// Send the conversation, trimming it and retrying once on a context-length error
+/// when the agent was configured with `on_context_overflow="trim"`.
+fn send_with_overflow_recovery<T: Clone, R>(
+    options: &AgentOptions,
+    conversation: &mut Vec<T>,
+    turns: &mut Turns,
+    mut send: impl FnMut(Vec<T>) -> Result<R, RustedChainError>,
+) -> PyResult<R> {
+    match send(conversation.clone()) {
+        Err(RustedChainError::ContextLengthExceeded(_))
+            if options.on_context_overflow == ContextOverflow::Trim
+                && trim_conversation(conversation, turns) =>
+        {
+            Ok(send(conversation.clone())?)
+        }
+        result => Ok(result?),
+    }
+}
+
+/// Record a finished provider request in the metrics registry, the usage webhooks and
+/// the audit log.
+fn record_outcome<C: ChatClient>(
+    client: &C,
+    options: &AgentOptions,
+    run: &RunRecord,
+    request: Option<Value>,
+    timestamp: DateTime<Utc>,
+    elapsed: Duration,
+    result: &Result<(Turn, C::Message, Usage), RustedChainError>,
+) {
+    let (status, usage) = match result {
+        Ok((_, _, usage)) => ("ok", *usage),
+        Err(err) => (err.kind(), Usage::default()),
+    };
+    metrics::record_request(client.provider(), client.model(), status, elapsed, usage);
+    usage_webhook::record(client, run, timestamp, elapsed, status, usage);
+    if let (Some(sink), Some(request)) = (&options.audit, request) {
+        audit::record_exchange(
+            sink,
+            client,
+            run,
+            client.system().map(str::to_string),
+            timestamp,
+            elapsed,
+            request,
+            result,
+        );
+    }
+}
+
+fn audit_request<M: Serialize>(options: &AgentOptions, messages: &[M]) -> Option<Value> {
+    options
+        .audit
+        .as_ref()
+        .map(|_| serde_json::to_value(messages).unwrap_or(Value::Null))
+}
+
+/// Make one provider request, recording it and its retries in the run, the metrics
+/// registry and the audit log.
+fn observed<C: ChatClient, F>(
+    client: &C,
+    options: &AgentOptions,
+    run: &mut RunRecord,
+    messages: Vec<C::Message>,
+    send: impl FnOnce(Vec<C::Message>) -> F + Send,
+) -> Result<(Turn, C::Message, Usage), RustedChainError>
+where
+    F: Future<Output = Result<(Turn, C::Message, Usage), RustedChainError>>,
+{
+    let key = request_key(client, options, &messages);
+    if let Some((turn, message)) = key.as_deref().and_then(idempotency::recall) {
+        return Ok((turn, message, Usage::default()));
+    }
+    let breaker = client.breaker_key();
+    circuit::admit(&breaker)?;
+    let request = audit_request(options, &messages);
+    let timestamp = Utc::now();
+    let started = Instant::now();
+    // Called with the GIL held by `invoke` and `run`, and without it by streams; other
+    // Python threads, such as an asyncio event loop, keep running while this waits.
+    let ((result, retries), served) = Python::attach(|py| {
+        py.detach(|| {
+            RUNTIME.block_on(keys::attributed(retry::recorded(idempotency::scoped(
+                key.clone(),
+                deadline::first_token(options.first_token_deadline, send(messages)),
+            ))))
+        })
+    });
+    circuit::record(&breaker, &result);
+    run.record_retries(
+        &retries,
+        result.as_ref().map_or_else(|err| err.kind(), |_| "ok"),
+    );
+    record_outcome(
+        client,
+        options,
+        run,
+        request,
+        timestamp,
+        started.elapsed(),
+        &result,
+    );
+    if let Ok((turn, message, usage)) = &result {
+        run.record_model_call(client.model(), *usage);
+        if let Some(served) = served {
+            served.add_usage(*usage);
+        }
+        if let Some(key) = key {
+            idempotency::remember(key, (turn.clone(), message.clone()));
+        }
+    }
+    result
+}
+
+/// The idempotency key of a request sending `messages`, in a call given one.
+fn request_key<C: ChatClient>(
+    client: &C,
+    options: &AgentOptions,
+    messages: &[C::Message],
+) -> Option<String> {
+    options
+        .idempotency_key
+        .as_deref()
+        .map(|base| idempotency::request_key(base, client.model(), messages))
+}
+
+/// Send `messages` with `send`, and again to each of `fallback_models` in turn while the
+/// provider misses `first_token_deadline_ms`.
+fn failing_over<C: ChatClient, T>(
+    client: &C,
+    options: &AgentOptions,
+    messages: Vec<C::Message>,
+    mut send: impl FnMut(&C, Vec<C::Message>) -> Result<T, RustedChainError>,
+) -> Result<T, RustedChainError> {
+    let retained = (!options.fallback_models.is_empty()).then(|| messages.clone());
+    let mut result = send(client, messages);
+    for model in &options.fallback_models {
+        let (Err(RustedChainError::FirstTokenDeadline(_)), Some(messages)) = (&result, &retained)
+        else {
+            break;
+        };
+        result = send(&client.for_model(model), messages.clone());
+    }
+    result
+}
+
+/// `observed` for a request that is not streamed, carrying on an answer the provider cut
+/// off.
+fn exchanged<C: ChatClient>(
+    client: &C,
+    options: &AgentOptions,
+    run: &mut RunRecord,
+    messages: Vec<C::Message>,
+) -> Result<(Turn, C::Message, Usage), RustedChainError> {
+    failing_over(client, options, messages, |client, messages| {
+        let retained = (options.max_continuations > 0).then(|| messages.clone());
+        let result = observed(client, options, run, messages, |messages| {
+            client.exchange(messages)
+        });
+        match retained {
+            Some(messages) => continued(client, options, run, None, &messages, result),
+            None => result,
+        }
+    })
+}
+
+/// Sent after an answer cut off at the output token limit, for providers that cannot
+/// carry it on themselves.
+const CONTINUE_PROMPT: &str =
+    "Your answer was cut off. Continue it exactly where it stopped, without repeating any of it.";
+
+/// Carry on `result` while it is a text answer cut off at the output token limit, with
+/// up to `max_continuations` more requests, and join the parts into one answer. With a
+/// `sink`, each part streams as it arrives.
+fn continued<C: ChatClient>(
+    client: &C,
+    options: &AgentOptions,
+    run: &mut RunRecord,
+    sink: Option<&TextSink>,
+    messages: &[C::Message],
+    mut result: Result<(Turn, C::Message, Usage), RustedChainError>,
+) -> Result<(Turn, C::Message, Usage), RustedChainError> {
+    let mut cut = matches!(&result, Ok((Turn::Text(_), message, _)) if C::truncated(message));
+    for _ in 0..options.max_continuations {
+        let (true, Ok((Turn::Text(text), _, usage))) = (cut, &result) else {
+            break;
+        };
+        let (text, mut usage) = (text.clone(), *usage);
+        let native = client.continued(&text);
+        let (sender, conversation) = match &native {
+            Some(native) => {
+                // The answer so far streams again first, as the start of the new one.
+                if let Some(sink) = sink {
+                    sink.skip_repeat(text.clone());
+                }
+                (native, messages.to_vec())
+            }
+            None => {
+                let mut conversation = messages.to_vec();
+                conversation.push(C::assistant_message(&text));
+                conversation.push(C::user_message(CONTINUE_PROMPT));
+                (client, conversation)
+            }
+        };
+        let (turn, mut message, more) =
+            observed(sender, options, run, conversation, |messages| async move {
+                match sink {
+                    Some(sink) => sender.exchange_stream(messages, sink).await,
+                    None => sender.exchange(messages).await,
+                }
+            })?;
+        cut = C::truncated(&message);
+        usage += more;
+        let turn = match (&native, turn) {
+            (None, Turn::Text(rest)) => {
+                let text = text + &rest;
+                message = C::assistant_message(&text);
+                Turn::Text(text)
+            }
+            (_, turn) => turn,
+        };
+        result = Ok((turn, message, usage));
+    }
+    result
+}
+
+/// `observed` for a streamed request, also recording how long its first text took and
+/// how fast the rest arrived.
+fn observed_stream<C: ChatClient>(
+    client: &C,
+    options: &AgentOptions,
+    run: &mut RunRecord,
+    sink: &TextSink,
+    messages: Vec<C::Message>,
+) -> Result<(Turn, C::Message, Usage), RustedChainError> {
+    let started = Instant::now();
+    sink.start_turn();
+    // A remembered answer is streamed whole; `observed` returns remembered tool calls.
+    let remembered = request_key(client, options, &messages)
+        .and_then(|key| idempotency::recall::<(Turn, C::Message)>(&key));
+    if let Some((Turn::Text(text), message)) = remembered {
+        RUNTIME.block_on(sink.send(text.clone()))?;
+        return Ok((Turn::Text(text), message, Usage::default()));
+    }
+    let retained =
+        (options.stream_resumes > 0 || options.max_continuations > 0).then(|| messages.clone());
+    let mut result = observed(client, options, run, messages, |messages| {
+        client.exchange_stream(messages, sink)
+    });
+    // A stream that dropped part way is asked for again, continuing from the text
+    // already sent, which is left out if the answer repeats it.
+    let mut resumes = 0;
+    while let (Err(RustedChainError::Network(_)), Some(messages)) = (&result, &retained) {
+        let partial = sink.turn_text();
+        if resumes >= options.stream_resumes || partial.is_empty() {
+            break;
+        }
+        let Some(resumed) = client.resumed(&partial) else {
+            break;
+        };
+        resumes += 1;
+        sink.skip_repeat(partial.clone());
+        result = observed(&resumed, options, run, messages.clone(), |messages| {
+            resumed.exchange_stream(messages, sink)
+        })
+        .map(|(turn, message, usage)| {
+            (prefill::ensure_prefix(Some(&partial), turn), message, usage)
+        });
+    }
+    if let Some(messages) = &retained {
+        result = continued(client, options, run, Some(sink), messages, result);
+    }
+    let result = result.and_then(|turn| RUNTIME.block_on(sink.flush()).map(|()| turn));
+    let finished = Instant::now();
+    // Turns that only call a tool stream no text and have no first token.
+    if let (Ok((_, _, usage)), Some(first_chunk)) = (&result, sink.take_request_first_chunk()) {
+        let generation = finished.saturating_duration_since(first_chunk);
+        let rate = (usage.output_tokens > 0 && !generation.is_zero())
+            .then(|| usage.output_tokens as f64 / generation.as_secs_f64());
+        metrics::record_stream(
+            client.provider(),
+            client.model(),
+            first_chunk.saturating_duration_since(started),
+            rate,
+        );
+        run.record_stream(first_chunk, rate);
+        if let Some(rate) = rate {
+            sink.set_tokens_per_second(rate);
+        }
+    }
+    result
+}
+
+/// A single-turn, tool-free completion for the batch helpers, recorded like any other
+/// request. Safe to run concurrently.
+pub(crate) async fn complete<C: ChatClient>(
+    client: &C,
+    options: &AgentOptions,
+    run: &RunRecord,
+    prompt: &str,
+) -> Result<(String, Usage), RustedChainError> {
+    let messages = vec![C::user_message(prompt)];
+    let key = request_key(client, options, &messages);
+    if let Some(text) = key.as_deref().and_then(idempotency::recall::<String>) {
+        return Ok((text, Usage::default()));
+    }
+    let breaker = client.breaker_key();
+    circuit::admit(&breaker)?;
+    let request = audit_request(options, &messages);
+    let timestamp = Utc::now();
+    let started = Instant::now();
+    let exchange = deadline::first_token(options.first_token_deadline, client.exchange(messages));
+    let (result, served) = keys::attributed(idempotency::scoped(key.clone(), exchange)).await;
+    circuit::record(&breaker, &result);
+    record_outcome(
+        client,
+        options,
+        run,
+        request,
+        timestamp,
+        started.elapsed(),
+        &result,
+    );
+    if let (Ok((_, _, usage)), Some(served)) = (&result, served) {
+        served.add_usage(*usage);
+    }
+    match result? {
+        (Turn::Text(text), _, usage) => {
+            if let Some(key) = key {
+                idempotency::remember(key, text.clone());
+            }
+            Ok((text, usage))
+        }
+        (Turn::ToolCall(call), _, _) => Err(RustedChainError::ToolExecutionNotSupported(call.name)),
+    }
+}
+
+/// Connect to the provider ahead of the first real request, returning the seconds it
+/// took. The connection stays in the shared HTTP client's pool for later calls.
+pub(crate) fn warmup<C: ChatClient + Sync>(py: Python, client: &C, prime: bool) -> PyResult<f64> {
+    let started = Instant::now();
+    py.detach(|| RUNTIME.block_on(client.warmup(prime)))?;
+    Ok(started.elapsed().as_secs_f64())
+}
+
+/// Send a request body the caller built and return the parsed response, for provider
+/// features without typed support. Streaming is not available this way.
+pub(crate) fn invoke_raw<C: ChatClient + Sync>(
+    py: Python,
+    client: &C,
+    payload: &Bound<'_, PyDict>,
+    path: Option<&str>,
+) -> PyResult<Py<PyAny>> {
+    let payload: Value = pythonize::depythonize(payload)?;
+    if payload
+        .get("stream")
+        .is_some_and(|stream| stream != &Value::Bool(false))
+    {
+        return Err(PyValueError::new_err(
+            "invoke_raw does not stream; leave \"stream\" out of the payload",
+        ));
+    }
+    let response = py.detach(|| RUNTIME.block_on(client.raw(payload, path)))?;
+    Ok(pythonize::pythonize(py, &response)?.unbind())
+}
+
+/// The first request a run of `query` would send, built as the run builds it but not
+/// sent: its URL, headers and body as a dict, with estimated input tokens and cost.
+/// `stream` builds the streamed request.
+pub(crate) fn dry_run<C: ChatClient>(
+    py: Python,
+    client: &C,
+    tools: &Option<Vec<Py<PyAny>>>,
+    options: &AgentOptions,
+    query: &str,
+    stream: bool,
+) -> PyResult<Py<PyAny>> {
+    if options.grpc {
+        return Err(PyValueError::new_err(
+            "dry_run is not available with transport='grpc'",
+        ));
+    }
+    let redactor = options.pii.clone().map(Redactor::new);
+    let conversation = vec![C::user_message(&outgoing(
+        py,
+        options,
+        redactor.as_ref(),
+        query,
+    )?)];
+    // The tool model makes a run's first request when there are tools to call.
+    let tool_client = match (tools, &options.tool_model) {
+        (Some(tools), Some(model)) if !tools.is_empty() => Some(client.for_model(model)),
+        _ => None,
+    };
+    let sender = tool_client.as_ref().unwrap_or(client);
+    let request = RUNTIME.block_on(dry_run::captured(async {
+        if stream {
+            let (sink, _stream) = stream::channel(options.stream_buffering);
+            sender.exchange_stream(conversation, &sink).await
+        } else {
+            sender.exchange(conversation).await
+        }
+    }))?;
+    Ok(pythonize::pythonize(py, &dry_run::describe(sender, &request))?.unbind())
+}
+
+/// The estimated prompt of a run sending `history` and `query`, for budget checks.
+fn planned<C: ChatClient>(client: &C, history: &[Entry], query: &str) -> Estimate {
+    let history: Vec<Value> = history.iter().map(Entry::to_json).collect();
+    let query = json!({ "role": "user", "content": query });
+    let tokens = estimate::prompt_tokens(
+        client.model(),
+        client.system(),
+        history.iter().chain([&query]),
+        &[],
+    );
+    Estimate::new(client.model(), tokens, 0)
+}
+
+/// Run the agent: a single-shot completion without tools, otherwise execute
+/// tools until the model produces a final answer.
+pub(crate) fn run_agent<C: ChatClient>(
+    py: Python,
+    client: &C,
+    tools: &Option<Vec<Py<PyAny>>>,
+    options: &AgentOptions,
+    query: &str,
+    tags: Option<Vec<String>>,
+    metadata: Option<Py<PyDict>>,
+) -> PyResult<AgentResponse> {
+    run_conversation(
+        py,
+        client,
+        tools,
+        options,
+        &mut Vec::new(),
+        &mut Usage::default(),
+        query,
+        tags,
+        metadata,
+    )
+}
+
+/// `run_agent` continuing an earlier conversation: `history` is sent ahead of `query`.
+/// After a text answer, the query, any tool calls with their results, and the answer
+/// are appended to it. The run's token usage is added to `spent`, whatever the outcome.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn run_conversation<C: ChatClient>(
+    py: Python,
+    client: &C,
+    tools: &Option<Vec<Py<PyAny>>>,
+    options: &AgentOptions,
+    history: &mut Vec<Entry>,
+    spent: &mut Usage,
+    query: &str,
+    tags: Option<Vec<String>>,
+    metadata: Option<Py<PyDict>>,
+) -> PyResult<AgentResponse> {
+    let _in_flight = runtime::admit()?;
+    let tenant = options.tenant.as_deref();
+    let metadata = match tenant {
+        Some(tenant) => tenant.label(py, metadata)?,
+        None => metadata,
+    };
+    let _permit = match tenant {
+        Some(tenant) => {
+            let planned = planned(client, history, query);
+            py.detach(|| tenant.admit(&planned))?
+        }
+        None => None,
+    };
+    let tools = tool_registry(py, tools);
+    let labels = Labels::from_py(py, tags, metadata.as_ref())?;
+    let mut run = RunRecord::start(
+        client,
+        options,
+        query,
+        tools.keys().cloned().collect(),
+        false,
+        labels,
+    );
+    let ctx = RunContext::create(py, &run.id, metadata, &options.artifacts)?;
+    let redactor = options
+        .pii
+        .clone()
+        .map(|rules| Arc::new(Redactor::new(rules)));
+    let conversation = history
+        .iter()
+        .map(|entry| match &redactor {
+            Some(redactor) => redactor.redact_entry(entry).to_message::<C>(),
+            None => entry.to_message::<C>(),
+        })
+        .collect();
+    let mut exchange = vec![Entry::User(query.to_string())];
+    let mut result = agent_loop(
+        py,
+        client,
+        &tools,
+        options,
+        conversation,
+        Turns::of(history),
+        query,
+        &ctx,
+        &mut run,
+        &mut exchange,
+        redactor.as_deref(),
+    )
+    .and_then(|response| translate_answer(client, options, &mut run, redactor.as_deref(), response))
+    .map(|response| restore_answer(redactor.as_deref(), response))
+    .map(|response| transform_answer(options, response))
+    .and_then(|response| moderate_answer(client, options, response))
+    .map(|mut response| {
+        let metadata = response.metadata_mut();
+        metadata.tool_usage = tool_usage::summarize(&run.tool_usage);
+        metadata.retries = run.retries.clone();
+        response
+    });
+    let artifacts = ctx.borrow(py).artifacts.list();
+    if let Ok(AgentResponse::Text { text, .. }) = &result {
+        exchange.push(Entry::Assistant(text.clone()));
+    }
+    let transcript: Vec<Entry> = history.iter().chain(&exchange).cloned().collect();
+    let outcome = match &result {
+        Ok(AgentResponse::Text { text, .. }) => Ok(text.clone()),
+        Ok(AgentResponse::ToolCall { tool_call, .. }) => Ok(tool_call.__repr__()),
+        Err(err) => Err(err.to_string()),
+    };
+    let answer = match &result {
+        Ok(AgentResponse::Text { text, .. }) => Some(text.as_str()),
+        _ => None,
+    };
+    let record = export::run_record(
+        &run,
+        &transcript,
+        &artifacts,
+        answer,
+        outcome.as_ref().err().map(String::as_str),
+    );
+    if let Some(run_log) = &options.run_log {
+        run_log.write(&record);
+    }
+    traces::record(&record);
+    if let Ok(response) = &mut result {
+        if response.is_text() {
+            history.append(&mut exchange);
+        }
+        let metadata = response.metadata_mut();
+        metadata.transcript = transcript;
+        metadata.run = Some(record);
+        metadata.feedback = Some(Arc::new(Feedback::new(
+            &run.id,
+            options.run_log.clone(),
+            options.audit.clone(),
+        )));
+        metadata.artifacts = artifacts;
+    }
+    *spent += run.usage;
+    if let Some(tenant) = tenant {
+        tenant.record(&run);
+    }
+    run.finish(outcome);
+    result
+}
+
+/// How many times a `code_only` answer with prose around its code is sent back.
+const MAX_CODE_REASKS: usize = 2;
+
+/// Under `code_only`, the follow-up asking for code alone if `text` has prose around
+/// its code and re-asks are left; otherwise the answer, cut down to its code once the
+/// re-asks have run out.
+fn check_code_only(
+    options: &AgentOptions,
+    text: String,
+    reasks: &mut usize,
+) -> Result<String, String> {
+    let Some(code_only) = &options.code_only else {
+        return Ok(text);
+    };
+    if code_only.accepts(&text) {
+        return Ok(text);
+    }
+    if *reasks < MAX_CODE_REASKS {
+        *reasks += 1;
+        return Err(code_only.reask());
+    }
+    Ok(code_only.code(&text).unwrap_or(text))
+}
+
+/// `text` as sent: shortened under `compress_prompt`, then redacted under `redact_pii`.
+fn outgoing<'a>(
+    py: Python,
+    options: &AgentOptions,
+    redactor: Option<&Redactor>,
+    text: &'a str,
+) -> PyResult<Cow<'a, str>> {
+    let text = match &options.compression {
+        Some(compressor) => compressor.compress(py, text)?,
+        None => Cow::Borrowed(text),
+    };
+    Ok(match redactor {
+        Some(redactor) => Cow::Owned(redactor.redact(&text)),
+        None => text,
+    })
+}
+
+/// A tool result as sent, like `outgoing`; only text results are compressed.
+fn outgoing_result(
+    py: Python,
+    options: &AgentOptions,
+    redactor: Option<&Redactor>,
+    result: &Value,
+) -> PyResult<Value> {
+    let result = match (&options.compression, result) {
+        (Some(compressor), Value::String(text)) => Value::from(compressor.compress(py, text)?),
+        _ => result.clone(),
+    };
+    Ok(match redactor {
+        Some(redactor) => redactor.redact_value(&result),
+        None => result,
+    })
+}
+
+/// The message carrying a tool result, sent as ASCII-only JSON text if the
+/// `tool_serializer` asks for it.
+fn tool_result_message<C: ChatClient>(
+    options: &AgentOptions,
+    call: &ToolRequest,
+    result: Value,
+) -> C::Message {
+    match options.tool_serializer.as_deref() {
+        Some(serializer) if serializer.ensure_ascii() => {
+            let text = serializer.text(&result);
+            C::tool_result_text_message(call, result, text)
+        }
+        _ => C::tool_result_message(call, result),
+    }
+}
+
+/// Put redacted values back into a final answer, if the `redact_pii` policy restores
+/// them, and into the arguments of a returned tool call, which are meant to be run.
+fn restore_answer(redactor: Option<&Redactor>, response: AgentResponse) -> AgentResponse {
+    let Some(redactor) = redactor else {
+        return response;
+    };
+    match response {
+        AgentResponse::Text { text, metadata } if redactor.restores() => AgentResponse::Text {
+            text: redactor.restore(&text),
+            metadata,
+        },
+        AgentResponse::ToolCall {
+            tool_call,
+            metadata,
+        } => {
+            let args = serde_json::from_str::<Value>(&tool_call.args)
+                .map(|args| redactor.restore_value(&args).to_string())
+                .unwrap_or(tool_call.args);
+            AgentResponse::ToolCall {
+                tool_call: ToolCall {
+                    name: tool_call.name,
+                    args,
+                },
+                metadata,
+            }
+        }
+        response => response,
+    }
+}
+
+/// `text` translated into `output_language` by the translation model, as a request of
+/// `run`.
+fn translated<C: ChatClient>(
+    client: &C,
+    options: &AgentOptions,
+    run: &mut RunRecord,
+    text: String,
+) -> Result<String, RustedChainError> {
+    let Some(language) = &options.output_language else {
+        return Ok(text);
+    };
+    let model = options
+        .translation_model
+        .as_deref()
+        .unwrap_or(client.model());
+    let instruction = language::translation_instruction(language);
+    let Some(translator) = client.side_client(model, &instruction) else {
+        return Ok(text);
+    };
+    let messages = vec![C::user_message(&text)];
+    match observed(&translator, options, run, messages, |messages| {
+        translator.exchange(messages)
+    })? {
+        (Turn::Text(translation), _, _) => Ok(translation),
+        (Turn::ToolCall(_), _, _) => Ok(text),
+    }
+}
+
+/// `text` with redacted values restored, under a redactor that restores them.
+fn restored(redactor: Option<&Redactor>, text: &str) -> String {
+    match redactor.filter(|r| r.restores()) {
+        Some(redactor) => redactor.restore(text),
+        None => text.to_string(),
+    }
+}
+
+/// Translate a final text answer with `translate="answer"` or `"both"`, keeping the
+/// original on `run`.
+fn translate_answer<C: ChatClient>(
+    client: &C,
+    options: &AgentOptions,
+    run: &mut RunRecord,
+    redactor: Option<&Redactor>,
+    response: AgentResponse,
+) -> PyResult<AgentResponse> {
+    if options.output_language.is_none() || !options.translate.answer() {
+        return Ok(response);
+    }
+    let AgentResponse::Text { text, metadata } = response else {
+        return Ok(response);
+    };
+    run.original_answer = Some(restored(redactor, &text));
+    let text = translated(client, options, run, text)?;
+    Ok(AgentResponse::Text { text, metadata })
+}
+
+/// A text tool result as sent with `translate="tool_results"` or `"both"`; the run's
+/// exchange keeps the original.
+fn translate_result<C: ChatClient>(
+    client: &C,
+    options: &AgentOptions,
+    run: &mut RunRecord,
+    result: Value,
+) -> Result<Value, RustedChainError> {
+    match result {
+        Value::String(text)
+            if options.output_language.is_some() && options.translate.tool_results() =>
+        {
+            translated(client, options, run, text).map(Value::from)
+        }
+        result => Ok(result),
+    }
+}
+
+/// Apply `output_transform` to a final text answer.
+fn transform_answer(options: &AgentOptions, response: AgentResponse) -> AgentResponse {
+    match (&options.output_transform, response) {
+        (Some(transform), AgentResponse::Text { text, metadata }) => AgentResponse::Text {
+            text: transform.apply(&text),
+            metadata,
+        },
+        (_, response) => response,
+    }
+}
+
+/// Moderate `text` with the client's OpenAI key (or `OPENAI_API_KEY`) and the agent's
+/// local rules.
+fn moderate_text<C: ChatClient>(
+    client: &C,
+    options: &AgentOptions,
+    text: &str,
+) -> Result<ModerationResult, RustedChainError> {
+    let api_key = moderation::api_key(client.provider(), &client.api_key().current()?);
+    Python::attach(|py| {
+        py.detach(|| {
+            RUNTIME.block_on(moderation::check(
+                text,
+                api_key.as_deref(),
+                moderation::DEFAULT_MODEL,
+                &options.moderation_rules,
+            ))
+        })
+    })
+}
+
+fn flagged_error(verdict: &ModerationResult) -> RustedChainError {
+    RustedChainError::ContentFiltered(format!(
+        "answer flagged by moderation: {}",
+        verdict.reason()
+    ))
+}
+
+/// Apply `auto_moderate` to a final answer: raise if it is flagged, or attach the
+/// verdict to its metadata.
+fn moderate_answer<C: ChatClient>(
+    client: &C,
+    options: &AgentOptions,
+    response: AgentResponse,
+) -> PyResult<AgentResponse> {
+    let Some(action) = options.auto_moderate else {
+        return Ok(response);
+    };
+    let AgentResponse::Text { text, mut metadata } = response else {
+        return Ok(response);
+    };
+    let verdict = moderate_text(client, options, &text)?;
+    match action {
+        ModerationAction::Raise if verdict.flagged() => Err(flagged_error(&verdict).into()),
+        ModerationAction::Raise => Ok(AgentResponse::Text { text, metadata }),
+        ModerationAction::Annotate => {
+            metadata.moderation = Some(verdict);
+            Ok(AgentResponse::Text { text, metadata })
+        }
+    }
+}
+
+/// Keep the text that came with a tool call, which is otherwise only in the provider's
+/// messages, in the run's exchange ahead of the call.
+fn record_tool_call_text<C: ChatClient>(
+    conversation: &[C::Message],
+    redactor: Option<&Redactor>,
+    exchange: &mut Vec<Entry>,
+) {
+    let Some(text) = conversation.last().and_then(C::tool_call_text) else {
+        return;
+    };
+    exchange.push(Entry::Assistant(match redactor.filter(|r| r.restores()) {
+        Some(redactor) => redactor.restore(&text),
+        None => text,
+    }));
+}
+
+#[allow(clippy::too_many_arguments)]
+fn agent_loop<C: ChatClient>(
+    py: Python,
+    client: &C,
+    tools: &HashMap<String, Py<PyAny>>,
+    options: &AgentOptions,
+    mut conversation: Vec<C::Message>,
+    mut turns: Turns,
+    query: &str,
+    ctx: &Py<RunContext>,
+    run: &mut RunRecord,
+    exchange: &mut Vec<Entry>,
+    redactor: Option<&Redactor>,
+) -> PyResult<AgentResponse> {
+    conversation.push(C::user_message(&outgoing(py, options, redactor, query)?));
+    let mut detector = LoopDetector::default();
+    let mut metadata = ResponseMetadata {
+        detected_language: language::detect_language(query).map(str::to_string),
+        ..Default::default()
+    };
+
+    let mut reasks = 0;
+    if tools.is_empty() {
+        loop {
+            let (turn, message, _) = exchanged(client, options, run, conversation.clone())?;
+            metadata.citations = C::citations(&message);
+            let Turn::Text(text) = turn else {
+                return Ok(turn.into_response(metadata));
+            };
+            match check_code_only(options, text, &mut reasks) {
+                Ok(text) => return Ok(AgentResponse::Text { text, metadata }),
+                Err(reask) => {
+                    conversation.push(message);
+                    conversation.push(C::user_message(&reask));
+                }
+            }
+        }
+    }
+
+    let tool_client = options
+        .tool_model
+        .as_deref()
+        .map(|model| client.for_model(model));
+    // The tool model makes the run's first request, to pick tools; from the first tool
+    // result on, the agent's own model takes the conversation.
+    let mut answering = false;
+    // A tool model's reply that calls no tool is set aside, so only requests whose reply
+    // the loop acts on are counted.
+    let mut iteration = 0;
+    while iteration < MAX_TOOL_ITERATIONS {
+        let sender = match &tool_client {
+            Some(tool_client) if !answering => tool_client,
+            _ => client,
+        };
+        let (turn, assistant_message, _) =
+            send_with_overflow_recovery(options, &mut conversation, &mut turns, |messages| {
+                exchanged(sender, options, run, messages)
+            })?;
+
+        // The tool model needs no tool, and the main model answers instead: the one
+        // request a `tool_model` adds to a run.
+        if tool_client.is_some() && !answering && matches!(turn, Turn::Text(_)) {
+            answering = true;
+            continue;
+        }
+        conversation.push(assistant_message);
+
+        match turn {
+            Turn::Text(text) => {
+                metadata.citations = conversation.last().map(C::citations).unwrap_or_default();
+                match check_code_only(options, text, &mut reasks) {
+                    Ok(text) => return Ok(AgentResponse::Text { text, metadata }),
+                    Err(reask) => conversation.push(C::user_message(&reask)),
+                }
+            }
+            Turn::ToolCall(call) => {
+                answering = true;
+                run.record_tool_call();
+                ctx.borrow_mut(py).iteration = iteration;
+                let call = redactor.map_or(call.clone(), |r| r.restore_call(&call));
+                let call = strict_arguments(py, client, options, tools, call);
+                record_tool_call_text::<C>(&conversation, redactor, exchange);
+                match handle_tool_call(py, tools, options, &mut detector, ctx, run, &call)? {
+                    ToolOutcome::Result(result) => {
+                        exchange.extend(Entry::tool_exchange(&call, &result, exchange.len()));
+                        let sent = outgoing_result(py, options, redactor, &result)?;
+                        let sent = translate_result(client, options, run, sent)?;
+                        conversation.push(tool_result_message::<C>(options, &call, sent));
+                    }
+                    ToolOutcome::Finish(text) => return Ok(AgentResponse::Text { text, metadata }),
+                }
+            }
+        }
+        iteration += 1;
+    }
+
+    Err(RustedChainError::MaxIterations(MAX_TOOL_ITERATIONS).into())
+}
+
+/// Run the agent loop on a background thread, streaming text to the returned iterator.
+/// Tool calls are executed between streamed turns, so the final answer arrives
+/// token-by-token instead of as one blob.
+pub(crate) fn stream_agent<C>(
+    py: Python,
+    client: C,
+    tools: &Option<Vec<Py<PyAny>>>,
+    options: &AgentOptions,
+    query: String,
+    tags: Option<Vec<String>>,
+    metadata: Option<Py<PyDict>>,
+) -> PyResult<TextStream>
+where
+    C: ChatClient + Send + 'static,
+{
+    runtime::check()?;
+    let planned = planned(&client, &[], &query);
+    let metadata = match &options.tenant {
+        Some(tenant) => {
+            tenant.check_budget(&planned)?;
+            tenant.label(py, metadata)?
+        }
+        None => metadata,
+    };
+    let tools = tool_registry(py, tools);
+    let labels = Labels::from_py(py, tags, metadata.as_ref())?;
+    let options = options.clone();
+    let (mut sink, text_stream) = stream::channel(options.stream_buffering);
+    let redactor = options
+        .pii
+        .clone()
+        .map(|rules| Arc::new(Redactor::new(rules)));
+    if let Some(redactor) = redactor.as_ref().filter(|r| r.restores()) {
+        sink.restore_with(redactor.clone());
+    }
+
+    std::thread::spawn(move || {
+        let _in_flight = match runtime::admit() {
+            Ok(in_flight) => in_flight,
+            Err(err) => return sink.fail(err.into()),
+        };
+        let _permit = match options.tenant.as_deref().map(|t| t.admit(&planned)) {
+            Some(Err(err)) => return sink.fail(err.into()),
+            Some(Ok(permit)) => permit,
+            None => None,
+        };
+        let mut run = RunRecord::start(
+            &client,
+            &options,
+            &query,
+            tools.keys().cloned().collect(),
+            true,
+            labels,
+        );
+        let mut exchange = vec![Entry::User(query.clone())];
+        let mut artifacts = Vec::new();
+        let result =
+            Python::attach(|py| RunContext::create(py, &run.id, metadata, &options.artifacts))
+                .and_then(|ctx| {
+                    let text = stream_loop(
+                        &client,
+                        &tools,
+                        &options,
+                        &query,
+                        &ctx,
+                        &sink,
+                        &mut run,
+                        &mut exchange,
+                        redactor.as_deref(),
+                    );
+                    artifacts = Python::attach(|py| ctx.borrow(py).artifacts.list());
+                    text
+                })
+                .and_then(|text| {
+                    // The answer has already been streamed; a flagged one fails the stream.
+                    if options.auto_moderate == Some(ModerationAction::Raise) {
+                        let verdict = moderate_text(&client, &options, &text)?;
+                        if verdict.flagged() {
+                            return Err(flagged_error(&verdict).into());
+                        }
+                    }
+                    Ok(text)
+                });
+        sink.set_retries(run.retries.clone());
+        sink.set_artifacts(artifacts.clone());
+        if let Some(tenant) = &options.tenant {
+            tenant.record(&run);
+        }
+        let outcome = match &result {
+            Ok(text) => Ok(text.clone()),
+            Err(err) => Err(err.to_string()),
+        };
+        if options.run_log.is_some() || traces::serving() {
+            if let Ok(text) = &outcome {
+                exchange.push(Entry::Assistant(text.clone()));
+            }
+            let answer = outcome.as_deref().ok();
+            let error = outcome.as_ref().err().map(String::as_str);
+            let record = export::run_record(&run, &exchange, &artifacts, answer, error);
+            if let Some(run_log) = &options.run_log {
+                run_log.write(&record);
+            }
+            traces::record(&record);
+        }
+        run.finish(outcome);
+        if let Err(err) = result {
+            sink.fail(err);
+        }
+    });
+
+    Ok(text_stream)
+}
+
+/// Stream the agent loop into `sink`, returning the complete final answer.
+#[allow(clippy::too_many_arguments)]
+fn stream_loop<C: ChatClient>(
+    client: &C,
+    tools: &HashMap<String, Py<PyAny>>,
+    options: &AgentOptions,
+    query: &str,
+    ctx: &Py<RunContext>,
+    sink: &TextSink,
+    run: &mut RunRecord,
+    exchange: &mut Vec<Entry>,
+    redactor: Option<&Redactor>,
+) -> PyResult<String> {
+    let query = Python::attach(|py| outgoing(py, options, redactor, query))?;
+    let mut conversation = vec![C::user_message(&query)];
+    let mut turns = Turns::default();
+    let mut detector = LoopDetector::default();
+
+    let tool_client = options
+        .tool_model
+        .as_deref()
+        .filter(|_| !tools.is_empty())
+        .map(|model| client.for_model(model));
+    let translating = options.output_language.is_some() && options.translate.answer();
+    // The tool model makes the run's first request, to pick tools; from the first tool
+    // result on, the agent's own model takes the conversation.
+    let mut answering = false;
+    // A tool model's reply that calls no tool is set aside, so only requests whose reply
+    // the loop acts on are counted.
+    let mut iteration = 0;
+    while iteration < MAX_TOOL_ITERATIONS {
+        let (turn, assistant_message, _) = match &tool_client {
+            // Not streamed: its text is never shown.
+            Some(tool_client) if !answering => {
+                send_with_overflow_recovery(options, &mut conversation, &mut turns, |messages| {
+                    exchanged(tool_client, options, run, messages)
+                })?
+            }
+            // Not streamed: only its translation is shown.
+            _ if translating => {
+                send_with_overflow_recovery(options, &mut conversation, &mut turns, |messages| {
+                    exchanged(client, options, run, messages)
+                })?
+            }
+            _ => send_with_overflow_recovery(options, &mut conversation, &mut turns, |messages| {
+                failing_over(client, options, messages, |client, messages| {
+                    observed_stream(client, options, run, sink, messages)
+                })
+            })?,
+        };
+
+        // The tool model needs no tool, and the main model streams the answer instead:
+        // the one request a `tool_model` adds to a run.
+        if tool_client.is_some() && !answering && matches!(turn, Turn::Text(_)) {
+            answering = true;
+            continue;
+        }
+        conversation.push(assistant_message);
+
+        match turn {
+            Turn::Text(text) if translating => {
+                sink.set_citations(conversation.last().map(C::citations).unwrap_or_default());
+                run.original_answer = Some(restored(redactor, &text));
+                let text = translated(client, options, run, text)?;
+                sink.start_turn();
+                RUNTIME.block_on(async {
+                    sink.send(text.clone()).await?;
+                    sink.flush().await
+                })?;
+                return Ok(restored(redactor, &text));
+            }
+            Turn::Text(text) => {
+                sink.set_citations(conversation.last().map(C::citations).unwrap_or_default());
+                return Ok(restored(redactor, &text));
+            }
+            Turn::ToolCall(call) => {
+                answering = true;
+                run.record_tool_call();
+                let call = redactor.map_or(call.clone(), |r| r.restore_call(&call));
+                let call = Python::attach(|py| strict_arguments(py, client, options, tools, call));
+                record_tool_call_text::<C>(&conversation, redactor, exchange);
+                let outcome = Python::attach(|py| {
+                    ctx.borrow_mut(py).iteration = iteration;
+                    handle_tool_call(py, tools, options, &mut detector, ctx, run, &call)
+                })?;
+                match outcome {
+                    ToolOutcome::Result(result) => {
+                        exchange.extend(Entry::tool_exchange(&call, &result, exchange.len()));
+                        let sent =
+                            Python::attach(|py| outgoing_result(py, options, redactor, &result))?;
+                        let sent = translate_result(client, options, run, sent)?;
+                        conversation.push(tool_result_message::<C>(options, &call, sent));
+                    }
+                    ToolOutcome::Finish(text) => {
+                        RUNTIME.block_on(sink.send(text.clone()))?;
+                        return Ok(text);
+                    }
+                }
+            }
+        }
+        iteration += 1;
+    }
+
+    Err(RustedChainError::MaxIterations(MAX_TOOL_ITERATIONS).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// History entries by role: user, assistant, tool call and tool result.
+    fn entries(roles: &str) -> Vec<Entry> {
+        let call = ToolRequest {
+            id: None,
+            name: "search".to_string(),
+            args: Value::Null,
+        };
+        roles
+            .chars()
+            .map(|role| match role {
+                'u' => Entry::User(String::new()),
+                'a' => Entry::Assistant(String::new()),
+                'c' => Entry::ToolCall(call.clone()),
+                _ => Entry::ToolResult {
+                    call: call.clone(),
+                    result: Value::Null,
+                },
+            })
+            .collect()
+    }
+
+    #[test]
+    fn turns_start_at_each_user_entry() {
+        let turns = Turns::of(&entries("ucrauaa"));
+        assert_eq!(turns.history, vec![0, 4]);
+        assert_eq!(turns.query, 7);
+        assert_eq!(Turns::of(&entries("au")).history, vec![0, 1]);
+        assert_eq!(Turns::of(&[]), Turns::default());
+    }
+
+    #[test]
+    fn trim_drops_whole_history_turns_before_the_query() {
+        // Three turns of history, the query, then one tool exchange.
+        let mut conversation = vec![
+            "u1", "c1", "r1", "a1", "u2", "a2", "u3", "a3", "q", "c", "r",
+        ];
+        let mut turns = Turns::of(&entries("ucrauaua"));
+        assert!(trim_conversation(&mut conversation, &mut turns));
+        assert_eq!(conversation, ["u3", "a3", "q", "c", "r"]);
+        assert_eq!(turns.history, vec![0]);
+        assert_eq!(turns.query, 2);
+        assert!(trim_conversation(&mut conversation, &mut turns));
+        assert_eq!(conversation, ["q", "c", "r"]);
+        assert_eq!(
+            turns,
+            Turns {
+                history: vec![],
+                query: 0
+            }
+        );
+    }
+
+    #[test]
+    fn trim_drops_whole_tool_exchanges_after_the_query() {
+        let mut conversation = vec!["q", "c1", "r1", "c2", "r2", "c3", "r3"];
+        let mut turns = Turns::default();
+        assert!(trim_conversation(&mut conversation, &mut turns));
+        assert_eq!(conversation, ["q", "c3", "r3"]);
+        assert!(trim_conversation(&mut conversation, &mut turns));
+        assert_eq!(conversation, ["q"]);
+        assert!(!trim_conversation(&mut conversation, &mut turns));
+        assert_eq!(conversation, ["q"]);
+    }
+}
@@ -0,0 +1,219 @@
+//! Files produced by tools during a run. Binary results, and text results over
+//! `artifact_threshold`, are written to a directory for the run instead of being sent to
+//! the model, which gets a short reference in their place.
+
+use pyo3::exceptions::PyOSError;
+use pyo3::prelude::*;
+use pyo3::types::{PyByteArray, PyBytes};
+use serde_json::{json, Value};
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// How much of a text artifact the model sees.
+const PREVIEW_CHARS: usize = 500;
+
+/// The `artifact_dir` and `artifact_threshold` options.
+#[derive(Clone, Default)]
+pub(crate) struct ArtifactSettings {
+    /// Where runs keep their artifacts, one directory per run; the system temp
+    /// directory by default.
+    pub(crate) dir: Option<PathBuf>,
+    /// Size in bytes of JSON above which a result is stored rather than sent.
+    pub(crate) threshold: Option<usize>,
+}
+
+/// A file a tool produced during a run.
+#[pyclass(frozen)]
+#[derive(Clone)]
+pub struct Artifact {
+    /// File name, unique within the run, as the model knows it.
+    #[pyo3(get)]
+    pub name: String,
+    #[pyo3(get)]
+    pub path: PathBuf,
+    /// Size in bytes.
+    #[pyo3(get)]
+    pub size: usize,
+    #[pyo3(get)]
+    pub media_type: String,
+    /// The tool that returned it.
+    #[pyo3(get)]
+    pub tool: String,
+}
+
+#[pymethods]
+impl Artifact {
+    /// The artifact's contents.
+    fn read<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+        let data = std::fs::read(&self.path).map_err(|e| {
+            PyOSError::new_err(format!("cannot read {}: {}", self.path.display(), e))
+        })?;
+        Ok(PyBytes::new(py, &data))
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Artifact(name='{}', media_type='{}', size={}, tool='{}')",
+            self.name, self.media_type, self.size, self.tool
+        )
+    }
+}
+
+impl Artifact {
+    pub(crate) fn to_json(&self) -> Value {
+        json!({
+            "name": self.name,
+            "path": self.path,
+            "size": self.size,
+            "media_type": self.media_type,
+            "tool": self.tool,
+        })
+    }
+}
+
+/// One run's artifacts. The directory is only created once something is stored.
+pub(crate) struct ArtifactStore {
+    dir: PathBuf,
+    threshold: Option<usize>,
+    artifacts: Mutex<Vec<Artifact>>,
+}
+
+impl ArtifactStore {
+    pub(crate) fn new(settings: &ArtifactSettings, run_id: &str) -> Self {
+        let base = settings
+            .dir
+            .clone()
+            .unwrap_or_else(|| std::env::temp_dir().join("rusted_chain-artifacts"));
+        Self {
+            dir: base.join(run_id),
+            threshold: settings.threshold,
+            artifacts: Mutex::new(Vec::new()),
+        }
+    }
+
+    pub(crate) fn list(&self) -> Vec<Artifact> {
+        self.artifacts.lock().unwrap().clone()
+    }
+
+    /// The bytes of a binary tool result, which cannot be sent as JSON.
+    pub(crate) fn binary(result: &Bound<'_, PyAny>) -> Option<Vec<u8>> {
+        if let Ok(bytes) = result.cast::<PyBytes>() {
+            return Some(bytes.as_bytes().to_vec());
+        }
+        result
+            .cast::<PyByteArray>()
+            .ok()
+            .map(|bytes| bytes.to_vec())
+    }
+
+    /// Store binary `data` returned by `tool`, returning the reference sent to the model.
+    pub(crate) fn store_binary(&self, tool: &str, data: &[u8]) -> PyResult<Value> {
+        let media_type = sniff(data);
+        let artifact = self.write(tool, data, media_type)?;
+        Ok(reference(&artifact, None))
+    }
+
+    /// `result` as sent to the model: itself, or a reference to it once stored if its
+    /// JSON is larger than the threshold.
+    pub(crate) fn store_if_large(&self, tool: &str, result: Value) -> PyResult<Value> {
+        let Some(threshold) = self.threshold else {
+            return Ok(result);
+        };
+        let (text, media_type) = match &result {
+            Value::String(text) => (text.clone(), "text/plain"),
+            other => (other.to_string(), "application/json"),
+        };
+        if text.len() <= threshold {
+            return Ok(result);
+        }
+        let artifact = self.write(tool, text.as_bytes(), media_type)?;
+        Ok(reference(&artifact, Some(&text)))
+    }
+
+    fn write(&self, tool: &str, data: &[u8], media_type: &str) -> PyResult<Artifact> {
+        let mut artifacts = self.artifacts.lock().unwrap();
+        let stem: String = tool
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let name = format!("{}-{}.{}", stem, artifacts.len() + 1, extension(media_type));
+        let path = self.dir.join(&name);
+        std::fs::create_dir_all(&self.dir)
+            .and_then(|_| std::fs::write(&path, data))
+            .map_err(|e| {
+                PyOSError::new_err(format!("cannot store artifact {}: {}", path.display(), e))
+            })?;
+        let artifact = Artifact {
+            name,
+            path,
+            size: data.len(),
+            media_type: media_type.to_string(),
+            tool: tool.to_string(),
+        };
+        artifacts.push(artifact.clone());
+        Ok(artifact)
+    }
+}
+
+/// What the model gets in place of an artifact: its name and type, and the start of
+/// it if it is text.
+fn reference(artifact: &Artifact, text: Option<&str>) -> Value {
+    let mut reference = json!({
+        "artifact": artifact.name,
+        "media_type": artifact.media_type,
+        "bytes": artifact.size,
+        "note": "Stored as a file and attached to the run; not shown here in full.",
+    });
+    if let Some(text) = text {
+        let mut preview: String = text.chars().take(PREVIEW_CHARS).collect();
+        if preview.len() < text.len() {
+            preview.push('…');
+        }
+        reference["preview"] = Value::from(preview);
+    }
+    reference
+}
+
+/// The media type of binary data, from its leading bytes.
+fn sniff(data: &[u8]) -> &'static str {
+    const SIGNATURES: &[(&[u8], &str)] = &[
+        (b"\x89PNG\r\n\x1a\n", "image/png"),
+        (b"\xff\xd8\xff", "image/jpeg"),
+        (b"GIF87a", "image/gif"),
+        (b"GIF89a", "image/gif"),
+        (b"%PDF-", "application/pdf"),
+        (b"PK\x03\x04", "application/zip"),
+        (b"\x1f\x8b", "application/gzip"),
+    ];
+    if let Some((_, media_type)) = SIGNATURES.iter().find(|(sig, _)| data.starts_with(sig)) {
+        return media_type;
+    }
+    if data.starts_with(b"RIFF") && data.get(8..12) == Some(b"WEBP") {
+        return "image/webp";
+    }
+    match std::str::from_utf8(data) {
+        Ok(_) => "text/plain",
+        Err(_) => "application/octet-stream",
+    }
+}
+
+fn extension(media_type: &str) -> &'static str {
+    match media_type {
+        "image/png" => "png",
+        "image/jpeg" => "jpg",
+        "image/gif" => "gif",
+        "image/webp" => "webp",
+        "application/pdf" => "pdf",
+        "application/zip" => "zip",
+        "application/gzip" => "gz",
+        "application/json" => "json",
+        "text/plain" => "txt",
+        _ => "bin",
+    }
+}
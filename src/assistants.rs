@@ -0,0 +1,670 @@
+//! OpenAI's Assistants API: assistants and threads whose state lives server-side.
+//!
+//! A thread keeps its own history, so each turn sends only what is new (the user's
+//! message or a tool's output) and then follows the run it starts until the run
+//! finishes or asks for a tool. That makes a thread a `ChatClient` like the chat models:
+//! it goes through the same agent loop, tool execution, metrics and audit log, and
+//! answers with the same `AgentResponse` and `ToolCall` objects.
+
+use crate::agent::{self, ChatClient, ToolRequest, Turn, Usage};
+use crate::error::RustedChainError;
+use crate::keys::ApiKey;
+use crate::media::Attachment;
+use crate::metrics;
+use crate::openai::{Message, OpenAI};
+use crate::options::AgentOptions;
+use crate::stream::{SseParser, TextSink};
+use crate::{AgentResponse, ResponseMetadata, RUNTIME};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use reqwest::Method;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const BETA: &[(&str, &str)] = &[("OpenAI-Beta", "assistants=v2")];
+
+/// How often a run is checked while it is queued or in progress.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+#[derive(Deserialize)]
+struct Created {
+    id: String,
+    #[serde(default)]
+    model: String,
+}
+
+#[derive(Deserialize)]
+struct Run {
+    id: String,
+    status: String,
+    required_action: Option<RequiredAction>,
+    last_error: Option<RunError>,
+    usage: Option<RunUsage>,
+}
+
+#[derive(Deserialize)]
+struct RequiredAction {
+    submit_tool_outputs: SubmitToolOutputs,
+}
+
+#[derive(Deserialize)]
+struct SubmitToolOutputs {
+    tool_calls: Vec<RunToolCall>,
+}
+
+#[derive(Deserialize)]
+struct RunToolCall {
+    id: String,
+    function: RunFunction,
+}
+
+#[derive(Deserialize)]
+struct RunFunction {
+    name: String,
+    arguments: String,
+}
+
+#[derive(Deserialize)]
+struct RunError {
+    #[serde(default)]
+    code: String,
+    #[serde(default)]
+    message: String,
+}
+
+#[derive(Deserialize)]
+struct RunUsage {
+    prompt_tokens: u64,
+    completion_tokens: u64,
+}
+
+#[derive(Deserialize)]
+struct MessageList {
+    data: Vec<ThreadMessage>,
+}
+
+#[derive(Deserialize)]
+struct ThreadMessage {
+    role: String,
+    #[serde(default)]
+    content: Vec<Content>,
+}
+
+/// A part of a message, or of a message delta when streaming.
+#[derive(Deserialize)]
+#[serde(tag = "type")]
+enum Content {
+    #[serde(rename = "text")]
+    Text { text: TextContent },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+struct TextContent {
+    #[serde(default)]
+    value: String,
+}
+
+/// One streamed event: a message delta or a run object, told apart by `object`.
+#[derive(Deserialize)]
+struct StreamEvent {
+    object: String,
+    #[serde(default)]
+    delta: Option<MessageDelta>,
+}
+
+#[derive(Deserialize)]
+struct MessageDelta {
+    #[serde(default)]
+    content: Vec<Content>,
+}
+
+fn text_of(content: Vec<Content>) -> String {
+    content
+        .into_iter()
+        .filter_map(|part| match part {
+            Content::Text { text } => Some(text.value),
+            Content::Other => None,
+        })
+        .collect()
+}
+
+/// The tool call a run is waiting on.
+#[derive(Clone)]
+struct PendingCall {
+    run_id: String,
+    call: ToolRequest,
+}
+
+/// A thread as a `ChatClient`. Clones share the run waiting on a tool output.
+#[derive(Clone)]
+pub(crate) struct ThreadClient {
+    chat: OpenAI,
+    assistant_id: String,
+    thread_id: String,
+    model: String,
+    /// Sent with every run, for assistants created elsewhere.
+    additional_instructions: Option<String>,
+    pending: Arc<Mutex<Option<PendingCall>>>,
+}
+
+impl ThreadClient {
+    fn path(&self, rest: &str) -> String {
+        format!("/threads/{}{}", self.thread_id, rest)
+    }
+
+    /// Add the user's message to the thread and start a run on it.
+    async fn start_run(
+        &self,
+        text: &str,
+        stream: bool,
+    ) -> Result<reqwest::Response, RustedChainError> {
+        // A run left waiting for a tool output blocks new messages.
+        let pending = self.pending.lock().unwrap().take();
+        if let Some(pending) = pending {
+            let path = self.path(&format!("/runs/{}/cancel", pending.run_id));
+            let _ = self.chat.request(Method::POST, &path, None, BETA).await;
+        }
+        let message = json!({ "role": "user", "content": text });
+        self.chat
+            .request(Method::POST, &self.path("/messages"), Some(&message), BETA)
+            .await?;
+        let mut body = json!({
+            "assistant_id": self.assistant_id,
+            // One call at a time, as the agent loop handles them.
+            "parallel_tool_calls": false,
+            "stream": stream,
+        });
+        if let Some(instructions) = &self.additional_instructions {
+            body["additional_instructions"] = json!(instructions);
+        }
+        self.chat
+            .request(Method::POST, &self.path("/runs"), Some(&body), BETA)
+            .await
+    }
+
+    /// Hand a tool's output to the run waiting for it.
+    async fn submit(
+        &self,
+        call_id: Option<&str>,
+        output: &str,
+        stream: bool,
+    ) -> Result<reqwest::Response, RustedChainError> {
+        let pending = self.pending.lock().unwrap().take().ok_or_else(|| {
+            RustedChainError::InvalidRequest("no run is waiting for a tool output".to_string())
+        })?;
+        let body = json!({
+            "tool_outputs": [{
+                "tool_call_id": call_id.or(pending.call.id.as_deref()),
+                "output": output,
+            }],
+            "stream": stream,
+        });
+        let path = self.path(&format!("/runs/{}/submit_tool_outputs", pending.run_id));
+        self.chat
+            .request(Method::POST, &path, Some(&body), BETA)
+            .await
+    }
+
+    /// Send the newest message of `conversation`: a tool output goes to the waiting
+    /// run, anything else starts a new one.
+    async fn send(
+        &self,
+        conversation: &[Message],
+        stream: bool,
+    ) -> Result<reqwest::Response, RustedChainError> {
+        let last = conversation.last().ok_or(RustedChainError::NoResponse)?;
+        if last.role == "tool" {
+            // Tool results are JSON; a string result is sent as the string itself.
+            let output = match serde_json::from_str(&last.content) {
+                Ok(Value::String(text)) => text,
+                _ => last.content.clone(),
+            };
+            self.submit(last.tool_call_id.as_deref(), &output, stream)
+                .await
+        } else {
+            self.start_run(&last.content, stream).await
+        }
+    }
+
+    /// Poll `run` until it finishes or needs a tool output.
+    async fn wait(&self, mut run: Run) -> Result<Run, RustedChainError> {
+        while matches!(run.status.as_str(), "queued" | "in_progress" | "cancelling") {
+            tokio::time::sleep(POLL_INTERVAL).await;
+            run = self
+                .chat
+                .request_json(
+                    Method::GET,
+                    &self.path(&format!("/runs/{}", run.id)),
+                    None,
+                    BETA,
+                )
+                .await?;
+        }
+        Ok(run)
+    }
+
+    /// The text of the assistant messages `run_id` added to the thread.
+    async fn run_text(&self, run_id: &str) -> Result<String, RustedChainError> {
+        let path = self.path(&format!("/messages?run_id={}&order=asc", run_id));
+        let messages: MessageList = self
+            .chat
+            .request_json(Method::GET, &path, None, BETA)
+            .await?;
+        Ok(messages
+            .data
+            .into_iter()
+            .filter(|message| message.role == "assistant")
+            .map(|message| text_of(message.content))
+            .collect::<Vec<_>>()
+            .join("\n\n"))
+    }
+
+    /// Turn a run that has stopped into the agent loop's view of it. `streamed` is the
+    /// text already streamed for it, if any.
+    async fn settle(
+        &self,
+        run: Run,
+        streamed: Option<String>,
+    ) -> Result<(Turn, Message, Usage), RustedChainError> {
+        let usage = run
+            .usage
+            .as_ref()
+            .map_or_else(Usage::default, |usage| Usage {
+                input_tokens: usage.prompt_tokens,
+                output_tokens: usage.completion_tokens,
+            });
+        match run.status.as_str() {
+            "requires_action" => {
+                let call = run
+                    .required_action
+                    .and_then(|action| action.submit_tool_outputs.tool_calls.into_iter().next())
+                    .ok_or(RustedChainError::NoResponse)?;
+                let call = ToolRequest {
+                    id: Some(call.id),
+                    name: call.function.name,
+                    args: serde_json::from_str(&call.function.arguments).unwrap_or(Value::Null),
+                };
+                *self.pending.lock().unwrap() = Some(PendingCall {
+                    run_id: run.id,
+                    call: call.clone(),
+                });
+                let message = OpenAI::tool_call_message(&call);
+                Ok((Turn::ToolCall(call), message, usage))
+            }
+            "completed" | "incomplete" => {
+                let text = match streamed {
+                    Some(text) => text,
+                    None => self.run_text(&run.id).await?,
+                };
+                let message = OpenAI::assistant_message(&text);
+                Ok((Turn::Text(text), message, usage))
+            }
+            "failed" => {
+                let error = run.last_error.unwrap_or(RunError {
+                    code: String::new(),
+                    message: "run failed".to_string(),
+                });
+                Err(match error.code.as_str() {
+                    "rate_limit_exceeded" => RustedChainError::RateLimited {
+                        retry_after: None,
+                        message: error.message,
+                    },
+                    "server_error" => RustedChainError::Overloaded(error.message),
+                    _ => RustedChainError::InvalidRequest(error.message),
+                })
+            }
+            "cancelled" => Err(RustedChainError::Cancelled),
+            other => Err(RustedChainError::InvalidRequest(format!(
+                "run {} ended with status '{}'",
+                run.id, other
+            ))),
+        }
+    }
+}
+
+impl ChatClient for ThreadClient {
+    type Message = Message;
+
+    fn provider(&self) -> &'static str {
+        "openai"
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    /// A thread's run belongs to its assistant and cannot change model between turns.
+    fn for_model(&self, _model: &str) -> Self {
+        self.clone()
+    }
+
+    fn api_key(&self) -> &ApiKey {
+        self.chat.api_key()
+    }
+
+    fn system(&self) -> Option<&str> {
+        self.chat.system()
+    }
+
+    fn user_message(text: &str) -> Message {
+        OpenAI::user_message(text)
+    }
+
+    fn assistant_message(text: &str) -> Message {
+        OpenAI::assistant_message(text)
+    }
+
+    fn tool_call_message(call: &ToolRequest) -> Message {
+        OpenAI::tool_call_message(call)
+    }
+
+    fn tool_result_message(call: &ToolRequest, result: Value) -> Message {
+        OpenAI::tool_result_message(call, result)
+    }
+
+    fn tool_result_text_message(call: &ToolRequest, result: Value, text: String) -> Message {
+        OpenAI::tool_result_text_message(call, result, text)
+    }
+
+    async fn exchange(
+        &self,
+        conversation: Vec<Message>,
+    ) -> Result<(Turn, Message, Usage), RustedChainError> {
+        let run = self
+            .send(&conversation, false)
+            .await?
+            .json()
+            .await
+            .map_err(|e| RustedChainError::ParseError(e.to_string()))?;
+        let run = self.wait(run).await?;
+        self.settle(run, None).await
+    }
+
+    async fn exchange_stream(
+        &self,
+        conversation: Vec<Message>,
+        sink: &TextSink,
+    ) -> Result<(Turn, Message, Usage), RustedChainError> {
+        let mut response = self.send(&conversation, true).await?;
+        let mut parser = SseParser::default();
+        let mut text = String::new();
+        let mut run: Option<Run> = None;
+        while let Some(chunk) = response.chunk().await? {
+            for data in parser.push(&chunk) {
+                if data == "[DONE]" {
+                    continue;
+                }
+                let event: StreamEvent = serde_json::from_str(&data)
+                    .map_err(|e| RustedChainError::ParseError(e.to_string()))?;
+                match event.object.as_str() {
+                    "thread.message.delta" => {
+                        let delta = text_of(event.delta.map(|d| d.content).unwrap_or_default());
+                        text.push_str(&delta);
+                        sink.send(delta).await?;
+                    }
+                    "thread.run" => {
+                        run = Some(
+                            serde_json::from_str(&data)
+                                .map_err(|e| RustedChainError::ParseError(e.to_string()))?,
+                        )
+                    }
+                    _ => {}
+                }
+            }
+        }
+        let run = run.ok_or(RustedChainError::NoResponse)?;
+        self.settle(run, Some(text)).await
+    }
+
+    async fn warmup(&self, prime: bool) -> Result<(), RustedChainError> {
+        ChatClient::warmup(&self.chat, prime).await
+    }
+
+    async fn multimodal(
+        &self,
+        _prompt: &str,
+        _files: &[Attachment],
+        _schema: Option<&Value>,
+    ) -> Result<(String, Usage), RustedChainError> {
+        Err(RustedChainError::InvalidRequest(
+            "assistant threads do not accept files".to_string(),
+        ))
+    }
+}
+
+fn clone_tools(py: Python, tools: &Option<Vec<Py<PyAny>>>) -> Option<Vec<Py<PyAny>>> {
+    tools
+        .as_ref()
+        .map(|tools| tools.iter().map(|tool| tool.clone_ref(py)).collect())
+}
+
+/// An OpenAI assistant: a model, instructions and tool definitions stored by OpenAI.
+/// Conversations with it happen on threads.
+#[pyclass]
+pub struct Assistant {
+    #[pyo3(get)]
+    id: String,
+    #[pyo3(get)]
+    model: String,
+    chat: OpenAI,
+    tools: Option<Vec<Py<PyAny>>>,
+    options: AgentOptions,
+    additional_instructions: Option<String>,
+}
+
+impl Assistant {
+    /// Create an assistant from a configured client: its model, system prompt and
+    /// tools.
+    pub(crate) fn create(
+        py: Python,
+        chat: OpenAI,
+        tools: &Option<Vec<Py<PyAny>>>,
+        options: &AgentOptions,
+        name: Option<String>,
+    ) -> PyResult<Self> {
+        let body = json!({
+            "model": chat.model(),
+            "name": name,
+            "instructions": chat.system(),
+            "tools": chat.tool_definitions(),
+        });
+        let created: Created = py.detach(|| {
+            RUNTIME.block_on(chat.request_json(Method::POST, "/assistants", Some(&body), BETA))
+        })?;
+        Ok(Assistant {
+            id: created.id,
+            model: created.model,
+            chat,
+            tools: clone_tools(py, tools),
+            options: options.clone(),
+            additional_instructions: None,
+        })
+    }
+
+    /// Use an existing assistant. Its stored instructions stay in place; the client's
+    /// system prompt is added to every run.
+    pub(crate) fn retrieve(
+        py: Python,
+        chat: OpenAI,
+        tools: &Option<Vec<Py<PyAny>>>,
+        options: &AgentOptions,
+        id: String,
+    ) -> PyResult<Self> {
+        let path = format!("/assistants/{}", id);
+        let found: Created =
+            py.detach(|| RUNTIME.block_on(chat.request_json(Method::GET, &path, None, BETA)))?;
+        Ok(Assistant {
+            id: found.id,
+            model: found.model,
+            additional_instructions: chat.system().map(str::to_string),
+            chat,
+            tools: clone_tools(py, tools),
+            options: options.clone(),
+        })
+    }
+
+    fn thread(&self, py: Python, id: String) -> AssistantThread {
+        AssistantThread {
+            id: id.clone(),
+            client: ThreadClient {
+                chat: self.chat.clone(),
+                assistant_id: self.id.clone(),
+                thread_id: id,
+                model: self.model.clone(),
+                additional_instructions: self.additional_instructions.clone(),
+                pending: Arc::new(Mutex::new(None)),
+            },
+            tools: clone_tools(py, &self.tools),
+            options: self.options.clone(),
+        }
+    }
+}
+
+#[pymethods]
+impl Assistant {
+    /// Start a new, empty thread.
+    fn create_thread(&self, py: Python) -> PyResult<AssistantThread> {
+        let created: Created = py.detach(|| {
+            RUNTIME.block_on(self.chat.request_json(
+                Method::POST,
+                "/threads",
+                Some(&json!({})),
+                BETA,
+            ))
+        })?;
+        Ok(self.thread(py, created.id))
+    }
+
+    /// Continue an existing thread by id.
+    fn get_thread(&self, py: Python, thread_id: String) -> AssistantThread {
+        self.thread(py, thread_id)
+    }
+
+    /// Delete the assistant from OpenAI.
+    fn delete(&self, py: Python) -> PyResult<()> {
+        let path = format!("/assistants/{}", self.id);
+        py.detach(|| RUNTIME.block_on(self.chat.request(Method::DELETE, &path, None, BETA)))?;
+        Ok(())
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Assistant(id='{}', model='{}')", self.id, self.model)
+    }
+}
+
+/// A conversation with an assistant, kept by OpenAI. Each call adds to it.
+#[pyclass]
+pub struct AssistantThread {
+    #[pyo3(get)]
+    id: String,
+    client: ThreadClient,
+    tools: Option<Vec<Py<PyAny>>>,
+    options: AgentOptions,
+}
+
+#[pymethods]
+impl AssistantThread {
+    #[getter]
+    fn assistant_id(&self) -> &str {
+        &self.client.assistant_id
+    }
+
+    /// Add `query` to the thread and run the assistant. Tools passed to the model are
+    /// executed until the run finishes; a call to any other tool comes back as
+    /// `AgentResponse.ToolCall`, to be answered with `submit_tool_output`.
+    #[pyo3(signature = (query, tags=None, metadata=None))]
+    fn invoke(
+        &self,
+        py: Python,
+        query: String,
+        tags: Option<Vec<String>>,
+        metadata: Option<Py<PyDict>>,
+    ) -> PyResult<AgentResponse> {
+        agent::run_agent(
+            py,
+            &self.client,
+            &self.tools,
+            &self.options,
+            &query,
+            tags,
+            metadata,
+        )
+    }
+
+    /// Add `query` to the thread and return the final answer, or an iterator of text
+    /// chunks with `stream=True`.
+    #[pyo3(signature = (query, stream=false, tags=None, metadata=None))]
+    fn run(
+        &self,
+        py: Python,
+        query: String,
+        stream: bool,
+        tags: Option<Vec<String>>,
+        metadata: Option<Py<PyDict>>,
+    ) -> PyResult<Py<PyAny>> {
+        if stream {
+            let text_stream = agent::stream_agent(
+                py,
+                self.client.clone(),
+                &self.tools,
+                &self.options,
+                query,
+                tags,
+                metadata,
+            )?;
+            return Ok(Py::new(py, text_stream)?.into_any());
+        }
+        let response = self.invoke(py, query, tags, metadata)?;
+        Ok(response.text()?.into_pyobject(py)?.into_any().unbind())
+    }
+
+    /// Answer the tool call the last run stopped on and let the run continue. Returns
+    /// the assistant's answer, or its next tool call.
+    fn submit_tool_output(&self, py: Python, output: &Bound<'_, PyAny>) -> PyResult<AgentResponse> {
+        let pending = self.client.pending.lock().unwrap().clone();
+        let pending = pending.ok_or_else(|| {
+            PyValueError::new_err("this thread has no run waiting for a tool output")
+        })?;
+        let result: Value = match output.extract::<String>() {
+            Ok(text) => Value::String(text),
+            Err(_) => pythonize::depythonize(output)?,
+        };
+        let message = OpenAI::tool_result_message(&pending.call, result);
+        let started = Instant::now();
+        let result = py.detach(|| RUNTIME.block_on(self.client.exchange(vec![message])));
+        let (status, usage) = match &result {
+            Ok((_, _, usage)) => ("ok", *usage),
+            Err(err) => (err.kind(), Usage::default()),
+        };
+        metrics::record_request(
+            "openai",
+            &self.client.model,
+            status,
+            started.elapsed(),
+            usage,
+        );
+        let (turn, _, _) = result?;
+        Ok(turn.into_response(ResponseMetadata::default()))
+    }
+
+    /// Delete the thread from OpenAI.
+    fn delete(&self, py: Python) -> PyResult<()> {
+        let path = format!("/threads/{}", self.id);
+        py.detach(|| {
+            RUNTIME.block_on(self.client.chat.request(Method::DELETE, &path, None, BETA))
+        })?;
+        Ok(())
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "AssistantThread(id='{}', assistant_id='{}')",
+            self.id, self.client.assistant_id
+        )
+    }
+}
@@ -0,0 +1,48 @@
+//! Coroutines for asyncio, through pyo3-async-runtimes. Awaiting one never blocks the
+//! event loop or takes a thread from its executor: futures are polled on a small tokio
+//! runtime of their own, and a blocking call such as an agent run gets a thread of its
+//! own, which waits for the provider with the GIL released.
+
+use crate::runtime;
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::prelude::*;
+use std::future::Future;
+use tokio::sync::oneshot;
+
+/// Set up the runtime that polls awaited futures. They only wait on channels, so one
+/// worker is enough. Called when the module is imported.
+pub(crate) fn init() {
+    let mut builder = tokio::runtime::Builder::new_multi_thread();
+    builder
+        .worker_threads(1)
+        .thread_name("rusted-chain-asyncio")
+        .enable_all();
+    pyo3_async_runtimes::tokio::init(builder);
+}
+
+/// An awaitable for the output of `future`. Must be called from a coroutine or callback
+/// running on an event loop.
+pub(crate) fn awaitable<F, T>(py: Python, future: F) -> PyResult<Py<PyAny>>
+where
+    F: Future<Output = PyResult<T>> + Send + 'static,
+    T: for<'py> IntoPyObject<'py> + Send + 'static,
+{
+    Ok(pyo3_async_runtimes::tokio::future_into_py(py, future)?.unbind())
+}
+
+/// An awaitable for the result of `call`, which blocks between provider requests and
+/// tool calls, and so runs on a thread of its own.
+pub(crate) fn spawn<F>(py: Python, call: F) -> PyResult<Py<PyAny>>
+where
+    F: FnOnce(Python) -> PyResult<Py<PyAny>> + Send + 'static,
+{
+    runtime::check()?;
+    let (tx, rx) = oneshot::channel();
+    std::thread::spawn(move || {
+        let _ = tx.send(Python::attach(call));
+    });
+    awaitable(py, async move {
+        rx.await
+            .map_err(|_| PyRuntimeError::new_err("the call's thread panicked"))?
+    })
+}
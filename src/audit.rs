@@ -0,0 +1,112 @@
+//! Audit trail of provider traffic, written as one JSON object per request/response pair.
+
+use crate::agent::{ChatClient, Turn, Usage};
+use crate::error::RustedChainError;
+use crate::run::RunRecord;
+use chrono::{DateTime, SecondsFormat, Utc};
+use pyo3::prelude::*;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Where audit records (and run exports, for `run_log`) go: appended to a JSONL file, or passed to a Python callable as a dict.
+pub(crate) enum AuditSink {
+    File(Mutex<File>),
+    Callback(Py<PyAny>),
+}
+
+impl AuditSink {
+    /// Build a sink from the `audit_log` or `run_log` option: a callable or a file path.
+    pub(crate) fn from_py(value: &Bound<'_, PyAny>) -> PyResult<Self> {
+        if value.is_callable() {
+            return Ok(Self::Callback(value.clone().unbind()));
+        }
+        let path: std::path::PathBuf = value.extract()?;
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(Self::File(Mutex::new(file)))
+    }
+
+    pub(crate) fn write(&self, record: &Value) {
+        match self {
+            Self::File(file) => {
+                let mut line = record.to_string();
+                line.push('\n');
+                let mut file = file.lock().unwrap_or_else(|e| e.into_inner());
+                if let Err(err) = file.write_all(line.as_bytes()) {
+                    // A lost record is a gap in the audit trail, not a passing problem.
+                    log::error!("failed to write log record: {}", err);
+                }
+            }
+            Self::Callback(callback) => Python::attach(|py| {
+                let result = pythonize::pythonize(py, record)
+                    .map_err(PyErr::from)
+                    .and_then(|dict| callback.bind(py).call1((dict,)));
+                if let Err(err) = result {
+                    err.write_unraisable(py, Some(callback.bind(py)));
+                }
+            }),
+        }
+    }
+}
+
+/// Keep only enough of a secret to tell keys apart: `"****abcd"`.
+pub(crate) fn redact_key(key: &str) -> Option<String> {
+    if key.is_empty() {
+        return None;
+    }
+    let tail: String = key
+        .chars()
+        .rev()
+        .take(4)
+        .collect::<Vec<_>>()
+        .into_iter()
+        .rev()
+        .collect();
+    Some(format!("****{}", tail))
+}
+
+fn hash_user(user: &str) -> String {
+    let digest = Sha256::digest(user.as_bytes());
+    let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("sha256:{}", hex)
+}
+
+/// Record one provider exchange. `request` is the conversation as sent.
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn record_exchange<C: ChatClient>(
+    sink: &AuditSink,
+    client: &C,
+    run: &RunRecord,
+    system: Option<String>,
+    timestamp: DateTime<Utc>,
+    latency: Duration,
+    request: Value,
+    result: &Result<(Turn, C::Message, Usage), RustedChainError>,
+) {
+    let (status, response, error) = match result {
+        Ok((_, message, usage)) => (
+            "ok",
+            json!({ "message": message, "usage": usage }),
+            Value::Null,
+        ),
+        Err(err) => (err.kind(), Value::Null, json!(err.to_string())),
+    };
+    sink.write(&json!({
+        "timestamp": timestamp.to_rfc3339_opts(SecondsFormat::Millis, true),
+        "run_id": run.id,
+        "tags": run.labels.tags,
+        "metadata": run.labels.metadata,
+        "provider": client.provider(),
+        "model": client.model(),
+        "api_key": client.api_key().redacted(),
+        "user": client.end_user().map(hash_user),
+        "latency_ms": latency.as_millis() as u64,
+        "status": status,
+        "request": { "system": system, "messages": request },
+        "response": response,
+        "error": error,
+    }));
+}
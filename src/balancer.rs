@@ -0,0 +1,231 @@
+//! Load balancing across deployments of the same model, such as one model deployed in
+//! several Azure regions. Requests are spread by weight, and a deployment that keeps
+//! failing is taken out of rotation by a circuit breaker until a probe succeeds.
+
+use crate::circuit::Breaker;
+use crate::error::RustedChainError;
+use crate::keys::ApiKey;
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+struct Deployment {
+    base_url: String,
+    api_key: ApiKey,
+    weight: i64,
+}
+
+#[derive(Default)]
+struct Health {
+    /// Smooth weighted round-robin: the deployment with the highest share goes next.
+    share: i64,
+    breaker: Breaker,
+    requests: u64,
+    failures: u64,
+}
+
+pub(crate) struct Balancer {
+    deployments: Vec<Deployment>,
+    failure_threshold: u32,
+    cooldown: Duration,
+    health: Mutex<Vec<Health>>,
+}
+
+impl Balancer {
+    /// The deployment the next request goes to, by weight among those available.
+    pub(crate) fn pick(&self) -> Result<usize, RustedChainError> {
+        let mut health = self.health.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        let available: Vec<usize> = (0..self.deployments.len())
+            .filter(|&i| health[i].breaker.available(self.cooldown, now))
+            .collect();
+        let total: i64 = available.iter().map(|&i| self.deployments[i].weight).sum();
+        let Some(&first) = available.first() else {
+            return Err(RustedChainError::Overloaded(
+                "every deployment's circuit breaker is open".to_string(),
+            ));
+        };
+        let mut chosen = first;
+        for &i in &available {
+            health[i].share += self.deployments[i].weight;
+            if health[i].share > health[chosen].share {
+                chosen = i;
+            }
+        }
+        let picked = &mut health[chosen];
+        picked.share -= total;
+        picked.breaker.admit(now);
+        Ok(chosen)
+    }
+
+    /// The API root and key of deployment `index`.
+    pub(crate) fn deployment(&self, index: usize) -> (&str, &ApiKey) {
+        let deployment = &self.deployments[index];
+        (&deployment.base_url, &deployment.api_key)
+    }
+
+    /// Count a request sent to deployment `index`. Errors worth retrying (network
+    /// failures, overload, rate limits and 5xx responses) count against its health;
+    /// `failure_threshold` of them in a row, or a failed probe, open its breaker.
+    pub(crate) fn record<T>(&self, index: usize, result: &Result<T, RustedChainError>) {
+        let mut health = self.health.lock().unwrap_or_else(|e| e.into_inner());
+        let health = &mut health[index];
+        let failed = result.as_ref().is_err_and(RustedChainError::is_retryable);
+        health.requests += 1;
+        health.failures += u64::from(failed);
+        health.breaker.record(failed, self.failure_threshold);
+    }
+
+    fn from_list(
+        deployments: &Bound<'_, PyList>,
+        failure_threshold: u32,
+        cooldown: f64,
+    ) -> PyResult<Self> {
+        if deployments.is_empty() {
+            return Err(PyValueError::new_err(
+                "a load balancer needs at least one deployment",
+            ));
+        }
+        if failure_threshold == 0 {
+            return Err(PyValueError::new_err(
+                "failure_threshold must be at least 1",
+            ));
+        }
+        let deployments = deployments
+            .iter()
+            .map(|deployment| {
+                let deployment = deployment.cast::<PyDict>().map_err(|_| {
+                    PyTypeError::new_err(
+                        "each deployment must be a dict with base_url, api_key and an optional weight",
+                    )
+                })?;
+                let mut base_url = None;
+                let mut api_key = None;
+                let mut weight = 1;
+                for (key, value) in deployment.iter() {
+                    match key.extract::<String>()?.as_str() {
+                        "base_url" => base_url = Some(value.extract::<String>()?),
+                        "api_key" => api_key = Some(value.extract::<ApiKey>()?),
+                        "weight" => weight = value.extract::<u32>()?,
+                        other => {
+                            return Err(PyValueError::new_err(format!(
+                                "unknown deployment key '{}'",
+                                other
+                            )))
+                        }
+                    }
+                }
+                let (Some(base_url), Some(api_key)) = (base_url, api_key) else {
+                    return Err(PyValueError::new_err(
+                        "each deployment needs a base_url and an api_key",
+                    ));
+                };
+                if weight == 0 {
+                    return Err(PyValueError::new_err("deployment weights must be at least 1"));
+                }
+                Ok(Deployment {
+                    base_url: base_url.trim_end_matches('/').to_string(),
+                    api_key,
+                    weight: weight.into(),
+                })
+            })
+            .collect::<PyResult<Vec<_>>>()?;
+        Ok(Balancer {
+            health: Mutex::new((0..deployments.len()).map(|_| Health::default()).collect()),
+            deployments,
+            failure_threshold,
+            cooldown: Duration::from_secs_f64(cooldown.max(0.0)),
+        })
+    }
+}
+
+/// `deployments` as an agent option: a `LoadBalancer`, or a list of deployment dicts
+/// balanced with the default breaker settings.
+pub(crate) fn from_py(deployments: &Bound<'_, PyAny>) -> PyResult<Arc<Balancer>> {
+    if let Ok(balancer) = deployments.cast::<LoadBalancer>() {
+        return Ok(balancer.get().balancer.clone());
+    }
+    let list = deployments.cast::<PyList>().map_err(|_| {
+        PyTypeError::new_err("deployments must be a LoadBalancer or a list of deployment dicts")
+    })?;
+    Ok(Arc::new(Balancer::from_list(
+        list,
+        DEFAULT_FAILURE_THRESHOLD,
+        DEFAULT_COOLDOWN,
+    )?))
+}
+
+const DEFAULT_FAILURE_THRESHOLD: u32 = 5;
+const DEFAULT_COOLDOWN: f64 = 30.0;
+
+/// Deployments of one OpenAI-compatible model that requests are spread across, shared
+/// by every model given the balancer.
+#[pyclass(frozen)]
+pub struct LoadBalancer {
+    balancer: Arc<Balancer>,
+}
+
+#[pymethods]
+impl LoadBalancer {
+    /// `deployments` is a list of `{"base_url", "api_key", "weight"}` dicts; `weight`
+    /// defaults to 1. After `failure_threshold` failed requests in a row a deployment
+    /// gets no traffic for `cooldown` seconds, then one probe request decides whether
+    /// it comes back.
+    #[new]
+    #[pyo3(signature = (deployments, failure_threshold=DEFAULT_FAILURE_THRESHOLD, cooldown=DEFAULT_COOLDOWN))]
+    fn new(
+        deployments: &Bound<'_, PyList>,
+        failure_threshold: u32,
+        cooldown: f64,
+    ) -> PyResult<Self> {
+        Ok(LoadBalancer {
+            balancer: Arc::new(Balancer::from_list(
+                deployments,
+                failure_threshold,
+                cooldown,
+            )?),
+        })
+    }
+
+    /// Per deployment, in order: `{"base_url", "weight", "state", "requests",
+    /// "failures", "consecutive_failures"}`, where `state` is `"closed"`, `"open"` or
+    /// `"half_open"`.
+    fn health<'py>(&self, py: Python<'py>) -> PyResult<Vec<Bound<'py, PyDict>>> {
+        let balancer = &self.balancer;
+        let health = balancer.health.lock().unwrap_or_else(|e| e.into_inner());
+        let now = Instant::now();
+        balancer
+            .deployments
+            .iter()
+            .zip(health.iter())
+            .map(|(deployment, health)| {
+                let entry = PyDict::new(py);
+                entry.set_item("base_url", &deployment.base_url)?;
+                entry.set_item("weight", deployment.weight)?;
+                entry.set_item("state", health.breaker.state(balancer.cooldown, now))?;
+                entry.set_item("requests", health.requests)?;
+                entry.set_item("failures", health.failures)?;
+                entry.set_item(
+                    "consecutive_failures",
+                    health.breaker.consecutive_failures(),
+                )?;
+                Ok(entry)
+            })
+            .collect()
+    }
+
+    fn __len__(&self) -> usize {
+        self.balancer.deployments.len()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "LoadBalancer(deployments={}, failure_threshold={}, cooldown={:.1})",
+            self.balancer.deployments.len(),
+            self.balancer.failure_threshold,
+            self.balancer.cooldown.as_secs_f64()
+        )
+    }
+}
@@ -0,0 +1,197 @@
+//! In-process BM25 keyword index, used for keyword and hybrid vector-store search.
+
+use crate::vector_store::Record;
+use std::collections::HashMap;
+
+const K1: f64 = 1.2;
+const B: f64 = 0.75;
+
+/// Lowercased alphanumeric terms; underscores are kept so identifiers stay whole.
+pub(crate) fn terms(text: &str) -> Vec<String> {
+    text.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|t| !t.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+struct Entry {
+    record: Record,
+    counts: HashMap<String, usize>,
+    length: usize,
+}
+
+#[derive(Default)]
+pub(crate) struct Bm25Index {
+    entries: HashMap<String, Entry>,
+    /// Number of entries containing each term.
+    document_frequency: HashMap<String, usize>,
+    total_length: usize,
+}
+
+impl Bm25Index {
+    /// Index `record`, replacing any entry with the same id. The vector is not kept.
+    pub(crate) fn insert(&mut self, mut record: Record) {
+        self.remove(&record.id);
+        record.vector = Vec::new();
+        let terms = terms(&record.text);
+        let mut counts = HashMap::new();
+        for term in &terms {
+            *counts.entry(term.clone()).or_insert(0) += 1;
+        }
+        for term in counts.keys() {
+            *self.document_frequency.entry(term.clone()).or_insert(0) += 1;
+        }
+        self.total_length += terms.len();
+        self.entries.insert(
+            record.id.clone(),
+            Entry {
+                record,
+                counts,
+                length: terms.len(),
+            },
+        );
+    }
+
+    pub(crate) fn remove(&mut self, id: &str) {
+        let Some(entry) = self.entries.remove(id) else {
+            return;
+        };
+        self.total_length -= entry.length;
+        for term in entry.counts.keys() {
+            if let Some(df) = self.document_frequency.get_mut(term) {
+                *df -= 1;
+                if *df == 0 {
+                    self.document_frequency.remove(term);
+                }
+            }
+        }
+    }
+
+    /// The `k` best-matching records for `query`, best first. Records matching no query
+    /// term are left out.
+    pub(crate) fn search(&self, query: &str, k: usize) -> Vec<(Record, f32)> {
+        if self.entries.is_empty() {
+            return Vec::new();
+        }
+        let n = self.entries.len() as f64;
+        let average_length = self.total_length as f64 / n;
+        let mut query_terms = terms(query);
+        query_terms.sort();
+        query_terms.dedup();
+        let weighted: Vec<(&String, f64)> = query_terms
+            .iter()
+            .filter_map(|term| {
+                let df = *self.document_frequency.get(term)? as f64;
+                Some((term, (1.0 + (n - df + 0.5) / (df + 0.5)).ln()))
+            })
+            .collect();
+
+        let mut scored: Vec<(&Entry, f64)> = self
+            .entries
+            .values()
+            .filter_map(|entry| {
+                let length_norm = 1.0 - B + B * entry.length as f64 / average_length.max(1.0);
+                let score: f64 = weighted
+                    .iter()
+                    .map(|(term, idf)| {
+                        let tf = entry.counts.get(*term).copied().unwrap_or(0) as f64;
+                        idf * tf * (K1 + 1.0) / (tf + K1 * length_norm)
+                    })
+                    .sum();
+                (score > 0.0).then_some((entry, score))
+            })
+            .collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+        scored
+            .into_iter()
+            .take(k)
+            .map(|(entry, score)| (entry.record.clone(), score as f32))
+            .collect()
+    }
+}
+
+/// Merge ranked lists by reciprocal rank fusion: each record scores the sum of
+/// `1 / (60 + rank)` over the lists it appears in, so agreement between lists counts
+/// more than any single list's raw scores.
+pub(crate) fn reciprocal_rank_fusion(
+    lists: Vec<Vec<(Record, f32)>>,
+    k: usize,
+) -> Vec<(Record, f32)> {
+    const RANK_OFFSET: f32 = 60.0;
+    let mut fused: Vec<(Record, f32)> = Vec::new();
+    for list in lists {
+        for (rank, (record, _)) in list.into_iter().enumerate() {
+            let score = 1.0 / (RANK_OFFSET + rank as f32 + 1.0);
+            match fused.iter_mut().find(|(r, _)| r.id == record.id) {
+                Some((_, total)) => *total += score,
+                None => fused.push((record, score)),
+            }
+        }
+    }
+    fused.sort_by(|a, b| b.1.total_cmp(&a.1));
+    fused.truncate(k);
+    fused
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index(texts: &[&str]) -> Bm25Index {
+        let mut index = Bm25Index::default();
+        for (i, text) in texts.iter().enumerate() {
+            index.insert(Record {
+                id: i.to_string(),
+                text: text.to_string(),
+                metadata: Default::default(),
+                vector: Vec::new(),
+            });
+        }
+        index
+    }
+
+    fn ids(results: &[(Record, f32)]) -> Vec<&str> {
+        results.iter().map(|(r, _)| r.id.as_str()).collect()
+    }
+
+    #[test]
+    fn an_empty_index_finds_nothing() {
+        assert!(index(&[]).search("apple", 5).is_empty());
+
+        let mut emptied = index(&["apple"]);
+        emptied.remove("0");
+        assert!(emptied.search("apple", 5).is_empty());
+    }
+
+    #[test]
+    fn a_term_in_every_record_still_scores() {
+        // One record of one term: tf and length cancel out, leaving ln(1 + 0.5 / 1.5).
+        let results = index(&["apple"]).search("apple", 5);
+        assert_eq!(ids(&results), ["0"]);
+        assert_eq!(results[0].1, (4.0f64 / 3.0).ln() as f32);
+
+        let results = index(&["apple banana", "apple cherry"]).search("apple", 5);
+        assert_eq!(results.len(), 2);
+        assert!(results.iter().all(|(_, score)| *score > 0.0));
+    }
+
+    #[test]
+    fn rarer_terms_weigh_more() {
+        let index = index(&["apple banana", "apple cherry", "apple durian"]);
+        let common = index.search("apple", 1)[0].1;
+        let rare = index.search("cherry", 1)[0].1;
+        assert!(rare > common, "{rare} <= {common}");
+        assert_eq!(ids(&index.search("apple cherry", 3))[0], "1");
+        assert!(index.search("mango", 3).is_empty());
+    }
+
+    #[test]
+    fn removing_a_record_updates_document_frequencies() {
+        let mut pruned = index(&["apple", "apple cherry"]);
+        pruned.remove("1");
+        assert_eq!(
+            pruned.search("apple", 1)[0].1,
+            index(&["apple"]).search("apple", 1)[0].1
+        );
+    }
+}
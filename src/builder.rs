@@ -0,0 +1,206 @@
+//! Step-by-step agent construction. Each setter records a value and returns the
+//! builder, and everything is checked together when `build()` makes the agent.
+
+use crate::keys::ApiKey;
+use crate::options::AgentOptions;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::collections::HashSet;
+
+/// A tool added with `AgentBuilder.tool`, with its settings.
+struct PendingTool {
+    tool: Py<PyAny>,
+    strict: Option<bool>,
+    max_concurrency: Option<usize>,
+    rate_limit_per_min: Option<usize>,
+    cache_ttl: Option<f64>,
+}
+
+/// Builds an agent one setting at a time:
+/// `AgentBuilder().model("gpt-4o").system(...).tool(f).temperature(0.3).build()`.
+/// `build()` returns the same model class `create_agent` does. Settings are checked
+/// there, so a mistake is reported once, before any request is made; a builder can be
+/// built from more than once.
+#[pyclass]
+pub struct AgentBuilder {
+    model: Option<String>,
+    provider: Option<String>,
+    api_key: Option<ApiKey>,
+    tools: Vec<PendingTool>,
+    /// Keyword options for the model, as `create_agent` takes them.
+    options: Py<PyDict>,
+}
+
+#[pymethods]
+impl AgentBuilder {
+    #[new]
+    fn new(py: Python) -> Self {
+        AgentBuilder {
+            model: None,
+            provider: None,
+            api_key: None,
+            tools: Vec::new(),
+            options: PyDict::new(py).unbind(),
+        }
+    }
+
+    /// The model to run, e.g. `"gpt-4o"`. The provider is picked from the name unless
+    /// `provider()` names it.
+    fn model(mut slf: PyRefMut<'_, Self>, model: String) -> PyRefMut<'_, Self> {
+        slf.model = Some(model);
+        slf
+    }
+
+    /// The provider, as `create_agent(provider=...)` takes it (see `Providers`).
+    fn provider(mut slf: PyRefMut<'_, Self>, provider: String) -> PyRefMut<'_, Self> {
+        slf.provider = Some(provider);
+        slf
+    }
+
+    /// An API key, `KeyPool` or token callback, instead of the provider's variable.
+    fn api_key(mut slf: PyRefMut<'_, Self>, api_key: ApiKey) -> PyRefMut<'_, Self> {
+        slf.api_key = Some(api_key);
+        slf
+    }
+
+    /// Add a tool: a function, LangChain tool or tool object such as `Delegate`.
+    /// `strict`, `max_concurrency`, `rate_limit_per_min` and `cache_ttl` are as for
+    /// `add_tool`.
+    #[pyo3(signature = (tool, strict=None, max_concurrency=None, rate_limit_per_min=None, cache_ttl=None))]
+    fn tool(
+        mut slf: PyRefMut<'_, Self>,
+        tool: Py<PyAny>,
+        strict: Option<bool>,
+        max_concurrency: Option<usize>,
+        rate_limit_per_min: Option<usize>,
+        cache_ttl: Option<f64>,
+    ) -> PyRefMut<'_, Self> {
+        slf.tools.push(PendingTool {
+            tool,
+            strict,
+            max_concurrency,
+            rate_limit_per_min,
+            cache_ttl,
+        });
+        slf
+    }
+
+    /// Add several tools.
+    fn tools(mut slf: PyRefMut<'_, Self>, tools: Vec<Py<PyAny>>) -> PyRefMut<'_, Self> {
+        slf.tools.extend(tools.into_iter().map(|tool| PendingTool {
+            tool,
+            strict: None,
+            max_concurrency: None,
+            rate_limit_per_min: None,
+            cache_ttl: None,
+        }));
+        slf
+    }
+
+    /// The system prompt.
+    fn system(slf: PyRefMut<'_, Self>, system: String) -> PyResult<PyRefMut<'_, Self>> {
+        Self::set(slf, "system", system)
+    }
+
+    /// The agent's task, sent after the system prompt with tool-use guidance.
+    fn instructions(slf: PyRefMut<'_, Self>, instructions: String) -> PyResult<PyRefMut<'_, Self>> {
+        Self::set(slf, "instructions", instructions)
+    }
+
+    /// The language answers are given in, as an ISO 639-1 code or name.
+    fn language(slf: PyRefMut<'_, Self>, language: String) -> PyResult<PyRefMut<'_, Self>> {
+        Self::set(slf, "language", language)
+    }
+
+    /// Sampling temperature, from 0 to 2 (to 1 for Claude).
+    fn temperature(slf: PyRefMut<'_, Self>, temperature: f64) -> PyResult<PyRefMut<'_, Self>> {
+        Self::set(slf, "temperature", temperature)
+    }
+
+    /// How many times a failed provider request is retried.
+    fn max_retries(slf: PyRefMut<'_, Self>, max_retries: u32) -> PyResult<PyRefMut<'_, Self>> {
+        Self::set(slf, "max_retries", max_retries)
+    }
+
+    /// Set any keyword option `create_agent` takes, e.g. `option("prefill", "{")`.
+    fn option<'py>(
+        slf: PyRefMut<'py, Self>,
+        name: String,
+        value: Bound<'py, PyAny>,
+    ) -> PyResult<PyRefMut<'py, Self>> {
+        Self::set(slf, &name, value)
+    }
+
+    /// Set several keyword options at once.
+    #[pyo3(signature = (**options))]
+    fn options<'py>(
+        slf: PyRefMut<'py, Self>,
+        options: Option<&Bound<'py, PyDict>>,
+    ) -> PyResult<PyRefMut<'py, Self>> {
+        if let Some(options) = options {
+            slf.options.bind(slf.py()).update(options.as_mapping())?;
+        }
+        Ok(slf)
+    }
+
+    /// Check the settings and make the agent. Raises `ValueError` or `TypeError` for a
+    /// missing model, a tool added twice, or an option that is unknown, out of range or
+    /// unsupported by the provider.
+    fn build(&self, py: Python) -> PyResult<Py<PyAny>> {
+        let model = self.model.clone().ok_or_else(|| {
+            PyValueError::new_err("AgentBuilder has no model; call .model() before .build()")
+        })?;
+        let wrap = py
+            .import("rusted_chain.tool_helpers")?
+            .getattr("ensure_tool_wrapper")?;
+        let mut options = AgentOptions::from_kwargs(Some(self.options.bind(py)))?;
+        let mut names = HashSet::new();
+        let mut tools = Vec::new();
+        for pending in &self.tools {
+            let tool = wrap.call1((pending.tool.bind(py), pending.strict))?;
+            let name: String = tool.getattr("__name__")?.extract()?;
+            if !names.insert(name.clone()) {
+                return Err(PyValueError::new_err(format!(
+                    "tool '{}' was added twice",
+                    name
+                )));
+            }
+            if pending.max_concurrency.is_some() || pending.rate_limit_per_min.is_some() {
+                options.limit_tool(&tool, pending.max_concurrency, pending.rate_limit_per_min)?;
+            }
+            options.cache_tool(&tool, pending.cache_ttl)?;
+            tools.push(tool.unbind());
+        }
+        crate::build_agent(
+            py,
+            model,
+            (!tools.is_empty()).then_some(tools),
+            self.api_key.clone(),
+            self.provider.as_deref(),
+            options,
+        )
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "AgentBuilder(model={}, tools={})",
+            self.model
+                .as_deref()
+                .map_or("None".to_string(), |model| format!("'{}'", model)),
+            self.tools.len()
+        )
+    }
+}
+
+impl AgentBuilder {
+    /// Record keyword option `name`, replacing an earlier value.
+    fn set<'py>(
+        slf: PyRefMut<'py, Self>,
+        name: &str,
+        value: impl IntoPyObject<'py>,
+    ) -> PyResult<PyRefMut<'py, Self>> {
+        slf.options.bind(slf.py()).set_item(name, value)?;
+        Ok(slf)
+    }
+}
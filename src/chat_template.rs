@@ -0,0 +1,1525 @@
+//! Chat templates for OpenAI-compatible servers that only take raw completion prompts.
+//!
+//! The template language is the subset of Jinja that HuggingFace chat templates use:
+//! `{{ }}` output, `{% if %}`/`{% elif %}`/`{% else %}`, `{% for %}` with `loop`,
+//! `{% set %}` (including `namespace` attributes), filters, tests, slices, comments and
+//! `-` whitespace control. As with `apply_chat_template`, blocks are rendered with
+//! `trim_blocks` and `lstrip_blocks`.
+
+use crate::error::RustedChainError;
+use crate::stream::TextSink;
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use serde_json::{json, Map, Value};
+
+type Result<T> = std::result::Result<T, String>;
+
+// ---------------------------------------------------------------------------
+// Lexing: template source into text and tags
+// ---------------------------------------------------------------------------
+
+enum Piece {
+    Text(String),
+    Output(String),
+    Block(String),
+}
+
+fn split_pieces(source: &str) -> Result<Vec<Piece>> {
+    let mut pieces = Vec::new();
+    let mut rest = source;
+    // Whether the text that follows should lose its leading whitespace (`-%}`) or its
+    // first newline (`%}` with trim_blocks).
+    let mut trim_next = false;
+    let mut strip_newline = false;
+
+    loop {
+        let next = ["{{", "{%", "{#"]
+            .iter()
+            .filter_map(|open| rest.find(open).map(|at| (at, *open)))
+            .min_by_key(|(at, _)| *at);
+        let (text, tag) = match next {
+            Some((at, open)) => (&rest[..at], Some((at, open))),
+            None => (rest, None),
+        };
+
+        let mut text = text.to_string();
+        if trim_next {
+            text = text.trim_start().to_string();
+        } else if strip_newline {
+            if let Some(stripped) = text
+                .strip_prefix("\r\n")
+                .or_else(|| text.strip_prefix('\n'))
+            {
+                text = stripped.to_string();
+            }
+        }
+        trim_next = false;
+        strip_newline = false;
+
+        let Some((at, open)) = tag else {
+            pieces.push(Piece::Text(text));
+            return Ok(pieces);
+        };
+        let close = match open {
+            "{{" => "}}",
+            "{%" => "%}",
+            _ => "#}",
+        };
+        let body_start = at + 2;
+        let end = rest[body_start..]
+            .find(close)
+            .map(|i| body_start + i)
+            .ok_or_else(|| format!("unclosed '{}' in template", open))?;
+        let mut body = &rest[body_start..end];
+
+        let trim_before = body.starts_with('-');
+        if trim_before {
+            body = &body[1..];
+            text = text.trim_end().to_string();
+        } else if open != "{{" {
+            // lstrip_blocks: whitespace between the line start and a block is dropped.
+            let line_start = text.rfind('\n').map_or(0, |i| i + 1);
+            if text[line_start..].chars().all(|c| c == ' ' || c == '\t') {
+                text.truncate(line_start);
+            }
+        }
+        if body.ends_with('-') {
+            body = &body[..body.len() - 1];
+            trim_next = true;
+        } else if open != "{{" {
+            strip_newline = true;
+        }
+
+        pieces.push(Piece::Text(text));
+        match open {
+            "{{" => pieces.push(Piece::Output(body.trim().to_string())),
+            "{%" => pieces.push(Piece::Block(body.trim().to_string())),
+            _ => {}
+        }
+        rest = &rest[end + 2..];
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Expressions
+// ---------------------------------------------------------------------------
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Name(String),
+    Str(String),
+    Int(i64),
+    Float(f64),
+    Op(&'static str),
+}
+
+const OPERATORS: &[&str] = &[
+    "==", "!=", "<=", ">=", "(", ")", "[", "]", ".", ",", ":", "|", "~", "+", "-", "*", "/", "%",
+    "<", ">", "=", "{", "}",
+];
+
+fn tokenize(source: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let chars: Vec<char> = source.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c == '\'' || c == '"' {
+            let mut text = String::new();
+            i += 1;
+            while i < chars.len() && chars[i] != c {
+                if chars[i] == '\\' && i + 1 < chars.len() {
+                    i += 1;
+                    text.push(match chars[i] {
+                        'n' => '\n',
+                        't' => '\t',
+                        'r' => '\r',
+                        other => other,
+                    });
+                } else {
+                    text.push(chars[i]);
+                }
+                i += 1;
+            }
+            if i == chars.len() {
+                return Err("unterminated string in template".to_string());
+            }
+            i += 1;
+            tokens.push(Token::Str(text));
+        } else if c.is_ascii_digit() {
+            let start = i;
+            while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                i += 1;
+            }
+            let number: String = chars[start..i].iter().collect();
+            tokens.push(if number.contains('.') {
+                Token::Float(
+                    number
+                        .parse()
+                        .map_err(|_| format!("bad number '{}'", number))?,
+                )
+            } else {
+                Token::Int(
+                    number
+                        .parse()
+                        .map_err(|_| format!("bad number '{}'", number))?,
+                )
+            });
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].is_alphanumeric() || chars[i] == '_') {
+                i += 1;
+            }
+            tokens.push(Token::Name(chars[start..i].iter().collect()));
+        } else {
+            let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+            let op = OPERATORS
+                .iter()
+                .find(|op| rest.starts_with(**op))
+                .ok_or_else(|| format!("unexpected '{}' in template", c))?;
+            i += op.len();
+            tokens.push(Token::Op(op));
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Literal(Value),
+    Var(String),
+    Attr(Box<Expr>, String),
+    Index(Box<Expr>, Box<Expr>),
+    Slice(Box<Expr>, Option<Box<Expr>>, Option<Box<Expr>>),
+    List(Vec<Expr>),
+    Dict(Vec<(Expr, Expr)>),
+    Call(Box<Expr>, Vec<(Option<String>, Expr)>),
+    Filter(Box<Expr>, String, Vec<(Option<String>, Expr)>),
+    Test(Box<Expr>, String, bool),
+    Not(Box<Expr>),
+    Neg(Box<Expr>),
+    Binary(&'static str, Box<Expr>, Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Conditional(Box<Expr>, Box<Expr>, Box<Expr>),
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn new(source: &str) -> Result<Self> {
+        Ok(Parser {
+            tokens: tokenize(source)?,
+            position: 0,
+        })
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn at_op(&self, op: &str) -> bool {
+        matches!(self.peek(), Some(Token::Op(o)) if *o == op)
+    }
+
+    fn at_name(&self, name: &str) -> bool {
+        matches!(self.peek(), Some(Token::Name(n)) if n == name)
+    }
+
+    fn eat_op(&mut self, op: &str) -> bool {
+        let found = self.at_op(op);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn eat_name(&mut self, name: &str) -> bool {
+        let found = self.at_name(name);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn expect_op(&mut self, op: &str) -> Result<()> {
+        if self.eat_op(op) {
+            Ok(())
+        } else {
+            Err(format!("expected '{}' in template expression", op))
+        }
+    }
+
+    fn name(&mut self) -> Result<String> {
+        match self.next() {
+            Some(Token::Name(name)) => Ok(name),
+            other => Err(format!("expected a name, found {:?}", other)),
+        }
+    }
+
+    fn finished(&self) -> bool {
+        self.position >= self.tokens.len()
+    }
+
+    fn expression(&mut self) -> Result<Expr> {
+        let value = self.or()?;
+        if self.eat_name("if") {
+            let condition = self.or()?;
+            let otherwise = if self.eat_name("else") {
+                self.expression()?
+            } else {
+                Expr::Literal(Value::String(String::new()))
+            };
+            return Ok(Expr::Conditional(
+                Box::new(condition),
+                Box::new(value),
+                Box::new(otherwise),
+            ));
+        }
+        Ok(value)
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        let mut left = self.and()?;
+        while self.eat_name("or") {
+            left = Expr::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        let mut left = self.not()?;
+        while self.eat_name("and") {
+            left = Expr::And(Box::new(left), Box::new(self.not()?));
+        }
+        Ok(left)
+    }
+
+    fn not(&mut self) -> Result<Expr> {
+        if self.eat_name("not") {
+            return Ok(Expr::Not(Box::new(self.not()?)));
+        }
+        self.comparison()
+    }
+
+    fn comparison(&mut self) -> Result<Expr> {
+        let mut left = self.concat()?;
+        loop {
+            let op = match self.peek() {
+                Some(Token::Op(op)) if ["==", "!=", "<", ">", "<=", ">="].contains(op) => *op,
+                Some(Token::Name(name)) if name == "in" => "in",
+                Some(Token::Name(name))
+                    if name == "not"
+                        && matches!(self.tokens.get(self.position + 1), Some(Token::Name(n)) if n == "in") =>
+                {
+                    self.position += 1;
+                    "not in"
+                }
+                Some(Token::Name(name)) if name == "is" => {
+                    self.position += 1;
+                    let negated = self.eat_name("not");
+                    let test = self.name()?;
+                    left = Expr::Test(Box::new(left), test, negated);
+                    continue;
+                }
+                _ => return Ok(left),
+            };
+            self.position += 1;
+            left = Expr::Binary(op, Box::new(left), Box::new(self.concat()?));
+        }
+    }
+
+    fn concat(&mut self) -> Result<Expr> {
+        let mut left = self.sum()?;
+        while self.eat_op("~") {
+            left = Expr::Binary("~", Box::new(left), Box::new(self.sum()?));
+        }
+        Ok(left)
+    }
+
+    fn sum(&mut self) -> Result<Expr> {
+        let mut left = self.product()?;
+        loop {
+            let op = if self.eat_op("+") {
+                "+"
+            } else if self.eat_op("-") {
+                "-"
+            } else {
+                return Ok(left);
+            };
+            left = Expr::Binary(op, Box::new(left), Box::new(self.product()?));
+        }
+    }
+
+    fn product(&mut self) -> Result<Expr> {
+        let mut left = self.unary()?;
+        loop {
+            let op = if self.eat_op("*") {
+                "*"
+            } else if self.eat_op("/") {
+                "/"
+            } else if self.eat_op("%") {
+                "%"
+            } else {
+                return Ok(left);
+            };
+            left = Expr::Binary(op, Box::new(left), Box::new(self.unary()?));
+        }
+    }
+
+    fn unary(&mut self) -> Result<Expr> {
+        if self.eat_op("-") {
+            return Ok(Expr::Neg(Box::new(self.unary()?)));
+        }
+        self.filtered()
+    }
+
+    fn filtered(&mut self) -> Result<Expr> {
+        let mut value = self.postfix()?;
+        while self.eat_op("|") {
+            let name = self.name()?;
+            let args = if self.eat_op("(") {
+                self.arguments()?
+            } else {
+                Vec::new()
+            };
+            value = Expr::Filter(Box::new(value), name, args);
+        }
+        Ok(value)
+    }
+
+    fn postfix(&mut self) -> Result<Expr> {
+        let mut value = self.primary()?;
+        loop {
+            if self.eat_op(".") {
+                value = Expr::Attr(Box::new(value), self.name()?);
+            } else if self.eat_op("[") {
+                let start = if self.at_op(":") {
+                    None
+                } else {
+                    Some(Box::new(self.expression()?))
+                };
+                if self.eat_op(":") {
+                    let end = if self.at_op("]") {
+                        None
+                    } else {
+                        Some(Box::new(self.expression()?))
+                    };
+                    self.expect_op("]")?;
+                    value = Expr::Slice(Box::new(value), start, end);
+                } else {
+                    self.expect_op("]")?;
+                    let index = start.ok_or("empty subscript in template")?;
+                    value = Expr::Index(Box::new(value), index);
+                }
+            } else if self.eat_op("(") {
+                value = Expr::Call(Box::new(value), self.arguments()?);
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    /// Call arguments after the opening parenthesis, positional or `name=value`.
+    fn arguments(&mut self) -> Result<Vec<(Option<String>, Expr)>> {
+        let mut args = Vec::new();
+        while !self.eat_op(")") {
+            let keyword = match (self.peek(), self.tokens.get(self.position + 1)) {
+                (Some(Token::Name(name)), Some(Token::Op("="))) => {
+                    let name = name.clone();
+                    self.position += 2;
+                    Some(name)
+                }
+                _ => None,
+            };
+            args.push((keyword, self.expression()?));
+            if !self.eat_op(",") {
+                self.expect_op(")")?;
+                break;
+            }
+        }
+        Ok(args)
+    }
+
+    fn primary(&mut self) -> Result<Expr> {
+        match self.next() {
+            Some(Token::Str(text)) => Ok(Expr::Literal(Value::String(text))),
+            Some(Token::Int(n)) => Ok(Expr::Literal(json!(n))),
+            Some(Token::Float(n)) => Ok(Expr::Literal(json!(n))),
+            Some(Token::Name(name)) => Ok(match name.as_str() {
+                "true" | "True" => Expr::Literal(Value::Bool(true)),
+                "false" | "False" => Expr::Literal(Value::Bool(false)),
+                "none" | "None" => Expr::Literal(Value::Null),
+                _ => Expr::Var(name),
+            }),
+            Some(Token::Op("(")) => {
+                let value = self.expression()?;
+                self.expect_op(")")?;
+                Ok(value)
+            }
+            Some(Token::Op("[")) => {
+                let mut items = Vec::new();
+                while !self.eat_op("]") {
+                    items.push(self.expression()?);
+                    if !self.eat_op(",") {
+                        self.expect_op("]")?;
+                        break;
+                    }
+                }
+                Ok(Expr::List(items))
+            }
+            Some(Token::Op("{")) => {
+                let mut entries = Vec::new();
+                while !self.eat_op("}") {
+                    let key = self.expression()?;
+                    self.expect_op(":")?;
+                    entries.push((key, self.expression()?));
+                    if !self.eat_op(",") {
+                        self.expect_op("}")?;
+                        break;
+                    }
+                }
+                Ok(Expr::Dict(entries))
+            }
+            other => Err(format!("unexpected {:?} in template expression", other)),
+        }
+    }
+}
+
+fn parse_expression(source: &str) -> Result<Expr> {
+    let mut parser = Parser::new(source)?;
+    let expr = parser.expression()?;
+    if !parser.finished() {
+        return Err(format!("unexpected text in expression '{}'", source));
+    }
+    Ok(expr)
+}
+
+// ---------------------------------------------------------------------------
+// Statements
+// ---------------------------------------------------------------------------
+
+#[derive(Clone, Debug)]
+enum Node {
+    Text(String),
+    Output(Expr),
+    If(Vec<(Expr, Vec<Node>)>, Vec<Node>),
+    For {
+        names: Vec<String>,
+        iterable: Expr,
+        filter: Option<Expr>,
+        body: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+    /// `{% set a = x %}` or `{% set ns.a = x %}`.
+    Set(String, Option<String>, Expr),
+}
+
+/// A block tag split into its keyword and the rest.
+fn keyword(tag: &str) -> (&str, &str) {
+    match tag.split_once(char::is_whitespace) {
+        Some((keyword, rest)) => (keyword, rest.trim()),
+        None => (tag, ""),
+    }
+}
+
+fn parse_nodes(
+    pieces: &mut std::iter::Peekable<std::vec::IntoIter<Piece>>,
+) -> Result<(Vec<Node>, Option<String>)> {
+    let mut nodes = Vec::new();
+    while let Some(piece) = pieces.next() {
+        match piece {
+            Piece::Text(text) if !text.is_empty() => nodes.push(Node::Text(text)),
+            Piece::Text(_) => {}
+            Piece::Output(expr) => nodes.push(Node::Output(parse_expression(&expr)?)),
+            Piece::Block(tag) => {
+                let (word, rest) = keyword(&tag);
+                match word {
+                    "if" => nodes.push(parse_if(pieces, rest)?),
+                    "for" => nodes.push(parse_for(pieces, rest)?),
+                    "set" => {
+                        let (target, value) = rest
+                            .split_once('=')
+                            .ok_or_else(|| format!("expected '=' in '{{% {} %}}'", tag))?;
+                        let target = target.trim();
+                        let (name, attribute) = match target.split_once('.') {
+                            Some((name, attribute)) => {
+                                (name.to_string(), Some(attribute.to_string()))
+                            }
+                            None => (target.to_string(), None),
+                        };
+                        nodes.push(Node::Set(name, attribute, parse_expression(value)?));
+                    }
+                    "elif" | "else" | "endif" | "endfor" => return Ok((nodes, Some(tag))),
+                    "generation" | "endgeneration" => {}
+                    _ => return Err(format!("unsupported template tag '{{% {} %}}'", tag)),
+                }
+            }
+        }
+    }
+    Ok((nodes, None))
+}
+
+fn parse_if(
+    pieces: &mut std::iter::Peekable<std::vec::IntoIter<Piece>>,
+    condition: &str,
+) -> Result<Node> {
+    let mut branches = Vec::new();
+    let mut condition = parse_expression(condition)?;
+    loop {
+        let (body, end) = parse_nodes(pieces)?;
+        let end = end.ok_or("missing '{% endif %}' in template")?;
+        let (word, rest) = keyword(&end);
+        match word {
+            "elif" => {
+                branches.push((condition, body));
+                condition = parse_expression(rest)?;
+            }
+            "else" => {
+                branches.push((condition, body));
+                let (otherwise, end) = parse_nodes(pieces)?;
+                if end.as_deref().map(keyword).map(|(w, _)| w) != Some("endif") {
+                    return Err("missing '{% endif %}' in template".to_string());
+                }
+                return Ok(Node::If(branches, otherwise));
+            }
+            "endif" => {
+                branches.push((condition, body));
+                return Ok(Node::If(branches, Vec::new()));
+            }
+            _ => return Err(format!("unexpected '{{% {} %}}' in if block", end)),
+        }
+    }
+}
+
+fn parse_for(
+    pieces: &mut std::iter::Peekable<std::vec::IntoIter<Piece>>,
+    header: &str,
+) -> Result<Node> {
+    let (names, rest) = header
+        .split_once(" in ")
+        .ok_or_else(|| format!("expected 'in' in '{{% for {} %}}'", header))?;
+    let names = names
+        .split(',')
+        .map(|name| name.trim().to_string())
+        .collect();
+    let (iterable, filter) = match split_top_level_if(rest) {
+        Some((iterable, filter)) => (iterable, Some(parse_expression(filter)?)),
+        None => (rest, None),
+    };
+    let iterable = parse_expression(iterable)?;
+    let (body, end) = parse_nodes(pieces)?;
+    let end = end.ok_or("missing '{% endfor %}' in template")?;
+    let otherwise = match keyword(&end).0 {
+        "endfor" => Vec::new(),
+        "else" => {
+            let (otherwise, end) = parse_nodes(pieces)?;
+            if end.as_deref().map(keyword).map(|(w, _)| w) != Some("endfor") {
+                return Err("missing '{% endfor %}' in template".to_string());
+            }
+            otherwise
+        }
+        _ => return Err(format!("unexpected '{{% {} %}}' in for block", end)),
+    };
+    Ok(Node::For {
+        names,
+        iterable,
+        filter,
+        body,
+        otherwise,
+    })
+}
+
+/// `messages if m.role != 'system'`: the loop filter, if there is one outside strings.
+fn split_top_level_if(source: &str) -> Option<(&str, &str)> {
+    let mut quote = None;
+    for (i, c) in source.char_indices() {
+        match quote {
+            Some(q) if c == q => quote = None,
+            Some(_) => {}
+            None if c == '\'' || c == '"' => quote = Some(c),
+            None if source[i..].starts_with(" if ") => {
+                return Some((&source[..i], &source[i + 4..]));
+            }
+            None => {}
+        }
+    }
+    None
+}
+
+// ---------------------------------------------------------------------------
+// Rendering
+// ---------------------------------------------------------------------------
+
+/// A parsed template.
+#[derive(Clone, Debug)]
+pub(crate) struct Template {
+    nodes: Vec<Node>,
+}
+
+impl Template {
+    pub(crate) fn parse(source: &str) -> Result<Self> {
+        let mut pieces = split_pieces(source)?.into_iter().peekable();
+        let (nodes, end) = parse_nodes(&mut pieces)?;
+        if let Some(end) = end {
+            return Err(format!("unexpected '{{% {} %}}' in template", end));
+        }
+        Ok(Template { nodes })
+    }
+
+    pub(crate) fn render(&self, variables: Map<String, Value>) -> Result<String> {
+        let mut scopes = vec![variables];
+        let mut out = String::new();
+        render_nodes(&self.nodes, &mut scopes, &mut out)?;
+        Ok(out)
+    }
+}
+
+fn render_nodes(
+    nodes: &[Node],
+    scopes: &mut Vec<Map<String, Value>>,
+    out: &mut String,
+) -> Result<()> {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Output(expr) => {
+                if let Some(value) = eval(expr, scopes)? {
+                    out.push_str(&display(&value));
+                }
+            }
+            Node::If(branches, otherwise) => {
+                let mut taken = false;
+                for (condition, body) in branches {
+                    if truthy(&eval(condition, scopes)?) {
+                        render_nodes(body, scopes, out)?;
+                        taken = true;
+                        break;
+                    }
+                }
+                if !taken {
+                    render_nodes(otherwise, scopes, out)?;
+                }
+            }
+            Node::For {
+                names,
+                iterable,
+                filter,
+                body,
+                otherwise,
+            } => {
+                let items = iterate(eval(iterable, scopes)?.unwrap_or(Value::Null))?;
+                let mut selected = Vec::new();
+                for item in items {
+                    if let Some(filter) = filter {
+                        scopes.push(bind(names, &item));
+                        let keep = truthy(&eval(filter, scopes)?);
+                        scopes.pop();
+                        if !keep {
+                            continue;
+                        }
+                    }
+                    selected.push(item);
+                }
+                if selected.is_empty() {
+                    render_nodes(otherwise, scopes, out)?;
+                }
+                let length = selected.len();
+                for (index, item) in selected.into_iter().enumerate() {
+                    let mut scope = bind(names, &item);
+                    scope.insert(
+                        "loop".to_string(),
+                        json!({
+                            "index": index + 1,
+                            "index0": index,
+                            "revindex": length - index,
+                            "revindex0": length - index - 1,
+                            "first": index == 0,
+                            "last": index + 1 == length,
+                            "length": length,
+                        }),
+                    );
+                    scopes.push(scope);
+                    let result = render_nodes(body, scopes, out);
+                    scopes.pop();
+                    result?;
+                }
+            }
+            Node::Set(name, attribute, expr) => {
+                let value = eval(expr, scopes)?.unwrap_or(Value::Null);
+                match attribute {
+                    None => {
+                        scopes
+                            .last_mut()
+                            .expect("there is always a scope")
+                            .insert(name.clone(), value);
+                    }
+                    Some(attribute) => {
+                        let target = scopes
+                            .iter_mut()
+                            .rev()
+                            .find_map(|scope| scope.get_mut(name))
+                            .and_then(Value::as_object_mut)
+                            .ok_or_else(|| format!("cannot set '{}.{}'", name, attribute))?;
+                        target.insert(attribute.clone(), value);
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn bind(names: &[String], item: &Value) -> Map<String, Value> {
+    let mut scope = Map::new();
+    if let [name] = names {
+        scope.insert(name.clone(), item.clone());
+    } else {
+        for (index, name) in names.iter().enumerate() {
+            scope.insert(
+                name.clone(),
+                item.get(index).cloned().unwrap_or(Value::Null),
+            );
+        }
+    }
+    scope
+}
+
+fn iterate(value: Value) -> Result<Vec<Value>> {
+    match value {
+        Value::Array(items) => Ok(items),
+        Value::Object(map) => Ok(map.into_iter().map(|(key, _)| Value::String(key)).collect()),
+        Value::String(text) => Ok(text.chars().map(|c| Value::String(c.to_string())).collect()),
+        Value::Null => Ok(Vec::new()),
+        other => Err(format!("cannot loop over {}", other)),
+    }
+}
+
+fn truthy(value: &Option<Value>) -> bool {
+    match value {
+        None | Some(Value::Null) => false,
+        Some(Value::Bool(b)) => *b,
+        Some(Value::Number(n)) => n.as_f64() != Some(0.0),
+        Some(Value::String(s)) => !s.is_empty(),
+        Some(Value::Array(items)) => !items.is_empty(),
+        Some(Value::Object(map)) => !map.is_empty(),
+    }
+}
+
+/// How a value prints, following Python's `str`.
+fn display(value: &Value) -> String {
+    match value {
+        Value::Null => "None".to_string(),
+        Value::Bool(true) => "True".to_string(),
+        Value::Bool(false) => "False".to_string(),
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
+
+fn number(value: &Value) -> Result<f64> {
+    value
+        .as_f64()
+        .ok_or_else(|| format!("expected a number, found {}", value))
+}
+
+fn numeric(result: f64, integers: bool) -> Value {
+    if integers && result.fract() == 0.0 {
+        json!(result as i64)
+    } else {
+        json!(result)
+    }
+}
+
+fn lookup(scopes: &[Map<String, Value>], name: &str) -> Option<Value> {
+    scopes
+        .iter()
+        .rev()
+        .find_map(|scope| scope.get(name).cloned())
+}
+
+fn index(value: &Value, key: &Value) -> Option<Value> {
+    match (value, key) {
+        (Value::Object(map), Value::String(key)) => map.get(key).cloned(),
+        (Value::Array(items), Value::Number(n)) => {
+            let n = n.as_i64()?;
+            let at = if n < 0 { items.len() as i64 + n } else { n };
+            items.get(usize::try_from(at).ok()?).cloned()
+        }
+        (Value::String(text), Value::Number(n)) => {
+            let chars: Vec<char> = text.chars().collect();
+            let n = n.as_i64()?;
+            let at = if n < 0 { chars.len() as i64 + n } else { n };
+            chars
+                .get(usize::try_from(at).ok()?)
+                .map(|c| Value::String(c.to_string()))
+        }
+        _ => None,
+    }
+}
+
+fn slice_bounds(len: usize, start: Option<i64>, end: Option<i64>) -> (usize, usize) {
+    let clamp = |n: i64| -> usize {
+        let n = if n < 0 { len as i64 + n } else { n };
+        n.clamp(0, len as i64) as usize
+    };
+    let start = start.map_or(0, clamp);
+    let end = end.map_or(len, clamp);
+    (start, end.max(start))
+}
+
+fn eval(expr: &Expr, scopes: &[Map<String, Value>]) -> Result<Option<Value>> {
+    let value = |expr: &Expr| -> Result<Value> { Ok(eval(expr, scopes)?.unwrap_or(Value::Null)) };
+    Ok(Some(match expr {
+        Expr::Literal(value) => value.clone(),
+        Expr::Var(name) => return Ok(lookup(scopes, name)),
+        Expr::Attr(target, name) => {
+            return Ok(eval(target, scopes)?.and_then(|target| index(&target, &json!(name))))
+        }
+        Expr::Index(target, key) => {
+            let key = value(key)?;
+            return Ok(eval(target, scopes)?.and_then(|target| index(&target, &key)));
+        }
+        Expr::Slice(target, start, end) => {
+            let start = start
+                .as_ref()
+                .map(|e| value(e))
+                .transpose()?
+                .and_then(|v| v.as_i64());
+            let end = end
+                .as_ref()
+                .map(|e| value(e))
+                .transpose()?
+                .and_then(|v| v.as_i64());
+            match value(target)? {
+                Value::Array(items) => {
+                    let (start, end) = slice_bounds(items.len(), start, end);
+                    Value::Array(items[start..end].to_vec())
+                }
+                Value::String(text) => {
+                    let chars: Vec<char> = text.chars().collect();
+                    let (start, end) = slice_bounds(chars.len(), start, end);
+                    Value::String(chars[start..end].iter().collect())
+                }
+                other => return Err(format!("cannot slice {}", other)),
+            }
+        }
+        Expr::List(items) => Value::Array(items.iter().map(value).collect::<Result<_>>()?),
+        Expr::Dict(entries) => {
+            let mut map = Map::new();
+            for (key, item) in entries {
+                map.insert(display(&value(key)?), value(item)?);
+            }
+            Value::Object(map)
+        }
+        Expr::Call(callee, args) => return call(callee, args, scopes),
+        Expr::Filter(target, name, args) => {
+            let args = args
+                .iter()
+                .map(|(keyword, arg)| Ok((keyword.clone(), value(arg)?)))
+                .collect::<Result<Vec<_>>>()?;
+            return filter(eval(target, scopes)?, name, &args);
+        }
+        Expr::Test(target, name, negated) => {
+            let target = eval(target, scopes)?;
+            let passed = match name.as_str() {
+                "defined" => target.is_some(),
+                "undefined" => target.is_none(),
+                "none" => matches!(target, Some(Value::Null)),
+                "string" => matches!(target, Some(Value::String(_))),
+                "number" => matches!(target, Some(Value::Number(_))),
+                "boolean" => matches!(target, Some(Value::Bool(_))),
+                "mapping" => matches!(target, Some(Value::Object(_))),
+                "sequence" | "iterable" => matches!(
+                    target,
+                    Some(Value::Array(_) | Value::String(_) | Value::Object(_))
+                ),
+                "true" => matches!(target, Some(Value::Bool(true))),
+                "false" => matches!(target, Some(Value::Bool(false))),
+                other => return Err(format!("unsupported template test '{}'", other)),
+            };
+            Value::Bool(passed != *negated)
+        }
+        Expr::Not(inner) => Value::Bool(!truthy(&eval(inner, scopes)?)),
+        Expr::Neg(inner) => {
+            let inner = value(inner)?;
+            numeric(-number(&inner)?, inner.is_i64())
+        }
+        Expr::And(left, right) => {
+            let left = eval(left, scopes)?;
+            if !truthy(&left) {
+                return Ok(left);
+            }
+            return eval(right, scopes);
+        }
+        Expr::Or(left, right) => {
+            let left = eval(left, scopes)?;
+            if truthy(&left) {
+                return Ok(left);
+            }
+            return eval(right, scopes);
+        }
+        Expr::Conditional(condition, then, otherwise) => {
+            return if truthy(&eval(condition, scopes)?) {
+                eval(then, scopes)
+            } else {
+                eval(otherwise, scopes)
+            };
+        }
+        Expr::Binary(op, left, right) => binary(op, value(left)?, value(right)?)?,
+    }))
+}
+
+fn binary(op: &str, left: Value, right: Value) -> Result<Value> {
+    let integers = left.is_i64() && right.is_i64();
+    Ok(match op {
+        "==" => Value::Bool(equal(&left, &right)),
+        "!=" => Value::Bool(!equal(&left, &right)),
+        "<" | ">" | "<=" | ">=" => {
+            let ordering = match (&left, &right) {
+                (Value::String(a), Value::String(b)) => a.cmp(b),
+                _ => number(&left)?
+                    .partial_cmp(&number(&right)?)
+                    .ok_or("cannot compare NaN")?,
+            };
+            Value::Bool(match op {
+                "<" => ordering.is_lt(),
+                ">" => ordering.is_gt(),
+                "<=" => ordering.is_le(),
+                _ => ordering.is_ge(),
+            })
+        }
+        "in" | "not in" => {
+            let found = match &right {
+                Value::String(text) => text.contains(&display(&left)),
+                Value::Array(items) => items.iter().any(|item| equal(item, &left)),
+                Value::Object(map) => map.contains_key(&display(&left)),
+                _ => false,
+            };
+            Value::Bool(found == (op == "in"))
+        }
+        "~" => Value::String(display(&left) + &display(&right)),
+        "+" => match (left, right) {
+            (Value::String(a), Value::String(b)) => Value::String(a + &b),
+            (Value::Array(mut a), Value::Array(b)) => {
+                a.extend(b);
+                Value::Array(a)
+            }
+            (a, b) => numeric(number(&a)? + number(&b)?, integers),
+        },
+        "-" => numeric(number(&left)? - number(&right)?, integers),
+        "*" => match (&left, &right) {
+            (Value::String(text), Value::Number(n)) => {
+                Value::String(text.repeat(n.as_u64().unwrap_or(0) as usize))
+            }
+            _ => numeric(number(&left)? * number(&right)?, integers),
+        },
+        "/" => json!(number(&left)? / number(&right)?),
+        "%" => {
+            let divisor = number(&right)?;
+            if divisor == 0.0 {
+                return Err("modulo by zero in template".to_string());
+            }
+            numeric(number(&left)?.rem_euclid(divisor), integers)
+        }
+        other => return Err(format!("unsupported operator '{}'", other)),
+    })
+}
+
+fn equal(left: &Value, right: &Value) -> bool {
+    match (left.as_f64(), right.as_f64()) {
+        (Some(a), Some(b)) => a == b,
+        _ => left == right,
+    }
+}
+
+fn call(
+    callee: &Expr,
+    args: &[(Option<String>, Expr)],
+    scopes: &[Map<String, Value>],
+) -> Result<Option<Value>> {
+    let mut positional = Vec::new();
+    let mut keywords = Map::new();
+    for (keyword, arg) in args {
+        let value = eval(arg, scopes)?.unwrap_or(Value::Null);
+        match keyword {
+            Some(keyword) => {
+                keywords.insert(keyword.clone(), value);
+            }
+            None => positional.push(value),
+        }
+    }
+    let text = |i: usize| positional.get(i).map(display).unwrap_or_default();
+
+    // Methods on strings and dicts.
+    if let Expr::Attr(target, method) = callee {
+        let target = eval(target, scopes)?.unwrap_or(Value::Null);
+        return Ok(Some(match (&target, method.as_str()) {
+            (Value::String(s), "strip") => json!(strip(s, positional.first(), true, true)),
+            (Value::String(s), "lstrip") => json!(strip(s, positional.first(), true, false)),
+            (Value::String(s), "rstrip") => json!(strip(s, positional.first(), false, true)),
+            (Value::String(s), "upper") => json!(s.to_uppercase()),
+            (Value::String(s), "lower") => json!(s.to_lowercase()),
+            (Value::String(s), "startswith") => json!(s.starts_with(&text(0))),
+            (Value::String(s), "endswith") => json!(s.ends_with(&text(0))),
+            (Value::String(s), "replace") => json!(s.replace(&text(0), &text(1))),
+            (Value::String(s), "split") => match positional.first() {
+                Some(separator) => json!(s.split(&display(separator)).collect::<Vec<_>>()),
+                None => json!(s.split_whitespace().collect::<Vec<_>>()),
+            },
+            (Value::Object(map), "items") => {
+                Value::Array(map.iter().map(|(key, value)| json!([key, value])).collect())
+            }
+            (Value::Object(map), "keys") => json!(map.keys().collect::<Vec<_>>()),
+            (Value::Object(map), "values") => json!(map.values().collect::<Vec<_>>()),
+            (Value::Object(map), "get") => map
+                .get(&text(0))
+                .cloned()
+                .or_else(|| positional.get(1).cloned())
+                .unwrap_or(Value::Null),
+            _ => return Err(format!("unsupported method '{}' in template", method)),
+        }));
+    }
+
+    let Expr::Var(name) = callee else {
+        return Err("only functions and methods can be called in templates".to_string());
+    };
+    Ok(Some(match name.as_str() {
+        "raise_exception" => return Err(format!("template error: {}", text(0))),
+        "namespace" => Value::Object(keywords),
+        "range" => {
+            let bounds: Vec<i64> = positional.iter().filter_map(Value::as_i64).collect();
+            let (start, end) = match bounds[..] {
+                [end] => (0, end),
+                [start, end, ..] => (start, end),
+                _ => return Err("range() needs one or two integers".to_string()),
+            };
+            json!((start..end).collect::<Vec<_>>())
+        }
+        "strftime_now" => json!(chrono::Local::now().format(&text(0)).to_string()),
+        other => return Err(format!("unknown template function '{}'", other)),
+    }))
+}
+
+fn strip(text: &str, chars: Option<&Value>, start: bool, end: bool) -> String {
+    let set: Option<Vec<char>> = chars.map(|c| display(c).chars().collect());
+    let matches = |c: char| match &set {
+        Some(set) => set.contains(&c),
+        None => c.is_whitespace(),
+    };
+    let mut text = text;
+    if start {
+        text = text.trim_start_matches(matches);
+    }
+    if end {
+        text = text.trim_end_matches(matches);
+    }
+    text.to_string()
+}
+
+fn filter(
+    target: Option<Value>,
+    name: &str,
+    args: &[(Option<String>, Value)],
+) -> Result<Option<Value>> {
+    if name == "default" || name == "d" {
+        return Ok(match target {
+            Some(value) => Some(value),
+            None => args.first().map(|(_, value)| value.clone()),
+        });
+    }
+    // Like Jinja's undefined, a missing value filters as an empty string.
+    let target = target.unwrap_or_else(|| json!(""));
+    let arg = |keyword: &str, position: usize| {
+        args.iter()
+            .find(|(k, _)| k.as_deref() == Some(keyword))
+            .or_else(|| args.iter().filter(|(k, _)| k.is_none()).nth(position))
+            .map(|(_, value)| value.clone())
+    };
+    Ok(Some(match name {
+        "trim" => json!(display(&target).trim()),
+        "upper" => json!(display(&target).to_uppercase()),
+        "lower" => json!(display(&target).to_lowercase()),
+        "capitalize" => {
+            let text = display(&target).to_lowercase();
+            let mut chars = text.chars();
+            json!(chars
+                .next()
+                .map(|first| first.to_uppercase().chain(chars).collect::<String>())
+                .unwrap_or_default())
+        }
+        "string" => json!(display(&target)),
+        "int" => json!(target
+            .as_f64()
+            .map(|n| n as i64)
+            .or_else(|| display(&target).trim().parse().ok())
+            .unwrap_or(0)),
+        "length" | "count" => json!(match &target {
+            Value::Array(items) => items.len(),
+            Value::Object(map) => map.len(),
+            Value::String(text) => text.chars().count(),
+            _ => 0,
+        }),
+        "first" => iterate(target)?.into_iter().next().unwrap_or(Value::Null),
+        "last" => iterate(target)?.pop().unwrap_or(Value::Null),
+        "join" => {
+            let separator = arg("d", 0).map(|s| display(&s)).unwrap_or_default();
+            json!(iterate(target)?
+                .iter()
+                .map(display)
+                .collect::<Vec<_>>()
+                .join(&separator))
+        }
+        "items" => match target {
+            Value::Object(map) => Value::Array(
+                map.into_iter()
+                    .map(|(key, value)| json!([key, value]))
+                    .collect(),
+            ),
+            _ => Value::Array(Vec::new()),
+        },
+        "tojson" => {
+            let text = match arg("indent", 0).and_then(|indent| indent.as_u64()) {
+                Some(indent) => {
+                    let indent = " ".repeat(indent as usize);
+                    let mut out = Vec::new();
+                    let formatter =
+                        serde_json::ser::PrettyFormatter::with_indent(indent.as_bytes());
+                    let mut serializer =
+                        serde_json::Serializer::with_formatter(&mut out, formatter);
+                    serde::Serialize::serialize(&target, &mut serializer)
+                        .map_err(|e| e.to_string())?;
+                    String::from_utf8(out).map_err(|e| e.to_string())?
+                }
+                None => python_json(&target),
+            };
+            json!(text)
+        }
+        other => return Err(format!("unsupported template filter '{}'", other)),
+    }))
+}
+
+/// JSON spaced like Python's `json.dumps`, which chat templates were written against.
+fn python_json(value: &Value) -> String {
+    match value {
+        Value::Array(items) => format!(
+            "[{}]",
+            items.iter().map(python_json).collect::<Vec<_>>().join(", ")
+        ),
+        Value::Object(map) => format!(
+            "{{{}}}",
+            map.iter()
+                .map(|(key, value)| format!(
+                    "{}: {}",
+                    Value::String(key.clone()),
+                    python_json(value)
+                ))
+                .collect::<Vec<_>>()
+                .join(", ")
+        ),
+        other => other.to_string(),
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Chat templates
+// ---------------------------------------------------------------------------
+
+const CHATML: &str = "{% for message in messages %}<|im_start|>{{ message.role }}\n{{ message.content }}<|im_end|>\n{% endfor %}{% if add_generation_prompt %}<|im_start|>assistant\n{% endif %}";
+
+const LLAMA3: &str = "{{ bos_token }}{% for message in messages %}<|start_header_id|>{% if message.role == 'tool' %}ipython{% else %}{{ message.role }}{% endif %}<|end_header_id|>\n\n{{ message.content | trim }}<|eot_id|>{% endfor %}{% if add_generation_prompt %}<|start_header_id|>assistant<|end_header_id|>\n\n{% endif %}";
+
+const MISTRAL: &str = "{% if messages[0].role == 'system' %}{% set system = messages[0].content + '\n\n' %}{% set messages = messages[1:] %}{% else %}{% set system = '' %}{% endif %}{{ bos_token }}{% for message in messages %}{% if message.role == 'user' %}[INST] {% if loop.first %}{{ system }}{% endif %}{{ message.content }} [/INST]{% elif message.role == 'tool' %}[TOOL_RESULTS] {{ message.content }} [/TOOL_RESULTS]{% else %}{{ message.content }}{{ eos_token }}{% endif %}{% endfor %}";
+
+const GEMMA: &str = "{{ bos_token }}{% if messages[0].role == 'system' %}{% set system = messages[0].content + '\n\n' %}{% set messages = messages[1:] %}{% else %}{% set system = '' %}{% endif %}{% for message in messages %}<start_of_turn>{% if message.role == 'assistant' %}model{% else %}user{% endif %}\n{% if loop.first %}{{ system }}{% endif %}{{ message.content | trim }}<end_of_turn>\n{% endfor %}{% if add_generation_prompt %}<start_of_turn>model\n{% endif %}";
+
+/// A chat template with the special tokens it refers to and the sequences that end a
+/// turn, sent as `stop`.
+#[derive(Clone, Debug)]
+pub(crate) struct ChatTemplate {
+    template: Template,
+    /// Whether the template renders `tools` itself; otherwise they are described in the
+    /// system message.
+    renders_tools: bool,
+    pub(crate) bos_token: String,
+    pub(crate) eos_token: String,
+    pub(crate) stop: Vec<String>,
+}
+
+impl ChatTemplate {
+    /// A built-in template: `"chatml"`, `"llama3"`, `"mistral"` or `"gemma"`.
+    pub(crate) fn preset(name: &str) -> Option<Self> {
+        let (source, bos, eos, stop): (&str, &str, &str, &[&str]) = match name {
+            "chatml" => (CHATML, "", "<|im_end|>", &["<|im_end|>"]),
+            "llama3" => (LLAMA3, "<|begin_of_text|>", "<|eot_id|>", &["<|eot_id|>"]),
+            "mistral" => (MISTRAL, "<s>", "</s>", &["</s>"]),
+            "gemma" => (GEMMA, "<bos>", "<eos>", &["<end_of_turn>"]),
+            _ => return None,
+        };
+        let mut template = Self::new(source).expect("built-in templates parse");
+        template.bos_token = bos.to_string();
+        template.eos_token = eos.to_string();
+        template.stop = stop.iter().map(|s| s.to_string()).collect();
+        Some(template)
+    }
+
+    /// Parse the `chat_template` option: a preset name, a template, or a dict with
+    /// `template` and any of `bos_token`, `eos_token` and `stop`.
+    pub(crate) fn from_py(value: &Bound<'_, PyAny>) -> PyResult<Self> {
+        if let Ok(text) = value.extract::<String>() {
+            return Self::from_text(&text);
+        }
+        let Ok(dict) = value.cast::<PyDict>() else {
+            return Err(PyTypeError::new_err(
+                "chat_template must be a preset name, a template string or a dict",
+            ));
+        };
+        let text: String = dict
+            .get_item("template")?
+            .ok_or_else(|| PyValueError::new_err("chat_template dict needs a 'template'"))?
+            .extract()?;
+        let mut template = Self::from_text(&text)?;
+        for (key, value) in dict.iter() {
+            match key.extract::<String>()?.as_str() {
+                "template" => {}
+                "bos_token" => template.bos_token = value.extract()?,
+                "eos_token" => template.eos_token = value.extract()?,
+                "stop" => {
+                    template.stop = match value.extract::<String>() {
+                        Ok(stop) => vec![stop],
+                        Err(_) => value.extract()?,
+                    }
+                }
+                other => {
+                    return Err(PyValueError::new_err(format!(
+                        "unknown chat_template key '{}' (expected 'template', 'bos_token', \
+                         'eos_token' or 'stop')",
+                        other
+                    )))
+                }
+            }
+        }
+        Ok(template)
+    }
+
+    fn from_text(text: &str) -> PyResult<Self> {
+        if let Some(preset) = Self::preset(text) {
+            return Ok(preset);
+        }
+        if !text.contains("{{") && !text.contains("{%") {
+            return Err(PyValueError::new_err(format!(
+                "unknown chat_template preset '{}' (expected 'chatml', 'llama3', 'mistral', \
+                 'gemma' or a Jinja template)",
+                text
+            )));
+        }
+        Self::new(text).map_err(|e| PyValueError::new_err(format!("invalid chat_template: {}", e)))
+    }
+
+    pub(crate) fn new(source: &str) -> Result<Self> {
+        Ok(ChatTemplate {
+            template: Template::parse(source)?,
+            renders_tools: source.contains("tools"),
+            bos_token: String::new(),
+            eos_token: String::new(),
+            stop: Vec::new(),
+        })
+    }
+
+    /// The completion prompt for `messages` (`{"role", "content", ...}` dicts), ending
+    /// with the opening of an assistant turn.
+    pub(crate) fn render(&self, messages: Vec<Value>, tools: Option<&[Value]>) -> Result<String> {
+        let mut messages = messages;
+        if let Some(tools) = tools.filter(|_| !self.renders_tools) {
+            describe_tools(&mut messages, tools);
+        }
+        let mut variables = Map::new();
+        variables.insert("messages".to_string(), Value::Array(messages));
+        variables.insert("bos_token".to_string(), json!(self.bos_token));
+        variables.insert("eos_token".to_string(), json!(self.eos_token));
+        variables.insert("add_generation_prompt".to_string(), Value::Bool(true));
+        if let Some(tools) = tools {
+            variables.insert("tools".to_string(), Value::Array(tools.to_vec()));
+        }
+        self.template.render(variables)
+    }
+}
+
+/// For templates that don't render `tools`: list them in the system message, with the
+/// `<tool_call>` format that `parse_tool_call` reads back.
+fn describe_tools(messages: &mut Vec<Value>, tools: &[Value]) {
+    let mut text = String::from(
+        "You can call the following tools. To call one, reply with only \
+         <tool_call>{\"name\": <tool name>, \"arguments\": <arguments object>}</tool_call>.\n",
+    );
+    for tool in tools {
+        text.push_str(&python_json(&tool["function"]));
+        text.push('\n');
+    }
+    match messages.first_mut() {
+        Some(first) if first["role"] == "system" => {
+            let system = first["content"].as_str().unwrap_or_default();
+            first["content"] = json!(format!("{}\n\n{}", system, text.trim_end()));
+        }
+        _ => messages.insert(0, json!({ "role": "system", "content": text.trim_end() })),
+    }
+}
+
+/// A tool call in a completion: `<tool_call>{...}</tool_call>` (Hermes, Qwen) or a bare
+/// JSON object with `name` and `arguments`/`parameters` (Llama 3.1).
+pub(crate) fn parse_tool_call(text: &str) -> Option<(String, Value)> {
+    let text = text.trim();
+    let json = match text.strip_prefix("<tool_call>") {
+        Some(rest) => rest.split("</tool_call>").next()?.trim(),
+        None => text,
+    };
+    let call: Value = serde_json::from_str(json).ok()?;
+    let name = call.get("name")?.as_str()?.to_string();
+    let args = call
+        .get("arguments")
+        .or_else(|| call.get("parameters"))
+        .cloned()
+        .unwrap_or_else(|| json!({}));
+    // Some models send the arguments as a JSON string.
+    let args = match args {
+        Value::String(text) => serde_json::from_str(&text).unwrap_or(Value::String(text)),
+        other => other,
+    };
+    Some((name, args))
+}
+
+/// How a tool call is written into the prompt, matching what `parse_tool_call` reads.
+pub(crate) fn format_tool_call(name: &str, args: &Value) -> String {
+    format!(
+        "<tool_call>{}</tool_call>",
+        python_json(&json!({ "name": name, "arguments": args }))
+    )
+}
+
+/// Streamed completion text on its way to a sink. With tools, text is held back while
+/// it could still turn out to be a tool call, which is never forwarded.
+pub(crate) struct HeldText {
+    text: String,
+    sent: usize,
+    holding: bool,
+}
+
+impl HeldText {
+    pub(crate) fn new(has_tools: bool) -> Self {
+        HeldText {
+            text: String::new(),
+            sent: 0,
+            holding: has_tools,
+        }
+    }
+
+    pub(crate) async fn push(
+        &mut self,
+        delta: &str,
+        sink: &TextSink,
+    ) -> std::result::Result<(), RustedChainError> {
+        self.text.push_str(delta);
+        self.holding = self.holding && could_be_tool_call(&self.text);
+        if !self.holding {
+            self.flush(sink).await?;
+        }
+        Ok(())
+    }
+
+    /// Forward what is left unless it is a tool call, and return the whole text.
+    pub(crate) async fn finish(
+        mut self,
+        sink: &TextSink,
+    ) -> std::result::Result<String, RustedChainError> {
+        if !self.holding || parse_tool_call(&self.text).is_none() {
+            self.flush(sink).await?;
+        }
+        Ok(self.text)
+    }
+
+    async fn flush(&mut self, sink: &TextSink) -> std::result::Result<(), RustedChainError> {
+        if self.sent < self.text.len() {
+            sink.send(self.text[self.sent..].to_string()).await?;
+            self.sent = self.text.len();
+        }
+        Ok(())
+    }
+}
+
+/// Whether a partial completion may still turn out to be a tool call.
+fn could_be_tool_call(text: &str) -> bool {
+    let text = text.trim_start();
+    text.starts_with('{') || text.starts_with("<tool_call>") || "<tool_call>".starts_with(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(source: &str, variables: Value) -> Result<String> {
+        let Value::Object(variables) = variables else {
+            panic!("variables must be an object");
+        };
+        Template::parse(source)?.render(variables)
+    }
+
+    #[test]
+    fn missing_variables_render_as_nothing() {
+        assert_eq!(render("[{{ name }}]", json!({})).unwrap(), "[]");
+        assert_eq!(render("[{{ user.name }}]", json!({})).unwrap(), "[]");
+        assert_eq!(
+            render("[{{ user['name'] }}]", json!({"user": {}})).unwrap(),
+            "[]"
+        );
+        assert_eq!(render("[{{ name | trim }}]", json!({})).unwrap(), "[]");
+    }
+
+    #[test]
+    fn missing_variables_are_falsy_and_undefined() {
+        let source = "{% if name %}yes{% else %}no{% endif %} \
+                      {{ name is defined }} {{ name is undefined }} {{ name is none }}";
+        assert_eq!(render(source, json!({})).unwrap(), "no False True False");
+        assert_eq!(
+            render(source, json!({"name": null})).unwrap(),
+            "no True False True"
+        );
+    }
+
+    #[test]
+    fn missing_variables_take_the_default() {
+        let source = "{{ name | default('anon') }}";
+        assert_eq!(render(source, json!({})).unwrap(), "anon");
+        assert_eq!(render(source, json!({"name": "Ada"})).unwrap(), "Ada");
+        assert_eq!(render(source, json!({"name": null})).unwrap(), "None");
+    }
+
+    #[test]
+    fn looping_over_a_missing_variable_renders_the_else_branch() {
+        let source = "{% for item in items %}{{ item }}{% else %}empty{% endfor %}";
+        assert_eq!(render(source, json!({})).unwrap(), "empty");
+    }
+
+    #[test]
+    fn presets_render_messages_missing_fields() {
+        let template = ChatTemplate::preset("gemma").unwrap();
+        let prompt = template
+            .render(vec![json!({"role": "user"})], None)
+            .unwrap();
+        assert_eq!(
+            prompt,
+            "<bos><start_of_turn>user<end_of_turn>\n<start_of_turn>model\n"
+        );
+    }
+}
@@ -0,0 +1,224 @@
+//! Circuit breakers: a provider that keeps failing gets no requests for a cooldown, so
+//! calls fail fast instead of waiting out retries against an outage.
+
+use crate::error::RustedChainError;
+use once_cell::sync::Lazy;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Closed while requests succeed; open for a cooldown after enough failures in a row;
+/// then half open, letting one probe through to decide whether it closes again.
+#[derive(Default)]
+pub(crate) struct Breaker {
+    consecutive_failures: u32,
+    /// When the breaker last opened; `None` while it is closed.
+    opened: Option<Instant>,
+    /// When the probe of a half-open breaker was sent.
+    probe: Option<Instant>,
+}
+
+impl Breaker {
+    pub(crate) fn state(&self, cooldown: Duration, now: Instant) -> &'static str {
+        match self.opened {
+            None => "closed",
+            Some(opened) if now < opened + cooldown => "open",
+            Some(_) => "half_open",
+        }
+    }
+
+    /// Whether a request may go through: always while closed, and once half open, as
+    /// the one probe. A probe that never reported back is given up after a cooldown.
+    pub(crate) fn available(&self, cooldown: Duration, now: Instant) -> bool {
+        match self.state(cooldown, now) {
+            "closed" => true,
+            "half_open" => self.probe.is_none_or(|sent| now >= sent + cooldown),
+            _ => false,
+        }
+    }
+
+    /// Note a request let through; a half-open breaker's is its probe.
+    pub(crate) fn admit(&mut self, now: Instant) {
+        if self.opened.is_some() {
+            self.probe = Some(now);
+        }
+    }
+
+    /// Count how a request went. `failure_threshold` failures in a row, or a failed
+    /// probe, open the breaker; a success closes it.
+    pub(crate) fn record(&mut self, failed: bool, failure_threshold: u32) {
+        self.probe = None;
+        if failed {
+            self.consecutive_failures += 1;
+            if self.opened.is_some() || self.consecutive_failures >= failure_threshold {
+                self.opened = Some(Instant::now());
+            }
+        } else {
+            self.consecutive_failures = 0;
+            self.opened = None;
+        }
+    }
+
+    pub(crate) fn consecutive_failures(&self) -> u32 {
+        self.consecutive_failures
+    }
+
+    /// How long until an open breaker lets a probe through.
+    fn reopens_in(&self, cooldown: Duration, now: Instant) -> Duration {
+        self.opened.map_or(Duration::ZERO, |opened| {
+            (opened + cooldown).saturating_duration_since(now)
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Settings {
+    failure_threshold: u32,
+    cooldown: Duration,
+}
+
+impl Settings {
+    /// The settings `configure_circuit_breaker` was called with, or why they are
+    /// invalid; `None` turns breakers off.
+    fn parse(failure_threshold: Option<u32>, cooldown: f64) -> Result<Option<Self>, String> {
+        if failure_threshold == Some(0) {
+            return Err("failure_threshold must be at least 1".to_string());
+        }
+        let cooldown = Duration::try_from_secs_f64(cooldown).map_err(|_| {
+            format!(
+                "cooldown must be a non-negative number of seconds, got {}",
+                cooldown
+            )
+        })?;
+        Ok(failure_threshold.map(|failure_threshold| Settings {
+            failure_threshold,
+            cooldown,
+        }))
+    }
+}
+
+/// Set by `configure_circuit_breaker`; provider breakers are off until then.
+static SETTINGS: Lazy<Mutex<Option<Settings>>> = Lazy::new(|| Mutex::new(None));
+
+/// One breaker per provider, or per API root for OpenAI-compatible servers.
+static BREAKERS: Lazy<Mutex<HashMap<String, Breaker>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn settings() -> Option<Settings> {
+    *SETTINGS.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Let a request to `provider` through, or fail with `ProviderUnavailable` while its
+/// breaker is open.
+pub(crate) fn admit(provider: &str) -> Result<(), RustedChainError> {
+    let Some(settings) = settings() else {
+        return Ok(());
+    };
+    let mut breakers = BREAKERS.lock().unwrap_or_else(|e| e.into_inner());
+    let breaker = breakers.entry(provider.to_string()).or_default();
+    let now = Instant::now();
+    if !breaker.available(settings.cooldown, now) {
+        return Err(RustedChainError::ProviderUnavailable {
+            provider: provider.to_string(),
+            failures: breaker.consecutive_failures,
+            retry_in: breaker.reopens_in(settings.cooldown, now).as_secs_f64(),
+        });
+    }
+    breaker.admit(now);
+    Ok(())
+}
+
+/// Count how a request to `provider` went. Outages count against it: network errors,
+/// overload, 5xx responses and missed first-token deadlines. Any other answer, an error
+/// or not, shows the provider is up.
+pub(crate) fn record<T>(provider: &str, result: &Result<T, RustedChainError>) {
+    let Some(settings) = settings() else {
+        return;
+    };
+    let failed = match result {
+        Err(RustedChainError::RateLimited { .. }) => false,
+        Err(RustedChainError::FirstTokenDeadline(_)) => true,
+        Err(err) => err.is_retryable(),
+        Ok(_) => false,
+    };
+    let mut breakers = BREAKERS.lock().unwrap_or_else(|e| e.into_inner());
+    breakers
+        .entry(provider.to_string())
+        .or_default()
+        .record(failed, settings.failure_threshold);
+}
+
+/// Turn on a circuit breaker per provider: after `failure_threshold` failed requests
+/// in a row, calls to that provider raise `ProviderUnavailableError` for `cooldown`
+/// seconds without sending anything, then one probe request decides whether it
+/// closes again. Failures are counted after retries. `failure_threshold=None` turns the
+/// breakers off and forgets their state.
+#[pyfunction]
+#[pyo3(signature = (failure_threshold=Some(5), cooldown=30.0))]
+pub fn configure_circuit_breaker(failure_threshold: Option<u32>, cooldown: f64) -> PyResult<()> {
+    *SETTINGS.lock().unwrap_or_else(|e| e.into_inner()) =
+        Settings::parse(failure_threshold, cooldown).map_err(PyValueError::new_err)?;
+    if failure_threshold.is_none() {
+        BREAKERS.lock().unwrap_or_else(|e| e.into_inner()).clear();
+    }
+    Ok(())
+}
+
+/// The provider breakers so far, keyed by provider (or API root): `{"state",
+/// "consecutive_failures", "retry_in_s"}`, where `state` is `"closed"`, `"open"` or
+/// `"half_open"` and `retry_in_s` is how long an open breaker stays open.
+#[pyfunction]
+pub fn circuit_breakers<'py>(py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+    let result = PyDict::new(py);
+    let Some(settings) = settings() else {
+        return Ok(result);
+    };
+    let breakers = BREAKERS.lock().unwrap_or_else(|e| e.into_inner());
+    let now = Instant::now();
+    for (provider, breaker) in breakers.iter() {
+        let entry = PyDict::new(py);
+        entry.set_item("state", breaker.state(settings.cooldown, now))?;
+        entry.set_item("consecutive_failures", breaker.consecutive_failures)?;
+        entry.set_item(
+            "retry_in_s",
+            breaker.reopens_in(settings.cooldown, now).as_secs_f64(),
+        )?;
+        result.set_item(provider, entry)?;
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn settings_take_a_threshold_and_cooldown() {
+        assert_eq!(
+            Settings::parse(Some(3), 1.5).unwrap(),
+            Some(Settings {
+                failure_threshold: 3,
+                cooldown: Duration::from_millis(1500),
+            })
+        );
+        assert_eq!(Settings::parse(None, 30.0).unwrap(), None);
+        assert_eq!(
+            Settings::parse(Some(1), 0.0).unwrap().map(|s| s.cooldown),
+            Some(Duration::ZERO)
+        );
+    }
+
+    #[test]
+    fn settings_reject_a_zero_threshold() {
+        assert!(Settings::parse(Some(0), 30.0).is_err());
+    }
+
+    #[test]
+    fn settings_reject_unusable_cooldowns() {
+        for cooldown in [-1.0, f64::NAN, f64::INFINITY, f64::MAX] {
+            assert!(Settings::parse(Some(5), cooldown).is_err(), "{cooldown}");
+        }
+    }
+}
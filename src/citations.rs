@@ -0,0 +1,95 @@
+//! Sources cited by an answer, from the extra fields search-backed OpenAI-compatible
+//! APIs (Perplexity's sonar models) add to their responses.
+
+use pyo3::prelude::*;
+use serde::Deserialize;
+
+/// A source the answer draws on.
+#[pyclass(get_all)]
+#[derive(Clone, Default)]
+pub struct Citation {
+    pub url: String,
+    pub title: Option<String>,
+    /// Publication date as the provider gives it, e.g. `"2025-03-01"`.
+    pub date: Option<String>,
+    pub snippet: Option<String>,
+}
+
+#[pymethods]
+impl Citation {
+    fn __repr__(&self) -> String {
+        match &self.title {
+            Some(title) => format!("Citation(url={:?}, title={:?})", self.url, title),
+            None => format!("Citation(url={:?})", self.url),
+        }
+    }
+
+    fn to_dict(&self) -> std::collections::HashMap<&'static str, Option<String>> {
+        [
+            ("url", Some(self.url.clone())),
+            ("title", self.title.clone()),
+            ("date", self.date.clone()),
+            ("snippet", self.snippet.clone()),
+        ]
+        .into_iter()
+        .collect()
+    }
+}
+
+/// The `citations` (URLs) and `search_results` (URLs with details) fields of a response
+/// or stream chunk.
+#[derive(Deserialize, Default)]
+pub(crate) struct Sources {
+    #[serde(default)]
+    citations: Vec<String>,
+    #[serde(default)]
+    search_results: Vec<SearchResult>,
+}
+
+#[derive(Deserialize)]
+struct SearchResult {
+    url: String,
+    title: Option<String>,
+    date: Option<String>,
+    snippet: Option<String>,
+}
+
+impl Sources {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.citations.is_empty() && self.search_results.is_empty()
+    }
+
+    /// One `Citation` per source, in the order the answer numbers them (`[1]`, `[2]`,
+    /// ...): `citations` gives the order, `search_results` the details.
+    pub(crate) fn into_citations(self) -> Vec<Citation> {
+        let Sources {
+            citations,
+            mut search_results,
+        } = self;
+        let mut result: Vec<Citation> = citations
+            .into_iter()
+            .map(
+                |url| match search_results.iter().position(|found| found.url == url) {
+                    Some(at) => search_results.remove(at).into(),
+                    None => Citation {
+                        url,
+                        ..Default::default()
+                    },
+                },
+            )
+            .collect();
+        result.extend(search_results.into_iter().map(Citation::from));
+        result
+    }
+}
+
+impl From<SearchResult> for Citation {
+    fn from(found: SearchResult) -> Self {
+        Citation {
+            url: found.url,
+            title: found.title,
+            date: found.date,
+            snippet: found.snippet,
+        }
+    }
+}
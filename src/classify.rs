@@ -0,0 +1,167 @@
+//! Classification into a fixed set of labels in one call. The answer is constrained to
+//! the labels with each provider's structured output (an `enum` schema), and OpenAI
+//! models also report how likely the chosen label was from its token probabilities.
+
+use crate::agent::ChatClient;
+use crate::client::{with_client, AnyClient};
+use crate::error::RustedChainError;
+use crate::run::Labels;
+use crate::transforms::strip_fences;
+use crate::vision::recorded;
+use crate::RUNTIME;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use serde_json::{json, Value};
+
+/// The label `classify` picked.
+#[pyclass(get_all, frozen)]
+pub struct Classification {
+    /// One of the labels given.
+    label: String,
+    /// How likely `label` is, from 0 to 1, if known.
+    confidence: Option<f64>,
+    /// `"logprobs"` when `confidence` is the model's probability of the label's tokens
+    /// (OpenAI), `"reported"` when it is the model's own estimate.
+    method: &'static str,
+}
+
+#[pymethods]
+impl Classification {
+    fn __repr__(&self) -> String {
+        format!(
+            "Classification(label={:?}, confidence={}, method='{}')",
+            self.label,
+            self.confidence
+                .map_or("None".to_string(), |c| format!("{:.3}", c)),
+            self.method
+        )
+    }
+}
+
+/// Classify `text` as exactly one of `labels` with `model`, a model class instance.
+/// `instructions` says what the labels mean or how to choose between them.
+#[pyfunction]
+#[pyo3(signature = (text, labels, model, instructions=None))]
+pub fn classify(
+    py: Python,
+    text: String,
+    labels: Vec<String>,
+    model: &Bound<'_, PyAny>,
+    instructions: Option<String>,
+) -> PyResult<Classification> {
+    let mut distinct = labels.clone();
+    distinct.sort();
+    distinct.dedup();
+    if distinct.len() < 2 || distinct.len() != labels.len() {
+        return Err(PyValueError::new_err(
+            "labels must hold at least two labels, with no duplicates",
+        ));
+    }
+    let (client, options) = AnyClient::from_model(py, model)?;
+    let schema = json!({
+        "type": "object",
+        "properties": {
+            "label": { "type": "string", "enum": labels },
+            "probability": {
+                "type": "number",
+                "description": "How likely the label is to be right, from 0 to 1.",
+            },
+        },
+        "required": ["label", "probability"],
+        "additionalProperties": false,
+    });
+    let prompt = prompt(&text, &labels, instructions.as_deref());
+
+    let (answer, tokens) = py.detach(|| {
+        RUNTIME.block_on(async {
+            match &client {
+                AnyClient::OpenAI(client) if client.reports_logprobs() => {
+                    let request = client.with_logprobs(&prompt, &schema);
+                    recorded(client, &options, &prompt, Labels::default(), request).await
+                }
+                other => {
+                    with_client!(other, client => {
+                        let request = async {
+                            let (text, usage) = client.multimodal(&prompt, &[], Some(&schema)).await?;
+                            Ok((text, Vec::new(), usage))
+                        };
+                        recorded(client, &options, &prompt, Labels::default(), request).await
+                    })
+                }
+            }
+        })
+    })?;
+
+    let parsed: Value = serde_json::from_str(&strip_fences(&answer))
+        .map_err(|e| RustedChainError::ParseError(e.to_string()))?;
+    let answered = parsed["label"].as_str().unwrap_or_default().trim();
+    // Providers without constrained decoding may vary the case.
+    let label = labels
+        .iter()
+        .find(|label| label.as_str() == answered)
+        .or_else(|| {
+            labels
+                .iter()
+                .find(|label| label.eq_ignore_ascii_case(answered))
+        })
+        .ok_or_else(|| {
+            RustedChainError::ParseError(format!(
+                "the model answered '{}', which is not one of the labels",
+                answered
+            ))
+        })?
+        .clone();
+    let (confidence, method) = match label_probability(&answer, &tokens) {
+        Some(probability) => (Some(probability), "logprobs"),
+        None => (
+            parsed["probability"].as_f64().map(|p| p.clamp(0.0, 1.0)),
+            "reported",
+        ),
+    };
+    Ok(Classification {
+        label,
+        confidence,
+        method,
+    })
+}
+
+fn prompt(text: &str, labels: &[String], instructions: Option<&str>) -> String {
+    let mut prompt = format!(
+        "Classify the text below as exactly one of these labels: {}.",
+        labels.join(", ")
+    );
+    if let Some(instructions) = instructions {
+        prompt.push_str("\n\n");
+        prompt.push_str(instructions);
+    }
+    prompt.push_str("\n\nText:\n");
+    prompt.push_str(text);
+    prompt
+}
+
+/// The probability of the label in `answer`, from the log probabilities of the tokens
+/// that spell it out.
+fn label_probability(answer: &str, tokens: &[(String, f64)]) -> Option<f64> {
+    if tokens.is_empty() {
+        return None;
+    }
+    let key = answer.find("\"label\"")? + "\"label\"".len();
+    let start = key + answer[key..].find('"')? + 1;
+    let end = start + answer[start..].find('"')?;
+    let mut offset = 0;
+    let mut logprob = 0.0;
+    let mut covered = false;
+    for (token, token_logprob) in tokens {
+        let token_end = offset + token.len();
+        if token_end > start && offset < end {
+            logprob += token_logprob;
+            covered = true;
+        }
+        offset = token_end;
+    }
+    // The tokens don't spell out the answer, so their offsets mean nothing.
+    if offset != answer.len() || !covered {
+        return None;
+    }
+    Some(logprob.exp())
+}
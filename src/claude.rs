@@ -1,25 +1,79 @@
+use crate::agent::{wrap_tool_result, ChatClient, ToolRequest, Turn, Usage};
+use crate::error::{retry_after_header, RustedChainError};
+use crate::keys::ApiKey;
+use crate::media::{Attachment, ImageLimits};
+use crate::message_transforms::MessageTransform;
+use crate::prefill;
+use crate::retry::RetryPolicy;
+use crate::signing::RequestSigner;
+use crate::stream::{SseParser, TextSink};
+use crate::HTTP;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 use std::env;
+use std::sync::Arc;
+
+const IMAGE_LIMITS: ImageLimits = ImageLimits {
+    provider: "Claude",
+    max_bytes: 5 * 1024 * 1024,
+    max_side: Some(8000),
+};
 
 #[derive(Serialize)]
 struct MessagesRequest {
     model: String,
     max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
     messages: Vec<Message>,
     #[serde(skip_serializing_if = "Option::is_none")]
     tools: Option<Vec<serde_json::Value>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f64>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
 }
 
 #[derive(Serialize, Clone)]
 pub(crate) struct Message {
     pub(crate) role: String,
     pub(crate) content: Vec<ContentBlock>,
+    /// Whether the answer stopped at `max_tokens`; not sent back.
+    #[serde(skip)]
+    pub(crate) truncated: bool,
 }
 
 #[derive(Deserialize)]
 struct MessagesResponse {
     content: Vec<ContentBlock>,
+    stop_reason: Option<String>,
+    #[serde(default)]
+    usage: TokenUsage,
+}
+
+/// Token counts; stream events report only the fields that changed.
+#[derive(Deserialize, Default)]
+struct TokenUsage {
+    input_tokens: Option<u64>,
+    output_tokens: Option<u64>,
+}
+
+impl TokenUsage {
+    fn apply(self, usage: &mut Usage) {
+        if let Some(input) = self.input_tokens {
+            usage.input_tokens = input;
+        }
+        if let Some(output) = self.output_tokens {
+            usage.output_tokens = output;
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct MessageStart {
+    #[serde(default)]
+    usage: TokenUsage,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -41,6 +95,51 @@ pub(crate) enum ContentBlock {
     },
 }
 
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum StreamEvent {
+    MessageStart {
+        message: MessageStart,
+    },
+    MessageDelta {
+        #[serde(default)]
+        delta: MessageDeltaBody,
+        #[serde(default)]
+        usage: TokenUsage,
+    },
+    ContentBlockStart {
+        content_block: ContentBlock,
+    },
+    ContentBlockDelta {
+        index: usize,
+        delta: BlockDelta,
+    },
+    Error {
+        error: Value,
+    },
+    #[serde(other)]
+    Other,
+}
+
+/// The message-level fields a `message_delta` event updates.
+#[derive(Deserialize, Default)]
+struct MessageDeltaBody {
+    stop_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum BlockDelta {
+    TextDelta {
+        text: String,
+    },
+    InputJsonDelta {
+        partial_json: String,
+    },
+    #[serde(other)]
+    Other,
+}
+
 pub enum ClaudeResponse {
     Text(String),
     ToolCall(ToolCall),
@@ -53,21 +152,34 @@ pub struct ToolCall {
     pub id: String,
 }
 
+#[derive(Clone)]
 pub struct Claude {
-    api_key: String,
+    api_key: ApiKey,
     model: String,
     client: Client,
     tools: Option<Vec<serde_json::Value>>,
+    retry: RetryPolicy,
+    system: Option<String>,
+    prefill: Option<String>,
+    temperature: Option<f64>,
+    message_transform: Option<Arc<MessageTransform>>,
+    signer: Option<RequestSigner>,
 }
 
 impl Default for Claude {
     fn default() -> Self {
         dotenv::dotenv().ok();
         Self {
-            api_key: env::var("ANTHROPIC_API_KEY").unwrap_or_default(),
+            api_key: env::var("ANTHROPIC_API_KEY").unwrap_or_default().into(),
             model: "claude-sonnet-4-20250514".to_string(),
-            client: Client::new(),
+            client: HTTP.clone(),
             tools: None,
+            retry: RetryPolicy::default(),
+            system: None,
+            prefill: None,
+            temperature: None,
+            message_transform: None,
+            signer: None,
         }
     }
 }
@@ -77,7 +189,7 @@ impl Claude {
         Self::default()
     }
 
-    pub fn with_api_key(mut self, api_key: String) -> Self {
+    pub(crate) fn with_api_key(mut self, api_key: ApiKey) -> Self {
         self.api_key = api_key;
         self
     }
@@ -92,8 +204,49 @@ impl Claude {
         self
     }
 
+    pub fn without_tools(mut self) -> Self {
+        self.tools = None;
+        self
+    }
+
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Add `signer`'s headers to every request, right before it is sent.
+    pub fn with_signer(mut self, signer: RequestSigner) -> Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    /// System prompt sent in the top-level `system` field.
+    pub fn with_system(mut self, system: String) -> Self {
+        self.system = Some(system);
+        self
+    }
+
+    /// Text every answer starts with, sent as a partial assistant turn for the model
+    /// to continue.
+    pub fn with_prefill(mut self, prefill: String) -> Self {
+        self.prefill = Some(prefill);
+        self
+    }
+
+    /// Sampling temperature, from 0 to 1.
+    pub fn with_temperature(mut self, temperature: f64) -> Self {
+        self.temperature = Some(temperature);
+        self
+    }
+
+    /// Rewrite the messages of every request with `transform`.
+    pub(crate) fn with_message_transform(mut self, transform: Arc<MessageTransform>) -> Self {
+        self.message_transform = Some(transform);
+        self
+    }
+
     #[allow(dead_code)]
-    pub async fn invoke(&self, prompt: &str) -> Result<String, String> {
+    pub async fn invoke(&self, prompt: &str) -> Result<String, RustedChainError> {
         match self.invoke_with_response(prompt).await? {
             ClaudeResponse::Text(text) => Ok(text),
             ClaudeResponse::ToolCall(tool_call) => {
@@ -102,84 +255,526 @@ impl Claude {
         }
     }
 
-    pub async fn invoke_with_response(&self, prompt: &str) -> Result<ClaudeResponse, String> {
+    pub async fn invoke_with_response(
+        &self,
+        prompt: &str,
+    ) -> Result<ClaudeResponse, RustedChainError> {
         let messages = vec![Message {
             role: "user".to_string(),
             content: vec![ContentBlock::Text {
                 text: prompt.to_string(),
             }],
+            truncated: false,
         }];
 
-        let (response, _) = self.exchange(messages).await?;
+        let (response, _, _) = self.exchange(messages).await?;
         Ok(response)
     }
 
-    pub(crate) async fn exchange(
+    /// A single turn with `files` attached. With a `schema` (describing an object), the
+    /// answer is forced through a tool whose input schema it is, and returned as JSON text.
+    pub(crate) async fn multimodal(
         &self,
-        messages: Vec<Message>,
-    ) -> Result<(ClaudeResponse, Message), String> {
-        let url = "https://api.anthropic.com/v1/messages";
+        prompt: &str,
+        files: &[Attachment],
+        schema: Option<&Value>,
+    ) -> Result<(String, Usage), RustedChainError> {
+        const TOOL: &str = "record_analysis";
+        let mut content = Vec::with_capacity(files.len() + 1);
+        for file in files {
+            let Attachment::Image(image) = file else {
+                return Err(RustedChainError::InvalidRequest(
+                    "Claude does not accept video; use a Gemini model".to_string(),
+                ));
+            };
+            let image = image.fit(&IMAGE_LIMITS)?;
+            content.push(json!({
+                "type": "image",
+                "source": {
+                    "type": "base64",
+                    "media_type": image.mime_type,
+                    "data": image.base64(),
+                },
+            }));
+        }
+        content.push(json!({ "type": "text", "text": prompt }));
+        let mut body = json!({
+            "model": self.model,
+            "max_tokens": 4096,
+            "messages": [{ "role": "user", "content": content }],
+        });
+        if let Some(schema) = schema {
+            body["tools"] = json!([{
+                "name": TOOL,
+                "description": "Record the answer in the requested structure.",
+                "input_schema": schema,
+            }]);
+            body["tool_choice"] = json!({ "type": "tool", "name": TOOL });
+        }
+        if let Some(system) = &self.system {
+            body["system"] = json!(system);
+        }
 
+        let response: MessagesResponse = self
+            .post(&body)
+            .await?
+            .json()
+            .await
+            .map_err(|e| RustedChainError::ParseError(e.to_string()))?;
+        let mut usage = Usage::default();
+        response.usage.apply(&mut usage);
+        if schema.is_some() {
+            return response
+                .content
+                .into_iter()
+                .find_map(|block| match block {
+                    ContentBlock::ToolUse { input, .. } => Some((input.to_string(), usage)),
+                    _ => None,
+                })
+                .ok_or(RustedChainError::NoResponse);
+        }
+        let text: String = response
+            .content
+            .into_iter()
+            .filter_map(|block| match block {
+                ContentBlock::Text { text } => Some(text),
+                _ => None,
+            })
+            .collect();
+        Ok((text, usage))
+    }
+
+    async fn send(
+        &self,
+        mut messages: Vec<Message>,
+        stream: bool,
+    ) -> Result<reqwest::Response, RustedChainError> {
+        if let Some(prefill) = &self.prefill {
+            messages.push(Self::assistant_message(prefill::partial_turn(prefill)));
+        }
         let request_body = MessagesRequest {
             model: self.model.clone(),
             max_tokens: 1024,
-            messages,
+            system: self.system.clone(),
+            messages: merge_turns(messages),
             tools: self.tools.clone(),
+            temperature: self.temperature,
+            stream,
         };
+        match &self.message_transform {
+            Some(transform) => {
+                let body = transform.apply_to("anthropic", &request_body, "messages")?;
+                self.post(&body).await
+            }
+            None => self.post(&request_body).await,
+        }
+    }
+
+    async fn post(&self, body: &impl Serialize) -> Result<reqwest::Response, RustedChainError> {
+        self.post_path("/v1/messages", body).await
+    }
+
+    /// POST `body` to `path` of the Anthropic API with this client's key and retry policy.
+    async fn post_path(
+        &self,
+        path: &str,
+        body: &impl Serialize,
+    ) -> Result<reqwest::Response, RustedChainError> {
+        let url = format!("https://api.anthropic.com{}", path);
+        self.retry
+            .run(|| async {
+                let response = self
+                    .api_key
+                    .send(self.signer.as_ref(), |key| {
+                        self.client
+                            .post(&url)
+                            .header("x-api-key", key)
+                            .header("anthropic-version", "2023-06-01")
+                            .header("content-type", "application/json")
+                            .json(body)
+                    })
+                    .await?;
 
-        let response = self
-            .client
-            .post(url)
-            .header("x-api-key", &self.api_key)
-            .header("anthropic-version", "2023-06-01")
-            .header("content-type", "application/json")
-            .json(&request_body)
-            .send()
+                if !response.status().is_success() {
+                    let status = response.status();
+                    let retry_after = retry_after_header(response.headers());
+                    let text = response.text().await.unwrap_or_default();
+                    return Err(RustedChainError::from_response(status, retry_after, &text));
+                }
+                Ok(response)
+            })
             .await
-            .map_err(|e| format!("Failed to send request: {}", e))?;
+    }
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let text = response.text().await.unwrap_or_default();
-            return Err(format!("API Error {}: {}", status, text));
+    /// Send a request body built by the caller to `path` of the Anthropic API, by default
+    /// `/v1/messages`, adding the model if it is missing, and for messages `max_tokens`.
+    /// Returns the parsed response.
+    pub(crate) async fn raw(
+        &self,
+        mut payload: Value,
+        path: Option<&str>,
+    ) -> Result<Value, RustedChainError> {
+        let path = path.unwrap_or("/v1/messages");
+        if let Some(body) = payload.as_object_mut() {
+            body.entry("model").or_insert_with(|| json!(self.model));
+            if path == "/v1/messages" {
+                body.entry("max_tokens").or_insert_with(|| json!(1024));
+            }
         }
+        self.post_path(path, &payload)
+            .await?
+            .json()
+            .await
+            .map_err(|e| RustedChainError::ParseError(e.to_string()))
+    }
+
+    /// Resolve DNS and open a TLS connection, which the shared HTTP client keeps for
+    /// later requests. With `prime`, also send a one-token message.
+    pub(crate) async fn warmup(&self, prime: bool) -> Result<(), RustedChainError> {
+        if prime {
+            let body = json!({
+                "model": self.model,
+                "max_tokens": 1,
+                "messages": [{ "role": "user", "content": "Hi" }],
+            });
+            self.post(&body).await?.bytes().await?;
+        } else {
+            // Any answer, even an authentication error, leaves a warm connection. The
+            // body is read so the connection goes back to the pool.
+            self.client
+                .get("https://api.anthropic.com/v1/models")
+                .header("x-api-key", self.api_key.current()?.as_ref())
+                .header("anthropic-version", "2023-06-01")
+                .send()
+                .await?
+                .bytes()
+                .await?;
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn exchange(
+        &self,
+        messages: Vec<Message>,
+    ) -> Result<(ClaudeResponse, Message, Usage), RustedChainError> {
+        let response = self.send(messages, false).await?;
 
         let response_body: MessagesResponse = response
             .json()
             .await
-            .map_err(|e| format!("Failed to parse response: {}", e))?;
+            .map_err(|e| RustedChainError::ParseError(e.to_string()))?;
 
-        let assistant_message = Message {
-            role: "assistant".to_string(),
-            content: response_body.content.clone(),
-        };
+        let mut usage = Usage::default();
+        response_body.usage.apply(&mut usage);
+        let (response, mut message) = into_response(response_body.content)?;
+        message.truncated = response_body.stop_reason.as_deref() == Some("max_tokens");
+        Ok(self.prefilled(response, message, usage))
+    }
 
-        let mut text_response: Option<String> = None;
-        for block in response_body.content {
-            match block {
-                ContentBlock::ToolUse { id, name, input } => {
-                    return Ok((
-                        ClaudeResponse::ToolCall(ToolCall {
-                            name,
-                            args: input,
-                            id,
-                        }),
-                        assistant_message,
-                    ));
-                }
-                ContentBlock::Text { text } => {
-                    if text_response.is_none() {
-                        text_response = Some(text);
+    pub(crate) async fn exchange_stream(
+        &self,
+        messages: Vec<Message>,
+        sink: &TextSink,
+    ) -> Result<(ClaudeResponse, Message, Usage), RustedChainError> {
+        let mut response = self.send(messages, true).await?;
+        let mut parser = SseParser::default();
+        let mut blocks: Vec<ContentBlock> = Vec::new();
+        let mut partial_inputs: Vec<String> = Vec::new();
+        let mut usage = Usage::default();
+        let mut prefill = self.prefill.as_deref().map(prefill::partial_turn);
+        let mut stop_reason = None;
+
+        while let Some(chunk) = response.chunk().await? {
+            for data in parser.push(&chunk) {
+                let event: StreamEvent = serde_json::from_str(&data)
+                    .map_err(|e| RustedChainError::ParseError(e.to_string()))?;
+                match event {
+                    StreamEvent::MessageStart { message } => message.usage.apply(&mut usage),
+                    StreamEvent::MessageDelta {
+                        delta,
+                        usage: delta_usage,
+                    } => {
+                        stop_reason = delta.stop_reason.or(stop_reason);
+                        delta_usage.apply(&mut usage);
                     }
+                    StreamEvent::ContentBlockStart { content_block, .. } => {
+                        blocks.push(content_block);
+                        partial_inputs.push(String::new());
+                    }
+                    StreamEvent::ContentBlockDelta { index, delta } => match delta {
+                        BlockDelta::TextDelta { text } => {
+                            if let Some(ContentBlock::Text { text: full }) = blocks.get_mut(index) {
+                                full.push_str(&text);
+                            }
+                            // The prefill is part of the answer, so it streams first.
+                            if let Some(prefill) = prefill.take() {
+                                sink.send(prefill.to_string()).await?;
+                            }
+                            sink.send(text).await?;
+                        }
+                        BlockDelta::InputJsonDelta { partial_json } => {
+                            if let Some(buffer) = partial_inputs.get_mut(index) {
+                                buffer.push_str(&partial_json);
+                            }
+                        }
+                        BlockDelta::Other => {}
+                    },
+                    StreamEvent::Error { error } => {
+                        let body = serde_json::json!({ "error": error }).to_string();
+                        return Err(RustedChainError::from_response(
+                            reqwest::StatusCode::INTERNAL_SERVER_ERROR,
+                            None,
+                            &body,
+                        ));
+                    }
+                    StreamEvent::Other => {}
+                }
+            }
+        }
+
+        for (block, partial) in blocks.iter_mut().zip(&partial_inputs) {
+            if let ContentBlock::ToolUse { input, .. } = block {
+                if !partial.is_empty() {
+                    *input = serde_json::from_str(partial)
+                        .map_err(|e| RustedChainError::ParseError(e.to_string()))?;
                 }
-                _ => {}
             }
         }
 
-        if let Some(text) = text_response {
-            return Ok((ClaudeResponse::Text(text), assistant_message));
+        let (response, mut message) = into_response(blocks)?;
+        message.truncated = stop_reason.as_deref() == Some("max_tokens");
+        Ok(self.prefilled(response, message, usage))
+    }
+
+    /// Put the prefill back in front of a text answer, which continues it.
+    fn prefilled(
+        &self,
+        response: ClaudeResponse,
+        mut message: Message,
+        usage: Usage,
+    ) -> (ClaudeResponse, Message, Usage) {
+        let (Some(prefill), ClaudeResponse::Text(text)) = (&self.prefill, &response) else {
+            return (response, message, usage);
+        };
+        let prefill = prefill::partial_turn(prefill);
+        if let Some(ContentBlock::Text { text }) = message
+            .content
+            .iter_mut()
+            .find(|block| matches!(block, ContentBlock::Text { .. }))
+        {
+            text.insert_str(0, prefill);
+        }
+        let text = format!("{}{}", prefill, text);
+        (ClaudeResponse::Text(text), message, usage)
+    }
+}
+
+/// `messages` with each run of same-role messages merged into one, which Claude
+/// requires: an imported user message followed by tool results, or results sent one
+/// message each, become a single user turn. Tool results go first in a merged turn, as
+/// Claude expects them straight after the calls they answer.
+fn merge_turns(messages: Vec<Message>) -> Vec<Message> {
+    let mut merged: Vec<Message> = Vec::with_capacity(messages.len());
+    for message in messages {
+        match merged.last_mut() {
+            Some(last) if last.role == message.role => {
+                last.content.extend(message.content);
+                if last.role == "user" {
+                    // A stable sort, so results and text each keep their order.
+                    last.content.sort_by_key(|block| match block {
+                        ContentBlock::ToolResult { .. } => 0,
+                        _ => 1,
+                    });
+                }
+            }
+            _ => merged.push(message),
+        }
+    }
+    merged
+}
+
+/// Turn the assistant's content blocks (complete or reassembled from stream deltas) into a response.
+fn into_response(
+    content: Vec<ContentBlock>,
+) -> Result<(ClaudeResponse, Message), RustedChainError> {
+    let assistant_message = Message {
+        role: "assistant".to_string(),
+        content: content.clone(),
+        truncated: false,
+    };
+
+    let mut text_response: Option<String> = None;
+    for block in content {
+        match block {
+            ContentBlock::ToolUse { id, name, input } => {
+                return Ok((
+                    ClaudeResponse::ToolCall(ToolCall {
+                        name,
+                        args: input,
+                        id,
+                    }),
+                    assistant_message,
+                ));
+            }
+            ContentBlock::Text { text } if text_response.is_none() => {
+                text_response = Some(text);
+            }
+            _ => {}
+        }
+    }
+
+    if let Some(text) = text_response {
+        return Ok((ClaudeResponse::Text(text), assistant_message));
+    }
+
+    Err(RustedChainError::NoResponse)
+}
+
+impl From<ClaudeResponse> for Turn {
+    fn from(response: ClaudeResponse) -> Self {
+        match response {
+            ClaudeResponse::Text(text) => Turn::Text(text),
+            ClaudeResponse::ToolCall(call) => Turn::ToolCall(ToolRequest {
+                id: Some(call.id),
+                name: call.name,
+                args: call.args,
+            }),
+        }
+    }
+}
+
+impl ChatClient for Claude {
+    type Message = Message;
+
+    fn tool_call_text(message: &Message) -> Option<String> {
+        let mut calls = false;
+        let mut text = String::new();
+        for block in &message.content {
+            match block {
+                ContentBlock::Text { text: part } => text.push_str(part),
+                ContentBlock::ToolUse { .. } => calls = true,
+                ContentBlock::ToolResult { .. } => {}
+            }
+        }
+        let text = text.trim();
+        (calls && !text.is_empty()).then(|| text.to_string())
+    }
+
+    fn provider(&self) -> &'static str {
+        "anthropic"
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn for_model(&self, model: &str) -> Self {
+        self.clone().with_model(model.to_string())
+    }
+
+    fn side_client(&self, model: &str, system: &str) -> Option<Self> {
+        let mut client = self
+            .for_model(model)
+            .without_tools()
+            .with_system(system.to_string());
+        client.prefill = None;
+        Some(client)
+    }
+
+    fn resumed(&self, partial: &str) -> Option<Self> {
+        Some(self.clone().with_prefill(partial.to_string()))
+    }
+
+    fn continued(&self, partial: &str) -> Option<Self> {
+        Some(self.clone().with_prefill(partial.to_string()))
+    }
+
+    fn truncated(message: &Message) -> bool {
+        message.truncated
+    }
+
+    fn api_key(&self) -> &ApiKey {
+        &self.api_key
+    }
+
+    fn system(&self) -> Option<&str> {
+        self.system.as_deref()
+    }
+
+    fn user_message(text: &str) -> Message {
+        Message {
+            role: "user".to_string(),
+            content: vec![ContentBlock::Text {
+                text: text.to_string(),
+            }],
+            truncated: false,
+        }
+    }
+
+    fn assistant_message(text: &str) -> Message {
+        Message {
+            role: "assistant".to_string(),
+            content: vec![ContentBlock::Text {
+                text: text.to_string(),
+            }],
+            truncated: false,
         }
+    }
+
+    fn tool_call_message(call: &ToolRequest) -> Message {
+        Message {
+            role: "assistant".to_string(),
+            content: vec![ContentBlock::ToolUse {
+                id: call.id.clone().unwrap_or_default(),
+                name: call.name.clone(),
+                input: call.args.clone(),
+            }],
+            truncated: false,
+        }
+    }
+
+    fn tool_result_message(call: &ToolRequest, result: Value) -> Message {
+        Message {
+            role: "user".to_string(),
+            content: vec![ContentBlock::ToolResult {
+                tool_use_id: call.id.clone().unwrap_or_default(),
+                content: wrap_tool_result(result),
+            }],
+            truncated: false,
+        }
+    }
+
+    async fn exchange(
+        &self,
+        conversation: Vec<Message>,
+    ) -> Result<(Turn, Message, Usage), RustedChainError> {
+        let (response, message, usage) = Claude::exchange(self, conversation).await?;
+        Ok((response.into(), message, usage))
+    }
+
+    async fn exchange_stream(
+        &self,
+        conversation: Vec<Message>,
+        sink: &TextSink,
+    ) -> Result<(Turn, Message, Usage), RustedChainError> {
+        let (response, message, usage) = Claude::exchange_stream(self, conversation, sink).await?;
+        Ok((response.into(), message, usage))
+    }
+
+    async fn warmup(&self, prime: bool) -> Result<(), RustedChainError> {
+        Claude::warmup(self, prime).await
+    }
+
+    async fn multimodal(
+        &self,
+        prompt: &str,
+        files: &[Attachment],
+        schema: Option<&Value>,
+    ) -> Result<(String, Usage), RustedChainError> {
+        Claude::multimodal(self, prompt, files, schema).await
+    }
 
-        Err("No response generated.".to_string())
+    async fn raw(&self, payload: Value, path: Option<&str>) -> Result<Value, RustedChainError> {
+        Claude::raw(self, payload, path).await
     }
 }
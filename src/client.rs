@@ -0,0 +1,166 @@
+//! Provider-erased access to the client behind a Python model object, for helpers that
+//! accept "any model" (`map_reduce`, ...), and racing several of them.
+
+use crate::agent::{self, ChatClient};
+use crate::claude::Claude;
+use crate::error::RustedChainError;
+use crate::gemini::Gemini;
+use crate::huggingface::HuggingFace;
+use crate::openai::OpenAI;
+use crate::options::AgentOptions;
+use crate::run::{Labels, RunRecord};
+use crate::{ClaudeModel, GeminiModel, HuggingFaceModel, OpenAIModel};
+use pyo3::exceptions::PyTypeError;
+use pyo3::prelude::*;
+
+pub(crate) enum AnyClient {
+    OpenAI(OpenAI),
+    Claude(Claude),
+    Gemini(Gemini),
+    HuggingFace(HuggingFace),
+}
+
+/// Evaluate `$body` with `$client` bound to the concrete client inside an `AnyClient`.
+macro_rules! with_client {
+    ($any:expr, $client:ident => $body:expr) => {
+        match $any {
+            $crate::client::AnyClient::OpenAI($client) => $body,
+            $crate::client::AnyClient::Claude($client) => $body,
+            $crate::client::AnyClient::Gemini($client) => $body,
+            $crate::client::AnyClient::HuggingFace($client) => $body,
+        }
+    };
+}
+pub(crate) use with_client;
+
+/// A model's client, options and tools, as needed to run the agent loop.
+pub(crate) type AgentParts = (AnyClient, AgentOptions, Option<Vec<Py<PyAny>>>);
+
+impl AnyClient {
+    /// Build a tool-free client and its options from a model class instance or the
+    /// Python wrapper around one.
+    pub(crate) fn from_model(
+        py: Python,
+        model: &Bound<'_, PyAny>,
+    ) -> PyResult<(AnyClient, AgentOptions)> {
+        let (client, options, _) = Self::from_agent(py, model)?;
+        Ok((client.without_tools(), options))
+    }
+
+    /// Like `from_model`, but keeping the model's tools, returned alongside for the
+    /// agent loop to execute.
+    pub(crate) fn from_agent(py: Python, model: &Bound<'_, PyAny>) -> PyResult<AgentParts> {
+        let model = match model.getattr("_agent") {
+            Ok(inner) => inner,
+            Err(_) => model.clone(),
+        };
+        let tools = |tools: &Option<Vec<Py<PyAny>>>| {
+            tools
+                .as_ref()
+                .map(|tools| tools.iter().map(|tool| tool.clone_ref(py)).collect())
+        };
+        if let Ok(m) = model.cast::<OpenAIModel>() {
+            let m = m.borrow();
+            Ok((
+                AnyClient::OpenAI(m.build_client(py)),
+                m.options.clone(),
+                tools(&m.tools),
+            ))
+        } else if let Ok(m) = model.cast::<ClaudeModel>() {
+            let m = m.borrow();
+            Ok((
+                AnyClient::Claude(m.build_client(py)),
+                m.options.clone(),
+                tools(&m.tools),
+            ))
+        } else if let Ok(m) = model.cast::<GeminiModel>() {
+            let m = m.borrow();
+            Ok((
+                AnyClient::Gemini(m.build_client(py)),
+                m.options.clone(),
+                tools(&m.tools),
+            ))
+        } else if let Ok(m) = model.cast::<HuggingFaceModel>() {
+            let m = m.borrow();
+            Ok((
+                AnyClient::HuggingFace(m.build_client(py)),
+                m.options.clone(),
+                tools(&m.tools),
+            ))
+        } else {
+            Err(PyTypeError::new_err(
+                "expected a GeminiModel, OpenAIModel, ClaudeModel or HuggingFaceModel",
+            ))
+        }
+    }
+
+    /// The client with `section` appended to its system prompt.
+    pub(crate) fn with_system_section(self, section: &str) -> Self {
+        let extend = |system: Option<&str>| match system {
+            Some(system) => format!("{}\n\n{}", system, section),
+            None => section.to_string(),
+        };
+        match self {
+            AnyClient::OpenAI(client) => {
+                let system = extend(client.system());
+                AnyClient::OpenAI(client.with_system(system))
+            }
+            AnyClient::Claude(client) => {
+                let system = extend(client.system());
+                AnyClient::Claude(client.with_system(system))
+            }
+            AnyClient::Gemini(client) => {
+                let system = extend(client.system());
+                AnyClient::Gemini(client.with_system(system))
+            }
+            AnyClient::HuggingFace(client) => {
+                let system = extend(client.system());
+                AnyClient::HuggingFace(client.with_system(system))
+            }
+        }
+    }
+
+    fn without_tools(self) -> Self {
+        match self {
+            AnyClient::OpenAI(client) => AnyClient::OpenAI(client.without_tools()),
+            AnyClient::Claude(client) => AnyClient::Claude(client.without_tools()),
+            AnyClient::Gemini(client) => AnyClient::Gemini(client.without_tools()),
+            AnyClient::HuggingFace(client) => AnyClient::HuggingFace(client.without_tools()),
+        }
+    }
+}
+
+/// A single tool-free completion, recorded as its own run.
+pub(crate) async fn complete(
+    client: &AnyClient,
+    options: &AgentOptions,
+    prompt: &str,
+) -> Result<String, RustedChainError> {
+    with_client!(client, client => {
+        let mut run =
+            RunRecord::start(client, options, prompt, Vec::new(), false, Labels::default());
+        let result = agent::complete(client, options, &run, prompt).await;
+        if let Ok((_, usage)) = &result {
+            run.record_call(*usage);
+        }
+        run.finish(match &result {
+            Ok((text, _)) => Ok(text.clone()),
+            Err(err) => Err(err.to_string()),
+        });
+        result.map(|(text, _)| text)
+    })
+}
+
+/// Send `prompt` to every client at once and return the first successful answer. The
+/// slower requests are dropped, which cancels them. Fails only if every client fails,
+/// with the last error.
+pub(crate) async fn race(
+    clients: &[(AnyClient, AgentOptions)],
+    prompt: &str,
+) -> Result<String, RustedChainError> {
+    let attempts = clients
+        .iter()
+        .map(|(client, options)| Box::pin(complete(client, options, prompt)));
+    let (text, _) = futures::future::select_ok(attempts).await?;
+    Ok(text)
+}
@@ -0,0 +1,123 @@
+//! The `current_date` option: the date and time, given to the model in the system
+//! prompt of each call, since models otherwise assume the date of their training data.
+
+use chrono::format::{Item, StrftimeItems};
+use chrono::{DateTime, FixedOffset, Local, Utc};
+use pyo3::exceptions::{PyUserWarning, PyValueError};
+use pyo3::prelude::*;
+use std::ffi::CString;
+use std::sync::Arc;
+
+/// E.g. "Thursday, October 15, 2026, 14:05 (UTC+02:00)".
+const DEFAULT_FORMAT: &str = "%A, %B %-d, %Y, %H:%M (UTC%:z)";
+
+/// How the current date is written into the system prompt.
+#[derive(Clone)]
+pub(crate) struct CurrentDate {
+    /// A strftime format.
+    format: String,
+    /// `None` for the machine's local timezone.
+    offset: Option<FixedOffset>,
+    /// Returns the time to report instead of the system clock, for tests.
+    clock: Option<Arc<Py<PyAny>>>,
+}
+
+impl CurrentDate {
+    /// `current_date`, `date_timezone` and `clock` as passed to the model. `current_date`
+    /// is `True` for the default format or a strftime format; `date_timezone` is
+    /// `"local"` (default), `"UTC"` or an offset such as `"+05:30"`; `clock` returns a
+    /// `datetime` or a Unix timestamp.
+    pub(crate) fn from_py(
+        current_date: &Bound<'_, PyAny>,
+        timezone: Option<&Bound<'_, PyAny>>,
+        clock: Option<&Bound<'_, PyAny>>,
+    ) -> PyResult<Option<Self>> {
+        let format = match current_date.extract::<bool>() {
+            Ok(true) => DEFAULT_FORMAT.to_string(),
+            Ok(false) => return Ok(None),
+            Err(_) if current_date.is_none() => return Ok(None),
+            Err(_) => {
+                let format: String = current_date.extract()?;
+                if StrftimeItems::new(&format).any(|item| matches!(item, Item::Error)) {
+                    return Err(PyValueError::new_err(format!(
+                        "current_date: invalid strftime format '{}'",
+                        format
+                    )));
+                }
+                format
+            }
+        };
+        let offset = match timezone.filter(|timezone| !timezone.is_none()) {
+            Some(timezone) => parse_timezone(&timezone.extract::<String>()?)?,
+            None => None,
+        };
+        let clock = match clock.filter(|clock| !clock.is_none()) {
+            Some(clock) if clock.is_callable() => Some(Arc::new(clock.clone().unbind())),
+            Some(_) => return Err(PyValueError::new_err("clock must be a callable")),
+            None => None,
+        };
+        Ok(Some(CurrentDate {
+            format,
+            offset,
+            clock,
+        }))
+    }
+
+    /// The system prompt sentence giving the current date and time.
+    pub(crate) fn instruction(&self) -> String {
+        format!(
+            "The current date and time is {}.",
+            self.now().format(&self.format)
+        )
+    }
+
+    fn now(&self) -> DateTime<FixedOffset> {
+        let utc = self
+            .clock
+            .as_ref()
+            .and_then(|clock| Python::attach(|py| read_clock(py, clock)))
+            .unwrap_or_else(Utc::now);
+        match self.offset {
+            Some(offset) => utc.with_timezone(&offset),
+            None => utc.with_timezone(&Local).fixed_offset(),
+        }
+    }
+}
+
+/// `"local"`, `"UTC"` or a fixed offset such as `"+05:30"`.
+fn parse_timezone(timezone: &str) -> PyResult<Option<FixedOffset>> {
+    match timezone {
+        "local" => Ok(None),
+        "UTC" | "utc" | "Z" => Ok(FixedOffset::east_opt(0)),
+        offset => offset.parse::<FixedOffset>().map(Some).map_err(|_| {
+            PyValueError::new_err(format!(
+                "date_timezone must be 'local', 'UTC' or an offset such as '+05:30', got '{}'",
+                offset
+            ))
+        }),
+    }
+}
+
+/// The time `clock` reports; if it fails, a warning and `None` for the system clock.
+fn read_clock(py: Python, clock: &Py<PyAny>) -> Option<DateTime<Utc>> {
+    let read = || -> PyResult<DateTime<Utc>> {
+        let now = clock.bind(py).call0()?;
+        let timestamp: f64 = if now.hasattr("timestamp")? {
+            now.call_method0("timestamp")?.extract()?
+        } else {
+            now.extract()?
+        };
+        DateTime::from_timestamp_millis((timestamp * 1000.0).round() as i64).ok_or_else(|| {
+            PyValueError::new_err(format!("timestamp {} is out of range", timestamp))
+        })
+    };
+    match read() {
+        Ok(now) => Some(now),
+        Err(e) => {
+            let message = CString::new(format!("clock failed, using the system clock: {}", e))
+                .unwrap_or_default();
+            PyErr::warn(py, &py.get_type::<PyUserWarning>(), &message, 1).ok();
+            None
+        }
+    }
+}
@@ -0,0 +1,254 @@
+//! Compression of long prompts before they are sent: the sentences carrying the least
+//! information are dropped until the prompt is down to a target share of its tokens,
+//! in the spirit of LLMLingua. The question at the end is always kept, and sentences
+//! that share its terms are preferred.
+
+use crate::bm25;
+use crate::splitter::count_tokens;
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+const STOPWORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "been", "but", "by", "can", "do", "does", "for",
+    "from", "had", "has", "have", "he", "her", "his", "i", "if", "in", "into", "is", "it", "its",
+    "of", "on", "or", "our", "she", "so", "that", "the", "their", "them", "then", "there", "these",
+    "they", "this", "to", "was", "we", "were", "what", "which", "who", "will", "with", "would",
+    "you", "your",
+];
+
+/// How much a term shared with the question weighs against one that is merely rare.
+const QUESTION_WEIGHT: f64 = 2.0;
+
+pub(crate) struct Compressor {
+    ratio: f64,
+    min_tokens: usize,
+    /// Scores sentences in place of the built-in heuristic: called with the sentences
+    /// and the question, returns one number per sentence.
+    scorer: Option<Py<PyAny>>,
+}
+
+/// Shortens long prompts to about `ratio` of their tokens by dropping low-information
+/// sentences. Prompts under `min_tokens` are sent as they are. `scorer`, e.g. a small
+/// model, replaces the built-in scoring: it is called with the list of sentences and
+/// the question, and returns a score per sentence; higher is kept first.
+#[pyclass(frozen)]
+pub struct PromptCompressor {
+    pub(crate) compressor: Arc<Compressor>,
+}
+
+#[pymethods]
+impl PromptCompressor {
+    #[new]
+    #[pyo3(signature = (ratio=0.5, min_tokens=1000, scorer=None))]
+    fn new(ratio: f64, min_tokens: usize, scorer: Option<Bound<'_, PyAny>>) -> PyResult<Self> {
+        if !(ratio > 0.0 && ratio <= 1.0) {
+            return Err(PyValueError::new_err(format!(
+                "ratio must be above 0 and at most 1, got {}",
+                ratio
+            )));
+        }
+        if scorer.as_ref().is_some_and(|scorer| !scorer.is_callable()) {
+            return Err(PyTypeError::new_err("scorer must be callable"));
+        }
+        Ok(PromptCompressor {
+            compressor: Arc::new(Compressor {
+                ratio,
+                min_tokens,
+                scorer: scorer.map(Bound::unbind),
+            }),
+        })
+    }
+
+    #[getter]
+    fn ratio(&self) -> f64 {
+        self.compressor.ratio
+    }
+
+    #[getter]
+    fn min_tokens(&self) -> usize {
+        self.compressor.min_tokens
+    }
+
+    /// `text` as it would be sent.
+    fn compress(&self, py: Python, text: &str) -> PyResult<String> {
+        Ok(self.compressor.compress(py, text)?.into_owned())
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "PromptCompressor(ratio={}, min_tokens={})",
+            self.compressor.ratio, self.compressor.min_tokens
+        )
+    }
+}
+
+impl Compressor {
+    /// The `compress_prompt` option: a ratio or a `PromptCompressor`.
+    pub(crate) fn from_py(value: &Bound<'_, PyAny>) -> PyResult<Arc<Self>> {
+        if let Ok(compressor) = value.cast::<PromptCompressor>() {
+            return Ok(compressor.get().compressor.clone());
+        }
+        match value.extract::<f64>() {
+            Ok(ratio) => Ok(PromptCompressor::new(ratio, 1000, None)?.compressor),
+            Err(_) => Err(PyTypeError::new_err(
+                "compress_prompt must be a ratio or a PromptCompressor",
+            )),
+        }
+    }
+
+    pub(crate) fn compress<'a>(&self, py: Python, text: &'a str) -> PyResult<Cow<'a, str>> {
+        let total = count_tokens(text);
+        if total < self.min_tokens || self.ratio >= 1.0 {
+            return Ok(Cow::Borrowed(text));
+        }
+        let mut sentences = split_sentences(text);
+        // The question, usually the last line after the context, is always kept.
+        let budget = (total as f64 * self.ratio) as usize;
+        let last = sentences.len().saturating_sub(1);
+        let question_start = sentences
+            .iter()
+            .rposition(|sentence| sentence.starts_line)
+            .filter(|&start| start > 0)
+            .filter(|&start| {
+                sentences[start..].iter().map(|s| s.tokens).sum::<usize>() <= budget / 2
+            })
+            .unwrap_or(last);
+        let (context, question) = sentences.split_at_mut(question_start);
+        let question_text: String = question.iter().map(|s| s.text).collect();
+        let scores = match &self.scorer {
+            Some(scorer) => {
+                let texts: Vec<&str> = context.iter().map(|s| s.text.trim()).collect();
+                let scores: Vec<f64> = scorer.bind(py).call1((texts, &question_text))?.extract()?;
+                if scores.len() != context.len() {
+                    return Err(PyValueError::new_err(format!(
+                        "scorer returned {} scores for {} sentences",
+                        scores.len(),
+                        context.len()
+                    )));
+                }
+                scores
+            }
+            None => heuristic_scores(context, &question_text),
+        };
+
+        for sentence in question.iter_mut() {
+            sentence.kept = true;
+        }
+        let mut spent: usize = question.iter().map(|s| s.tokens).sum();
+        let mut order: Vec<usize> = (0..context.len()).collect();
+        order.sort_by(|&a, &b| scores[b].total_cmp(&scores[a]));
+        for index in order {
+            if spent + context[index].tokens <= budget {
+                spent += context[index].tokens;
+                context[index].kept = true;
+            }
+        }
+        let mut out = String::with_capacity(text.len());
+        for sentence in sentences.iter().filter(|s| s.kept) {
+            if sentence.starts_line && !out.is_empty() && !out.ends_with('\n') {
+                out.push('\n');
+            }
+            out.push_str(sentence.text);
+        }
+        Ok(Cow::Owned(out))
+    }
+}
+
+struct Sentence<'a> {
+    /// With its trailing whitespace, so kept sentences join up as they were.
+    text: &'a str,
+    starts_line: bool,
+    tokens: usize,
+    kept: bool,
+}
+
+/// `text` cut after sentence-ending punctuation and at line breaks.
+fn split_sentences(text: &str) -> Vec<Sentence<'_>> {
+    let mut sentences = Vec::new();
+    let mut start = 0;
+    let mut starts_line = true;
+    let mut chars = text.char_indices().peekable();
+    while let Some((at, c)) = chars.next() {
+        let next = chars.peek().map(|&(_, next)| next);
+        let ends = match c {
+            '\n' => true,
+            '.' | '!' | '?' => next.is_some_and(|next| next == ' ' || next == '\n'),
+            _ => false,
+        };
+        if !ends {
+            continue;
+        }
+        // The whitespace that follows goes with the sentence.
+        let mut end = at + c.len_utf8();
+        let mut line_break = c == '\n';
+        while let Some(&(at, next)) = chars.peek() {
+            if !next.is_whitespace() {
+                break;
+            }
+            end = at + next.len_utf8();
+            chars.next();
+            if next == '\n' {
+                line_break = true;
+            }
+        }
+        push_sentence(&mut sentences, &text[start..end], starts_line);
+        starts_line = line_break;
+        start = end;
+    }
+    push_sentence(&mut sentences, &text[start..], starts_line);
+    sentences
+}
+
+fn push_sentence<'a>(sentences: &mut Vec<Sentence<'a>>, text: &'a str, starts_line: bool) {
+    if text.trim().is_empty() {
+        return;
+    }
+    sentences.push(Sentence {
+        text,
+        starts_line,
+        tokens: count_tokens(text),
+        kept: false,
+    });
+}
+
+/// Information per token: rare terms count more, terms from the question more again,
+/// and repeats of an earlier sentence nothing.
+fn heuristic_scores(sentences: &[Sentence], question: &str) -> Vec<f64> {
+    let content_terms = |text: &str| -> HashSet<String> {
+        bm25::terms(text)
+            .into_iter()
+            .filter(|term| !STOPWORDS.contains(&term.as_str()))
+            .collect()
+    };
+    let terms: Vec<HashSet<String>> = sentences.iter().map(|s| content_terms(s.text)).collect();
+    let mut frequency: HashMap<&str, usize> = HashMap::new();
+    for term in terms.iter().flatten() {
+        *frequency.entry(term).or_default() += 1;
+    }
+    let count = sentences.len() as f64;
+    let question = content_terms(question);
+    let mut seen = HashSet::new();
+    sentences
+        .iter()
+        .zip(&terms)
+        .map(|(sentence, terms)| {
+            if !seen.insert(sentence.text.trim().to_lowercase()) {
+                return 0.0;
+            }
+            let information: f64 = terms
+                .iter()
+                .map(|term| {
+                    let idf = (1.0 + count / frequency[term.as_str()] as f64).ln();
+                    match question.contains(term) {
+                        true => idf * (1.0 + QUESTION_WEIGHT),
+                        false => idf,
+                    }
+                })
+                .sum();
+            information / sentence.tokens.max(1) as f64
+        })
+        .collect()
+}
@@ -0,0 +1,43 @@
+//! Named values for the strings the API takes, so a typo is an attribute error that
+//! IDEs and type checkers catch rather than a `ValueError` at run time. The values are
+//! plain strings and can be passed wherever the string is accepted.
+
+use pyo3::prelude::*;
+
+/// Provider names, as taken by `create_agent(provider=...)` and reported in run
+/// records.
+#[pyclass(frozen)]
+pub struct Providers;
+
+#[pymethods]
+impl Providers {
+    #[classattr]
+    const OPENAI: &'static str = "openai";
+    #[classattr]
+    const ANTHROPIC: &'static str = "anthropic";
+    #[classattr]
+    const GEMINI: &'static str = "gemini";
+    #[classattr]
+    const HUGGINGFACE: &'static str = "huggingface";
+}
+
+/// Message roles, as in `Session.history`. `MODEL` and `FUNCTION` are Gemini's names
+/// for `ASSISTANT` and `TOOL`, accepted by `Session.load`.
+#[pyclass(frozen)]
+pub struct Roles;
+
+#[pymethods]
+impl Roles {
+    #[classattr]
+    const USER: &'static str = "user";
+    #[classattr]
+    const ASSISTANT: &'static str = "assistant";
+    #[classattr]
+    const TOOL: &'static str = "tool";
+    #[classattr]
+    const SYSTEM: &'static str = "system";
+    #[classattr]
+    const MODEL: &'static str = "model";
+    #[classattr]
+    const FUNCTION: &'static str = "function";
+}
@@ -0,0 +1,81 @@
+//! Per-run context handed to tools that ask for it.
+
+use crate::artifacts::{Artifact, ArtifactSettings, ArtifactStore};
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList};
+use serde_json::Value;
+
+/// Passed as `ctx` to tools whose first parameter is `ctx`, instead of a value chosen
+/// by the model. Lives for one run, so tools can share state without globals.
+#[pyclass]
+pub struct RunContext {
+    /// Identifier of the run, the same one used in run records and audit logs.
+    #[pyo3(get)]
+    pub session_id: String,
+    /// Agent loop iteration of the current tool call, starting at 0.
+    #[pyo3(get)]
+    pub iteration: usize,
+    /// Earlier tool calls in this run, as `{"name", "args", "result"}` dicts.
+    #[pyo3(get)]
+    pub tool_results: Py<PyList>,
+    /// Metadata passed to `invoke` or `run`.
+    #[pyo3(get)]
+    pub metadata: Py<PyDict>,
+    /// Scratch space shared by every tool call in the run.
+    #[pyo3(get)]
+    pub state: Py<PyDict>,
+    pub(crate) artifacts: ArtifactStore,
+}
+
+#[pymethods]
+impl RunContext {
+    /// Artifacts stored so far in this run.
+    #[getter]
+    fn artifacts(&self) -> Vec<Artifact> {
+        self.artifacts.list()
+    }
+
+    fn __repr__(&self, py: Python) -> String {
+        format!(
+            "RunContext(session_id='{}', iteration={}, tool_results={})",
+            self.session_id,
+            self.iteration,
+            self.tool_results.bind(py).len()
+        )
+    }
+}
+
+impl RunContext {
+    pub(crate) fn create(
+        py: Python,
+        session_id: &str,
+        metadata: Option<Py<PyDict>>,
+        artifacts: &ArtifactSettings,
+    ) -> PyResult<Py<RunContext>> {
+        Py::new(
+            py,
+            RunContext {
+                session_id: session_id.to_string(),
+                iteration: 0,
+                tool_results: PyList::empty(py).unbind(),
+                metadata: metadata.unwrap_or_else(|| PyDict::new(py).unbind()),
+                state: PyDict::new(py).unbind(),
+                artifacts: ArtifactStore::new(artifacts, session_id),
+            },
+        )
+    }
+
+    pub(crate) fn record(
+        &self,
+        py: Python,
+        name: &str,
+        args: &Value,
+        result: &Value,
+    ) -> PyResult<()> {
+        let entry = PyDict::new(py);
+        entry.set_item("name", name)?;
+        entry.set_item("args", pythonize::pythonize(py, args)?)?;
+        entry.set_item("result", pythonize::pythonize(py, result)?)?;
+        self.tool_results.bind(py).append(entry)
+    }
+}
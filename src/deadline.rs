@@ -0,0 +1,44 @@
+//! `first_token_deadline_ms`: how long a provider has to start answering a request
+//! before it is abandoned.
+
+use crate::error::RustedChainError;
+use std::cell::Cell;
+use std::future::Future;
+use std::time::Duration;
+
+tokio::task_local! {
+    /// Whether the response to the request inside `first_token` has started.
+    static STARTED: Cell<bool>;
+}
+
+/// Note that the response has started: its first event if it is streamed, otherwise the
+/// response itself.
+pub(crate) fn started() {
+    let _ = STARTED.try_with(|started| started.set(true));
+}
+
+/// Await `future`, failing with `FirstTokenDeadline` if its response has not started
+/// within `limit`. A response that has started is awaited to the end.
+pub(crate) async fn first_token<F, T>(
+    limit: Option<Duration>,
+    future: F,
+) -> Result<T, RustedChainError>
+where
+    F: Future<Output = Result<T, RustedChainError>>,
+{
+    let Some(limit) = limit else {
+        return future.await;
+    };
+    STARTED
+        .scope(Cell::new(false), async {
+            futures::pin_mut!(future);
+            match tokio::time::timeout(limit, &mut future).await {
+                Ok(result) => result,
+                Err(_) if STARTED.with(Cell::get) => future.await,
+                Err(_) => Err(RustedChainError::FirstTokenDeadline(
+                    limit.as_millis() as u64
+                )),
+            }
+        })
+        .await
+}
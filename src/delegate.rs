@@ -0,0 +1,182 @@
+//! A tool through which a model splits work into tasks, each run by a sub-agent with
+//! a history of its own, in parallel.
+
+use crate::context::RunContext;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// How deep sub-agents may delegate in turn, when their model has a `Delegate` too.
+const MAX_DEPTH: u64 = 2;
+
+const DESCRIPTION: &str = "Hand independent tasks to sub-agents that work on them in \
+     parallel and return each task's answer. Each sub-agent sees only its own task, so \
+     write every task to be self-contained. Use it to split research or other work that \
+     has separate parts.";
+
+/// Add to a model's tools to let it fan work out: the model calls `delegate` with a
+/// list of tasks, `model` runs each one as a fresh conversation, at most
+/// `max_concurrency` at a time, and the answers come back together as the tool result.
+/// Calls with more than `max_tasks` tasks run the first `max_tasks`.
+#[pyclass(frozen)]
+pub struct Delegate {
+    model: Py<PyAny>,
+    max_concurrency: usize,
+    max_tasks: usize,
+    name: String,
+    description: String,
+}
+
+#[pymethods]
+impl Delegate {
+    #[new]
+    #[pyo3(signature = (model, max_concurrency=4, max_tasks=8, name="delegate", description=None))]
+    fn new(
+        model: Py<PyAny>,
+        max_concurrency: usize,
+        max_tasks: usize,
+        name: &str,
+        description: Option<String>,
+    ) -> PyResult<Self> {
+        if max_concurrency == 0 || max_tasks == 0 {
+            return Err(PyValueError::new_err(
+                "max_concurrency and max_tasks must be at least 1",
+            ));
+        }
+        Ok(Delegate {
+            model,
+            max_concurrency,
+            max_tasks,
+            name: name.to_string(),
+            description: description.unwrap_or_else(|| DESCRIPTION.to_string()),
+        })
+    }
+
+    #[getter(__name__)]
+    fn tool_name(&self) -> &str {
+        &self.name
+    }
+
+    /// Asks the agent for the run context, to link sub-agent runs to the run that
+    /// delegated them.
+    #[getter]
+    fn takes_context(&self) -> bool {
+        true
+    }
+
+    /// The function schema the model sees.
+    fn to_dict<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let schema = json!({
+            "name": self.name,
+            "description": self.description,
+            "parameters": {
+                "type": "object",
+                "properties": {
+                    "tasks": {
+                        "type": "array",
+                        "items": { "type": "string" },
+                        "description": format!(
+                            "Self-contained tasks, one per sub-agent, at most {}.",
+                            self.max_tasks
+                        ),
+                    },
+                },
+                "required": ["tasks"],
+            },
+        });
+        Ok(pythonize::pythonize(py, &schema)?)
+    }
+
+    /// Run `tasks`, returning `{"task", "answer"}` for each, or `{"task", "error"}` for
+    /// those that failed or were not run.
+    #[pyo3(signature = (tasks, ctx=None))]
+    fn __call__<'py>(
+        &self,
+        py: Python<'py>,
+        tasks: Vec<String>,
+        ctx: Option<Py<RunContext>>,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let (parent, depth) = match &ctx {
+            Some(ctx) => {
+                let ctx = ctx.borrow(py);
+                let depth: u64 = ctx
+                    .metadata
+                    .bind(py)
+                    .get_item("delegate_depth")?
+                    .and_then(|depth| depth.extract().ok())
+                    .unwrap_or(0);
+                (Some(ctx.session_id.clone()), depth)
+            }
+            None => (None, 0),
+        };
+        let metadata = json!({ "parent_run_id": parent, "delegate_depth": depth + 1 });
+        let run = tasks.len().min(self.max_tasks);
+        let results: Vec<Mutex<Option<Value>>> = (0..run).map(|_| Mutex::new(None)).collect();
+        if depth < MAX_DEPTH {
+            let next = AtomicUsize::new(0);
+            py.detach(|| {
+                std::thread::scope(|scope| {
+                    for _ in 0..self.max_concurrency.min(run) {
+                        scope.spawn(|| loop {
+                            let index = next.fetch_add(1, Ordering::Relaxed);
+                            if index >= run {
+                                break;
+                            }
+                            let outcome =
+                                Python::attach(|py| self.run_task(py, &tasks[index], &metadata));
+                            *results[index].lock().unwrap() = Some(outcome);
+                        });
+                    }
+                })
+            });
+        }
+        let report: Vec<Value> = tasks
+            .iter()
+            .enumerate()
+            .map(|(index, task)| {
+                let outcome = results
+                    .get(index)
+                    .and_then(|result| result.lock().unwrap().take());
+                match outcome {
+                    Some(outcome) => outcome,
+                    None if index >= run => json!({
+                        "task": task,
+                        "error": format!("not run: at most {} tasks per call", self.max_tasks),
+                    }),
+                    None => json!({
+                        "task": task,
+                        "error": format!("not run: delegation is limited to {} levels", MAX_DEPTH),
+                    }),
+                }
+            })
+            .collect();
+        Ok(pythonize::pythonize(py, &report)?)
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Delegate(name='{}', max_concurrency={}, max_tasks={})",
+            self.name, self.max_concurrency, self.max_tasks
+        )
+    }
+}
+
+impl Delegate {
+    fn run_task(&self, py: Python, task: &str, metadata: &Value) -> Value {
+        let answer = (|| -> PyResult<String> {
+            let kwargs = PyDict::new(py);
+            kwargs.set_item("metadata", pythonize::pythonize(py, metadata)?)?;
+            self.model
+                .bind(py)
+                .call_method("run", (task,), Some(&kwargs))?
+                .extract()
+        })();
+        match answer {
+            Ok(answer) => json!({ "task": task, "answer": answer }),
+            Err(err) => json!({ "task": task, "error": err.to_string() }),
+        }
+    }
+}
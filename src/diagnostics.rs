@@ -0,0 +1,146 @@
+//! A report of what this installation can do: which providers have keys and answer,
+//! the features compiled in, the runtime's state and the versions in use. Meant for
+//! bug reports and for checking a deployment before it takes traffic.
+
+use crate::agent::ChatClient;
+use crate::claude::Claude;
+use crate::error::RustedChainError;
+use crate::gemini::Gemini;
+use crate::huggingface::HuggingFace;
+use crate::openai::OpenAI;
+use crate::presets::PRESETS;
+use crate::{runtime, RUNTIME};
+use futures::future::{join_all, LocalBoxFuture};
+use futures::FutureExt;
+use pyo3::prelude::*;
+use serde_json::{json, Map, Value};
+use std::env;
+use std::time::{Duration, Instant};
+
+/// Features this build was compiled with.
+const FEATURES: &[(&str, bool)] = &[
+    ("loaders", cfg!(feature = "loaders")),
+    ("pgvector", cfg!(feature = "pgvector")),
+    ("images", cfg!(feature = "images")),
+    ("grpc", cfg!(feature = "grpc")),
+];
+
+/// Whether the provider's endpoint answered a connection, and how fast. Any answer
+/// counts, an authentication error included: the key is checked separately.
+async fn reach(client: impl ChatClient, timeout: Duration) -> Value {
+    let started = Instant::now();
+    match tokio::time::timeout(timeout, client.warmup(false)).await {
+        Ok(Ok(())) => json!({
+            "reachable": true,
+            "latency_s": started.elapsed().as_secs_f64(),
+            "error": null,
+        }),
+        Ok(Err(err)) => json!({ "reachable": false, "latency_s": null, "error": err.to_string() }),
+        Err(_) => json!({
+            "reachable": false,
+            "latency_s": null,
+            "error": format!("no answer within {}s", timeout.as_secs_f64()),
+        }),
+    }
+}
+
+/// The version of an installed Python distribution, if it is installed.
+fn distribution_version(py: Python, name: &str) -> Option<String> {
+    py.import("importlib.metadata")
+        .and_then(|metadata| metadata.call_method1("version", (name,)))
+        .and_then(|version| version.extract())
+        .ok()
+}
+
+/// Report on this installation as a dict:
+///
+/// - `providers`: per provider, the environment variable its key is read from,
+///   whether it is set, and with `network=True` whether the endpoint answered within
+///   `timeout` seconds. `usable` is true when the key is set and the endpoint was not
+///   found unreachable.
+/// - `features`: the optional features compiled in.
+/// - `runtime`: the tokio runtime's flavor, threads and whether it is taking calls.
+/// - `versions`: rusted_chain, Python and langchain-core.
+///
+/// Probing sends no prompts and uses no tokens; it starts the runtime if it has not
+/// started yet, so the `runtime` section is read first.
+#[pyfunction]
+#[pyo3(signature = (network=true, timeout=5.0))]
+pub fn diagnostics(py: Python, network: bool, timeout: f64) -> PyResult<Py<PyAny>> {
+    dotenv::dotenv().ok();
+    let runtime = runtime::status();
+
+    let mut providers = vec![
+        ("openai", "OPENAI_API_KEY"),
+        ("anthropic", "ANTHROPIC_API_KEY"),
+        ("gemini", "GOOGLE_API_KEY"),
+        ("huggingface", "HF_TOKEN"),
+    ];
+    providers.extend(PRESETS.iter().map(|preset| (preset.name, preset.env_var)));
+    let timeout = Duration::from_secs_f64(timeout.max(0.0));
+    let reached: Result<Vec<Value>, RustedChainError> = match network {
+        true => py.detach(|| {
+            RUNTIME.block_on(async {
+                // In the order of `providers`.
+                let mut probes: Vec<LocalBoxFuture<'_, Value>> = vec![
+                    reach(OpenAI::new(), timeout).boxed_local(),
+                    reach(Claude::new(), timeout).boxed_local(),
+                    reach(Gemini::new(), timeout).boxed_local(),
+                    reach(HuggingFace::new(), timeout).boxed_local(),
+                ];
+                for preset in PRESETS {
+                    let client = OpenAI::new()
+                        .with_base_url(preset.base_url.to_string())
+                        .with_api_key(preset.api_key().into());
+                    probes.push(reach(client, timeout).boxed_local());
+                }
+                Ok(join_all(probes).await)
+            })
+        }),
+        false => Ok(vec![Value::Null; providers.len()]),
+    };
+
+    let mut report = Map::new();
+    for (index, (name, env_var)) in providers.into_iter().enumerate() {
+        let key_present = env::var(env_var).is_ok_and(|key| !key.is_empty());
+        let (reachable, latency, error) = match &reached {
+            Ok(reached) => (
+                reached[index].get("reachable").cloned(),
+                reached[index].get("latency_s").cloned(),
+                reached[index].get("error").cloned(),
+            ),
+            Err(err) => (None, None, Some(json!(err.to_string()))),
+        };
+        let usable = key_present && reachable.as_ref() != Some(&json!(false));
+        report.insert(
+            name.to_string(),
+            json!({
+                "key_env": env_var,
+                "key_present": key_present,
+                "reachable": reachable,
+                "latency_s": latency,
+                "error": error,
+                "usable": usable,
+            }),
+        );
+    }
+
+    let sys = py.import("sys")?;
+    let python: String = sys.getattr("version")?.extract()?;
+    let report = json!({
+        "providers": report,
+        "features": FEATURES
+            .iter()
+            .map(|(name, enabled)| (name.to_string(), json!(enabled)))
+            .collect::<Map<_, _>>(),
+        "runtime": runtime,
+        "versions": {
+            "rusted_chain": distribution_version(py, "rusted_chain")
+                .unwrap_or_else(|| env!("CARGO_PKG_VERSION").to_string()),
+            "python": python.split_whitespace().next(),
+            "langchain_core": distribution_version(py, "langchain-core"),
+        },
+        "platform": sys.getattr("platform")?.extract::<String>()?,
+    });
+    Ok(pythonize::pythonize(py, &report)?.unbind())
+}
@@ -0,0 +1,64 @@
+//! A piece of text with metadata, shared by loaders, splitters and retrieval.
+
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+
+#[pyclass]
+pub struct Document {
+    #[pyo3(get, set)]
+    pub text: String,
+    #[pyo3(get, set)]
+    pub metadata: Py<PyDict>,
+    /// Identifier in a vector store, if the document has been stored.
+    #[pyo3(get, set)]
+    pub id: Option<String>,
+}
+
+#[pymethods]
+impl Document {
+    #[new]
+    #[pyo3(signature = (text, metadata=None, id=None))]
+    fn new(py: Python, text: String, metadata: Option<Py<PyDict>>, id: Option<String>) -> Self {
+        Document {
+            text,
+            metadata: metadata.unwrap_or_else(|| PyDict::new(py).unbind()),
+            id,
+        }
+    }
+
+    fn __repr__(&self, py: Python) -> PyResult<String> {
+        const PREVIEW: usize = 60;
+        let mut preview: String = self.text.chars().take(PREVIEW).collect();
+        if self.text.chars().nth(PREVIEW).is_some() {
+            preview.push_str("...");
+        }
+        let id = match &self.id {
+            Some(id) => format!("id={:?}, ", id),
+            None => String::new(),
+        };
+        Ok(format!(
+            "Document({}text={:?}, metadata={})",
+            id,
+            preview,
+            self.metadata.bind(py).repr()?
+        ))
+    }
+}
+
+impl Document {
+    pub(crate) fn create(
+        py: Python,
+        text: String,
+        metadata: Bound<'_, PyDict>,
+        id: Option<String>,
+    ) -> PyResult<Py<Document>> {
+        Py::new(
+            py,
+            Document {
+                text,
+                metadata: metadata.unbind(),
+                id,
+            },
+        )
+    }
+}
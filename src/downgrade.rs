@@ -0,0 +1,95 @@
+//! Switching a session to a cheaper model once it has spent enough.
+
+use crate::agent::Usage;
+use crate::pricing;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use serde_json::{json, Value};
+
+/// Tokens and estimated cost spent by a session or tenant.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct Spend {
+    pub(crate) usage: Usage,
+    /// Summed over turns whose model has a known price.
+    pub(crate) cost_usd: f64,
+}
+
+impl Spend {
+    pub(crate) fn add(&mut self, model: &str, usage: Usage) {
+        self.usage += usage;
+        self.cost_usd += pricing::cost_usd(model, usage).unwrap_or(0.0);
+    }
+
+    pub(crate) fn tokens(&self) -> u64 {
+        self.usage.input_tokens + self.usage.output_tokens
+    }
+
+    pub(crate) fn to_json(self) -> Value {
+        json!({
+            "input_tokens": self.usage.input_tokens,
+            "output_tokens": self.usage.output_tokens,
+            "cost_usd": self.cost_usd,
+        })
+    }
+}
+
+/// When a `Session` moves to a cheaper model: after its estimated cost passes
+/// `max_cost_usd` or its tokens pass `max_tokens`, whichever comes first.
+#[pyclass(frozen)]
+pub struct DowngradePolicy {
+    /// A model object, or a model name resolved like `Session.set_model`.
+    #[pyo3(get)]
+    pub(crate) to: Py<PyAny>,
+    #[pyo3(get)]
+    max_cost_usd: Option<f64>,
+    #[pyo3(get)]
+    max_tokens: Option<u64>,
+}
+
+impl DowngradePolicy {
+    /// Why `spend` calls for the switch, or `None` while it is within the limits.
+    pub(crate) fn reason(&self, spend: &Spend) -> Option<String> {
+        if let Some(limit) = self.max_cost_usd.filter(|limit| spend.cost_usd > *limit) {
+            return Some(format!(
+                "cost ${:.4} exceeded max_cost_usd={}",
+                spend.cost_usd, limit
+            ));
+        }
+        self.max_tokens
+            .filter(|limit| spend.tokens() > *limit)
+            .map(|limit| format!("{} tokens exceeded max_tokens={}", spend.tokens(), limit))
+    }
+}
+
+#[pymethods]
+impl DowngradePolicy {
+    #[new]
+    #[pyo3(signature = (to, max_cost_usd=None, max_tokens=None))]
+    fn new(to: Py<PyAny>, max_cost_usd: Option<f64>, max_tokens: Option<u64>) -> PyResult<Self> {
+        if max_cost_usd.is_none() && max_tokens.is_none() {
+            return Err(PyValueError::new_err(
+                "DowngradePolicy needs max_cost_usd, max_tokens or both",
+            ));
+        }
+        Ok(DowngradePolicy {
+            to,
+            max_cost_usd,
+            max_tokens,
+        })
+    }
+
+    fn __repr__(&self, py: Python) -> PyResult<String> {
+        let mut limits = Vec::new();
+        if let Some(cost) = self.max_cost_usd {
+            limits.push(format!("max_cost_usd={}", cost));
+        }
+        if let Some(tokens) = self.max_tokens {
+            limits.push(format!("max_tokens={}", tokens));
+        }
+        Ok(format!(
+            "DowngradePolicy(to={}, {})",
+            self.to.bind(py).repr()?,
+            limits.join(", ")
+        ))
+    }
+}
@@ -0,0 +1,85 @@
+//! Dry runs: the request a call would send, built by its provider client as usual and
+//! taken at the point where it would go out, so nothing reaches the network.
+
+use crate::agent::{ChatClient, Usage};
+use crate::error::RustedChainError;
+use crate::{pricing, splitter};
+use reqwest::{Request, RequestBuilder};
+use serde_json::{json, Map, Value};
+use std::cell::RefCell;
+use std::future::Future;
+
+/// Sent in place of the API key, so a dry run neither takes a key nor shows one.
+pub(crate) const API_KEY: &str = "<api-key>";
+
+tokio::task_local! {
+    /// The request built inside a `captured` future.
+    static CAPTURED: RefCell<Option<Request>>;
+}
+
+/// Whether requests are being captured rather than sent.
+pub(crate) fn active() -> bool {
+    CAPTURED.try_with(|_| ()).is_ok()
+}
+
+/// Keep `request` as the one the dry run would send. Returns the error that stops the
+/// client before it sends anything.
+pub(crate) fn capture(request: RequestBuilder) -> RustedChainError {
+    match request.build() {
+        Ok(request) => {
+            let _ = CAPTURED.try_with(|captured| *captured.borrow_mut() = Some(request));
+            RustedChainError::Cancelled
+        }
+        Err(e) => e.into(),
+    }
+}
+
+/// Run `future` with its requests captured, returning the first one it built.
+pub(crate) async fn captured<F, T>(future: F) -> Result<Request, RustedChainError>
+where
+    F: Future<Output = Result<T, RustedChainError>>,
+{
+    CAPTURED
+        .scope(RefCell::new(None), async {
+            let result = future.await;
+            match CAPTURED.with(|captured| captured.borrow_mut().take()) {
+                Some(request) => Ok(request),
+                None => Err(result.err().unwrap_or(RustedChainError::NoResponse)),
+            }
+        })
+        .await
+}
+
+/// `request` as a dry run returns it, with an estimate of its input tokens and their
+/// cost with `client`'s model.
+pub(crate) fn describe<C: ChatClient>(client: &C, request: &Request) -> Value {
+    let bytes = request
+        .body()
+        .and_then(|body| body.as_bytes())
+        .unwrap_or_default();
+    let text = String::from_utf8_lossy(bytes);
+    let body = serde_json::from_str(&text).unwrap_or_else(|_| Value::String(text.to_string()));
+    let headers: Map<String, Value> = request
+        .headers()
+        .iter()
+        .map(|(name, value)| {
+            let value = String::from_utf8_lossy(value.as_bytes()).to_string();
+            (name.to_string(), Value::String(value))
+        })
+        .collect();
+    let input_tokens = splitter::count_tokens(&text) as u64;
+    let usage = Usage {
+        input_tokens,
+        output_tokens: 0,
+    };
+    json!({
+        "provider": client.provider(),
+        "model": client.model(),
+        "method": request.method().as_str(),
+        "url": request.url().as_str(),
+        "headers": headers,
+        "body": body,
+        "input_tokens": input_tokens,
+        "cost_usd": pricing::cost_usd(client.model(), usage),
+    })
+}
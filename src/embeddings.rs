@@ -0,0 +1,159 @@
+//! Text embeddings from OpenAI or Gemini, for vector search.
+
+use crate::error::{retry_after_header, RustedChainError};
+use crate::retry::RetryPolicy;
+use crate::{HTTP, RUNTIME};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::json;
+use std::env;
+
+/// Inputs per request; both providers cap batch sizes.
+const BATCH: usize = 96;
+
+#[derive(Clone, Copy)]
+enum Provider {
+    OpenAI,
+    Gemini,
+}
+
+#[derive(Deserialize)]
+struct OpenAIEmbedding {
+    embedding: Vec<f32>,
+}
+
+#[derive(Deserialize)]
+struct OpenAIResponse {
+    data: Vec<OpenAIEmbedding>,
+}
+
+#[derive(Deserialize)]
+struct GeminiEmbedding {
+    values: Vec<f32>,
+}
+
+#[derive(Deserialize)]
+struct GeminiResponse {
+    embeddings: Vec<GeminiEmbedding>,
+}
+
+/// An embedding model.
+#[pyclass]
+pub struct Embeddings {
+    provider: Provider,
+    #[pyo3(get)]
+    model: String,
+    api_key: String,
+    client: Client,
+    retry: RetryPolicy,
+}
+
+#[pymethods]
+impl Embeddings {
+    /// `provider` is `"openai"` (default model `text-embedding-3-small`) or `"gemini"`
+    /// (`gemini-embedding-001`). The API key defaults to `OPENAI_API_KEY` or
+    /// `GEMINI_API_KEY`.
+    #[new]
+    #[pyo3(signature = (provider="openai", model=None, api_key=None))]
+    fn new(provider: &str, model: Option<String>, api_key: Option<String>) -> PyResult<Self> {
+        dotenv::dotenv().ok();
+        let (provider, default_model, key_var) = match provider {
+            "openai" => (Provider::OpenAI, "text-embedding-3-small", "OPENAI_API_KEY"),
+            "gemini" => (Provider::Gemini, "gemini-embedding-001", "GEMINI_API_KEY"),
+            other => {
+                return Err(PyValueError::new_err(format!(
+                    "provider must be 'openai' or 'gemini', got '{}'",
+                    other
+                )))
+            }
+        };
+        Ok(Embeddings {
+            provider,
+            model: model.unwrap_or_else(|| default_model.to_string()),
+            api_key: api_key.unwrap_or_else(|| env::var(key_var).unwrap_or_default()),
+            client: HTTP.clone(),
+            retry: RetryPolicy::default(),
+        })
+    }
+
+    /// Embed each text, returning one vector per input.
+    fn embed(&self, py: Python, texts: Vec<String>) -> PyResult<Vec<Vec<f32>>> {
+        Ok(py.detach(|| RUNTIME.block_on(self.embed_all(&texts)))?)
+    }
+}
+
+impl Embeddings {
+    pub(crate) async fn embed_all(
+        &self,
+        texts: &[String],
+    ) -> Result<Vec<Vec<f32>>, RustedChainError> {
+        let mut vectors = Vec::with_capacity(texts.len());
+        for batch in texts.chunks(BATCH) {
+            vectors.extend(self.embed_batch(batch).await?);
+        }
+        Ok(vectors)
+    }
+
+    async fn embed_batch(&self, texts: &[String]) -> Result<Vec<Vec<f32>>, RustedChainError> {
+        let (url, body) = match self.provider {
+            Provider::OpenAI => (
+                "https://api.openai.com/v1/embeddings".to_string(),
+                json!({ "model": self.model, "input": texts }),
+            ),
+            Provider::Gemini => {
+                let model = format!("models/{}", self.model);
+                let requests: Vec<_> = texts
+                    .iter()
+                    .map(|text| json!({ "model": model, "content": { "parts": [{ "text": text }] } }))
+                    .collect();
+                (
+                    format!(
+                        "https://generativelanguage.googleapis.com/v1beta/{}:batchEmbedContents?key={}",
+                        model, self.api_key
+                    ),
+                    json!({ "requests": requests }),
+                )
+            }
+        };
+
+        let response = self
+            .retry
+            .run(|| async {
+                let mut request = self.client.post(&url).json(&body);
+                if let Provider::OpenAI = self.provider {
+                    request = request.bearer_auth(&self.api_key);
+                }
+                let response = request.send().await?;
+                if !response.status().is_success() {
+                    let status = response.status();
+                    let retry_after = retry_after_header(response.headers());
+                    let text = response.text().await.unwrap_or_default();
+                    return Err(RustedChainError::from_response(status, retry_after, &text));
+                }
+                Ok(response)
+            })
+            .await?;
+
+        let parse = |e: reqwest::Error| RustedChainError::ParseError(e.to_string());
+        let vectors: Vec<Vec<f32>> = match self.provider {
+            Provider::OpenAI => {
+                let body: OpenAIResponse = response.json().await.map_err(parse)?;
+                body.data.into_iter().map(|d| d.embedding).collect()
+            }
+            Provider::Gemini => {
+                let body: GeminiResponse = response.json().await.map_err(parse)?;
+                body.embeddings.into_iter().map(|e| e.values).collect()
+            }
+        };
+        if vectors.len() != texts.len() {
+            return Err(RustedChainError::ParseError(format!(
+                "expected {} embeddings, got {}",
+                texts.len(),
+                vectors.len()
+            )));
+        }
+        Ok(vectors)
+    }
+}
@@ -1,12 +1,47 @@
 //! Errors that bubble up through the Python bindings.
 
+use pyo3::create_exception;
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::types::PyAnyMethods;
+use serde_json::{json, Value};
 use thiserror::Error;
 
+create_exception!(rusted_chain, ProviderError, PyRuntimeError);
+create_exception!(rusted_chain, RateLimitError, ProviderError);
+create_exception!(rusted_chain, OverloadedError, ProviderError);
+create_exception!(rusted_chain, ContextLengthError, ProviderError);
+create_exception!(rusted_chain, ContentFilterError, ProviderError);
+create_exception!(rusted_chain, InvalidRequestError, ProviderError);
+create_exception!(rusted_chain, ProviderUnavailableError, ProviderError);
+create_exception!(rusted_chain, ToolLoopError, PyRuntimeError);
+create_exception!(rusted_chain, BudgetExceededError, PyRuntimeError);
+
 #[derive(Error, Debug)]
 pub enum RustedChainError {
     #[error("API error {status}: {message}")]
     Api { status: u16, message: String },
 
+    #[error("Rate limited{}: {message}", .retry_after.map(|s| format!(" (retry after {:.1}s)", s)).unwrap_or_default())]
+    RateLimited {
+        retry_after: Option<f64>,
+        message: String,
+    },
+
+    #[error("Provider overloaded: {0}")]
+    Overloaded(String),
+
+    #[error("Context length exceeded: {0}")]
+    ContextLengthExceeded(String),
+
+    #[error("Content filtered: {0}")]
+    ContentFiltered(String),
+
+    #[error("Content filtered: {}", .0.message)]
+    Blocked(Box<ContentBlock>),
+
+    #[error("Invalid request: {0}")]
+    InvalidRequest(String),
+
     #[error("Network error: {0}")]
     Network(#[from] reqwest::Error),
 
@@ -19,11 +54,65 @@ pub enum RustedChainError {
     #[error("Max iterations ({0}) reached without getting a final answer")]
     MaxIterations(usize),
 
+    #[error("Tool '{name}' was called {count} times with identical arguments")]
+    ToolLoop { name: String, count: usize },
+
     #[error("Tool '{0}' was requested but invoke() only supports tool schemas. Use run_with_tools(query, tools_dict) to provide executable tool functions.")]
     ToolExecutionNotSupported(String),
 
+    #[error("Vector store error: {0}")]
+    Store(String),
+
     #[error("No valid response from API")]
     NoResponse,
+
+    #[error("Request cancelled")]
+    Cancelled,
+
+    #[error("No response within first_token_deadline_ms={0}")]
+    FirstTokenDeadline(u64),
+
+    #[error("Provider '{provider}' unavailable: circuit breaker open after {failures} failures in a row, retrying in {retry_in:.1}s")]
+    ProviderUnavailable {
+        provider: String,
+        failures: u32,
+        retry_in: f64,
+    },
+
+    #[error("Could not get an API key: {0}")]
+    Credentials(String),
+
+    #[error("Budget exceeded: {0}")]
+    BudgetExceeded(String),
+
+    #[error("rusted_chain has been shut down and accepts no new calls")]
+    ShutDown,
+
+    #[error("Stream consumer fell behind: its buffer of {0} chunks is full")]
+    StreamOverflow(usize),
+}
+
+/// What Gemini reported when it withheld a response: why the prompt was blocked, or
+/// why the answer was stopped, with the safety ratings behind it.
+#[derive(Debug)]
+pub struct ContentBlock {
+    pub message: String,
+    /// `promptFeedback.blockReason`, when the prompt itself was blocked.
+    pub block_reason: Option<String>,
+    /// The candidate's `finishReason`, such as `SAFETY` or `RECITATION`.
+    pub finish_reason: Option<String>,
+    pub prompt_feedback: Option<Value>,
+    pub safety_ratings: Vec<Value>,
+}
+
+impl ContentBlock {
+    /// Whether looser safety settings could let the response through; recitation and
+    /// blocklist hits are not decided by them.
+    pub fn is_safety(&self) -> bool {
+        [&self.block_reason, &self.finish_reason]
+            .into_iter()
+            .any(|reason| reason.as_deref() == Some("SAFETY"))
+    }
 }
 
 impl RustedChainError {
@@ -33,6 +122,132 @@ impl RustedChainError {
             message,
         }
     }
+
+    /// Classify a non-2xx provider response. OpenAI, Anthropic and Gemini wrap failures
+    /// in an `{"error": {...}}` object, so we look at its message/type/code/status fields
+    /// rather than only the HTTP status. HuggingFace sends `{"error": "message"}`.
+    pub fn from_response(
+        status: reqwest::StatusCode,
+        retry_after_header: Option<f64>,
+        body: &str,
+    ) -> Self {
+        let parsed: Value = serde_json::from_str(body).unwrap_or(Value::Null);
+        let error = parsed.get("error").unwrap_or(&Value::Null);
+        let field = |key: &str| {
+            error
+                .get(key)
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_lowercase()
+        };
+        let message = error
+            .get("message")
+            .and_then(Value::as_str)
+            .or_else(|| error.as_str())
+            .map(str::to_string)
+            .unwrap_or_else(|| body.to_string());
+        let kind = format!("{} {} {}", field("type"), field("code"), field("status"));
+        let lowered = message.to_lowercase();
+
+        const CONTEXT_MARKERS: &[&str] = &[
+            "context_length_exceeded",
+            "maximum context length",
+            "prompt is too long",
+            "exceeds the maximum number of tokens",
+            "input token count",
+            "`inputs` tokens + `max_new_tokens`",
+        ];
+        if CONTEXT_MARKERS
+            .iter()
+            .any(|m| kind.contains(m) || lowered.contains(m))
+        {
+            return Self::ContextLengthExceeded(message);
+        }
+
+        if kind.contains("content_filter") || kind.contains("content_policy_violation") {
+            return Self::ContentFiltered(message);
+        }
+
+        match status.as_u16() {
+            429 if kind.contains("insufficient_quota") => Self::api_error(status, message),
+            429 => Self::RateLimited {
+                retry_after: retry_after_header.or_else(|| gemini_retry_delay(error)),
+                message,
+            },
+            503 | 529 => Self::Overloaded(message),
+            _ if kind.contains("overloaded") || kind.contains("unavailable") => {
+                Self::Overloaded(message)
+            }
+            400 | 404 | 413 | 422 => Self::InvalidRequest(message),
+            _ if kind.contains("invalid_request") || kind.contains("invalid_argument") => {
+                Self::InvalidRequest(message)
+            }
+            _ => Self::api_error(status, message),
+        }
+    }
+
+    /// Whether the retry layer should try the request again.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::RateLimited { .. } | Self::Overloaded(_) => true,
+            Self::Network(e) => e.is_timeout() || e.is_connect() || e.is_request(),
+            Self::Api { status, .. } => *status >= 500,
+            _ => false,
+        }
+    }
+
+    /// Short machine-readable label, used as the `status` of metrics and log records.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Api { .. } => "api_error",
+            Self::RateLimited { .. } => "rate_limited",
+            Self::Overloaded(_) => "overloaded",
+            Self::ContextLengthExceeded(_) => "context_length_exceeded",
+            Self::ContentFiltered(_) | Self::Blocked(_) => "content_filtered",
+            Self::InvalidRequest(_) => "invalid_request",
+            Self::Network(_) => "network_error",
+            Self::ParseError(_) => "parse_error",
+            Self::ToolNotFound(_) => "tool_not_found",
+            Self::MaxIterations(_) => "max_iterations",
+            Self::ToolLoop { .. } => "tool_loop",
+            Self::ToolExecutionNotSupported(_) => "tool_execution_not_supported",
+            Self::Store(_) => "store_error",
+            Self::NoResponse => "no_response",
+            Self::Cancelled => "cancelled",
+            Self::FirstTokenDeadline(_) => "first_token_deadline",
+            Self::ProviderUnavailable { .. } => "provider_unavailable",
+            Self::Credentials(_) => "credentials_error",
+            Self::BudgetExceeded(_) => "budget_exceeded",
+            Self::ShutDown => "shut_down",
+            Self::StreamOverflow(_) => "stream_overflow",
+        }
+    }
+
+    /// Server-suggested delay before retrying, in seconds.
+    pub fn retry_after(&self) -> Option<f64> {
+        match self {
+            Self::RateLimited { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+/// Gemini reports the suggested delay as a `google.rpc.RetryInfo` detail, e.g. `"retryDelay": "31s"`.
+fn gemini_retry_delay(error: &Value) -> Option<f64> {
+    error
+        .get("details")?
+        .as_array()?
+        .iter()
+        .filter_map(|d| d.get("retryDelay").and_then(Value::as_str))
+        .find_map(|d| d.trim_end_matches('s').parse().ok())
+}
+
+/// Parse a `Retry-After` header given in seconds.
+pub(crate) fn retry_after_header(headers: &reqwest::header::HeaderMap) -> Option<f64> {
+    headers
+        .get(reqwest::header::RETRY_AFTER)
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.trim().parse().ok())
 }
 
 impl From<RustedChainError> for pyo3::PyErr {
@@ -44,6 +259,40 @@ impl From<RustedChainError> for pyo3::PyErr {
             RustedChainError::ToolExecutionNotSupported(_) => {
                 pyo3::PyErr::new::<pyo3::exceptions::PyNotImplementedError, _>(err.to_string())
             }
+            RustedChainError::RateLimited { .. } => RateLimitError::new_err(err.to_string()),
+            RustedChainError::Overloaded(_) => OverloadedError::new_err(err.to_string()),
+            RustedChainError::ContextLengthExceeded(_) => {
+                ContextLengthError::new_err(err.to_string())
+            }
+            RustedChainError::ContentFiltered(_) => ContentFilterError::new_err(err.to_string()),
+            RustedChainError::Blocked(block) => {
+                let py_err = ContentFilterError::new_err(err.to_string());
+                pyo3::Python::attach(|py| {
+                    let value = py_err.value(py);
+                    let attributes = [
+                        ("block_reason", json!(block.block_reason)),
+                        ("finish_reason", json!(block.finish_reason)),
+                        ("prompt_feedback", json!(block.prompt_feedback)),
+                        ("safety_ratings", json!(block.safety_ratings)),
+                    ];
+                    for (name, attribute) in attributes {
+                        if let Ok(attribute) = pythonize::pythonize(py, &attribute) {
+                            let _ = value.setattr(name, attribute);
+                        }
+                    }
+                });
+                py_err
+            }
+            RustedChainError::InvalidRequest(_) => InvalidRequestError::new_err(err.to_string()),
+            RustedChainError::ProviderUnavailable { .. } => {
+                ProviderUnavailableError::new_err(err.to_string())
+            }
+            RustedChainError::Api { .. } => ProviderError::new_err(err.to_string()),
+            RustedChainError::ToolLoop { .. } => ToolLoopError::new_err(err.to_string()),
+            RustedChainError::BudgetExceeded(_) => BudgetExceededError::new_err(err.to_string()),
+            RustedChainError::FirstTokenDeadline(_) => {
+                pyo3::PyErr::new::<pyo3::exceptions::PyTimeoutError, _>(err.to_string())
+            }
             _ => pyo3::PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(err.to_string()),
         }
     }
@@ -55,4 +304,3 @@ impl From<String> for RustedChainError {
         RustedChainError::ParseError(s)
     }
 }
-
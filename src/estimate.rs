@@ -0,0 +1,133 @@
+//! Offline estimates of what a request will cost: prompt tokens counted with a local
+//! tokenizer, priced with the published list prices in `pricing`.
+//!
+//! Counts use OpenAI's tokenizers, so for Claude, Gemini and Hugging Face models they
+//! are approximate.
+
+use crate::agent::{ChatClient, Usage};
+use crate::client::{with_client, AnyClient};
+use crate::{convert_tools, pricing, splitter};
+use pyo3::exceptions::PyTypeError;
+use pyo3::prelude::*;
+use pyo3::types::{PyList, PyString};
+use serde_json::Value;
+
+/// Tokens a chat format adds around every message (role and delimiters).
+const MESSAGE_OVERHEAD: u64 = 4;
+/// Tokens that prime the model's reply.
+const REPLY_OVERHEAD: u64 = 3;
+
+/// The estimated prompt size and cost range of a request. The cost is bounded below
+/// by the prompt alone and above by the prompt plus `max_output_tokens` of answer.
+#[pyclass(frozen, get_all)]
+#[derive(Clone)]
+pub struct Estimate {
+    pub model: String,
+    pub prompt_tokens: u64,
+    pub max_output_tokens: u64,
+    /// `None` for models without a known price.
+    pub min_cost_usd: Option<f64>,
+    pub max_cost_usd: Option<f64>,
+}
+
+impl Estimate {
+    pub(crate) fn new(model: &str, prompt_tokens: u64, max_output_tokens: u64) -> Self {
+        let cost = |output_tokens| {
+            let usage = Usage {
+                input_tokens: prompt_tokens,
+                output_tokens,
+            };
+            pricing::cost_usd(model, usage)
+        };
+        Estimate {
+            model: model.to_string(),
+            prompt_tokens,
+            max_output_tokens,
+            min_cost_usd: cost(0),
+            max_cost_usd: cost(max_output_tokens),
+        }
+    }
+}
+
+#[pymethods]
+impl Estimate {
+    fn __repr__(&self) -> String {
+        let cost = |cost: Option<f64>| cost.map_or("None".to_string(), |c| format!("{:.6}", c));
+        format!(
+            "Estimate(model='{}', prompt_tokens={}, max_output_tokens={}, min_cost_usd={}, max_cost_usd={})",
+            self.model,
+            self.prompt_tokens,
+            self.max_output_tokens,
+            cost(self.min_cost_usd),
+            cost(self.max_cost_usd)
+        )
+    }
+}
+
+/// Tokens in a prompt of `system`, `messages` and `tools` for `model`. Only the text in
+/// a message counts, plus a fixed overhead per message.
+pub(crate) fn prompt_tokens<'a>(
+    model: &str,
+    system: Option<&str>,
+    messages: impl IntoIterator<Item = &'a Value>,
+    tools: &[Value],
+) -> u64 {
+    let bpe = splitter::tokenizer(model);
+    let count = |text: &str| bpe.encode_ordinary(text).len() as u64;
+    let mut tokens = REPLY_OVERHEAD;
+    if let Some(system) = system {
+        tokens += MESSAGE_OVERHEAD + count(system);
+    }
+    for message in messages {
+        tokens += MESSAGE_OVERHEAD + text_tokens(message, &count);
+    }
+    if !tools.is_empty() {
+        tokens += count(&serde_json::to_string(tools).unwrap_or_default());
+    }
+    tokens
+}
+
+/// Tokens in every string in `value`, leaving out object keys.
+fn text_tokens(value: &Value, count: &impl Fn(&str) -> u64) -> u64 {
+    match value {
+        Value::String(text) => count(text),
+        Value::Array(items) => items.iter().map(|item| text_tokens(item, count)).sum(),
+        Value::Object(fields) => fields.values().map(|field| text_tokens(field, count)).sum(),
+        _ => 0,
+    }
+}
+
+/// Estimate the prompt tokens and cost of sending `query_or_messages`, a query or a list
+/// of `{"role", "content"}` dicts, to `model`, a model name or a model object. A model
+/// object's system prompt and tools are counted too. Nothing is sent.
+#[pyfunction]
+#[pyo3(signature = (query_or_messages, model, max_output_tokens=4096))]
+pub fn estimate(
+    py: Python,
+    query_or_messages: &Bound<'_, PyAny>,
+    model: &Bound<'_, PyAny>,
+    max_output_tokens: u64,
+) -> PyResult<Estimate> {
+    let messages: Vec<Value> = if let Ok(query) = query_or_messages.cast::<PyString>() {
+        vec![serde_json::json!({ "role": "user", "content": query.to_str()? })]
+    } else if query_or_messages.is_instance_of::<PyList>() {
+        pythonize::depythonize(query_or_messages)?
+    } else {
+        return Err(PyTypeError::new_err(
+            "estimate takes a query or a list of message dicts",
+        ));
+    };
+    let (model, system, tools) = match model.extract::<String>() {
+        Ok(name) => (name, None, Vec::new()),
+        Err(_) => {
+            let (client, _, tools) = AnyClient::from_agent(py, model)?;
+            with_client!(&client, client => (
+                client.model().to_string(),
+                client.system().map(str::to_string),
+                convert_tools(py, &tools),
+            ))
+        }
+    };
+    let tokens = py.detach(|| prompt_tokens(&model, system.as_deref(), &messages, &tools));
+    Ok(Estimate::new(&model, tokens, max_output_tokens))
+}
@@ -0,0 +1,128 @@
+//! Complete agent runs as JSON lines in a stable, versioned schema, so runs can move
+//! between the run log, evaluation scripts and `Replay`.
+//!
+//! Every record carries `"schema": "rusted_chain.run/1"`. Fields may be added without
+//! a version change; readers ignore the ones they do not know. Removing or changing a
+//! field bumps the version, and records from a newer version are refused.
+
+use crate::artifacts::Artifact;
+use crate::roles;
+use crate::run::RunRecord;
+use crate::session::Entry;
+use chrono::{DateTime, SecondsFormat, Utc};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyList;
+use serde_json::{json, Map, Value};
+
+const SCHEMA_PREFIX: &str = "rusted_chain.run/";
+const VERSION: u64 = 1;
+
+/// The export of `run`, which had the conversation `messages`, stored `artifacts` and
+/// ended with `answer` or `error`.
+pub(crate) fn run_record(
+    run: &RunRecord,
+    messages: &[Entry],
+    artifacts: &[Artifact],
+    answer: Option<&str>,
+    error: Option<&str>,
+) -> Value {
+    let by_model: Vec<Value> = run
+        .usage_by_model()
+        .into_iter()
+        .map(|(model, usage)| {
+            json!({
+                "model": model,
+                "input_tokens": usage.input_tokens,
+                "output_tokens": usage.output_tokens,
+            })
+        })
+        .collect();
+    let tool_usage: Map<String, Value> = run
+        .tool_usage
+        .iter()
+        .map(|(name, tally)| (name.clone(), tally.to_json()))
+        .collect();
+    json!({
+        "schema": format!("{}{}", SCHEMA_PREFIX, VERSION),
+        "run_id": run.id,
+        "provider": run.provider,
+        "model": run.model,
+        "tool_model": run.tool_model_usage.as_ref().map(|(model, _)| model),
+        "stream": run.stream,
+        "tags": run.labels.tags,
+        "metadata": run.labels.metadata,
+        "query": run.query,
+        "tools": run.tools,
+        "messages": messages.iter().map(Entry::to_json).collect::<Vec<_>>(),
+        "answer": answer,
+        "original_answer": run.original_answer,
+        "error": error,
+        "usage": {
+            "input_tokens": run.usage.input_tokens,
+            "output_tokens": run.usage.output_tokens,
+            "by_model": by_model,
+            "cost_usd": run.cost_usd(),
+        },
+        "timings": {
+            "started_at": DateTime::<Utc>::from(run.started_at)
+                .to_rfc3339_opts(SecondsFormat::Millis, true),
+            "latency_ms": run.elapsed().as_millis() as u64,
+            "time_to_first_token_ms": run.time_to_first_token.map(|t| t.as_millis() as u64),
+            "tokens_per_second": run.tokens_per_second,
+        },
+        "model_calls": run.model_calls,
+        "tool_calls": run.tool_calls,
+        "tool_usage": tool_usage,
+        "artifacts": artifacts.iter().map(Artifact::to_json).collect::<Vec<_>>(),
+    })
+}
+
+/// Whether `record` is a run export rather than, say, an audit record.
+pub(crate) fn is_run_record(record: &Value) -> bool {
+    record["schema"]
+        .as_str()
+        .is_some_and(|schema| schema.starts_with(SCHEMA_PREFIX))
+}
+
+/// Refuse records this version cannot read.
+pub(crate) fn check(record: &Value) -> Result<(), String> {
+    let Some(schema) = record["schema"].as_str() else {
+        return Err("not a run record: missing 'schema'".to_string());
+    };
+    let version = schema
+        .strip_prefix(SCHEMA_PREFIX)
+        .and_then(|version| version.parse::<u64>().ok())
+        .ok_or_else(|| format!("unknown schema '{}'", schema))?;
+    if version > VERSION {
+        return Err(format!(
+            "schema '{}' was written by a newer rusted_chain; this one reads up to {}{}",
+            schema, SCHEMA_PREFIX, VERSION
+        ));
+    }
+    if record["run_id"].as_str().is_none() || !record["messages"].is_array() {
+        return Err(format!("{} record without 'run_id' or 'messages'", schema));
+    }
+    messages(record).map(|_| ())
+}
+
+/// The conversation of a run record.
+pub(crate) fn messages(record: &Value) -> Result<Vec<Entry>, String> {
+    let messages = record["messages"].as_array().map_or(&[][..], Vec::as_slice);
+    roles::import(messages)
+}
+
+/// Read run records from a JSONL file (as written by `run_log`) or a list of dicts
+/// (such as `AgentResponse.to_run()` results), checking each against the schema.
+/// Feedback recorded in the file is attached to its run as `feedback`. Records from a
+/// newer, incompatible version raise `ValueError`.
+#[pyfunction]
+pub fn load_runs<'py>(py: Python<'py>, source: &Bound<'py, PyAny>) -> PyResult<Bound<'py, PyList>> {
+    let records = crate::feedback::fold(crate::replay::read_records(source)?);
+    let list = PyList::empty(py);
+    for (index, record) in records.iter().enumerate() {
+        check(record).map_err(|e| PyValueError::new_err(format!("run {}: {}", index + 1, e)))?;
+        list.append(pythonize::pythonize(py, record)?)?;
+    }
+    Ok(list)
+}
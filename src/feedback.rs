@@ -0,0 +1,112 @@
+//! Feedback on finished runs, such as a user's rating of an answer. It is written next
+//! to the run in the run and audit logs, attached to the run in the trace viewer and
+//! exports, and sent to the experiment trackers the run was uploaded to.
+
+use crate::audit::AuditSink;
+use crate::{traces, tracking};
+use chrono::{SecondsFormat, Utc};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+const SCHEMA: &str = "rusted_chain.feedback/1";
+
+/// Feedback on one run, given through its response.
+pub(crate) struct Feedback {
+    run_id: String,
+    run_log: Option<Arc<AuditSink>>,
+    audit: Option<Arc<AuditSink>>,
+    /// What has been recorded so far, oldest first.
+    given: Mutex<Vec<Value>>,
+}
+
+impl Feedback {
+    pub(crate) fn new(
+        run_id: &str,
+        run_log: Option<Arc<AuditSink>>,
+        audit: Option<Arc<AuditSink>>,
+    ) -> Self {
+        Self {
+            run_id: run_id.to_string(),
+            run_log,
+            audit,
+            given: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Record `score` and an optional `comment` for the run.
+    pub(crate) fn record(&self, score: f64, comment: Option<String>) {
+        let recorded_at = Utc::now().to_rfc3339_opts(SecondsFormat::Millis, true);
+        let entry = json!({ "score": score, "comment": comment, "recorded_at": recorded_at });
+        let record = json!({
+            "schema": SCHEMA,
+            "run_id": self.run_id,
+            "score": score,
+            "comment": comment,
+            "recorded_at": recorded_at,
+        });
+        for sink in self.run_log.iter().chain(&self.audit) {
+            sink.write(&record);
+        }
+        traces::add_feedback(&self.run_id, &entry);
+        tracking::log_feedback(&self.run_id, score, comment.as_deref());
+        self.given
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .push(entry);
+    }
+
+    /// The feedback recorded so far, as a run record's `feedback` field.
+    pub(crate) fn given(&self) -> Vec<Value> {
+        self.given.lock().unwrap_or_else(|e| e.into_inner()).clone()
+    }
+}
+
+/// Whether `record` is feedback rather than a run or an audit record.
+pub(crate) fn is_feedback(record: &Value) -> bool {
+    record["schema"] == SCHEMA
+}
+
+/// `records` with feedback records taken out and added, in order, to the `feedback`
+/// of the run they are about. Feedback on runs not among `records` is dropped.
+pub(crate) fn fold(records: Vec<Value>) -> Vec<Value> {
+    let mut feedback: HashMap<String, Vec<Value>> = HashMap::new();
+    let (given, mut runs): (Vec<Value>, Vec<Value>) = records.into_iter().partition(is_feedback);
+    for mut record in given {
+        let Some(run_id) = record["run_id"].as_str().map(str::to_string) else {
+            continue;
+        };
+        if let Some(fields) = record.as_object_mut() {
+            fields.remove("schema");
+            fields.remove("run_id");
+        }
+        feedback.entry(run_id).or_default().push(record);
+    }
+    for run in &mut runs {
+        let given = run["run_id"]
+            .as_str()
+            .and_then(|run_id| feedback.remove(run_id));
+        if let Some(given) = given {
+            add(run, given);
+        }
+    }
+    runs
+}
+
+/// Add `given` to the `feedback` list of the run record `run`.
+pub(crate) fn add(run: &mut Value, given: Vec<Value>) {
+    match run.get_mut("feedback").and_then(Value::as_array_mut) {
+        Some(feedback) => feedback.extend(given),
+        None => run["feedback"] = Value::Array(given),
+    }
+}
+
+/// The latest score a run record was given.
+pub(crate) fn latest_score(run: &Value) -> Option<f64> {
+    run["feedback"].as_array().and_then(|feedback| {
+        feedback
+            .iter()
+            .rev()
+            .find_map(|entry| entry["score"].as_f64())
+    })
+}
@@ -0,0 +1,158 @@
+//! Recorded conversations as fine-tuning data: the chat-format JSONL OpenAI and Gemini
+//! tuning jobs take, one example per successful run or session.
+
+use crate::export;
+use crate::feedback;
+use crate::roles;
+use crate::session::{Entry, Session};
+use crate::AgentResponse;
+use pyo3::exceptions::{PyOSError, PyValueError};
+use pyo3::prelude::*;
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::PathBuf;
+
+/// A conversation to train on, with the rating it was given, if any.
+struct Example {
+    entries: Vec<Entry>,
+    rating: Option<f64>,
+}
+
+impl Example {
+    /// A finished run record; failed runs and other records give none.
+    fn from_record(record: &Value) -> Result<Option<Self>, String> {
+        if !export::is_run_record(record) {
+            return Ok(None);
+        }
+        export::check(record)?;
+        let Some(answer) = record["answer"]
+            .as_str()
+            .filter(|_| record["error"].is_null())
+        else {
+            return Ok(None);
+        };
+        let mut entries = export::messages(record)?;
+        if !matches!(entries.last(), Some(Entry::Assistant(_))) {
+            entries.push(Entry::Assistant(answer.to_string()));
+        }
+        Ok(Some(Self {
+            entries,
+            rating: rating(record),
+        }))
+    }
+
+    fn from_py(item: &Bound<'_, PyAny>) -> PyResult<Option<Self>> {
+        if let Ok(session) = item.cast::<Session>() {
+            return Ok(Some(Self {
+                entries: session.borrow().entries().to_vec(),
+                rating: None,
+            }));
+        }
+        let record: Value = if item.is_instance_of::<AgentResponse>() {
+            pythonize::depythonize(&item.call_method0("to_run")?)?
+        } else {
+            pythonize::depythonize(item)?
+        };
+        Self::from_record(&record).map_err(PyValueError::new_err)
+    }
+
+    /// The example in `format`, or `None` if it has no answer to learn from.
+    fn to_json(&self, format: &str, system: Option<&str>) -> Result<Option<Value>, String> {
+        if !matches!(self.entries.last(), Some(Entry::Assistant(_))) {
+            return Ok(None);
+        }
+        let messages = roles::export(&self.entries, format)?;
+        Ok(Some(match format {
+            "gemini" => {
+                let mut example = json!({ "contents": messages });
+                if let Some(system) = system {
+                    example["systemInstruction"] =
+                        json!({ "role": "system", "parts": [{ "text": system }] });
+                }
+                example
+            }
+            _ => {
+                let system = system.map(|system| json!({ "role": "system", "content": system }));
+                json!({ "messages": system.into_iter().chain(messages).collect::<Vec<_>>() })
+            }
+        }))
+    }
+}
+
+/// The rating a run was given: its latest feedback score, else a numeric `rating` in
+/// its metadata.
+fn rating(record: &Value) -> Option<f64> {
+    feedback::latest_score(record).or_else(|| record["metadata"]["rating"].as_f64())
+}
+
+/// Write successful runs as fine-tuning examples to `path`, one JSON object per line,
+/// and return how many were written. `sessions` is a run log file, or a list of
+/// `Session`s, `AgentResponse`s and run records (as `load_runs` returns). `format` is
+/// `"openai"` (chat `messages`) or `"gemini"` (`contents`). Runs that failed, and with
+/// `min_rating` runs rated lower or not at all, are left out; with `dedupe`, so are
+/// repeats of an earlier example. A run's rating is its latest `record_feedback` score,
+/// else a numeric `rating` in its metadata. `system` is added to every example as the system
+/// prompt.
+#[pyfunction]
+#[pyo3(signature = (sessions, path, format="openai", min_rating=None, dedupe=true, system=None))]
+pub fn export_finetune_jsonl(
+    sessions: &Bound<'_, PyAny>,
+    path: PathBuf,
+    format: &str,
+    min_rating: Option<f64>,
+    dedupe: bool,
+    system: Option<String>,
+) -> PyResult<usize> {
+    if !matches!(format, "openai" | "gemini") {
+        return Err(PyValueError::new_err(format!(
+            "format must be 'openai' or 'gemini', got '{}'",
+            format
+        )));
+    }
+    let examples = if sessions.extract::<PathBuf>().is_ok() {
+        feedback::fold(crate::replay::read_records(sessions)?)
+            .iter()
+            .enumerate()
+            .filter_map(|(index, record)| {
+                Example::from_record(record)
+                    .map_err(|e| PyValueError::new_err(format!("run {}: {}", index + 1, e)))
+                    .transpose()
+            })
+            .collect::<PyResult<Vec<_>>>()?
+    } else {
+        let mut examples = Vec::new();
+        for item in sessions.try_iter()? {
+            examples.extend(Example::from_py(&item?)?);
+        }
+        examples
+    };
+
+    let mut seen = HashSet::new();
+    let mut lines = Vec::new();
+    for example in examples {
+        if min_rating.is_some_and(|min| example.rating.is_none_or(|rating| rating < min)) {
+            continue;
+        }
+        let Some(json) = example
+            .to_json(format, system.as_deref())
+            .map_err(PyValueError::new_err)?
+        else {
+            continue;
+        };
+        let line = json.to_string();
+        if !dedupe || seen.insert(line.clone()) {
+            lines.push(line);
+        }
+    }
+
+    let write = || -> std::io::Result<()> {
+        let mut file = std::io::BufWriter::new(std::fs::File::create(&path)?);
+        for line in &lines {
+            writeln!(file, "{}", line)?;
+        }
+        file.flush()
+    };
+    write().map_err(|e| PyOSError::new_err(format!("cannot write {}: {}", path.display(), e)))?;
+    Ok(lines.len())
+}
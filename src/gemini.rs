@@ -1,18 +1,80 @@
+use crate::agent::{wrap_tool_result, ChatClient, ToolRequest, Turn, Usage};
+use crate::error::{retry_after_header, ContentBlock, RustedChainError};
+#[cfg(feature = "grpc")]
+use crate::gemini_grpc;
+use crate::keys::ApiKey;
+use crate::media::{Attachment, Video};
+use crate::message_transforms::MessageTransform;
+use crate::prefill;
+use crate::retry::RetryPolicy;
+use crate::signing::RequestSigner;
+use crate::stream::{SseParser, TextSink};
+use crate::HTTP;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{json, Value};
 use std::env;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Inline data is capped at 20 MB per request; larger images go through the File API.
+/// Leaves headroom for the prompt and the rest of the request.
+const MAX_INLINE_BYTES: usize = 19 * 1024 * 1024;
+
+/// Upload chunk size; chunks other than the last must be multiples of 256 KiB.
+const UPLOAD_CHUNK: usize = 8 * 1024 * 1024;
+
+/// How long to wait for an uploaded video to finish processing, and how often to check.
+const PROCESSING_TIMEOUT: Duration = Duration::from_secs(600);
+const PROCESSING_POLL: Duration = Duration::from_secs(2);
+
+/// Finish reasons under which a candidate comes back without its content.
+const BLOCKING_FINISH_REASONS: &[&str] = &[
+    "SAFETY",
+    "RECITATION",
+    "BLOCKLIST",
+    "PROHIBITED_CONTENT",
+    "SPII",
+    "IMAGE_SAFETY",
+    "LANGUAGE",
+];
+
+/// The harm categories `safetySettings` can adjust.
+pub(crate) const HARM_CATEGORIES: &[&str] = &[
+    "HARM_CATEGORY_HARASSMENT",
+    "HARM_CATEGORY_HATE_SPEECH",
+    "HARM_CATEGORY_SEXUALLY_EXPLICIT",
+    "HARM_CATEGORY_DANGEROUS_CONTENT",
+    "HARM_CATEGORY_CIVIC_INTEGRITY",
+];
+
+/// Block thresholds, strictest first.
+pub(crate) const BLOCK_THRESHOLDS: &[&str] = &[
+    "BLOCK_LOW_AND_ABOVE",
+    "BLOCK_MEDIUM_AND_ABOVE",
+    "BLOCK_ONLY_HIGH",
+    "BLOCK_NONE",
+    "OFF",
+];
 
 #[derive(Serialize, Clone)]
-struct GenerateContentRequest {
-    contents: Vec<Content>,
+pub(crate) struct GenerateContentRequest {
     #[serde(skip_serializing_if = "Option::is_none")]
-    tools: Option<Vec<Tool>>,
+    pub(crate) system_instruction: Option<Content>,
+    pub(crate) contents: Vec<Content>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub(crate) tools: Option<Vec<Tool>>,
+    #[serde(rename = "generationConfig", skip_serializing_if = "Option::is_none")]
+    pub(crate) generation_config: Option<Value>,
+    #[serde(rename = "safetySettings", skip_serializing_if = "Option::is_none")]
+    pub(crate) safety_settings: Option<Value>,
 }
 
 #[derive(Serialize, Clone)]
-struct Tool {
-    function_declarations: Vec<Value>,
+pub(crate) struct Tool {
+    pub(crate) function_declarations: Vec<Value>,
 }
 
 #[derive(Serialize, Clone)]
@@ -20,6 +82,9 @@ pub(crate) struct Content {
     pub(crate) parts: Vec<Part>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) role: Option<String>,
+    /// Whether the answer stopped at the output token limit; not sent back.
+    #[serde(skip)]
+    pub(crate) truncated: bool,
 }
 
 #[derive(Serialize, Clone, Deserialize)]
@@ -36,6 +101,53 @@ pub(crate) enum Part {
         #[serde(rename = "functionResponse")]
         function_response: FunctionResponseData,
     },
+    InlineData {
+        #[serde(rename = "inlineData")]
+        inline_data: Blob,
+        #[serde(
+            rename = "videoMetadata",
+            default,
+            skip_serializing_if = "Option::is_none"
+        )]
+        video_metadata: Option<Value>,
+    },
+    FileData {
+        #[serde(rename = "fileData")]
+        file_data: FileData,
+        #[serde(
+            rename = "videoMetadata",
+            default,
+            skip_serializing_if = "Option::is_none"
+        )]
+        video_metadata: Option<Value>,
+    },
+}
+
+#[derive(Serialize, Clone, Deserialize)]
+pub(crate) struct FileData {
+    #[serde(rename = "mimeType")]
+    pub(crate) mime_type: String,
+    #[serde(rename = "fileUri")]
+    pub(crate) file_uri: String,
+}
+
+#[derive(Deserialize)]
+struct UploadResponse {
+    file: UploadedFile,
+}
+
+#[derive(Deserialize)]
+struct UploadedFile {
+    name: String,
+    uri: String,
+    state: Option<String>,
+}
+
+#[derive(Serialize, Clone, Deserialize)]
+pub(crate) struct Blob {
+    #[serde(rename = "mimeType")]
+    pub(crate) mime_type: String,
+    pub(crate) data: String,
 }
 
 #[derive(Serialize, Clone, Deserialize)]
@@ -51,37 +163,90 @@ pub(crate) struct FunctionResponseData {
 }
 
 #[derive(Deserialize)]
-struct GenerateContentResponse {
-    candidates: Option<Vec<Candidate>>,
+pub(crate) struct GenerateContentResponse {
+    pub(crate) candidates: Option<Vec<Candidate>>,
+    /// Set instead of candidates when the prompt was blocked.
+    #[serde(rename = "promptFeedback")]
+    pub(crate) prompt_feedback: Option<Value>,
+    #[serde(rename = "usageMetadata")]
+    pub(crate) usage_metadata: Option<UsageMetadata>,
 }
 
 #[derive(Deserialize)]
-struct Candidate {
-    content: ContentResponse,
+pub(crate) struct UsageMetadata {
+    #[serde(rename = "promptTokenCount", default)]
+    pub(crate) prompt_token_count: u64,
+    #[serde(rename = "candidatesTokenCount", default)]
+    pub(crate) candidates_token_count: u64,
+}
+
+impl From<UsageMetadata> for Usage {
+    fn from(usage: UsageMetadata) -> Self {
+        Usage {
+            input_tokens: usage.prompt_token_count,
+            output_tokens: usage.candidates_token_count,
+        }
+    }
 }
 
 #[derive(Deserialize)]
-struct ContentResponse {
-    parts: Vec<Part>,
+pub(crate) struct Candidate {
+    #[serde(default)]
+    pub(crate) content: ContentResponse,
+    #[serde(rename = "finishReason")]
+    pub(crate) finish_reason: Option<String>,
+    #[serde(rename = "safetyRatings", default)]
+    pub(crate) safety_ratings: Vec<Value>,
+}
+
+#[derive(Deserialize, Default)]
+pub(crate) struct ContentResponse {
+    #[serde(default)]
+    pub(crate) parts: Vec<Part>,
     #[allow(dead_code)]
-    role: Option<String>,
+    pub(crate) role: Option<String>,
 }
 
+#[derive(Clone)]
 pub struct Gemini {
-    api_key: String,
+    api_key: ApiKey,
     model: String,
     client: Client,
     tools: Option<Vec<serde_json::Value>>,
+    retry: RetryPolicy,
+    system: Option<String>,
+    prefill: Option<String>,
+    temperature: Option<f64>,
+    /// `safetySettings` sent with every request.
+    safety_settings: Option<Value>,
+    /// Threshold every harm category is loosened to for one retry of a response
+    /// withheld for safety.
+    retry_blocked: Option<String>,
+    message_transform: Option<Arc<MessageTransform>>,
+    signer: Option<RequestSigner>,
+    /// Send `generateContent` calls over gRPC instead of REST.
+    #[cfg(feature = "grpc")]
+    grpc: bool,
 }
 
 impl Default for Gemini {
     fn default() -> Self {
         dotenv::dotenv().ok();
         Self {
-            api_key: env::var("GOOGLE_API_KEY").unwrap_or_default(),
+            api_key: env::var("GOOGLE_API_KEY").unwrap_or_default().into(),
             model: "gemini-2.5-flash".to_string(),
-            client: Client::new(),
+            client: HTTP.clone(),
             tools: None,
+            retry: RetryPolicy::default(),
+            system: None,
+            prefill: None,
+            temperature: None,
+            safety_settings: None,
+            retry_blocked: None,
+            message_transform: None,
+            signer: None,
+            #[cfg(feature = "grpc")]
+            grpc: false,
         }
     }
 }
@@ -102,7 +267,7 @@ impl Gemini {
         Self::default()
     }
 
-    pub fn with_api_key(mut self, api_key: String) -> Self {
+    pub(crate) fn with_api_key(mut self, api_key: ApiKey) -> Self {
         self.api_key = api_key;
         self
     }
@@ -117,50 +282,376 @@ impl Gemini {
         self
     }
 
-    async fn send_request(
+    pub fn without_tools(mut self) -> Self {
+        self.tools = None;
+        self
+    }
+
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Add `signer`'s headers to every request, right before it is sent.
+    pub fn with_signer(mut self, signer: RequestSigner) -> Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    /// System prompt sent as `system_instruction`.
+    pub fn with_system(mut self, system: String) -> Self {
+        self.system = Some(system);
+        self
+    }
+
+    /// Text every answer starts with. Without native prefill, the model is asked to
+    /// start with it in the system prompt, and answers that don't are prefixed.
+    pub fn with_prefill(mut self, prefill: String) -> Self {
+        self.system = Some(prefill::with_instruction(self.system.take(), &prefill));
+        self.prefill = Some(prefill);
+        self
+    }
+
+    /// Sampling temperature, from 0 to 2.
+    pub fn with_temperature(mut self, temperature: f64) -> Self {
+        self.temperature = Some(temperature);
+        self
+    }
+
+    /// Rewrite the messages of every request with `transform`.
+    pub(crate) fn with_message_transform(mut self, transform: Arc<MessageTransform>) -> Self {
+        self.message_transform = Some(transform);
+        self
+    }
+
+    /// `safetySettings`, as a list of `{"category", "threshold"}` objects.
+    pub fn with_safety_settings(mut self, settings: Value) -> Self {
+        self.safety_settings = Some(settings);
+        self
+    }
+
+    /// Retry a response withheld for safety once, with every harm category loosened to
+    /// `threshold`.
+    pub fn with_blocked_retry(mut self, threshold: String) -> Self {
+        self.retry_blocked = Some(threshold);
+        self
+    }
+
+    /// A copy to retry with when `error` is a safety block that `retry_blocked` allows
+    /// another try at.
+    fn relaxed(&self, error: &RustedChainError) -> Option<Self> {
+        let RustedChainError::Blocked(block) = error else {
+            return None;
+        };
+        let threshold = self
+            .retry_blocked
+            .as_deref()
+            .filter(|_| block.is_safety())?;
+        let mut client = self.clone();
+        client.safety_settings = Some(loosened(self.safety_settings.as_ref(), threshold));
+        client.retry_blocked = None;
+        Some(client)
+    }
+
+    /// Use the gRPC transport for chat turns. Multimodal requests and file uploads
+    /// stay on REST.
+    #[cfg(feature = "grpc")]
+    pub fn with_grpc(mut self) -> Self {
+        self.grpc = true;
+        self
+    }
+
+    fn request(
         &self,
         contents: Vec<Content>,
-    ) -> Result<GenerateContentResponse, String> {
-        let url = format!(
-            "https://generativelanguage.googleapis.com/v1beta/models/{}:generateContent?key={}",
-            self.model, self.api_key
-        );
-
+        mut generation_config: Option<Value>,
+    ) -> GenerateContentRequest {
+        if let Some(temperature) = self.temperature {
+            generation_config.get_or_insert_with(|| json!({}))["temperature"] = json!(temperature);
+        }
         let tools = self.tools.as_ref().map(|t| {
             vec![Tool {
                 function_declarations: t.clone(),
             }]
         });
 
-        let request_body = GenerateContentRequest { contents, tools };
+        let system_instruction = self.system.as_ref().map(|system| Content {
+            parts: vec![Part::Text {
+                text: system.clone(),
+            }],
+            role: None,
+            truncated: false,
+        });
+
+        GenerateContentRequest {
+            system_instruction,
+            contents,
+            tools,
+            generation_config,
+            safety_settings: self.safety_settings.clone(),
+        }
+    }
+
+    async fn post(
+        &self,
+        method: &str,
+        contents: Vec<Content>,
+        generation_config: Option<Value>,
+    ) -> Result<reqwest::Response, RustedChainError> {
+        let request_body = self.request(contents, generation_config);
+        let request_body = match &self.message_transform {
+            Some(transform) => transform.apply_to("gemini", &request_body, "contents")?,
+            None => json!(request_body),
+        };
+        self.post_body(method, &request_body).await
+    }
+
+    /// POST `body` to the model's `method` with this client's key and retry policy.
+    async fn post_body(
+        &self,
+        method: &str,
+        body: &Value,
+    ) -> Result<reqwest::Response, RustedChainError> {
+        self.retry
+            .run(|| async {
+                let response = self
+                    .api_key
+                    .send(self.signer.as_ref(), |key| {
+                        let mut url = format!(
+                            "https://generativelanguage.googleapis.com/v1beta/models/{}:{}?key={}",
+                            self.model, method, key
+                        );
+                        if method == "streamGenerateContent" {
+                            url.push_str("&alt=sse");
+                        }
+                        self.client.post(&url).json(body)
+                    })
+                    .await?;
 
-        let response = self
-            .client
-            .post(&url)
-            .json(&request_body)
-            .send()
+                if !response.status().is_success() {
+                    let status = response.status();
+                    let retry_after = retry_after_header(response.headers());
+                    let text = response.text().await.unwrap_or_default();
+                    return Err(RustedChainError::from_response(status, retry_after, &text));
+                }
+                Ok(response)
+            })
+            .await
+    }
+
+    async fn send_request(
+        &self,
+        contents: Vec<Content>,
+    ) -> Result<GenerateContentResponse, RustedChainError> {
+        #[cfg(feature = "grpc")]
+        if self.grpc {
+            let request = self.request(contents, None);
+            return self
+                .retry
+                .run(|| async {
+                    let (index, key) = self.api_key.take()?;
+                    let result = gemini_grpc::generate_content(&key, &self.model, &request).await;
+                    self.api_key.record_result(index, &result);
+                    result
+                })
+                .await;
+        }
+        self.post("generateContent", contents, None)
+            .await?
+            .json()
             .await
-            .map_err(|e| format!("Failed to send request: {}", e))?;
+            .map_err(|e| RustedChainError::ParseError(e.to_string()))
+    }
+
+    /// Upload `data` through the File API's resumable protocol, in chunks, and wait until
+    /// it has been processed. Returns the file URI to reference from a `fileData` part.
+    async fn upload(&self, mime_type: &str, data: &[u8]) -> Result<String, RustedChainError> {
+        let url = format!(
+            "https://generativelanguage.googleapis.com/upload/v1beta/files?key={}",
+            self.api_key.current()?
+        );
+        let started = self
+            .retry
+            .run(|| async {
+                let response = self
+                    .client
+                    .post(&url)
+                    .header("X-Goog-Upload-Protocol", "resumable")
+                    .header("X-Goog-Upload-Command", "start")
+                    .header("X-Goog-Upload-Header-Content-Length", data.len())
+                    .header("X-Goog-Upload-Header-Content-Type", mime_type)
+                    .json(&json!({ "file": { "display_name": "rusted-chain-upload" } }))
+                    .send()
+                    .await?;
+                check(response).await
+            })
+            .await?;
+        let upload_url = started
+            .headers()
+            .get("x-goog-upload-url")
+            .and_then(|value| value.to_str().ok())
+            .ok_or_else(|| RustedChainError::ParseError("File API returned no upload URL".into()))?
+            .to_string();
+
+        let chunks: Vec<&[u8]> = data.chunks(UPLOAD_CHUNK).collect();
+        let mut offset = 0;
+        for (index, chunk) in chunks.iter().enumerate() {
+            let command = if index + 1 == chunks.len() {
+                "upload, finalize"
+            } else {
+                "upload"
+            };
+            let response = self
+                .retry
+                .run(|| async {
+                    let response = self
+                        .client
+                        .post(&upload_url)
+                        .header("X-Goog-Upload-Command", command)
+                        .header("X-Goog-Upload-Offset", offset)
+                        .body(chunk.to_vec())
+                        .send()
+                        .await?;
+                    check(response).await
+                })
+                .await?;
+            offset += chunk.len();
+            if index + 1 == chunks.len() {
+                let uploaded: UploadResponse = response
+                    .json()
+                    .await
+                    .map_err(|e| RustedChainError::ParseError(e.to_string()))?;
+                return self.wait_until_active(uploaded.file).await;
+            }
+        }
+        Err(RustedChainError::InvalidRequest(
+            "cannot upload an empty file".to_string(),
+        ))
+    }
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let text = response.text().await.unwrap_or_default();
-            return Err(format!("API Error {}: {}", status, text));
+    /// Poll an uploaded file until it leaves `PROCESSING`; videos take a while.
+    async fn wait_until_active(&self, mut file: UploadedFile) -> Result<String, RustedChainError> {
+        let deadline = Instant::now() + PROCESSING_TIMEOUT;
+        loop {
+            match file.state.as_deref() {
+                Some("PROCESSING") if Instant::now() < deadline => {}
+                Some("PROCESSING") => {
+                    return Err(RustedChainError::Api {
+                        status: 504,
+                        message: format!("file {} is still processing", file.name),
+                    })
+                }
+                Some("FAILED") => {
+                    return Err(RustedChainError::InvalidRequest(format!(
+                        "file {} failed processing",
+                        file.name
+                    )))
+                }
+                _ => return Ok(file.uri),
+            }
+            tokio::time::sleep(PROCESSING_POLL).await;
+            let url = format!(
+                "https://generativelanguage.googleapis.com/v1beta/{}?key={}",
+                file.name,
+                self.api_key.current()?
+            );
+            file = self
+                .retry
+                .run(|| async { check(self.client.get(&url).send().await?).await })
+                .await?
+                .json()
+                .await
+                .map_err(|e| RustedChainError::ParseError(e.to_string()))?;
+        }
+    }
+
+    /// A single turn with `files` attached, answered in JSON matching `schema` when one
+    /// is given. Files are sent inline unless together they exceed the inline limit, in
+    /// which case they are uploaded through the File API.
+    pub(crate) async fn multimodal(
+        &self,
+        prompt: &str,
+        files: &[Attachment],
+        schema: Option<&Value>,
+    ) -> Result<(String, Usage), RustedChainError> {
+        let mut parts = vec![Part::Text {
+            text: prompt.to_string(),
+        }];
+        let inline_bytes: usize = files.iter().map(Attachment::encoded_len).sum();
+        for file in files {
+            let (mime_type, data, video_metadata) = match file {
+                Attachment::Image(image) => (&image.mime_type, &image.data, None),
+                Attachment::Video(video) => (&video.mime_type, &video.data, video_metadata(video)),
+            };
+            let part = if inline_bytes > MAX_INLINE_BYTES {
+                Part::FileData {
+                    file_data: FileData {
+                        mime_type: mime_type.clone(),
+                        file_uri: self.upload(mime_type, data).await?,
+                    },
+                    video_metadata,
+                }
+            } else {
+                Part::InlineData {
+                    inline_data: Blob {
+                        mime_type: mime_type.clone(),
+                        data: STANDARD.encode(data),
+                    },
+                    video_metadata,
+                }
+            };
+            parts.push(part);
         }
+        let contents = vec![Content {
+            parts,
+            role: Some("user".to_string()),
+            truncated: false,
+        }];
+        let generation_config = schema.map(|schema| {
+            json!({
+                "responseMimeType": "application/json",
+                "responseJsonSchema": schema,
+            })
+        });
 
-        response
+        let response: GenerateContentResponse = self
+            .post("generateContent", contents, generation_config)
+            .await?
             .json()
             .await
-            .map_err(|e| format!("Failed to parse response: {}", e))
+            .map_err(|e| RustedChainError::ParseError(e.to_string()))?;
+        let usage = response.usage_metadata.map(Usage::from).unwrap_or_default();
+        let feedback = response.prompt_feedback.as_ref();
+        let Some(candidate) = response.candidates.into_iter().flatten().next() else {
+            return Err(no_content(feedback, None, &[]));
+        };
+        let text: String = candidate
+            .content
+            .parts
+            .iter()
+            .filter_map(|part| match part {
+                Part::Text { text } => Some(text.as_str()),
+                _ => None,
+            })
+            .collect();
+        match no_content(
+            feedback,
+            candidate.finish_reason.as_deref(),
+            &candidate.safety_ratings,
+        ) {
+            error @ RustedChainError::Blocked(_) if text.is_empty() => Err(error),
+            _ => Ok((text, usage)),
+        }
     }
 
     #[allow(dead_code)]
-    pub async fn invoke(&self, prompt: &str) -> Result<String, String> {
+    pub async fn invoke(&self, prompt: &str) -> Result<String, RustedChainError> {
         let contents = vec![Content {
             parts: vec![Part::Text {
                 text: prompt.to_string(),
             }],
             role: Some("user".to_string()),
+            truncated: false,
         }];
 
         const MAX_ITERATIONS: usize = 10;
@@ -177,20 +668,34 @@ impl Gemini {
                         }
                     }
 
-                    return Err("Function calling not yet supported in invoke()".to_string());
+                    let name = parts
+                        .iter()
+                        .find_map(|part| match part {
+                            Part::FunctionCall { function_call } => {
+                                Some(function_call.name.clone())
+                            }
+                            _ => None,
+                        })
+                        .unwrap_or_default();
+                    return Err(RustedChainError::ToolExecutionNotSupported(name));
                 }
             }
         }
 
-        Err("Max iterations reached without getting a text response".to_string())
+        Err(RustedChainError::MaxIterations(MAX_ITERATIONS))
     }
 
-    pub async fn invoke_with_response(&self, prompt: &str) -> Result<GeminiResponse, String> {
+    #[allow(dead_code)]
+    pub async fn invoke_with_response(
+        &self,
+        prompt: &str,
+    ) -> Result<GeminiResponse, RustedChainError> {
         let contents = vec![Content {
             parts: vec![Part::Text {
                 text: prompt.to_string(),
             }],
             role: Some("user".to_string()),
+            truncated: false,
         }];
 
         let response = self.send_request(contents).await?;
@@ -216,7 +721,7 @@ impl Gemini {
             }
         }
 
-        Err("No valid response from Gemini".to_string())
+        Err(RustedChainError::NoResponse)
     }
 
     #[allow(dead_code)]
@@ -226,7 +731,7 @@ impl Gemini {
         conversation_history: Vec<Content>,
         tool_call: &ToolCall,
         result: Value,
-    ) -> Result<String, String> {
+    ) -> Result<String, RustedChainError> {
         let mut contents = conversation_history;
 
         contents.push(Content {
@@ -237,6 +742,7 @@ impl Gemini {
                 },
             }],
             role: Some("function".to_string()),
+            truncated: false,
         });
 
         let response = self.send_request(contents).await?;
@@ -251,42 +757,490 @@ impl Gemini {
             }
         }
 
-        Err("No text response after tool execution".to_string())
+        Err(RustedChainError::NoResponse)
+    }
+
+    /// Send a request body built by the caller to the model's `method`, by default
+    /// `generateContent`. Returns the parsed response.
+    pub(crate) async fn raw(
+        &self,
+        payload: Value,
+        method: Option<&str>,
+    ) -> Result<Value, RustedChainError> {
+        let method = method.unwrap_or("generateContent").trim_start_matches(':');
+        self.post_body(method, &payload)
+            .await?
+            .json()
+            .await
+            .map_err(|e| RustedChainError::ParseError(e.to_string()))
+    }
+
+    /// Resolve DNS and open a TLS connection (or the gRPC channel), which is kept for
+    /// later requests. With `prime`, also send a one-token request.
+    pub(crate) async fn warmup(&self, prime: bool) -> Result<(), RustedChainError> {
+        let ping = || vec![Self::user_message("Hi")];
+        let one_token = || Some(json!({ "maxOutputTokens": 1 }));
+        #[cfg(feature = "grpc")]
+        if self.grpc {
+            if prime {
+                let request = self.request(ping(), one_token());
+                gemini_grpc::generate_content(&self.api_key.current()?, &self.model, &request)
+                    .await?;
+            } else {
+                gemini_grpc::connect().await?;
+            }
+            return Ok(());
+        }
+        if prime {
+            self.post("generateContent", ping(), one_token())
+                .await?
+                .bytes()
+                .await?;
+        } else {
+            // Any answer, even an authentication error, leaves a warm connection. The
+            // body is read so the connection goes back to the pool.
+            let url = format!(
+                "https://generativelanguage.googleapis.com/v1beta/models/{}?key={}",
+                self.model,
+                self.api_key.current()?
+            );
+            self.client.get(&url).send().await?.bytes().await?;
+        }
+        Ok(())
     }
 
     pub(crate) async fn exchange(
         &self,
         conversation: Vec<Content>,
-    ) -> Result<(GeminiResponse, Content), String> {
+    ) -> Result<(GeminiResponse, Content, Usage), RustedChainError> {
         let response = self.send_request(conversation.clone()).await?;
+        let usage = response.usage_metadata.map(Usage::from).unwrap_or_default();
+        let candidate = response.candidates.as_ref().and_then(|c| c.first());
 
-        if let Some(candidates) = response.candidates {
-            if let Some(candidate) = candidates.first() {
-                let assistant_content = Content {
-                    parts: candidate.content.parts.clone(),
-                    role: candidate.content.role.clone(),
-                };
+        if let Some(candidate) = candidate {
+            let assistant_content = Content {
+                parts: candidate.content.parts.clone(),
+                role: candidate.content.role.clone(),
+                truncated: candidate.finish_reason.as_deref() == Some("MAX_TOKENS"),
+            };
 
-                for part in &candidate.content.parts {
-                    match part {
-                        Part::Text { text } => {
-                            return Ok((GeminiResponse::Text(text.clone()), assistant_content));
-                        }
-                        Part::FunctionCall { function_call } => {
-                            return Ok((
-                                GeminiResponse::ToolCall(ToolCall {
-                                    name: function_call.name.clone(),
-                                    args: function_call.args.clone(),
-                                }),
-                                assistant_content,
-                            ));
-                        }
-                        _ => {}
+            for part in &candidate.content.parts {
+                match part {
+                    Part::Text { text } => {
+                        return Ok((GeminiResponse::Text(text.clone()), assistant_content, usage));
+                    }
+                    Part::FunctionCall { function_call } => {
+                        return Ok((
+                            GeminiResponse::ToolCall(ToolCall {
+                                name: function_call.name.clone(),
+                                args: function_call.args.clone(),
+                            }),
+                            assistant_content,
+                            usage,
+                        ));
                     }
+                    _ => {}
+                }
+            }
+        }
+
+        Err(no_content(
+            response.prompt_feedback.as_ref(),
+            candidate.and_then(|c| c.finish_reason.as_deref()),
+            candidate.map_or(&[], |c| &c.safety_ratings),
+        ))
+    }
+
+    pub(crate) async fn exchange_stream(
+        &self,
+        conversation: Vec<Content>,
+        sink: &TextSink,
+    ) -> Result<(GeminiResponse, Content, Usage), RustedChainError> {
+        let mut turn = StreamedTurn::default();
+
+        #[cfg(feature = "grpc")]
+        if self.grpc {
+            use futures::StreamExt;
+            let request = self.request(conversation, None);
+            let chunks = self
+                .retry
+                .run(|| async {
+                    let (index, key) = self.api_key.take()?;
+                    let result =
+                        gemini_grpc::stream_generate_content(&key, &self.model, &request).await;
+                    self.api_key.record_result(index, &result);
+                    result
+                })
+                .await?;
+            futures::pin_mut!(chunks);
+            while let Some(body) = chunks.next().await {
+                turn.absorb(body?, sink).await?;
+            }
+            return turn.finish();
+        }
+
+        let mut response = self
+            .post("streamGenerateContent", conversation, None)
+            .await?;
+        let mut parser = SseParser::default();
+        while let Some(chunk) = response.chunk().await? {
+            for data in parser.push(&chunk) {
+                let body: GenerateContentResponse = serde_json::from_str(&data)
+                    .map_err(|e| RustedChainError::ParseError(e.to_string()))?;
+                turn.absorb(body, sink).await?;
+            }
+        }
+        turn.finish()
+    }
+}
+
+/// A streamed turn assembled from response chunks, whichever transport they came over.
+#[derive(Default)]
+struct StreamedTurn {
+    text: String,
+    calls: Vec<FunctionCallData>,
+    role: Option<String>,
+    usage: Usage,
+    prompt_feedback: Option<Value>,
+    finish_reason: Option<String>,
+    safety_ratings: Vec<Value>,
+}
+
+impl StreamedTurn {
+    async fn absorb(
+        &mut self,
+        body: GenerateContentResponse,
+        sink: &TextSink,
+    ) -> Result<(), RustedChainError> {
+        // Each chunk carries the running totals so far.
+        if let Some(chunk_usage) = body.usage_metadata {
+            self.usage = chunk_usage.into();
+        }
+        self.prompt_feedback = body.prompt_feedback.or(self.prompt_feedback.take());
+        let Some(candidate) = body.candidates.and_then(|c| c.into_iter().next()) else {
+            return Ok(());
+        };
+        self.role = self.role.take().or(candidate.content.role);
+        self.finish_reason = candidate.finish_reason.or(self.finish_reason.take());
+        if !candidate.safety_ratings.is_empty() {
+            self.safety_ratings = candidate.safety_ratings;
+        }
+        for part in candidate.content.parts {
+            match part {
+                Part::Text { text: delta } => {
+                    self.text.push_str(&delta);
+                    sink.send(delta).await?;
                 }
+                Part::FunctionCall { function_call } => self.calls.push(function_call),
+                _ => {}
             }
         }
+        Ok(())
+    }
+
+    fn finish(self) -> Result<(GeminiResponse, Content, Usage), RustedChainError> {
+        let StreamedTurn {
+            text,
+            calls,
+            role,
+            usage,
+            prompt_feedback,
+            finish_reason,
+            safety_ratings,
+        } = self;
+        let mut parts = Vec::new();
+        if !text.is_empty() {
+            parts.push(Part::Text { text: text.clone() });
+        }
+        parts.extend(calls.iter().map(|call| Part::FunctionCall {
+            function_call: call.clone(),
+        }));
+        let assistant_content = Content {
+            parts,
+            role: role.or_else(|| Some("model".to_string())),
+            truncated: finish_reason.as_deref() == Some("MAX_TOKENS"),
+        };
+
+        if let Some(call) = calls.into_iter().next() {
+            return Ok((
+                GeminiResponse::ToolCall(ToolCall {
+                    name: call.name,
+                    args: call.args,
+                }),
+                assistant_content,
+                usage,
+            ));
+        }
+        if !text.is_empty() {
+            return Ok((GeminiResponse::Text(text), assistant_content, usage));
+        }
+
+        Err(no_content(
+            prompt_feedback.as_ref(),
+            finish_reason.as_deref(),
+            &safety_ratings,
+        ))
+    }
+}
+
+impl From<GeminiResponse> for Turn {
+    fn from(response: GeminiResponse) -> Self {
+        match response {
+            GeminiResponse::Text(text) => Turn::Text(text),
+            GeminiResponse::ToolCall(call) => Turn::ToolCall(ToolRequest {
+                id: None,
+                name: call.name,
+                args: call.args,
+            }),
+        }
+    }
+}
+
+/// The error for a response without usable content: `Blocked`, with what Gemini
+/// reported, if the prompt was blocked or the answer stopped by a filter, otherwise
+/// `NoResponse`.
+fn no_content(
+    prompt_feedback: Option<&Value>,
+    finish_reason: Option<&str>,
+    safety_ratings: &[Value],
+) -> RustedChainError {
+    let block_reason = prompt_feedback
+        .and_then(|feedback| feedback["blockReason"].as_str())
+        .map(str::to_string);
+    let finish_reason = finish_reason
+        .filter(|reason| BLOCKING_FINISH_REASONS.contains(reason))
+        .map(str::to_string);
+    let (message, ratings) = match (&block_reason, &finish_reason) {
+        (Some(reason), _) => (
+            format!("Gemini blocked the prompt ({})", reason),
+            prompt_feedback
+                .and_then(|feedback| feedback["safetyRatings"].as_array())
+                .cloned()
+                .unwrap_or_default(),
+        ),
+        (None, Some(reason)) => (
+            format!("Gemini withheld the answer ({})", reason),
+            safety_ratings.to_vec(),
+        ),
+        (None, None) => return RustedChainError::NoResponse,
+    };
+    let flagged: Vec<String> = ratings
+        .iter()
+        .filter(|rating| {
+            rating["blocked"].as_bool() == Some(true)
+                || matches!(rating["probability"].as_str(), Some("MEDIUM" | "HIGH"))
+        })
+        .filter_map(|rating| {
+            let category = rating["category"].as_str()?;
+            Some(format!(
+                "{} {}",
+                category.trim_start_matches("HARM_CATEGORY_"),
+                rating["probability"].as_str().unwrap_or("blocked")
+            ))
+        })
+        .collect();
+    let message = match flagged.is_empty() {
+        true => message,
+        false => format!("{}: {}", message, flagged.join(", ")),
+    };
+    RustedChainError::Blocked(Box::new(ContentBlock {
+        message,
+        block_reason,
+        finish_reason,
+        prompt_feedback: prompt_feedback.cloned(),
+        safety_ratings: ratings,
+    }))
+}
+
+/// `settings` with every harm category at `threshold`, or left where it is if that is
+/// already looser.
+fn loosened(settings: Option<&Value>, threshold: &str) -> Value {
+    let looseness = |threshold: &str| {
+        BLOCK_THRESHOLDS
+            .iter()
+            .position(|known| *known == threshold)
+            .unwrap_or(0)
+    };
+    let settings: Vec<Value> = HARM_CATEGORIES
+        .iter()
+        .map(|category| {
+            let current = settings
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .find(|setting| setting["category"] == *category)
+                .and_then(|setting| setting["threshold"].as_str())
+                .filter(|current| looseness(current) > looseness(threshold))
+                .unwrap_or(threshold);
+            json!({ "category": category, "threshold": current })
+        })
+        .collect();
+    Value::Array(settings)
+}
+
+/// Gemini's `videoMetadata` for a clip with sampling options, if it has any.
+fn video_metadata(video: &Video) -> Option<Value> {
+    let mut metadata = serde_json::Map::new();
+    if let Some(fps) = video.fps {
+        metadata.insert("fps".to_string(), json!(fps));
+    }
+    if let Some(start) = video.start_offset {
+        metadata.insert("startOffset".to_string(), json!(format!("{}s", start)));
+    }
+    if let Some(end) = video.end_offset {
+        metadata.insert("endOffset".to_string(), json!(format!("{}s", end)));
+    }
+    (!metadata.is_empty()).then_some(Value::Object(metadata))
+}
+
+async fn check(response: reqwest::Response) -> Result<reqwest::Response, RustedChainError> {
+    if !response.status().is_success() {
+        let status = response.status();
+        let retry_after = retry_after_header(response.headers());
+        let text = response.text().await.unwrap_or_default();
+        return Err(RustedChainError::from_response(status, retry_after, &text));
+    }
+    Ok(response)
+}
+
+impl ChatClient for Gemini {
+    type Message = Content;
+
+    fn provider(&self) -> &'static str {
+        "gemini"
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn for_model(&self, model: &str) -> Self {
+        self.clone().with_model(model.to_string())
+    }
+
+    fn side_client(&self, model: &str, system: &str) -> Option<Self> {
+        let mut client = self
+            .for_model(model)
+            .without_tools()
+            .with_system(system.to_string());
+        client.prefill = None;
+        Some(client)
+    }
+
+    fn resumed(&self, partial: &str) -> Option<Self> {
+        Some(self.clone().with_prefill(partial.to_string()))
+    }
+
+    fn truncated(message: &Content) -> bool {
+        message.truncated
+    }
+
+    fn api_key(&self) -> &ApiKey {
+        &self.api_key
+    }
+
+    fn system(&self) -> Option<&str> {
+        self.system.as_deref()
+    }
+
+    fn user_message(text: &str) -> Content {
+        Content {
+            parts: vec![Part::Text {
+                text: text.to_string(),
+            }],
+            role: Some("user".to_string()),
+            truncated: false,
+        }
+    }
+
+    fn assistant_message(text: &str) -> Content {
+        Content {
+            parts: vec![Part::Text {
+                text: text.to_string(),
+            }],
+            role: Some("model".to_string()),
+            truncated: false,
+        }
+    }
+
+    fn tool_call_message(call: &ToolRequest) -> Content {
+        Content {
+            parts: vec![Part::FunctionCall {
+                function_call: FunctionCallData {
+                    name: call.name.clone(),
+                    args: call.args.clone(),
+                },
+            }],
+            role: Some("model".to_string()),
+            truncated: false,
+        }
+    }
+
+    fn tool_result_message(call: &ToolRequest, result: Value) -> Content {
+        Content {
+            parts: vec![Part::FunctionResponse {
+                function_response: FunctionResponseData {
+                    name: call.name.clone(),
+                    response: wrap_tool_result(result),
+                },
+            }],
+            role: Some("function".to_string()),
+            truncated: false,
+        }
+    }
+
+    async fn exchange(
+        &self,
+        conversation: Vec<Content>,
+    ) -> Result<(Turn, Content, Usage), RustedChainError> {
+        let (response, content, usage) = match Gemini::exchange(self, conversation.clone()).await {
+            Err(error) => match self.relaxed(&error) {
+                Some(relaxed) => Gemini::exchange(&relaxed, conversation).await?,
+                None => return Err(error),
+            },
+            result => result?,
+        };
+        let turn = prefill::ensure_prefix(self.prefill.as_deref(), response.into());
+        Ok((turn, content, usage))
+    }
+
+    async fn exchange_stream(
+        &self,
+        conversation: Vec<Content>,
+        sink: &TextSink,
+    ) -> Result<(Turn, Content, Usage), RustedChainError> {
+        // A blocked turn streamed nothing, so it can be tried again.
+        let (response, content, usage) =
+            match Gemini::exchange_stream(self, conversation.clone(), sink).await {
+                Err(error) => match self.relaxed(&error) {
+                    Some(relaxed) => Gemini::exchange_stream(&relaxed, conversation, sink).await?,
+                    None => return Err(error),
+                },
+                result => result?,
+            };
+        Ok((response.into(), content, usage))
+    }
+
+    async fn warmup(&self, prime: bool) -> Result<(), RustedChainError> {
+        Gemini::warmup(self, prime).await
+    }
+
+    async fn multimodal(
+        &self,
+        prompt: &str,
+        files: &[Attachment],
+        schema: Option<&Value>,
+    ) -> Result<(String, Usage), RustedChainError> {
+        match Gemini::multimodal(self, prompt, files, schema).await {
+            Err(error) => match self.relaxed(&error) {
+                Some(relaxed) => Gemini::multimodal(&relaxed, prompt, files, schema).await,
+                None => Err(error),
+            },
+            result => result,
+        }
+    }
 
-        Err("No valid response from Gemini".to_string())
+    async fn raw(&self, payload: Value, path: Option<&str>) -> Result<Value, RustedChainError> {
+        Gemini::raw(self, payload, path).await
     }
 }
@@ -0,0 +1,593 @@
+//! gRPC transport for the Gemini API, behind the `grpc` feature.
+//!
+//! Requests go to `google.ai.generativelanguage.v1beta.GenerativeService` over one
+//! shared HTTP/2 channel, which saves a connection setup per call and streams responses
+//! as protobuf messages instead of SSE. The messages below are hand-written subsets of
+//! the published protos, covering what the agent loop sends and reads; they are
+//! converted to and from the REST types so the rest of the Gemini client is shared.
+
+use crate::deadline;
+use crate::error::RustedChainError;
+use crate::gemini::{
+    Blob, Candidate, Content, ContentResponse, FileData, FunctionCallData, FunctionResponseData,
+    GenerateContentRequest, GenerateContentResponse, Part, UsageMetadata, BLOCK_THRESHOLDS,
+    HARM_CATEGORIES,
+};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use futures::{Stream, StreamExt};
+use prost_types::value::Kind;
+use serde_json::{json, Value};
+use tokio::sync::OnceCell;
+use tonic::codegen::http::uri::PathAndQuery;
+use tonic::metadata::MetadataValue;
+use tonic::transport::{Channel, ClientTlsConfig, Endpoint};
+use tonic_prost::ProstCodec;
+
+const ENDPOINT: &str = "https://generativelanguage.googleapis.com";
+
+/// Names of the protobuf enum values, by number, as the REST API spells them.
+const FINISH_REASONS: &[&str] = &[
+    "FINISH_REASON_UNSPECIFIED",
+    "STOP",
+    "MAX_TOKENS",
+    "SAFETY",
+    "RECITATION",
+    "OTHER",
+    "LANGUAGE",
+    "BLOCKLIST",
+    "PROHIBITED_CONTENT",
+    "SPII",
+    "MALFORMED_FUNCTION_CALL",
+    "IMAGE_SAFETY",
+];
+const BLOCK_REASONS: &[&str] = &[
+    "BLOCK_REASON_UNSPECIFIED",
+    "SAFETY",
+    "OTHER",
+    "BLOCKLIST",
+    "PROHIBITED_CONTENT",
+    "IMAGE_SAFETY",
+];
+const HARM_PROBABILITIES: &[&str] = &[
+    "HARM_PROBABILITY_UNSPECIFIED",
+    "NEGLIGIBLE",
+    "LOW",
+    "MEDIUM",
+    "HIGH",
+];
+/// `HarmCategory` numbers the categories in `HARM_CATEGORIES` from 7.
+const FIRST_HARM_CATEGORY: i32 = 7;
+
+/// Connected on first use and shared by every Gemini client, so calls reuse one
+/// HTTP/2 connection. The channel reconnects by itself if the connection drops.
+static CHANNEL: OnceCell<Channel> = OnceCell::const_new();
+
+mod proto {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct GenerateContentRequest {
+        #[prost(string, tag = "1")]
+        pub model: String,
+        #[prost(message, repeated, tag = "2")]
+        pub contents: Vec<Content>,
+        #[prost(message, repeated, tag = "5")]
+        pub tools: Vec<Tool>,
+        #[prost(message, optional, tag = "8")]
+        pub system_instruction: Option<Content>,
+        #[prost(message, optional, tag = "4")]
+        pub generation_config: Option<GenerationConfig>,
+        #[prost(message, repeated, tag = "3")]
+        pub safety_settings: Vec<SafetySetting>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct SafetySetting {
+        /// `HarmCategory`.
+        #[prost(int32, tag = "3")]
+        pub category: i32,
+        /// `HarmBlockThreshold`.
+        #[prost(int32, tag = "4")]
+        pub threshold: i32,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct GenerationConfig {
+        #[prost(int32, optional, tag = "4")]
+        pub max_output_tokens: Option<i32>,
+        #[prost(float, optional, tag = "5")]
+        pub temperature: Option<f32>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Content {
+        #[prost(message, repeated, tag = "1")]
+        pub parts: Vec<Part>,
+        #[prost(string, tag = "2")]
+        pub role: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Part {
+        #[prost(oneof = "part::Payload", tags = "2, 3, 4, 5, 6")]
+        pub data: Option<part::Payload>,
+    }
+
+    pub mod part {
+        #[derive(Clone, PartialEq, prost::Oneof)]
+        pub enum Payload {
+            #[prost(string, tag = "2")]
+            Text(String),
+            #[prost(message, tag = "3")]
+            InlineData(super::Blob),
+            #[prost(message, tag = "4")]
+            FunctionCall(super::FunctionCall),
+            #[prost(message, tag = "5")]
+            FunctionResponse(super::FunctionResponse),
+            #[prost(message, tag = "6")]
+            FileData(super::FileData),
+        }
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Blob {
+        #[prost(string, tag = "1")]
+        pub mime_type: String,
+        #[prost(bytes = "vec", tag = "2")]
+        pub data: Vec<u8>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct FileData {
+        #[prost(string, tag = "1")]
+        pub mime_type: String,
+        #[prost(string, tag = "2")]
+        pub file_uri: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct FunctionCall {
+        #[prost(string, tag = "1")]
+        pub name: String,
+        #[prost(message, optional, tag = "2")]
+        pub args: Option<prost_types::Struct>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct FunctionResponse {
+        #[prost(string, tag = "1")]
+        pub name: String,
+        #[prost(message, optional, tag = "2")]
+        pub response: Option<prost_types::Struct>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Tool {
+        #[prost(message, repeated, tag = "1")]
+        pub function_declarations: Vec<FunctionDeclaration>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct FunctionDeclaration {
+        #[prost(string, tag = "1")]
+        pub name: String,
+        #[prost(string, tag = "2")]
+        pub description: String,
+        /// The parameters as JSON Schema, which spares converting them to `Schema`.
+        #[prost(message, optional, tag = "6")]
+        pub parameters_json_schema: Option<prost_types::Value>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct GenerateContentResponse {
+        #[prost(message, repeated, tag = "1")]
+        pub candidates: Vec<Candidate>,
+        #[prost(message, optional, tag = "2")]
+        pub prompt_feedback: Option<PromptFeedback>,
+        #[prost(message, optional, tag = "3")]
+        pub usage_metadata: Option<UsageMetadata>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct PromptFeedback {
+        /// `BlockReason`.
+        #[prost(int32, tag = "1")]
+        pub block_reason: i32,
+        #[prost(message, repeated, tag = "2")]
+        pub safety_ratings: Vec<SafetyRating>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Candidate {
+        #[prost(message, optional, tag = "1")]
+        pub content: Option<Content>,
+        /// `FinishReason`.
+        #[prost(int32, optional, tag = "2")]
+        pub finish_reason: Option<i32>,
+        #[prost(message, repeated, tag = "5")]
+        pub safety_ratings: Vec<SafetyRating>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct SafetyRating {
+        /// `HarmCategory`.
+        #[prost(int32, tag = "3")]
+        pub category: i32,
+        /// `HarmProbability`.
+        #[prost(int32, tag = "4")]
+        pub probability: i32,
+        #[prost(bool, tag = "5")]
+        pub blocked: bool,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct UsageMetadata {
+        #[prost(int32, tag = "1")]
+        pub prompt_token_count: i32,
+        #[prost(int32, tag = "2")]
+        pub candidates_token_count: i32,
+    }
+}
+
+/// `generateContent` over gRPC.
+pub(crate) async fn generate_content(
+    api_key: &str,
+    model: &str,
+    request: &GenerateContentRequest,
+) -> Result<GenerateContentResponse, RustedChainError> {
+    let mut client = client().await?;
+    let response = client
+        .unary(
+            authorized(api_key, to_proto(model, request)?)?,
+            PathAndQuery::from_static(
+                "/google.ai.generativelanguage.v1beta.GenerativeService/GenerateContent",
+            ),
+            ProstCodec::<proto::GenerateContentRequest, proto::GenerateContentResponse>::default(),
+        )
+        .await
+        .map_err(status_error)?;
+    deadline::started();
+    Ok(from_proto(response.into_inner()))
+}
+
+/// `streamGenerateContent` over gRPC: one response message per chunk.
+pub(crate) async fn stream_generate_content(
+    api_key: &str,
+    model: &str,
+    request: &GenerateContentRequest,
+) -> Result<impl Stream<Item = Result<GenerateContentResponse, RustedChainError>>, RustedChainError>
+{
+    let mut client = client().await?;
+    let response = client
+        .server_streaming(
+            authorized(api_key, to_proto(model, request)?)?,
+            PathAndQuery::from_static(
+                "/google.ai.generativelanguage.v1beta.GenerativeService/StreamGenerateContent",
+            ),
+            ProstCodec::<proto::GenerateContentRequest, proto::GenerateContentResponse>::default(),
+        )
+        .await
+        .map_err(status_error)?;
+    Ok(response.into_inner().map(|chunk| {
+        deadline::started();
+        chunk.map(from_proto).map_err(status_error)
+    }))
+}
+
+/// The shared channel, connecting it if this is the first call.
+pub(crate) async fn connect() -> Result<Channel, RustedChainError> {
+    let unavailable = |e: tonic::transport::Error| RustedChainError::Api {
+        status: 503,
+        message: format!("gRPC connection failed: {}", e),
+    };
+    let channel = CHANNEL
+        .get_or_try_init(|| async {
+            let mut endpoint = Endpoint::from_static(ENDPOINT);
+            if ENDPOINT.starts_with("https://") {
+                endpoint = endpoint
+                    .tls_config(ClientTlsConfig::new().with_webpki_roots())
+                    .map_err(unavailable)?;
+            }
+            endpoint.connect().await.map_err(unavailable)
+        })
+        .await?;
+    Ok(channel.clone())
+}
+
+async fn client() -> Result<tonic::client::Grpc<Channel>, RustedChainError> {
+    let mut client = tonic::client::Grpc::new(connect().await?);
+    client.ready().await.map_err(|e| RustedChainError::Api {
+        status: 503,
+        message: format!("gRPC channel unavailable: {}", e),
+    })?;
+    Ok(client)
+}
+
+fn authorized<T>(api_key: &str, message: T) -> Result<tonic::Request<T>, RustedChainError> {
+    let mut request = tonic::Request::new(message);
+    let key = MetadataValue::try_from(api_key)
+        .map_err(|_| RustedChainError::InvalidRequest("invalid API key".to_string()))?;
+    request.metadata_mut().insert("x-goog-api-key", key);
+    Ok(request)
+}
+
+/// Classify a gRPC status the way the REST path classifies an HTTP error, using the
+/// HTTP status Google maps each code to.
+fn status_error(status: tonic::Status) -> RustedChainError {
+    use tonic::Code;
+    let http = match status.code() {
+        Code::InvalidArgument | Code::FailedPrecondition | Code::OutOfRange => 400,
+        Code::Unauthenticated => 401,
+        Code::PermissionDenied => 403,
+        Code::NotFound => 404,
+        Code::AlreadyExists | Code::Aborted => 409,
+        Code::ResourceExhausted => 429,
+        Code::Cancelled => 499,
+        Code::Unimplemented => 501,
+        Code::Unavailable => 503,
+        Code::DeadlineExceeded => 504,
+        _ => 500,
+    };
+    let body = json!({
+        "error": {
+            "message": status.message(),
+            "status": format!("{:?}", status.code()),
+        }
+    });
+    RustedChainError::from_response(
+        reqwest::StatusCode::from_u16(http).unwrap_or(reqwest::StatusCode::INTERNAL_SERVER_ERROR),
+        None,
+        &body.to_string(),
+    )
+}
+
+fn to_proto(
+    model: &str,
+    request: &GenerateContentRequest,
+) -> Result<proto::GenerateContentRequest, RustedChainError> {
+    let tools = request
+        .tools
+        .iter()
+        .flatten()
+        .map(|tool| proto::Tool {
+            function_declarations: tool
+                .function_declarations
+                .iter()
+                .map(|declaration| proto::FunctionDeclaration {
+                    name: string_field(declaration, "name"),
+                    description: string_field(declaration, "description"),
+                    parameters_json_schema: declaration.get("parameters").map(to_value),
+                })
+                .collect(),
+        })
+        .collect();
+    Ok(proto::GenerateContentRequest {
+        model: format!("models/{}", model),
+        contents: request
+            .contents
+            .iter()
+            .map(content_to_proto)
+            .collect::<Result<_, _>>()?,
+        tools,
+        system_instruction: request
+            .system_instruction
+            .as_ref()
+            .map(content_to_proto)
+            .transpose()?,
+        generation_config: request.generation_config.as_ref().map(|config| {
+            proto::GenerationConfig {
+                max_output_tokens: config
+                    .get("maxOutputTokens")
+                    .and_then(Value::as_i64)
+                    .map(|n| n as i32),
+                temperature: config
+                    .get("temperature")
+                    .and_then(Value::as_f64)
+                    .map(|t| t as f32),
+            }
+        }),
+        safety_settings: request
+            .safety_settings
+            .iter()
+            .filter_map(Value::as_array)
+            .flatten()
+            .filter_map(|setting| {
+                let position = |names: &[&str], key: &str| {
+                    names
+                        .iter()
+                        .position(|name| setting[key].as_str() == Some(*name))
+                        .map(|index| index as i32)
+                };
+                Some(proto::SafetySetting {
+                    category: position(HARM_CATEGORIES, "category")? + FIRST_HARM_CATEGORY,
+                    threshold: position(BLOCK_THRESHOLDS, "threshold")? + 1,
+                })
+            })
+            .collect(),
+    })
+}
+
+fn string_field(value: &Value, key: &str) -> String {
+    value
+        .get(key)
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string()
+}
+
+fn content_to_proto(content: &Content) -> Result<proto::Content, RustedChainError> {
+    use proto::part::Payload;
+    let parts = content
+        .parts
+        .iter()
+        .map(|part| {
+            let data = match part {
+                Part::Text { text } => Payload::Text(text.clone()),
+                Part::FunctionCall { function_call } => {
+                    Payload::FunctionCall(proto::FunctionCall {
+                        name: function_call.name.clone(),
+                        args: Some(to_struct(&function_call.args)),
+                    })
+                }
+                Part::FunctionResponse { function_response } => {
+                    Payload::FunctionResponse(proto::FunctionResponse {
+                        name: function_response.name.clone(),
+                        response: Some(to_struct(&function_response.response)),
+                    })
+                }
+                Part::InlineData { inline_data, .. } => Payload::InlineData(proto::Blob {
+                    mime_type: inline_data.mime_type.clone(),
+                    data: STANDARD
+                        .decode(&inline_data.data)
+                        .map_err(|e| RustedChainError::InvalidRequest(e.to_string()))?,
+                }),
+                Part::FileData { file_data, .. } => Payload::FileData(proto::FileData {
+                    mime_type: file_data.mime_type.clone(),
+                    file_uri: file_data.file_uri.clone(),
+                }),
+            };
+            Ok(proto::Part { data: Some(data) })
+        })
+        .collect::<Result<_, RustedChainError>>()?;
+    Ok(proto::Content {
+        parts,
+        role: content.role.clone().unwrap_or_default(),
+    })
+}
+
+fn from_proto(response: proto::GenerateContentResponse) -> GenerateContentResponse {
+    use proto::part::Payload;
+    let candidates = response
+        .candidates
+        .into_iter()
+        .map(|candidate| {
+            let content = candidate.content.unwrap_or_default();
+            let parts = content
+                .parts
+                .into_iter()
+                .filter_map(|part| match part.data? {
+                    Payload::Text(text) => Some(Part::Text { text }),
+                    Payload::FunctionCall(call) => Some(Part::FunctionCall {
+                        function_call: FunctionCallData {
+                            name: call.name,
+                            args: call.args.map(from_struct).unwrap_or_else(|| json!({})),
+                        },
+                    }),
+                    Payload::FunctionResponse(response) => Some(Part::FunctionResponse {
+                        function_response: FunctionResponseData {
+                            name: response.name,
+                            response: response.response.map(from_struct).unwrap_or(Value::Null),
+                        },
+                    }),
+                    Payload::InlineData(blob) => Some(Part::InlineData {
+                        inline_data: Blob {
+                            mime_type: blob.mime_type,
+                            data: STANDARD.encode(blob.data),
+                        },
+                        video_metadata: None,
+                    }),
+                    Payload::FileData(file) => Some(Part::FileData {
+                        file_data: FileData {
+                            mime_type: file.mime_type,
+                            file_uri: file.file_uri,
+                        },
+                        video_metadata: None,
+                    }),
+                })
+                .collect();
+            Candidate {
+                content: ContentResponse {
+                    parts,
+                    role: (!content.role.is_empty()).then_some(content.role),
+                },
+                finish_reason: candidate
+                    .finish_reason
+                    .map(|reason| enum_name(FINISH_REASONS, reason)),
+                safety_ratings: candidate.safety_ratings.iter().map(rating).collect(),
+            }
+        })
+        .collect();
+    GenerateContentResponse {
+        candidates: Some(candidates),
+        prompt_feedback: response.prompt_feedback.map(|feedback| {
+            json!({
+                "blockReason": (feedback.block_reason != 0)
+                    .then(|| enum_name(BLOCK_REASONS, feedback.block_reason)),
+                "safetyRatings": feedback.safety_ratings.iter().map(rating).collect::<Vec<_>>(),
+            })
+        }),
+        usage_metadata: response.usage_metadata.map(|usage| UsageMetadata {
+            prompt_token_count: usage.prompt_token_count.max(0) as u64,
+            candidates_token_count: usage.candidates_token_count.max(0) as u64,
+        }),
+    }
+}
+
+fn enum_name(names: &[&str], value: i32) -> String {
+    usize::try_from(value)
+        .ok()
+        .and_then(|index| names.get(index))
+        .map_or_else(|| value.to_string(), |name| name.to_string())
+}
+
+/// A safety rating as the REST API reports it.
+fn rating(rating: &proto::SafetyRating) -> Value {
+    let category = usize::try_from(rating.category - FIRST_HARM_CATEGORY)
+        .ok()
+        .and_then(|index| HARM_CATEGORIES.get(index))
+        .map_or_else(|| rating.category.to_string(), |name| name.to_string());
+    json!({
+        "category": category,
+        "probability": enum_name(HARM_PROBABILITIES, rating.probability),
+        "blocked": rating.blocked,
+    })
+}
+
+/// A JSON object as a protobuf `Struct`; other JSON is wrapped as `{"result": ...}`,
+/// matching what the REST path sends for non-object tool results.
+fn to_struct(value: &Value) -> prost_types::Struct {
+    match value {
+        Value::Object(map) => prost_types::Struct {
+            fields: map.iter().map(|(k, v)| (k.clone(), to_value(v))).collect(),
+        },
+        other => to_struct(&json!({ "result": other })),
+    }
+}
+
+fn to_value(value: &Value) -> prost_types::Value {
+    let kind = match value {
+        Value::Null => Kind::NullValue(0),
+        Value::Bool(b) => Kind::BoolValue(*b),
+        Value::Number(n) => Kind::NumberValue(n.as_f64().unwrap_or_default()),
+        Value::String(s) => Kind::StringValue(s.clone()),
+        Value::Array(items) => Kind::ListValue(prost_types::ListValue {
+            values: items.iter().map(to_value).collect(),
+        }),
+        Value::Object(_) => Kind::StructValue(to_struct(value)),
+    };
+    prost_types::Value { kind: Some(kind) }
+}
+
+fn from_struct(value: prost_types::Struct) -> Value {
+    Value::Object(
+        value
+            .fields
+            .into_iter()
+            .map(|(k, v)| (k, from_value(v)))
+            .collect(),
+    )
+}
+
+fn from_value(value: prost_types::Value) -> Value {
+    match value.kind {
+        None | Some(Kind::NullValue(_)) => Value::Null,
+        Some(Kind::BoolValue(b)) => Value::Bool(b),
+        // Protobuf numbers are doubles; integral ones come back as JSON integers.
+        Some(Kind::NumberValue(n)) if n.fract() == 0.0 && n.abs() < 9.0e15 => json!(n as i64),
+        Some(Kind::NumberValue(n)) => json!(n),
+        Some(Kind::StringValue(s)) => Value::String(s),
+        Some(Kind::ListValue(list)) => {
+            Value::Array(list.values.into_iter().map(from_value).collect())
+        }
+        Some(Kind::StructValue(s)) => from_struct(s),
+    }
+}
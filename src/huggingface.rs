@@ -0,0 +1,450 @@
+//! HuggingFace Inference Providers and Inference Endpoints.
+//!
+//! The `chat-completion` task uses the OpenAI-compatible Messages API, on the
+//! HuggingFace router or on a dedicated endpoint. The `text-generation` task sends a
+//! prompt rendered with a chat template to the text-generation API instead, for models
+//! served without a chat template.
+
+use crate::agent::{ChatClient, ToolRequest, Turn, Usage};
+use crate::chat_template::{ChatTemplate, HeldText};
+use crate::citations::Citation;
+use crate::error::RustedChainError;
+use crate::keys::ApiKey;
+use crate::media::Attachment;
+use crate::message_transforms::MessageTransform;
+use crate::openai::{Message, OpenAI};
+use crate::prefill;
+use crate::retry::RetryPolicy;
+use crate::signing::RequestSigner;
+use crate::stream::{SseParser, TextSink};
+use pyo3::exceptions::PyValueError;
+use pyo3::PyResult;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::env;
+use std::sync::Arc;
+
+const ROUTER: &str = "https://router.huggingface.co";
+
+/// Which HuggingFace API the model is called through.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum Task {
+    #[default]
+    ChatCompletion,
+    TextGeneration,
+}
+
+impl Task {
+    pub(crate) fn parse(value: &str) -> PyResult<Self> {
+        match value {
+            "chat-completion" => Ok(Self::ChatCompletion),
+            "text-generation" => Ok(Self::TextGeneration),
+            other => Err(PyValueError::new_err(format!(
+                "task must be 'chat-completion' or 'text-generation', got '{}'",
+                other
+            ))),
+        }
+    }
+}
+
+/// A text-generation response: a list with one generation from the serverless API, a
+/// bare object from a TGI endpoint.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Generations {
+    List(Vec<Generation>),
+    One(Generation),
+}
+
+#[derive(Deserialize)]
+struct Generation {
+    #[serde(default)]
+    generated_text: Option<String>,
+    details: Option<GenerationDetails>,
+}
+
+#[derive(Deserialize)]
+struct GenerationDetails {
+    #[serde(default)]
+    generated_tokens: u64,
+    /// `length` when generation stopped at `max_new_tokens`.
+    finish_reason: Option<String>,
+}
+
+/// One server-sent event of a streamed generation.
+#[derive(Deserialize)]
+struct GenerationChunk {
+    token: Option<GeneratedToken>,
+    details: Option<GenerationDetails>,
+}
+
+#[derive(Deserialize)]
+struct GeneratedToken {
+    text: String,
+    #[serde(default)]
+    special: bool,
+}
+
+#[derive(Clone)]
+pub struct HuggingFace {
+    /// Talks to the Messages API; also holds the model, token, tools and system prompt
+    /// used for text generation.
+    chat: OpenAI,
+    model: String,
+    endpoint: Option<String>,
+    inference_provider: Option<String>,
+    task: Task,
+    template: Option<Arc<ChatTemplate>>,
+    prefill: Option<String>,
+    temperature: Option<f64>,
+}
+
+impl Default for HuggingFace {
+    fn default() -> Self {
+        dotenv::dotenv().ok();
+        let mut client = Self {
+            chat: OpenAI::new()
+                .with_api_key(env::var("HF_TOKEN").unwrap_or_default().into())
+                .with_base_url(format!("{}/v1", ROUTER)),
+            model: "meta-llama/Llama-3.3-70B-Instruct".to_string(),
+            endpoint: None,
+            inference_provider: None,
+            task: Task::default(),
+            template: None,
+            prefill: None,
+            temperature: None,
+        };
+        client.route();
+        client
+    }
+}
+
+impl HuggingFace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub(crate) fn with_api_key(mut self, api_key: ApiKey) -> Self {
+        self.chat = self.chat.with_api_key(api_key);
+        self
+    }
+
+    pub fn with_model(mut self, model: String) -> Self {
+        self.model = model;
+        self.route();
+        self
+    }
+
+    /// URL of a dedicated Inference Endpoint (or any TGI server) instead of the router.
+    pub fn with_endpoint(mut self, endpoint: String) -> Self {
+        self.endpoint = Some(endpoint.trim_end_matches('/').to_string());
+        self.route();
+        self
+    }
+
+    /// Inference provider the router sends the model to, e.g. `"together"`, or
+    /// `"fastest"` / `"cheapest"`. By default the router picks.
+    pub fn with_inference_provider(mut self, provider: String) -> Self {
+        self.inference_provider = Some(provider);
+        self.route();
+        self
+    }
+
+    pub(crate) fn with_task(mut self, task: Task) -> Self {
+        self.task = task;
+        self
+    }
+
+    /// Template that renders conversations into prompts. Required for text generation;
+    /// with chat completion, prompts go to the endpoint's `/v1/completions`.
+    pub(crate) fn with_chat_template(mut self, template: Arc<ChatTemplate>) -> Self {
+        self.chat = self.chat.with_chat_template(template.clone());
+        self.template = Some(template);
+        self
+    }
+
+    pub fn with_tools(mut self, tools: Vec<Value>) -> Self {
+        self.chat = self.chat.with_tools(tools);
+        self
+    }
+
+    pub fn without_tools(mut self) -> Self {
+        self.chat = self.chat.without_tools();
+        self
+    }
+
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.chat = self.chat.with_retry_policy(retry);
+        self
+    }
+
+    /// Add `signer`'s headers to every request, right before it is sent.
+    pub fn with_signer(mut self, signer: RequestSigner) -> Self {
+        self.chat = self.chat.with_signer(signer);
+        self
+    }
+
+    pub fn with_system(mut self, system: String) -> Self {
+        self.chat = self.chat.with_system(system);
+        self
+    }
+
+    pub fn with_prefill(mut self, prefill: String) -> Self {
+        self.chat = self.chat.with_prefill(prefill.clone());
+        self.prefill = Some(prefill);
+        self
+    }
+
+    pub fn with_temperature(mut self, temperature: f64) -> Self {
+        self.chat = self.chat.with_temperature(temperature);
+        self.temperature = Some(temperature);
+        self
+    }
+
+    /// Rewrite the messages of every request with `transform`, whichever task serves it.
+    pub(crate) fn with_message_transform(mut self, transform: Arc<MessageTransform>) -> Self {
+        self.chat = self.chat.with_message_transform(transform, "huggingface");
+        self
+    }
+
+    /// Point the Messages API client at the endpoint or the router. Router models carry
+    /// the inference provider as a `:provider` suffix.
+    fn route(&mut self) {
+        let base_url = match &self.endpoint {
+            Some(endpoint) if endpoint.ends_with("/v1") => endpoint.clone(),
+            Some(endpoint) => format!("{}/v1", endpoint),
+            None => format!("{}/v1", ROUTER),
+        };
+        let model = match (&self.endpoint, &self.inference_provider) {
+            (None, Some(provider)) => format!("{}:{}", self.model, provider),
+            _ => self.model.clone(),
+        };
+        let chat = std::mem::take(&mut self.chat);
+        self.chat = chat.with_base_url(base_url).with_model(model);
+    }
+
+    /// Where text-generation requests go.
+    fn generation_url(&self) -> String {
+        match &self.endpoint {
+            Some(endpoint) => endpoint.trim_end_matches("/v1").to_string(),
+            None => format!("{}/hf-inference/models/{}", ROUTER, self.model),
+        }
+    }
+
+    async fn generate(
+        &self,
+        messages: Vec<Message>,
+        sink: Option<&TextSink>,
+    ) -> Result<(Turn, Message, Usage), RustedChainError> {
+        let template = self.template.as_ref().ok_or_else(|| {
+            RustedChainError::InvalidRequest(
+                "task='text-generation' needs a chat_template".to_string(),
+            )
+        })?;
+        let mut parameters = json!({ "return_full_text": false, "details": true });
+        if !template.stop.is_empty() {
+            parameters["stop"] = json!(template.stop);
+        }
+        if let Some(temperature) = self.temperature {
+            parameters["temperature"] = json!(temperature);
+        }
+        let body = json!({
+            "inputs": self.chat.template_prompt(template, &messages)?,
+            "parameters": parameters,
+            "stream": sink.is_some(),
+        });
+        let mut response = self.chat.post_url(&self.generation_url(), &body).await?;
+
+        let mut usage = Usage::default();
+        let mut finish_reason = None;
+        let text = match sink {
+            None => {
+                let generation = match response
+                    .json::<Generations>()
+                    .await
+                    .map_err(|e| RustedChainError::ParseError(e.to_string()))?
+                {
+                    Generations::List(list) => list.into_iter().next(),
+                    Generations::One(one) => Some(one),
+                }
+                .ok_or(RustedChainError::NoResponse)?;
+                if let Some(details) = generation.details {
+                    usage.output_tokens = details.generated_tokens;
+                    finish_reason = details.finish_reason;
+                }
+                generation
+                    .generated_text
+                    .ok_or(RustedChainError::NoResponse)?
+            }
+            Some(sink) => {
+                let mut parser = SseParser::default();
+                let mut held = HeldText::new(self.chat.has_tools());
+                while let Some(chunk) = response.chunk().await? {
+                    for data in parser.push(&chunk) {
+                        let chunk: GenerationChunk = serde_json::from_str(&data)
+                            .map_err(|e| RustedChainError::ParseError(e.to_string()))?;
+                        if let Some(details) = chunk.details {
+                            usage.output_tokens = details.generated_tokens;
+                            finish_reason = details.finish_reason;
+                        }
+                        match chunk.token {
+                            Some(token) if !token.special => held.push(&token.text, sink).await?,
+                            _ => {}
+                        }
+                    }
+                }
+                held.finish(sink).await?
+            }
+        };
+        let (response, mut message) = self.chat.template_turn(text)?;
+        message.truncated = finish_reason.as_deref() == Some("length");
+        let turn = prefill::ensure_prefix(self.prefill.as_deref(), response.into());
+        Ok((turn, message, usage))
+    }
+}
+
+impl ChatClient for HuggingFace {
+    type Message = Message;
+
+    fn provider(&self) -> &'static str {
+        "huggingface"
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn for_model(&self, model: &str) -> Self {
+        self.clone().with_model(model.to_string())
+    }
+
+    fn resumed(&self, partial: &str) -> Option<Self> {
+        Some(self.clone().with_prefill(partial.to_string()))
+    }
+
+    fn side_client(&self, model: &str, system: &str) -> Option<Self> {
+        let mut client = self.for_model(model);
+        let chat_model = client.chat.model().to_string();
+        client.chat = client.chat.side_client(&chat_model, system)?;
+        client.prefill = None;
+        Some(client)
+    }
+
+    fn api_key(&self) -> &ApiKey {
+        self.chat.api_key()
+    }
+
+    fn system(&self) -> Option<&str> {
+        self.chat.system()
+    }
+
+    fn user_message(text: &str) -> Message {
+        OpenAI::user_message(text)
+    }
+
+    fn assistant_message(text: &str) -> Message {
+        OpenAI::assistant_message(text)
+    }
+
+    fn tool_call_message(call: &ToolRequest) -> Message {
+        OpenAI::tool_call_message(call)
+    }
+
+    fn tool_result_message(call: &ToolRequest, result: Value) -> Message {
+        OpenAI::tool_result_message(call, result)
+    }
+
+    fn tool_result_text_message(call: &ToolRequest, result: Value, text: String) -> Message {
+        OpenAI::tool_result_text_message(call, result, text)
+    }
+
+    fn citations(message: &Message) -> Vec<Citation> {
+        OpenAI::citations(message)
+    }
+
+    fn truncated(message: &Message) -> bool {
+        OpenAI::truncated(message)
+    }
+
+    async fn exchange(
+        &self,
+        conversation: Vec<Message>,
+    ) -> Result<(Turn, Message, Usage), RustedChainError> {
+        match self.task {
+            Task::ChatCompletion => self.chat.exchange(conversation).await,
+            Task::TextGeneration => self.generate(conversation, None).await,
+        }
+    }
+
+    async fn exchange_stream(
+        &self,
+        conversation: Vec<Message>,
+        sink: &TextSink,
+    ) -> Result<(Turn, Message, Usage), RustedChainError> {
+        match self.task {
+            Task::ChatCompletion => self.chat.exchange_stream(conversation, sink).await,
+            Task::TextGeneration => self.generate(conversation, Some(sink)).await,
+        }
+    }
+
+    async fn warmup(&self, prime: bool) -> Result<(), RustedChainError> {
+        match self.task {
+            Task::ChatCompletion => ChatClient::warmup(&self.chat, prime).await,
+            Task::TextGeneration if prime => {
+                let body = json!({ "inputs": "Hi", "parameters": { "max_new_tokens": 1 } });
+                self.chat
+                    .post_url(&self.generation_url(), &body)
+                    .await?
+                    .bytes()
+                    .await?;
+                Ok(())
+            }
+            Task::TextGeneration => {
+                // Any answer leaves a warm connection; the body is read so the
+                // connection goes back to the pool.
+                crate::HTTP
+                    .get(self.generation_url())
+                    .bearer_auth(self.chat.api_key().current()?)
+                    .send()
+                    .await?
+                    .bytes()
+                    .await?;
+                Ok(())
+            }
+        }
+    }
+
+    async fn multimodal(
+        &self,
+        prompt: &str,
+        files: &[Attachment],
+        schema: Option<&Value>,
+    ) -> Result<(String, Usage), RustedChainError> {
+        match self.task {
+            Task::ChatCompletion => self.chat.multimodal(prompt, files, schema).await,
+            Task::TextGeneration => Err(RustedChainError::InvalidRequest(
+                "task='text-generation' does not accept files; use task='chat-completion' \
+                 with a vision model"
+                    .to_string(),
+            )),
+        }
+    }
+
+    async fn raw(&self, payload: Value, path: Option<&str>) -> Result<Value, RustedChainError> {
+        match (self.task, path) {
+            (Task::ChatCompletion, _) => self.chat.raw(payload, path).await,
+            (Task::TextGeneration, None) => self
+                .chat
+                .post_url(&self.generation_url(), &payload)
+                .await?
+                .json()
+                .await
+                .map_err(|e| RustedChainError::ParseError(e.to_string())),
+            (Task::TextGeneration, Some(_)) => Err(RustedChainError::InvalidRequest(
+                "task='text-generation' sends raw requests to the generation URL; \
+                 path is not accepted"
+                    .to_string(),
+            )),
+        }
+    }
+}
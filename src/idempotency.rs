@@ -0,0 +1,137 @@
+//! Idempotency keys, so that retrying a request cannot bill or run it twice.
+//!
+//! Every provider request carries one key through all of its retries; OpenAI receives
+//! it as the `Idempotency-Key` header and answers a repeat with the stored response.
+//! A call given an `idempotency_key` derives the key of each of its requests from it
+//! and the conversation sent, so running the same call again yields the same keys in
+//! the same order. Answers to those requests are also remembered in the process for
+//! `TTL`, up to the `CAPACITY` most recent, which stands in for the header with
+//! providers that don't support it.
+
+use once_cell::sync::Lazy;
+use serde::Serialize;
+use std::any::Any;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// How long an answer is remembered, as long as OpenAI keeps idempotent responses.
+const TTL: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// How many answers are remembered at most; storing another forgets the oldest.
+const CAPACITY: usize = 1024;
+
+tokio::task_local! {
+    /// The key of the request being made inside a `scoped` future.
+    static KEY: String;
+}
+
+type Remembered = (Instant, Box<dyn Any + Send>);
+
+/// Answers by request key, with when they were stored.
+#[derive(Default)]
+struct Answers(HashMap<String, Remembered>);
+
+impl Answers {
+    fn get<T: Clone + 'static>(&self, key: &str, now: Instant) -> Option<T> {
+        self.0
+            .get(key)
+            .filter(|(stored, _)| now.duration_since(*stored) < TTL)
+            .and_then(|(_, answer)| answer.downcast_ref::<T>().cloned())
+    }
+
+    /// Store `answer`, dropping expired answers and then, if still full, the oldest.
+    fn insert<T: Send + 'static>(&mut self, key: String, answer: T, now: Instant) {
+        self.0
+            .retain(|_, (stored, _)| now.duration_since(*stored) < TTL);
+        if self.0.len() >= CAPACITY && !self.0.contains_key(&key) {
+            let oldest = self
+                .0
+                .iter()
+                .min_by_key(|(_, (stored, _))| *stored)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                self.0.remove(&oldest);
+            }
+        }
+        self.0.insert(key, (now, Box::new(answer)));
+    }
+}
+
+static ANSWERS: Lazy<Mutex<Answers>> = Lazy::new(Mutex::default);
+
+/// The key of a request sending `messages` to `model` for a call keyed `base`.
+pub(crate) fn request_key<M: Serialize>(base: &str, model: &str, messages: &[M]) -> String {
+    let messages = serde_json::to_string(messages).unwrap_or_default();
+    let name = format!("{}\n{}\n{}", base, model, messages);
+    Uuid::new_v5(&Uuid::NAMESPACE_OID, name.as_bytes()).to_string()
+}
+
+/// Run `future` with `key` as the key of the request it makes.
+pub(crate) async fn scoped<F: Future>(key: Option<String>, future: F) -> F::Output {
+    match key {
+        Some(key) => KEY.scope(key, future).await,
+        None => future.await,
+    }
+}
+
+/// The key to send a request with: the scoped one, or a new one for a request made
+/// outside a keyed call. Taken once per request, before its retries.
+pub(crate) fn current() -> String {
+    KEY.try_with(String::clone)
+        .unwrap_or_else(|_| Uuid::new_v4().to_string())
+}
+
+/// The answer remembered for the request keyed `key`, if it hasn't expired.
+pub(crate) fn recall<T: Clone + 'static>(key: &str) -> Option<T> {
+    let answers = ANSWERS.lock().unwrap_or_else(|e| e.into_inner());
+    answers.get(key, Instant::now())
+}
+
+/// Remember `answer` to the request keyed `key`.
+pub(crate) fn remember<T: Send + 'static>(key: String, answer: T) {
+    let mut answers = ANSWERS.lock().unwrap_or_else(|e| e.into_inner());
+    answers.insert(key, answer, Instant::now());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn answers_expire_after_the_ttl() {
+        let start = Instant::now();
+        let mut answers = Answers::default();
+        answers.insert("a".to_string(), 1u32, start);
+        assert_eq!(answers.get::<u32>("a", start + TTL / 2), Some(1));
+        assert_eq!(answers.get::<u32>("a", start + TTL), None);
+        assert_eq!(answers.get::<String>("a", start), None);
+
+        answers.insert("b".to_string(), 2u32, start + TTL);
+        assert_eq!(answers.0.len(), 1);
+    }
+
+    #[test]
+    fn storing_past_capacity_forgets_the_oldest_answer() {
+        let start = Instant::now();
+        let mut answers = Answers::default();
+        for n in 0..=CAPACITY {
+            let stored = start + Duration::from_secs(n as u64);
+            answers.insert(n.to_string(), n, stored);
+        }
+        assert_eq!(answers.0.len(), CAPACITY);
+        assert_eq!(answers.get::<usize>("0", start), None);
+        assert_eq!(
+            answers.get::<usize>("1", start + Duration::from_secs(1)),
+            Some(1)
+        );
+
+        // Storing an answer again replaces it rather than forgetting another.
+        let later = start + Duration::from_secs(CAPACITY as u64 + 1);
+        answers.insert("1".to_string(), 1, later);
+        assert_eq!(answers.0.len(), CAPACITY);
+        assert_eq!(answers.get::<usize>("2", later), Some(2));
+    }
+}
@@ -0,0 +1,161 @@
+//! Agent instructions: the task description given with `instructions`, followed by
+//! guidance on using tools, worded the way each provider recommends.
+//!
+//! `system` is sent untouched; `instructions` is what the agent loop augments. The
+//! guidance has three parts, each replaceable through `tool_guidance`: when to call a
+//! tool, when to answer directly, and how to present the final answer. Agents without
+//! tools get the instructions alone.
+
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBool, PyDict};
+
+const PARTS: [&str; 3] = ["tools", "direct", "final"];
+
+/// How the guidance after `instructions` is chosen.
+#[derive(Clone, Default)]
+pub(crate) enum ToolGuidance {
+    /// The provider's defaults.
+    #[default]
+    Default,
+    /// No guidance, only the instructions.
+    Off,
+    /// This text replaces the guidance.
+    Text(String),
+    /// Parts replaced by name (`"tools"`, `"direct"`, `"final"`); `None` drops a part.
+    Parts(Vec<(&'static str, Option<String>)>),
+}
+
+impl ToolGuidance {
+    /// Parse the `tool_guidance` option: `True`, `False`, a string or a dict of parts.
+    pub(crate) fn parse(value: &Bound<'_, PyAny>) -> PyResult<Self> {
+        if value.is_none() {
+            return Ok(Self::Default);
+        }
+        if value.is_instance_of::<PyBool>() {
+            return Ok(if value.extract()? {
+                Self::Default
+            } else {
+                Self::Off
+            });
+        }
+        if let Ok(text) = value.extract::<String>() {
+            return Ok(Self::Text(text));
+        }
+        let Ok(parts) = value.cast::<PyDict>() else {
+            return Err(PyTypeError::new_err(
+                "tool_guidance must be a bool, a string or a dict",
+            ));
+        };
+        let mut overrides = Vec::new();
+        for (key, text) in parts.iter() {
+            let key: String = key.extract()?;
+            let part = PARTS.into_iter().find(|part| *part == key).ok_or_else(|| {
+                PyValueError::new_err(format!(
+                    "unknown tool_guidance part '{}' (expected 'tools', 'direct' or 'final')",
+                    key
+                ))
+            })?;
+            overrides.push((part, text.extract()?));
+        }
+        Ok(Self::Parts(overrides))
+    }
+}
+
+/// Best-practice guidance for `provider`, as `(part, text)`.
+fn defaults(provider: &str) -> [(&'static str, &'static str); 3] {
+    match provider {
+        "anthropic" => [
+            (
+                "tools",
+                "Use a tool whenever the request depends on information or actions you do not \
+                 have. If several independent lookups are needed, make them one after another \
+                 rather than guessing any of the results.",
+            ),
+            (
+                "direct",
+                "If you can answer fully from the conversation and your own knowledge, answer \
+                 directly without calling a tool.",
+            ),
+            (
+                "final",
+                "Once you have what you need, write the final answer for the user. Do not \
+                 mention the tools or describe how you called them.",
+            ),
+        ],
+        "gemini" => [
+            (
+                "tools",
+                "Call a function when the request needs data or actions it provides. Don't \
+                 make assumptions about the values of function arguments; if a required \
+                 value is missing or ambiguous, ask the user instead.",
+            ),
+            (
+                "direct",
+                "Answer without calling a function when the request does not need one.",
+            ),
+            (
+                "final",
+                "After the function results arrive, answer the user's request in plain \
+                 language, without referring to the functions.",
+            ),
+        ],
+        _ => [
+            (
+                "tools",
+                "Use the available tools to look up information or take actions instead of \
+                 guessing. Call a tool only with arguments taken from the conversation or \
+                 from earlier tool results.",
+            ),
+            (
+                "direct",
+                "If the question can be answered without a tool, answer it directly.",
+            ),
+            (
+                "final",
+                "When you have enough information, reply with the final answer only. Do not \
+                 describe the tool calls you made.",
+            ),
+        ],
+    }
+}
+
+/// `instructions` followed by the tool-use guidance for `provider`. Claude is given the
+/// guidance in XML tags, which it is trained to pay attention to.
+pub(crate) fn render(
+    instructions: &str,
+    guidance: &ToolGuidance,
+    provider: &str,
+    has_tools: bool,
+) -> String {
+    let lines: Vec<String> = match guidance {
+        _ if !has_tools => Vec::new(),
+        ToolGuidance::Off => Vec::new(),
+        ToolGuidance::Text(text) => vec![text.clone()],
+        ToolGuidance::Default | ToolGuidance::Parts(_) => defaults(provider)
+            .into_iter()
+            .filter_map(|(part, text)| {
+                let overridden = match guidance {
+                    ToolGuidance::Parts(parts) => parts.iter().find(|(name, _)| *name == part),
+                    _ => None,
+                };
+                match overridden {
+                    Some((_, text)) => text.clone(),
+                    None => Some(text.to_string()),
+                }
+            })
+            .collect(),
+    };
+    if lines.is_empty() {
+        return instructions.to_string();
+    }
+    let guidance = lines.join("\n");
+    if provider == "anthropic" {
+        format!(
+            "<instructions>\n{}\n</instructions>\n\n<guidance>\n{}\n</guidance>",
+            instructions, guidance
+        )
+    } else {
+        format!("{}\n\n{}", instructions, guidance)
+    }
+}
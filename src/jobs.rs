@@ -0,0 +1,335 @@
+//! Long-running requests that are submitted now and collected later.
+//!
+//! OpenAI runs these as background responses: the request is stored server-side and
+//! polled by id, so no connection is held open while a reasoning model works. Other
+//! providers have no equivalent, so the request runs on a background thread instead.
+
+use crate::agent::{self, ChatClient};
+use crate::claude::Claude;
+use crate::error::RustedChainError;
+use crate::gemini::Gemini;
+use crate::huggingface::HuggingFace;
+use crate::metrics;
+use crate::openai::{BackgroundResponse, OpenAI};
+use crate::options::AgentOptions;
+use crate::run::{Labels, RunRecord};
+use crate::RUNTIME;
+use pyo3::exceptions::{PyTimeoutError, PyValueError};
+use pyo3::prelude::*;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tokio::sync::oneshot;
+
+enum Backend {
+    /// An OpenAI background response, polled by id.
+    OpenAI { client: Box<OpenAI> },
+    /// A request running on a background thread.
+    Local {
+        done: Mutex<oneshot::Receiver<Result<String, RustedChainError>>>,
+        cancel: Mutex<Option<oneshot::Sender<()>>>,
+    },
+}
+
+/// A request submitted with `submit_long_running()`. Poll it with `status()` or
+/// `done()`, block on it with `result()`, or stop it with `cancel()`.
+#[pyclass]
+pub struct LongRunningJob {
+    /// The OpenAI response id, or a generated id for other providers.
+    #[pyo3(get)]
+    id: String,
+    backend: Backend,
+    /// The run to record once an OpenAI response finishes; `None` for jobs resumed by
+    /// id and for local jobs, which record their own.
+    pending: Mutex<Option<(RunRecord, Instant)>>,
+    /// The final status and outcome, once known.
+    outcome: Mutex<Option<(String, Result<String, PyErr>)>>,
+}
+
+impl LongRunningJob {
+    /// Submit `prompt` as an OpenAI background response.
+    pub(crate) fn submit_openai(
+        py: Python,
+        client: OpenAI,
+        options: &AgentOptions,
+        prompt: &str,
+        labels: Labels,
+    ) -> PyResult<Self> {
+        let run = RunRecord::start(&client, options, prompt, Vec::new(), false, labels);
+        let submitted = Instant::now();
+        let response = py.detach(|| RUNTIME.block_on(client.submit_background(prompt)))?;
+        let job = LongRunningJob {
+            id: response.id.clone(),
+            backend: Backend::OpenAI {
+                client: Box::new(client),
+            },
+            pending: Mutex::new(Some((run, submitted))),
+            outcome: Mutex::new(None),
+        };
+        job.settle(response);
+        Ok(job)
+    }
+
+    /// Pick up a background response submitted earlier, possibly by another process.
+    pub(crate) fn resume_openai(client: OpenAI, id: String) -> Self {
+        LongRunningJob {
+            id,
+            backend: Backend::OpenAI {
+                client: Box::new(client),
+            },
+            pending: Mutex::new(None),
+            outcome: Mutex::new(None),
+        }
+    }
+
+    /// Run `prompt` on a background thread, for providers without background mode.
+    pub(crate) fn spawn<C>(
+        client: C,
+        options: &AgentOptions,
+        prompt: String,
+        labels: Labels,
+    ) -> Self
+    where
+        C: ChatClient + Send + 'static,
+    {
+        let options = options.clone();
+        let run = RunRecord::start(&client, &options, &prompt, Vec::new(), false, labels);
+        let id = run.id.clone();
+        let (done_tx, done_rx) = oneshot::channel();
+        let (cancel_tx, cancel_rx) = oneshot::channel::<()>();
+
+        std::thread::spawn(move || {
+            let mut run = run;
+            let result = RUNTIME.block_on(async {
+                tokio::select! {
+                    result = agent::complete(&client, &options, &run, &prompt) => result,
+                    _ = cancel_rx => Err(RustedChainError::Cancelled),
+                }
+            });
+            if let Ok((_, usage)) = &result {
+                run.record_call(*usage);
+            }
+            run.finish(match &result {
+                Ok((text, _)) => Ok(text.clone()),
+                Err(err) => Err(err.to_string()),
+            });
+            let _ = done_tx.send(result.map(|(text, _)| text));
+        });
+
+        LongRunningJob {
+            id,
+            backend: Backend::Local {
+                done: Mutex::new(done_rx),
+                cancel: Mutex::new(Some(cancel_tx)),
+            },
+            pending: Mutex::new(None),
+            outcome: Mutex::new(None),
+        }
+    }
+
+    /// Store the outcome of a finished OpenAI response and record its run. Does
+    /// nothing while the response is still queued or in progress.
+    fn settle(&self, response: BackgroundResponse) {
+        if !response.is_terminal() {
+            return;
+        }
+        let result = response.outcome();
+        if let Backend::OpenAI { client } = &self.backend {
+            if let Some((mut run, submitted)) = self.pending.lock().unwrap().take() {
+                let usage = response.usage();
+                let status = match &result {
+                    Ok(_) => "ok",
+                    Err(err) => err.kind(),
+                };
+                metrics::record_request(
+                    client.provider(),
+                    client.model(),
+                    status,
+                    submitted.elapsed(),
+                    usage,
+                );
+                run.record_call(usage);
+                run.finish(match &result {
+                    Ok(text) => Ok(text.clone()),
+                    Err(err) => Err(err.to_string()),
+                });
+            }
+        }
+        self.store(result);
+    }
+
+    fn store(&self, result: Result<String, RustedChainError>) {
+        let status = match &result {
+            Ok(_) => "completed",
+            Err(RustedChainError::Cancelled) => "cancelled",
+            Err(_) => "failed",
+        };
+        let mut outcome = self.outcome.lock().unwrap();
+        if outcome.is_none() {
+            *outcome = Some((status.to_string(), result.map_err(PyErr::from)));
+        }
+    }
+
+    fn status_now(&self) -> Option<String> {
+        self.outcome
+            .lock()
+            .unwrap()
+            .as_ref()
+            .map(|(status, _)| status.clone())
+    }
+
+    /// Check for completion without blocking, returning the current status.
+    fn poll(&self, py: Python) -> PyResult<String> {
+        if let Some(status) = self.status_now() {
+            return Ok(status);
+        }
+        match &self.backend {
+            Backend::OpenAI { client, .. } => {
+                let response =
+                    py.detach(|| RUNTIME.block_on(client.retrieve_background(&self.id)))?;
+                let status = response.status.clone();
+                self.settle(response);
+                Ok(self.status_now().unwrap_or(status))
+            }
+            Backend::Local { done, .. } => {
+                match done.lock().unwrap().try_recv() {
+                    Ok(result) => self.store(result),
+                    Err(oneshot::error::TryRecvError::Empty) => {
+                        return Ok("in_progress".to_string())
+                    }
+                    Err(oneshot::error::TryRecvError::Closed) => {
+                        self.store(Err(RustedChainError::NoResponse))
+                    }
+                }
+                Ok(self.status_now().unwrap_or_default())
+            }
+        }
+    }
+
+    /// Wait up to `timeout` for a local job to finish.
+    fn wait_local(&self, py: Python, timeout: Duration) {
+        let Backend::Local { done, .. } = &self.backend else {
+            return;
+        };
+        let result = py.detach(|| {
+            let mut done = done.lock().unwrap();
+            RUNTIME.block_on(async { tokio::time::timeout(timeout, &mut *done).await.ok() })
+        });
+        match result {
+            Some(Ok(result)) => self.store(result),
+            Some(Err(_)) => self.store(Err(RustedChainError::NoResponse)),
+            None => {}
+        }
+    }
+}
+
+/// Clients `submit_long_running()` can hand a request to.
+pub(crate) trait Submit: ChatClient + Send + Sized + 'static {
+    /// Start `prompt` as a job, by default on a background thread.
+    fn submit(
+        self,
+        _py: Python,
+        options: &AgentOptions,
+        prompt: String,
+        labels: Labels,
+    ) -> PyResult<LongRunningJob> {
+        Ok(LongRunningJob::spawn(self, options, prompt, labels))
+    }
+}
+
+impl Submit for OpenAI {
+    fn submit(
+        self,
+        py: Python,
+        options: &AgentOptions,
+        prompt: String,
+        labels: Labels,
+    ) -> PyResult<LongRunningJob> {
+        LongRunningJob::submit_openai(py, self, options, &prompt, labels)
+    }
+}
+
+impl Submit for Claude {}
+impl Submit for Gemini {}
+impl Submit for HuggingFace {}
+
+#[pymethods]
+impl LongRunningJob {
+    /// `queued`, `in_progress`, `completed`, `failed` or `cancelled`.
+    fn status(&self, py: Python) -> PyResult<String> {
+        self.poll(py)
+    }
+
+    /// Whether the job has finished, successfully or not.
+    fn done(&self, py: Python) -> PyResult<bool> {
+        let status = self.poll(py)?;
+        Ok(!matches!(status.as_str(), "queued" | "in_progress"))
+    }
+
+    /// Block until the job finishes and return its answer, raising its error if it
+    /// failed. Polls every `poll_interval` seconds; raises `TimeoutError` if it is
+    /// still running after `timeout` seconds.
+    #[pyo3(signature = (timeout=None, poll_interval=2.0))]
+    fn result(&self, py: Python, timeout: Option<f64>, poll_interval: f64) -> PyResult<String> {
+        if poll_interval <= 0.0 {
+            return Err(PyValueError::new_err("poll_interval must be positive"));
+        }
+        let deadline = timeout.map(|t| Instant::now() + Duration::from_secs_f64(t.max(0.0)));
+        loop {
+            if let Some((_, result)) = self.outcome.lock().unwrap().as_ref() {
+                return match result {
+                    Ok(text) => Ok(text.clone()),
+                    Err(err) => Err(err.clone_ref(py)),
+                };
+            }
+            let mut wait = Duration::from_secs_f64(poll_interval);
+            if let Some(deadline) = deadline {
+                let remaining = deadline.saturating_duration_since(Instant::now());
+                if remaining.is_zero() {
+                    return Err(PyTimeoutError::new_err(format!(
+                        "job {} is still running",
+                        self.id
+                    )));
+                }
+                wait = wait.min(remaining);
+            }
+            match &self.backend {
+                Backend::OpenAI { .. } => {
+                    if self.status_now().is_none() {
+                        self.poll(py)?;
+                    }
+                    if self.status_now().is_none() {
+                        py.detach(|| std::thread::sleep(wait));
+                    }
+                }
+                Backend::Local { .. } => self.wait_local(py, wait),
+            }
+        }
+    }
+
+    /// Ask the provider to stop the job. Finished jobs are left as they are.
+    fn cancel(&self, py: Python) -> PyResult<()> {
+        if self.status_now().is_some() {
+            return Ok(());
+        }
+        match &self.backend {
+            Backend::OpenAI { client, .. } => {
+                let response =
+                    py.detach(|| RUNTIME.block_on(client.cancel_background(&self.id)))?;
+                self.settle(response);
+            }
+            Backend::Local { cancel, .. } => {
+                if let Some(cancel) = cancel.lock().unwrap().take() {
+                    let _ = cancel.send(());
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn __repr__(&self) -> String {
+        match self.status_now() {
+            Some(status) => format!("LongRunningJob(id='{}', status='{}')", self.id, status),
+            None => format!("LongRunningJob(id='{}')", self.id),
+        }
+    }
+}
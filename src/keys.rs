@@ -0,0 +1,377 @@
+//! API keys: one key, a pool of keys that requests rotate across, or a callback
+//! handing out short-lived tokens.
+
+use crate::agent::Usage;
+use crate::audit;
+use crate::deadline;
+use crate::dry_run;
+use crate::error::RustedChainError;
+use crate::signing::RequestSigner;
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use reqwest::{RequestBuilder, Response, StatusCode};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// A cached token is fetched again this long before it expires.
+const EXPIRY_MARGIN: Duration = Duration::from_secs(30);
+
+tokio::task_local! {
+    /// The pooled key that last sent a request inside an `attributed` future.
+    static SERVED: RefCell<Option<Served>>;
+}
+
+/// The key a client authenticates with.
+#[derive(Clone)]
+pub(crate) enum ApiKey {
+    Single(String),
+    Pool(Arc<Pool>),
+    Callback(Arc<TokenSource>),
+}
+
+impl From<String> for ApiKey {
+    fn from(key: String) -> Self {
+        ApiKey::Single(key)
+    }
+}
+
+impl<'py> FromPyObject<'_, 'py> for ApiKey {
+    type Error = PyErr;
+
+    fn extract(key: Borrowed<'_, 'py, PyAny>) -> PyResult<Self> {
+        if let Ok(pool) = key.cast::<KeyPool>() {
+            return Ok(ApiKey::Pool(pool.get().pool.clone()));
+        }
+        if let Ok(key) = key.extract::<String>() {
+            return Ok(ApiKey::Single(key));
+        }
+        if key.is_callable() {
+            return Ok(ApiKey::Callback(Arc::new(TokenSource {
+                callback: key.to_owned().unbind(),
+                cached: Mutex::new(None),
+            })));
+        }
+        match key.extract::<Vec<String>>() {
+            Ok(keys) => Ok(ApiKey::Pool(Arc::new(Pool::new(
+                keys,
+                Rotation::RoundRobin,
+            )?))),
+            Err(_) => Err(PyTypeError::new_err(
+                "api_key must be a string, a list of strings, a KeyPool or a callable",
+            )),
+        }
+    }
+}
+
+impl ApiKey {
+    /// The key the next request would use, fetching a token if none is cached.
+    pub(crate) fn current(&self) -> Result<Cow<'_, str>, RustedChainError> {
+        match self {
+            ApiKey::Single(key) => Ok(Cow::Borrowed(key)),
+            ApiKey::Pool(pool) => Ok(Cow::Borrowed(
+                &pool.keys[pool.next.load(Ordering::Relaxed) % pool.keys.len()],
+            )),
+            ApiKey::Callback(source) => source.token().map(Cow::Owned),
+        }
+    }
+
+    /// The current key reduced to its last four characters, for logs. Never calls a
+    /// token callback.
+    pub(crate) fn redacted(&self) -> Option<String> {
+        match self {
+            ApiKey::Callback(source) => source.cached().and_then(|token| audit::redact_key(&token)),
+            key => audit::redact_key(&key.current().ok()?),
+        }
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        match self {
+            ApiKey::Callback(_) => false,
+            key => key.current().is_ok_and(|key| key.is_empty()),
+        }
+    }
+
+    /// Take a key for one request. With a pool, report how it went with `record`.
+    pub(crate) fn take(&self) -> Result<(usize, Cow<'_, str>), RustedChainError> {
+        match self {
+            ApiKey::Pool(pool) => {
+                let index = pool.pick();
+                Ok((index, Cow::Borrowed(&pool.keys[index])))
+            }
+            key => Ok((0, key.current()?)),
+        }
+    }
+
+    /// Count a request sent with key `index`; a rate-limited one moves an `"on_429"`
+    /// pool to its next key.
+    pub(crate) fn record(&self, index: usize, rate_limited: bool) {
+        let ApiKey::Pool(pool) = self else {
+            return;
+        };
+        pool.record(index, rate_limited);
+        let _ = SERVED.try_with(|served| {
+            *served.borrow_mut() = Some(Served {
+                pool: pool.clone(),
+                index,
+            })
+        });
+    }
+
+    /// Send the request `build` makes with the next key, counting it against that key,
+    /// signed by `signer` if there is one. A token the provider rejects as unauthorized
+    /// is fetched again and the request resent once. In a dry run, the request is
+    /// captured instead.
+    pub(crate) async fn send(
+        &self,
+        signer: Option<&RequestSigner>,
+        build: impl Fn(&str) -> RequestBuilder,
+    ) -> Result<Response, RustedChainError> {
+        let signed = |request: RequestBuilder| match signer {
+            Some(signer) => signer.sign(request),
+            None => Ok(request),
+        };
+        if dry_run::active() {
+            return Err(dry_run::capture(signed(build(dry_run::API_KEY))?));
+        }
+        let (index, key) = self.take()?;
+        let mut response = signed(build(&key))?.send().await?;
+        if let ApiKey::Callback(source) = self {
+            if response.status() == StatusCode::UNAUTHORIZED {
+                source.expire();
+                response = signed(build(&source.token()?))?.send().await?;
+                note_started(&response);
+                return Ok(response);
+            }
+        }
+        self.record(index, response.status() == StatusCode::TOO_MANY_REQUESTS);
+        note_started(&response);
+        Ok(response)
+    }
+
+    /// `record` for a request whose outcome is only known as a result.
+    #[cfg(feature = "grpc")]
+    pub(crate) fn record_result<T>(&self, index: usize, result: &Result<T, RustedChainError>) {
+        self.record(
+            index,
+            matches!(result, Err(RustedChainError::RateLimited { .. })),
+        );
+    }
+}
+
+/// Note a response as started for `first_token_deadline_ms`, unless it is an event
+/// stream, which starts with its first event.
+fn note_started(response: &Response) {
+    let streamed = response
+        .headers()
+        .get(reqwest::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/event-stream"));
+    if !streamed {
+        deadline::started();
+    }
+}
+
+/// A Python callable returning a token, or a `(token, expires_in_seconds)` tuple. The
+/// token is cached until shortly before it expires, or until the provider rejects it.
+pub(crate) struct TokenSource {
+    callback: Py<PyAny>,
+    cached: Mutex<Option<(String, Option<Instant>)>>,
+}
+
+impl TokenSource {
+    fn cached(&self) -> Option<String> {
+        let cached = self.cached.lock().unwrap_or_else(|e| e.into_inner());
+        match &*cached {
+            Some((token, expires))
+                if expires.is_none_or(|at| Instant::now() + EXPIRY_MARGIN < at) =>
+            {
+                Some(token.clone())
+            }
+            _ => None,
+        }
+    }
+
+    fn token(&self) -> Result<String, RustedChainError> {
+        if let Some(token) = self.cached() {
+            return Ok(token);
+        }
+        // The lock is not held while Python runs, so a callback that takes the GIL
+        // from another thread cannot deadlock with us.
+        let (token, expires_in) = Python::attach(|py| -> PyResult<(String, Option<f64>)> {
+            let value = self.callback.bind(py).call0()?;
+            match value.extract::<String>() {
+                Ok(token) => Ok((token, None)),
+                Err(_) => value.extract().map_err(|_| {
+                    PyTypeError::new_err(
+                        "api_key callback must return a token or a (token, expires_in_seconds) tuple",
+                    )
+                }),
+            }
+        })
+        .map_err(|err| RustedChainError::Credentials(err.to_string()))?;
+        let expires =
+            expires_in.map(|seconds| Instant::now() + Duration::from_secs_f64(seconds.max(0.0)));
+        *self.cached.lock().unwrap_or_else(|e| e.into_inner()) = Some((token.clone(), expires));
+        Ok(token)
+    }
+
+    fn expire(&self) {
+        *self.cached.lock().unwrap_or_else(|e| e.into_inner()) = None;
+    }
+}
+
+/// The pooled key that served a request, for attributing its token usage.
+pub(crate) struct Served {
+    pool: Arc<Pool>,
+    index: usize,
+}
+
+impl Served {
+    pub(crate) fn add_usage(&self, usage: Usage) {
+        let mut tallies = self.pool.tallies.lock().unwrap_or_else(|e| e.into_inner());
+        let tally = &mut tallies[self.index];
+        tally.input_tokens += usage.input_tokens;
+        tally.output_tokens += usage.output_tokens;
+    }
+}
+
+/// Await `future`, returning the pooled key that sent its last request, if any.
+pub(crate) async fn attributed<F: Future>(future: F) -> (F::Output, Option<Served>) {
+    SERVED
+        .scope(RefCell::new(None), async {
+            let output = future.await;
+            (output, SERVED.with(RefCell::take))
+        })
+        .await
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Rotation {
+    /// Each request takes the next key.
+    RoundRobin,
+    /// Stay on a key until it is rate limited.
+    On429,
+}
+
+#[derive(Clone, Copy, Default)]
+struct Tally {
+    requests: u64,
+    rate_limited: u64,
+    input_tokens: u64,
+    output_tokens: u64,
+}
+
+pub(crate) struct Pool {
+    keys: Vec<String>,
+    rotation: Rotation,
+    next: AtomicUsize,
+    tallies: Mutex<Vec<Tally>>,
+}
+
+impl Pool {
+    fn new(keys: Vec<String>, rotation: Rotation) -> PyResult<Self> {
+        if keys.is_empty() {
+            return Err(PyValueError::new_err("a key pool needs at least one key"));
+        }
+        Ok(Pool {
+            tallies: Mutex::new(vec![Tally::default(); keys.len()]),
+            keys,
+            rotation,
+            next: AtomicUsize::new(0),
+        })
+    }
+
+    fn pick(&self) -> usize {
+        match self.rotation {
+            Rotation::RoundRobin => self.next.fetch_add(1, Ordering::Relaxed) % self.keys.len(),
+            Rotation::On429 => self.next.load(Ordering::Relaxed) % self.keys.len(),
+        }
+    }
+
+    fn record(&self, index: usize, rate_limited: bool) {
+        let mut tallies = self.tallies.lock().unwrap_or_else(|e| e.into_inner());
+        tallies[index].requests += 1;
+        if rate_limited {
+            tallies[index].rate_limited += 1;
+            if self.rotation == Rotation::On429 {
+                // Concurrent requests may hit the limit together; move on only once.
+                let _ = self.next.compare_exchange(
+                    index,
+                    (index + 1) % self.keys.len(),
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                );
+            }
+        }
+    }
+}
+
+/// Several API keys for one provider, shared by every model given the pool.
+#[pyclass(frozen)]
+pub struct KeyPool {
+    pool: Arc<Pool>,
+}
+
+#[pymethods]
+impl KeyPool {
+    /// `rotation` is `"round_robin"` (each request takes the next key) or `"on_429"`
+    /// (stay on a key until it is rate limited).
+    #[new]
+    #[pyo3(signature = (keys, rotation="round_robin"))]
+    fn new(keys: Vec<String>, rotation: &str) -> PyResult<Self> {
+        let rotation = match rotation {
+            "round_robin" => Rotation::RoundRobin,
+            "on_429" => Rotation::On429,
+            other => {
+                return Err(PyValueError::new_err(format!(
+                    "rotation must be 'round_robin' or 'on_429', got '{}'",
+                    other
+                )))
+            }
+        };
+        Ok(KeyPool {
+            pool: Arc::new(Pool::new(keys, rotation)?),
+        })
+    }
+
+    /// Per key, in pool order: `{"key", "requests", "rate_limited", "input_tokens",
+    /// "output_tokens"}`, with the key reduced to its last four characters.
+    fn usage<'py>(&self, py: Python<'py>) -> PyResult<Vec<Bound<'py, PyDict>>> {
+        let tallies = self.pool.tallies.lock().unwrap_or_else(|e| e.into_inner());
+        self.pool
+            .keys
+            .iter()
+            .zip(tallies.iter())
+            .map(|(key, tally)| {
+                let entry = PyDict::new(py);
+                entry.set_item("key", audit::redact_key(key))?;
+                entry.set_item("requests", tally.requests)?;
+                entry.set_item("rate_limited", tally.rate_limited)?;
+                entry.set_item("input_tokens", tally.input_tokens)?;
+                entry.set_item("output_tokens", tally.output_tokens)?;
+                Ok(entry)
+            })
+            .collect()
+    }
+
+    fn __len__(&self) -> usize {
+        self.pool.keys.len()
+    }
+
+    fn __repr__(&self) -> String {
+        let rotation = match self.pool.rotation {
+            Rotation::RoundRobin => "round_robin",
+            Rotation::On429 => "on_429",
+        };
+        format!(
+            "KeyPool(keys={}, rotation='{}')",
+            self.pool.keys.len(),
+            rotation
+        )
+    }
+}
@@ -0,0 +1,166 @@
+//! Language hints: instruction text for the `language` option and lightweight detection
+//! of the language a query was written in.
+
+const LANGUAGE_NAMES: &[(&str, &str)] = &[
+    ("ar", "Arabic"),
+    ("de", "German"),
+    ("el", "Greek"),
+    ("en", "English"),
+    ("es", "Spanish"),
+    ("fr", "French"),
+    ("he", "Hebrew"),
+    ("hi", "Hindi"),
+    ("it", "Italian"),
+    ("ja", "Japanese"),
+    ("ko", "Korean"),
+    ("nl", "Dutch"),
+    ("pl", "Polish"),
+    ("pt", "Portuguese"),
+    ("ru", "Russian"),
+    ("sv", "Swedish"),
+    ("th", "Thai"),
+    ("tr", "Turkish"),
+    ("uk", "Ukrainian"),
+    ("zh", "Chinese"),
+];
+
+/// Stopwords that are frequent in one Latin-script language and rare in the others.
+const STOPWORDS: &[(&str, &[&str])] = &[
+    (
+        "en",
+        &[
+            "the", "and", "is", "are", "what", "how", "of", "to", "you", "with", "this", "that",
+        ],
+    ),
+    (
+        "de",
+        &[
+            "der", "die", "und", "ist", "nicht", "das", "ich", "wie", "mit", "ein", "eine", "was",
+        ],
+    ),
+    (
+        "fr",
+        &[
+            "le", "la", "les", "et", "est", "une", "des", "je", "pas", "que", "qui", "pour",
+        ],
+    ),
+    (
+        "es",
+        &[
+            "el", "los", "las", "es", "una", "que", "por", "para", "con", "qué", "cómo", "está",
+        ],
+    ),
+    (
+        "it",
+        &[
+            "il", "gli", "della", "che", "non", "sono", "una", "per", "come", "cosa", "è", "questo",
+        ],
+    ),
+    (
+        "pt",
+        &[
+            "o", "os", "uma", "não", "que", "para", "com", "como", "você", "é", "está", "isso",
+        ],
+    ),
+    (
+        "nl",
+        &[
+            "de", "het", "een", "en", "is", "niet", "wat", "hoe", "ik", "je", "met", "van",
+        ],
+    ),
+];
+
+/// Human-readable name for an ISO 639-1 code; unknown values are passed through.
+pub(crate) fn language_name(code: &str) -> &str {
+    LANGUAGE_NAMES
+        .iter()
+        .find(|(c, _)| c.eq_ignore_ascii_case(code))
+        .map(|(_, name)| *name)
+        .unwrap_or(code)
+}
+
+pub(crate) fn language_instruction(code: &str) -> String {
+    format!(
+        "Always respond in {}, regardless of the language used in the request or in tool results.",
+        language_name(code)
+    )
+}
+
+/// System prompt of the model translating into `code` for `output_language`.
+pub(crate) fn translation_instruction(code: &str) -> String {
+    format!(
+        "Translate the user's message into {}. Keep its formatting, code, numbers, names, \
+         URLs and placeholders in square brackets unchanged. Reply with the translation only.",
+        language_name(code)
+    )
+}
+
+/// Guess the ISO 639-1 code of `text`. Non-Latin scripts are identified by their
+/// Unicode block; Latin-script text by stopword frequency. Returns `None` when unsure.
+pub(crate) fn detect_language(text: &str) -> Option<&'static str> {
+    if let Some(code) = detect_script(text) {
+        return Some(code);
+    }
+
+    let words: Vec<String> = text
+        .split(|c: char| !c.is_alphabetic())
+        .filter(|w| !w.is_empty())
+        .map(str::to_lowercase)
+        .collect();
+
+    STOPWORDS
+        .iter()
+        .map(|(code, stopwords)| {
+            let hits = words
+                .iter()
+                .filter(|w| stopwords.contains(&w.as_str()))
+                .count();
+            (*code, hits)
+        })
+        .filter(|(_, hits)| *hits > 0)
+        .max_by_key(|(_, hits)| *hits)
+        .map(|(code, _)| code)
+}
+
+fn detect_script(text: &str) -> Option<&'static str> {
+    let mut counts: Vec<(&'static str, usize)> = Vec::new();
+    let mut letters = 0;
+    for c in text.chars().filter(|c| c.is_alphabetic()) {
+        letters += 1;
+        let code = match c as u32 {
+            0x0370..=0x03FF => "el",
+            0x0400..=0x04FF => {
+                if "іїєґІЇЄҐ".contains(c) {
+                    "uk"
+                } else {
+                    "ru"
+                }
+            }
+            0x0590..=0x05FF => "he",
+            0x0600..=0x06FF => "ar",
+            0x0900..=0x097F => "hi",
+            0x0E00..=0x0E7F => "th",
+            0x3040..=0x30FF => "ja",
+            0xAC00..=0xD7AF | 0x1100..=0x11FF => "ko",
+            0x4E00..=0x9FFF => "zh",
+            _ => continue,
+        };
+        match counts.iter_mut().find(|(c, _)| *c == code) {
+            Some((_, n)) => *n += 1,
+            None => counts.push((code, 1)),
+        }
+    }
+
+    // Kana anywhere means Japanese, even though most characters may be kanji.
+    if counts.iter().any(|(c, _)| *c == "ja") {
+        return Some("ja");
+    }
+    if counts.iter().any(|(c, _)| *c == "uk") {
+        return Some("uk");
+    }
+    counts
+        .into_iter()
+        .max_by_key(|(_, n)| *n)
+        .filter(|(_, n)| *n * 2 >= letters)
+        .map(|(code, _)| code)
+}
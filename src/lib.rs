@@ -1,30 +1,171 @@
+mod agent;
+mod artifacts;
+mod assistants;
+mod asyncio;
+mod audit;
+mod balancer;
+mod bm25;
+mod builder;
+mod chat_template;
+mod circuit;
+mod citations;
+mod classify;
 mod claude;
+mod client;
+mod clock;
+mod compression;
+mod constants;
+mod context;
+mod deadline;
+mod delegate;
+mod diagnostics;
+mod document;
+mod downgrade;
+mod dry_run;
+mod embeddings;
 mod error;
+mod estimate;
+mod export;
+mod feedback;
+mod finetune;
 mod gemini;
+#[cfg(feature = "grpc")]
+mod gemini_grpc;
+mod huggingface;
+mod idempotency;
+mod instructions;
+mod jobs;
+mod keys;
+mod language;
+mod limits;
+#[cfg(feature = "loaders")]
+mod loaders;
+mod map_reduce;
+mod media;
+mod memory;
+mod message_transforms;
+mod metrics;
+mod moderation;
 mod openai;
-
-use claude::{Claude, ContentBlock as ClaudeContentBlock, Message as ClaudeMessage};
-use dotenv;
-#[allow(unused_imports)]
-use error::RustedChainError;
-use gemini::{
-    Content as GeminiContent, FunctionResponseData, Gemini, GeminiResponse, Part as GeminiPart,
+mod options;
+mod partial_json;
+#[cfg(feature = "pgvector")]
+mod pgvector;
+mod pii;
+mod prefill;
+mod presets;
+mod pricing;
+mod prompts;
+mod qdrant;
+mod replay;
+mod rerank;
+mod retry;
+mod roles;
+mod run;
+mod runtime;
+mod serialize;
+mod server_events;
+mod session;
+mod signing;
+mod splitter;
+mod stream;
+mod tenant;
+mod testing;
+mod tool_args;
+mod tool_cache;
+mod tool_usage;
+mod tool_versions;
+mod traces;
+mod tracking;
+mod transcript;
+mod transforms;
+mod usage_webhook;
+mod validation;
+mod vector_store;
+mod vision;
+mod workflow;
+
+use agent::ChatClient;
+use claude::Claude;
+use error::{
+    BudgetExceededError, ContentFilterError, ContextLengthError, InvalidRequestError,
+    OverloadedError, ProviderError, ProviderUnavailableError, RateLimitError, ToolLoopError,
 };
+use gemini::Gemini;
+use huggingface::HuggingFace;
+use jobs::Submit;
+use keys::ApiKey;
 use once_cell::sync::Lazy;
-use openai::{Message as OpenAIMessage, OpenAI};
+use openai::OpenAI;
+use options::AgentOptions;
+use partial_json::{parse_partial_json, JsonStream};
 use pyo3::prelude::*;
-use serde_json::json;
-use tokio::runtime::Runtime;
+use pyo3::types::PyDict;
+use run::Labels;
+use runtime::SharedRuntime;
+use session::Entry;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Arc;
+use stream::TextStream;
+use tenant::{Scoped, Tenant};
 
 const MAX_TOOL_ITERATIONS: usize = 10;
 
-static RUNTIME: Lazy<Runtime> =
-    Lazy::new(|| Runtime::new().expect("Failed to create tokio runtime"));
+static RUNTIME: Lazy<SharedRuntime> = Lazy::new(SharedRuntime::new);
+
+/// One HTTP client for every request, so open connections are reused across calls and
+/// models, and `warmup()` has a lasting effect.
+static HTTP: Lazy<reqwest::Client> = Lazy::new(reqwest::Client::new);
 
 enum Provider {
     Gemini,
     OpenAI,
     Claude,
+    HuggingFace,
+}
+
+/// The provider named by `create_agent(provider=...)`, which overrides detection.
+fn parse_provider(name: &str) -> PyResult<Provider> {
+    match name {
+        "openai" => Ok(Provider::OpenAI),
+        "anthropic" => Ok(Provider::Claude),
+        "gemini" => Ok(Provider::Gemini),
+        "huggingface" => Ok(Provider::HuggingFace),
+        other => Err(pyo3::exceptions::PyValueError::new_err(format!(
+            "unknown provider '{}' (expected 'openai', 'anthropic', 'gemini', 'huggingface' \
+             or a preset: {})",
+            other,
+            presets::PRESETS
+                .iter()
+                .map(|preset| preset.name)
+                .collect::<Vec<_>>()
+                .join(", ")
+        ))),
+    }
+}
+
+/// An `OpenAIModel` talking to `preset`'s OpenAI-compatible API, with the key from the
+/// preset's environment variable unless one is given.
+fn preset_agent(
+    preset: &presets::Preset,
+    model: &str,
+    tools: Option<Vec<Py<PyAny>>>,
+    api_key: Option<ApiKey>,
+    mut options: AgentOptions,
+) -> PyResult<OpenAIModel> {
+    options.check_provider("openai")?;
+    options
+        .base_url
+        .get_or_insert_with(|| preset.base_url.to_string());
+    Ok(OpenAIModel {
+        model: Some(preset.model_id(model)),
+        tools,
+        api_key: Some(api_key.unwrap_or_else(|| preset.api_key().into())),
+        user: options.user.clone(),
+        safety_identifier: options.safety_identifier.clone(),
+        options,
+    })
 }
 
 fn detect_provider(model: &str) -> PyResult<Provider> {
@@ -73,7 +214,9 @@ fn detect_provider(model: &str) -> PyResult<Provider> {
     }
 
     Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(format!(
-        "Unknown model '{}'. Supported models:\n  OpenAI: {}\n  Claude: {}\n  Gemini: {}",
+        "Unknown model '{}'. Supported models:\n  OpenAI: {}\n  Claude: {}\n  Gemini: {}\n\
+         Prefix the id with together/, fireworks/, perplexity/ or openrouter/ for those \
+         providers, or pass provider='huggingface' (or another provider) for any other id.",
         model,
         OPENAI_MODELS.join(", "),
         CLAUDE_MODELS.join(", "),
@@ -81,7 +224,12 @@ fn detect_provider(model: &str) -> PyResult<Provider> {
     )))
 }
 
-fn convert_tools(py: Python, tools: &Option<Vec<Py<PyAny>>>) -> Vec<serde_json::Value> {
+/// Whether the agent has any tools configured.
+fn has_tools(tools: &Option<Vec<Py<PyAny>>>) -> bool {
+    tools.as_ref().is_some_and(|tools| !tools.is_empty())
+}
+
+pub(crate) fn convert_tools(py: Python, tools: &Option<Vec<Py<PyAny>>>) -> Vec<serde_json::Value> {
     tools
         .as_ref()
         .map(|t| {
@@ -101,47 +249,210 @@ fn convert_tools(py: Python, tools: &Option<Vec<Py<PyAny>>>) -> Vec<serde_json::
         .unwrap_or_default()
 }
 
-fn wrap_tool_result(value: serde_json::Value) -> serde_json::Value {
-    match value {
-        serde_json::Value::Object(_) => value,
-        other => json!({ "result": other }),
+/// Whether each tool asked for strict function calling, falling back to `default` for
+/// tools that do not say.
+fn strict_flags(py: Python, tools: &Option<Vec<Py<PyAny>>>, default: bool) -> Vec<bool> {
+    tools
+        .iter()
+        .flatten()
+        .map(|tool| strict_flag(tool.bind(py), default))
+        .collect()
+}
+
+/// Whether `tool` asked for strict function calling, or `default` if it does not say.
+pub(crate) fn strict_flag(tool: &Bound<'_, PyAny>, default: bool) -> bool {
+    tool.getattr("strict")
+        .and_then(|strict| strict.extract::<Option<bool>>())
+        .ok()
+        .flatten()
+        .unwrap_or(default)
+}
+
+/// One call of a model's `invoke`, `run` or `stream`: its client and options under the
+/// call's tenant, with its `tool_model` and `idempotency_key`.
+struct Call<C> {
+    client: C,
+    tools: Option<Vec<Py<PyAny>>>,
+    options: AgentOptions,
+}
+
+impl<C: ChatClient + Scoped + Send + 'static> Call<C> {
+    fn new(
+        py: Python,
+        client: C,
+        tools: &Option<Vec<Py<PyAny>>>,
+        options: &AgentOptions,
+        context: Option<Bound<'_, Tenant>>,
+        tool_model: Option<String>,
+        idempotency_key: Option<String>,
+    ) -> Self {
+        let (client, options) = tenant::scope(client, options, context.as_ref());
+        let options = AgentOptions::with_tool_model(options, tool_model);
+        let options = AgentOptions::with_idempotency_key(options, idempotency_key);
+        let tools = tools
+            .as_ref()
+            .map(|tools| tools.iter().map(|tool| tool.clone_ref(py)).collect());
+        Call {
+            client,
+            tools,
+            options: options.into_owned(),
+        }
+    }
+
+    fn invoke(
+        self,
+        py: Python,
+        query: String,
+        tags: Option<Vec<String>>,
+        metadata: Option<Py<PyDict>>,
+        dry_run: bool,
+    ) -> PyResult<Py<PyAny>> {
+        let Call {
+            client,
+            tools,
+            options,
+        } = self;
+        if dry_run {
+            return agent::dry_run(py, &client, &tools, &options, &query, false);
+        }
+        let response = agent::run_agent(py, &client, &tools, &options, &query, tags, metadata)?;
+        Ok(response.into_pyobject(py)?.into_any().unbind())
+    }
+
+    fn run(
+        self,
+        py: Python,
+        query: String,
+        stream: bool,
+        tags: Option<Vec<String>>,
+        metadata: Option<Py<PyDict>>,
+        dry_run: bool,
+    ) -> PyResult<Py<PyAny>> {
+        if dry_run {
+            return agent::dry_run(py, &self.client, &self.tools, &self.options, &query, stream);
+        }
+        if stream {
+            let text_stream = self.stream(py, query, tags, metadata)?;
+            return Ok(Py::new(py, text_stream)?.into_any());
+        }
+        let Call {
+            client,
+            tools,
+            options,
+        } = self;
+        let response = agent::run_agent(py, &client, &tools, &options, &query, tags, metadata)?;
+        Ok(response.text()?.into_pyobject(py)?.into_any().unbind())
+    }
+
+    fn stream(
+        self,
+        py: Python,
+        query: String,
+        tags: Option<Vec<String>>,
+        metadata: Option<Py<PyDict>>,
+    ) -> PyResult<TextStream> {
+        agent::stream_agent(
+            py,
+            self.client,
+            &self.tools,
+            &self.options,
+            query,
+            tags,
+            metadata,
+        )
+    }
+
+    /// `method` of this call as a coroutine for asyncio. It runs on a thread of its own,
+    /// so awaiting it doesn't block the event loop.
+    fn spawn<F>(self, py: Python, method: F) -> PyResult<Py<PyAny>>
+    where
+        F: FnOnce(Self, Python) -> PyResult<Py<PyAny>> + Send + 'static,
+    {
+        asyncio::spawn(py, move |py| method(self, py))
     }
 }
 
 #[pyfunction]
-#[pyo3(signature = (model, tools=None, api_key=None))]
+#[pyo3(signature = (model, tools=None, api_key=None, provider=None, **options))]
 fn create_agent(
     py: Python,
     model: String,
     tools: Option<Vec<Py<PyAny>>>,
-    api_key: Option<String>,
+    api_key: Option<ApiKey>,
+    provider: Option<&str>,
+    options: Option<&Bound<'_, PyDict>>,
+) -> PyResult<Py<PyAny>> {
+    let options = AgentOptions::from_kwargs(options)?;
+    build_agent(py, model, tools, api_key, provider, options)
+}
+
+/// The model class instance `create_agent` and `AgentBuilder.build` return.
+pub(crate) fn build_agent(
+    py: Python,
+    model: String,
+    tools: Option<Vec<Py<PyAny>>>,
+    api_key: Option<ApiKey>,
+    provider: Option<&str>,
+    options: AgentOptions,
 ) -> PyResult<Py<PyAny>> {
     dotenv::dotenv().ok();
 
-    let provider = detect_provider(&model)?;
+    let preset = match provider {
+        Some(name) => presets::by_name(name),
+        None => presets::detect(&model),
+    };
+    if let Some(preset) = preset {
+        let agent = preset_agent(preset, &model, tools, api_key, options)?;
+        return Ok(Py::new(py, agent)?.into());
+    }
+    let provider = match provider {
+        Some(name) => parse_provider(name)?,
+        None => detect_provider(&model)?,
+    };
 
     match provider {
         Provider::OpenAI => {
+            options.check_provider("openai")?;
             let agent = OpenAIModel {
                 model: Some(model),
                 tools,
                 api_key,
+                user: options.user.clone(),
+                safety_identifier: options.safety_identifier.clone(),
+                options,
             };
             Ok(Py::new(py, agent)?.into())
         }
         Provider::Claude => {
+            options.check_provider("anthropic")?;
             let agent = ClaudeModel {
                 model: Some(model),
                 tools,
                 api_key,
+                options,
             };
             Ok(Py::new(py, agent)?.into())
         }
         Provider::Gemini => {
+            options.check_provider("gemini")?;
             let agent = GeminiModel {
                 model: Some(model),
                 tools,
                 api_key,
+                options,
+            };
+            Ok(Py::new(py, agent)?.into())
+        }
+        Provider::HuggingFace => {
+            options.check_provider("huggingface")?;
+            let agent = HuggingFaceModel {
+                model: Some(model),
+                tools,
+                api_key,
+                endpoint: None,
+                task: huggingface::Task::default(),
+                inference_provider: None,
+                options,
             };
             Ok(Py::new(py, agent)?.into())
         }
@@ -164,10 +475,82 @@ impl ToolCall {
     }
 }
 
+/// Details about how a response was produced, alongside its content.
+#[pyclass]
+#[derive(Clone, Default)]
+pub struct ResponseMetadata {
+    /// Best-guess ISO 639-1 code of the query, or `None` if it could not be determined.
+    #[pyo3(get)]
+    pub detected_language: Option<String>,
+    /// The moderation verdict on the answer, with `auto_moderate="annotate"`.
+    #[pyo3(get)]
+    pub moderation: Option<moderation::ModerationResult>,
+    /// `ToolUsage` per tool executed during the run.
+    #[pyo3(get)]
+    pub tool_usage: HashMap<String, tool_usage::ToolUsage>,
+    /// Sources the answer cites, for providers that return them (Perplexity).
+    #[pyo3(get)]
+    pub citations: Vec<citations::Citation>,
+    /// How the run's provider requests were retried.
+    #[pyo3(get)]
+    pub retries: retry::Retries,
+    /// Files stored from tool results instead of being sent to the model.
+    #[pyo3(get)]
+    pub artifacts: Vec<artifacts::Artifact>,
+    /// The conversation the run had: earlier history, the query, tool calls with their
+    /// results, and the answer.
+    pub(crate) transcript: Vec<Entry>,
+    /// The run in the `export` schema.
+    pub(crate) run: Option<serde_json::Value>,
+    /// Where `record_feedback` on the response writes to.
+    pub(crate) feedback: Option<Arc<feedback::Feedback>>,
+}
+
+#[pymethods]
+impl ResponseMetadata {
+    fn __repr__(&self) -> String {
+        format!(
+            "ResponseMetadata(detected_language={})",
+            self.detected_language
+                .as_deref()
+                .map_or("None".to_string(), |code| format!("'{}'", code))
+        )
+    }
+}
+
 #[pyclass]
 pub enum AgentResponse {
-    Text { text: String },
-    ToolCall { tool_call: ToolCall },
+    Text {
+        text: String,
+        metadata: ResponseMetadata,
+    },
+    ToolCall {
+        tool_call: ToolCall,
+        metadata: ResponseMetadata,
+    },
+}
+
+impl AgentResponse {
+    /// The recorded conversation, or just the answer for responses produced without
+    /// one (such as `invoke_with_files`).
+    fn transcript(&self) -> Cow<'_, [Entry]> {
+        match self {
+            AgentResponse::Text { text, metadata } if metadata.transcript.is_empty() => {
+                Cow::Owned(vec![Entry::Assistant(text.clone())])
+            }
+            AgentResponse::Text { metadata, .. } | AgentResponse::ToolCall { metadata, .. } => {
+                Cow::Borrowed(&metadata.transcript)
+            }
+        }
+    }
+
+    pub(crate) fn metadata_mut(&mut self) -> &mut ResponseMetadata {
+        match self {
+            AgentResponse::Text { metadata, .. } | AgentResponse::ToolCall { metadata, .. } => {
+                metadata
+            }
+        }
+    }
 }
 
 #[pymethods]
@@ -185,17 +568,81 @@ impl AgentResponse {
     #[getter]
     fn text(&self) -> PyResult<String> {
         match self {
-            AgentResponse::Text { text } => Ok(text.clone()),
+            AgentResponse::Text { text, .. } => Ok(text.clone()),
             _ => Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
                 "Response is not a text response",
             )),
         }
     }
 
+    /// The fenced code blocks in a text answer as `(language, code)` tuples, in order.
+    /// The language is lowercased, or `None` for an untagged block.
+    fn code_blocks(&self) -> Vec<(Option<String>, String)> {
+        match self {
+            AgentResponse::Text { text, .. } => transforms::code_blocks(text)
+                .into_iter()
+                .map(|block| (block.language, block.code))
+                .collect(),
+            AgentResponse::ToolCall { .. } => Vec::new(),
+        }
+    }
+
+    /// The complete run as a dict in the `rusted_chain.run/1` schema: messages, tool
+    /// calls, usage and timings. One per line is what `run_log` writes.
+    fn to_run<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        let (AgentResponse::Text { metadata, .. } | AgentResponse::ToolCall { metadata, .. }) =
+            self;
+        let run = metadata.run.as_ref().ok_or_else(|| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                "this response was not produced by an agent run",
+            )
+        })?;
+        let mut run = run.clone();
+        if let Some(given) = metadata.feedback.as_ref().map(|f| f.given()) {
+            if !given.is_empty() {
+                feedback::add(&mut run, given);
+            }
+        }
+        Ok(pythonize::pythonize(py, &run)?)
+    }
+
+    /// Record feedback on the run, such as a user's rating of the answer: a numeric
+    /// `score` and an optional `comment`. It is written to the agent's `run_log` and
+    /// `audit_log`, shown with the run in the trace viewer, included in `to_run()`,
+    /// and sent to the experiment trackers the run was logged to.
+    #[pyo3(signature = (score, comment=None))]
+    fn record_feedback(&self, score: f64, comment: Option<String>) -> PyResult<()> {
+        if !score.is_finite() {
+            return Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                "score must be a finite number",
+            ));
+        }
+        let (AgentResponse::Text { metadata, .. } | AgentResponse::ToolCall { metadata, .. }) =
+            self;
+        let target = metadata.feedback.as_ref().ok_or_else(|| {
+            PyErr::new::<pyo3::exceptions::PyValueError, _>(
+                "this response was not produced by an agent run",
+            )
+        })?;
+        target.record(score, comment);
+        Ok(())
+    }
+
+    /// The run's conversation as Markdown: each message under a heading, with tool
+    /// arguments and results in fenced blocks.
+    fn to_markdown(&self) -> String {
+        transcript::to_markdown(&self.transcript())
+    }
+
+    /// The run's conversation as a self-contained HTML page.
+    fn to_html(&self) -> String {
+        transcript::to_html(&self.transcript())
+    }
+
     #[getter]
     fn tool_call(&self) -> PyResult<ToolCall> {
         match self {
-            AgentResponse::ToolCall { tool_call } => Ok(tool_call.clone()),
+            AgentResponse::ToolCall { tool_call, .. } => Ok(tool_call.clone()),
             _ => Err(PyErr::new::<pyo3::exceptions::PyValueError, _>(
                 "Response is not a tool call",
             )),
@@ -204,19 +651,241 @@ impl AgentResponse {
 
     fn __repr__(&self) -> String {
         match self {
-            AgentResponse::Text { text } => format!("AgentResponse.Text('{}')", text),
-            AgentResponse::ToolCall { tool_call } => {
+            AgentResponse::Text { text, .. } => format!("AgentResponse.Text('{}')", text),
+            AgentResponse::ToolCall { tool_call, .. } => {
                 format!("AgentResponse.ToolCall({})", tool_call.__repr__())
             }
         }
     }
 }
 
+/// The `#[pymethods]` every model class shares, next to the class's own (its constructor,
+/// and anything only that provider has). The names in brackets are per-call arguments
+/// that the class's `call` takes after `py`. Each is an optional string, placed after
+/// the query in every signature that runs the model.
+macro_rules! model_methods {
+    ($model:ident) => {
+        model_methods!($model());
+    };
+    ($model:ident($($arg:ident),*)) => {
+        #[pymethods]
+        impl $model {
+            /// Add a tool, optionally capping how many calls to it run at once and how many
+            /// start per minute across every run of this model. With `cache_ttl`, results are
+            /// reused for `cache_ttl` seconds by calls with the same arguments, in any run.
+            #[pyo3(signature = (tool, max_concurrency=None, rate_limit_per_min=None, cache_ttl=None))]
+            fn add_tool(
+                &mut self,
+                py: Python,
+                tool: Py<PyAny>,
+                max_concurrency: Option<usize>,
+                rate_limit_per_min: Option<usize>,
+                cache_ttl: Option<f64>,
+            ) -> PyResult<()> {
+                self.options
+                    .limit_tool(tool.bind(py), max_concurrency, rate_limit_per_min)?;
+                self.options.cache_tool(tool.bind(py), cache_ttl)?;
+                if let Some(tools) = &mut self.tools {
+                    tools.push(tool);
+                } else {
+                    self.tools = Some(vec![tool]);
+                }
+                Ok(())
+            }
+
+            /// Invoke the model.
+            /// If tools are provided, this will run the agent loop (execute tools) until a final answer is reached.
+            /// If no tools are provided, it runs a single-shot completion.
+            /// `tags` and `metadata` label the run in usage records, audit logs and trackers.
+            /// `context` is a `Tenant` whose keys, budget and limits apply to this call.
+            /// `tool_model` overrides the agent's `tool_model` for this call.
+            /// Calls given the same `idempotency_key` send the same keys and share remembered answers.
+            /// `dry_run=True` returns the request the call would send, as a dict, without sending it.
+            #[pyo3(signature = (query, $($arg=None,)* tags=None, metadata=None, context=None, tool_model=None, idempotency_key=None, dry_run=false))]
+            #[allow(clippy::too_many_arguments)]
+            fn invoke(
+                &self,
+                py: Python,
+                query: String,
+                $($arg: Option<String>,)*
+                tags: Option<Vec<String>>,
+                metadata: Option<Py<PyDict>>,
+                context: Option<Bound<'_, Tenant>>,
+                tool_model: Option<String>,
+                idempotency_key: Option<String>,
+                dry_run: bool,
+            ) -> PyResult<Py<PyAny>> {
+                self.call(py, $($arg,)* context, tool_model, idempotency_key)
+                    .invoke(py, query, tags, metadata, dry_run)
+            }
+
+            /// Invoke the model with images attached: file paths, bytes or URLs. Gemini also
+            /// takes videos, including `Video` objects carrying sampling options. This is a
+            /// single turn; tools are not offered.
+            #[pyo3(signature = (query, files, $($arg=None,)* tags=None, metadata=None))]
+            #[allow(clippy::too_many_arguments)]
+            fn invoke_with_files(
+                &self,
+                py: Python,
+                query: String,
+                files: &Bound<'_, PyAny>,
+                $($arg: Option<String>,)*
+                tags: Option<Vec<String>>,
+                metadata: Option<Py<PyDict>>,
+            ) -> PyResult<AgentResponse> {
+                let client = model_methods!(@client self, py; $($arg),*);
+                vision::invoke_with_files(py, &client, &self.options, &query, files, tags, metadata)
+            }
+
+            /// Connect to the provider ahead of the first real request, so it doesn't pay for
+            /// DNS, TCP and TLS setup; the connection is kept for later calls. With
+            /// `prime=True`, a one-token request is sent as well. Returns the seconds taken.
+            #[pyo3(signature = (prime=false))]
+            fn warmup(&self, py: Python, prime: bool) -> PyResult<f64> {
+                agent::warmup(py, &self.build_client(py), prime)
+            }
+
+            /// Send `payload`, a request body as the provider's API takes it, and return the
+            /// response as a dict, for parameters this library doesn't expose. Streaming isn't
+            /// supported. The model is filled in if missing, and for Claude `max_tokens` too.
+            /// `path` is relative to the base URL: `/chat/completions` by default, or
+            /// `/v1/messages` for Claude. For Gemini it is the model method,
+            /// `generateContent` by default, always sent over REST. Hugging Face's
+            /// text-generation task always posts to the generation URL.
+            #[pyo3(signature = (payload, path=None))]
+            fn invoke_raw(
+                &self,
+                py: Python,
+                payload: Bound<'_, PyDict>,
+                path: Option<String>,
+            ) -> PyResult<Py<PyAny>> {
+                agent::invoke_raw(py, &self.build_client(py), &payload, path.as_deref())
+            }
+
+            /// `ToolUsage` per tool over every run of this agent: calls, failure rate, average
+            /// latency and result size. `reset=True` starts the counts over.
+            #[pyo3(signature = (reset=false))]
+            fn tool_usage(&self, reset: bool) -> HashMap<String, tool_usage::ToolUsage> {
+                tool_usage::snapshot(&self.options.tool_usage, reset)
+            }
+
+            /// Submit `query` as a long-running request and return a `LongRunningJob` straight
+            /// away. This is a single turn; tools are not offered. OpenAI runs it as a
+            /// background response, with no connection held open, which suits slow reasoning
+            /// models; collect it later with `result()`, here or in another process via
+            /// `get_long_running(job.id)`. Other providers run it in the background here.
+            #[pyo3(signature = (query, $($arg=None,)* tags=None, metadata=None))]
+            fn submit_long_running(
+                &self,
+                py: Python,
+                query: String,
+                $($arg: Option<String>,)*
+                tags: Option<Vec<String>>,
+                metadata: Option<Py<PyDict>>,
+            ) -> PyResult<jobs::LongRunningJob> {
+                let client = model_methods!(@client self, py; $($arg),*);
+                let labels = Labels::from_py(py, tags, metadata.as_ref())?;
+                client.submit(py, &self.options, query, labels)
+            }
+
+            /// `invoke` as a coroutine for asyncio. The call runs on a thread of its own, so
+            /// awaiting it doesn't block the event loop.
+            #[pyo3(signature = (query, $($arg=None,)* tags=None, metadata=None, context=None, tool_model=None, idempotency_key=None))]
+            #[allow(clippy::too_many_arguments)]
+            fn ainvoke(
+                &self,
+                py: Python,
+                query: String,
+                $($arg: Option<String>,)*
+                tags: Option<Vec<String>>,
+                metadata: Option<Py<PyDict>>,
+                context: Option<Bound<'_, Tenant>>,
+                tool_model: Option<String>,
+                idempotency_key: Option<String>,
+            ) -> PyResult<Py<PyAny>> {
+                let call = self.call(py, $($arg,)* context, tool_model, idempotency_key);
+                call.spawn(py, move |call, py| {
+                    call.invoke(py, query, tags, metadata, false)
+                })
+            }
+
+            /// `run` as a coroutine for asyncio, returning the final answer.
+            #[pyo3(signature = (query, $($arg=None,)* tags=None, metadata=None, context=None, tool_model=None, idempotency_key=None))]
+            #[allow(clippy::too_many_arguments)]
+            fn arun(
+                &self,
+                py: Python,
+                query: String,
+                $($arg: Option<String>,)*
+                tags: Option<Vec<String>>,
+                metadata: Option<Py<PyDict>>,
+                context: Option<Bound<'_, Tenant>>,
+                tool_model: Option<String>,
+                idempotency_key: Option<String>,
+            ) -> PyResult<Py<PyAny>> {
+                let call = self.call(py, $($arg,)* context, tool_model, idempotency_key);
+                call.spawn(py, move |call, py| {
+                    call.run(py, query, false, tags, metadata, false)
+                })
+            }
+
+            /// Run the agent and return an iterator of text chunks as the final answer is
+            /// generated; the same as `run(query, stream=True)`.
+            #[pyo3(signature = (query, $($arg=None,)* tags=None, metadata=None, context=None, tool_model=None, idempotency_key=None))]
+            #[allow(clippy::too_many_arguments)]
+            fn stream(
+                &self,
+                py: Python,
+                query: String,
+                $($arg: Option<String>,)*
+                tags: Option<Vec<String>>,
+                metadata: Option<Py<PyDict>>,
+                context: Option<Bound<'_, Tenant>>,
+                tool_model: Option<String>,
+                idempotency_key: Option<String>,
+            ) -> PyResult<TextStream> {
+                self.call(py, $($arg,)* context, tool_model, idempotency_key)
+                    .stream(py, query, tags, metadata)
+            }
+
+            /// Run the agent and return the final answer.
+            /// With `stream=True`, returns an iterator of text chunks instead; tools are still
+            /// executed between turns and the final answer is streamed as it is generated.
+            #[pyo3(signature = (query, stream=false, $($arg=None,)* tags=None, metadata=None, context=None, tool_model=None, idempotency_key=None, dry_run=false))]
+            #[allow(clippy::too_many_arguments)]
+            fn run(
+                &self,
+                py: Python,
+                query: String,
+                stream: bool,
+                $($arg: Option<String>,)*
+                tags: Option<Vec<String>>,
+                metadata: Option<Py<PyDict>>,
+                context: Option<Bound<'_, Tenant>>,
+                tool_model: Option<String>,
+                idempotency_key: Option<String>,
+                dry_run: bool,
+            ) -> PyResult<Py<PyAny>> {
+                self.call(py, $($arg,)* context, tool_model, idempotency_key)
+                    .run(py, query, stream, tags, metadata, dry_run)
+            }
+        }
+    };
+    // The client for a call, with its per-call arguments applied.
+    (@client $self:ident, $py:ident;) => {
+        $self.build_client($py)
+    };
+    (@client $self:ident, $py:ident; $($arg:ident),+) => {
+        $self.build_call_client($py, $($arg),+)
+    };
+}
+
 #[pyclass]
 pub struct GeminiModel {
     model: Option<String>,
     tools: Option<Vec<Py<PyAny>>>,
-    api_key: Option<String>,
+    api_key: Option<ApiKey>,
+    options: AgentOptions,
 }
 
 impl GeminiModel {
@@ -229,139 +898,93 @@ impl GeminiModel {
         if let Some(k) = &self.api_key {
             client = client.with_api_key(k.clone());
         }
+        client = client.with_retry_policy(self.options.retry_policy());
+        if let Some(signer) = &self.options.request_signer {
+            client = client.with_signer(signer.clone());
+        }
+        if let Some(system) = self.options.system_prompt("gemini", has_tools(&self.tools)) {
+            client = client.with_system(system);
+        }
+        if let Some(prefill) = &self.options.prefill {
+            client = client.with_prefill(prefill.clone());
+        }
+        if let Some(temperature) = self.options.temperature {
+            client = client.with_temperature(temperature);
+        }
+        if let Some(transform) = &self.options.message_transform {
+            client = client.with_message_transform(transform.clone());
+        }
+        if let Some(settings) = &self.options.safety_settings {
+            client = client.with_safety_settings(settings.clone());
+        }
+        if let Some(threshold) = &self.options.retry_blocked {
+            client = client.with_blocked_retry(threshold.clone());
+        }
+        #[cfg(feature = "grpc")]
+        if self.options.grpc {
+            client = client.with_grpc();
+        }
         let tools_json = convert_tools(py, &self.tools);
         if !tools_json.is_empty() {
             client = client.with_tools(tools_json);
         }
         client
     }
+
+    /// The client and options for one call (internal method)
+    fn call(
+        &self,
+        py: Python,
+        context: Option<Bound<'_, Tenant>>,
+        tool_model: Option<String>,
+        idempotency_key: Option<String>,
+    ) -> Call<Gemini> {
+        let client = self.build_client(py);
+        Call::new(
+            py,
+            client,
+            &self.tools,
+            &self.options,
+            context,
+            tool_model,
+            idempotency_key,
+        )
+    }
 }
 
 #[pymethods]
 impl GeminiModel {
     #[new]
-    #[pyo3(signature = (model=None, tools=None, api_key=None))]
-    fn new(model: Option<String>, tools: Option<Vec<Py<PyAny>>>, api_key: Option<String>) -> Self {
-        GeminiModel {
+    #[pyo3(signature = (model=None, tools=None, api_key=None, **options))]
+    fn new(
+        model: Option<String>,
+        tools: Option<Vec<Py<PyAny>>>,
+        api_key: Option<ApiKey>,
+        options: Option<&Bound<'_, PyDict>>,
+    ) -> PyResult<Self> {
+        let options = AgentOptions::from_kwargs(options)?;
+        options.check_provider("gemini")?;
+        Ok(GeminiModel {
             model,
             tools,
             api_key,
-        }
-    }
-
-    fn add_tool(&mut self, tool: Py<PyAny>) {
-        if let Some(tools) = &mut self.tools {
-            tools.push(tool);
-        } else {
-            self.tools = Some(vec![tool]);
-        }
-    }
-
-    /// Invoke the model.
-    /// If tools are provided, this will run the agent loop (execute tools) until a final answer is reached.
-    /// If no tools are provided, it runs a single-shot completion.
-    fn invoke(&self, py: Python, query: String) -> PyResult<AgentResponse> {
-        // Check if we have tools. If not, do single-shot.
-        let has_tools = self.tools.as_ref().map(|t| !t.is_empty()).unwrap_or(false);
-
-        if !has_tools {
-            // Single-shot logic (original invoke)
-            let client = self.build_client(py);
-            let response = RUNTIME.block_on(async {
-                client
-                    .invoke_with_response(&query)
-                    .await
-                    .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e))
-            })?;
-
-            return match response {
-                GeminiResponse::Text(text) => Ok(AgentResponse::Text { text }),
-                GeminiResponse::ToolCall(tool_call) => Ok(AgentResponse::ToolCall {
-                    tool_call: ToolCall {
-                        name: tool_call.name,
-                        args: serde_json::to_string(&tool_call.args)
-                            .unwrap_or_else(|_| "{}".to_string()),
-                    },
-                }),
-            };
-        }
-
-        // Agent loop logic (original run)
-        let tools_dict = pyo3::types::PyDict::new(py);
-        if let Some(tools) = &self.tools {
-            for tool in tools {
-                let tool_obj = tool.bind(py);
-                if let Ok(name) = tool_obj.getattr("__name__") {
-                    tools_dict.set_item(name, tool_obj)?;
-                }
-            }
-        }
-
-        let client = self.build_client(py);
-        let mut conversation = vec![GeminiContent {
-            parts: vec![GeminiPart::Text {
-                text: query.clone(),
-            }],
-            role: Some("user".to_string()),
-        }];
-
-        for _iteration in 0..MAX_TOOL_ITERATIONS {
-            let (response, assistant_content) = RUNTIME.block_on(async {
-                client
-                    .exchange(conversation.clone())
-                    .await
-                    .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e))
-            })?;
-
-            conversation.push(assistant_content);
-
-            match response {
-                GeminiResponse::Text(text) => {
-                    return Ok(AgentResponse::Text { text });
-                }
-                GeminiResponse::ToolCall(tool_call) => {
-                    let tool_fn = tools_dict.get_item(&tool_call.name)?.ok_or_else(|| {
-                        PyErr::new::<pyo3::exceptions::PyKeyError, _>(format!(
-                            "Tool '{}' not found",
-                            tool_call.name
-                        ))
-                    })?;
-
-                    let kwargs = pythonize::pythonize(py, &tool_call.args)?;
-                    let result = if let Ok(dict) = kwargs.cast::<pyo3::types::PyDict>() {
-                        tool_fn.call((), Some(&dict))?
-                    } else {
-                        tool_fn.call0()?
-                    };
-
-                    let result_value =
-                        pythonize::depythonize(&result).unwrap_or(serde_json::Value::Null);
-                    let response_json = wrap_tool_result(result_value);
-
-                    conversation.push(GeminiContent {
-                        parts: vec![GeminiPart::FunctionResponse {
-                            function_response: FunctionResponseData {
-                                name: tool_call.name.clone(),
-                                response: response_json,
-                            },
-                        }],
-                        role: Some("function".to_string()),
-                    });
-                }
-            }
-        }
-
-        Err(PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(
-            "Max iterations reached without getting a final answer",
-        ))
+            options,
+        })
     }
 }
 
+model_methods!(GeminiModel);
+
+/// `user` and `safety_identifier` apply to every call, and each call that runs the model
+/// can override them for that call only.
 #[pyclass]
 pub struct OpenAIModel {
     model: Option<String>,
     tools: Option<Vec<Py<PyAny>>>,
-    api_key: Option<String>,
+    api_key: Option<ApiKey>,
+    user: Option<String>,
+    safety_identifier: Option<String>,
+    options: AgentOptions,
 }
 
 impl OpenAIModel {
@@ -374,137 +997,155 @@ impl OpenAIModel {
         if let Some(k) = &self.api_key {
             client = client.with_api_key(k.clone());
         }
-        let tools_json = convert_tools(py, &self.tools);
+        client = client.with_retry_policy(self.options.retry_policy());
+        if let Some(signer) = &self.options.request_signer {
+            client = client.with_signer(signer.clone());
+        }
+        if let Some(system) = self.options.system_prompt("openai", has_tools(&self.tools)) {
+            client = client.with_system(system);
+        }
+        if let Some(prefill) = &self.options.prefill {
+            client = client.with_prefill(prefill.clone());
+        }
+        if let Some(temperature) = self.options.temperature {
+            client = client.with_temperature(temperature);
+        }
+        if let Some(transform) = &self.options.message_transform {
+            client = client.with_message_transform(transform.clone(), "openai");
+        }
+        if let Some(bias) = &self.options.logit_bias {
+            client = client.with_logit_bias(bias.clone());
+        }
+        if let Some(url) = &self.options.base_url {
+            client = client.with_base_url(url.clone());
+        }
+        if let Some(deployments) = &self.options.deployments {
+            client = client.with_deployments(deployments.clone());
+        }
+        if let Some(template) = &self.options.chat_template {
+            client = client.with_chat_template(template.clone());
+        }
+        if let Some(u) = &self.user {
+            client = client.with_user(u.clone());
+        }
+        if let Some(id) = &self.safety_identifier {
+            client = client.with_safety_identifier(id.clone());
+        }
+        let strict = strict_flags(py, &self.tools, self.options.strict_tools);
+        let tools_json: Vec<_> = convert_tools(py, &self.tools)
+            .into_iter()
+            .zip(strict)
+            .map(|(tool, strict)| {
+                if strict {
+                    openai::strict_function(tool)
+                } else {
+                    tool
+                }
+            })
+            .collect();
         if !tools_json.is_empty() {
             client = client.with_tools(tools_json);
         }
         client
     }
+
+    /// Build a client with per-call attribution overrides applied
+    fn build_call_client(
+        &self,
+        py: Python,
+        user: Option<String>,
+        safety_identifier: Option<String>,
+    ) -> OpenAI {
+        let mut client = self.build_client(py);
+        if let Some(u) = user {
+            client = client.with_user(u);
+        }
+        if let Some(id) = safety_identifier {
+            client = client.with_safety_identifier(id);
+        }
+        client
+    }
+
+    /// The client and options for one call (internal method)
+    fn call(
+        &self,
+        py: Python,
+        user: Option<String>,
+        safety_identifier: Option<String>,
+        context: Option<Bound<'_, Tenant>>,
+        tool_model: Option<String>,
+        idempotency_key: Option<String>,
+    ) -> Call<OpenAI> {
+        let client = self.build_call_client(py, user, safety_identifier);
+        Call::new(
+            py,
+            client,
+            &self.tools,
+            &self.options,
+            context,
+            tool_model,
+            idempotency_key,
+        )
+    }
 }
 
 #[pymethods]
 impl OpenAIModel {
     #[new]
-    #[pyo3(signature = (model=None, tools=None, api_key=None))]
-    fn new(model: Option<String>, tools: Option<Vec<Py<PyAny>>>, api_key: Option<String>) -> Self {
-        OpenAIModel {
+    #[pyo3(signature = (model=None, tools=None, api_key=None, user=None, safety_identifier=None, **options))]
+    fn new(
+        model: Option<String>,
+        tools: Option<Vec<Py<PyAny>>>,
+        api_key: Option<ApiKey>,
+        user: Option<String>,
+        safety_identifier: Option<String>,
+        options: Option<&Bound<'_, PyDict>>,
+    ) -> PyResult<Self> {
+        let options = AgentOptions::from_kwargs(options)?;
+        options.check_provider("openai")?;
+        Ok(OpenAIModel {
             model,
             tools,
             api_key,
-        }
+            user,
+            safety_identifier,
+            options,
+        })
     }
 
-    fn add_tool(&mut self, tool: Py<PyAny>) {
-        if let Some(tools) = &mut self.tools {
-            tools.push(tool);
-        } else {
-            self.tools = Some(vec![tool]);
-        }
+    /// Pick up a background response by id, e.g. one submitted by another process.
+    fn get_long_running(&self, py: Python, job_id: String) -> jobs::LongRunningJob {
+        jobs::LongRunningJob::resume_openai(self.build_client(py), job_id)
     }
 
-    /// Invoke the model.
-    /// If tools are provided, this will run the agent loop (execute tools) until a final answer is reached.
-    /// If no tools are provided, it runs a single-shot completion.
-    fn invoke(&self, py: Python, query: String) -> PyResult<AgentResponse> {
-        let has_tools = self.tools.as_ref().map(|t| !t.is_empty()).unwrap_or(false);
-
-        if !has_tools {
-            let client = self.build_client(py);
-            let response = RUNTIME.block_on(async {
-                client
-                    .invoke_with_response(&query)
-                    .await
-                    .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e))
-            })?;
-
-            return match response {
-                openai::OpenAIResponse::Text(text) => Ok(AgentResponse::Text { text }),
-                openai::OpenAIResponse::ToolCall(tool_call) => Ok(AgentResponse::ToolCall {
-                    tool_call: ToolCall {
-                        name: tool_call.name,
-                        args: serde_json::to_string(&tool_call.args)
-                            .unwrap_or_else(|_| "{}".to_string()),
-                    },
-                }),
-            };
-        }
-
-        // Agent loop logic
-        let tools_dict = pyo3::types::PyDict::new(py);
-        if let Some(tools) = &self.tools {
-            for tool in tools {
-                let tool_obj = tool.bind(py);
-                if let Ok(name) = tool_obj.getattr("__name__") {
-                    tools_dict.set_item(name, tool_obj)?;
-                }
-            }
-        }
-
+    /// Create an OpenAI assistant (Assistants API) with this model, system prompt and
+    /// tools. Conversations with it run on server-side threads.
+    #[pyo3(signature = (name=None))]
+    fn create_assistant(
+        &self,
+        py: Python,
+        name: Option<String>,
+    ) -> PyResult<assistants::Assistant> {
         let client = self.build_client(py);
-        let mut conversation = vec![OpenAIMessage {
-            role: "user".to_string(),
-            content: query.clone(),
-            name: None,
-            tool_call_id: None,
-            tool_calls: None,
-        }];
-
-        for _iteration in 0..MAX_TOOL_ITERATIONS {
-            let (response, assistant_message) = RUNTIME.block_on(async {
-                client
-                    .chat(conversation.clone())
-                    .await
-                    .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e))
-            })?;
-
-            conversation.push(assistant_message);
-
-            match response {
-                openai::OpenAIResponse::Text(text) => {
-                    return Ok(AgentResponse::Text { text });
-                }
-                openai::OpenAIResponse::ToolCall(tool_call) => {
-                    let tool_fn = tools_dict.get_item(&tool_call.name)?.ok_or_else(|| {
-                        PyErr::new::<pyo3::exceptions::PyKeyError, _>(format!(
-                            "Tool '{}' not found",
-                            tool_call.name
-                        ))
-                    })?;
-
-                    let kwargs = pythonize::pythonize(py, &tool_call.args)?;
-                    let result = if let Ok(dict) = kwargs.cast::<pyo3::types::PyDict>() {
-                        tool_fn.call((), Some(&dict))?
-                    } else {
-                        tool_fn.call0()?
-                    };
-
-                    let result_value =
-                        pythonize::depythonize(&result).unwrap_or(serde_json::Value::Null);
-                    let result_text =
-                        serde_json::to_string(&result_value).unwrap_or_else(|_| "null".to_string());
-
-                    conversation.push(OpenAIMessage {
-                        role: "tool".to_string(),
-                        content: result_text,
-                        name: None,
-                        tool_call_id: Some(tool_call.id.clone()),
-                        tool_calls: None,
-                    });
-                }
-            }
-        }
+        assistants::Assistant::create(py, client, &self.tools, &self.options, name)
+    }
 
-        Err(PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(
-            "Max iterations reached without getting a final answer",
-        ))
+    /// Use an existing assistant by id. This model's tools are executed when its runs
+    /// call them, and its system prompt is added to every run.
+    fn get_assistant(&self, py: Python, assistant_id: String) -> PyResult<assistants::Assistant> {
+        let client = self.build_client(py);
+        assistants::Assistant::retrieve(py, client, &self.tools, &self.options, assistant_id)
     }
 }
 
+model_methods!(OpenAIModel(user, safety_identifier));
+
 #[pyclass]
 pub struct ClaudeModel {
     model: Option<String>,
     tools: Option<Vec<Py<PyAny>>>,
-    api_key: Option<String>,
+    api_key: Option<ApiKey>,
+    options: AgentOptions,
 }
 
 impl ClaudeModel {
@@ -517,137 +1158,291 @@ impl ClaudeModel {
         if let Some(k) = &self.api_key {
             client = client.with_api_key(k.clone());
         }
+        client = client.with_retry_policy(self.options.retry_policy());
+        if let Some(signer) = &self.options.request_signer {
+            client = client.with_signer(signer.clone());
+        }
+        if let Some(system) = self
+            .options
+            .system_prompt("anthropic", has_tools(&self.tools))
+        {
+            client = client.with_system(system);
+        }
+        if let Some(prefill) = &self.options.prefill {
+            client = client.with_prefill(prefill.clone());
+        }
+        if let Some(temperature) = self.options.temperature {
+            client = client.with_temperature(temperature);
+        }
+        if let Some(transform) = &self.options.message_transform {
+            client = client.with_message_transform(transform.clone());
+        }
         let tools_json = convert_tools(py, &self.tools);
         if !tools_json.is_empty() {
             client = client.with_tools(tools_json);
         }
         client
     }
+
+    /// The client and options for one call (internal method)
+    fn call(
+        &self,
+        py: Python,
+        context: Option<Bound<'_, Tenant>>,
+        tool_model: Option<String>,
+        idempotency_key: Option<String>,
+    ) -> Call<Claude> {
+        let client = self.build_client(py);
+        Call::new(
+            py,
+            client,
+            &self.tools,
+            &self.options,
+            context,
+            tool_model,
+            idempotency_key,
+        )
+    }
 }
 
 #[pymethods]
 impl ClaudeModel {
     #[new]
-    #[pyo3(signature = (model=None, tools=None, api_key=None))]
-    fn new(model: Option<String>, tools: Option<Vec<Py<PyAny>>>, api_key: Option<String>) -> Self {
-        ClaudeModel {
+    #[pyo3(signature = (model=None, tools=None, api_key=None, **options))]
+    fn new(
+        model: Option<String>,
+        tools: Option<Vec<Py<PyAny>>>,
+        api_key: Option<ApiKey>,
+        options: Option<&Bound<'_, PyDict>>,
+    ) -> PyResult<Self> {
+        let options = AgentOptions::from_kwargs(options)?;
+        options.check_provider("anthropic")?;
+        Ok(ClaudeModel {
             model,
             tools,
             api_key,
-        }
-    }
-
-    fn add_tool(&mut self, tool: Py<PyAny>) {
-        if let Some(tools) = &mut self.tools {
-            tools.push(tool);
-        } else {
-            self.tools = Some(vec![tool]);
-        }
+            options,
+        })
     }
+}
 
-    /// Invoke the model.
-    /// If tools are provided, this will run the agent loop (execute tools) until a final answer is reached.
-    /// If no tools are provided, it runs a single-shot completion.
-    fn invoke(&self, py: Python, query: String) -> PyResult<AgentResponse> {
-        let has_tools = self.tools.as_ref().map(|t| !t.is_empty()).unwrap_or(false);
+model_methods!(ClaudeModel);
 
-        if !has_tools {
-            let client = self.build_client(py);
-            let response = RUNTIME.block_on(async {
-                client
-                    .invoke_with_response(&query)
-                    .await
-                    .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e))
-            })?;
+#[pyclass]
+pub struct HuggingFaceModel {
+    model: Option<String>,
+    tools: Option<Vec<Py<PyAny>>>,
+    api_key: Option<ApiKey>,
+    endpoint: Option<String>,
+    task: huggingface::Task,
+    inference_provider: Option<String>,
+    options: AgentOptions,
+}
 
-            return match response {
-                claude::ClaudeResponse::Text(text) => Ok(AgentResponse::Text { text }),
-                claude::ClaudeResponse::ToolCall(tool_call) => Ok(AgentResponse::ToolCall {
-                    tool_call: ToolCall {
-                        name: tool_call.name,
-                        args: serde_json::to_string(&tool_call.args)
-                            .unwrap_or_else(|_| "{}".to_string()),
-                    },
-                }),
-            };
+impl HuggingFaceModel {
+    /// Build a configured HuggingFace client (internal method)
+    fn build_client(&self, py: Python) -> HuggingFace {
+        let mut client = HuggingFace::new().with_task(self.task);
+        if let Some(m) = &self.model {
+            client = client.with_model(m.clone());
         }
-
-        // Agent loop logic
-        let tools_dict = pyo3::types::PyDict::new(py);
-        if let Some(tools) = &self.tools {
-            for tool in tools {
-                let tool_obj = tool.bind(py);
-                if let Ok(name) = tool_obj.getattr("__name__") {
-                    tools_dict.set_item(name, tool_obj)?;
-                }
-            }
+        if let Some(k) = &self.api_key {
+            client = client.with_api_key(k.clone());
         }
+        if let Some(endpoint) = &self.endpoint {
+            client = client.with_endpoint(endpoint.clone());
+        }
+        if let Some(provider) = &self.inference_provider {
+            client = client.with_inference_provider(provider.clone());
+        }
+        client = client.with_retry_policy(self.options.retry_policy());
+        if let Some(signer) = &self.options.request_signer {
+            client = client.with_signer(signer.clone());
+        }
+        if let Some(system) = self
+            .options
+            .system_prompt("huggingface", has_tools(&self.tools))
+        {
+            client = client.with_system(system);
+        }
+        if let Some(prefill) = &self.options.prefill {
+            client = client.with_prefill(prefill.clone());
+        }
+        if let Some(temperature) = self.options.temperature {
+            client = client.with_temperature(temperature);
+        }
+        if let Some(transform) = &self.options.message_transform {
+            client = client.with_message_transform(transform.clone());
+        }
+        if let Some(template) = &self.options.chat_template {
+            client = client.with_chat_template(template.clone());
+        }
+        let tools_json = convert_tools(py, &self.tools);
+        if !tools_json.is_empty() {
+            client = client.with_tools(tools_json);
+        }
+        client
+    }
 
+    /// The client and options for one call (internal method)
+    fn call(
+        &self,
+        py: Python,
+        context: Option<Bound<'_, Tenant>>,
+        tool_model: Option<String>,
+        idempotency_key: Option<String>,
+    ) -> Call<HuggingFace> {
         let client = self.build_client(py);
-        let mut conversation = vec![ClaudeMessage {
-            role: "user".to_string(),
-            content: vec![ClaudeContentBlock::Text {
-                text: query.clone(),
-            }],
-        }];
-
-        for _iteration in 0..MAX_TOOL_ITERATIONS {
-            let (response, assistant_message) = RUNTIME.block_on(async {
-                client
-                    .exchange(conversation.clone())
-                    .await
-                    .map_err(|e| PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(e))
-            })?;
-
-            conversation.push(assistant_message);
-
-            match response {
-                claude::ClaudeResponse::Text(text) => {
-                    return Ok(AgentResponse::Text { text });
-                }
-                claude::ClaudeResponse::ToolCall(tool_call) => {
-                    let tool_fn = tools_dict.get_item(&tool_call.name)?.ok_or_else(|| {
-                        PyErr::new::<pyo3::exceptions::PyKeyError, _>(format!(
-                            "Tool '{}' not found",
-                            tool_call.name
-                        ))
-                    })?;
-
-                    let kwargs = pythonize::pythonize(py, &tool_call.args)?;
-                    let result = if let Ok(dict) = kwargs.cast::<pyo3::types::PyDict>() {
-                        tool_fn.call((), Some(&dict))?
-                    } else {
-                        tool_fn.call0()?
-                    };
-
-                    let result_value =
-                        pythonize::depythonize(&result).unwrap_or(serde_json::Value::Null);
-                    let wrapped_result = wrap_tool_result(result_value);
-
-                    conversation.push(ClaudeMessage {
-                        role: "user".to_string(),
-                        content: vec![ClaudeContentBlock::ToolResult {
-                            tool_use_id: tool_call.id.clone(),
-                            content: wrapped_result,
-                        }],
-                    });
-                }
-            }
-        }
+        Call::new(
+            py,
+            client,
+            &self.tools,
+            &self.options,
+            context,
+            tool_model,
+            idempotency_key,
+        )
+    }
+}
 
-        Err(PyErr::new::<pyo3::exceptions::PyRuntimeError, _>(
-            "Max iterations reached without getting a final answer",
-        ))
+#[pymethods]
+impl HuggingFaceModel {
+    #[new]
+    #[pyo3(signature = (model=None, tools=None, api_key=None, endpoint=None, task="chat-completion", inference_provider=None, **options))]
+    fn new(
+        model: Option<String>,
+        tools: Option<Vec<Py<PyAny>>>,
+        api_key: Option<ApiKey>,
+        endpoint: Option<String>,
+        task: &str,
+        inference_provider: Option<String>,
+        options: Option<&Bound<'_, PyDict>>,
+    ) -> PyResult<Self> {
+        let options = AgentOptions::from_kwargs(options)?;
+        options.check_provider("huggingface")?;
+        let task = huggingface::Task::parse(task)?;
+        if task == huggingface::Task::TextGeneration && options.chat_template.is_none() {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "task='text-generation' needs a chat_template to build prompts",
+            ));
+        }
+        if endpoint.is_some() && inference_provider.is_some() {
+            return Err(pyo3::exceptions::PyValueError::new_err(
+                "inference_provider applies to the router; it cannot be combined with endpoint",
+            ));
+        }
+        Ok(HuggingFaceModel {
+            model,
+            tools,
+            api_key,
+            endpoint,
+            task,
+            inference_provider,
+            options,
+        })
     }
 }
 
+model_methods!(HuggingFaceModel);
+
 #[pymodule]
-fn rusted_chain(_py: Python, m: &Bound<'_, PyModule>) -> PyResult<()> {
+fn rusted_chain(py: Python, m: &Bound<'_, PyModule>) -> PyResult<()> {
+    // Warnings from background work (tracker uploads, usage webhooks, the audit log)
+    // go to Python's `logging`, under loggers named after their module.
+    pyo3_log::init();
+    asyncio::init();
     m.add_function(wrap_pyfunction!(create_agent, m)?)?;
+    m.add_class::<builder::AgentBuilder>()?;
     m.add_class::<GeminiModel>()?;
     m.add_class::<OpenAIModel>()?;
     m.add_class::<ClaudeModel>()?;
+    m.add_class::<HuggingFaceModel>()?;
     m.add_class::<AgentResponse>()?;
     m.add_class::<ToolCall>()?;
+    m.add_class::<ResponseMetadata>()?;
+    m.add_class::<tool_usage::ToolUsage>()?;
+    m.add_class::<estimate::Estimate>()?;
+    m.add_class::<retry::Retries>()?;
+    m.add_class::<citations::Citation>()?;
+    m.add_class::<context::RunContext>()?;
+    m.add_class::<media::VideoFile>()?;
+    m.add_class::<jobs::LongRunningJob>()?;
+    m.add_class::<assistants::Assistant>()?;
+    m.add_class::<assistants::AssistantThread>()?;
+    m.add_class::<session::Session>()?;
+    m.add_class::<keys::KeyPool>()?;
+    m.add_class::<balancer::LoadBalancer>()?;
+    m.add_class::<downgrade::DowngradePolicy>()?;
+    m.add_class::<memory::MemoryPolicy>()?;
+    m.add_class::<tenant::Tenant>()?;
+    m.add_class::<replay::Replay>()?;
+    m.add_class::<pii::PiiPolicy>()?;
+    m.add_class::<compression::PromptCompressor>()?;
+    m.add_class::<serialize::ToolSerializer>()?;
+    m.add_class::<artifacts::Artifact>()?;
+    m.add_class::<delegate::Delegate>()?;
+    m.add_class::<TextStream>()?;
+    m.add_class::<JsonStream>()?;
+    m.add_class::<server_events::EventStream>()?;
+    m.add_class::<workflow::Workflow>()?;
+    m.add_class::<workflow::WorkflowResult>()?;
+    m.add_class::<workflow::Router>()?;
+    m.add_class::<prompts::PromptTemplate>()?;
+    m.add_class::<prompts::PromptLibrary>()?;
+    m.add_class::<transforms::OutputTransform>()?;
+    m.add_class::<message_transforms::MessageTransform>()?;
+    m.add_class::<signing::HmacSigner>()?;
+    m.add("END", workflow::END)?;
+    m.add_class::<constants::Providers>()?;
+    m.add_class::<constants::Roles>()?;
+    m.add_function(wrap_pyfunction!(parse_partial_json, m)?)?;
+    m.add_function(wrap_pyfunction!(splitter::token_ids, m)?)?;
+    m.add_function(wrap_pyfunction!(estimate::estimate, m)?)?;
+    m.add_function(wrap_pyfunction!(map_reduce::map_reduce, m)?)?;
+    m.add_function(wrap_pyfunction!(vision::analyze_image, m)?)?;
+    m.add_function(wrap_pyfunction!(classify::classify, m)?)?;
+    m.add_class::<classify::Classification>()?;
+    m.add_function(wrap_pyfunction!(moderation::moderate, m)?)?;
+    m.add_class::<moderation::ModerationResult>()?;
+    m.add_function(wrap_pyfunction!(validation::validate_answer, m)?)?;
+    m.add_class::<validation::ValidationReport>()?;
+    m.add_class::<validation::QuoteCheck>()?;
+    m.add_class::<splitter::RecursiveCharacterTextSplitter>()?;
+    m.add_class::<splitter::TokenTextSplitter>()?;
+    m.add_class::<document::Document>()?;
+    m.add_class::<rerank::Reranker>()?;
+    m.add_class::<rerank::RerankResult>()?;
+    m.add_class::<embeddings::Embeddings>()?;
+    m.add_class::<vector_store::PyVectorStore>()?;
+    #[cfg(feature = "loaders")]
+    loaders::register(m)?;
+    testing::register(m)?;
+    m.add_function(wrap_pyfunction!(export::load_runs, m)?)?;
+    m.add_function(wrap_pyfunction!(finetune::export_finetune_jsonl, m)?)?;
+    m.add_function(wrap_pyfunction!(tracking::flush_tracking, m)?)?;
+    m.add_function(wrap_pyfunction!(usage_webhook::add_usage_webhook, m)?)?;
+    m.add_function(wrap_pyfunction!(usage_webhook::clear_usage_webhooks, m)?)?;
+    m.add_function(wrap_pyfunction!(usage_webhook::flush_usage_webhooks, m)?)?;
+    m.add_function(wrap_pyfunction!(runtime::shutdown, m)?)?;
+    m.add_function(wrap_pyfunction!(runtime::configure_runtime, m)?)?;
+    m.add_function(wrap_pyfunction!(circuit::configure_circuit_breaker, m)?)?;
+    m.add_function(wrap_pyfunction!(circuit::circuit_breakers, m)?)?;
+    m.add_function(wrap_pyfunction!(diagnostics::diagnostics, m)?)?;
+    m.add_function(wrap_pyfunction!(metrics::get_metrics, m)?)?;
+    m.add_function(wrap_pyfunction!(metrics::metrics_text, m)?)?;
+    m.add_function(wrap_pyfunction!(metrics::start_metrics_server, m)?)?;
+    m.add_function(wrap_pyfunction!(traces::serve_traces, m)?)?;
+    m.add("ProviderError", py.get_type::<ProviderError>())?;
+    m.add("RateLimitError", py.get_type::<RateLimitError>())?;
+    m.add("OverloadedError", py.get_type::<OverloadedError>())?;
+    m.add("ContextLengthError", py.get_type::<ContextLengthError>())?;
+    m.add("ContentFilterError", py.get_type::<ContentFilterError>())?;
+    m.add("InvalidRequestError", py.get_type::<InvalidRequestError>())?;
+    m.add(
+        "ProviderUnavailableError",
+        py.get_type::<ProviderUnavailableError>(),
+    )?;
+    m.add("ToolLoopError", py.get_type::<ToolLoopError>())?;
+    m.add("BudgetExceededError", py.get_type::<BudgetExceededError>())?;
     Ok(())
 }
@@ -0,0 +1,89 @@
+//! Per-tool concurrency and rate limits, shared by every run of a model.
+
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+#[derive(Default)]
+struct LimiterState {
+    running: usize,
+    /// Start times of the calls made within the last `RATE_WINDOW`.
+    recent: VecDeque<Instant>,
+}
+
+/// Throttles calls to one tool: at most `max_concurrency` in flight and at most
+/// `rate_limit_per_min` started in any sliding minute.
+pub(crate) struct ToolLimiter {
+    max_concurrency: Option<usize>,
+    rate_limit_per_min: Option<usize>,
+    state: Mutex<LimiterState>,
+    released: Condvar,
+}
+
+/// A slot held while the tool runs; dropping it lets the next waiter in.
+pub(crate) struct ToolPermit<'a> {
+    limiter: &'a ToolLimiter,
+}
+
+impl Drop for ToolPermit<'_> {
+    fn drop(&mut self) {
+        let mut state = self.limiter.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.running -= 1;
+        self.limiter.released.notify_all();
+    }
+}
+
+impl ToolLimiter {
+    pub(crate) fn new(max_concurrency: Option<usize>, rate_limit_per_min: Option<usize>) -> Self {
+        Self {
+            max_concurrency,
+            rate_limit_per_min,
+            state: Mutex::new(LimiterState::default()),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Block until the tool may run. Call with the GIL released.
+    pub(crate) fn acquire(&self) -> ToolPermit<'_> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        loop {
+            let now = Instant::now();
+            while state
+                .recent
+                .front()
+                .is_some_and(|start| now.duration_since(*start) >= RATE_WINDOW)
+            {
+                state.recent.pop_front();
+            }
+
+            let busy = self
+                .max_concurrency
+                .is_some_and(|limit| state.running >= limit);
+            let rate_wait = match self.rate_limit_per_min {
+                Some(limit) if state.recent.len() >= limit => state
+                    .recent
+                    .front()
+                    .map(|oldest| RATE_WINDOW.saturating_sub(now.duration_since(*oldest))),
+                _ => None,
+            };
+
+            if !busy && rate_wait.is_none() {
+                state.running += 1;
+                state.recent.push_back(now);
+                return ToolPermit { limiter: self };
+            }
+
+            state = match rate_wait {
+                Some(wait) => {
+                    self.released
+                        .wait_timeout(state, wait)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0
+                }
+                None => self.released.wait(state).unwrap_or_else(|e| e.into_inner()),
+            };
+        }
+    }
+}
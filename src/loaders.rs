@@ -0,0 +1,301 @@
+//! Document loaders for PDF, DOCX, HTML, CSV and Markdown files, built with the
+//! `loaders` feature. Parsing runs without the GIL.
+
+use crate::document::Document;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use serde_json::{json, Map, Value};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Wide enough that html2text never wraps lines.
+const HTML_WIDTH: usize = 100_000;
+
+/// Text and metadata produced off the GIL, turned into `Document`s afterwards.
+struct Loaded {
+    text: String,
+    metadata: Map<String, Value>,
+}
+
+impl Loaded {
+    fn new(path: &Path, text: String) -> Self {
+        let mut metadata = Map::new();
+        metadata.insert("source".into(), json!(path.display().to_string()));
+        Loaded { text, metadata }
+    }
+
+    fn with(mut self, key: &str, value: Value) -> Self {
+        self.metadata.insert(key.into(), value);
+        self
+    }
+}
+
+fn parse_error(path: &Path, err: impl std::fmt::Display) -> PyErr {
+    PyValueError::new_err(format!("failed to parse {}: {}", path.display(), err))
+}
+
+fn into_documents(py: Python, loaded: Vec<Loaded>) -> PyResult<Vec<Py<Document>>> {
+    loaded
+        .into_iter()
+        .map(|doc| {
+            let metadata = pythonize::pythonize(py, &doc.metadata)?.cast_into::<PyDict>()?;
+            Document::create(py, doc.text, metadata, None)
+        })
+        .collect()
+}
+
+fn run<F>(py: Python, load: F) -> PyResult<Vec<Py<Document>>>
+where
+    F: FnOnce() -> PyResult<Vec<Loaded>> + Send,
+{
+    let loaded = py.detach(load)?;
+    into_documents(py, loaded)
+}
+
+fn pdf(path: &Path, by_page: bool) -> PyResult<Vec<Loaded>> {
+    // Surface a missing file as FileNotFoundError rather than a parse failure.
+    fs::metadata(path)?;
+    let pages: Vec<String> = pdf_extract::extract_text_by_pages(path)
+        .map_err(|e| parse_error(path, e))?
+        .into_iter()
+        .map(|page| page.trim().to_string())
+        .collect();
+    let total = pages.len();
+    if !by_page {
+        return Ok(vec![
+            Loaded::new(path, pages.join("\n\n")).with("pages", json!(total))
+        ]);
+    }
+    Ok(pages
+        .into_iter()
+        .enumerate()
+        .map(|(i, text)| {
+            Loaded::new(path, text)
+                .with("page", json!(i + 1))
+                .with("pages", json!(total))
+        })
+        .collect())
+}
+
+fn docx(path: &Path) -> PyResult<Vec<Loaded>> {
+    use quick_xml::events::Event;
+
+    let mut archive =
+        zip::ZipArchive::new(fs::File::open(path)?).map_err(|e| parse_error(path, e))?;
+    let mut xml = String::new();
+    archive
+        .by_name("word/document.xml")
+        .map_err(|e| parse_error(path, e))?
+        .read_to_string(&mut xml)?;
+
+    let mut reader = quick_xml::Reader::from_str(&xml);
+    let mut text = String::new();
+    let mut in_text = false;
+    loop {
+        match reader.read_event().map_err(|e| parse_error(path, e))? {
+            Event::Start(tag) if tag.local_name().as_ref() == "t" => in_text = true,
+            Event::End(tag) => match tag.local_name().as_ref() {
+                "t" => in_text = false,
+                "p" => text.push('\n'),
+                _ => {}
+            },
+            Event::Empty(tag) => match tag.local_name().as_ref() {
+                "tab" => text.push('\t'),
+                "br" | "cr" => text.push('\n'),
+                _ => {}
+            },
+            Event::Text(content) if in_text => text.push_str(&content.xml10_content()),
+            Event::GeneralRef(entity) if in_text => {
+                let entity = format!("&{};", entity.as_ref());
+                text.push_str(
+                    &quick_xml::escape::unescape(&entity).unwrap_or(entity.as_str().into()),
+                );
+            }
+            Event::Eof => break,
+            _ => {}
+        }
+    }
+    Ok(vec![Loaded::new(path, text)])
+}
+
+/// Readable text of an HTML document, without markdown-style decoration.
+fn html_text(html: &str) -> Result<String, html2text::Error> {
+    html2text::config::with_decorator(html2text::render::TrivialDecorator::new())
+        .string_from_read(html.as_bytes(), HTML_WIDTH)
+}
+
+/// Contents of the first `<title>` element, if any.
+fn html_title(html: &str) -> Option<String> {
+    let lower = html.to_ascii_lowercase();
+    let open = lower.find("<title")?;
+    let start = open + lower[open..].find('>')? + 1;
+    let end = start + lower[start..].find("</title")?;
+    let title = html[start..end].trim();
+    (!title.is_empty()).then(|| title.to_string())
+}
+
+fn html(path: &Path) -> PyResult<Vec<Loaded>> {
+    let source = fs::read_to_string(path)?;
+    let text = html_text(&source).map_err(|e| parse_error(path, e))?;
+    let loaded = Loaded::new(path, text);
+    Ok(vec![match html_title(&source) {
+        Some(title) => loaded.with("title", json!(title)),
+        None => loaded,
+    }])
+}
+
+fn csv(path: &Path, content_columns: Option<&[String]>, delimiter: u8) -> PyResult<Vec<Loaded>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(delimiter)
+        .from_path(path)
+        .map_err(|e| parse_error(path, e))?;
+    let headers = reader.headers().map_err(|e| parse_error(path, e))?.clone();
+    if let Some(columns) = content_columns {
+        if let Some(missing) = columns.iter().find(|c| !headers.iter().any(|h| h == *c)) {
+            return Err(PyValueError::new_err(format!(
+                "column '{}' not found in {}",
+                missing,
+                path.display()
+            )));
+        }
+    }
+
+    let mut rows = Vec::new();
+    for (row, record) in reader.records().enumerate() {
+        let record = record.map_err(|e| parse_error(path, e))?;
+        let mut lines = Vec::new();
+        let mut loaded = Loaded::new(path, String::new()).with("row", json!(row));
+        for (header, value) in headers.iter().zip(record.iter()) {
+            match content_columns {
+                Some(columns) if !columns.iter().any(|c| c == header) => {
+                    loaded = loaded.with(header, json!(value));
+                }
+                _ => lines.push(format!("{}: {}", header, value)),
+            }
+        }
+        loaded.text = lines.join("\n");
+        rows.push(loaded);
+    }
+    Ok(rows)
+}
+
+fn markdown(path: &Path) -> PyResult<Vec<Loaded>> {
+    use pulldown_cmark::{Event, Parser, Tag, TagEnd};
+
+    let source = fs::read_to_string(path)?;
+    let mut text = String::new();
+    let mut title: Option<String> = None;
+    let mut in_heading = false;
+    for event in Parser::new(&source) {
+        match event {
+            Event::Start(Tag::Heading { .. }) => in_heading = true,
+            Event::End(TagEnd::Heading(_)) => {
+                in_heading = false;
+                text.push_str("\n\n");
+            }
+            Event::End(TagEnd::Paragraph | TagEnd::CodeBlock | TagEnd::BlockQuote(_)) => {
+                text.push_str("\n\n")
+            }
+            Event::End(TagEnd::List(_)) => text.push('\n'),
+            Event::End(TagEnd::Item) | Event::SoftBreak | Event::HardBreak => text.push('\n'),
+            Event::Text(content) | Event::Code(content) => {
+                if in_heading && title.is_none() {
+                    title = Some(content.to_string());
+                }
+                text.push_str(&content);
+            }
+            _ => {}
+        }
+    }
+    let loaded = Loaded::new(path, text.trim_end().to_string());
+    Ok(vec![match title {
+        Some(title) => loaded.with("title", json!(title)),
+        None => loaded,
+    }])
+}
+
+fn plain(path: &Path) -> PyResult<Vec<Loaded>> {
+    Ok(vec![Loaded::new(path, fs::read_to_string(path)?)])
+}
+
+/// Text of a PDF, one `Document` per page unless `by_page` is false.
+#[pyfunction]
+#[pyo3(signature = (path, by_page=true))]
+fn load_pdf(py: Python, path: PathBuf, by_page: bool) -> PyResult<Vec<Py<Document>>> {
+    run(py, || pdf(&path, by_page))
+}
+
+/// Paragraph text of a Word document.
+#[pyfunction]
+fn load_docx(py: Python, path: PathBuf) -> PyResult<Vec<Py<Document>>> {
+    run(py, || docx(&path))
+}
+
+/// Readable text of an HTML page, with its `<title>` in the metadata.
+#[pyfunction]
+fn load_html(py: Python, path: PathBuf) -> PyResult<Vec<Py<Document>>> {
+    run(py, || html(&path))
+}
+
+/// One `Document` per row, formatted as `column: value` lines. With `content_columns`,
+/// only those columns form the text and the rest go into the metadata.
+#[pyfunction]
+#[pyo3(signature = (path, content_columns=None, delimiter=','))]
+fn load_csv(
+    py: Python,
+    path: PathBuf,
+    content_columns: Option<Vec<String>>,
+    delimiter: char,
+) -> PyResult<Vec<Py<Document>>> {
+    let delimiter = u8::try_from(delimiter)
+        .map_err(|_| PyValueError::new_err("delimiter must be an ASCII character"))?;
+    run(py, || csv(&path, content_columns.as_deref(), delimiter))
+}
+
+/// Markdown rendered to plain text, with its first heading as the title.
+#[pyfunction]
+fn load_markdown(py: Python, path: PathBuf) -> PyResult<Vec<Py<Document>>> {
+    run(py, || markdown(&path))
+}
+
+/// Load any supported file, choosing the loader by extension. Unknown extensions are
+/// read as plain text.
+#[pyfunction]
+fn load(py: Python, path: PathBuf) -> PyResult<Vec<Py<Document>>> {
+    let extension = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase)
+        .unwrap_or_default();
+    run(py, || match extension.as_str() {
+        "pdf" => pdf(&path, true),
+        "docx" => docx(&path),
+        "html" | "htm" => html(&path),
+        "csv" => csv(&path, None, b','),
+        "tsv" => csv(&path, None, b'\t'),
+        "md" | "markdown" => markdown(&path),
+        _ => plain(&path),
+    })
+}
+
+/// Convert an HTML string to readable text.
+#[pyfunction]
+fn html_to_text(py: Python, html: &str) -> PyResult<String> {
+    py.detach(|| html_text(html))
+        .map_err(|e| PyValueError::new_err(e.to_string()))
+}
+
+/// Add the `loaders` submodule to the extension module.
+pub(crate) fn register(parent: &Bound<'_, PyModule>) -> PyResult<()> {
+    let m = PyModule::new(parent.py(), "loaders")?;
+    m.add_function(wrap_pyfunction!(load, &m)?)?;
+    m.add_function(wrap_pyfunction!(load_pdf, &m)?)?;
+    m.add_function(wrap_pyfunction!(load_docx, &m)?)?;
+    m.add_function(wrap_pyfunction!(load_html, &m)?)?;
+    m.add_function(wrap_pyfunction!(load_csv, &m)?)?;
+    m.add_function(wrap_pyfunction!(load_markdown, &m)?)?;
+    m.add_function(wrap_pyfunction!(html_to_text, &m)?)?;
+    parent.add_submodule(&m)
+}
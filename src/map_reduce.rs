@@ -0,0 +1,173 @@
+//! Map-reduce summarization of documents too long for a single request.
+
+use crate::agent::{self, ChatClient};
+use crate::client::{with_client, AnyClient};
+use crate::error::RustedChainError;
+use crate::options::AgentOptions;
+use crate::run::{Labels, RunRecord};
+use crate::splitter::{self, Keep, Length, SplitConfig};
+use crate::RUNTIME;
+use futures::stream::{self, StreamExt};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyString;
+
+/// Insert `text` at the `{text}` placeholder, or append it when there is none.
+fn fill(template: &str, text: &str) -> String {
+    if template.contains("{text}") {
+        template.replace("{text}", text)
+    } else {
+        format!("{}\n\n{}", template, text)
+    }
+}
+
+/// Pack outputs into groups whose combined size fits `budget` tokens. Every group takes
+/// at least two outputs so each reduce round shrinks the list.
+fn group(outputs: Vec<String>, budget: usize) -> Vec<String> {
+    let mut groups: Vec<Vec<String>> = Vec::new();
+    let mut size = 0;
+    for output in outputs {
+        let tokens = splitter::count_tokens(&output);
+        match groups.last_mut() {
+            Some(current) if current.len() < 2 || size + tokens <= budget => {
+                current.push(output);
+                size += tokens;
+            }
+            _ => {
+                groups.push(vec![output]);
+                size = tokens;
+            }
+        }
+    }
+    groups.into_iter().map(|g| g.join("\n\n")).collect()
+}
+
+/// Run every prompt with at most `concurrency` requests in flight, keeping order.
+async fn complete_all<C: ChatClient>(
+    client: &C,
+    options: &AgentOptions,
+    run: &mut RunRecord,
+    prompts: Vec<String>,
+    concurrency: usize,
+) -> Result<Vec<String>, RustedChainError> {
+    let record = &*run;
+    let results: Vec<_> = stream::iter(prompts)
+        .map(|prompt| async move { agent::complete(client, options, record, &prompt).await })
+        .buffered(concurrency)
+        .collect()
+        .await;
+
+    let mut outputs = Vec::with_capacity(results.len());
+    for result in results {
+        let (text, usage) = result?;
+        run.record_call(usage);
+        outputs.push(text);
+    }
+    Ok(outputs)
+}
+
+async fn run_map_reduce<C: ChatClient>(
+    client: &C,
+    options: &AgentOptions,
+    chunks: Vec<String>,
+    map_prompt: &str,
+    reduce_prompt: &str,
+    concurrency: usize,
+    chunk_tokens: usize,
+) -> Result<String, RustedChainError> {
+    let mut run = RunRecord::start(
+        client,
+        options,
+        &format!("map_reduce over {} chunks", chunks.len()),
+        Vec::new(),
+        false,
+        Labels::default(),
+    );
+
+    let result: Result<String, RustedChainError> = async {
+        let prompts = chunks.iter().map(|c| fill(map_prompt, c)).collect();
+        let mut outputs = complete_all(client, options, &mut run, prompts, concurrency).await?;
+        loop {
+            let prompts = group(outputs, chunk_tokens)
+                .iter()
+                .map(|g| fill(reduce_prompt, g))
+                .collect();
+            outputs = complete_all(client, options, &mut run, prompts, concurrency).await?;
+            if outputs.len() == 1 {
+                return Ok(outputs.remove(0));
+            }
+        }
+    }
+    .await;
+
+    run.finish(match &result {
+        Ok(text) => Ok(text.clone()),
+        Err(err) => Err(err.to_string()),
+    });
+    result
+}
+
+/// Summarize (or otherwise process) documents of any length: split them into chunks of
+/// about `chunk_tokens` tokens, run `map_prompt` on every chunk with up to `concurrency`
+/// requests in flight, then combine the results with `reduce_prompt`, hierarchically
+/// until a single answer remains. Prompts receive their input at a `{text}` placeholder.
+/// Submitting the same documents again with the same `idempotency_key` reuses the
+/// answers already received instead of requesting them twice.
+#[pyfunction]
+#[pyo3(signature = (documents, map_prompt, reduce_prompt, model, concurrency=4, chunk_tokens=3000, idempotency_key=None))]
+#[allow(clippy::too_many_arguments)]
+pub fn map_reduce(
+    py: Python,
+    documents: &Bound<'_, PyAny>,
+    map_prompt: String,
+    reduce_prompt: String,
+    model: &Bound<'_, PyAny>,
+    concurrency: usize,
+    chunk_tokens: usize,
+    idempotency_key: Option<String>,
+) -> PyResult<String> {
+    if concurrency == 0 || chunk_tokens == 0 {
+        return Err(PyValueError::new_err(
+            "concurrency and chunk_tokens must be at least 1",
+        ));
+    }
+    let documents: Vec<String> = if documents.is_instance_of::<PyString>() {
+        vec![documents.extract()?]
+    } else {
+        documents.extract()?
+    };
+    let (client, mut options) = AnyClient::from_model(py, model)?;
+    options.idempotency_key = idempotency_key;
+
+    let separators: Vec<String> = splitter::DEFAULT_SEPARATORS
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+    let config = SplitConfig {
+        chunk_size: chunk_tokens,
+        chunk_overlap: 0,
+        separators: &separators,
+        length: Length::Tokens,
+        keep: Keep::End,
+    };
+    let chunks: Vec<String> = documents
+        .iter()
+        .flat_map(|document| splitter::split_text(document, &config))
+        .collect();
+    if chunks.is_empty() {
+        return Err(PyValueError::new_err("documents contain no text"));
+    }
+
+    let result = py.detach(|| {
+        with_client!(&client, client => RUNTIME.block_on(run_map_reduce(
+            client,
+            &options,
+            chunks,
+            &map_prompt,
+            &reduce_prompt,
+            concurrency,
+            chunk_tokens,
+        )))
+    });
+    Ok(result?)
+}
@@ -0,0 +1,341 @@
+//! Images and videos sent alongside a prompt, loaded from a path, raw bytes or a URL.
+
+use crate::error::RustedChainError;
+use crate::RUNTIME;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use std::borrow::Cow;
+use std::path::PathBuf;
+
+/// What a provider accepts for one inline image.
+pub(crate) struct ImageLimits {
+    pub(crate) provider: &'static str,
+    /// Maximum base64-encoded size.
+    pub(crate) max_bytes: usize,
+    /// Maximum width and height in pixels, if the provider enforces one. Only checked
+    /// when the `images` feature can decode the image.
+    #[cfg_attr(not(feature = "images"), allow(dead_code))]
+    pub(crate) max_side: Option<u32>,
+}
+
+#[derive(Clone)]
+pub(crate) struct Image {
+    pub(crate) mime_type: String,
+    pub(crate) data: Vec<u8>,
+}
+
+/// A video clip. Gemini samples it at `fps` frames per second (default 1) between the
+/// optional offsets, given in seconds.
+#[derive(Clone)]
+pub(crate) struct Video {
+    pub(crate) mime_type: String,
+    pub(crate) data: Vec<u8>,
+    pub(crate) fps: Option<f64>,
+    pub(crate) start_offset: Option<f64>,
+    pub(crate) end_offset: Option<f64>,
+}
+
+/// A file attached to a prompt.
+pub(crate) enum Attachment {
+    Image(Image),
+    Video(Video),
+}
+
+/// A video attachment with sampling options, for `invoke_with_files`. Plain paths,
+/// bytes and URLs of video files are accepted too; this adds the options.
+#[pyclass(name = "Video")]
+pub struct VideoFile {
+    source: Py<PyAny>,
+    #[pyo3(get)]
+    fps: Option<f64>,
+    #[pyo3(get)]
+    start_offset: Option<f64>,
+    #[pyo3(get)]
+    end_offset: Option<f64>,
+}
+
+#[pymethods]
+impl VideoFile {
+    /// `source` is a file path, bytes or a URL. `fps` sets how many frames per second
+    /// are sampled; `start_offset` and `end_offset` (seconds) clip the video.
+    #[new]
+    #[pyo3(signature = (source, fps=None, start_offset=None, end_offset=None))]
+    fn new(
+        source: Py<PyAny>,
+        fps: Option<f64>,
+        start_offset: Option<f64>,
+        end_offset: Option<f64>,
+    ) -> PyResult<Self> {
+        if fps.is_some_and(|fps| fps <= 0.0) {
+            return Err(PyValueError::new_err("fps must be positive"));
+        }
+        if let (Some(start), Some(end)) = (start_offset, end_offset) {
+            if start >= end {
+                return Err(PyValueError::new_err(
+                    "start_offset must be before end_offset",
+                ));
+            }
+        }
+        Ok(VideoFile {
+            source,
+            fps,
+            start_offset,
+            end_offset,
+        })
+    }
+}
+
+/// Recognise the image formats every provider accepts from their magic bytes.
+fn sniff_image(data: &[u8]) -> Option<&'static str> {
+    if data.starts_with(b"\x89PNG\r\n\x1a\n") {
+        Some("image/png")
+    } else if data.starts_with(&[0xff, 0xd8, 0xff]) {
+        Some("image/jpeg")
+    } else if data.starts_with(b"GIF87a") || data.starts_with(b"GIF89a") {
+        Some("image/gif")
+    } else if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        Some("image/webp")
+    } else {
+        None
+    }
+}
+
+/// Recognise common video containers from their magic bytes.
+fn sniff_video(data: &[u8]) -> Option<&'static str> {
+    if data.len() >= 12 && &data[4..8] == b"ftyp" {
+        Some(match &data[8..11] {
+            b"qt " => "video/quicktime",
+            b"3gp" => "video/3gpp",
+            _ => "video/mp4",
+        })
+    } else if data.starts_with(&[0x1a, 0x45, 0xdf, 0xa3]) {
+        Some("video/webm")
+    } else if data.len() >= 12 && &data[..4] == b"RIFF" && &data[8..12] == b"AVI " {
+        Some("video/avi")
+    } else if data.starts_with(b"FLV") {
+        Some("video/x-flv")
+    } else if data.starts_with(&[0x00, 0x00, 0x01, 0xba]) {
+        Some("video/mpeg")
+    } else {
+        None
+    }
+}
+
+async fn fetch(url: &str) -> Result<Vec<u8>, RustedChainError> {
+    let response = reqwest::get(url).await?;
+    let status = response.status();
+    if !status.is_success() {
+        return Err(RustedChainError::InvalidRequest(format!(
+            "fetching {} failed with status {}",
+            url, status
+        )));
+    }
+    Ok(response.bytes().await?.to_vec())
+}
+
+/// Read a file path, `bytes`, an `http(s)://` URL or a `data:` URL.
+fn read_source(py: Python, source: &Bound<'_, PyAny>) -> PyResult<Vec<u8>> {
+    if let Ok(bytes) = source.cast::<PyBytes>() {
+        return Ok(bytes.as_bytes().to_vec());
+    }
+    let Ok(text) = source.extract::<String>() else {
+        return Ok(std::fs::read(source.extract::<PathBuf>()?)?);
+    };
+    if let Some(rest) = text.strip_prefix("data:") {
+        let (_, encoded) = rest
+            .split_once(";base64,")
+            .ok_or_else(|| PyValueError::new_err("data URLs must be base64-encoded"))?;
+        STANDARD
+            .decode(encoded)
+            .map_err(|e| PyValueError::new_err(format!("invalid data URL: {}", e)))
+    } else if text.starts_with("http://") || text.starts_with("https://") {
+        Ok(py.detach(|| RUNTIME.block_on(fetch(&text)))?)
+    } else {
+        Ok(std::fs::read(&text)?)
+    }
+}
+
+impl Image {
+    /// Load an image from `bytes`, a file path, an `http(s)://` URL or a `data:` URL.
+    pub(crate) fn from_py(py: Python, image: &Bound<'_, PyAny>) -> PyResult<Self> {
+        let data = read_source(py, image)?;
+        let mime_type = sniff_image(&data).ok_or_else(|| {
+            PyValueError::new_err("unsupported image format; expected PNG, JPEG, GIF or WebP")
+        })?;
+        Ok(Image {
+            mime_type: mime_type.to_string(),
+            data,
+        })
+    }
+
+    pub(crate) fn base64(&self) -> String {
+        STANDARD.encode(&self.data)
+    }
+
+    pub(crate) fn data_url(&self) -> String {
+        format!("data:{};base64,{}", self.mime_type, self.base64())
+    }
+
+    /// Size once base64-encoded, which is what the providers measure.
+    pub(crate) fn encoded_len(&self) -> usize {
+        self.data.len().div_ceil(3) * 4
+    }
+
+    /// The image itself if it fits `limits`, otherwise a downscaled copy (with the
+    /// `images` feature) or an error explaining the limit.
+    pub(crate) fn fit(&self, limits: &ImageLimits) -> Result<Cow<'_, Image>, RustedChainError> {
+        #[cfg(feature = "images")]
+        {
+            downscale::fit(self, limits)
+        }
+        #[cfg(not(feature = "images"))]
+        {
+            if self.encoded_len() <= limits.max_bytes {
+                return Ok(Cow::Borrowed(self));
+            }
+            Err(RustedChainError::InvalidRequest(format!(
+                "image is {:.1} MB encoded but {} accepts at most {:.1} MB; build with the \
+                 `images` feature to downscale large images automatically",
+                megabytes(self.encoded_len()),
+                limits.provider,
+                megabytes(limits.max_bytes),
+            )))
+        }
+    }
+}
+
+impl Attachment {
+    /// Load an image or video, telling them apart by content. `Video` objects carry
+    /// sampling options.
+    pub(crate) fn from_py(py: Python, file: &Bound<'_, PyAny>) -> PyResult<Self> {
+        if let Ok(video) = file.cast::<VideoFile>() {
+            let video = video.borrow();
+            let data = read_source(py, video.source.bind(py))?;
+            let mime_type = sniff_video(&data).ok_or_else(|| {
+                PyValueError::new_err(
+                    "unsupported video format; expected MP4, MOV, WebM, AVI, FLV, MPEG or 3GP",
+                )
+            })?;
+            return Ok(Attachment::Video(Video {
+                mime_type: mime_type.to_string(),
+                data,
+                fps: video.fps,
+                start_offset: video.start_offset,
+                end_offset: video.end_offset,
+            }));
+        }
+        let data = read_source(py, file)?;
+        if let Some(mime_type) = sniff_image(&data) {
+            Ok(Attachment::Image(Image {
+                mime_type: mime_type.to_string(),
+                data,
+            }))
+        } else if let Some(mime_type) = sniff_video(&data) {
+            Ok(Attachment::Video(Video {
+                mime_type: mime_type.to_string(),
+                data,
+                fps: None,
+                start_offset: None,
+                end_offset: None,
+            }))
+        } else {
+            Err(PyValueError::new_err(
+                "unsupported file; expected an image (PNG, JPEG, GIF, WebP) or a video",
+            ))
+        }
+    }
+
+    pub(crate) fn encoded_len(&self) -> usize {
+        match self {
+            Attachment::Image(image) => image.encoded_len(),
+            Attachment::Video(video) => video.data.len().div_ceil(3) * 4,
+        }
+    }
+}
+
+fn megabytes(bytes: usize) -> f64 {
+    bytes as f64 / (1024.0 * 1024.0)
+}
+
+#[cfg(feature = "images")]
+mod downscale {
+    use super::{Image, ImageLimits};
+    use crate::error::RustedChainError;
+    use image::codecs::jpeg::JpegEncoder;
+    use image::imageops::FilterType;
+    use image::{DynamicImage, ImageFormat, ImageReader};
+    use std::borrow::Cow;
+    use std::io::Cursor;
+
+    /// Each retry shrinks both sides by this factor.
+    const SHRINK: f64 = 0.75;
+    /// Give up rather than send an unrecognisable thumbnail.
+    const MIN_SIDE: u32 = 64;
+    const JPEG_QUALITY: u8 = 85;
+
+    fn invalid(err: impl std::fmt::Display) -> RustedChainError {
+        RustedChainError::InvalidRequest(format!("could not downscale image: {}", err))
+    }
+
+    fn encode(image: &DynamicImage) -> Result<Image, RustedChainError> {
+        let mut data = Vec::new();
+        // Keep transparency as PNG; everything else compresses far better as JPEG.
+        let mime_type = if image.color().has_alpha() {
+            image
+                .write_to(&mut Cursor::new(&mut data), ImageFormat::Png)
+                .map_err(invalid)?;
+            "image/png"
+        } else {
+            let encoder = JpegEncoder::new_with_quality(&mut data, JPEG_QUALITY);
+            image
+                .to_rgb8()
+                .write_with_encoder(encoder)
+                .map_err(invalid)?;
+            "image/jpeg"
+        };
+        Ok(Image {
+            mime_type: mime_type.to_string(),
+            data,
+        })
+    }
+
+    pub(super) fn fit<'a>(
+        original: &'a Image,
+        limits: &ImageLimits,
+    ) -> Result<Cow<'a, Image>, RustedChainError> {
+        let reader = ImageReader::new(Cursor::new(&original.data))
+            .with_guessed_format()
+            .map_err(invalid)?;
+        let (width, height) = reader.into_dimensions().map_err(invalid)?;
+        let longest = width.max(height);
+        let max_side = limits.max_side.unwrap_or(u32::MAX);
+        if original.encoded_len() <= limits.max_bytes && longest <= max_side {
+            return Ok(Cow::Borrowed(original));
+        }
+
+        let decoded = image::load_from_memory(&original.data).map_err(invalid)?;
+        let mut side = longest.min(max_side);
+        loop {
+            let resized = if side < longest {
+                decoded.resize(side, side, FilterType::Lanczos3)
+            } else {
+                decoded.clone()
+            };
+            let image = encode(&resized)?;
+            if image.encoded_len() <= limits.max_bytes {
+                return Ok(Cow::Owned(image));
+            }
+            side = (side as f64 * SHRINK) as u32;
+            if side < MIN_SIDE {
+                return Err(RustedChainError::InvalidRequest(format!(
+                    "image could not be shrunk to {}'s {:.1} MB limit",
+                    limits.provider,
+                    super::megabytes(limits.max_bytes),
+                )));
+            }
+        }
+    }
+}
@@ -0,0 +1,169 @@
+//! What a session sends of its history: long histories lose turns and tool results
+//! that repeat others, judged by embedding similarity.
+
+use crate::session::Entry;
+use crate::vector_store::{cosine, Embedder};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use serde_json::{json, Value};
+use std::collections::{BTreeSet, HashMap};
+
+/// Texts shorter than this only count as repeats when identical; embeddings of short
+/// texts ("ok", "no results") are too alike to tell apart.
+const MIN_EMBED_CHARS: usize = 40;
+
+/// How a `Session` compresses the history it sends. Once the history has
+/// `min_messages` entries, a turn is left out when a later turn has a similar message
+/// and a similar answer, and a tool result similar to an earlier one from the same
+/// tool is replaced by a pointer to it. The session's own history keeps everything.
+#[pyclass(frozen)]
+pub struct MemoryPolicy {
+    embedder: Embedder,
+    /// Cosine similarity from which two texts count as repeats.
+    #[pyo3(get)]
+    similarity: f32,
+    #[pyo3(get)]
+    min_messages: usize,
+}
+
+#[pymethods]
+impl MemoryPolicy {
+    /// `embeddings` is an `Embeddings` model or a callable mapping a list of strings to
+    /// a list of vectors.
+    #[new]
+    #[pyo3(signature = (embeddings, similarity=0.95, min_messages=10))]
+    fn new(embeddings: &Bound<'_, PyAny>, similarity: f32, min_messages: usize) -> PyResult<Self> {
+        if !(0.0..=1.0).contains(&similarity) {
+            return Err(PyValueError::new_err(format!(
+                "similarity must be between 0 and 1, got {}",
+                similarity
+            )));
+        }
+        Ok(MemoryPolicy {
+            embedder: Embedder::from_py(embeddings)?,
+            similarity,
+            min_messages,
+        })
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "MemoryPolicy(similarity={}, min_messages={})",
+            self.similarity, self.min_messages
+        )
+    }
+}
+
+impl MemoryPolicy {
+    /// The entries of `history` to send. `vectors` caches embeddings across calls.
+    pub(crate) fn compress(
+        &self,
+        py: Python,
+        history: &[Entry],
+        vectors: &mut HashMap<String, Vec<f32>>,
+    ) -> PyResult<Vec<Entry>> {
+        if history.len() < self.min_messages {
+            return Ok(history.to_vec());
+        }
+        let missing: Vec<String> = history
+            .iter()
+            .filter_map(compared_text)
+            .filter(|text| text.chars().count() >= MIN_EMBED_CHARS && !vectors.contains_key(text))
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        if !missing.is_empty() {
+            let embedded = self.embedder.embed(py, missing.clone())?;
+            vectors.extend(missing.into_iter().zip(embedded));
+        }
+        let similar = |a: &str, b: &str| {
+            a == b
+                || match (vectors.get(a), vectors.get(b)) {
+                    (Some(a), Some(b)) => cosine(a, b) >= self.similarity,
+                    _ => false,
+                }
+        };
+
+        let turns = turns(history);
+        let mut sent = Vec::with_capacity(history.len());
+        for (i, turn) in turns.iter().enumerate() {
+            let repeated = gist(turn).is_some_and(|(question, answer)| {
+                turns[i + 1..].iter().filter_map(|later| gist(later)).any(
+                    |(later_question, later_answer)| {
+                        similar(question, later_question) && similar(answer, later_answer)
+                    },
+                )
+            });
+            if !repeated {
+                sent.extend_from_slice(turn);
+            }
+        }
+
+        let mut results: Vec<(String, Option<String>, String)> = Vec::new();
+        for entry in &mut sent {
+            let Entry::ToolResult { call, result } = entry else {
+                continue;
+            };
+            let text = result_text(result);
+            let earlier = results
+                .iter()
+                .find(|(name, _, earlier)| *name == call.name && similar(earlier, &text));
+            match earlier {
+                Some((_, id, _)) => {
+                    *result = json!(format!(
+                        "Same result as the earlier {} call {}.",
+                        call.name,
+                        id.as_deref().unwrap_or("")
+                    ))
+                }
+                None => results.push((call.name.clone(), call.id.clone(), text)),
+            }
+        }
+        Ok(sent)
+    }
+}
+
+/// `history` split before each user message.
+fn turns(history: &[Entry]) -> Vec<&[Entry]> {
+    let mut turns = Vec::new();
+    let mut start = 0;
+    for (i, entry) in history.iter().enumerate() {
+        if matches!(entry, Entry::User(_)) && i > start {
+            turns.push(&history[start..i]);
+            start = i;
+        }
+    }
+    if start < history.len() {
+        turns.push(&history[start..]);
+    }
+    turns
+}
+
+/// A turn's user message and final answer.
+fn gist(turn: &[Entry]) -> Option<(&str, &str)> {
+    let question = turn.iter().find_map(|entry| match entry {
+        Entry::User(text) => Some(text.as_str()),
+        _ => None,
+    })?;
+    let answer = turn.iter().rev().find_map(|entry| match entry {
+        Entry::Assistant(text) => Some(text.as_str()),
+        _ => None,
+    })?;
+    Some((question, answer))
+}
+
+/// The text an entry is compared by, if it takes part in deduplication.
+fn compared_text(entry: &Entry) -> Option<String> {
+    match entry {
+        Entry::User(text) | Entry::Assistant(text) => Some(text.clone()),
+        Entry::ToolResult { result, .. } => Some(result_text(result)),
+        Entry::ToolCall(_) => None,
+    }
+}
+
+fn result_text(result: &Value) -> String {
+    match result {
+        Value::String(text) => text.clone(),
+        other => other.to_string(),
+    }
+}
@@ -0,0 +1,427 @@
+//! Message transforms: rewrites applied to the messages of every request as it is
+//! serialized, in the provider's own format, to work around what a provider or an
+//! OpenAI-compatible server rejects. Steps compose with `|` into one transform attached
+//! to a model with `message_transform=`.
+
+use crate::error::RustedChainError;
+use once_cell::sync::Lazy;
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyList;
+use regex::Regex;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::sync::Arc;
+
+/// A Markdown image, `![alt](url)` or `![alt](url "title")`.
+static MARKDOWN_IMAGE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r#"!\[([^\]]*)\]\((\S+?)(?:\s+"[^"]*")?\)"#).unwrap());
+
+#[derive(Clone)]
+enum Step {
+    StripFields(Vec<String>),
+    MarkdownImages,
+    SplitLong(usize),
+    /// A Python function taking the messages and the provider name.
+    Custom(Arc<Py<PyAny>>),
+    /// Steps applied only to requests for these providers.
+    Only(Vec<String>, Vec<Step>),
+}
+
+impl Step {
+    fn apply(&self, provider: &str, messages: Vec<Value>) -> Result<Vec<Value>, RustedChainError> {
+        Ok(match self {
+            Self::StripFields(fields) => messages
+                .into_iter()
+                .map(|message| strip_fields(message, fields))
+                .collect(),
+            Self::MarkdownImages => messages
+                .into_iter()
+                .map(|message| markdown_images(provider, message))
+                .collect(),
+            Self::SplitLong(max_chars) => messages
+                .into_iter()
+                .flat_map(|message| split_long(provider, message, *max_chars))
+                .collect(),
+            Self::Custom(function) => call(function, provider, messages)?,
+            Self::Only(providers, steps) if providers.iter().any(|p| p == provider) => {
+                apply_steps(steps, provider, messages)?
+            }
+            Self::Only(..) => messages,
+        })
+    }
+
+    fn name(&self, py: Python) -> String {
+        match self {
+            Self::StripFields(fields) => format!("strip_fields({})", quoted(fields)),
+            Self::MarkdownImages => "markdown_images()".to_string(),
+            Self::SplitLong(max_chars) => format!("split_long({})", max_chars),
+            Self::Custom(function) => function
+                .bind(py)
+                .getattr("__name__")
+                .and_then(|name| name.extract::<String>())
+                .unwrap_or_else(|_| "<function>".to_string()),
+            Self::Only(providers, steps) => {
+                let names: Vec<String> = steps.iter().map(|step| step.name(py)).collect();
+                match names.as_slice() {
+                    [name] => format!("{}.only({})", name, quoted(providers)),
+                    _ => format!("({}).only({})", names.join(" | "), quoted(providers)),
+                }
+            }
+        }
+    }
+}
+
+fn quoted(names: &[String]) -> String {
+    let names: Vec<String> = names.iter().map(|name| format!("'{}'", name)).collect();
+    names.join(", ")
+}
+
+fn apply_steps(
+    steps: &[Step],
+    provider: &str,
+    messages: Vec<Value>,
+) -> Result<Vec<Value>, RustedChainError> {
+    steps
+        .iter()
+        .try_fold(messages, |messages, step| step.apply(provider, messages))
+}
+
+/// A sequence of message rewrites, applied in order to each request's messages.
+#[pyclass(frozen)]
+#[derive(Clone, Default)]
+pub struct MessageTransform {
+    steps: Vec<Step>,
+}
+
+impl MessageTransform {
+    /// A `message_transform` value: a `MessageTransform`, a function, or a list of
+    /// either, composed in order.
+    pub(crate) fn from_py(value: &Bound<'_, PyAny>) -> PyResult<Self> {
+        if let Ok(transform) = value.cast::<MessageTransform>() {
+            return Ok(transform.get().clone());
+        }
+        if let Ok(list) = value.cast::<PyList>() {
+            let mut steps = Vec::new();
+            for item in list.iter() {
+                steps.extend(Self::from_py(&item)?.steps);
+            }
+            return Ok(MessageTransform { steps });
+        }
+        if value.is_callable() {
+            return Ok(MessageTransform {
+                steps: vec![Step::Custom(Arc::new(value.clone().unbind()))],
+            });
+        }
+        Err(PyTypeError::new_err(
+            "message_transform must be a MessageTransform, a function or a list of them",
+        ))
+    }
+
+    /// `body` as JSON, with the messages under `key` transformed as for `provider`.
+    pub(crate) fn apply_to(
+        &self,
+        provider: &str,
+        body: &impl Serialize,
+        key: &str,
+    ) -> Result<Value, RustedChainError> {
+        let mut body =
+            serde_json::to_value(body).map_err(|e| RustedChainError::ParseError(e.to_string()))?;
+        if let Some(Value::Array(messages)) = body.get_mut(key) {
+            *messages = self.apply(provider, std::mem::take(messages))?;
+        }
+        Ok(body)
+    }
+
+    pub(crate) fn apply(
+        &self,
+        provider: &str,
+        messages: Vec<Value>,
+    ) -> Result<Vec<Value>, RustedChainError> {
+        apply_steps(&self.steps, provider, messages)
+    }
+}
+
+#[pymethods]
+impl MessageTransform {
+    /// Remove `fields` from every message and every content part, e.g. `name` for a
+    /// server that rejects it.
+    #[staticmethod]
+    #[pyo3(signature = (*fields))]
+    fn strip_fields(fields: Vec<String>) -> PyResult<Self> {
+        if fields.is_empty() {
+            return Err(PyValueError::new_err(
+                "strip_fields needs at least one field",
+            ));
+        }
+        Ok(MessageTransform {
+            steps: vec![Step::StripFields(fields)],
+        })
+    }
+
+    /// Turn Markdown images (`![alt](url)`) in user messages into image parts, for
+    /// OpenAI-compatible providers and Claude. Gemini cannot fetch image URLs, so there
+    /// they become plain links, `alt (url)`.
+    #[staticmethod]
+    fn markdown_images() -> Self {
+        MessageTransform {
+            steps: vec![Step::MarkdownImages],
+        }
+    }
+
+    /// Split text longer than `max_chars` characters at paragraph, line or word breaks:
+    /// into several text parts for Claude and Gemini, and into consecutive messages of
+    /// the same role for OpenAI-compatible providers. Tool calls and results are left
+    /// whole.
+    #[staticmethod]
+    fn split_long(max_chars: usize) -> PyResult<Self> {
+        if max_chars == 0 {
+            return Err(PyValueError::new_err("max_chars must be at least 1"));
+        }
+        Ok(MessageTransform {
+            steps: vec![Step::SplitLong(max_chars)],
+        })
+    }
+
+    /// This transform, applied only to requests for `providers` (see `Providers`).
+    #[pyo3(signature = (*providers))]
+    fn only(&self, providers: Vec<String>) -> Self {
+        MessageTransform {
+            steps: vec![Step::Only(providers, self.steps.clone())],
+        }
+    }
+
+    /// Apply this transform, then `other`: a `MessageTransform` or a function.
+    fn __or__(&self, other: &Bound<'_, PyAny>) -> PyResult<Self> {
+        let mut steps = self.steps.clone();
+        steps.extend(Self::from_py(other)?.steps);
+        Ok(MessageTransform { steps })
+    }
+
+    /// The transformed `messages`, given as the provider's API takes them.
+    #[pyo3(signature = (messages, provider="openai"))]
+    fn __call__(
+        &self,
+        py: Python,
+        messages: Bound<'_, PyAny>,
+        provider: &str,
+    ) -> PyResult<Py<PyAny>> {
+        let messages: Vec<Value> = pythonize::depythonize(&messages)?;
+        let messages = py.detach(|| self.apply(provider, messages))?;
+        Ok(pythonize::pythonize(py, &messages)?.unbind())
+    }
+
+    fn __repr__(&self, py: Python) -> String {
+        let steps: Vec<String> = self.steps.iter().map(|step| step.name(py)).collect();
+        format!("MessageTransform({})", steps.join(" | "))
+    }
+}
+
+/// Run a Python step: it gets the messages and the provider name, and returns the new
+/// messages, or `None` for the ones it changed in place.
+fn call(
+    function: &Py<PyAny>,
+    provider: &str,
+    messages: Vec<Value>,
+) -> Result<Vec<Value>, RustedChainError> {
+    Python::attach(|py| -> PyResult<Vec<Value>> {
+        let list = pythonize::pythonize(py, &messages)?;
+        let returned = function.bind(py).call1((&list, provider))?;
+        let result = if returned.is_none() { list } else { returned };
+        Ok(pythonize::depythonize(&result)?)
+    })
+    .map_err(|err| RustedChainError::InvalidRequest(format!("message_transform: {}", err)))
+}
+
+fn strip_fields(mut message: Value, fields: &[String]) -> Value {
+    let strip = |object: &mut Map<String, Value>| {
+        for field in fields {
+            object.remove(field);
+        }
+    };
+    if let Some(object) = message.as_object_mut() {
+        strip(object);
+        for key in ["content", "parts"] {
+            if let Some(Value::Array(parts)) = object.get_mut(key) {
+                parts
+                    .iter_mut()
+                    .filter_map(Value::as_object_mut)
+                    .for_each(strip);
+            }
+        }
+    }
+    message
+}
+
+/// Part of a text cut around its Markdown images: text, or an image's URL.
+enum Piece<'a> {
+    Text(&'a str),
+    Image(&'a str),
+}
+
+fn pieces(text: &str) -> Vec<Piece<'_>> {
+    let mut pieces = Vec::new();
+    let mut last = 0;
+    for image in MARKDOWN_IMAGE.captures_iter(text) {
+        let whole = image.get(0).unwrap();
+        if whole.start() > last {
+            pieces.push(Piece::Text(&text[last..whole.start()]));
+        }
+        pieces.push(Piece::Image(image.get(2).unwrap().as_str()));
+        last = whole.end();
+    }
+    if last < text.len() {
+        pieces.push(Piece::Text(&text[last..]));
+    }
+    pieces
+}
+
+fn markdown_images(provider: &str, mut message: Value) -> Value {
+    if message["role"] != "user" {
+        return message;
+    }
+    match provider {
+        "gemini" => {
+            if let Some(Value::Array(parts)) = message.get_mut("parts") {
+                for part in parts.iter_mut() {
+                    if let Some(Value::String(text)) = part.get_mut("text") {
+                        *text = MARKDOWN_IMAGE.replace_all(text, "$1 ($2)").into_owned();
+                    }
+                }
+            }
+        }
+        "anthropic" => {
+            if let Some(content) = message.get_mut("content") {
+                *content = content_parts(content.take(), |piece| match piece {
+                    Piece::Text(text) => json!({ "type": "text", "text": text }),
+                    Piece::Image(url) => {
+                        json!({ "type": "image", "source": { "type": "url", "url": url } })
+                    }
+                });
+            }
+        }
+        _ => {
+            if let Some(content) = message.get_mut("content") {
+                *content = content_parts(content.take(), |piece| match piece {
+                    Piece::Text(text) => json!({ "type": "text", "text": text }),
+                    Piece::Image(url) => {
+                        json!({ "type": "image_url", "image_url": { "url": url } })
+                    }
+                });
+            }
+        }
+    }
+    message
+}
+
+/// `content`, a string or a list of parts, with each text part holding an image split
+/// into the parts `part` makes. Content without images is returned as it was.
+fn content_parts(content: Value, part: impl Fn(Piece) -> Value) -> Value {
+    let parts = match content {
+        Value::String(text) if MARKDOWN_IMAGE.is_match(&text) => {
+            vec![json!({ "type": "text", "text": text })]
+        }
+        Value::Array(parts) => parts,
+        content => return content,
+    };
+    let mut split = Vec::with_capacity(parts.len());
+    for original in parts {
+        match original.get("text").and_then(Value::as_str) {
+            Some(text) if original["type"] == "text" && MARKDOWN_IMAGE.is_match(text) => {
+                split.extend(pieces(text).into_iter().map(&part));
+            }
+            _ => split.push(original),
+        }
+    }
+    Value::Array(split)
+}
+
+/// `text` in pieces of at most `max_chars` characters, cut after the last paragraph,
+/// line or word break in each piece's second half where there is one.
+fn split_text(text: &str, max_chars: usize) -> Vec<String> {
+    let mut pieces = Vec::new();
+    let mut rest = text;
+    while rest.chars().count() > max_chars {
+        let limit = rest
+            .char_indices()
+            .nth(max_chars)
+            .map_or(rest.len(), |(index, _)| index);
+        let window = &rest[..limit];
+        let half = window
+            .char_indices()
+            .nth(max_chars / 2)
+            .map_or(0, |(index, _)| index);
+        let cut = ["\n\n", "\n", " "]
+            .iter()
+            .find_map(|separator| {
+                window
+                    .rfind(separator)
+                    .map(|at| at + separator.len())
+                    .filter(|&end| end > half)
+            })
+            .unwrap_or(limit);
+        pieces.push(rest[..cut].to_string());
+        rest = &rest[cut..];
+    }
+    if !rest.is_empty() || pieces.is_empty() {
+        pieces.push(rest.to_string());
+    }
+    pieces
+}
+
+/// Text parts holding more than `max_chars` characters split into several.
+fn split_parts(parts: &mut Vec<Value>, max_chars: usize) {
+    let mut split = Vec::with_capacity(parts.len());
+    for part in parts.drain(..) {
+        match part.get("text").and_then(Value::as_str) {
+            Some(text) if text.chars().count() > max_chars => {
+                split.extend(split_text(text, max_chars).into_iter().map(|piece| {
+                    let mut part = part.clone();
+                    part["text"] = json!(piece);
+                    part
+                }));
+            }
+            _ => split.push(part),
+        }
+    }
+    *parts = split;
+}
+
+fn split_long(provider: &str, mut message: Value, max_chars: usize) -> Vec<Value> {
+    match provider {
+        "gemini" | "anthropic" => {
+            let key = if provider == "gemini" {
+                "parts"
+            } else {
+                "content"
+            };
+            if let Some(Value::Array(parts)) = message.get_mut(key) {
+                split_parts(parts, max_chars);
+            }
+            vec![message]
+        }
+        _ => {
+            let whole = message
+                .get("tool_calls")
+                .is_some_and(|calls| !calls.is_null())
+                || message.get("tool_call_id").is_some_and(|id| !id.is_null());
+            match message.get_mut("content") {
+                Some(Value::String(text)) if !whole && text.chars().count() > max_chars => {
+                    split_text(text, max_chars)
+                        .into_iter()
+                        .map(|piece| {
+                            let mut message = message.clone();
+                            message["content"] = json!(piece);
+                            message
+                        })
+                        .collect()
+                }
+                Some(Value::Array(parts)) if !whole => {
+                    split_parts(parts, max_chars);
+                    vec![message]
+                }
+                _ => vec![message],
+            }
+        }
+    }
+}
@@ -0,0 +1,418 @@
+//! Process-wide counters and histograms for provider requests and tool executions,
+//! readable as a Python snapshot or in the Prometheus text exposition format.
+
+use crate::agent::Usage;
+use crate::RUNTIME;
+use once_cell::sync::Lazy;
+use pyo3::exceptions::PyOSError;
+use pyo3::prelude::*;
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// Upper bounds (seconds) of the latency histogram buckets.
+const BUCKETS: &[f64] = &[0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0];
+
+/// Upper bounds of the output-rate (tokens per second) histogram buckets.
+const RATE_BUCKETS: &[f64] = &[5.0, 10.0, 25.0, 50.0, 100.0, 200.0, 400.0, 800.0];
+
+/// Upper bounds of the tool result size (bytes) histogram buckets.
+const BYTE_BUCKETS: &[f64] = &[
+    64.0, 256.0, 1024.0, 4096.0, 16384.0, 65536.0, 262144.0, 1048576.0,
+];
+
+#[derive(Clone)]
+struct Histogram {
+    bounds: &'static [f64],
+    /// Non-cumulative count per bucket; observations above the last bound only count
+    /// towards `count`.
+    buckets: Vec<u64>,
+    sum: f64,
+    count: u64,
+}
+
+impl Default for Histogram {
+    fn default() -> Self {
+        Self::new(BUCKETS)
+    }
+}
+
+impl Histogram {
+    fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            buckets: vec![0; bounds.len()],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    fn observe(&mut self, value: f64) {
+        if let Some(i) = self.bounds.iter().position(|bound| value <= *bound) {
+            self.buckets[i] += 1;
+        }
+        self.sum += value;
+        self.count += 1;
+    }
+
+    fn cumulative(&self) -> impl Iterator<Item = (f64, u64)> + '_ {
+        self.bounds
+            .iter()
+            .zip(&self.buckets)
+            .scan(0, |total, (bound, count)| {
+                *total += count;
+                Some((*bound, *total))
+            })
+    }
+
+    fn to_json(&self) -> Value {
+        let buckets: serde_json::Map<String, Value> = self
+            .cumulative()
+            .map(|(bound, total)| (bound.to_string(), json!(total)))
+            .collect();
+        json!({ "count": self.count, "sum": self.sum, "buckets": buckets })
+    }
+}
+
+/// Labels are `(provider, model)` for requests and tokens, the tool name for tools.
+#[derive(Default)]
+struct Registry {
+    requests: BTreeMap<(String, String, &'static str), u64>,
+    request_duration: BTreeMap<(String, String), Histogram>,
+    tokens: BTreeMap<(String, String, &'static str), u64>,
+    /// Streamed requests only: time until the first text arrived, and the output rate
+    /// after it.
+    time_to_first_token: BTreeMap<(String, String), Histogram>,
+    tokens_per_second: BTreeMap<(String, String), Histogram>,
+    tool_executions: BTreeMap<(String, &'static str), u64>,
+    tool_duration: BTreeMap<String, Histogram>,
+    tool_result_bytes: BTreeMap<String, Histogram>,
+}
+
+static REGISTRY: Lazy<Mutex<Registry>> = Lazy::new(|| Mutex::new(Registry::default()));
+
+fn registry() -> std::sync::MutexGuard<'static, Registry> {
+    REGISTRY.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Record one provider request. `status` is `"ok"` or an error kind.
+pub(crate) fn record_request(
+    provider: &str,
+    model: &str,
+    status: &'static str,
+    elapsed: Duration,
+    usage: Usage,
+) {
+    let mut registry = registry();
+    let key = (provider.to_string(), model.to_string());
+    *registry
+        .requests
+        .entry((key.0.clone(), key.1.clone(), status))
+        .or_default() += 1;
+    registry
+        .request_duration
+        .entry(key.clone())
+        .or_default()
+        .observe(elapsed.as_secs_f64());
+    if status == "ok" {
+        *registry
+            .tokens
+            .entry((key.0.clone(), key.1.clone(), "input"))
+            .or_default() += usage.input_tokens;
+        *registry.tokens.entry((key.0, key.1, "output")).or_default() += usage.output_tokens;
+    }
+}
+
+/// Record the timing of one streamed request: time to the first text chunk and, when
+/// known, output tokens per second after it.
+pub(crate) fn record_stream(
+    provider: &str,
+    model: &str,
+    time_to_first_token: Duration,
+    tokens_per_second: Option<f64>,
+) {
+    let mut registry = registry();
+    let key = (provider.to_string(), model.to_string());
+    registry
+        .time_to_first_token
+        .entry(key.clone())
+        .or_default()
+        .observe(time_to_first_token.as_secs_f64());
+    if let Some(rate) = tokens_per_second {
+        registry
+            .tokens_per_second
+            .entry(key)
+            .or_insert_with(|| Histogram::new(RATE_BUCKETS))
+            .observe(rate);
+    }
+}
+
+/// Record one tool execution, with the size of its JSON result; `result_bytes` is
+/// `None` when the tool raised.
+pub(crate) fn record_tool(name: &str, elapsed: Duration, result_bytes: Option<usize>) {
+    let status = if result_bytes.is_some() {
+        "ok"
+    } else {
+        "error"
+    };
+    let mut registry = registry();
+    *registry
+        .tool_executions
+        .entry((name.to_string(), status))
+        .or_default() += 1;
+    registry
+        .tool_duration
+        .entry(name.to_string())
+        .or_default()
+        .observe(elapsed.as_secs_f64());
+    if let Some(bytes) = result_bytes {
+        registry
+            .tool_result_bytes
+            .entry(name.to_string())
+            .or_insert_with(|| Histogram::new(BYTE_BUCKETS))
+            .observe(bytes as f64);
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn write_histogram(out: &mut String, name: &str, labels: &str, histogram: &Histogram) {
+    for (bound, total) in histogram.cumulative() {
+        let _ = writeln!(out, "{name}_bucket{{{labels},le=\"{bound}\"}} {total}");
+    }
+    let _ = writeln!(
+        out,
+        "{name}_bucket{{{labels},le=\"+Inf\"}} {}",
+        histogram.count
+    );
+    let _ = writeln!(out, "{name}_sum{{{labels}}} {}", histogram.sum);
+    let _ = writeln!(out, "{name}_count{{{labels}}} {}", histogram.count);
+}
+
+fn render() -> String {
+    let registry = registry();
+    let mut out = String::new();
+
+    out.push_str("# HELP rusted_chain_requests_total Provider requests by outcome.\n");
+    out.push_str("# TYPE rusted_chain_requests_total counter\n");
+    for ((provider, model, status), count) in &registry.requests {
+        let _ = writeln!(
+            out,
+            "rusted_chain_requests_total{{provider=\"{}\",model=\"{}\",status=\"{}\"}} {}",
+            escape(provider),
+            escape(model),
+            status,
+            count
+        );
+    }
+
+    out.push_str("# HELP rusted_chain_request_duration_seconds Provider request latency.\n");
+    out.push_str("# TYPE rusted_chain_request_duration_seconds histogram\n");
+    for ((provider, model), histogram) in &registry.request_duration {
+        let labels = format!(
+            "provider=\"{}\",model=\"{}\"",
+            escape(provider),
+            escape(model)
+        );
+        write_histogram(
+            &mut out,
+            "rusted_chain_request_duration_seconds",
+            &labels,
+            histogram,
+        );
+    }
+
+    out.push_str("# HELP rusted_chain_tokens_total Tokens billed by the provider.\n");
+    out.push_str("# TYPE rusted_chain_tokens_total counter\n");
+    for ((provider, model, kind), count) in &registry.tokens {
+        let _ = writeln!(
+            out,
+            "rusted_chain_tokens_total{{provider=\"{}\",model=\"{}\",type=\"{}\"}} {}",
+            escape(provider),
+            escape(model),
+            kind,
+            count
+        );
+    }
+
+    out.push_str(
+        "# HELP rusted_chain_time_to_first_token_seconds Time until a streamed request's first text.\n",
+    );
+    out.push_str("# TYPE rusted_chain_time_to_first_token_seconds histogram\n");
+    for ((provider, model), histogram) in &registry.time_to_first_token {
+        let labels = format!(
+            "provider=\"{}\",model=\"{}\"",
+            escape(provider),
+            escape(model)
+        );
+        write_histogram(
+            &mut out,
+            "rusted_chain_time_to_first_token_seconds",
+            &labels,
+            histogram,
+        );
+    }
+
+    out.push_str(
+        "# HELP rusted_chain_output_tokens_per_second Output rate of streamed requests after the first token.\n",
+    );
+    out.push_str("# TYPE rusted_chain_output_tokens_per_second histogram\n");
+    for ((provider, model), histogram) in &registry.tokens_per_second {
+        let labels = format!(
+            "provider=\"{}\",model=\"{}\"",
+            escape(provider),
+            escape(model)
+        );
+        write_histogram(
+            &mut out,
+            "rusted_chain_output_tokens_per_second",
+            &labels,
+            histogram,
+        );
+    }
+
+    out.push_str("# HELP rusted_chain_tool_executions_total Tool executions by outcome.\n");
+    out.push_str("# TYPE rusted_chain_tool_executions_total counter\n");
+    for ((tool, status), count) in &registry.tool_executions {
+        let _ = writeln!(
+            out,
+            "rusted_chain_tool_executions_total{{tool=\"{}\",status=\"{}\"}} {}",
+            escape(tool),
+            status,
+            count
+        );
+    }
+
+    out.push_str("# HELP rusted_chain_tool_duration_seconds Tool execution latency.\n");
+    out.push_str("# TYPE rusted_chain_tool_duration_seconds histogram\n");
+    for (tool, histogram) in &registry.tool_duration {
+        let labels = format!("tool=\"{}\"", escape(tool));
+        write_histogram(
+            &mut out,
+            "rusted_chain_tool_duration_seconds",
+            &labels,
+            histogram,
+        );
+    }
+
+    out.push_str(
+        "# HELP rusted_chain_tool_result_bytes Size of successful tool results as JSON.\n",
+    );
+    out.push_str("# TYPE rusted_chain_tool_result_bytes histogram\n");
+    for (tool, histogram) in &registry.tool_result_bytes {
+        let labels = format!("tool=\"{}\"", escape(tool));
+        write_histogram(
+            &mut out,
+            "rusted_chain_tool_result_bytes",
+            &labels,
+            histogram,
+        );
+    }
+
+    out
+}
+
+fn snapshot() -> Value {
+    let registry = registry();
+    json!({
+        "requests_total": registry.requests.iter().map(|((provider, model, status), count)| {
+            json!({ "provider": provider, "model": model, "status": status, "value": count })
+        }).collect::<Vec<_>>(),
+        "request_duration_seconds": registry.request_duration.iter().map(|((provider, model), h)| {
+            let mut sample = h.to_json();
+            sample["provider"] = json!(provider);
+            sample["model"] = json!(model);
+            sample
+        }).collect::<Vec<_>>(),
+        "tokens_total": registry.tokens.iter().map(|((provider, model, kind), count)| {
+            json!({ "provider": provider, "model": model, "type": kind, "value": count })
+        }).collect::<Vec<_>>(),
+        "time_to_first_token_seconds": registry.time_to_first_token.iter().map(|((provider, model), h)| {
+            let mut sample = h.to_json();
+            sample["provider"] = json!(provider);
+            sample["model"] = json!(model);
+            sample
+        }).collect::<Vec<_>>(),
+        "output_tokens_per_second": registry.tokens_per_second.iter().map(|((provider, model), h)| {
+            let mut sample = h.to_json();
+            sample["provider"] = json!(provider);
+            sample["model"] = json!(model);
+            sample
+        }).collect::<Vec<_>>(),
+        "tool_executions_total": registry.tool_executions.iter().map(|((tool, status), count)| {
+            json!({ "tool": tool, "status": status, "value": count })
+        }).collect::<Vec<_>>(),
+        "tool_duration_seconds": registry.tool_duration.iter().map(|(tool, h)| {
+            let mut sample = h.to_json();
+            sample["tool"] = json!(tool);
+            sample
+        }).collect::<Vec<_>>(),
+        "tool_result_bytes": registry.tool_result_bytes.iter().map(|(tool, h)| {
+            let mut sample = h.to_json();
+            sample["tool"] = json!(tool);
+            sample
+        }).collect::<Vec<_>>(),
+    })
+}
+
+/// Snapshot of every metric as a dict of sample lists.
+#[pyfunction]
+pub fn get_metrics(py: Python) -> PyResult<Py<PyAny>> {
+    Ok(pythonize::pythonize(py, &snapshot())?.unbind())
+}
+
+/// All metrics in the Prometheus text exposition format.
+#[pyfunction]
+pub fn metrics_text() -> String {
+    render()
+}
+
+/// Serve the metrics at `http://host:port/metrics` from a background task.
+#[pyfunction]
+#[pyo3(signature = (port=9464, host="127.0.0.1"))]
+pub fn start_metrics_server(py: Python, port: u16, host: &str) -> PyResult<()> {
+    let address = format!("{}:{}", host, port);
+    let listener = py.detach(|| {
+        RUNTIME.block_on(async {
+            TcpListener::bind(&address)
+                .await
+                .map_err(|e| PyOSError::new_err(format!("cannot listen on {}: {}", address, e)))
+        })
+    })?;
+
+    RUNTIME.spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            tokio::spawn(async move {
+                let mut request = [0u8; 1024];
+                let read = socket.read(&mut request).await.unwrap_or(0);
+                let path = std::str::from_utf8(&request[..read])
+                    .ok()
+                    .and_then(|r| r.split_whitespace().nth(1))
+                    .unwrap_or("");
+                let (status, body) = if path == "/metrics" {
+                    ("200 OK", render())
+                } else {
+                    ("404 Not Found", String::new())
+                };
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                let _ = socket.write_all(response.as_bytes()).await;
+            });
+        }
+    });
+    Ok(())
+}
@@ -0,0 +1,258 @@
+//! Content moderation: OpenAI's moderation endpoint plus optional local keyword and
+//! regex rules, callable directly or run on an agent's final answers.
+
+use crate::error::{retry_after_header, RustedChainError};
+use crate::retry::RetryPolicy;
+use crate::{HTTP, RUNTIME};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use regex::Regex;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::HashMap;
+use std::env;
+
+pub(crate) const DEFAULT_MODEL: &str = "omni-moderation-latest";
+
+#[derive(Deserialize)]
+struct ModerationResponse {
+    results: Vec<ModerationVerdict>,
+}
+
+#[derive(Deserialize)]
+struct ModerationVerdict {
+    flagged: bool,
+    #[serde(default)]
+    categories: HashMap<String, bool>,
+    #[serde(default)]
+    category_scores: HashMap<String, f64>,
+}
+
+/// Local rules checked before (or instead of) the moderation endpoint.
+#[derive(Clone, Default)]
+pub(crate) struct ModerationRules {
+    /// Lowercased; matched case-insensitively anywhere in the text.
+    keywords: Vec<String>,
+    patterns: Vec<Regex>,
+}
+
+impl ModerationRules {
+    pub(crate) fn new(keywords: Vec<String>, patterns: &[String]) -> PyResult<Self> {
+        let patterns = patterns
+            .iter()
+            .map(|pattern| {
+                Regex::new(pattern).map_err(|e| {
+                    PyValueError::new_err(format!("invalid pattern '{}': {}", pattern, e))
+                })
+            })
+            .collect::<PyResult<_>>()?;
+        Ok(ModerationRules {
+            keywords: keywords.into_iter().map(|k| k.to_lowercase()).collect(),
+            patterns,
+        })
+    }
+
+    pub(crate) fn set_keywords(&mut self, keywords: Vec<String>) {
+        self.keywords = keywords.into_iter().map(|k| k.to_lowercase()).collect();
+    }
+
+    pub(crate) fn set_patterns(&mut self, patterns: &[String]) -> PyResult<()> {
+        self.patterns = Self::new(Vec::new(), patterns)?.patterns;
+        Ok(())
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.keywords.is_empty() && self.patterns.is_empty()
+    }
+
+    /// The keywords and patterns found in `text`.
+    fn matches(&self, text: &str) -> Vec<String> {
+        let lower = text.to_lowercase();
+        self.keywords
+            .iter()
+            .filter(|keyword| lower.contains(keyword.as_str()))
+            .cloned()
+            .chain(
+                self.patterns
+                    .iter()
+                    .filter(|pattern| pattern.is_match(text))
+                    .map(|pattern| pattern.as_str().to_string()),
+            )
+            .collect()
+    }
+}
+
+/// What `auto_moderate` does with a flagged answer.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ModerationAction {
+    /// Raise `ContentFilterError`.
+    Raise,
+    /// Return the answer with the verdict in `metadata.moderation`.
+    Annotate,
+}
+
+impl ModerationAction {
+    /// `True`/`"raise"`, `"annotate"`, or `False`/`None` for off.
+    pub(crate) fn parse(value: &Bound<'_, PyAny>) -> PyResult<Option<Self>> {
+        if value.is_none() {
+            return Ok(None);
+        }
+        if let Ok(enabled) = value.extract::<bool>() {
+            return Ok(enabled.then_some(Self::Raise));
+        }
+        match value.extract::<String>()?.as_str() {
+            "raise" => Ok(Some(Self::Raise)),
+            "annotate" => Ok(Some(Self::Annotate)),
+            other => Err(PyValueError::new_err(format!(
+                "auto_moderate must be True, False, 'raise' or 'annotate', got '{}'",
+                other
+            ))),
+        }
+    }
+}
+
+/// The verdict on one text.
+#[pyclass(get_all)]
+#[derive(Clone, Default)]
+pub struct ModerationResult {
+    /// Whether the endpoint or any local rule flagged the text.
+    flagged: bool,
+    /// Names of the endpoint categories that were flagged, e.g. `"harassment"`.
+    categories: Vec<String>,
+    /// Endpoint score per category, from 0 to 1. Empty for local-only checks.
+    category_scores: HashMap<String, f64>,
+    /// Local keywords and patterns found in the text.
+    matches: Vec<String>,
+}
+
+#[pymethods]
+impl ModerationResult {
+    fn __repr__(&self) -> String {
+        let list = |items: &[String]| {
+            let quoted: Vec<String> = items.iter().map(|item| format!("'{}'", item)).collect();
+            format!("[{}]", quoted.join(", "))
+        };
+        format!(
+            "ModerationResult(flagged={}, categories={}, matches={})",
+            if self.flagged { "True" } else { "False" },
+            list(&self.categories),
+            list(&self.matches)
+        )
+    }
+}
+
+impl ModerationResult {
+    pub(crate) fn flagged(&self) -> bool {
+        self.flagged
+    }
+
+    /// Why the text was flagged, for error messages.
+    pub(crate) fn reason(&self) -> String {
+        self.categories
+            .iter()
+            .chain(&self.matches)
+            .cloned()
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Check `text` against `rules` and, given an API key, the moderation endpoint.
+pub(crate) async fn check(
+    text: &str,
+    api_key: Option<&str>,
+    model: &str,
+    rules: &ModerationRules,
+) -> Result<ModerationResult, RustedChainError> {
+    let mut result = ModerationResult {
+        matches: rules.matches(text),
+        ..Default::default()
+    };
+    if let Some(api_key) = api_key {
+        let verdict = request(text, api_key, model).await?;
+        result.flagged = verdict.flagged;
+        result.categories = verdict
+            .categories
+            .into_iter()
+            .filter_map(|(name, flagged)| flagged.then_some(name))
+            .collect();
+        result.categories.sort();
+        result.category_scores = verdict.category_scores;
+    } else if rules.is_empty() {
+        return Err(RustedChainError::InvalidRequest(
+            "moderation needs an OpenAI API key or local keywords/patterns".to_string(),
+        ));
+    }
+    result.flagged |= !result.matches.is_empty();
+    Ok(result)
+}
+
+async fn request(
+    text: &str,
+    api_key: &str,
+    model: &str,
+) -> Result<ModerationVerdict, RustedChainError> {
+    let client = &*HTTP;
+    let body = json!({ "model": model, "input": text });
+    let response = RetryPolicy::default()
+        .run(|| async {
+            let response = client
+                .post("https://api.openai.com/v1/moderations")
+                .bearer_auth(api_key)
+                .json(&body)
+                .send()
+                .await?;
+            if !response.status().is_success() {
+                let status = response.status();
+                let retry_after = retry_after_header(response.headers());
+                let text = response.text().await.unwrap_or_default();
+                return Err(RustedChainError::from_response(status, retry_after, &text));
+            }
+            Ok(response)
+        })
+        .await?;
+    let response: ModerationResponse = response
+        .json()
+        .await
+        .map_err(|e| RustedChainError::ParseError(e.to_string()))?;
+    response
+        .results
+        .into_iter()
+        .next()
+        .ok_or(RustedChainError::NoResponse)
+}
+
+/// The key used for moderation: the model's own OpenAI key, else `OPENAI_API_KEY`.
+pub(crate) fn api_key(provider: &str, model_key: &str) -> Option<String> {
+    if provider == "openai" && !model_key.is_empty() {
+        return Some(model_key.to_string());
+    }
+    dotenv::dotenv().ok();
+    env::var("OPENAI_API_KEY")
+        .ok()
+        .filter(|key| !key.is_empty())
+}
+
+/// Check `text` with OpenAI's moderation endpoint and any local `keywords` (matched
+/// case-insensitively) and regex `patterns`. The API key defaults to `OPENAI_API_KEY`;
+/// with `local_only=True` only the local rules run.
+#[pyfunction]
+#[pyo3(signature = (text, api_key=None, model=None, keywords=None, patterns=None, local_only=false))]
+pub fn moderate(
+    py: Python,
+    text: String,
+    api_key: Option<String>,
+    model: Option<String>,
+    keywords: Option<Vec<String>>,
+    patterns: Option<Vec<String>>,
+    local_only: bool,
+) -> PyResult<ModerationResult> {
+    let rules = ModerationRules::new(keywords.unwrap_or_default(), &patterns.unwrap_or_default())?;
+    let api_key = if local_only {
+        None
+    } else {
+        api_key.or_else(|| self::api_key("openai", ""))
+    };
+    let model = model.unwrap_or_else(|| DEFAULT_MODEL.to_string());
+    Ok(py.detach(|| RUNTIME.block_on(check(&text, api_key.as_deref(), &model, &rules)))?)
+}
@@ -1,7 +1,33 @@
+use crate::agent::{ChatClient, ToolRequest, Turn, Usage};
+use crate::balancer::Balancer;
+use crate::chat_template::{self, ChatTemplate, HeldText};
+use crate::citations::{Citation, Sources};
+use crate::dry_run;
+use crate::error::{retry_after_header, RustedChainError};
+use crate::idempotency;
+use crate::keys::ApiKey;
+use crate::media::{Attachment, ImageLimits};
+use crate::message_transforms::MessageTransform;
+use crate::prefill;
+use crate::retry::RetryPolicy;
+use crate::signing::RequestSigner;
+use crate::stream::{SseParser, TextSink};
+use crate::HTTP;
 use reqwest::Client;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{json, Value};
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::env;
+use std::future::Future;
+use std::sync::Arc;
+
+const IMAGE_LIMITS: ImageLimits = ImageLimits {
+    provider: "OpenAI",
+    max_bytes: 20 * 1024 * 1024,
+    max_side: None,
+};
 
 #[derive(Serialize)]
 struct ChatCompletionRequest {
@@ -9,6 +35,18 @@ struct ChatCompletionRequest {
     messages: Vec<Message>,
     #[serde(skip_serializing_if = "Option::is_none")]
     tools: Option<Vec<Tool>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    user: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    safety_identifier: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    logit_bias: Option<HashMap<u32, i32>>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<Value>,
 }
 
 #[derive(Serialize)]
@@ -28,16 +66,53 @@ pub(crate) struct Message {
     pub(crate) tool_call_id: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub(crate) tool_calls: Option<Vec<ToolCallResponse>>,
+    /// Sources cited by an answer; not sent back.
+    #[serde(skip)]
+    pub(crate) citations: Vec<Citation>,
+    /// Whether the answer stopped at the output token limit; not sent back.
+    #[serde(skip)]
+    pub(crate) truncated: bool,
 }
 
 #[derive(Deserialize)]
 struct ChatCompletionResponse {
     choices: Vec<Choice>,
+    usage: Option<TokenUsage>,
+    #[serde(flatten)]
+    sources: Sources,
+}
+
+#[derive(Deserialize)]
+struct TokenUsage {
+    prompt_tokens: u64,
+    completion_tokens: u64,
+}
+
+impl From<TokenUsage> for Usage {
+    fn from(usage: TokenUsage) -> Self {
+        Usage {
+            input_tokens: usage.prompt_tokens,
+            output_tokens: usage.completion_tokens,
+        }
+    }
 }
 
 #[derive(Deserialize)]
 struct Choice {
     message: MessageResponse,
+    finish_reason: Option<String>,
+    logprobs: Option<ChoiceLogprobs>,
+}
+
+#[derive(Deserialize)]
+struct ChoiceLogprobs {
+    content: Option<Vec<TokenLogprob>>,
+}
+
+#[derive(Deserialize)]
+struct TokenLogprob {
+    token: String,
+    logprob: f64,
 }
 
 #[derive(Deserialize)]
@@ -56,12 +131,147 @@ pub(crate) struct ToolCallResponse {
     function: FunctionCall,
 }
 
-#[derive(Serialize, Deserialize, Clone)]
+impl Default for ToolCallResponse {
+    fn default() -> Self {
+        Self {
+            id: String::new(),
+            tool_type: "function".to_string(),
+            function: FunctionCall::default(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Default)]
 struct FunctionCall {
     name: String,
     arguments: String,
 }
 
+/// A Responses API object, as returned when submitting, polling or cancelling a
+/// background response.
+#[derive(Deserialize)]
+pub(crate) struct BackgroundResponse {
+    pub(crate) id: String,
+    /// `queued`, `in_progress`, `completed`, `failed`, `cancelled` or `incomplete`.
+    pub(crate) status: String,
+    #[serde(default)]
+    output: Vec<Value>,
+    usage: Option<ResponsesUsage>,
+    error: Option<Value>,
+    incomplete_details: Option<Value>,
+}
+
+#[derive(Deserialize)]
+struct ResponsesUsage {
+    input_tokens: u64,
+    output_tokens: u64,
+}
+
+impl BackgroundResponse {
+    pub(crate) fn is_terminal(&self) -> bool {
+        !matches!(self.status.as_str(), "queued" | "in_progress")
+    }
+
+    /// The concatenated `output_text` of every message in the output.
+    pub(crate) fn text(&self) -> String {
+        self.output
+            .iter()
+            .filter(|item| item["type"] == "message")
+            .flat_map(|item| item["content"].as_array().into_iter().flatten())
+            .filter(|part| part["type"] == "output_text")
+            .filter_map(|part| part["text"].as_str())
+            .collect()
+    }
+
+    pub(crate) fn usage(&self) -> Usage {
+        self.usage
+            .as_ref()
+            .map(|usage| Usage {
+                input_tokens: usage.input_tokens,
+                output_tokens: usage.output_tokens,
+            })
+            .unwrap_or_default()
+    }
+
+    /// The final answer of a finished response, or why there is none.
+    pub(crate) fn outcome(&self) -> Result<String, RustedChainError> {
+        match self.status.as_str() {
+            "completed" => Ok(self.text()),
+            "cancelled" => Err(RustedChainError::Cancelled),
+            "incomplete" => Err(RustedChainError::Api {
+                status: 200,
+                message: format!(
+                    "response {} is incomplete: {}",
+                    self.id,
+                    self.incomplete_details
+                        .as_ref()
+                        .and_then(|details| details["reason"].as_str())
+                        .unwrap_or("unknown reason")
+                ),
+            }),
+            _ => Err(RustedChainError::Api {
+                status: 500,
+                message: self
+                    .error
+                    .as_ref()
+                    .and_then(|error| error["message"].as_str())
+                    .unwrap_or("background response failed")
+                    .to_string(),
+            }),
+        }
+    }
+}
+
+/// A `/completions` response or stream chunk, used with a chat template.
+#[derive(Deserialize)]
+struct CompletionResponse {
+    #[serde(default)]
+    choices: Vec<CompletionChoice>,
+    usage: Option<TokenUsage>,
+}
+
+#[derive(Deserialize)]
+struct CompletionChoice {
+    #[serde(default)]
+    text: String,
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ChatCompletionChunk {
+    #[serde(default)]
+    choices: Vec<ChunkChoice>,
+    usage: Option<TokenUsage>,
+    #[serde(flatten)]
+    sources: Sources,
+}
+
+#[derive(Deserialize)]
+struct ChunkChoice {
+    delta: ChunkDelta,
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ChunkDelta {
+    role: Option<String>,
+    content: Option<String>,
+    tool_calls: Option<Vec<ToolCallDelta>>,
+}
+
+#[derive(Deserialize)]
+struct ToolCallDelta {
+    index: usize,
+    id: Option<String>,
+    function: Option<FunctionDelta>,
+}
+
+#[derive(Deserialize)]
+struct FunctionDelta {
+    name: Option<String>,
+    arguments: Option<String>,
+}
+
 pub enum OpenAIResponse {
     Text(String),
     ToolCall(ToolCall),
@@ -74,21 +284,50 @@ pub struct ToolCall {
     pub id: String,
 }
 
+const OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+
+#[derive(Clone)]
 pub struct OpenAI {
-    api_key: String,
+    api_key: ApiKey,
     model: String,
     client: Client,
     tools: Option<Vec<serde_json::Value>>,
+    retry: RetryPolicy,
+    user: Option<String>,
+    safety_identifier: Option<String>,
+    system: Option<String>,
+    prefill: Option<String>,
+    temperature: Option<f64>,
+    logit_bias: Option<HashMap<u32, i32>>,
+    base_url: String,
+    /// Deployments that requests are spread across instead of `base_url`.
+    deployments: Option<Arc<Balancer>>,
+    chat_template: Option<Arc<ChatTemplate>>,
+    /// Rewrites applied to request messages, as for the provider named with them.
+    message_transform: Option<(Arc<MessageTransform>, &'static str)>,
+    signer: Option<RequestSigner>,
 }
 
 impl Default for OpenAI {
     fn default() -> Self {
         dotenv::dotenv().ok();
         Self {
-            api_key: env::var("OPENAI_API_KEY").unwrap_or_default(),
+            api_key: env::var("OPENAI_API_KEY").unwrap_or_default().into(),
             model: "gpt-4o-mini".to_string(),
-            client: Client::new(),
+            client: HTTP.clone(),
             tools: None,
+            retry: RetryPolicy::default(),
+            user: None,
+            safety_identifier: None,
+            system: None,
+            prefill: None,
+            temperature: None,
+            logit_bias: None,
+            base_url: env::var("OPENAI_BASE_URL").unwrap_or_else(|_| OPENAI_BASE_URL.to_string()),
+            deployments: None,
+            chat_template: None,
+            message_transform: None,
+            signer: None,
         }
     }
 }
@@ -98,7 +337,7 @@ impl OpenAI {
         Self::default()
     }
 
-    pub fn with_api_key(mut self, api_key: String) -> Self {
+    pub(crate) fn with_api_key(mut self, api_key: ApiKey) -> Self {
         self.api_key = api_key;
         self
     }
@@ -113,8 +352,94 @@ impl OpenAI {
         self
     }
 
+    pub fn without_tools(mut self) -> Self {
+        self.tools = None;
+        self
+    }
+
+    pub fn with_retry_policy(mut self, retry: RetryPolicy) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Add `signer`'s headers to every request, right before it is sent.
+    pub fn with_signer(mut self, signer: RequestSigner) -> Self {
+        self.signer = Some(signer);
+        self
+    }
+
+    /// End-user id forwarded as OpenAI's `user` field for attribution.
+    pub fn with_user(mut self, user: String) -> Self {
+        self.user = Some(user);
+        self
+    }
+
+    /// Stable hashed end-user id forwarded as `safety_identifier` for abuse monitoring.
+    pub fn with_safety_identifier(mut self, safety_identifier: String) -> Self {
+        self.safety_identifier = Some(safety_identifier);
+        self
+    }
+
+    /// Sampling temperature, from 0 to 2.
+    pub fn with_temperature(mut self, temperature: f64) -> Self {
+        self.temperature = Some(temperature);
+        self
+    }
+
+    /// Token id to bias from -100 (never sampled) to 100 (always sampled).
+    pub fn with_logit_bias(mut self, logit_bias: HashMap<u32, i32>) -> Self {
+        self.logit_bias = Some(logit_bias);
+        self
+    }
+
+    /// System prompt sent as a leading `system` message.
+    pub fn with_system(mut self, system: String) -> Self {
+        self.system = Some(system);
+        self
+    }
+
+    /// Root of the API, e.g. `http://localhost:8000/v1` for a local OpenAI-compatible
+    /// server. Defaults to `OPENAI_BASE_URL`, then OpenAI itself.
+    pub fn with_base_url(mut self, base_url: String) -> Self {
+        self.base_url = base_url.trim_end_matches('/').to_string();
+        self
+    }
+
+    /// Spread requests across `deployments`, each with its own API root and key, in
+    /// place of `base_url` and the client's key.
+    pub(crate) fn with_deployments(mut self, deployments: Arc<Balancer>) -> Self {
+        self.deployments = Some(deployments);
+        self
+    }
+
+    /// Render conversations into a raw prompt with `template` and send them to the
+    /// `/completions` endpoint, for servers without a chat template of their own.
+    pub(crate) fn with_chat_template(mut self, template: Arc<ChatTemplate>) -> Self {
+        self.chat_template = Some(template);
+        self
+    }
+
+    /// Rewrite the messages of every request with `transform`, as for `provider`: OpenAI
+    /// or another provider served through an OpenAI-compatible API.
+    pub(crate) fn with_message_transform(
+        mut self,
+        transform: Arc<MessageTransform>,
+        provider: &'static str,
+    ) -> Self {
+        self.message_transform = Some((transform, provider));
+        self
+    }
+
+    /// Text every answer starts with. Without native prefill, the model is asked to
+    /// start with it in the system prompt, and answers that don't are prefixed.
+    pub fn with_prefill(mut self, prefill: String) -> Self {
+        self.system = Some(prefill::with_instruction(self.system.take(), &prefill));
+        self.prefill = Some(prefill);
+        self
+    }
+
     #[allow(dead_code)]
-    pub async fn invoke(&self, prompt: &str) -> Result<String, String> {
+    pub async fn invoke(&self, prompt: &str) -> Result<String, RustedChainError> {
         match self.invoke_with_response(prompt).await? {
             OpenAIResponse::Text(text) => Ok(text),
             OpenAIResponse::ToolCall(tool_call) => {
@@ -124,25 +449,29 @@ impl OpenAI {
         }
     }
 
-    pub async fn invoke_with_response(&self, prompt: &str) -> Result<OpenAIResponse, String> {
+    pub async fn invoke_with_response(
+        &self,
+        prompt: &str,
+    ) -> Result<OpenAIResponse, RustedChainError> {
         let messages = vec![Message {
             role: "user".to_string(),
             content: prompt.to_string(),
             name: None,
             tool_call_id: None,
             tool_calls: None,
+            citations: Vec::new(),
+            truncated: false,
         }];
 
-        let (response, _) = self.chat(messages).await?;
+        let (response, _, _) = self.chat(messages).await?;
         Ok(response)
     }
 
-    pub(crate) async fn chat(
+    async fn send(
         &self,
         messages: Vec<Message>,
-    ) -> Result<(OpenAIResponse, Message), String> {
-        let url = "https://api.openai.com/v1/chat/completions";
-
+        stream: bool,
+    ) -> Result<reqwest::Response, RustedChainError> {
         let tools = self.tools.as_ref().map(|t| {
             t.iter()
                 .map(|tool| Tool {
@@ -152,63 +481,938 @@ impl OpenAI {
                 .collect()
         });
 
+        let messages = match &self.system {
+            Some(system) => std::iter::once(Message {
+                role: "system".to_string(),
+                content: system.clone(),
+                name: None,
+                tool_call_id: None,
+                tool_calls: None,
+                citations: Vec::new(),
+                truncated: false,
+            })
+            .chain(messages)
+            .collect(),
+            None => messages,
+        };
+
         let request_body = ChatCompletionRequest {
             model: self.model.clone(),
             messages,
             tools,
+            user: self.user.clone(),
+            safety_identifier: self.safety_identifier.clone(),
+            temperature: self.temperature,
+            logit_bias: self.logit_bias.clone(),
+            stream,
+            // Ask for a final chunk carrying token usage.
+            stream_options: stream.then(|| serde_json::json!({ "include_usage": true })),
         };
+        match &self.message_transform {
+            Some((transform, provider)) => {
+                let body = transform.apply_to(provider, &request_body, "messages")?;
+                self.post("/chat/completions", &body).await
+            }
+            None => self.post("/chat/completions", &request_body).await,
+        }
+    }
 
-        let response = self
-            .client
-            .post(url)
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .json(&request_body)
-            .send()
+    async fn post(
+        &self,
+        path: &str,
+        body: &impl Serialize,
+    ) -> Result<reqwest::Response, RustedChainError> {
+        self.post_to(|base_url| format!("{}{}", base_url, path), body)
             .await
-            .map_err(|e| format!("Failed to send request: {}", e))?;
+    }
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let text = response.text().await.unwrap_or_default();
-            return Err(format!("API Error {}: {}", status, text));
+    /// POST `body` to `url` with this client's key and retry policy, under one
+    /// idempotency key for every attempt.
+    pub(crate) async fn post_url(
+        &self,
+        url: &str,
+        body: &impl Serialize,
+    ) -> Result<reqwest::Response, RustedChainError> {
+        self.post_to(|_| url.to_string(), body).await
+    }
+
+    /// `post_url` to the URL `url` makes of the API root.
+    async fn post_to(
+        &self,
+        url: impl Fn(&str) -> String,
+        body: &impl Serialize,
+    ) -> Result<reqwest::Response, RustedChainError> {
+        let idempotency_key = &idempotency::current();
+        let url = &url;
+        self.retry
+            .run(|| {
+                self.attempt(|base_url, api_key| async move {
+                    let url = url(base_url);
+                    let response = api_key
+                        .send(self.signer.as_ref(), |key| {
+                            self.client
+                                .post(&url)
+                                .header("Authorization", format!("Bearer {}", key))
+                                .header("Idempotency-Key", idempotency_key)
+                                .json(body)
+                        })
+                        .await?;
+
+                    if !response.status().is_success() {
+                        let status = response.status();
+                        let retry_after = retry_after_header(response.headers());
+                        let text = response.text().await.unwrap_or_default();
+                        return Err(RustedChainError::from_response(status, retry_after, &text));
+                    }
+                    Ok(response)
+                })
+            })
+            .await
+    }
+
+    /// Make one attempt at a request with the API root and key of the next deployment,
+    /// recording how it went, or with the client's own without deployments.
+    async fn attempt<'s, T, F, Fut>(&'s self, send: F) -> Result<T, RustedChainError>
+    where
+        F: FnOnce(&'s str, &'s ApiKey) -> Fut,
+        Fut: Future<Output = Result<T, RustedChainError>>,
+    {
+        let Some(deployments) = &self.deployments else {
+            return send(&self.base_url, &self.api_key).await;
+        };
+        // A dry run shows the first deployment and leaves the breakers alone.
+        if dry_run::active() {
+            let (base_url, api_key) = deployments.deployment(0);
+            return send(base_url, api_key).await;
         }
+        let index = deployments.pick()?;
+        let (base_url, api_key) = deployments.deployment(index);
+        let result = send(base_url, api_key).await;
+        deployments.record(index, &result);
+        result
+    }
+
+    /// Send a request body built by the caller to `path` under the base URL, by default
+    /// `/chat/completions` (`/completions` with a chat template), adding the model if it
+    /// is missing. Returns the parsed response.
+    pub(crate) async fn raw(
+        &self,
+        mut payload: Value,
+        path: Option<&str>,
+    ) -> Result<Value, RustedChainError> {
+        if let Some(body) = payload.as_object_mut() {
+            body.entry("model").or_insert_with(|| json!(self.model));
+        }
+        let path = path.unwrap_or(match self.chat_template {
+            Some(_) => "/completions",
+            None => "/chat/completions",
+        });
+        self.post(path, &payload)
+            .await?
+            .json()
+            .await
+            .map_err(|e| RustedChainError::ParseError(e.to_string()))
+    }
+
+    /// Resolve DNS and open a TLS connection, which the shared HTTP client keeps for
+    /// later requests. With `prime`, also send a one-token completion.
+    pub(crate) async fn warmup(&self, prime: bool) -> Result<(), RustedChainError> {
+        if prime && self.chat_template.is_some() {
+            let body = json!({ "model": self.model, "prompt": "Hi", "max_tokens": 1 });
+            self.post("/completions", &body).await?.bytes().await?;
+        } else if prime {
+            let body = json!({
+                "model": self.model,
+                "messages": [{ "role": "user", "content": "Hi" }],
+                "max_completion_tokens": 1,
+            });
+            self.post("/chat/completions", &body).await?.bytes().await?;
+        } else {
+            // Any answer, even an authentication error, leaves a warm connection. The
+            // body is read so the connection goes back to the pool.
+            self.attempt(|base_url, api_key| async move {
+                self.client
+                    .get(format!("{}/models", base_url))
+                    .bearer_auth(api_key.current()?)
+                    .send()
+                    .await?
+                    .bytes()
+                    .await?;
+                Ok(())
+            })
+            .await?;
+        }
+        Ok(())
+    }
+
+    pub(crate) async fn chat(
+        &self,
+        messages: Vec<Message>,
+    ) -> Result<(OpenAIResponse, Message, Usage), RustedChainError> {
+        if let Some(template) = &self.chat_template {
+            return self.complete(template, messages, None).await;
+        }
+        let response = self.send(messages, false).await?;
 
         let response_body: ChatCompletionResponse = response
             .json()
             .await
-            .map_err(|e| format!("Failed to parse response: {}", e))?;
+            .map_err(|e| RustedChainError::ParseError(e.to_string()))?;
 
-        if let Some(choice) = response_body.choices.first() {
-            let assistant_message = Message {
-                role: choice.message.role.clone(),
-                content: choice.message.content.clone().unwrap_or_default(),
-                name: None,
-                tool_call_id: None,
-                tool_calls: choice.message.tool_calls.clone(),
+        let choice = response_body
+            .choices
+            .into_iter()
+            .next()
+            .ok_or(RustedChainError::NoResponse)?;
+        let (response, mut message) = into_response(
+            choice.message.role,
+            choice.message.content,
+            choice.message.tool_calls,
+        )?;
+        message.citations = response_body.sources.into_citations();
+        message.truncated = choice.finish_reason.as_deref() == Some("length");
+        let usage = response_body.usage.map(Usage::from).unwrap_or_default();
+        Ok((response, message, usage))
+    }
+
+    /// A single turn with `files` attached, answered in JSON matching `schema` (using
+    /// strict structured outputs) when one is given. `schema` must describe an object.
+    pub(crate) async fn multimodal(
+        &self,
+        prompt: &str,
+        files: &[Attachment],
+        schema: Option<&Value>,
+    ) -> Result<(String, Usage), RustedChainError> {
+        let (text, _, usage) = self.structured(prompt, files, schema, false).await?;
+        Ok((text, usage))
+    }
+
+    /// A tool-free turn answered in JSON matching `schema`, with each answer token and
+    /// its log probability.
+    pub(crate) async fn with_logprobs(
+        &self,
+        prompt: &str,
+        schema: &Value,
+    ) -> Result<(String, Vec<(String, f64)>, Usage), RustedChainError> {
+        self.structured(prompt, &[], Some(schema), true).await
+    }
+
+    async fn structured(
+        &self,
+        prompt: &str,
+        files: &[Attachment],
+        schema: Option<&Value>,
+        logprobs: bool,
+    ) -> Result<(String, Vec<(String, f64)>, Usage), RustedChainError> {
+        let mut content = vec![json!({ "type": "text", "text": prompt })];
+        for file in files {
+            let Attachment::Image(image) = file else {
+                return Err(RustedChainError::InvalidRequest(
+                    "OpenAI chat models do not accept video; use a Gemini model".to_string(),
+                ));
             };
+            let url = image.fit(&IMAGE_LIMITS)?.data_url();
+            content.push(json!({ "type": "image_url", "image_url": { "url": url } }));
+        }
+        let mut messages = Vec::new();
+        if let Some(system) = &self.system {
+            messages.push(json!({ "role": "system", "content": system }));
+        }
+        messages.push(json!({ "role": "user", "content": content }));
+        let mut body = json!({ "model": self.model, "messages": messages });
+        if let Some(schema) = schema {
+            let mut schema = schema.clone();
+            strict_schema(&mut schema);
+            body["response_format"] = json!({
+                "type": "json_schema",
+                "json_schema": { "name": "analysis", "schema": schema, "strict": true },
+            });
+        }
+        if let Some(user) = &self.user {
+            body["user"] = json!(user);
+        }
+        if let Some(id) = &self.safety_identifier {
+            body["safety_identifier"] = json!(id);
+        }
+        if logprobs {
+            body["logprobs"] = json!(true);
+        }
+
+        let response: ChatCompletionResponse = self
+            .post("/chat/completions", &body)
+            .await?
+            .json()
+            .await
+            .map_err(|e| RustedChainError::ParseError(e.to_string()))?;
+        let usage = response.usage.map(Usage::from).unwrap_or_default();
+        let choice = response
+            .choices
+            .into_iter()
+            .next()
+            .ok_or(RustedChainError::NoResponse)?;
+        let tokens = choice
+            .logprobs
+            .and_then(|logprobs| logprobs.content)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|token| (token.token, token.logprob))
+            .collect();
+        let text = choice.message.content.ok_or(RustedChainError::NoResponse)?;
+        Ok((text, tokens, usage))
+    }
+
+    /// Whether the model returns token log probabilities; reasoning models do not.
+    pub(crate) fn reports_logprobs(&self) -> bool {
+        !["o1", "o3", "o4", "gpt-5"]
+            .iter()
+            .any(|prefix| self.model.starts_with(prefix))
+    }
+
+    /// Send a request to `path` under the API root with this client's key and retry
+    /// policy, plus any extra `headers`. A POST has one idempotency key for every attempt.
+    pub(crate) async fn request(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        body: Option<&Value>,
+        headers: &[(&str, &str)],
+    ) -> Result<reqwest::Response, RustedChainError> {
+        let idempotency_key = &(method == reqwest::Method::POST).then(idempotency::current);
+        let method = &method;
+        self.retry
+            .run(|| {
+                self.attempt(|base_url, api_key| async move {
+                    let url = format!("{}{}", base_url, path);
+                    let response = api_key
+                        .send(self.signer.as_ref(), |key| {
+                            let mut request =
+                                self.client.request(method.clone(), &url).bearer_auth(key);
+                            if let Some(idempotency_key) = &idempotency_key {
+                                request = request.header("Idempotency-Key", idempotency_key);
+                            }
+                            for (name, value) in headers {
+                                request = request.header(*name, *value);
+                            }
+                            if let Some(body) = body {
+                                request = request.json(body);
+                            }
+                            request
+                        })
+                        .await?;
+                    if !response.status().is_success() {
+                        let status = response.status();
+                        let retry_after = retry_after_header(response.headers());
+                        let text = response.text().await.unwrap_or_default();
+                        return Err(RustedChainError::from_response(status, retry_after, &text));
+                    }
+                    Ok(response)
+                })
+            })
+            .await
+    }
 
-            if let Some(tool_calls) = &choice.message.tool_calls {
-                if let Some(tool_call) = tool_calls.first() {
-                    let args_value: Value =
-                        serde_json::from_str(&tool_call.function.arguments)
-                            .unwrap_or(Value::Null);
-
-                    return Ok((
-                        OpenAIResponse::ToolCall(ToolCall {
-                            name: tool_call.function.name.clone(),
-                            args: args_value,
-                            id: tool_call.id.clone(),
-                        }),
-                        assistant_message,
-                    ));
+    /// `request`, parsing the JSON body of the response.
+    pub(crate) async fn request_json<T: DeserializeOwned>(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        body: Option<&Value>,
+        headers: &[(&str, &str)],
+    ) -> Result<T, RustedChainError> {
+        self.request(method, path, body, headers)
+            .await?
+            .json()
+            .await
+            .map_err(|e| RustedChainError::ParseError(e.to_string()))
+    }
+
+    /// Send a Responses API request and parse the response object it returns.
+    async fn responses(
+        &self,
+        method: reqwest::Method,
+        path: &str,
+        body: Option<&Value>,
+    ) -> Result<BackgroundResponse, RustedChainError> {
+        self.request_json(method, &format!("/responses{}", path), body, &[])
+            .await
+    }
+
+    /// Start a background response for `prompt`: OpenAI runs it to completion without an
+    /// open connection, and it is fetched later by id.
+    pub(crate) async fn submit_background(
+        &self,
+        prompt: &str,
+    ) -> Result<BackgroundResponse, RustedChainError> {
+        let mut body = json!({
+            "model": self.model,
+            "input": prompt,
+            "background": true,
+            "store": true,
+        });
+        if let Some(system) = &self.system {
+            body["instructions"] = json!(system);
+        }
+        if let Some(user) = &self.user {
+            body["user"] = json!(user);
+        }
+        if let Some(id) = &self.safety_identifier {
+            body["safety_identifier"] = json!(id);
+        }
+        if let Some(temperature) = self.temperature {
+            body["temperature"] = json!(temperature);
+        }
+        self.responses(reqwest::Method::POST, "", Some(&body)).await
+    }
+
+    pub(crate) async fn retrieve_background(
+        &self,
+        id: &str,
+    ) -> Result<BackgroundResponse, RustedChainError> {
+        self.responses(reqwest::Method::GET, &format!("/{}", id), None)
+            .await
+    }
+
+    pub(crate) async fn cancel_background(
+        &self,
+        id: &str,
+    ) -> Result<BackgroundResponse, RustedChainError> {
+        self.responses(reqwest::Method::POST, &format!("/{}/cancel", id), None)
+            .await
+    }
+
+    pub(crate) async fn chat_stream(
+        &self,
+        messages: Vec<Message>,
+        sink: &TextSink,
+    ) -> Result<(OpenAIResponse, Message, Usage), RustedChainError> {
+        if let Some(template) = &self.chat_template {
+            return self.complete(template, messages, Some(sink)).await;
+        }
+        let mut response = self.send(messages, true).await?;
+        let mut parser = SseParser::default();
+        let mut role = "assistant".to_string();
+        let mut content: Option<String> = None;
+        let mut tool_calls: Vec<ToolCallResponse> = Vec::new();
+        let mut usage = Usage::default();
+        let mut sources = Sources::default();
+        let mut finish_reason = None;
+
+        while let Some(chunk) = response.chunk().await? {
+            for data in parser.push(&chunk) {
+                if data == "[DONE]" {
+                    continue;
+                }
+                let chunk: ChatCompletionChunk = serde_json::from_str(&data)
+                    .map_err(|e| RustedChainError::ParseError(e.to_string()))?;
+                if let Some(chunk_usage) = chunk.usage {
+                    usage = chunk_usage.into();
+                }
+                // Each chunk repeats the sources found so far.
+                if !chunk.sources.is_empty() {
+                    sources = chunk.sources;
+                }
+                for choice in chunk.choices {
+                    finish_reason = choice.finish_reason.or(finish_reason);
+                    let delta = choice.delta;
+                    if let Some(r) = delta.role {
+                        role = r;
+                    }
+                    if let Some(text) = delta.content {
+                        content.get_or_insert_with(String::new).push_str(&text);
+                        sink.send(text).await?;
+                    }
+                    for call in delta.tool_calls.unwrap_or_default() {
+                        if tool_calls.len() <= call.index {
+                            tool_calls.resize_with(call.index + 1, ToolCallResponse::default);
+                        }
+                        let entry = &mut tool_calls[call.index];
+                        if let Some(id) = call.id {
+                            entry.id = id;
+                        }
+                        if let Some(function) = call.function {
+                            entry.function.name += &function.name.unwrap_or_default();
+                            entry.function.arguments += &function.arguments.unwrap_or_default();
+                        }
+                    }
                 }
             }
+        }
 
-            if let Some(content) = &choice.message.content {
-                return Ok((OpenAIResponse::Text(content.clone()), assistant_message));
+        let tool_calls = (!tool_calls.is_empty()).then_some(tool_calls);
+        let (response, mut message) = into_response(role, content, tool_calls)?;
+        message.citations = sources.into_citations();
+        message.truncated = finish_reason.as_deref() == Some("length");
+        Ok((response, message, usage))
+    }
+
+    /// Render the conversation with `template` and send it to `/completions`, streaming
+    /// the text to `sink` if given. A completion that is a tool call in the template's
+    /// format comes back as a tool call.
+    async fn complete(
+        &self,
+        template: &ChatTemplate,
+        messages: Vec<Message>,
+        sink: Option<&TextSink>,
+    ) -> Result<(OpenAIResponse, Message, Usage), RustedChainError> {
+        let mut body = json!({
+            "model": self.model,
+            "prompt": self.template_prompt(template, &messages)?,
+            "stream": sink.is_some(),
+        });
+        if !template.stop.is_empty() {
+            body["stop"] = json!(template.stop);
+        }
+        if sink.is_some() {
+            body["stream_options"] = json!({ "include_usage": true });
+        }
+        if let Some(user) = &self.user {
+            body["user"] = json!(user);
+        }
+        if let Some(temperature) = self.temperature {
+            body["temperature"] = json!(temperature);
+        }
+        if let Some(bias) = &self.logit_bias {
+            body["logit_bias"] = json!(bias);
+        }
+        let mut response = self.post("/completions", &body).await?;
+
+        let mut usage = Usage::default();
+        let mut finish_reason = None;
+        let text = match sink {
+            None => {
+                let completion: CompletionResponse = response
+                    .json()
+                    .await
+                    .map_err(|e| RustedChainError::ParseError(e.to_string()))?;
+                usage = completion.usage.map(Usage::from).unwrap_or_default();
+                let choice = completion
+                    .choices
+                    .into_iter()
+                    .next()
+                    .ok_or(RustedChainError::NoResponse)?;
+                finish_reason = choice.finish_reason;
+                choice.text
+            }
+            Some(sink) => {
+                let mut parser = SseParser::default();
+                let mut held = HeldText::new(self.has_tools());
+                while let Some(chunk) = response.chunk().await? {
+                    for data in parser.push(&chunk) {
+                        if data == "[DONE]" {
+                            continue;
+                        }
+                        let chunk: CompletionResponse = serde_json::from_str(&data)
+                            .map_err(|e| RustedChainError::ParseError(e.to_string()))?;
+                        if let Some(chunk_usage) = chunk.usage {
+                            usage = chunk_usage.into();
+                        }
+                        for choice in chunk.choices {
+                            finish_reason = choice.finish_reason.or(finish_reason);
+                            held.push(&choice.text, sink).await?;
+                        }
+                    }
+                }
+                held.finish(sink).await?
             }
+        };
+        let (response, mut message) = self.template_turn(text)?;
+        message.truncated = finish_reason.as_deref() == Some("length");
+        Ok((response, message, usage))
+    }
+
+    /// The tools in the `{"type": "function", "function": ...}` form OpenAI's APIs take.
+    pub(crate) fn tool_definitions(&self) -> Vec<Value> {
+        self.tools
+            .iter()
+            .flatten()
+            .map(|function| json!({ "type": "function", "function": function }))
+            .collect()
+    }
+
+    pub(crate) fn has_tools(&self) -> bool {
+        self.tools.is_some()
+    }
+
+    /// The raw prompt for `messages` under `template`, system prompt and tools included.
+    pub(crate) fn template_prompt(
+        &self,
+        template: &ChatTemplate,
+        messages: &[Message],
+    ) -> Result<String, RustedChainError> {
+        let system = self
+            .system
+            .iter()
+            .map(|system| json!({ "role": "system", "content": system }));
+        let messages: Vec<Value> = system
+            .chain(messages.iter().map(template_message))
+            .collect();
+        let messages = match &self.message_transform {
+            Some((transform, provider)) => transform.apply(provider, messages)?,
+            None => messages,
+        };
+        let tools: Option<Vec<Value>> = self.tools.as_ref().map(|tools| {
+            tools
+                .iter()
+                .map(|tool| json!({ "type": "function", "function": tool }))
+                .collect()
+        });
+        template
+            .render(messages, tools.as_deref())
+            .map_err(|e| RustedChainError::ParseError(format!("chat template: {}", e)))
+    }
+
+    /// The response to a raw completion: a tool call if the agent has tools and the
+    /// text is one, otherwise the text.
+    pub(crate) fn template_turn(
+        &self,
+        text: String,
+    ) -> Result<(OpenAIResponse, Message), RustedChainError> {
+        let call = self
+            .has_tools()
+            .then(|| chat_template::parse_tool_call(&text))
+            .flatten();
+        let Some((name, args)) = call else {
+            return into_response("assistant".to_string(), Some(text), None);
+        };
+        let id = format!("call_{}", uuid::Uuid::new_v4().simple());
+        let message = Message {
+            role: "assistant".to_string(),
+            content: text,
+            name: None,
+            tool_call_id: None,
+            tool_calls: Some(vec![ToolCallResponse {
+                id: id.clone(),
+                function: FunctionCall {
+                    name: name.clone(),
+                    arguments: args.to_string(),
+                },
+                ..Default::default()
+            }]),
+            citations: Vec::new(),
+            truncated: false,
+        };
+        Ok((
+            OpenAIResponse::ToolCall(ToolCall { name, args, id }),
+            message,
+        ))
+    }
+}
+
+/// A message as chat templates expect it. Tool calls are written into the content in
+/// the format `parse_tool_call` reads, unless the message already has it.
+fn template_message(message: &Message) -> Value {
+    let mut value = json!({ "role": message.role, "content": message.content });
+    if let Some(calls) = &message.tool_calls {
+        let calls: Vec<(&str, Value)> = calls
+            .iter()
+            .map(|call| {
+                let args = serde_json::from_str(&call.function.arguments).unwrap_or(Value::Null);
+                (call.function.name.as_str(), args)
+            })
+            .collect();
+        if message.content.is_empty() {
+            let content: Vec<String> = calls
+                .iter()
+                .map(|(name, args)| chat_template::format_tool_call(name, args))
+                .collect();
+            value["content"] = json!(content.join("\n"));
+        }
+        value["tool_calls"] = calls
+            .into_iter()
+            .map(|(name, args)| {
+                json!({ "type": "function", "function": { "name": name, "arguments": args } })
+            })
+            .collect();
+    }
+    if let Some(id) = &message.tool_call_id {
+        value["tool_call_id"] = json!(id);
+    }
+    value
+}
+
+/// Rewrite a function schema for `strict: true` function calling: every object closes
+/// with `additionalProperties: false` and lists all of its properties as required, with
+/// formerly optional properties made nullable instead. Defaults are not allowed.
+pub(crate) fn strict_function(mut function: Value) -> Value {
+    if let Some(parameters) = function.get_mut("parameters") {
+        strict_schema(parameters);
+    }
+    function["strict"] = Value::Bool(true);
+    function
+}
+
+fn strict_schema(schema: &mut Value) {
+    let Some(object) = schema.as_object_mut() else {
+        return;
+    };
+    object.remove("default");
+    for key in ["anyOf", "oneOf", "allOf", "prefixItems"] {
+        if let Some(Value::Array(variants)) = object.get_mut(key) {
+            variants.iter_mut().for_each(strict_schema);
         }
+    }
+    for key in ["$defs", "definitions"] {
+        if let Some(Value::Object(definitions)) = object.get_mut(key) {
+            definitions.values_mut().for_each(strict_schema);
+        }
+    }
+    if let Some(items) = object.get_mut("items") {
+        strict_schema(items);
+    }
+
+    if !object.get("properties").is_some_and(Value::is_object) {
+        if object.get("type") == Some(&Value::from("object")) {
+            object.insert("additionalProperties".to_string(), Value::Bool(false));
+        }
+        return;
+    }
+    let required: Vec<Value> = object
+        .get("required")
+        .and_then(Value::as_array)
+        .cloned()
+        .unwrap_or_default();
+    let Some(Value::Object(properties)) = object.get_mut("properties") else {
+        return;
+    };
+    for (name, property) in properties.iter_mut() {
+        strict_schema(property);
+        if !required.contains(&Value::from(name.as_str())) {
+            make_nullable(property);
+        }
+    }
+    let names = properties.keys().cloned().map(Value::from).collect();
+    object.insert("required".to_string(), Value::Array(names));
+    object.insert("additionalProperties".to_string(), Value::Bool(false));
+}
+
+fn make_nullable(schema: &mut Value) {
+    if let Some(Value::Array(values)) = schema.get_mut("enum") {
+        if !values.contains(&Value::Null) {
+            values.push(Value::Null);
+        }
+    }
+    let null = json!({ "type": "null" });
+    match schema.get_mut("anyOf") {
+        Some(Value::Array(variants)) => {
+            if !variants.contains(&null) {
+                variants.push(null);
+            }
+        }
+        _ => match schema.get("type").cloned() {
+            Some(Value::String(kind)) if kind != "null" => {
+                schema["type"] = json!([kind, "null"]);
+            }
+            Some(Value::Array(mut kinds)) => {
+                if !kinds.contains(&Value::from("null")) {
+                    kinds.push(Value::from("null"));
+                }
+                schema["type"] = Value::Array(kinds);
+            }
+            Some(_) => {}
+            None => *schema = json!({ "anyOf": [schema.take(), null] }),
+        },
+    }
+}
+
+/// Turn an assistant message (complete or reassembled from stream deltas) into a response.
+fn into_response(
+    role: String,
+    content: Option<String>,
+    tool_calls: Option<Vec<ToolCallResponse>>,
+) -> Result<(OpenAIResponse, Message), RustedChainError> {
+    let assistant_message = Message {
+        role,
+        content: content.clone().unwrap_or_default(),
+        name: None,
+        tool_call_id: None,
+        tool_calls: tool_calls.clone(),
+        citations: Vec::new(),
+        truncated: false,
+    };
+
+    if let Some(tool_call) = tool_calls.as_ref().and_then(|calls| calls.first()) {
+        let args_value: Value =
+            serde_json::from_str(&tool_call.function.arguments).unwrap_or(Value::Null);
+
+        return Ok((
+            OpenAIResponse::ToolCall(ToolCall {
+                name: tool_call.function.name.clone(),
+                args: args_value,
+                id: tool_call.id.clone(),
+            }),
+            assistant_message,
+        ));
+    }
+
+    match content {
+        Some(content) => Ok((OpenAIResponse::Text(content), assistant_message)),
+        None => Err(RustedChainError::NoResponse),
+    }
+}
+
+impl From<OpenAIResponse> for Turn {
+    fn from(response: OpenAIResponse) -> Self {
+        match response {
+            OpenAIResponse::Text(text) => Turn::Text(text),
+            OpenAIResponse::ToolCall(call) => Turn::ToolCall(ToolRequest {
+                id: Some(call.id),
+                name: call.name,
+                args: call.args,
+            }),
+        }
+    }
+}
+
+impl ChatClient for OpenAI {
+    type Message = Message;
+
+    fn provider(&self) -> &'static str {
+        "openai"
+    }
+
+    /// OpenAI-compatible servers each get a breaker of their own.
+    fn breaker_key(&self) -> Cow<'_, str> {
+        if self.deployments.is_some() || self.base_url == OPENAI_BASE_URL {
+            Cow::Borrowed("openai")
+        } else {
+            Cow::Borrowed(&self.base_url)
+        }
+    }
+
+    fn model(&self) -> &str {
+        &self.model
+    }
+
+    fn for_model(&self, model: &str) -> Self {
+        self.clone().with_model(model.to_string())
+    }
+
+    fn resumed(&self, partial: &str) -> Option<Self> {
+        Some(self.clone().with_prefill(partial.to_string()))
+    }
+
+    fn side_client(&self, model: &str, system: &str) -> Option<Self> {
+        let mut client = self
+            .for_model(model)
+            .without_tools()
+            .with_system(system.to_string());
+        client.prefill = None;
+        client.logit_bias = None;
+        Some(client)
+    }
+
+    fn api_key(&self) -> &ApiKey {
+        &self.api_key
+    }
+
+    fn system(&self) -> Option<&str> {
+        self.system.as_deref()
+    }
+
+    fn end_user(&self) -> Option<&str> {
+        self.user.as_deref().or(self.safety_identifier.as_deref())
+    }
+
+    fn user_message(text: &str) -> Message {
+        Message {
+            role: "user".to_string(),
+            content: text.to_string(),
+            name: None,
+            tool_call_id: None,
+            tool_calls: None,
+            citations: Vec::new(),
+            truncated: false,
+        }
+    }
+
+    fn assistant_message(text: &str) -> Message {
+        Message {
+            role: "assistant".to_string(),
+            content: text.to_string(),
+            name: None,
+            tool_call_id: None,
+            tool_calls: None,
+            citations: Vec::new(),
+            truncated: false,
+        }
+    }
+
+    fn tool_call_message(call: &ToolRequest) -> Message {
+        Message {
+            role: "assistant".to_string(),
+            content: String::new(),
+            name: None,
+            tool_call_id: None,
+            tool_calls: Some(vec![ToolCallResponse {
+                id: call.id.clone().unwrap_or_default(),
+                function: FunctionCall {
+                    name: call.name.clone(),
+                    arguments: call.args.to_string(),
+                },
+                ..Default::default()
+            }]),
+            citations: Vec::new(),
+            truncated: false,
+        }
+    }
+
+    fn citations(message: &Message) -> Vec<Citation> {
+        message.citations.clone()
+    }
+
+    fn truncated(message: &Message) -> bool {
+        message.truncated
+    }
+
+    fn tool_result_message(call: &ToolRequest, result: Value) -> Message {
+        let text = serde_json::to_string(&result).unwrap_or_else(|_| "null".to_string());
+        Self::tool_result_text_message(call, result, text)
+    }
+
+    fn tool_result_text_message(call: &ToolRequest, _result: Value, text: String) -> Message {
+        Message {
+            role: "tool".to_string(),
+            content: text,
+            name: None,
+            tool_call_id: call.id.clone(),
+            tool_calls: None,
+            citations: Vec::new(),
+            truncated: false,
+        }
+    }
+
+    async fn exchange(
+        &self,
+        conversation: Vec<Message>,
+    ) -> Result<(Turn, Message, Usage), RustedChainError> {
+        let (response, message, usage) = self.chat(conversation).await?;
+        let turn = prefill::ensure_prefix(self.prefill.as_deref(), response.into());
+        Ok((turn, message, usage))
+    }
+
+    async fn exchange_stream(
+        &self,
+        conversation: Vec<Message>,
+        sink: &TextSink,
+    ) -> Result<(Turn, Message, Usage), RustedChainError> {
+        let (response, message, usage) = self.chat_stream(conversation, sink).await?;
+        Ok((response.into(), message, usage))
+    }
+
+    async fn warmup(&self, prime: bool) -> Result<(), RustedChainError> {
+        OpenAI::warmup(self, prime).await
+    }
+
+    async fn multimodal(
+        &self,
+        prompt: &str,
+        files: &[Attachment],
+        schema: Option<&Value>,
+    ) -> Result<(String, Usage), RustedChainError> {
+        OpenAI::multimodal(self, prompt, files, schema).await
+    }
 
-        Err("No response generated.".to_string())
+    async fn raw(&self, payload: Value, path: Option<&str>) -> Result<Value, RustedChainError> {
+        OpenAI::raw(self, payload, path).await
     }
 }
@@ -0,0 +1,672 @@
+//! Agent-level settings shared by every provider model, parsed from `**options` kwargs.
+
+use crate::artifacts::ArtifactSettings;
+use crate::audit::AuditSink;
+use crate::balancer::{self, Balancer};
+use crate::chat_template::ChatTemplate;
+use crate::clock::CurrentDate;
+use crate::compression::Compressor;
+use crate::gemini;
+use crate::instructions::{self, ToolGuidance};
+use crate::language;
+use crate::limits::ToolLimiter;
+use crate::message_transforms::MessageTransform;
+use crate::moderation::{ModerationAction, ModerationRules};
+use crate::pii::PiiRules;
+use crate::retry::RetryPolicy;
+use crate::serialize::Serializer;
+use crate::signing::RequestSigner;
+use crate::stream::{Buffering, Overflow};
+use crate::tenant::TenantState;
+use crate::tool_args::Coercion;
+use crate::tool_cache::ToolCache;
+use crate::tool_usage::ToolLedger;
+use crate::transforms::{CodeOnly, OutputTransform};
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use serde_json::{json, Value};
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// What the agent loop does when a provider rejects the conversation as too long.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum ContextOverflow {
+    #[default]
+    Error,
+    Trim,
+}
+
+impl ContextOverflow {
+    fn parse(value: &str) -> PyResult<Self> {
+        match value {
+            "error" => Ok(Self::Error),
+            "trim" => Ok(Self::Trim),
+            other => Err(PyValueError::new_err(format!(
+                "on_context_overflow must be 'trim' or 'error', got '{}'",
+                other
+            ))),
+        }
+    }
+}
+
+/// What the agent loop does when the model keeps repeating an identical tool call.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum ToolLoopAction {
+    /// Skip the call and tell the model to use the earlier result.
+    #[default]
+    Nudge,
+    /// Stop and answer with the earlier result.
+    Return,
+    /// Raise `ToolLoopError`.
+    Raise,
+}
+
+impl ToolLoopAction {
+    fn parse(value: &str) -> PyResult<Self> {
+        match value {
+            "nudge" => Ok(Self::Nudge),
+            "return" => Ok(Self::Return),
+            "raise" => Ok(Self::Raise),
+            other => Err(PyValueError::new_err(format!(
+                "on_tool_loop must be 'nudge', 'return' or 'raise', got '{}'",
+                other
+            ))),
+        }
+    }
+}
+
+/// What `output_language` translates.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum Translate {
+    #[default]
+    Answer,
+    ToolResults,
+    Both,
+}
+
+impl Translate {
+    fn parse(value: &str) -> PyResult<Self> {
+        match value {
+            "answer" => Ok(Self::Answer),
+            "tool_results" => Ok(Self::ToolResults),
+            "both" => Ok(Self::Both),
+            other => Err(PyValueError::new_err(format!(
+                "translate must be 'answer', 'tool_results' or 'both', got '{}'",
+                other
+            ))),
+        }
+    }
+
+    pub(crate) fn answer(self) -> bool {
+        self != Self::ToolResults
+    }
+
+    pub(crate) fn tool_results(self) -> bool {
+        self != Self::Answer
+    }
+}
+
+/// Whether `transport` selects gRPC. Only builds with the `grpc` feature have it.
+fn parse_transport(value: &str) -> PyResult<bool> {
+    match value {
+        "rest" => Ok(false),
+        "grpc" if cfg!(feature = "grpc") => Ok(true),
+        "grpc" => Err(PyValueError::new_err(
+            "transport='grpc' requires rusted_chain built with the `grpc` feature",
+        )),
+        other => Err(PyValueError::new_err(format!(
+            "transport must be 'rest' or 'grpc', got '{}'",
+            other
+        ))),
+    }
+}
+
+/// A Gemini block threshold, upper-cased: `"block_only_high"` is `BLOCK_ONLY_HIGH`.
+fn parse_threshold(value: &str) -> PyResult<String> {
+    let threshold = value.to_uppercase();
+    if !gemini::BLOCK_THRESHOLDS.contains(&threshold.as_str()) {
+        return Err(PyValueError::new_err(format!(
+            "unknown safety threshold '{}' (expected one of {})",
+            value,
+            gemini::BLOCK_THRESHOLDS.join(", ")
+        )));
+    }
+    Ok(threshold)
+}
+
+/// The `safety_settings` option: one threshold for every harm category, or a dict of
+/// thresholds by category, named in full or without the `HARM_CATEGORY_` prefix.
+fn parse_safety_settings(value: &Bound<'_, PyAny>) -> PyResult<Value> {
+    let thresholds: Vec<(String, String)> = match value.extract::<String>() {
+        Ok(threshold) => gemini::HARM_CATEGORIES
+            .iter()
+            .map(|category| (category.to_string(), threshold.clone()))
+            .collect(),
+        Err(_) => value
+            .extract::<HashMap<String, String>>()
+            .map_err(|_| {
+                PyTypeError::new_err("safety_settings must be a threshold or a dict of them")
+            })?
+            .into_iter()
+            .collect(),
+    };
+    let mut settings = Vec::new();
+    for (category, threshold) in thresholds {
+        let upper = category.to_uppercase();
+        let full = match upper.starts_with("HARM_CATEGORY_") {
+            true => upper,
+            false => format!("HARM_CATEGORY_{}", upper),
+        };
+        if !gemini::HARM_CATEGORIES.contains(&full.as_str()) {
+            return Err(PyValueError::new_err(format!(
+                "unknown harm category '{}'",
+                category
+            )));
+        }
+        settings.push(json!({ "category": full, "threshold": parse_threshold(&threshold)? }));
+    }
+    settings.sort_by(|a, b| a["category"].as_str().cmp(&b["category"].as_str()));
+    Ok(Value::Array(settings))
+}
+
+#[derive(Clone)]
+pub(crate) struct AgentOptions {
+    pub(crate) max_retries: Option<u32>,
+    pub(crate) on_context_overflow: ContextOverflow,
+    pub(crate) on_tool_loop: ToolLoopAction,
+    pub(crate) tool_loop_threshold: usize,
+    pub(crate) system: Option<String>,
+    /// The agent's task, sent after `system` with tool-use guidance appended.
+    pub(crate) instructions: Option<String>,
+    pub(crate) tool_guidance: ToolGuidance,
+    /// ISO 639-1 code (or language name) the model is told to answer in.
+    pub(crate) language: Option<String>,
+    /// The current date and time, added to the system prompt of each call.
+    pub(crate) current_date: Option<CurrentDate>,
+    /// Limits registered through `add_tool`, keyed by tool name. Shared across runs.
+    pub(crate) tool_limits: HashMap<String, Arc<ToolLimiter>>,
+    /// Result caches registered through `add_tool`, keyed by tool name. Shared across runs.
+    pub(crate) tool_caches: HashMap<String, Arc<ToolCache>>,
+    pub(crate) audit: Option<Arc<AuditSink>>,
+    /// Where each finished run is exported, in the `export` schema.
+    pub(crate) run_log: Option<Arc<AuditSink>>,
+    /// Send tools with OpenAI `strict: true` unless a tool sets its own `strict`.
+    pub(crate) strict_tools: bool,
+    /// Whether tool arguments are converted to the types their schema declares.
+    pub(crate) coerce_tool_args: Coercion,
+    /// Check final answers with the moderation endpoint and local rules.
+    pub(crate) auto_moderate: Option<ModerationAction>,
+    pub(crate) moderation_rules: ModerationRules,
+    /// Text the answer is made to start with.
+    pub(crate) prefill: Option<String>,
+    /// How many times a stream that drops part way is resumed from the text received.
+    pub(crate) stream_resumes: u32,
+    /// Size of a stream's buffer of unread chunks, and what happens once it is full.
+    pub(crate) stream_buffering: Buffering,
+    /// How many more requests carry on an answer cut off at the output token limit.
+    pub(crate) max_continuations: u32,
+    /// How long the provider has to start answering a request before it is abandoned.
+    pub(crate) first_token_deadline: Option<Duration>,
+    /// Models of the same provider a request goes to in turn when the one before misses
+    /// `first_token_deadline`.
+    pub(crate) fallback_models: Vec<String>,
+    /// Sampling temperature, from 0 (most deterministic) to 2.
+    pub(crate) temperature: Option<f64>,
+    /// OpenAI token id to bias (-100 to 100) added to its logit when sampling.
+    pub(crate) logit_bias: Option<HashMap<u32, i32>>,
+    /// OpenAI's `user` and `safety_identifier` request fields, as `create_agent` and
+    /// `AgentBuilder` take them; `OpenAIModel` has arguments of its own for them.
+    pub(crate) user: Option<String>,
+    pub(crate) safety_identifier: Option<String>,
+    /// Root of an OpenAI-compatible API, such as a local vLLM or llama.cpp server.
+    pub(crate) base_url: Option<String>,
+    /// Deployments of the model that requests are spread across, in place of `base_url`.
+    pub(crate) deployments: Option<Arc<Balancer>>,
+    /// Render conversations with this template and use the completions endpoint.
+    pub(crate) chat_template: Option<Arc<ChatTemplate>>,
+    /// Talk to Gemini over gRPC (`transport="grpc"`) rather than REST.
+    pub(crate) grpc: bool,
+    /// Gemini `safetySettings`.
+    pub(crate) safety_settings: Option<Value>,
+    /// Threshold a Gemini response withheld for safety is retried once with.
+    pub(crate) retry_blocked: Option<String>,
+    /// Per-tool statistics over every run of the agent.
+    pub(crate) tool_usage: ToolLedger,
+    /// Clean-up applied to final text answers.
+    pub(crate) output_transform: Option<OutputTransform>,
+    /// Rewrites applied to the messages of every request.
+    pub(crate) message_transform: Option<Arc<MessageTransform>>,
+    /// Adds headers to every provider request right before it is sent.
+    pub(crate) request_signer: Option<RequestSigner>,
+    /// Set per call: the key the call's provider requests derive theirs from.
+    pub(crate) idempotency_key: Option<String>,
+    /// Ask for answers that are only a code block, and send back ones with prose.
+    pub(crate) code_only: Option<CodeOnly>,
+    /// A cheaper model of the same provider for a run's first request, which picks
+    /// tools; the agent's own model takes the tool results and writes the answer.
+    pub(crate) tool_model: Option<String>,
+    /// ISO 639-1 code (or language name) answers or tool results are translated into.
+    pub(crate) output_language: Option<String>,
+    pub(crate) translate: Translate,
+    /// A cheaper model of the same provider that does the translating; the agent's own
+    /// model by default.
+    pub(crate) translation_model: Option<String>,
+    /// Shortens long queries and tool results before they are sent.
+    pub(crate) compression: Option<Arc<Compressor>>,
+    /// Personal data to keep out of what is sent to the provider.
+    pub(crate) pii: Option<Arc<PiiRules>>,
+    /// How tool results are turned into JSON, when not the default way.
+    pub(crate) tool_serializer: Option<Arc<Serializer>>,
+    /// Where binary and oversized tool results are stored instead of being sent.
+    pub(crate) artifacts: ArtifactSettings,
+    /// The tenant a call was made for, set per call rather than by a keyword.
+    pub(crate) tenant: Option<Arc<TenantState>>,
+}
+
+impl Default for AgentOptions {
+    fn default() -> Self {
+        Self {
+            max_retries: None,
+            on_context_overflow: ContextOverflow::default(),
+            on_tool_loop: ToolLoopAction::default(),
+            tool_loop_threshold: 3,
+            system: None,
+            instructions: None,
+            tool_guidance: ToolGuidance::Default,
+            language: None,
+            current_date: None,
+            tool_limits: HashMap::new(),
+            tool_caches: HashMap::new(),
+            audit: None,
+            run_log: None,
+            strict_tools: false,
+            coerce_tool_args: Coercion::default(),
+            auto_moderate: None,
+            moderation_rules: ModerationRules::default(),
+            prefill: None,
+            stream_resumes: 0,
+            stream_buffering: Buffering::default(),
+            max_continuations: 0,
+            first_token_deadline: None,
+            fallback_models: Vec::new(),
+            temperature: None,
+            logit_bias: None,
+            user: None,
+            safety_identifier: None,
+            base_url: None,
+            deployments: None,
+            chat_template: None,
+            grpc: false,
+            safety_settings: None,
+            retry_blocked: None,
+            tool_usage: ToolLedger::default(),
+            output_transform: None,
+            message_transform: None,
+            request_signer: None,
+            idempotency_key: None,
+            code_only: None,
+            tool_model: None,
+            output_language: None,
+            translate: Translate::default(),
+            translation_model: None,
+            compression: None,
+            pii: None,
+            tool_serializer: None,
+            artifacts: ArtifactSettings::default(),
+            tenant: None,
+        }
+    }
+}
+
+impl AgentOptions {
+    /// `options` with a per-call `idempotency_key` applied, if one was given.
+    pub(crate) fn with_idempotency_key(
+        options: Cow<'_, Self>,
+        idempotency_key: Option<String>,
+    ) -> Cow<'_, Self> {
+        match idempotency_key {
+            Some(key) => {
+                let mut options = options.into_owned();
+                options.idempotency_key = Some(key);
+                Cow::Owned(options)
+            }
+            None => options,
+        }
+    }
+
+    /// `options` with a per-call `tool_model` applied, if one was given.
+    pub(crate) fn with_tool_model(
+        options: Cow<'_, Self>,
+        tool_model: Option<String>,
+    ) -> Cow<'_, Self> {
+        match tool_model {
+            Some(model) => {
+                let mut options = options.into_owned();
+                options.tool_model = Some(model);
+                Cow::Owned(options)
+            }
+            None => options,
+        }
+    }
+
+    pub(crate) fn from_kwargs(kwargs: Option<&Bound<'_, PyDict>>) -> PyResult<Self> {
+        let mut options = Self::default();
+        let Some(kwargs) = kwargs else {
+            return Ok(options);
+        };
+        for (key, value) in kwargs.iter() {
+            let key: String = key.extract()?;
+            match key.as_str() {
+                "max_retries" => options.max_retries = Some(value.extract()?),
+                "stream_resumes" => options.stream_resumes = value.extract()?,
+                "stream_buffer" => {
+                    let capacity: usize = value.extract()?;
+                    if capacity == 0 {
+                        return Err(PyValueError::new_err("stream_buffer must be at least 1"));
+                    }
+                    options.stream_buffering.capacity = capacity;
+                }
+                "on_stream_overflow" => {
+                    options.stream_buffering.overflow =
+                        Overflow::parse(&value.extract::<String>()?)?
+                }
+                "max_continuations" => options.max_continuations = value.extract()?,
+                "first_token_deadline_ms" => {
+                    options.first_token_deadline =
+                        value.extract::<Option<u64>>()?.map(Duration::from_millis)
+                }
+                "fallback_models" => options.fallback_models = value.extract()?,
+                "on_context_overflow" => {
+                    options.on_context_overflow =
+                        ContextOverflow::parse(&value.extract::<String>()?)?
+                }
+                "on_tool_loop" => {
+                    options.on_tool_loop = ToolLoopAction::parse(&value.extract::<String>()?)?
+                }
+                "tool_loop_threshold" => {
+                    let threshold: usize = value.extract()?;
+                    if threshold < 2 {
+                        return Err(PyValueError::new_err(
+                            "tool_loop_threshold must be at least 2",
+                        ));
+                    }
+                    options.tool_loop_threshold = threshold;
+                }
+                "system" => options.system = Some(value.extract()?),
+                "instructions" => options.instructions = value.extract()?,
+                "tool_guidance" => options.tool_guidance = ToolGuidance::parse(&value)?,
+                "language" => options.language = Some(value.extract()?),
+                "tool_model" => options.tool_model = Some(value.extract()?),
+                "output_language" => options.output_language = Some(value.extract()?),
+                "translate" => options.translate = Translate::parse(&value.extract::<String>()?)?,
+                "translation_model" => options.translation_model = Some(value.extract()?),
+                "prefill" => options.prefill = value.extract()?,
+                "temperature" => {
+                    options.temperature = value.extract()?;
+                    if options
+                        .temperature
+                        .is_some_and(|t| !(0.0..=2.0).contains(&t))
+                    {
+                        return Err(PyValueError::new_err("temperature must be between 0 and 2"));
+                    }
+                }
+                "logit_bias" => {
+                    let bias: HashMap<u32, i32> = value.extract()?;
+                    if bias.values().any(|b| !(-100..=100).contains(b)) {
+                        return Err(PyValueError::new_err(
+                            "logit_bias values must be between -100 and 100",
+                        ));
+                    }
+                    options.logit_bias = Some(bias);
+                }
+                "user" => options.user = value.extract()?,
+                "safety_identifier" => options.safety_identifier = value.extract()?,
+                "base_url" => options.base_url = value.extract()?,
+                "deployments" => {
+                    options.deployments = if value.is_none() {
+                        None
+                    } else {
+                        Some(balancer::from_py(&value)?)
+                    }
+                }
+                "chat_template" => {
+                    options.chat_template = if value.is_none() {
+                        None
+                    } else {
+                        Some(Arc::new(ChatTemplate::from_py(&value)?))
+                    }
+                }
+                "output_transform" => {
+                    options.output_transform = if value.is_none() {
+                        None
+                    } else {
+                        Some(OutputTransform::from_py(&value)?)
+                    }
+                }
+                "message_transform" => {
+                    options.message_transform = if value.is_none() {
+                        None
+                    } else {
+                        Some(Arc::new(MessageTransform::from_py(&value)?))
+                    }
+                }
+                "request_signer" => {
+                    options.request_signer = if value.is_none() {
+                        None
+                    } else {
+                        Some(RequestSigner::from_py(&value)?)
+                    }
+                }
+                "code_only" => options.code_only = CodeOnly::from_py(&value)?,
+                "transport" => options.grpc = parse_transport(&value.extract::<String>()?)?,
+                "safety_settings" => {
+                    options.safety_settings = if value.is_none() {
+                        None
+                    } else {
+                        Some(parse_safety_settings(&value)?)
+                    }
+                }
+                "retry_blocked" => {
+                    options.retry_blocked = match value.extract::<bool>() {
+                        Ok(true) => Some("BLOCK_ONLY_HIGH".to_string()),
+                        Ok(false) => None,
+                        Err(_) => Some(parse_threshold(&value.extract::<String>()?)?),
+                    }
+                }
+                "strict_tools" => options.strict_tools = value.extract()?,
+                "coerce_tool_args" => {
+                    options.coerce_tool_args = Coercion::parse(&value.extract::<String>()?)?
+                }
+                "auto_moderate" => options.auto_moderate = ModerationAction::parse(&value)?,
+                "moderation_keywords" => options.moderation_rules.set_keywords(value.extract()?),
+                "moderation_patterns" => options
+                    .moderation_rules
+                    .set_patterns(&value.extract::<Vec<String>>()?)?,
+                "audit_log" => options.audit = Some(Arc::new(AuditSink::from_py(&value)?)),
+                "compress_prompt" => options.compression = Some(Compressor::from_py(&value)?),
+                "redact_pii" => options.pii = PiiRules::from_py(&value)?,
+                "artifact_dir" => options.artifacts.dir = value.extract()?,
+                "artifact_threshold" => options.artifacts.threshold = value.extract()?,
+                "tool_serializer" => options.tool_serializer = Some(Serializer::from_py(&value)?),
+                "run_log" => options.run_log = Some(Arc::new(AuditSink::from_py(&value)?)),
+                "current_date" => {
+                    options.current_date = CurrentDate::from_py(
+                        &value,
+                        kwargs.get_item("date_timezone")?.as_ref(),
+                        kwargs.get_item("clock")?.as_ref(),
+                    )?
+                }
+                // Read along with current_date.
+                "date_timezone" | "clock" => {}
+                other => {
+                    return Err(PyTypeError::new_err(format!(
+                        "unexpected keyword argument '{}'",
+                        other
+                    )))
+                }
+            }
+        }
+        let given = |key: &str| -> PyResult<bool> {
+            Ok(kwargs.get_item(key)?.is_some_and(|value| !value.is_none()))
+        };
+        if (given("date_timezone")? || given("clock")?) && options.current_date.is_none() {
+            return Err(PyValueError::new_err(
+                "date_timezone and clock apply to current_date; pass current_date=True as well",
+            ));
+        }
+        if kwargs.contains("tool_guidance")? && options.instructions.is_none() {
+            return Err(PyValueError::new_err(
+                "tool_guidance applies to instructions; pass instructions as well",
+            ));
+        }
+        if (given("translate")? || given("translation_model")?) && options.output_language.is_none()
+        {
+            return Err(PyValueError::new_err(
+                "translate and translation_model apply to output_language; pass output_language as well",
+            ));
+        }
+        if !options.fallback_models.is_empty() && options.first_token_deadline.is_none() {
+            return Err(PyValueError::new_err(
+                "fallback_models apply to first_token_deadline_ms; pass first_token_deadline_ms as well",
+            ));
+        }
+        Ok(options)
+    }
+
+    /// Reject options that `provider`'s models cannot honour.
+    pub(crate) fn check_provider(&self, provider: &str) -> PyResult<()> {
+        if self.logit_bias.is_some() && provider != "openai" {
+            return Err(PyValueError::new_err(
+                "logit_bias is only supported by OpenAI models",
+            ));
+        }
+        if self.temperature.is_some_and(|t| t > 1.0) && provider == "anthropic" {
+            return Err(PyValueError::new_err(
+                "temperature must be between 0 and 1 for Claude models",
+            ));
+        }
+        if (self.user.is_some() || self.safety_identifier.is_some()) && provider != "openai" {
+            return Err(PyValueError::new_err(
+                "user and safety_identifier are only supported by OpenAI models",
+            ));
+        }
+        if self.base_url.is_some() && provider != "openai" {
+            return Err(PyValueError::new_err(
+                "base_url is only supported by OpenAI models",
+            ));
+        }
+        if self.deployments.is_some() && provider != "openai" {
+            return Err(PyValueError::new_err(
+                "deployments are only supported by OpenAI models",
+            ));
+        }
+        if self.chat_template.is_some() && !["openai", "huggingface"].contains(&provider) {
+            return Err(PyValueError::new_err(
+                "chat_template is only supported by OpenAI and HuggingFace models",
+            ));
+        }
+        if (self.safety_settings.is_some() || self.retry_blocked.is_some()) && provider != "gemini"
+        {
+            return Err(PyValueError::new_err(
+                "safety_settings and retry_blocked are only supported by Gemini models",
+            ));
+        }
+        if self.grpc && provider != "gemini" {
+            return Err(PyValueError::new_err(
+                "transport='grpc' is only supported by Gemini models",
+            ));
+        }
+        if self.grpc && self.message_transform.is_some() {
+            return Err(PyValueError::new_err(
+                "message_transform is not supported with transport='grpc'",
+            ));
+        }
+        if self.grpc && self.request_signer.is_some() {
+            return Err(PyValueError::new_err(
+                "request_signer is not supported with transport='grpc'",
+            ));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn retry_policy(&self) -> RetryPolicy {
+        let mut policy = RetryPolicy::default();
+        if let Some(max_retries) = self.max_retries {
+            policy.max_retries = max_retries;
+        }
+        policy
+    }
+
+    /// Register concurrency and rate limits for `tool`, replacing any earlier ones.
+    pub(crate) fn limit_tool(
+        &mut self,
+        tool: &Bound<'_, PyAny>,
+        max_concurrency: Option<usize>,
+        rate_limit_per_min: Option<usize>,
+    ) -> PyResult<()> {
+        if max_concurrency == Some(0) || rate_limit_per_min == Some(0) {
+            return Err(PyValueError::new_err(
+                "max_concurrency and rate_limit_per_min must be at least 1",
+            ));
+        }
+        let name: String = tool.getattr("__name__")?.extract()?;
+        if max_concurrency.is_none() && rate_limit_per_min.is_none() {
+            self.tool_limits.remove(&name);
+        } else {
+            self.tool_limits.insert(
+                name,
+                Arc::new(ToolLimiter::new(max_concurrency, rate_limit_per_min)),
+            );
+        }
+        Ok(())
+    }
+
+    /// Cache `tool`'s results for `cache_ttl` seconds, replacing any earlier cache, or
+    /// with `None` stop caching them.
+    pub(crate) fn cache_tool(
+        &mut self,
+        tool: &Bound<'_, PyAny>,
+        cache_ttl: Option<f64>,
+    ) -> PyResult<()> {
+        let name: String = tool.getattr("__name__")?.extract()?;
+        match cache_ttl {
+            None => {
+                self.tool_caches.remove(&name);
+            }
+            Some(ttl) => {
+                let ttl = Duration::try_from_secs_f64(ttl)
+                    .ok()
+                    .filter(|ttl| !ttl.is_zero())
+                    .ok_or_else(|| {
+                        PyValueError::new_err(format!(
+                            "cache_ttl must be a positive number of seconds, got {}",
+                            ttl
+                        ))
+                    })?;
+                self.tool_caches.insert(name, Arc::new(ToolCache::new(ttl)));
+            }
+        }
+        Ok(())
+    }
+
+    /// The system prompt sent to `provider`: `system`, then `instructions` with their
+    /// tool-use guidance, then the language hint, the `code_only` instruction and the
+    /// current date.
+    pub(crate) fn system_prompt(&self, provider: &str, has_tools: bool) -> Option<String> {
+        let instructions = self
+            .instructions
+            .as_deref()
+            .map(|text| instructions::render(text, &self.tool_guidance, provider, has_tools));
+        let language = self.language.as_deref().map(language::language_instruction);
+        let code_only = self.code_only.as_ref().map(CodeOnly::instruction);
+        let date = self.current_date.as_ref().map(CurrentDate::instruction);
+        let sections: Vec<String> = [self.system.clone(), instructions, language, code_only, date]
+            .into_iter()
+            .flatten()
+            .collect();
+        (!sections.is_empty()).then(|| sections.join("\n\n"))
+    }
+}
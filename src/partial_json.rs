@@ -0,0 +1,209 @@
+//! Best-effort parsing of JSON documents that are still being streamed.
+
+use crate::stream::TextStream;
+use pyo3::prelude::*;
+use serde_json::Value;
+
+/// Parse the longest complete prefix of a JSON document that is still arriving.
+///
+/// Only finished fields are kept: a half-written string, number or key is dropped, and
+/// any containers left open are closed. Text before the first `{` or `[` (for example a
+/// Markdown code fence) is ignored. Returns `None` until the document has started.
+pub(crate) fn parse_partial(text: &str) -> Option<Value> {
+    let start = text.find(['{', '['])?;
+    let bytes = &text.as_bytes()[start..];
+
+    // Open containers as (closing byte, object currently expects a key).
+    let mut stack: Vec<(u8, bool)> = Vec::new();
+    // Last position where the prefix ends on a value boundary, with the closers needed there.
+    let mut safe: Option<(usize, Vec<u8>)> = None;
+    let mut in_string = false;
+    let mut string_is_key = false;
+    let mut escaped = false;
+    let mut scalar_pending = false;
+
+    let closers = |stack: &[(u8, bool)]| stack.iter().rev().map(|(c, _)| *c).collect();
+
+    for (i, &b) in bytes.iter().enumerate() {
+        if in_string {
+            match b {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => {
+                    in_string = false;
+                    if !string_is_key {
+                        safe = Some((i + 1, closers(&stack)));
+                    }
+                }
+                _ => {}
+            }
+            continue;
+        }
+
+        let ends_scalar = matches!(b, b',' | b'}' | b']') || b.is_ascii_whitespace();
+        if scalar_pending && ends_scalar {
+            scalar_pending = false;
+            safe = Some((i, closers(&stack)));
+        }
+
+        match b {
+            b'{' | b'[' => {
+                stack.push((if b == b'{' { b'}' } else { b']' }, b == b'{'));
+                safe = Some((i + 1, closers(&stack)));
+            }
+            b'}' | b']' => {
+                stack.pop();
+                if stack.is_empty() {
+                    return serde_json::from_slice(&bytes[..=i]).ok();
+                }
+                safe = Some((i + 1, closers(&stack)));
+            }
+            b'"' => {
+                in_string = true;
+                string_is_key = matches!(stack.last(), Some((b'}', true)));
+            }
+            b':' => {
+                if let Some(top) = stack.last_mut() {
+                    top.1 = false;
+                }
+            }
+            b',' => {
+                if let Some(top) = stack.last_mut() {
+                    top.1 = top.0 == b'}';
+                }
+            }
+            _ if b.is_ascii_whitespace() => {}
+            _ => scalar_pending = true,
+        }
+    }
+
+    let (end, closers) = safe?;
+    let mut candidate = String::from_utf8_lossy(&bytes[..end])
+        .trim_end()
+        .trim_end_matches(',')
+        .to_string();
+    candidate.extend(closers.into_iter().map(char::from));
+    serde_json::from_str(&candidate).ok()
+}
+
+/// Parse a partially streamed JSON document into Python objects, or `None` if nothing
+/// parseable has arrived yet.
+#[pyfunction]
+pub fn parse_partial_json(py: Python, text: &str) -> PyResult<Py<PyAny>> {
+    match parse_partial(text) {
+        Some(value) => Ok(pythonize::pythonize(py, &value)?.unbind()),
+        None => Ok(py.None()),
+    }
+}
+
+/// Iterator yielding progressively more complete objects parsed from a text stream.
+#[pyclass]
+pub struct JsonStream {
+    inner: Py<TextStream>,
+    buffer: String,
+    last: Option<Value>,
+}
+
+impl JsonStream {
+    pub(crate) fn new(inner: Py<TextStream>) -> Self {
+        Self {
+            inner,
+            buffer: String::new(),
+            last: None,
+        }
+    }
+}
+
+#[pymethods]
+impl JsonStream {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python) -> PyResult<Option<Py<PyAny>>> {
+        loop {
+            let chunk = self.inner.bind(py).borrow().next_chunk(py)?;
+            let Some(chunk) = chunk else {
+                return Ok(None);
+            };
+            self.buffer.push_str(&chunk);
+            let Some(value) = parse_partial(&self.buffer) else {
+                continue;
+            };
+            if self.last.as_ref() != Some(&value) {
+                let object = pythonize::pythonize(py, &value)?.unbind();
+                self.last = Some(value);
+                return Ok(Some(object));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn a_half_written_string_value_is_dropped() {
+        assert_eq!(
+            parse_partial(r#"{"a": 1, "b": "hel"#),
+            Some(json!({"a": 1}))
+        );
+    }
+
+    #[test]
+    fn a_half_written_key_is_dropped() {
+        assert_eq!(parse_partial(r#"{"a": "x", "bo"#), Some(json!({"a": "x"})));
+        assert_eq!(
+            parse_partial(r#"{"a": "x", "b": "#),
+            Some(json!({"a": "x"}))
+        );
+    }
+
+    #[test]
+    fn a_trailing_comma_is_dropped() {
+        assert_eq!(
+            parse_partial(r#"{"a": [1, 2],"#),
+            Some(json!({"a": [1, 2]}))
+        );
+        assert_eq!(parse_partial("[1, 2, "), Some(json!([1, 2])));
+    }
+
+    #[test]
+    fn an_escaped_quote_does_not_end_a_string() {
+        assert_eq!(
+            parse_partial(r#"{"a": "say \"hi\"", "b": "x"#),
+            Some(json!({"a": "say \"hi\""}))
+        );
+        assert_eq!(parse_partial(r#"{"a": "say \"hi"#), Some(json!({})));
+    }
+
+    #[test]
+    fn a_truncated_number_is_dropped() {
+        assert_eq!(parse_partial(r#"{"a": 12"#), Some(json!({})));
+        assert_eq!(parse_partial(r#"{"a": 1, "b": 12."#), Some(json!({"a": 1})));
+        assert_eq!(parse_partial("[1, 2, 3"), Some(json!([1, 2])));
+    }
+
+    #[test]
+    fn nested_arrays_are_closed() {
+        assert_eq!(
+            parse_partial("[[1, 2], [3, [4"),
+            Some(json!([[1, 2], [3, []]]))
+        );
+    }
+
+    #[test]
+    fn a_leading_code_fence_is_ignored() {
+        assert_eq!(parse_partial("```json\n"), None);
+        assert_eq!(
+            parse_partial("```json\n{\"a\": 1}\n```"),
+            Some(json!({"a": 1}))
+        );
+        assert_eq!(
+            parse_partial("```json\n{\"a\": 1, \"b\""),
+            Some(json!({"a": 1}))
+        );
+    }
+}
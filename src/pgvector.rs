@@ -0,0 +1,163 @@
+//! PostgreSQL vector store using the pgvector extension, built with the `pgvector`
+//! feature.
+
+use crate::error::RustedChainError;
+use crate::vector_store::{Record, VectorStore};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use serde_json::{Map, Value};
+use sqlx::postgres::{PgPool, PgPoolOptions};
+use sqlx::{AssertSqlSafe, Row};
+use tokio::sync::OnceCell;
+
+impl From<sqlx::Error> for RustedChainError {
+    fn from(err: sqlx::Error) -> Self {
+        RustedChainError::Store(err.to_string())
+    }
+}
+
+/// pgvector's text input format, e.g. `[0.1,0.2]`.
+fn literal(vector: &[f32]) -> String {
+    let values: Vec<String> = vector.iter().map(f32::to_string).collect();
+    format!("[{}]", values.join(","))
+}
+
+fn parse_literal(text: &str) -> Vec<f32> {
+    text.trim_matches(|c| c == '[' || c == ']')
+        .split(',')
+        .filter_map(|v| v.trim().parse().ok())
+        .collect()
+}
+
+pub(crate) struct PgVector {
+    pool: PgPool,
+    table: String,
+    /// Set once the extension and table are known to exist.
+    ready: OnceCell<()>,
+}
+
+impl PgVector {
+    pub(crate) fn new(url: &str, table: &str) -> PyResult<Self> {
+        // The name is interpolated into SQL, so only allow plain identifiers.
+        if table.is_empty() || !table.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(PyValueError::new_err(format!(
+                "table must be a plain identifier, got '{}'",
+                table
+            )));
+        }
+        // Connections open lazily, inside the runtime, on first use.
+        let _guard = crate::RUNTIME.enter();
+        let pool = PgPoolOptions::new()
+            .connect_lazy(url)
+            .map_err(|e| PyValueError::new_err(e.to_string()))?;
+        Ok(PgVector {
+            pool,
+            table: table.to_string(),
+            ready: OnceCell::new(),
+        })
+    }
+
+    /// `statement` with `{table}` replaced by the table name, which `new` validated.
+    fn sql(&self, statement: &str) -> AssertSqlSafe<String> {
+        AssertSqlSafe(statement.replace("{table}", &self.table))
+    }
+
+    async fn ensure_table(&self, dimensions: usize) -> Result<(), RustedChainError> {
+        self.ready
+            .get_or_try_init(|| async {
+                sqlx::query("CREATE EXTENSION IF NOT EXISTS vector")
+                    .execute(&self.pool)
+                    .await?;
+                sqlx::query(self.sql(&format!(
+                    "CREATE TABLE IF NOT EXISTS {{table}} (\
+                     id TEXT PRIMARY KEY, \
+                     text TEXT NOT NULL, \
+                     metadata JSONB NOT NULL, \
+                     embedding vector({}) NOT NULL)",
+                    dimensions
+                )))
+                .execute(&self.pool)
+                .await?;
+                Ok::<_, RustedChainError>(())
+            })
+            .await?;
+        Ok(())
+    }
+
+    async fn table_exists(&self) -> Result<bool, RustedChainError> {
+        let row = sqlx::query("SELECT to_regclass($1) IS NOT NULL")
+            .bind(&self.table)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(row.try_get(0)?)
+    }
+}
+
+impl VectorStore for PgVector {
+    async fn upsert(&self, records: Vec<Record>) -> Result<(), RustedChainError> {
+        let Some(first) = records.first() else {
+            return Ok(());
+        };
+        self.ensure_table(first.vector.len()).await?;
+        let statement = "INSERT INTO {table} (id, text, metadata, embedding) \
+             VALUES ($1, $2, $3, $4::vector) \
+             ON CONFLICT (id) DO UPDATE SET \
+             text = EXCLUDED.text, metadata = EXCLUDED.metadata, embedding = EXCLUDED.embedding";
+        let mut transaction = self.pool.begin().await?;
+        for record in records {
+            sqlx::query(self.sql(statement))
+                .bind(record.id)
+                .bind(record.text)
+                .bind(Value::Object(record.metadata))
+                .bind(literal(&record.vector))
+                .execute(&mut *transaction)
+                .await?;
+        }
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    async fn search(
+        &self,
+        vector: &[f32],
+        k: usize,
+    ) -> Result<Vec<(Record, f32)>, RustedChainError> {
+        if self.ready.get().is_none() && !self.table_exists().await? {
+            return Ok(Vec::new());
+        }
+        let rows = sqlx::query(self.sql(
+            "SELECT id, text, metadata, embedding::text, 1 - (embedding <=> $1::vector) \
+             FROM {table} ORDER BY embedding <=> $1::vector LIMIT $2",
+        ))
+        .bind(literal(vector))
+        .bind(k as i64)
+        .fetch_all(&self.pool)
+        .await?;
+        rows.into_iter()
+            .map(|row| {
+                let metadata = match row.try_get::<Value, _>(2)? {
+                    Value::Object(metadata) => metadata,
+                    _ => Map::new(),
+                };
+                let record = Record {
+                    id: row.try_get(0)?,
+                    text: row.try_get(1)?,
+                    metadata,
+                    vector: parse_literal(row.try_get(3)?),
+                };
+                Ok((record, row.try_get::<f64, _>(4)? as f32))
+            })
+            .collect()
+    }
+
+    async fn delete(&self, ids: &[String]) -> Result<(), RustedChainError> {
+        if self.ready.get().is_none() && !self.table_exists().await? {
+            return Ok(());
+        }
+        sqlx::query(self.sql("DELETE FROM {table} WHERE id = ANY($1)"))
+            .bind(ids)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
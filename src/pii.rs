@@ -0,0 +1,335 @@
+//! Redaction of personal data from what is sent to providers. Each value found is
+//! replaced by a placeholder such as `[EMAIL_1]`, the same one every time it appears in
+//! a run, and the mapping is kept so the answer and tool arguments can get the
+//! original back.
+
+use crate::agent::ToolRequest;
+use crate::session::Entry;
+use once_cell::sync::Lazy;
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use regex::Regex;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+const ENTITIES: &[&str] = &["email", "credit_card", "ssn", "phone", "ip_address"];
+const DEFAULT_ENTITIES: &[&str] = &["email", "credit_card", "phone"];
+
+static PLACEHOLDER: Lazy<Regex> = Lazy::new(|| Regex::new(r"\[[A-Z0-9_]+_\d+\]").unwrap());
+
+/// The longest text a placeholder split across stream chunks is held back for.
+const MAX_PLACEHOLDER: usize = 48;
+
+fn builtin(entity: &str) -> Regex {
+    let pattern = match entity {
+        "email" => r"[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)*\.[A-Za-z]{2,}",
+        "credit_card" => r"\b\d(?:[ -]?\d){12,18}\b",
+        "ssn" => r"\b\d{3}-\d{2}-\d{4}\b",
+        "phone" => r"(?:\+\d{1,3}[\s.-]?)?(?:\(\d{1,4}\)|\d{2,4})[\s.-]?\d{3,4}[\s.-]?\d{3,4}\b",
+        _ => r"\b(?:\d{1,3}\.){3}\d{1,3}\b",
+    };
+    Regex::new(pattern).unwrap()
+}
+
+/// Whether a match is the entity and not just text of the same shape.
+fn plausible(entity: &str, text: &str, before: &str, after: &str) -> bool {
+    let digits: Vec<u32> = text.chars().filter_map(|c| c.to_digit(10)).collect();
+    match entity {
+        "credit_card" => luhn(&digits),
+        // Not part of a longer number, and as long as a phone number can be.
+        "phone" => {
+            !continues_number(before.chars().rev())
+                && !continues_number(after.chars())
+                && (7..=15).contains(&digits.len())
+        }
+        "ip_address" => text.split('.').all(|octet| octet.parse::<u8>().is_ok()),
+        _ => true,
+    }
+}
+
+/// Whether text next to a match, read away from it, is a digit or a separated digit.
+fn continues_number(mut chars: impl Iterator<Item = char>) -> bool {
+    match chars.next() {
+        Some(c) if c.is_ascii_digit() => true,
+        Some('-' | '.') => chars.next().is_some_and(|c| c.is_ascii_digit()),
+        _ => false,
+    }
+}
+
+fn luhn(digits: &[u32]) -> bool {
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(index, &digit)| match index % 2 {
+            0 => digit,
+            _ if digit > 4 => digit * 2 - 9,
+            _ => digit * 2,
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+/// What to redact: `(entity, pattern)` in the order they are applied.
+pub(crate) struct PiiRules {
+    rules: Vec<(String, Regex)>,
+    restore: bool,
+}
+
+/// Which personal data is kept out of prompts. `entities` picks built-in detectors:
+/// `"email"`, `"credit_card"` (Luhn-checked), `"ssn"`, `"phone"` and `"ip_address"`;
+/// `patterns` maps further entity names to regexes, applied first. With `restore`,
+/// placeholders in the answer are replaced by the original values. Tools always get
+/// the original values.
+#[pyclass(frozen)]
+pub struct PiiPolicy {
+    pub(crate) rules: Arc<PiiRules>,
+}
+
+#[pymethods]
+impl PiiPolicy {
+    #[new]
+    #[pyo3(signature = (entities=None, patterns=None, restore=true))]
+    fn new(
+        entities: Option<Vec<String>>,
+        patterns: Option<HashMap<String, String>>,
+        restore: bool,
+    ) -> PyResult<Self> {
+        let mut rules = Vec::new();
+        let mut patterns: Vec<(String, String)> =
+            patterns.unwrap_or_default().into_iter().collect();
+        patterns.sort();
+        for (name, pattern) in patterns {
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+                return Err(PyValueError::new_err(format!(
+                    "entity names must be letters, digits and underscores, got '{}'",
+                    name
+                )));
+            }
+            let regex = Regex::new(&pattern).map_err(|e| {
+                PyValueError::new_err(format!("invalid pattern '{}': {}", pattern, e))
+            })?;
+            rules.push((name, regex));
+        }
+        let entities =
+            entities.unwrap_or_else(|| DEFAULT_ENTITIES.iter().map(|e| e.to_string()).collect());
+        if let Some(unknown) = entities.iter().find(|e| !ENTITIES.contains(&e.as_str())) {
+            return Err(PyValueError::new_err(format!(
+                "unknown entity '{}', expected one of {}",
+                unknown,
+                ENTITIES.join(", ")
+            )));
+        }
+        // Built-ins in a fixed order, so card numbers are not taken for phone numbers.
+        for entity in ENTITIES.iter().filter(|e| entities.iter().any(|x| x == *e)) {
+            rules.push((entity.to_string(), builtin(entity)));
+        }
+        Ok(PiiPolicy {
+            rules: Arc::new(PiiRules { rules, restore }),
+        })
+    }
+
+    #[getter]
+    fn entities(&self) -> Vec<String> {
+        self.rules
+            .rules
+            .iter()
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    #[getter]
+    fn restore(&self) -> bool {
+        self.rules.restore
+    }
+
+    /// `text` with the policy applied, and the placeholders mapped to what they
+    /// replace; for checking a policy against sample data.
+    fn redact(&self, text: &str) -> (String, HashMap<String, String>) {
+        let redactor = Redactor::new(self.rules.clone());
+        let redacted = redactor.redact(text);
+        let mapping = redactor.state().originals.clone();
+        (redacted, mapping)
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "PiiPolicy(entities=[{}], restore={})",
+            self.entities().join(", "),
+            if self.rules.restore { "True" } else { "False" }
+        )
+    }
+}
+
+impl PiiRules {
+    /// The `redact_pii` option: `True` for the default entities, a list of entities,
+    /// or a `PiiPolicy`.
+    pub(crate) fn from_py(value: &Bound<'_, PyAny>) -> PyResult<Option<Arc<Self>>> {
+        if let Ok(policy) = value.cast::<PiiPolicy>() {
+            return Ok(Some(policy.get().rules.clone()));
+        }
+        if let Ok(enabled) = value.extract::<bool>() {
+            if !enabled {
+                return Ok(None);
+            }
+            return PiiPolicy::new(None, None, true).map(|policy| Some(policy.rules));
+        }
+        match value.extract::<Vec<String>>() {
+            Ok(entities) => Ok(Some(PiiPolicy::new(Some(entities), None, true)?.rules)),
+            Err(_) => Err(PyTypeError::new_err(
+                "redact_pii must be True, a list of entities or a PiiPolicy",
+            )),
+        }
+    }
+}
+
+#[derive(Default)]
+struct State {
+    /// Placeholder per original value, and back.
+    placeholders: HashMap<String, String>,
+    originals: HashMap<String, String>,
+    counts: HashMap<String, usize>,
+}
+
+/// One run's redactions. Shared with the stream sink, which restores as it forwards.
+pub(crate) struct Redactor {
+    rules: Arc<PiiRules>,
+    state: Mutex<State>,
+}
+
+impl Redactor {
+    pub(crate) fn new(rules: Arc<PiiRules>) -> Self {
+        Self {
+            rules,
+            state: Mutex::new(State::default()),
+        }
+    }
+
+    fn state(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Whether the answer gets the original values back.
+    pub(crate) fn restores(&self) -> bool {
+        self.rules.restore
+    }
+
+    pub(crate) fn redact(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        let mut state = self.state();
+        for (entity, regex) in &self.rules.rules {
+            let mut out = String::with_capacity(text.len());
+            let mut last = 0;
+            for found in regex.find_iter(&text) {
+                let (before, after) = (&text[..found.start()], &text[found.end()..]);
+                if !plausible(entity, found.as_str(), before, after) {
+                    continue;
+                }
+                out.push_str(&text[last..found.start()]);
+                out.push_str(&state.placeholder(entity, found.as_str()));
+                last = found.end();
+            }
+            out.push_str(&text[last..]);
+            text = out;
+        }
+        text
+    }
+
+    /// `value` with every string in it redacted.
+    pub(crate) fn redact_value(&self, value: &Value) -> Value {
+        map_strings(value, &|text| self.redact(text))
+    }
+
+    pub(crate) fn restore(&self, text: &str) -> String {
+        let state = self.state();
+        PLACEHOLDER
+            .replace_all(text, |found: &regex::Captures| {
+                let placeholder = &found[0];
+                state
+                    .originals
+                    .get(placeholder)
+                    .cloned()
+                    .unwrap_or_else(|| placeholder.to_string())
+            })
+            .into_owned()
+    }
+
+    pub(crate) fn restore_value(&self, value: &Value) -> Value {
+        map_strings(value, &|text| self.restore(text))
+    }
+
+    /// A tool call with its arguments restored, for running the tool.
+    pub(crate) fn restore_call(&self, call: &ToolRequest) -> ToolRequest {
+        ToolRequest {
+            id: call.id.clone(),
+            name: call.name.clone(),
+            args: self.restore_value(&call.args),
+        }
+    }
+
+    /// A history entry as it is sent.
+    pub(crate) fn redact_entry(&self, entry: &Entry) -> Entry {
+        match entry {
+            Entry::User(text) => Entry::User(self.redact(text)),
+            Entry::Assistant(text) => Entry::Assistant(self.redact(text)),
+            Entry::ToolCall(call) => Entry::ToolCall(self.redact_call(call)),
+            Entry::ToolResult { call, result } => Entry::ToolResult {
+                call: self.redact_call(call),
+                result: self.redact_value(result),
+            },
+        }
+    }
+
+    fn redact_call(&self, call: &ToolRequest) -> ToolRequest {
+        ToolRequest {
+            id: call.id.clone(),
+            name: call.name.clone(),
+            args: self.redact_value(&call.args),
+        }
+    }
+
+    /// Restore a streamed chunk. Text from a `[` that may open a placeholder is held in
+    /// `pending` until the next chunk shows whether it does; `flush` sends the rest.
+    pub(crate) fn restore_chunk(&self, pending: &mut String, chunk: &str) -> String {
+        pending.push_str(chunk);
+        let held = pending
+            .rfind('[')
+            .filter(|&at| !pending[at..].contains(']') && pending.len() - at < MAX_PLACEHOLDER)
+            .unwrap_or(pending.len());
+        let ready = self.restore(&pending[..held]);
+        pending.drain(..held);
+        ready
+    }
+}
+
+impl State {
+    fn placeholder(&mut self, entity: &str, value: &str) -> String {
+        if let Some(placeholder) = self.placeholders.get(value) {
+            return placeholder.clone();
+        }
+        let count = self.counts.entry(entity.to_string()).or_default();
+        *count += 1;
+        let placeholder = format!("[{}_{}]", entity.to_uppercase(), count);
+        self.placeholders
+            .insert(value.to_string(), placeholder.clone());
+        self.originals
+            .insert(placeholder.clone(), value.to_string());
+        placeholder
+    }
+}
+
+fn map_strings(value: &Value, f: &dyn Fn(&str) -> String) -> Value {
+    match value {
+        Value::String(text) => Value::String(f(text)),
+        Value::Array(items) => {
+            Value::Array(items.iter().map(|item| map_strings(item, f)).collect())
+        }
+        Value::Object(map) => Value::Object(
+            map.iter()
+                .map(|(key, item)| (key.clone(), map_strings(item, f)))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
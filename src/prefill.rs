@@ -0,0 +1,41 @@
+//! Prefilled answers: text the model's answer is made to start with, such as `{` to
+//! force JSON. Claude continues a partial assistant turn natively; other providers are
+//! told to start with the prefix, and the prefix is added if they don't.
+
+use crate::agent::Turn;
+
+/// The text Claude continues from. Claude rejects a final assistant turn that ends in
+/// whitespace, so trailing whitespace is left for the model to produce.
+pub(crate) fn partial_turn(prefill: &str) -> &str {
+    prefill.trim_end()
+}
+
+/// The system-prompt line asking a provider without native prefill to use the prefix.
+pub(crate) fn instruction(prefill: &str) -> String {
+    format!(
+        "Begin your answer with exactly the following text, then continue it:\n{}",
+        prefill
+    )
+}
+
+/// `system` extended with the prefill instruction.
+pub(crate) fn with_instruction(system: Option<String>, prefill: &str) -> String {
+    match system {
+        Some(system) => format!("{}\n\n{}", system, instruction(prefill)),
+        None => instruction(prefill),
+    }
+}
+
+/// A text turn from a provider that was only asked to use the prefix: leading
+/// whitespace before the prefix is dropped, and a missing prefix is added.
+pub(crate) fn ensure_prefix(prefill: Option<&str>, turn: Turn) -> Turn {
+    let (Some(prefill), Turn::Text(text)) = (prefill, &turn) else {
+        return turn;
+    };
+    let trimmed = text.trim_start();
+    if trimmed.starts_with(prefill.trim_end()) {
+        Turn::Text(trimmed.to_string())
+    } else {
+        Turn::Text(format!("{}{}", prefill, text))
+    }
+}
@@ -0,0 +1,74 @@
+//! OpenAI-compatible providers known by name: where they live, which environment
+//! variable holds their key, and how their model ids are recognised.
+
+use std::env;
+
+pub(crate) struct Preset {
+    pub(crate) name: &'static str,
+    pub(crate) base_url: &'static str,
+    pub(crate) env_var: &'static str,
+    /// Routing prefix on model ids, removed before the id is sent (`openrouter/...`).
+    prefix: &'static str,
+    /// Prefixes of the provider's own model ids, which are sent unchanged.
+    native_prefixes: &'static [&'static str],
+}
+
+pub(crate) const PRESETS: &[Preset] = &[
+    Preset {
+        name: "together",
+        base_url: "https://api.together.xyz/v1",
+        env_var: "TOGETHER_API_KEY",
+        prefix: "together/",
+        native_prefixes: &[],
+    },
+    Preset {
+        name: "fireworks",
+        base_url: "https://api.fireworks.ai/inference/v1",
+        env_var: "FIREWORKS_API_KEY",
+        prefix: "fireworks/",
+        native_prefixes: &["accounts/fireworks/"],
+    },
+    Preset {
+        name: "perplexity",
+        base_url: "https://api.perplexity.ai",
+        env_var: "PERPLEXITY_API_KEY",
+        prefix: "perplexity/",
+        native_prefixes: &["sonar"],
+    },
+    Preset {
+        name: "openrouter",
+        base_url: "https://openrouter.ai/api/v1",
+        env_var: "OPENROUTER_API_KEY",
+        prefix: "openrouter/",
+        native_prefixes: &[],
+    },
+];
+
+pub(crate) fn by_name(name: &str) -> Option<&'static Preset> {
+    PRESETS.iter().find(|preset| preset.name == name)
+}
+
+/// The preset whose routing prefix or model naming `model` uses.
+pub(crate) fn detect(model: &str) -> Option<&'static Preset> {
+    PRESETS.iter().find(|preset| {
+        model.starts_with(preset.prefix)
+            || preset
+                .native_prefixes
+                .iter()
+                .any(|prefix| model.starts_with(prefix))
+    })
+}
+
+impl Preset {
+    /// The id the provider knows `model` by.
+    pub(crate) fn model_id(&self, model: &str) -> String {
+        model.strip_prefix(self.prefix).unwrap_or(model).to_string()
+    }
+
+    /// The key from the preset's environment variable, or an empty one so that an
+    /// OpenAI key is never sent to another provider.
+    pub(crate) fn api_key(&self) -> String {
+        dotenv::dotenv().ok();
+        env::var(self.env_var).unwrap_or_default()
+    }
+}
@@ -0,0 +1,470 @@
+//! Prompt templates, and a library of named, versioned templates loaded from files.
+//!
+//! A prompt file holds the template text, optionally preceded by YAML front matter
+//! between `---` lines. The front matter can set `name`, `version`, `model` (the model
+//! the prompt was written for) and `params` (e.g. temperature); other keys are kept as
+//! metadata. Without `name`/`version`, both come from the file's path:
+//! `summarize@v3.md`, or `summarize/v3.md` with one directory per prompt.
+
+use pyo3::exceptions::{PyKeyError, PyOSError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use serde_json::{Map, Value};
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const EXTENSIONS: &[&str] = &["md", "txt", "prompt"];
+
+/// A `str.format` template, with the name, version, model hint and parameters it was
+/// loaded with.
+#[pyclass]
+pub struct PromptTemplate {
+    #[pyo3(get)]
+    template: String,
+    #[pyo3(get)]
+    name: Option<String>,
+    #[pyo3(get)]
+    version: Option<String>,
+    /// The model the prompt was written for.
+    #[pyo3(get)]
+    model: Option<String>,
+    params: Map<String, Value>,
+    metadata: Map<String, Value>,
+    /// Values bound with `partial()`.
+    partials: Vec<(String, Py<PyAny>)>,
+}
+
+#[pymethods]
+impl PromptTemplate {
+    #[new]
+    #[pyo3(signature = (template, name=None, version=None, model=None, params=None))]
+    fn new(
+        template: String,
+        name: Option<String>,
+        version: Option<String>,
+        model: Option<String>,
+        params: Option<&Bound<'_, PyDict>>,
+    ) -> PyResult<Self> {
+        let params = match params {
+            Some(params) => match pythonize::depythonize(params.as_any())? {
+                Value::Object(map) => map,
+                _ => Map::new(),
+            },
+            None => Map::new(),
+        };
+        Ok(PromptTemplate {
+            template,
+            name,
+            version,
+            model,
+            params,
+            metadata: Map::new(),
+            partials: Vec::new(),
+        })
+    }
+
+    /// The placeholders still to be filled, in order of first appearance.
+    #[getter]
+    fn input_variables(&self) -> Vec<String> {
+        placeholders(&self.template)
+            .into_iter()
+            .filter(|name| !self.partials.iter().any(|(bound, _)| bound == name))
+            .collect()
+    }
+
+    /// Model parameters from the front matter, e.g. `{"temperature": 0.2}`.
+    #[getter]
+    fn params<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        Ok(pythonize::pythonize(py, &self.params)?)
+    }
+
+    /// The whole front matter.
+    #[getter]
+    fn metadata<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        Ok(pythonize::pythonize(py, &self.metadata)?)
+    }
+
+    /// Render the template with `str.format`. Unused keyword arguments are ignored, so
+    /// a whole state dict can be passed.
+    #[pyo3(signature = (**kwargs))]
+    fn format(&self, py: Python, kwargs: Option<&Bound<'_, PyDict>>) -> PyResult<String> {
+        let values = PyDict::new(py);
+        for (name, value) in &self.partials {
+            values.set_item(name, value.bind(py))?;
+        }
+        if let Some(kwargs) = kwargs {
+            values.update(kwargs.as_mapping())?;
+        }
+        self.template
+            .clone()
+            .into_pyobject(py)?
+            .call_method("format", (), Some(&values))?
+            .extract()
+    }
+
+    /// A copy with some placeholders filled in ahead of time.
+    #[pyo3(signature = (**kwargs))]
+    fn partial(&self, py: Python, kwargs: Option<&Bound<'_, PyDict>>) -> PyResult<Self> {
+        let mut template = self.clone_ref(py);
+        for (name, value) in kwargs.into_iter().flatten() {
+            let name: String = name.extract()?;
+            template.partials.retain(|(bound, _)| *bound != name);
+            template.partials.push((name, value.unbind()));
+        }
+        Ok(template)
+    }
+
+    fn __repr__(&self) -> String {
+        let variables = format!(
+            "[{}]",
+            self.input_variables()
+                .iter()
+                .map(|v| format!("'{}'", v))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        match &self.name {
+            Some(name) => format!(
+                "PromptTemplate(name='{}', version={}, input_variables={})",
+                name,
+                self.version
+                    .as_ref()
+                    .map_or("None".to_string(), |v| format!("'{}'", v)),
+                variables
+            ),
+            None => format!("PromptTemplate(input_variables={})", variables),
+        }
+    }
+}
+
+impl PromptTemplate {
+    fn clone_ref(&self, py: Python) -> Self {
+        PromptTemplate {
+            template: self.template.clone(),
+            name: self.name.clone(),
+            version: self.version.clone(),
+            model: self.model.clone(),
+            params: self.params.clone(),
+            metadata: self.metadata.clone(),
+            partials: self
+                .partials
+                .iter()
+                .map(|(name, value)| (name.clone(), value.clone_ref(py)))
+                .collect(),
+        }
+    }
+}
+
+/// Field names in a `str.format` template, without attribute, index or format parts.
+fn placeholders(template: &str) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+            }
+            '{' => {
+                let field: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                let name = field
+                    .split([':', '!', '.', '['])
+                    .next()
+                    .unwrap_or_default()
+                    .trim()
+                    .to_string();
+                if !name.is_empty() && !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+            _ => {}
+        }
+    }
+    names
+}
+
+/// One prompt file.
+struct PromptFile {
+    version: Option<String>,
+    template: String,
+    front_matter: Map<String, Value>,
+    path: PathBuf,
+}
+
+impl PromptFile {
+    fn to_template(&self, name: &str) -> PromptTemplate {
+        let text = |key: &str| {
+            self.front_matter
+                .get(key)
+                .and_then(Value::as_str)
+                .map(str::to_string)
+        };
+        PromptTemplate {
+            template: self.template.clone(),
+            name: Some(name.to_string()),
+            version: self.version.clone(),
+            model: text("model"),
+            params: match self.front_matter.get("params") {
+                Some(Value::Object(params)) => params.clone(),
+                _ => Map::new(),
+            },
+            metadata: self.front_matter.clone(),
+            partials: Vec::new(),
+        }
+    }
+}
+
+/// Named, versioned prompt templates loaded from a directory. `get("summarize@v3")`
+/// returns one version; `get("summarize")` the latest.
+#[pyclass]
+pub struct PromptLibrary {
+    #[pyo3(get)]
+    directory: String,
+    /// Versions of each prompt, oldest first.
+    prompts: BTreeMap<String, Vec<PromptFile>>,
+}
+
+#[pymethods]
+impl PromptLibrary {
+    /// Load every `.md`, `.txt` and `.prompt` file under `directory`.
+    #[new]
+    fn new(directory: String) -> PyResult<Self> {
+        let prompts = load(Path::new(&directory))?;
+        Ok(PromptLibrary { directory, prompts })
+    }
+
+    /// The template for `reference`: `"name@version"`, or `"name"` for the latest
+    /// version.
+    fn get(&self, reference: &str) -> PyResult<PromptTemplate> {
+        let (name, version) = match reference.rsplit_once('@') {
+            Some((name, version)) => (name, Some(version)),
+            None => (reference, None),
+        };
+        let files = self
+            .prompts
+            .get(name)
+            .ok_or_else(|| PyKeyError::new_err(format!("no prompt named '{}'", name)))?;
+        let file = match version {
+            Some(version) => files
+                .iter()
+                .find(|file| file.version.as_deref() == Some(version))
+                .ok_or_else(|| {
+                    PyKeyError::new_err(format!(
+                        "prompt '{}' has no version '{}' (available: {})",
+                        name,
+                        version,
+                        self.versions(name).join(", ")
+                    ))
+                })?,
+            None => files.last().expect("every loaded prompt has a file"),
+        };
+        Ok(file.to_template(name))
+    }
+
+    /// Prompt names, sorted.
+    fn names(&self) -> Vec<String> {
+        self.prompts.keys().cloned().collect()
+    }
+
+    /// The versions of prompt `name`, oldest first.
+    fn versions(&self, name: &str) -> Vec<String> {
+        self.prompts
+            .get(name)
+            .into_iter()
+            .flatten()
+            .filter_map(|file| file.version.clone())
+            .collect()
+    }
+
+    /// Read the directory again, picking up edited, added and removed files.
+    fn reload(&mut self) -> PyResult<()> {
+        self.prompts = load(Path::new(&self.directory))?;
+        Ok(())
+    }
+
+    fn __contains__(&self, reference: &str) -> bool {
+        self.get(reference).is_ok()
+    }
+
+    fn __len__(&self) -> usize {
+        self.prompts.len()
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "PromptLibrary(directory='{}', prompts={})",
+            self.directory,
+            self.prompts.len()
+        )
+    }
+}
+
+fn load(directory: &Path) -> PyResult<BTreeMap<String, Vec<PromptFile>>> {
+    let mut paths = Vec::new();
+    collect_files(directory, &mut paths)?;
+    let mut prompts: BTreeMap<String, Vec<PromptFile>> = BTreeMap::new();
+    for path in paths {
+        let relative = path.strip_prefix(directory).unwrap_or(&path).to_path_buf();
+        let (name, file) = read_prompt(&path, &relative)?;
+        let versions = prompts.entry(name.clone()).or_default();
+        if let Some(existing) = versions.iter().find(|f| f.version == file.version) {
+            return Err(PyValueError::new_err(format!(
+                "prompt '{}'{} is defined by both {} and {}",
+                name,
+                file.version
+                    .as_ref()
+                    .map_or(String::new(), |v| format!(" version '{}'", v)),
+                existing.path.display(),
+                file.path.display()
+            )));
+        }
+        versions.push(file);
+    }
+    for versions in prompts.values_mut() {
+        versions.sort_by(|a, b| compare_versions(a.version.as_deref(), b.version.as_deref()));
+    }
+    Ok(prompts)
+}
+
+fn collect_files(directory: &Path, paths: &mut Vec<PathBuf>) -> PyResult<()> {
+    let entries = fs::read_dir(directory)
+        .map_err(|e| PyOSError::new_err(format!("cannot read {}: {}", directory.display(), e)))?;
+    for entry in entries {
+        let path = entry.map_err(|e| PyOSError::new_err(e.to_string()))?.path();
+        let hidden = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .is_some_and(|n| n.starts_with('.'));
+        if hidden {
+            continue;
+        }
+        if path.is_dir() {
+            collect_files(&path, paths)?;
+        } else if path
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| EXTENSIONS.contains(&e))
+        {
+            paths.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// Parse a prompt file, returning its name and contents.
+fn read_prompt(path: &Path, relative: &Path) -> PyResult<(String, PromptFile)> {
+    let text = fs::read_to_string(path)
+        .map_err(|e| PyOSError::new_err(format!("cannot read {}: {}", path.display(), e)))?;
+    let (front_matter, template) = split_front_matter(&text)
+        .map_err(|e| PyValueError::new_err(format!("{}: {}", path.display(), e)))?;
+
+    let (mut name, mut version) = name_from_path(relative);
+    if let Some(value) = front_matter.get("name").and_then(scalar) {
+        name = value;
+    }
+    if let Some(value) = front_matter.get("version").and_then(scalar) {
+        version = Some(value);
+    }
+    Ok((
+        name,
+        PromptFile {
+            version,
+            template,
+            front_matter,
+            path: path.to_path_buf(),
+        },
+    ))
+}
+
+/// `summarize@v3.md` and `summarize/v3.md` are version `v3` of `summarize`; a file
+/// at the top level without `@` is an unversioned prompt named after it.
+fn name_from_path(relative: &Path) -> (String, Option<String>) {
+    let stem = relative
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .unwrap_or_default()
+        .to_string();
+    let parent = relative
+        .parent()
+        .map(|p| p.to_string_lossy().replace('\\', "/"))
+        .filter(|p| !p.is_empty());
+    match (stem.split_once('@'), parent) {
+        (Some((name, version)), Some(parent)) => {
+            (format!("{}/{}", parent, name), Some(version.to_string()))
+        }
+        (Some((name, version)), None) => (name.to_string(), Some(version.to_string())),
+        (None, Some(parent)) => (parent, Some(stem)),
+        (None, None) => (stem, None),
+    }
+}
+
+/// A front-matter value as text; YAML reads `version: 3` as a number.
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        _ => None,
+    }
+}
+
+/// Separate YAML front matter from the template. The newline after the closing `---`
+/// is dropped.
+fn split_front_matter(text: &str) -> Result<(Map<String, Value>, String), String> {
+    let text = text.strip_prefix('\u{feff}').unwrap_or(text);
+    let Some(rest) = text
+        .strip_prefix("---\n")
+        .or_else(|| text.strip_prefix("---\r\n"))
+    else {
+        return Ok((Map::new(), text.to_string()));
+    };
+    let mut offset = 0;
+    for line in rest.split_inclusive('\n') {
+        if line.trim_end() == "---" {
+            let yaml = &rest[..offset];
+            let body = &rest[offset + line.len()..];
+            let front_matter = match serde_yaml::from_str::<Value>(yaml) {
+                Ok(Value::Object(map)) => map,
+                Ok(Value::Null) => Map::new(),
+                Ok(_) => return Err("front matter must be a mapping".to_string()),
+                Err(e) => return Err(format!("invalid front matter: {}", e)),
+            };
+            return Ok((front_matter, body.to_string()));
+        }
+        offset += line.len();
+    }
+    Err("front matter is missing its closing '---'".to_string())
+}
+
+/// Natural ordering, so `v10` sorts after `v9`; unversioned files come first.
+fn compare_versions(a: Option<&str>, b: Option<&str>) -> Ordering {
+    match (a, b) {
+        (None, None) => Ordering::Equal,
+        (None, Some(_)) => Ordering::Less,
+        (Some(_), None) => Ordering::Greater,
+        (Some(a), Some(b)) => natural_key(a).cmp(&natural_key(b)),
+    }
+}
+
+/// Digit runs compare as numbers and everything else as text.
+fn natural_key(version: &str) -> Vec<(u64, String)> {
+    let mut key = Vec::new();
+    let mut chars = version.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        let digits = c.is_ascii_digit();
+        let mut run = String::new();
+        while let Some(&c) = chars.peek() {
+            if c.is_ascii_digit() != digits {
+                break;
+            }
+            run.push(c);
+            chars.next();
+        }
+        if digits {
+            key.push((run.parse().unwrap_or(u64::MAX), String::new()));
+        } else {
+            key.push((0, run));
+        }
+    }
+    key
+}
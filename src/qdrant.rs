@@ -0,0 +1,191 @@
+//! Qdrant vector store over its REST API.
+
+use crate::error::{retry_after_header, RustedChainError};
+use crate::retry::RetryPolicy;
+use crate::vector_store::{Record, VectorStore};
+use crate::HTTP;
+use reqwest::{Client, Method, StatusCode};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::sync::atomic::{AtomicBool, Ordering};
+use uuid::Uuid;
+
+/// Qdrant only accepts integers and UUIDs as point ids, so ours are mapped to a
+/// name-based UUID and kept in the payload.
+fn point_id(id: &str) -> String {
+    Uuid::new_v5(&Uuid::NAMESPACE_OID, id.as_bytes()).to_string()
+}
+
+#[derive(Deserialize)]
+struct SearchResponse {
+    result: Vec<ScoredPoint>,
+}
+
+#[derive(Deserialize)]
+struct ScoredPoint {
+    score: f32,
+    #[serde(default)]
+    payload: Map<String, Value>,
+    #[serde(default)]
+    vector: Vec<f32>,
+}
+
+pub(crate) struct Qdrant {
+    url: String,
+    collection: String,
+    api_key: Option<String>,
+    client: Client,
+    retry: RetryPolicy,
+    /// Set once the collection is known to exist.
+    ready: AtomicBool,
+}
+
+impl Qdrant {
+    pub(crate) fn new(url: &str, collection: &str, api_key: Option<String>) -> Self {
+        Qdrant {
+            url: url.trim_end_matches('/').to_string(),
+            collection: collection.to_string(),
+            api_key,
+            client: HTTP.clone(),
+            retry: RetryPolicy::default(),
+            ready: AtomicBool::new(false),
+        }
+    }
+
+    async fn request(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&Value>,
+    ) -> Result<reqwest::Response, RustedChainError> {
+        let url = format!("{}/collections/{}{}", self.url, self.collection, path);
+        self.retry
+            .run(|| async {
+                let mut request = self.client.request(method.clone(), &url);
+                if let Some(key) = &self.api_key {
+                    request = request.header("api-key", key);
+                }
+                if let Some(body) = body {
+                    request = request.json(body);
+                }
+                let response = request.send().await?;
+                if !response.status().is_success() && response.status() != StatusCode::NOT_FOUND {
+                    let status = response.status();
+                    let retry_after = retry_after_header(response.headers());
+                    let text = response.text().await.unwrap_or_default();
+                    return Err(RustedChainError::from_response(status, retry_after, &text));
+                }
+                Ok(response)
+            })
+            .await
+    }
+
+    /// Fail on any unsuccessful response, including the 404s `request` lets through.
+    async fn expect_success(
+        &self,
+        method: Method,
+        path: &str,
+        body: Option<&Value>,
+    ) -> Result<reqwest::Response, RustedChainError> {
+        let response = self.request(method, path, body).await?;
+        if response.status() == StatusCode::NOT_FOUND {
+            return Err(RustedChainError::Store(format!(
+                "Qdrant collection '{}' does not exist",
+                self.collection
+            )));
+        }
+        Ok(response)
+    }
+
+    async fn ensure_collection(&self, dimensions: usize) -> Result<(), RustedChainError> {
+        if self.ready.load(Ordering::Acquire) {
+            return Ok(());
+        }
+        let existing = self.request(Method::GET, "", None).await?;
+        if existing.status() == StatusCode::NOT_FOUND {
+            let config = json!({ "vectors": { "size": dimensions, "distance": "Cosine" } });
+            self.expect_success(Method::PUT, "", Some(&config)).await?;
+        }
+        self.ready.store(true, Ordering::Release);
+        Ok(())
+    }
+}
+
+impl VectorStore for Qdrant {
+    async fn upsert(&self, records: Vec<Record>) -> Result<(), RustedChainError> {
+        let Some(first) = records.first() else {
+            return Ok(());
+        };
+        self.ensure_collection(first.vector.len()).await?;
+        let points: Vec<Value> = records
+            .into_iter()
+            .map(|r| {
+                json!({
+                    "id": point_id(&r.id),
+                    "vector": r.vector,
+                    "payload": { "id": r.id, "text": r.text, "metadata": r.metadata },
+                })
+            })
+            .collect();
+        self.expect_success(
+            Method::PUT,
+            "/points?wait=true",
+            Some(&json!({ "points": points })),
+        )
+        .await?;
+        Ok(())
+    }
+
+    async fn search(
+        &self,
+        vector: &[f32],
+        k: usize,
+    ) -> Result<Vec<(Record, f32)>, RustedChainError> {
+        let body = json!({ "vector": vector, "limit": k, "with_payload": true });
+        let response = self
+            .request(Method::POST, "/points/search", Some(&body))
+            .await?;
+        // Nothing has been stored yet.
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(Vec::new());
+        }
+        let response: SearchResponse = response
+            .json()
+            .await
+            .map_err(|e| RustedChainError::ParseError(e.to_string()))?;
+        Ok(response
+            .result
+            .into_iter()
+            .map(|mut point| {
+                let text = |key: &str| match point.payload.get(key) {
+                    Some(Value::String(s)) => s.clone(),
+                    _ => String::new(),
+                };
+                let (id, text) = (text("id"), text("text"));
+                let metadata = match point.payload.remove("metadata") {
+                    Some(Value::Object(metadata)) => metadata,
+                    _ => Map::new(),
+                };
+                let record = Record {
+                    id,
+                    text,
+                    metadata,
+                    vector: point.vector,
+                };
+                (record, point.score)
+            })
+            .collect())
+    }
+
+    async fn delete(&self, ids: &[String]) -> Result<(), RustedChainError> {
+        let points: Vec<String> = ids.iter().map(|id| point_id(id)).collect();
+        // A 404 means the collection was never created, so there is nothing to delete.
+        self.request(
+            Method::POST,
+            "/points/delete?wait=true",
+            Some(&json!({ "points": points })),
+        )
+        .await?;
+        Ok(())
+    }
+}
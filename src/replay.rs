@@ -0,0 +1,488 @@
+//! Step-by-step replay of a run recorded in an audit log or a run export.
+//!
+//! Every provider exchange of a run is one audit record: the conversation as sent and
+//! the model's reply. Replaying walks those replies in order without calling the
+//! provider. Tool calls are either re-executed with local tools, to see whether a fix
+//! changes what the model would have been told, or answered with the recorded output.
+
+use crate::agent::{self, wrap_tool_result, ToolRequest};
+use crate::artifacts::ArtifactSettings;
+use crate::context::RunContext;
+use crate::export;
+use crate::feedback;
+use crate::serialize;
+use crate::session::Entry;
+use crate::tool_args::Coercion;
+use pyo3::exceptions::{PyOSError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+/// What the model did at one step of the recorded run.
+enum Recorded {
+    Text(String),
+    ToolCall {
+        call: ToolRequest,
+        /// The output sent back to the model, read from the next request. `None` when
+        /// the run stopped before sending it.
+        result: Option<Value>,
+    },
+    /// The request failed; `kind` is the error kind from the audit record.
+    Error {
+        kind: String,
+        message: String,
+    },
+}
+
+struct Step {
+    recorded: Recorded,
+    latency_ms: u64,
+}
+
+/// Replays a recorded run step by step. Iterating yields one dict per model reply.
+#[pyclass]
+pub struct Replay {
+    #[pyo3(get)]
+    run_id: String,
+    #[pyo3(get)]
+    provider: String,
+    #[pyo3(get)]
+    model: String,
+    /// The user message that started the run.
+    #[pyo3(get)]
+    query: Option<String>,
+    /// Index of the next step.
+    #[pyo3(get)]
+    position: usize,
+    steps: Vec<Step>,
+    tools: HashMap<String, Py<PyAny>>,
+    metadata: Value,
+    ctx: Py<RunContext>,
+}
+
+#[pymethods]
+impl Replay {
+    /// `recording` is an audit log file (`audit_log="audit.jsonl"`) or a list of the
+    /// records an `audit_log` callback received, or the same for run exports
+    /// (`run_log`, `AgentResponse.to_run()`). `run_id` picks the run, by default the
+    /// last one recorded. Tool calls to one of `tools` are executed again; the others
+    /// are answered with their recorded output.
+    #[new]
+    #[pyo3(signature = (recording, run_id=None, tools=None))]
+    fn new(
+        py: Python,
+        recording: &Bound<'_, PyAny>,
+        run_id: Option<String>,
+        tools: Option<Vec<Bound<'_, PyAny>>>,
+    ) -> PyResult<Self> {
+        let mut records = read_records(recording)?;
+        records.retain(|record| !feedback::is_feedback(record));
+        let wrap = py
+            .import("rusted_chain.tool_helpers")?
+            .getattr("ensure_tool_wrapper")?;
+        let tools = tools
+            .into_iter()
+            .flatten()
+            .map(|tool| wrap.call1((tool,)).map(Bound::unbind))
+            .collect::<PyResult<Vec<_>>>()?;
+        let tools = agent::tool_registry(py, &Some(tools));
+        if records.iter().any(export::is_run_record) {
+            return Self::from_export(py, &records, run_id, tools);
+        }
+
+        let run_id = match run_id {
+            Some(run_id) => run_id,
+            None => records
+                .iter()
+                .rev()
+                .find_map(|record| record["run_id"].as_str())
+                .ok_or_else(|| PyValueError::new_err("the recording holds no runs"))?
+                .to_string(),
+        };
+        let records: Vec<&Value> = records
+            .iter()
+            .filter(|record| record["run_id"].as_str() == Some(run_id.as_str()))
+            .collect();
+        let first = records
+            .first()
+            .ok_or_else(|| PyValueError::new_err(format!("no records for run '{}'", run_id)))?;
+        let provider = first["provider"].as_str().unwrap_or_default().to_string();
+        let metadata = first["metadata"].clone();
+        Ok(Replay {
+            model: first["model"].as_str().unwrap_or_default().to_string(),
+            query: first["request"]["messages"]
+                .as_array()
+                .and_then(|messages| messages.iter().rev().find_map(|m| user_text(&provider, m))),
+            position: 0,
+            steps: steps(&provider, &records),
+            tools,
+            ctx: new_context(py, &run_id, &metadata)?,
+            metadata,
+            provider,
+            run_id,
+        })
+    }
+
+    /// Replay the next step, or return `None` after the last one.
+    ///
+    /// The dict has `index`, `type` (`"text"`, `"tool_call"` or `"error"`) and
+    /// `latency_ms`, plus `text`, `tool_call` or `error`. A tool call also carries
+    /// `recorded_result` and `result`: the recorded output, or the output of running
+    /// the tool again (`source` tells which), with `diverged` set when they differ.
+    fn step<'py>(&mut self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyDict>>> {
+        let Some(step) = self.steps.get(self.position) else {
+            return Ok(None);
+        };
+        let index = self.position;
+        self.position += 1;
+
+        let out = PyDict::new(py);
+        out.set_item("index", index)?;
+        out.set_item("latency_ms", step.latency_ms)?;
+        match &step.recorded {
+            Recorded::Text(text) => {
+                out.set_item("type", "text")?;
+                out.set_item("text", text)?;
+            }
+            Recorded::Error { kind, message } => {
+                out.set_item("type", "error")?;
+                out.set_item(
+                    "error",
+                    pythonize::pythonize(py, &json!({ "kind": kind, "message": message }))?,
+                )?;
+            }
+            Recorded::ToolCall { call, result } => {
+                out.set_item("type", "tool_call")?;
+                out.set_item(
+                    "tool_call",
+                    pythonize::pythonize(
+                        py,
+                        &json!({ "id": call.id, "name": call.name, "args": call.args }),
+                    )?,
+                )?;
+                out.set_item("recorded_result", pythonize::pythonize(py, result)?)?;
+                if self.tools.contains_key(&call.name) {
+                    self.ctx.borrow_mut(py).iteration = index;
+                    let executed = agent::execute_tool(
+                        py,
+                        &self.tools,
+                        &self.ctx,
+                        call,
+                        &serialize::DEFAULT,
+                        Coercion::default(),
+                    )?;
+                    self.ctx
+                        .borrow(py)
+                        .record(py, &call.name, &call.args, &executed)?;
+                    let diverged = result.as_ref().map(|r| wrap_tool_result(r.clone()))
+                        != Some(wrap_tool_result(executed.clone()));
+                    out.set_item("result", pythonize::pythonize(py, &executed)?)?;
+                    out.set_item("source", "executed")?;
+                    out.set_item("diverged", diverged)?;
+                } else {
+                    out.set_item("result", pythonize::pythonize(py, result)?)?;
+                    out.set_item("source", "recorded")?;
+                    out.set_item("diverged", false)?;
+                }
+            }
+        }
+        Ok(Some(out))
+    }
+
+    /// Replay the remaining steps and return their dicts.
+    fn run<'py>(&mut self, py: Python<'py>) -> PyResult<Bound<'py, PyList>> {
+        let list = PyList::empty(py);
+        while let Some(step) = self.step(py)? {
+            list.append(step)?;
+        }
+        Ok(list)
+    }
+
+    /// Start over from the first step, with a fresh `RunContext` for the tools.
+    fn reset(&mut self, py: Python) -> PyResult<()> {
+        self.position = 0;
+        self.ctx = new_context(py, &self.run_id, &self.metadata)?;
+        Ok(())
+    }
+
+    /// The recorded final answer, if the run ended with one.
+    #[getter]
+    fn answer(&self) -> Option<String> {
+        match self.steps.last().map(|step| &step.recorded) {
+            Some(Recorded::Text(text)) => Some(text.clone()),
+            _ => None,
+        }
+    }
+
+    fn __len__(&self) -> usize {
+        self.steps.len()
+    }
+
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__<'py>(&mut self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyDict>>> {
+        self.step(py)
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "Replay(run_id='{}', provider='{}', model='{}', steps={}, position={})",
+            self.run_id,
+            self.provider,
+            self.model,
+            self.steps.len(),
+            self.position
+        )
+    }
+}
+
+impl Replay {
+    /// A replay of an exported run. Exports keep timings per run, not per reply, so
+    /// every step has a `latency_ms` of 0.
+    fn from_export(
+        py: Python,
+        records: &[Value],
+        run_id: Option<String>,
+        tools: HashMap<String, Py<PyAny>>,
+    ) -> PyResult<Self> {
+        let record = match &run_id {
+            Some(run_id) => records
+                .iter()
+                .find(|record| record["run_id"].as_str() == Some(run_id.as_str()))
+                .ok_or_else(|| PyValueError::new_err(format!("no records for run '{}'", run_id)))?,
+            None => records
+                .iter()
+                .rev()
+                .find(|record| export::is_run_record(record))
+                .ok_or_else(|| PyValueError::new_err("the recording holds no runs"))?,
+        };
+        export::check(record).map_err(PyValueError::new_err)?;
+        let messages = export::messages(record).map_err(PyValueError::new_err)?;
+        let run_id = record["run_id"].as_str().unwrap_or_default().to_string();
+        let metadata = record["metadata"].clone();
+        Ok(Replay {
+            provider: record["provider"].as_str().unwrap_or_default().to_string(),
+            model: record["model"].as_str().unwrap_or_default().to_string(),
+            query: record["query"].as_str().map(str::to_string),
+            position: 0,
+            steps: exported_steps(&messages, record["error"].as_str()),
+            tools,
+            ctx: new_context(py, &run_id, &metadata)?,
+            metadata,
+            run_id,
+        })
+    }
+}
+
+fn new_context(py: Python, run_id: &str, metadata: &Value) -> PyResult<Py<RunContext>> {
+    let metadata = match pythonize::pythonize(py, metadata)?.cast_into::<PyDict>() {
+        Ok(dict) => Some(dict.unbind()),
+        Err(_) => None,
+    };
+    RunContext::create(py, run_id, metadata, &ArtifactSettings::default())
+}
+
+pub(crate) fn read_records(recording: &Bound<'_, PyAny>) -> PyResult<Vec<Value>> {
+    if let Ok(path) = recording.extract::<std::path::PathBuf>() {
+        let text = std::fs::read_to_string(&path)
+            .map_err(|e| PyOSError::new_err(format!("cannot read {}: {}", path.display(), e)))?;
+        return text
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .enumerate()
+            .map(|(number, line)| {
+                serde_json::from_str(line).map_err(|e| {
+                    PyValueError::new_err(format!("{} line {}: {}", path.display(), number + 1, e))
+                })
+            })
+            .collect();
+    }
+    Ok(pythonize::depythonize(recording)?)
+}
+
+fn steps(provider: &str, records: &[&Value]) -> Vec<Step> {
+    records
+        .iter()
+        .enumerate()
+        .map(|(index, record)| {
+            let latency_ms = record["latency_ms"].as_u64().unwrap_or_default();
+            if record["status"] != "ok" {
+                return Step {
+                    recorded: Recorded::Error {
+                        kind: record["status"].as_str().unwrap_or_default().to_string(),
+                        message: record["error"].as_str().unwrap_or_default().to_string(),
+                    },
+                    latency_ms,
+                };
+            }
+            let message = &record["response"]["message"];
+            let recorded = match tool_call(provider, message) {
+                Some(call) => {
+                    let result = records
+                        .get(index + 1)
+                        .and_then(|next| next["request"]["messages"].as_array())
+                        .and_then(|messages| messages.last())
+                        .and_then(|last| tool_result(provider, last, &call));
+                    Recorded::ToolCall { call, result }
+                }
+                None => Recorded::Text(assistant_text(provider, message)),
+            };
+            Step {
+                recorded,
+                latency_ms,
+            }
+        })
+        .collect()
+}
+
+/// The steps of an exported run: the replies after its query, then its error if it
+/// failed.
+fn exported_steps(messages: &[Entry], error: Option<&str>) -> Vec<Step> {
+    let start = messages
+        .iter()
+        .rposition(|entry| matches!(entry, Entry::User(_)))
+        .map_or(0, |index| index + 1);
+    let mut steps: Vec<Step> = messages[start..]
+        .iter()
+        .enumerate()
+        .filter_map(|(index, entry)| {
+            let recorded = match entry {
+                Entry::Assistant(text) => Recorded::Text(text.clone()),
+                Entry::ToolCall(call) => Recorded::ToolCall {
+                    call: call.clone(),
+                    result: match messages.get(start + index + 1) {
+                        Some(Entry::ToolResult {
+                            call: answered,
+                            result,
+                        }) if answered.id == call.id => Some(result.clone()),
+                        _ => None,
+                    },
+                },
+                _ => return None,
+            };
+            Some(Step {
+                recorded,
+                latency_ms: 0,
+            })
+        })
+        .collect();
+    if let Some(message) = error {
+        steps.push(Step {
+            recorded: Recorded::Error {
+                kind: "error".to_string(),
+                message: message.to_string(),
+            },
+            latency_ms: 0,
+        });
+    }
+    steps
+}
+
+/// The tool call in a recorded assistant message, in each provider's format.
+fn tool_call(provider: &str, message: &Value) -> Option<ToolRequest> {
+    match provider {
+        "openai" | "huggingface" => {
+            let call = message["tool_calls"].as_array()?.first()?;
+            let arguments = call["function"]["arguments"].as_str().unwrap_or("{}");
+            Some(ToolRequest {
+                id: call["id"].as_str().map(str::to_string),
+                name: call["function"]["name"].as_str()?.to_string(),
+                args: serde_json::from_str(arguments).unwrap_or(Value::Null),
+            })
+        }
+        "anthropic" => {
+            let block = message["content"]
+                .as_array()?
+                .iter()
+                .find(|block| block["type"] == "tool_use")?;
+            Some(ToolRequest {
+                id: block["id"].as_str().map(str::to_string),
+                name: block["name"].as_str()?.to_string(),
+                args: block["input"].clone(),
+            })
+        }
+        _ => {
+            let call = message["parts"]
+                .as_array()?
+                .iter()
+                .find_map(|part| part.get("functionCall"))?;
+            Some(ToolRequest {
+                id: None,
+                name: call["name"].as_str()?.to_string(),
+                args: call["args"].clone(),
+            })
+        }
+    }
+}
+
+/// The output of `call` if `message` is the tool result that answered it.
+fn tool_result(provider: &str, message: &Value, call: &ToolRequest) -> Option<Value> {
+    match provider {
+        "openai" | "huggingface" => {
+            if message["role"] != "tool" || message["tool_call_id"].as_str() != call.id.as_deref() {
+                return None;
+            }
+            let content = message["content"].as_str()?;
+            Some(serde_json::from_str(content).unwrap_or_else(|_| json!(content)))
+        }
+        "anthropic" => message["content"]
+            .as_array()?
+            .iter()
+            .find(|block| {
+                block["type"] == "tool_result"
+                    && block["tool_use_id"].as_str() == call.id.as_deref()
+            })
+            .map(|block| unwrap_result(block["content"].clone())),
+        _ => message["parts"]
+            .as_array()?
+            .iter()
+            .filter_map(|part| part.get("functionResponse"))
+            .find(|response| response["name"] == call.name.as_str())
+            .map(|response| unwrap_result(response["response"].clone())),
+    }
+}
+
+/// Undo `wrap_tool_result` for outputs that were not objects to begin with.
+fn unwrap_result(value: Value) -> Value {
+    match value {
+        Value::Object(mut map) if map.len() == 1 && map.contains_key("result") => {
+            map.remove("result").unwrap_or_default()
+        }
+        other => other,
+    }
+}
+
+fn assistant_text(provider: &str, message: &Value) -> String {
+    match provider {
+        "openai" | "huggingface" => message["content"].as_str().unwrap_or_default().to_string(),
+        "anthropic" => join_text(message["content"].as_array(), "text"),
+        _ => join_text(message["parts"].as_array(), "text"),
+    }
+}
+
+fn join_text(blocks: Option<&Vec<Value>>, key: &str) -> String {
+    blocks
+        .into_iter()
+        .flatten()
+        .filter_map(|block| block[key].as_str())
+        .collect()
+}
+
+/// The text of a recorded user message, skipping tool results.
+fn user_text(provider: &str, message: &Value) -> Option<String> {
+    if message["role"] != "user" {
+        return None;
+    }
+    let text = match provider {
+        "openai" | "huggingface" => message["content"].as_str()?.to_string(),
+        "anthropic" => match &message["content"] {
+            Value::String(text) => text.clone(),
+            content => join_text(content.as_array(), "text"),
+        },
+        _ => join_text(message["parts"].as_array(), "text"),
+    };
+    (!text.is_empty()).then_some(text)
+}
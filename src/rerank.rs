@@ -0,0 +1,276 @@
+//! Reranking of retrieved documents by relevance to a query, through Cohere's or
+//! Voyage's rerank endpoints or a local lexical heuristic.
+
+use crate::bm25::terms;
+use crate::document::Document;
+use crate::error::{retry_after_header, RustedChainError};
+use crate::retry::RetryPolicy;
+use crate::{HTTP, RUNTIME};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use reqwest::Client;
+use serde::Deserialize;
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
+use std::env;
+
+#[derive(Clone, Copy)]
+enum Backend {
+    Cohere,
+    Voyage,
+    Local,
+}
+
+#[derive(Deserialize)]
+struct Ranked {
+    index: usize,
+    relevance_score: f64,
+}
+
+#[derive(Deserialize)]
+struct CohereResponse {
+    results: Vec<Ranked>,
+}
+
+#[derive(Deserialize)]
+struct VoyageResponse {
+    data: Vec<Ranked>,
+}
+
+/// Score each text by the query terms it contains, weighted by how rare each term is
+/// among the candidates and saturating with repeated occurrences. Scores are in [0, 1].
+fn local_scores(query: &str, texts: &[String]) -> Vec<f64> {
+    const K1: f64 = 1.2;
+    let query: HashSet<String> = terms(query).into_iter().collect();
+    let counts: Vec<HashMap<String, usize>> = texts
+        .iter()
+        .map(|text| {
+            let mut counts = HashMap::new();
+            for term in terms(text) {
+                *counts.entry(term).or_insert(0) += 1;
+            }
+            counts
+        })
+        .collect();
+
+    let n = texts.len() as f64;
+    let idf: HashMap<&String, f64> = query
+        .iter()
+        .filter_map(|term| {
+            let df = counts.iter().filter(|c| c.contains_key(term)).count() as f64;
+            // Terms no candidate contains cannot tell candidates apart.
+            (df > 0.0).then(|| (term, (1.0 + (n - df + 0.5) / (df + 0.5)).ln()))
+        })
+        .collect();
+    let max: f64 = idf.values().sum();
+
+    counts
+        .iter()
+        .map(|counts| {
+            if max == 0.0 {
+                return 0.0;
+            }
+            let score: f64 = idf
+                .iter()
+                .map(|(term, idf)| {
+                    let tf = counts.get(*term).copied().unwrap_or(0) as f64;
+                    idf * tf * (K1 + 1.0) / (tf + K1) / (K1 + 1.0)
+                })
+                .sum();
+            score / max
+        })
+        .collect()
+}
+
+/// One reranked document: its position in the input, its relevance score and the
+/// document itself.
+#[pyclass(get_all)]
+pub struct RerankResult {
+    pub index: usize,
+    pub score: f64,
+    pub document: Py<PyAny>,
+}
+
+#[pymethods]
+impl RerankResult {
+    fn __repr__(&self) -> String {
+        format!(
+            "RerankResult(index={}, score={:.4})",
+            self.index, self.score
+        )
+    }
+}
+
+/// Orders documents by relevance to a query.
+#[pyclass]
+pub struct Reranker {
+    backend: Backend,
+    model: String,
+    api_key: String,
+    client: Client,
+    retry: RetryPolicy,
+}
+
+#[pymethods]
+impl Reranker {
+    /// `provider` is `"cohere"`, `"voyage"` or `"local"`. The API key defaults to
+    /// `COHERE_API_KEY` or `VOYAGE_API_KEY`.
+    #[new]
+    #[pyo3(signature = (provider="local", model=None, api_key=None, max_retries=None))]
+    fn new(
+        provider: &str,
+        model: Option<String>,
+        api_key: Option<String>,
+        max_retries: Option<u32>,
+    ) -> PyResult<Self> {
+        dotenv::dotenv().ok();
+        let (backend, default_model, key_var) = match provider {
+            "cohere" => (Backend::Cohere, "rerank-v3.5", "COHERE_API_KEY"),
+            "voyage" => (Backend::Voyage, "rerank-2", "VOYAGE_API_KEY"),
+            "local" => (Backend::Local, "", ""),
+            other => {
+                return Err(PyValueError::new_err(format!(
+                    "provider must be 'cohere', 'voyage' or 'local', got '{}'",
+                    other
+                )))
+            }
+        };
+        let mut retry = RetryPolicy::default();
+        if let Some(max_retries) = max_retries {
+            retry.max_retries = max_retries;
+        }
+        Ok(Reranker {
+            backend,
+            model: model.unwrap_or_else(|| default_model.to_string()),
+            api_key: api_key.unwrap_or_else(|| env::var(key_var).unwrap_or_default()),
+            client: HTTP.clone(),
+            retry,
+        })
+    }
+
+    /// Rank `documents` (strings or `Document`s) by relevance to `query`, best first,
+    /// keeping the `top_n` best when given.
+    #[pyo3(signature = (query, documents, top_n=None))]
+    fn rerank(
+        &self,
+        py: Python,
+        query: &str,
+        documents: Vec<Py<PyAny>>,
+        top_n: Option<usize>,
+    ) -> PyResult<Vec<RerankResult>> {
+        let texts = documents
+            .iter()
+            .map(|doc| document_text(doc.bind(py)))
+            .collect::<PyResult<Vec<_>>>()?;
+        let ranked = py.detach(|| RUNTIME.block_on(self.rank(query, &texts, top_n)))?;
+        Ok(ranked
+            .into_iter()
+            .map(|(index, score)| RerankResult {
+                index,
+                score,
+                document: documents[index].clone_ref(py),
+            })
+            .collect())
+    }
+}
+
+/// The text of a string or `Document`.
+pub(crate) fn document_text(doc: &Bound<'_, PyAny>) -> PyResult<String> {
+    match doc.cast::<Document>() {
+        Ok(doc) => Ok(doc.borrow().text.clone()),
+        Err(_) => doc.extract(),
+    }
+}
+
+impl Reranker {
+    /// The local heuristic reranker.
+    pub(crate) fn local() -> Self {
+        Reranker {
+            backend: Backend::Local,
+            model: String::new(),
+            api_key: String::new(),
+            client: HTTP.clone(),
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    /// `(index, score)` pairs for `texts`, best first.
+    pub(crate) async fn rank(
+        &self,
+        query: &str,
+        texts: &[String],
+        top_n: Option<usize>,
+    ) -> Result<Vec<(usize, f64)>, RustedChainError> {
+        if texts.is_empty() {
+            return Ok(Vec::new());
+        }
+        let top_n = top_n.unwrap_or(texts.len()).min(texts.len());
+        let mut ranked: Vec<(usize, f64)> = match self.backend {
+            Backend::Local => local_scores(query, texts).into_iter().enumerate().collect(),
+            Backend::Cohere => {
+                let body = json!({
+                    "model": self.model,
+                    "query": query,
+                    "documents": texts,
+                    "top_n": top_n,
+                });
+                let response: CohereResponse =
+                    self.post("https://api.cohere.com/v2/rerank", &body).await?;
+                response
+                    .results
+                    .into_iter()
+                    .map(|r| (r.index, r.relevance_score))
+                    .collect()
+            }
+            Backend::Voyage => {
+                let body = json!({
+                    "model": self.model,
+                    "query": query,
+                    "documents": texts,
+                    "top_k": top_n,
+                });
+                let response: VoyageResponse = self
+                    .post("https://api.voyageai.com/v1/rerank", &body)
+                    .await?;
+                response
+                    .data
+                    .into_iter()
+                    .map(|r| (r.index, r.relevance_score))
+                    .collect()
+            }
+        };
+        ranked.sort_by(|a, b| b.1.total_cmp(&a.1));
+        ranked.truncate(top_n);
+        Ok(ranked)
+    }
+
+    async fn post<T: for<'de> Deserialize<'de>>(
+        &self,
+        url: &str,
+        body: &serde_json::Value,
+    ) -> Result<T, RustedChainError> {
+        let response = self
+            .retry
+            .run(|| async {
+                let response = self
+                    .client
+                    .post(url)
+                    .bearer_auth(&self.api_key)
+                    .json(body)
+                    .send()
+                    .await?;
+                if !response.status().is_success() {
+                    let status = response.status();
+                    let retry_after = retry_after_header(response.headers());
+                    let text = response.text().await.unwrap_or_default();
+                    return Err(RustedChainError::from_response(status, retry_after, &text));
+                }
+                Ok(response)
+            })
+            .await?;
+        response
+            .json()
+            .await
+            .map_err(|e| RustedChainError::ParseError(e.to_string()))
+    }
+}
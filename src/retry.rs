@@ -0,0 +1,192 @@
+//! Retry layer shared by the provider clients.
+
+use crate::error::RustedChainError;
+use pyo3::prelude::*;
+use std::cell::RefCell;
+use std::future::Future;
+use std::time::Duration;
+
+tokio::task_local! {
+    /// Retries made inside a `recorded` future.
+    static RETRIES: RefCell<Vec<Retry>>;
+}
+
+/// A failed attempt that was retried.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Retry {
+    pub(crate) error: &'static str,
+    pub(crate) backoff: Duration,
+}
+
+/// Await `future`, returning the retries `RetryPolicy::run` made along the way.
+pub(crate) async fn recorded<F: Future>(future: F) -> (F::Output, Vec<Retry>) {
+    RETRIES
+        .scope(RefCell::new(Vec::new()), async {
+            let output = future.await;
+            (output, RETRIES.with(RefCell::take))
+        })
+        .await
+}
+
+/// How the provider requests of a run were retried.
+#[pyclass(get_all)]
+#[derive(Clone, Debug)]
+pub struct Retries {
+    /// Requests sent, retries included.
+    pub attempts: u64,
+    /// Requests that failed and were sent again.
+    pub retries: u64,
+    /// Seconds waited before each retry.
+    pub backoff_s: Vec<f64>,
+    /// Error kind of each retried attempt, such as `"rate_limited"`.
+    pub errors: Vec<String>,
+    /// `"ok"`, or the error kind of the last request if it failed for good.
+    pub final_status: String,
+}
+
+impl Default for Retries {
+    fn default() -> Self {
+        Self {
+            attempts: 0,
+            retries: 0,
+            backoff_s: Vec::new(),
+            errors: Vec::new(),
+            final_status: "ok".to_string(),
+        }
+    }
+}
+
+impl Retries {
+    /// Count one request, its `retries` and the status it ended with.
+    pub(crate) fn record(&mut self, retries: &[Retry], status: &str) {
+        self.attempts += 1 + retries.len() as u64;
+        self.retries += retries.len() as u64;
+        for retry in retries {
+            self.backoff_s.push(retry.backoff.as_secs_f64());
+            self.errors.push(retry.error.to_string());
+        }
+        self.final_status = status.to_string();
+    }
+
+    /// Total time spent waiting between attempts.
+    pub(crate) fn backoff_total_s(&self) -> f64 {
+        self.backoff_s.iter().fold(0.0, |total, s| total + s)
+    }
+}
+
+#[pymethods]
+impl Retries {
+    fn __repr__(&self) -> String {
+        format!(
+            "Retries(attempts={}, retries={}, backoff_s={:.3}, final_status='{}')",
+            self.attempts,
+            self.retries,
+            self.backoff_total_s(),
+            self.final_status
+        )
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RetryPolicy {
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 2,
+            initial_backoff: Duration::from_millis(500),
+            max_backoff: Duration::from_secs(30),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Backoff before retry number `attempt` (0-based), honouring a server-provided delay.
+    fn backoff(&self, attempt: u32, err: &RustedChainError) -> Duration {
+        let exponential = self
+            .initial_backoff
+            .saturating_mul(2u32.saturating_pow(attempt));
+        // The delay comes from the server: a negative, NaN or overflowing one is ignored.
+        let delay = err
+            .retry_after()
+            .and_then(|seconds| Duration::try_from_secs_f64(seconds).ok())
+            .unwrap_or(exponential);
+        delay.min(self.max_backoff)
+    }
+
+    /// Run `op`, retrying errors classified as retryable.
+    pub async fn run<T, F, Fut>(&self, mut op: F) -> Result<T, RustedChainError>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, RustedChainError>>,
+    {
+        let mut attempt = 0;
+        loop {
+            match op().await {
+                Err(err) if err.is_retryable() && attempt < self.max_retries => {
+                    let backoff = self.backoff(attempt, &err);
+                    // Outside `recorded` there is no one to report to.
+                    let _ = RETRIES.try_with(|retries| {
+                        retries.borrow_mut().push(Retry {
+                            error: err.kind(),
+                            backoff,
+                        })
+                    });
+                    tokio::time::sleep(backoff).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rate_limited(retry_after: Option<f64>) -> RustedChainError {
+        RustedChainError::RateLimited {
+            retry_after,
+            message: String::new(),
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let policy = RetryPolicy::default();
+        let err = RustedChainError::Overloaded(String::new());
+        assert_eq!(policy.backoff(0, &err), Duration::from_millis(500));
+        assert_eq!(policy.backoff(2, &err), Duration::from_secs(2));
+        assert_eq!(policy.backoff(40, &err), policy.max_backoff);
+    }
+
+    #[test]
+    fn backoff_honours_the_server_delay() {
+        let policy = RetryPolicy::default();
+        assert_eq!(
+            policy.backoff(0, &rate_limited(Some(3.5))),
+            Duration::from_secs_f64(3.5)
+        );
+        assert_eq!(
+            policy.backoff(0, &rate_limited(Some(3600.0))),
+            policy.max_backoff
+        );
+    }
+
+    #[test]
+    fn backoff_ignores_unusable_server_delays() {
+        let policy = RetryPolicy::default();
+        for seconds in [-1.0, f64::NAN, f64::INFINITY, f64::NEG_INFINITY, 1e300] {
+            assert_eq!(
+                policy.backoff(1, &rate_limited(Some(seconds))),
+                Duration::from_secs(1),
+                "retry after {seconds}"
+            );
+        }
+    }
+}
@@ -0,0 +1,217 @@
+//! Conversations in other formats. Histories written as OpenAI chat messages
+//! (`assistant`, `tool`) or Gemini contents (`model`, `function`) are read into the
+//! provider-independent history, and a history can be written out in either.
+
+use crate::agent::{ChatClient, ToolRequest};
+use crate::claude::Claude;
+use crate::gemini::Gemini;
+use crate::openai::OpenAI;
+use crate::session::Entry;
+use serde_json::{json, Value};
+
+#[derive(Clone, Copy, PartialEq)]
+enum Role {
+    User,
+    Assistant,
+    Tool,
+    System,
+}
+
+/// The role a message has whatever format it was written in.
+fn role(name: &str) -> Option<Role> {
+    match name {
+        "user" | "human" => Some(Role::User),
+        "assistant" | "model" | "ai" => Some(Role::Assistant),
+        "tool" | "function" => Some(Role::Tool),
+        "system" | "developer" => Some(Role::System),
+        _ => None,
+    }
+}
+
+/// `messages` as a history. Each message may be in this crate's own format (as in
+/// `Session.history`), OpenAI's or Gemini's, so mixed histories read as one.
+pub(crate) fn import(messages: &[Value]) -> Result<Vec<Entry>, String> {
+    let mut entries: Vec<Entry> = Vec::new();
+    for (index, message) in messages.iter().enumerate() {
+        let name = message["role"].as_str();
+        let parts = message["parts"].as_array();
+        // Gemini sends function responses back as `user` as well as `function`.
+        let answers = parts.is_some_and(|parts| {
+            parts
+                .iter()
+                .any(|part| part.get("functionResponse").is_some())
+        });
+        let role = match name.and_then(role) {
+            Some(Role::User) if answers => Role::Tool,
+            Some(role) => role,
+            None => return Err(format!("message {} has unknown role {:?}", index, name)),
+        };
+        match role {
+            Role::System => {
+                return Err(format!(
+                    "message {} is a system message; pass it as the model's `system` option",
+                    index
+                ))
+            }
+            Role::User => {
+                entries
+                    .push(Entry::User(text(message).ok_or_else(|| {
+                        format!("message {} has no text content", index)
+                    })?))
+            }
+            Role::Assistant => {
+                let calls = calls(message, index)?;
+                match text(message) {
+                    Some(text) if !text.is_empty() || calls.is_empty() => {
+                        entries.push(Entry::Assistant(text))
+                    }
+                    None if calls.is_empty() => {
+                        return Err(format!("message {} has no text content", index))
+                    }
+                    _ => {}
+                }
+                // Gemini calls have no id; OpenAI and Claude need one to pair results.
+                for mut call in calls {
+                    call.id
+                        .get_or_insert_with(|| format!("call_{}", entries.len()));
+                    entries.push(Entry::ToolCall(call));
+                }
+            }
+            Role::Tool => {
+                for (id, name, result) in results(message) {
+                    let call = answered(&entries, id.as_deref(), name.as_deref())
+                        .ok_or_else(|| format!("tool result {} answers no earlier call", index))?;
+                    entries.push(Entry::ToolResult { call, result });
+                }
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// `history` as the messages `format` (`"openai"`, `"gemini"` or `"claude"`) sends.
+pub(crate) fn export(history: &[Entry], format: &str) -> Result<Vec<Value>, String> {
+    fn messages<C: ChatClient>(history: &[Entry]) -> Vec<Value> {
+        history
+            .iter()
+            .map(|entry| serde_json::to_value(entry.to_message::<C>()).unwrap_or(Value::Null))
+            .collect()
+    }
+    match format {
+        "openai" => Ok(messages::<OpenAI>(history)),
+        "gemini" => Ok(messages::<Gemini>(history)),
+        "claude" | "anthropic" => Ok(messages::<Claude>(history)),
+        other => Err(format!(
+            "format must be 'openai', 'gemini' or 'claude', got '{}'",
+            other
+        )),
+    }
+}
+
+/// The text of a message: a string `content`, or the text parts of a list of them.
+fn text(message: &Value) -> Option<String> {
+    if let Some(text) = message["content"].as_str() {
+        return Some(text.to_string());
+    }
+    let parts = message["content"]
+        .as_array()
+        .or_else(|| message["parts"].as_array())?;
+    Some(
+        parts
+            .iter()
+            .filter_map(|part| part["text"].as_str())
+            .collect(),
+    )
+}
+
+/// The tool calls an assistant message makes.
+fn calls(message: &Value, index: usize) -> Result<Vec<ToolRequest>, String> {
+    let unnamed = || format!("tool call {} has no name", index);
+    // This crate's own format.
+    if let Some(call) = message.get("tool_call") {
+        return Ok(vec![ToolRequest {
+            id: call["id"].as_str().map(str::to_string),
+            name: call["name"].as_str().ok_or_else(unnamed)?.to_string(),
+            args: call["args"].clone(),
+        }]);
+    }
+    let mut calls = Vec::new();
+    for call in message["tool_calls"].as_array().into_iter().flatten() {
+        let arguments = &call["function"]["arguments"];
+        calls.push(ToolRequest {
+            id: call["id"].as_str().map(str::to_string),
+            name: call["function"]["name"]
+                .as_str()
+                .ok_or_else(unnamed)?
+                .to_string(),
+            args: match arguments.as_str() {
+                Some(text) => serde_json::from_str(text).unwrap_or_else(|_| json!({})),
+                None => arguments.clone(),
+            },
+        });
+    }
+    for part in message["parts"].as_array().into_iter().flatten() {
+        let Some(call) = part.get("functionCall") else {
+            continue;
+        };
+        calls.push(ToolRequest {
+            id: call["id"].as_str().map(str::to_string),
+            name: call["name"].as_str().ok_or_else(unnamed)?.to_string(),
+            args: call.get("args").cloned().unwrap_or_else(|| json!({})),
+        });
+    }
+    Ok(calls)
+}
+
+/// The `(call id, tool name, result)` of each tool result in a message.
+fn results(message: &Value) -> Vec<(Option<String>, Option<String>, Value)> {
+    let responses: Vec<_> = message["parts"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(|part| part.get("functionResponse"))
+        .map(|response| {
+            // Gemini takes objects only; other results were sent as `{"result": ...}`.
+            let result = match response["response"].as_object() {
+                Some(object) if object.len() == 1 && object.contains_key("result") => {
+                    object["result"].clone()
+                }
+                _ => response["response"].clone(),
+            };
+            (
+                response["id"].as_str().map(str::to_string),
+                response["name"].as_str().map(str::to_string),
+                result,
+            )
+        })
+        .collect();
+    if !responses.is_empty() {
+        return responses;
+    }
+    // OpenAI sends results as JSON text; objects and lists are read back as such.
+    let content = &message["content"];
+    let result = content
+        .as_str()
+        .and_then(|text| serde_json::from_str::<Value>(text).ok())
+        .filter(|parsed| parsed.is_object() || parsed.is_array())
+        .unwrap_or_else(|| content.clone());
+    vec![(
+        message["tool_call_id"].as_str().map(str::to_string),
+        message["name"].as_str().map(str::to_string),
+        result,
+    )]
+}
+
+/// The latest call a result answers: by id where the result has one that matches,
+/// otherwise the latest call to the tool named, as Gemini pairs them.
+fn answered(entries: &[Entry], id: Option<&str>, name: Option<&str>) -> Option<ToolRequest> {
+    let calls = || {
+        entries.iter().rev().filter_map(|entry| match entry {
+            Entry::ToolCall(call) => Some(call),
+            _ => None,
+        })
+    };
+    id.and_then(|id| calls().find(|call| call.id.as_deref() == Some(id)))
+        .or_else(|| calls().find(|call| name == Some(call.name.as_str())))
+        .cloned()
+}
@@ -0,0 +1,193 @@
+//! Bookkeeping for a single agent run, handed to the tracking integrations when it ends.
+
+use crate::agent::{ChatClient, Usage};
+use crate::options::AgentOptions;
+use crate::retry::{Retries, Retry};
+use crate::tool_usage::Tallies;
+use crate::{pricing, tracking};
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use serde_json::{Map, Value};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+static RUN_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Caller-supplied `tags` and `metadata` for slicing runs in dashboards, copied onto the
+/// run record, every audit record and the experiment trackers.
+#[derive(Clone, Default)]
+pub(crate) struct Labels {
+    pub(crate) tags: Vec<String>,
+    pub(crate) metadata: Map<String, Value>,
+}
+
+impl Labels {
+    pub(crate) fn from_py(
+        py: Python,
+        tags: Option<Vec<String>>,
+        metadata: Option<&Py<PyDict>>,
+    ) -> PyResult<Self> {
+        let metadata = match metadata {
+            Some(metadata) => pythonize::depythonize(metadata.bind(py).as_any())?,
+            None => Map::new(),
+        };
+        Ok(Self {
+            tags: tags.unwrap_or_default(),
+            metadata,
+        })
+    }
+}
+
+pub(crate) struct RunRecord {
+    /// Unique, lowercase alphanumeric id (accepted as a W&B run name).
+    pub(crate) id: String,
+    pub(crate) provider: &'static str,
+    pub(crate) model: String,
+    pub(crate) query: String,
+    pub(crate) tools: Vec<String>,
+    pub(crate) language: Option<String>,
+    pub(crate) stream: bool,
+    pub(crate) started_at: SystemTime,
+    pub(crate) latency: Duration,
+    /// Number of requests sent to the provider.
+    pub(crate) model_calls: usize,
+    pub(crate) tool_calls: usize,
+    /// Executions per tool, with their failures, latency and result size.
+    pub(crate) tool_usage: Tallies,
+    pub(crate) usage: Usage,
+    /// The part of `usage` spent on a `tool_model`, and that model.
+    pub(crate) tool_model_usage: Option<(String, Usage)>,
+    pub(crate) retries: Retries,
+    pub(crate) output: Option<String>,
+    /// The answer as the model wrote it, when `output_language` translated it.
+    pub(crate) original_answer: Option<String>,
+    pub(crate) error: Option<String>,
+    pub(crate) labels: Labels,
+    /// Streamed runs only: time from the start of the run to the first text chunk.
+    pub(crate) time_to_first_token: Option<Duration>,
+    /// Streamed runs only: output rate of the last streamed request after its first chunk.
+    pub(crate) tokens_per_second: Option<f64>,
+    timer: Instant,
+}
+
+impl RunRecord {
+    pub(crate) fn start<C: ChatClient>(
+        client: &C,
+        options: &AgentOptions,
+        query: &str,
+        tools: Vec<String>,
+        stream: bool,
+        labels: Labels,
+    ) -> Self {
+        let started_at = SystemTime::now();
+        let nanos = started_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_nanos();
+        let sequence = RUN_COUNTER.fetch_add(1, Ordering::Relaxed);
+        Self {
+            id: format!("{:x}{:04x}", nanos, sequence & 0xffff),
+            provider: client.provider(),
+            model: client.model().to_string(),
+            query: query.to_string(),
+            tools,
+            language: options.language.clone(),
+            stream,
+            started_at,
+            latency: Duration::ZERO,
+            model_calls: 0,
+            tool_calls: 0,
+            tool_usage: Tallies::new(),
+            usage: Usage::default(),
+            tool_model_usage: None,
+            retries: Retries::default(),
+            output: None,
+            original_answer: None,
+            error: None,
+            labels,
+            time_to_first_token: None,
+            tokens_per_second: None,
+            timer: Instant::now(),
+        }
+    }
+
+    pub(crate) fn record_call(&mut self, usage: Usage) {
+        self.model_calls += 1;
+        self.usage += usage;
+    }
+
+    /// `record_call` for a request answered by `model` rather than the run's model.
+    pub(crate) fn record_model_call(&mut self, model: &str, usage: Usage) {
+        self.record_call(usage);
+        if model != self.model {
+            self.tool_model_usage
+                .get_or_insert_with(|| (model.to_string(), Usage::default()))
+                .1 += usage;
+        }
+    }
+
+    /// Usage per model: the run's own, then its `tool_model`'s if one was used.
+    pub(crate) fn usage_by_model(&self) -> Vec<(&str, Usage)> {
+        let Some((tool_model, tool_usage)) = &self.tool_model_usage else {
+            return vec![(&self.model, self.usage)];
+        };
+        let own = Usage {
+            input_tokens: self.usage.input_tokens - tool_usage.input_tokens,
+            output_tokens: self.usage.output_tokens - tool_usage.output_tokens,
+        };
+        vec![(&self.model, own), (tool_model, *tool_usage)]
+    }
+
+    /// Record the retries of a provider request and the status it ended with.
+    pub(crate) fn record_retries(&mut self, retries: &[Retry], status: &str) {
+        self.retries.record(retries, status);
+    }
+
+    /// Record a streamed request whose first chunk arrived at `first_chunk`.
+    pub(crate) fn record_stream(&mut self, first_chunk: Instant, tokens_per_second: Option<f64>) {
+        self.time_to_first_token
+            .get_or_insert_with(|| first_chunk.saturating_duration_since(self.timer));
+        if tokens_per_second.is_some() {
+            self.tokens_per_second = tokens_per_second;
+        }
+    }
+
+    pub(crate) fn record_tool_call(&mut self) {
+        self.tool_calls += 1;
+    }
+
+    /// Record a tool execution; `result_bytes` is `None` when the tool raised.
+    pub(crate) fn record_tool(
+        &mut self,
+        name: &str,
+        elapsed: Duration,
+        result_bytes: Option<usize>,
+    ) {
+        self.tool_usage
+            .entry(name.to_string())
+            .or_default()
+            .record(elapsed, result_bytes);
+    }
+
+    pub(crate) fn cost_usd(&self) -> Option<f64> {
+        self.usage_by_model()
+            .into_iter()
+            .map(|(model, usage)| pricing::cost_usd(model, usage))
+            .sum()
+    }
+
+    /// Time since the run started.
+    pub(crate) fn elapsed(&self) -> Duration {
+        self.timer.elapsed()
+    }
+
+    /// Close the run with its final answer or error and hand it to the trackers.
+    pub(crate) fn finish(mut self, outcome: Result<String, String>) {
+        self.latency = self.timer.elapsed();
+        match outcome {
+            Ok(output) => self.output = Some(output),
+            Err(error) => self.error = Some(error),
+        }
+        tracking::log_run(self);
+    }
+}
@@ -0,0 +1,308 @@
+//! The tokio runtime every request runs on: how it is built, and shutting it down.
+//! On shutdown new calls are refused, calls in flight are waited for, then the runtime
+//! is dropped.
+
+use crate::error::RustedChainError;
+use crate::{tracking, usage_webhook};
+use once_cell::sync::Lazy;
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use serde_json::{json, Value};
+use std::cell::Cell;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+#[cfg(feature = "pgvector")]
+use tokio::runtime::EnterGuard;
+use tokio::runtime::{Builder, Handle, Runtime};
+use tokio::task::JoinHandle;
+
+/// How the runtime is built, set by `configure_runtime` before its first use.
+struct RuntimeConfig {
+    worker_threads: Option<usize>,
+    thread_name: Option<String>,
+    current_thread: bool,
+}
+
+static CONFIG: Mutex<RuntimeConfig> = Mutex::new(RuntimeConfig {
+    worker_threads: None,
+    thread_name: None,
+    current_thread: false,
+});
+
+struct State {
+    accepting: bool,
+    /// Threads inside an admitted call.
+    in_flight: usize,
+}
+
+/// How long a shutdown without a timeout gives tracker uploads and background tasks.
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+static STATE: Mutex<State> = Mutex::new(State {
+    accepting: true,
+    in_flight: 0,
+});
+static DRAINED: Condvar = Condvar::new();
+
+thread_local! {
+    /// Admitted calls on this thread, so a call made inside one (the next request of a
+    /// tool loop, a model used as a tool) is let through during a shutdown.
+    static ADMITTED: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Held for the length of a call; the runtime is not torn down while any is alive.
+pub(crate) struct InFlight {
+    /// Admission is counted per thread.
+    _thread: PhantomData<*const ()>,
+}
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        let depth = ADMITTED.get() - 1;
+        ADMITTED.set(depth);
+        if depth == 0 {
+            let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+            state.in_flight -= 1;
+            DRAINED.notify_all();
+        }
+    }
+}
+
+/// Start a call, unless a shutdown has begun and this thread is not already in one.
+pub(crate) fn admit() -> Result<InFlight, RustedChainError> {
+    if ADMITTED.get() == 0 {
+        let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+        if !state.accepting {
+            return Err(RustedChainError::ShutDown);
+        }
+        state.in_flight += 1;
+    }
+    ADMITTED.set(ADMITTED.get() + 1);
+    Ok(InFlight {
+        _thread: PhantomData,
+    })
+}
+
+/// Fail early, on the caller's thread, for a call that will be admitted elsewhere.
+pub(crate) fn check() -> Result<(), RustedChainError> {
+    let state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    if state.accepting || ADMITTED.get() > 0 {
+        Ok(())
+    } else {
+        Err(RustedChainError::ShutDown)
+    }
+}
+
+/// What a refused `block_on` returns in place of the future's output.
+pub(crate) trait Refusable {
+    fn refused(err: RustedChainError) -> Self;
+}
+
+impl<T, E: From<RustedChainError>> Refusable for Result<T, E> {
+    fn refused(err: RustedChainError) -> Self {
+        Err(err.into())
+    }
+}
+
+/// An output with something recorded alongside it, such as the retries made.
+impl<R: Refusable, T: Default> Refusable for (R, T) {
+    fn refused(err: RustedChainError) -> Self {
+        (R::refused(err), T::default())
+    }
+}
+
+impl<T> Refusable for Option<T> {
+    fn refused(_: RustedChainError) -> Self {
+        None
+    }
+}
+
+impl Refusable for () {
+    fn refused(_: RustedChainError) {}
+}
+
+pub(crate) struct SharedRuntime {
+    runtime: Mutex<Option<Arc<Runtime>>>,
+    handle: Handle,
+}
+
+impl SharedRuntime {
+    pub(crate) fn new() -> Self {
+        let config = CONFIG.lock().unwrap_or_else(|e| e.into_inner());
+        let mut builder = if config.current_thread {
+            Builder::new_current_thread()
+        } else {
+            Builder::new_multi_thread()
+        };
+        builder.enable_all();
+        if let Some(threads) = config.worker_threads {
+            builder.worker_threads(threads);
+        }
+        if let Some(name) = &config.thread_name {
+            builder.thread_name(name.clone());
+        }
+        let runtime = builder.build().expect("Failed to create tokio runtime");
+        Self {
+            handle: runtime.handle().clone(),
+            runtime: Mutex::new(Some(Arc::new(runtime))),
+        }
+    }
+
+    /// Run `future` to completion as an admitted call, or return its refusal.
+    pub(crate) fn block_on<F>(&self, future: F) -> F::Output
+    where
+        F: Future,
+        F::Output: Refusable,
+    {
+        let in_flight = match admit() {
+            Ok(in_flight) => in_flight,
+            Err(err) => return F::Output::refused(err),
+        };
+        // A current-thread runtime only drives IO and timers inside `Runtime::block_on`.
+        let runtime = self
+            .runtime
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .clone();
+        let output = match runtime {
+            Some(runtime) => runtime.block_on(future),
+            None => F::Output::refused(RustedChainError::ShutDown),
+        };
+        drop(in_flight);
+        output
+    }
+
+    pub(crate) fn spawn<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        self.handle.spawn(future)
+    }
+
+    #[cfg(feature = "pgvector")]
+    pub(crate) fn enter(&self) -> EnterGuard<'_> {
+        self.handle.enter()
+    }
+}
+
+/// Stop accepting new calls, wait up to `timeout` seconds (forever if `None`) for
+/// calls in flight, pending tracker uploads and queued usage webhook events, then shut
+/// the runtime down. Returns whether everything finished in time; if not, the runtime
+/// is left running for the calls still going. New calls raise `RuntimeError` either way.
+#[pyfunction]
+#[pyo3(signature = (timeout=None))]
+pub fn shutdown(py: Python, timeout: Option<f64>) -> bool {
+    let deadline =
+        timeout.map(|seconds| Instant::now() + Duration::from_secs_f64(seconds.max(0.0)));
+    let remaining = || deadline.map(|at| at.saturating_duration_since(Instant::now()));
+    py.detach(|| {
+        let mut state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+        state.accepting = false;
+        while state.in_flight > 0 {
+            state = match remaining() {
+                Some(wait) if wait.is_zero() => return false,
+                Some(wait) => {
+                    DRAINED
+                        .wait_timeout(state, wait)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0
+                }
+                None => DRAINED.wait(state).unwrap_or_else(|e| e.into_inner()),
+            };
+        }
+        drop(state);
+        let Some(runtime) = crate::RUNTIME
+            .runtime
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .take()
+        else {
+            return true;
+        };
+        runtime.block_on(tracking::drain(remaining().unwrap_or(FLUSH_TIMEOUT)));
+        runtime.block_on(usage_webhook::drain(remaining().unwrap_or(FLUSH_TIMEOUT)));
+        // Nothing is in flight, so no call holds another reference.
+        if let Ok(runtime) = Arc::try_unwrap(runtime) {
+            runtime.shutdown_timeout(remaining().unwrap_or(FLUSH_TIMEOUT));
+        }
+        true
+    })
+}
+
+/// How the runtime is configured and whether it is taking calls, for `diagnostics()`.
+pub(crate) fn status() -> Value {
+    let config = CONFIG.lock().unwrap_or_else(|e| e.into_inner());
+    let state = STATE.lock().unwrap_or_else(|e| e.into_inner());
+    let started = Lazy::get(&crate::RUNTIME).is_some();
+    let running = started
+        && crate::RUNTIME
+            .runtime
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .is_some();
+    // Tokio starts one worker per core unless told otherwise.
+    let worker_threads = match config.current_thread {
+        true => None,
+        false => config.worker_threads.or_else(|| {
+            std::thread::available_parallelism()
+                .ok()
+                .map(|cores| cores.get())
+        }),
+    };
+    json!({
+        "started": started,
+        "running": running,
+        "flavor": if config.current_thread { "current_thread" } else { "multi_thread" },
+        "worker_threads": worker_threads,
+        "thread_name": config.thread_name,
+        "accepting_calls": state.accepting,
+        "in_flight": state.in_flight,
+    })
+}
+
+/// Set how the runtime behind every request is built. Call it before the first
+/// request; the runtime starts on first use and cannot be rebuilt. `flavor` is
+/// `"multi_thread"` (the default, with `worker_threads` threads, one per core unless
+/// set) or `"current_thread"`, which runs everything on the calling threads, for
+/// constrained environments such as AWS Lambda. `thread_name` names the worker threads.
+#[pyfunction]
+#[pyo3(signature = (worker_threads=None, thread_name=None, flavor="multi_thread"))]
+pub fn configure_runtime(
+    worker_threads: Option<usize>,
+    thread_name: Option<String>,
+    flavor: &str,
+) -> PyResult<()> {
+    let current_thread = match flavor {
+        "multi_thread" => false,
+        "current_thread" => true,
+        other => {
+            return Err(PyValueError::new_err(format!(
+                "flavor must be 'multi_thread' or 'current_thread', got '{}'",
+                other
+            )))
+        }
+    };
+    if worker_threads == Some(0) {
+        return Err(PyValueError::new_err("worker_threads must be at least 1"));
+    }
+    if current_thread && worker_threads.is_some() {
+        return Err(PyValueError::new_err(
+            "worker_threads only applies to the 'multi_thread' flavor",
+        ));
+    }
+    let mut config = CONFIG.lock().unwrap_or_else(|e| e.into_inner());
+    if Lazy::get(&crate::RUNTIME).is_some() {
+        return Err(PyRuntimeError::new_err(
+            "the runtime has already started; call configure_runtime before the first request",
+        ));
+    }
+    *config = RuntimeConfig {
+        worker_threads,
+        thread_name,
+        current_thread,
+    };
+    Ok(())
+}
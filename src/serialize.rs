@@ -0,0 +1,298 @@
+//! Conversion of what Python tools return into the JSON sent to the model. Types JSON
+//! has no form for (datetimes, `Decimal`, numpy values, ...) get a sensible one
+//! instead of becoming `null`.
+
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{
+    PyBool, PyBytes, PyDict, PyFloat, PyFrozenSet, PyInt, PyList, PySet, PyString, PyTuple,
+};
+use serde_json::{Map, Number, Value};
+use std::sync::Arc;
+
+/// Nesting deeper than this is taken for a cycle, or an encoder that never finishes.
+const MAX_DEPTH: usize = 100;
+
+pub(crate) struct Serializer {
+    ensure_ascii: bool,
+    float_digits: Option<i32>,
+    /// NaN and infinities as `"NaN"`, `"Infinity"` and `"-Infinity"` rather than `null`.
+    non_finite_strings: bool,
+    /// Datetimes as Unix timestamps rather than ISO 8601 strings.
+    timestamps: bool,
+    encoder: Option<Py<PyAny>>,
+}
+
+/// Used when an agent sets no `tool_serializer`.
+pub(crate) static DEFAULT: Serializer = Serializer {
+    ensure_ascii: false,
+    float_digits: None,
+    non_finite_strings: true,
+    timestamps: false,
+    encoder: None,
+};
+
+/// How tool results are turned into JSON. Built in: datetimes, dates and times as
+/// ISO 8601 (or Unix timestamps with `datetimes="timestamp"`), timedeltas as seconds,
+/// `Decimal` as a number, numpy scalars and arrays as numbers and lists, UUIDs and
+/// paths as strings, enums as their value, and dataclasses and pydantic models as
+/// objects. Anything else goes to `encoder`, which returns something serializable,
+/// like `json.dumps(default=...)`; without one it becomes its `str()`.
+///
+/// `float_digits` rounds floats; `non_finite` is `"string"` (the default, `"NaN"`) or
+/// `"null"`. `ensure_ascii` escapes non-ASCII characters in results sent as JSON text
+/// (OpenAI-compatible APIs), as Python's `json.dumps` does by default.
+#[pyclass(frozen)]
+pub struct ToolSerializer {
+    pub(crate) serializer: Arc<Serializer>,
+}
+
+#[pymethods]
+impl ToolSerializer {
+    #[new]
+    #[pyo3(signature = (ensure_ascii=false, float_digits=None, non_finite="string", datetimes="iso", encoder=None))]
+    fn new(
+        ensure_ascii: bool,
+        float_digits: Option<i32>,
+        non_finite: &str,
+        datetimes: &str,
+        encoder: Option<Bound<'_, PyAny>>,
+    ) -> PyResult<Self> {
+        let non_finite_strings = match non_finite {
+            "string" => true,
+            "null" => false,
+            other => {
+                return Err(PyValueError::new_err(format!(
+                    "non_finite must be 'string' or 'null', got '{}'",
+                    other
+                )))
+            }
+        };
+        let timestamps = match datetimes {
+            "iso" => false,
+            "timestamp" => true,
+            other => {
+                return Err(PyValueError::new_err(format!(
+                    "datetimes must be 'iso' or 'timestamp', got '{}'",
+                    other
+                )))
+            }
+        };
+        if encoder
+            .as_ref()
+            .is_some_and(|encoder| !encoder.is_callable())
+        {
+            return Err(PyTypeError::new_err("encoder must be callable"));
+        }
+        Ok(ToolSerializer {
+            serializer: Arc::new(Serializer {
+                ensure_ascii,
+                float_digits,
+                non_finite_strings,
+                timestamps,
+                encoder: encoder.map(Bound::unbind),
+            }),
+        })
+    }
+
+    /// `value` as it would be sent to the model, as JSON text.
+    fn dumps(&self, value: &Bound<'_, PyAny>) -> PyResult<String> {
+        let json = self.serializer.to_json(value)?;
+        Ok(self.serializer.text(&json))
+    }
+
+    fn __repr__(&self) -> String {
+        let serializer = &self.serializer;
+        format!(
+            "ToolSerializer(ensure_ascii={}, float_digits={}, non_finite='{}', datetimes='{}')",
+            if serializer.ensure_ascii {
+                "True"
+            } else {
+                "False"
+            },
+            serializer
+                .float_digits
+                .map_or("None".to_string(), |digits| digits.to_string()),
+            if serializer.non_finite_strings {
+                "string"
+            } else {
+                "null"
+            },
+            if serializer.timestamps {
+                "timestamp"
+            } else {
+                "iso"
+            },
+        )
+    }
+}
+
+impl Serializer {
+    /// The `tool_serializer` option: a `ToolSerializer`, or an encoder callable for the
+    /// default settings.
+    pub(crate) fn from_py(value: &Bound<'_, PyAny>) -> PyResult<Arc<Self>> {
+        if let Ok(serializer) = value.cast::<ToolSerializer>() {
+            return Ok(serializer.get().serializer.clone());
+        }
+        if value.is_callable() {
+            return Ok(
+                ToolSerializer::new(false, None, "string", "iso", Some(value.clone()))?.serializer,
+            );
+        }
+        Err(PyTypeError::new_err(
+            "tool_serializer must be a ToolSerializer or an encoder callable",
+        ))
+    }
+
+    /// Whether results must be sent as ASCII-only JSON text.
+    pub(crate) fn ensure_ascii(&self) -> bool {
+        self.ensure_ascii
+    }
+
+    /// `value` as JSON text, escaped to ASCII under `ensure_ascii`.
+    pub(crate) fn text(&self, value: &Value) -> String {
+        let text = value.to_string();
+        if !self.ensure_ascii {
+            return text;
+        }
+        let mut out = String::with_capacity(text.len());
+        for c in text.chars() {
+            if c.is_ascii() {
+                out.push(c);
+                continue;
+            }
+            let mut units = [0u16; 2];
+            for unit in c.encode_utf16(&mut units) {
+                out.push_str(&format!("\\u{:04x}", unit));
+            }
+        }
+        out
+    }
+
+    pub(crate) fn to_json(&self, value: &Bound<'_, PyAny>) -> PyResult<Value> {
+        self.convert(value, 0)
+    }
+
+    fn convert(&self, value: &Bound<'_, PyAny>, depth: usize) -> PyResult<Value> {
+        if depth > MAX_DEPTH {
+            return Err(PyValueError::new_err(
+                "tool result nests too deeply to serialize (a cycle, or an encoder returning \
+                 what it was given?)",
+            ));
+        }
+        let py = value.py();
+        if value.is_none() {
+            return Ok(Value::Null);
+        }
+        if let Ok(flag) = value.cast::<PyBool>() {
+            return Ok(Value::Bool(flag.is_true()));
+        }
+        if value.is_instance_of::<PyInt>() {
+            if let Ok(number) = value.extract::<i64>() {
+                return Ok(Value::from(number));
+            }
+            if let Ok(number) = value.extract::<u64>() {
+                return Ok(Value::from(number));
+            }
+            // Larger than JSON numbers are read back exactly.
+            return Ok(Value::String(value.str()?.to_string()));
+        }
+        if let Ok(float) = value.cast::<PyFloat>() {
+            return Ok(self.float(float.value()));
+        }
+        if let Ok(text) = value.cast::<PyString>() {
+            return Ok(Value::String(text.to_string()));
+        }
+        if let Ok(dict) = value.cast::<PyDict>() {
+            let mut map = Map::new();
+            for (key, item) in dict.iter() {
+                let key = match key.cast::<PyString>() {
+                    Ok(key) => key.to_string(),
+                    Err(_) => match self.convert(&key, depth + 1)? {
+                        Value::String(key) => key,
+                        other => other.to_string(),
+                    },
+                };
+                map.insert(key, self.convert(&item, depth + 1)?);
+            }
+            return Ok(Value::Object(map));
+        }
+        if value.is_instance_of::<PyList>()
+            || value.is_instance_of::<PyTuple>()
+            || value.is_instance_of::<PySet>()
+            || value.is_instance_of::<PyFrozenSet>()
+        {
+            return value
+                .try_iter()?
+                .map(|item| self.convert(&item?, depth + 1))
+                .collect::<PyResult<Vec<_>>>()
+                .map(Value::Array);
+        }
+        if let Ok(bytes) = value.cast::<PyBytes>() {
+            return Ok(Value::String(
+                String::from_utf8_lossy(bytes.as_bytes()).into_owned(),
+            ));
+        }
+
+        let kind = value.get_type();
+        let module = kind.module()?.to_string();
+        let name = kind.name()?.to_string();
+        match (module.split('.').next().unwrap_or_default(), name.as_str()) {
+            ("datetime", "datetime") if self.timestamps => {
+                return Ok(self.float(value.call_method0("timestamp")?.extract()?))
+            }
+            ("datetime", "datetime" | "date" | "time") => {
+                return Ok(Value::String(value.call_method0("isoformat")?.extract()?))
+            }
+            ("datetime", "timedelta") => {
+                return Ok(self.float(value.call_method0("total_seconds")?.extract()?))
+            }
+            ("decimal", "Decimal") => {
+                return Ok(self.float(value.call_method0("__float__")?.extract()?))
+            }
+            ("uuid", _) | ("pathlib", _) | ("ipaddress", _) => {
+                return Ok(Value::String(value.str()?.to_string()))
+            }
+            // Scalars and arrays both turn into plain Python values.
+            ("numpy", _) if value.hasattr("tolist")? => {
+                return self.convert(&value.call_method0("tolist")?, depth + 1)
+            }
+            _ => {}
+        }
+        let enum_type = py.import("enum")?.getattr("Enum")?;
+        if value.is_instance(&enum_type)? {
+            return self.convert(&value.getattr("value")?, depth + 1);
+        }
+        if value.hasattr("model_dump")? {
+            return self.convert(&value.call_method0("model_dump")?, depth + 1);
+        }
+        if value.hasattr("__dataclass_fields__")? && !value.is_instance_of::<pyo3::types::PyType>()
+        {
+            let fields = py.import("dataclasses")?.call_method1("asdict", (value,))?;
+            return self.convert(&fields, depth + 1);
+        }
+        match &self.encoder {
+            Some(encoder) => self.convert(&encoder.bind(py).call1((value,))?, depth + 1),
+            None => Ok(Value::String(value.str()?.to_string())),
+        }
+    }
+
+    fn float(&self, value: f64) -> Value {
+        if !value.is_finite() {
+            return match self.non_finite_strings {
+                true if value.is_nan() => Value::from("NaN"),
+                true if value > 0.0 => Value::from("Infinity"),
+                true => Value::from("-Infinity"),
+                false => Value::Null,
+            };
+        }
+        let value = match self.float_digits {
+            Some(digits) => {
+                let scale = 10f64.powi(digits);
+                (value * scale).round() / scale
+            }
+            None => value,
+        };
+        Number::from_f64(value).map_or(Value::Null, Value::Number)
+    }
+}
@@ -0,0 +1,113 @@
+//! Text streams formatted for servers: server-sent events, or messages for a WebSocket.
+//!
+//! The channel behind a `TextStream` is bounded, so a consumer that falls behind holds
+//! back the provider stream instead of losing chunks. Reading from the iterator is what
+//! makes room, which is also what a server does as it writes events out.
+
+use crate::stream::TextStream;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::time::Duration;
+
+/// An event written to the client.
+enum Event {
+    Chunk(String),
+    KeepAlive,
+    End,
+    Error(String),
+}
+
+impl Event {
+    /// The event in `text/event-stream` format. Each line of the text gets its own
+    /// `data` field, so a client joins them back with newlines.
+    fn sse(&self) -> String {
+        match self {
+            Event::Chunk(text) => format!("{}\n", data(text)),
+            Event::KeepAlive => ": keep-alive\n\n".to_string(),
+            Event::End => "event: end\ndata: \n\n".to_string(),
+            Event::Error(message) => format!("event: error\n{}\n", data(message)),
+        }
+    }
+
+    fn message<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let message = PyDict::new(py);
+        match self {
+            Event::Chunk(text) => {
+                message.set_item("type", "chunk")?;
+                message.set_item("text", text)?;
+            }
+            Event::KeepAlive => message.set_item("type", "keepalive")?,
+            Event::End => message.set_item("type", "end")?,
+            Event::Error(text) => {
+                message.set_item("type", "error")?;
+                message.set_item("message", text)?;
+            }
+        }
+        Ok(message)
+    }
+}
+
+fn data(text: &str) -> String {
+    text.split('\n')
+        .map(|line| format!("data: {line}\n"))
+        .collect()
+}
+
+/// Iterator over a text stream's chunks as server events, with keep-alives while the
+/// model is quiet, e.g. between tool calls.
+#[pyclass]
+pub struct EventStream {
+    inner: Py<TextStream>,
+    keepalive: Option<Duration>,
+    sse: bool,
+    finished: bool,
+}
+
+impl EventStream {
+    pub(crate) fn new(inner: Py<TextStream>, keepalive: Option<f64>, sse: bool) -> PyResult<Self> {
+        let keepalive = match keepalive {
+            Some(seconds) if !(seconds.is_finite() && seconds > 0.0) => {
+                return Err(PyValueError::new_err(format!(
+                    "keepalive must be a positive number of seconds, got {seconds}"
+                )))
+            }
+            seconds => seconds.map(Duration::from_secs_f64),
+        };
+        Ok(Self {
+            inner,
+            keepalive,
+            sse,
+            finished: false,
+        })
+    }
+}
+
+#[pymethods]
+impl EventStream {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&mut self, py: Python) -> PyResult<Option<Py<PyAny>>> {
+        if self.finished {
+            return Ok(None);
+        }
+        let next = self
+            .inner
+            .bind(py)
+            .borrow()
+            .next_chunk_within(py, self.keepalive);
+        let event = match next {
+            Ok(Some(Some(text))) => Event::Chunk(text),
+            Ok(Some(None)) => Event::End,
+            Ok(None) => Event::KeepAlive,
+            Err(err) => Event::Error(err.value(py).to_string()),
+        };
+        self.finished = matches!(event, Event::End | Event::Error(_));
+        Ok(Some(match self.sse {
+            true => event.sse().into_pyobject(py)?.into_any().unbind(),
+            false => event.message(py)?.into_any().unbind(),
+        }))
+    }
+}
@@ -0,0 +1,844 @@
+//! Multi-turn conversations: a model plus the history sent along with each message.
+
+use crate::agent::{self, ChatClient, ToolRequest, Usage};
+use crate::client::{self, with_client, AnyClient};
+use crate::downgrade::{DowngradePolicy, Spend};
+use crate::export;
+use crate::memory::MemoryPolicy;
+use crate::presets;
+use crate::roles;
+use crate::tool_versions::{self, ToolSchemas};
+use crate::transcript;
+use crate::transforms::strip_fences;
+use crate::{AgentResponse, ClaudeModel, GeminiModel, HuggingFaceModel, OpenAIModel, RUNTIME};
+use pyo3::exceptions::{PyOSError, PyUserWarning, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList};
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::CString;
+use std::path::PathBuf;
+
+const TITLE_PROMPT: &str = "Write a short title, at most six words, for the conversation \
+     below. Reply with the title only, without quotes.";
+
+const SCHEMA_PREFIX: &str = "rusted_chain.session/";
+const VERSION: u64 = 1;
+
+const SUMMARY_PROMPT: &str = "Summarize the conversation below in two or three sentences: \
+     what the user wanted and what was concluded. Reply with the summary only.";
+
+const CARRY_PROMPT: &str = "The conversation below is about to be dropped from memory. \
+     Write down what is needed to continue it, as a JSON object with \"summary\": a short \
+     paragraph on what the user wanted, what was done and what is still open, and \
+     \"facts\": a list of short, self-contained facts to remember, such as names, \
+     preferences, decisions, constraints and figures. Fold in the earlier summary and facts \
+     given below, if any, leaving out facts that no longer hold. Reply with the JSON only.";
+
+const FACTS_PROMPT: &str = "Below are the facts remembered so far about the user and \
+     this conversation, as a JSON object, then the latest exchange. Reply with a JSON object \
+     of changes only: new or corrected facts as short strings under short snake_case keys \
+     (such as \"name\", \"preferred_language\" or \"budget\"), and null for facts that no \
+     longer hold. Keep only lasting facts: names, preferences, constraints and decisions. \
+     Reply with {} if nothing changed.";
+
+/// What a session keeps of the history it has dropped for its token budget.
+#[derive(Clone, Default)]
+struct Carried {
+    summary: String,
+    facts: Vec<String>,
+}
+
+impl Carried {
+    /// The opening exchange that gives the model the dropped history.
+    fn entries(&self) -> [Entry; 2] {
+        let mut text = format!("Summary of our conversation so far: {}", self.summary);
+        if !self.facts.is_empty() {
+            text.push_str("\n\nKey facts:");
+            for fact in &self.facts {
+                text.push_str("\n- ");
+                text.push_str(fact);
+            }
+        }
+        [
+            Entry::User(text),
+            Entry::Assistant("Understood, I'll keep that in mind.".to_string()),
+        ]
+    }
+
+    /// Read a summary model's answer to `CARRY_PROMPT`. An answer that isn't the JSON
+    /// asked for is taken as the summary, keeping the earlier facts.
+    fn parse(answer: &str, earlier: Option<&Carried>) -> Self {
+        let facts = earlier
+            .map(|earlier| earlier.facts.clone())
+            .unwrap_or_default();
+        match serde_json::from_str::<Value>(&strip_fences(answer)) {
+            Ok(value @ Value::Object(_)) => Self::from_json(&value, facts),
+            _ => Carried {
+                summary: answer.trim().to_string(),
+                facts,
+            },
+        }
+    }
+
+    /// `{"summary", "facts"}`, as saved or written by the summary model; `facts` stand
+    /// in for a missing list.
+    fn from_json(carried: &Value, facts: Vec<String>) -> Self {
+        Carried {
+            summary: carried["summary"]
+                .as_str()
+                .unwrap_or_default()
+                .trim()
+                .to_string(),
+            facts: match &carried["facts"] {
+                Value::Array(items) => items
+                    .iter()
+                    .filter_map(Value::as_str)
+                    .map(|fact| fact.trim().to_string())
+                    .filter(|fact| !fact.is_empty())
+                    .collect(),
+                _ => facts,
+            },
+        }
+    }
+
+    fn to_json(&self) -> Value {
+        json!({ "summary": self.summary, "facts": self.facts })
+    }
+}
+
+/// One message of a session's history, independent of any provider's format, so the
+/// conversation can continue with a model from another provider.
+#[derive(Clone)]
+pub(crate) enum Entry {
+    User(String),
+    Assistant(String),
+    ToolCall(ToolRequest),
+    ToolResult { call: ToolRequest, result: Value },
+}
+
+impl Entry {
+    /// A tool call and its result. Calls without an id (Gemini matches results by
+    /// name) get one, since OpenAI and Claude pair calls and results by id.
+    pub(crate) fn tool_exchange(call: &ToolRequest, result: &Value, position: usize) -> [Entry; 2] {
+        let mut call = call.clone();
+        call.id.get_or_insert_with(|| format!("call_{}", position));
+        [
+            Entry::ToolCall(call.clone()),
+            Entry::ToolResult {
+                call,
+                result: result.clone(),
+            },
+        ]
+    }
+
+    /// The entry in `C`'s message format.
+    pub(crate) fn to_message<C: ChatClient>(&self) -> C::Message {
+        match self {
+            Entry::User(text) => C::user_message(text),
+            Entry::Assistant(text) => C::assistant_message(text),
+            Entry::ToolCall(call) => C::tool_call_message(call),
+            Entry::ToolResult { call, result } => C::tool_result_message(call, result.clone()),
+        }
+    }
+
+    pub(crate) fn to_json(&self) -> Value {
+        match self {
+            Entry::User(text) => json!({ "role": "user", "content": text }),
+            Entry::Assistant(text) => json!({ "role": "assistant", "content": text }),
+            Entry::ToolCall(call) => json!({
+                "role": "assistant",
+                "tool_call": { "id": call.id, "name": call.name, "args": call.args },
+            }),
+            Entry::ToolResult { call, result } => json!({
+                "role": "tool",
+                "tool_call_id": call.id,
+                "name": call.name,
+                "content": result,
+            }),
+        }
+    }
+}
+
+/// A conversation with a model. Each message is sent with the earlier ones, and the
+/// model's tools run as usual in between.
+#[pyclass]
+pub struct Session {
+    model: Py<PyAny>,
+    summary_model: Option<Py<PyAny>>,
+    history: Vec<Entry>,
+    downgrade: Option<Py<DowngradePolicy>>,
+    spend: Spend,
+    /// `{"from", "to", "reason"}` once the downgrade policy has switched models.
+    model_switch: Option<Value>,
+    memory: Option<Py<MemoryPolicy>>,
+    /// Embeddings of history texts, for `memory`.
+    vectors: HashMap<String, Vec<f32>>,
+    /// The schemas of the tools the history was made with.
+    tool_schemas: ToolSchemas,
+    /// Rewrites the arguments of earlier calls to a tool whose schema has changed.
+    migrate: Option<Py<PyAny>>,
+    /// Tokens after which the history is summarized and dropped.
+    token_budget: Option<u64>,
+    /// Tokens spent since the history was last dropped.
+    working_tokens: u64,
+    /// The summary and facts of the dropped history, sent ahead of what is left.
+    carried: Option<Carried>,
+    /// Whether `facts` is updated after each turn.
+    remember_facts: bool,
+    /// What is known about the user and the conversation, sent in the system prompt.
+    facts: BTreeMap<String, String>,
+}
+
+#[pymethods]
+impl Session {
+    /// `model` answers the messages. `summary_model`, typically a cheaper one, writes
+    /// titles and summaries; it defaults to `model`. `downgrade`, a `DowngradePolicy`,
+    /// switches to a cheaper model once the session has spent enough. `memory`, a
+    /// `MemoryPolicy`, leaves repeated turns and tool results out of what is sent.
+    /// `migrate(name, args, old_schema, new_schema)` rewrites the arguments of earlier
+    /// calls to a tool whose schema has changed, or returns `None` to keep them as text.
+    /// Once the messages since the last reset have used `token_budget` tokens, the
+    /// summary model condenses the history into a summary and a list of key facts, which
+    /// are sent in its place from then on. With `remember_facts`, the summary model
+    /// updates `facts` after each turn, and they are added to the system prompt.
+    #[new]
+    #[pyo3(signature = (model, summary_model=None, downgrade=None, memory=None, migrate=None, token_budget=None, remember_facts=false))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        py: Python,
+        model: Py<PyAny>,
+        summary_model: Option<Py<PyAny>>,
+        downgrade: Option<Py<DowngradePolicy>>,
+        memory: Option<Py<MemoryPolicy>>,
+        migrate: Option<Py<PyAny>>,
+        token_budget: Option<u64>,
+        remember_facts: bool,
+    ) -> PyResult<Self> {
+        if token_budget == Some(0) {
+            return Err(PyValueError::new_err("token_budget must be positive"));
+        }
+        AnyClient::from_model(py, model.bind(py))?;
+        if let Some(summary_model) = &summary_model {
+            AnyClient::from_model(py, summary_model.bind(py))?;
+        }
+        if let Some(policy) = &downgrade {
+            let to = policy.get().to.bind(py);
+            if to.extract::<String>().is_err() {
+                AnyClient::from_model(py, to)?;
+            }
+        }
+        Ok(Session {
+            model,
+            summary_model,
+            history: Vec::new(),
+            downgrade,
+            spend: Spend::default(),
+            model_switch: None,
+            memory,
+            vectors: HashMap::new(),
+            tool_schemas: ToolSchemas::new(),
+            migrate,
+            token_budget,
+            working_tokens: 0,
+            carried: None,
+            remember_facts,
+            facts: BTreeMap::new(),
+        })
+    }
+
+    /// A session saved with `save` or `to_dict`, continued with `model`. Earlier calls
+    /// to tools whose schema has changed since are migrated, with a warning, before the
+    /// next message is sent. Usage and model switches start over. `source` may also be
+    /// a list of chat messages in OpenAI or Gemini format, or a mix of the two.
+    #[staticmethod]
+    #[pyo3(signature = (source, model, summary_model=None, downgrade=None, memory=None, migrate=None, token_budget=None, remember_facts=false))]
+    #[allow(clippy::too_many_arguments)]
+    fn load(
+        py: Python,
+        source: &Bound<'_, PyAny>,
+        model: Py<PyAny>,
+        summary_model: Option<Py<PyAny>>,
+        downgrade: Option<Py<DowngradePolicy>>,
+        memory: Option<Py<MemoryPolicy>>,
+        migrate: Option<Py<PyAny>>,
+        token_budget: Option<u64>,
+        remember_facts: bool,
+    ) -> PyResult<Self> {
+        let saved: Value = match source.extract::<PathBuf>() {
+            Ok(path) => {
+                let text = std::fs::read_to_string(&path).map_err(|e| {
+                    PyOSError::new_err(format!("cannot read {}: {}", path.display(), e))
+                })?;
+                serde_json::from_str(&text).map_err(|e| {
+                    PyValueError::new_err(format!("{} is not JSON: {}", path.display(), e))
+                })?
+            }
+            Err(_) => pythonize::depythonize(source)?,
+        };
+        let mut session = Session::new(
+            py,
+            model,
+            summary_model,
+            downgrade,
+            memory,
+            migrate,
+            token_budget,
+            remember_facts,
+        )?;
+        if let Value::Array(messages) = &saved {
+            session.history = roles::import(messages).map_err(PyValueError::new_err)?;
+            return Ok(session);
+        }
+        check(&saved).map_err(PyValueError::new_err)?;
+        session.history = export::messages(&saved).map_err(PyValueError::new_err)?;
+        session.tool_schemas = saved["tools"]
+            .as_object()
+            .into_iter()
+            .flatten()
+            .map(|(name, tool)| (name.clone(), tool["schema"].clone()))
+            .collect();
+        session.carried = saved
+            .get("carried")
+            .map(|carried| Carried::from_json(carried, Vec::new()));
+        session.facts = merge_facts(BTreeMap::new(), &saved["facts"]);
+        Ok(session)
+    }
+
+    /// The conversation and the versions of the tools it used, for `Session.load`.
+    fn to_dict<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        Ok(pythonize::pythonize(py, &self.to_json())?)
+    }
+
+    /// Write `to_dict()` to `path` as JSON.
+    fn save(&self, path: PathBuf) -> PyResult<()> {
+        std::fs::write(&path, self.to_json().to_string())
+            .map_err(|e| PyOSError::new_err(format!("cannot write {}: {}", path.display(), e)))
+    }
+
+    /// The version of each tool the history was made with, a hash of its name and
+    /// parameters.
+    #[getter]
+    fn tool_versions(&self) -> HashMap<String, String> {
+        self.tool_schemas
+            .iter()
+            .map(|(name, schema)| (name.clone(), tool_versions::version(schema)))
+            .collect()
+    }
+
+    /// Send `message` with the conversation so far. A text answer is added to the
+    /// history together with the message and any tool calls made on the way.
+    #[pyo3(signature = (message, tags=None, metadata=None))]
+    fn invoke(
+        &mut self,
+        py: Python,
+        message: String,
+        tags: Option<Vec<String>>,
+        metadata: Option<Py<PyDict>>,
+    ) -> PyResult<AgentResponse> {
+        let metadata = match self.apply_downgrade(py)? {
+            // The run that first uses the cheaper model records the switch.
+            Some(switch) => {
+                let labelled = match &metadata {
+                    Some(metadata) => metadata.bind(py).copy()?,
+                    None => PyDict::new(py),
+                };
+                labelled.set_item("model_downgrade", pythonize::pythonize(py, &switch)?)?;
+                Some(labelled.unbind())
+            }
+            None => metadata,
+        };
+        self.apply_budget(py)?;
+        let (mut client, options, tools) = AnyClient::from_agent(py, self.model.bind(py))?;
+        if !self.facts.is_empty() {
+            client = client.with_system_section(&self.facts_section());
+        }
+        let current = tool_versions::schemas(py, &tools);
+        if current != self.tool_schemas {
+            self.history = tool_versions::reconcile(
+                py,
+                &self.history,
+                &self.tool_schemas,
+                &current,
+                self.migrate.as_ref(),
+            )?;
+            self.tool_schemas = current;
+        }
+        let mut sent = match &self.memory {
+            Some(memory) => memory
+                .get()
+                .compress(py, &self.history, &mut self.vectors)?,
+            None => self.history.clone(),
+        };
+        if let Some(carried) = &self.carried {
+            sent.splice(0..0, carried.entries());
+        }
+        let earlier = sent.len();
+        let mut usage = Usage::default();
+        let response = with_client!(&client, client => agent::run_conversation(
+            py,
+            client,
+            &tools,
+            &options,
+            &mut sent,
+            &mut usage,
+            &message,
+            tags,
+            metadata,
+        ));
+        let start = self.history.len();
+        self.history.extend(sent.drain(earlier..));
+        let model = with_client!(&client, client => client.model().to_string());
+        self.spend.add(&model, usage);
+        self.working_tokens += usage.input_tokens + usage.output_tokens;
+        if self.remember_facts && response.is_ok() {
+            let turn = self.history[start..].to_vec();
+            self.update_facts(py, &turn)?;
+        }
+        response
+    }
+
+    /// Send `message` and return the answer text.
+    #[pyo3(signature = (message, tags=None, metadata=None))]
+    fn run(
+        &mut self,
+        py: Python,
+        message: String,
+        tags: Option<Vec<String>>,
+        metadata: Option<Py<PyDict>>,
+    ) -> PyResult<String> {
+        self.invoke(py, message, tags, metadata)?.text()
+    }
+
+    /// The conversation so far, as `{"role": ..., "content": ...}` dicts. Tool calls
+    /// appear as `{"role": "assistant", "tool_call": {...}}` followed by a `"tool"`
+    /// message with the result.
+    #[getter]
+    fn history<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyList>> {
+        let list = PyList::empty(py);
+        for entry in &self.history {
+            list.append(pythonize::pythonize(py, &entry.to_json())?)?;
+        }
+        Ok(list)
+    }
+
+    /// The conversation so far as the messages `format` (`"openai"`, `"gemini"` or
+    /// `"claude"`) sends, with that provider's roles.
+    #[pyo3(signature = (format="openai"))]
+    fn to_messages<'py>(&self, py: Python<'py>, format: &str) -> PyResult<Bound<'py, PyAny>> {
+        let messages = roles::export(&self.history, format).map_err(PyValueError::new_err)?;
+        Ok(pythonize::pythonize(py, &messages)?)
+    }
+
+    /// The conversation so far as Markdown, tool calls and results included.
+    fn to_markdown(&self) -> String {
+        transcript::to_markdown(&self.history)
+    }
+
+    /// The conversation so far as a self-contained HTML page.
+    fn to_html(&self) -> String {
+        transcript::to_html(&self.history)
+    }
+
+    /// Continue the conversation with another model: a model object, or a model name
+    /// such as `"claude-sonnet-4-5"` whose provider is recognised from the name. A name
+    /// keeps the current model's tools and options. The history, tool calls included,
+    /// is converted to the new provider's format.
+    fn set_model(&mut self, py: Python, model: &Bound<'_, PyAny>) -> PyResult<()> {
+        self.model = match model.extract::<String>() {
+            Ok(name) => model_named(py, self.model.bind(py), &name)?,
+            Err(_) => {
+                AnyClient::from_model(py, model)?;
+                model.clone().unbind()
+            }
+        };
+        Ok(())
+    }
+
+    /// The name of the model answering the messages.
+    #[getter]
+    fn model(&self, py: Python) -> PyResult<String> {
+        let (client, _) = AnyClient::from_model(py, self.model.bind(py))?;
+        Ok(with_client!(&client, client => client.model().to_string()))
+    }
+
+    /// Tokens and estimated cost so far: `{"input_tokens", "output_tokens",
+    /// "cost_usd"}`. Models without a known price add no cost.
+    #[getter]
+    fn usage<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyAny>> {
+        Ok(pythonize::pythonize(py, &self.spend.to_json())?)
+    }
+
+    /// `{"from", "to", "reason"}` once the downgrade policy has switched models,
+    /// otherwise `None`.
+    #[getter]
+    fn model_switch<'py>(&self, py: Python<'py>) -> PyResult<Option<Bound<'py, PyAny>>> {
+        self.model_switch
+            .as_ref()
+            .map(|switch| Ok(pythonize::pythonize(py, switch)?))
+            .transpose()
+    }
+
+    /// The summary of the history dropped for the token budget, or `None` while nothing
+    /// has been dropped.
+    #[getter]
+    fn carried_summary(&self) -> Option<String> {
+        self.carried.as_ref().map(|carried| carried.summary.clone())
+    }
+
+    /// The key facts kept from the history dropped for the token budget.
+    #[getter]
+    fn key_facts(&self) -> Vec<String> {
+        self.carried
+            .as_ref()
+            .map(|carried| carried.facts.clone())
+            .unwrap_or_default()
+    }
+
+    /// What is known about the user and the conversation, by short key. Kept up to date
+    /// with `remember_facts`, and may be set to seed or correct it.
+    #[getter]
+    fn facts(&self) -> BTreeMap<String, String> {
+        self.facts.clone()
+    }
+
+    #[setter]
+    fn set_facts(&mut self, facts: BTreeMap<String, String>) {
+        self.facts = facts;
+    }
+
+    /// Forget the conversation, including any summary and facts kept from it.
+    fn clear(&mut self) {
+        self.history.clear();
+        self.vectors.clear();
+        self.carried = None;
+        self.working_tokens = 0;
+        self.facts.clear();
+    }
+
+    /// A short title for the conversation, written by `model` or the summary model.
+    #[pyo3(signature = (model=None))]
+    fn title(&self, py: Python, model: Option<Py<PyAny>>) -> PyResult<String> {
+        let title = self.ask(py, model, TITLE_PROMPT)?;
+        Ok(title
+            .trim()
+            .trim_matches(|c: char| c == '"' || c == '\'' || c == '.')
+            .to_string())
+    }
+
+    /// A few sentences summarizing the conversation, written by `model` or the
+    /// summary model.
+    #[pyo3(signature = (model=None))]
+    fn summary(&self, py: Python, model: Option<Py<PyAny>>) -> PyResult<String> {
+        Ok(self.ask(py, model, SUMMARY_PROMPT)?.trim().to_string())
+    }
+
+    fn __len__(&self) -> usize {
+        self.history.len()
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Session(messages={})", self.history.len())
+    }
+}
+
+impl Session {
+    /// The conversation so far.
+    pub(crate) fn entries(&self) -> &[Entry] {
+        &self.history
+    }
+
+    /// Switch to the downgrade policy's model if the session has spent past its
+    /// limits, returning the switch. Happens at most once.
+    fn apply_downgrade(&mut self, py: Python) -> PyResult<Option<Value>> {
+        let Some(policy) = self.downgrade.as_ref().map(|policy| policy.clone_ref(py)) else {
+            return Ok(None);
+        };
+        let policy = policy.get();
+        if self.model_switch.is_some() {
+            return Ok(None);
+        }
+        let Some(reason) = policy.reason(&self.spend) else {
+            return Ok(None);
+        };
+        let from = self.model(py)?;
+        self.set_model(py, policy.to.bind(py))?;
+        let switch = json!({ "from": from, "to": self.model(py)?, "reason": reason });
+        self.model_switch = Some(switch.clone());
+        Ok(Some(switch))
+    }
+
+    /// Summarize and drop the history once the token budget is spent, keeping the summary
+    /// and key facts to send in its place.
+    fn apply_budget(&mut self, py: Python) -> PyResult<()> {
+        let over = self
+            .token_budget
+            .is_some_and(|budget| self.working_tokens >= budget);
+        if !over || self.history.is_empty() {
+            return Ok(());
+        }
+        let mut instruction = CARRY_PROMPT.to_string();
+        if let Some(carried) = &self.carried {
+            instruction.push_str(&format!(
+                "\n\nEarlier summary and facts:\n{}",
+                carried.to_json()
+            ));
+        }
+        let answer = self.ask(py, None, &instruction)?;
+        self.carried = Some(Carried::parse(&answer, self.carried.as_ref()));
+        self.history.clear();
+        self.vectors.clear();
+        self.working_tokens = 0;
+        Ok(())
+    }
+
+    fn to_json(&self) -> Value {
+        let tools: Map<String, Value> = self
+            .tool_schemas
+            .iter()
+            .map(|(name, schema)| {
+                let tool = json!({ "version": tool_versions::version(schema), "schema": schema });
+                (name.clone(), tool)
+            })
+            .collect();
+        let mut saved = json!({
+            "schema": format!("{}{}", SCHEMA_PREFIX, VERSION),
+            "messages": self.history.iter().map(Entry::to_json).collect::<Vec<_>>(),
+            "tools": tools,
+        });
+        if let Some(carried) = &self.carried {
+            saved["carried"] = carried.to_json();
+        }
+        if !self.facts.is_empty() {
+            saved["facts"] = json!(self.facts);
+        }
+        saved
+    }
+
+    /// The system prompt section listing `facts`.
+    fn facts_section(&self) -> String {
+        let mut section = "Facts remembered from this conversation:".to_string();
+        for (key, value) in &self.facts {
+            section.push_str(&format!("\n- {}: {}", key, value));
+        }
+        section
+    }
+
+    /// Have the summary model update `facts` from the exchange in `turn`. If that fails,
+    /// or the reply isn't a JSON object, they stay as they are, with a warning.
+    fn update_facts(&mut self, py: Python, turn: &[Entry]) -> PyResult<()> {
+        let instruction = format!("{}\n\nFacts: {}", FACTS_PROMPT, json!(self.facts));
+        let problem = match self.ask_about(py, None, &instruction, turn) {
+            Ok(answer) => match serde_json::from_str::<Value>(&strip_fences(&answer)) {
+                Ok(changes @ Value::Object(_)) => {
+                    self.facts = merge_facts(std::mem::take(&mut self.facts), &changes);
+                    return Ok(());
+                }
+                _ => format!("the summary model replied {:?}", answer),
+            },
+            Err(e) => e.to_string(),
+        };
+        let message = CString::new(format!("facts not updated: {}", problem)).unwrap_or_default();
+        PyErr::warn(py, &py.get_type::<PyUserWarning>(), &message, 1)
+    }
+
+    fn transcript(entries: &[Entry]) -> String {
+        entries
+            .iter()
+            .filter_map(|entry| match entry {
+                Entry::User(text) => Some(format!("User: {}", text)),
+                Entry::Assistant(text) => Some(format!("Assistant: {}", text)),
+                Entry::ToolCall(_) | Entry::ToolResult { .. } => None,
+            })
+            .collect::<Vec<_>>()
+            .join("\n\n")
+    }
+
+    /// Ask a tool-free model about the transcript.
+    fn ask(&self, py: Python, model: Option<Py<PyAny>>, instruction: &str) -> PyResult<String> {
+        if self.history.is_empty() {
+            return Err(PyValueError::new_err("the session has no messages yet"));
+        }
+        self.ask_about(py, model, instruction, &self.history)
+    }
+
+    /// Ask a tool-free model about the transcript of `entries`.
+    fn ask_about(
+        &self,
+        py: Python,
+        model: Option<Py<PyAny>>,
+        instruction: &str,
+        entries: &[Entry],
+    ) -> PyResult<String> {
+        let model = model
+            .or_else(|| self.summary_model.as_ref().map(|m| m.clone_ref(py)))
+            .unwrap_or_else(|| self.model.clone_ref(py));
+        let (client, options) = AnyClient::from_model(py, model.bind(py))?;
+        let prompt = format!("{}\n\n{}", instruction, Self::transcript(entries));
+        Ok(py.detach(|| RUNTIME.block_on(client::complete(&client, &options, &prompt)))?)
+    }
+}
+
+/// `facts` with `changes` applied: strings (or other values, as text) set a fact and
+/// `null` removes it.
+fn merge_facts(mut facts: BTreeMap<String, String>, changes: &Value) -> BTreeMap<String, String> {
+    for (key, value) in changes.as_object().into_iter().flatten() {
+        match value {
+            Value::Null => {
+                facts.remove(key);
+            }
+            Value::String(text) => {
+                facts.insert(key.clone(), text.trim().to_string());
+            }
+            other => {
+                facts.insert(key.clone(), other.to_string());
+            }
+        }
+    }
+    facts
+}
+
+/// Refuse saved sessions this version cannot read.
+fn check(saved: &Value) -> Result<(), String> {
+    let schema = saved["schema"].as_str().unwrap_or_default();
+    let version = schema
+        .strip_prefix(SCHEMA_PREFIX)
+        .and_then(|version| version.parse::<u64>().ok())
+        .ok_or_else(|| format!("not a saved session: schema '{}'", schema))?;
+    if version > VERSION {
+        return Err(format!(
+            "schema '{}' was written by a newer rusted_chain; this one reads up to {}{}",
+            schema, SCHEMA_PREFIX, VERSION
+        ));
+    }
+    Ok(())
+}
+
+/// Which provider serves a model, judging by its name.
+fn provider_of(name: &str) -> Option<&'static str> {
+    let name = name.to_lowercase();
+    if name.starts_with("claude") {
+        Some("anthropic")
+    } else if name.starts_with("gemini") || name.starts_with("gemma") {
+        Some("gemini")
+    } else if ["gpt", "chatgpt", "o1", "o3", "o4"]
+        .iter()
+        .any(|prefix| name.starts_with(prefix))
+    {
+        Some("openai")
+    } else if name.contains('/') {
+        // Hub ids such as `meta-llama/Llama-3.3-70B-Instruct`.
+        Some("huggingface")
+    } else {
+        None
+    }
+}
+
+/// A model called `name`, with `current`'s tools and options. Its API key and OpenAI
+/// attribution carry over only when the provider stays the same.
+fn model_named(py: Python, current: &Bound<'_, PyAny>, name: &str) -> PyResult<Py<PyAny>> {
+    if let Some(preset) = presets::detect(name) {
+        let (_, mut options, tools) = AnyClient::from_agent(py, current)?;
+        options.logit_bias = None;
+        options.grpc = false;
+        options.base_url = None;
+        options.deployments = None;
+        let model = crate::preset_agent(preset, name, tools, None, options)?;
+        return Ok(Py::new(py, model)?.into_any());
+    }
+    let provider = provider_of(name).ok_or_else(|| {
+        PyValueError::new_err(format!(
+            "cannot tell the provider of model '{}'; pass a model object instead",
+            name
+        ))
+    })?;
+    let (client, mut options, tools) = AnyClient::from_agent(py, current)?;
+    let current = match current.getattr("_agent") {
+        Ok(inner) => inner,
+        Err(_) => current.clone(),
+    };
+    let same_provider = with_client!(&client, client => client.provider()) == provider;
+    if !same_provider {
+        // Token ids are specific to OpenAI's tokenizers, and only Gemini has gRPC.
+        options.logit_bias = None;
+        options.grpc = false;
+        options.base_url = None;
+        options.deployments = None;
+        if provider != "openai" && provider != "huggingface" {
+            options.chat_template = None;
+        }
+    }
+    let api_key = if same_provider {
+        let key = with_client!(&client, client => client.api_key().clone());
+        Some(key).filter(|key| !key.is_empty())
+    } else {
+        None
+    };
+    let model = Some(name.to_string());
+    let new = match provider {
+        "openai" => {
+            let (user, safety_identifier) = match current.cast::<OpenAIModel>() {
+                Ok(m) => {
+                    let m = m.borrow();
+                    (m.user.clone(), m.safety_identifier.clone())
+                }
+                Err(_) => (None, None),
+            };
+            Py::new(
+                py,
+                OpenAIModel {
+                    model,
+                    tools,
+                    api_key,
+                    user,
+                    safety_identifier,
+                    options,
+                },
+            )?
+            .into_any()
+        }
+        "anthropic" => Py::new(
+            py,
+            ClaudeModel {
+                model,
+                tools,
+                api_key,
+                options,
+            },
+        )?
+        .into_any(),
+        "huggingface" => {
+            let (endpoint, task, inference_provider) = match current.cast::<HuggingFaceModel>() {
+                Ok(m) => {
+                    let m = m.borrow();
+                    (m.endpoint.clone(), m.task, m.inference_provider.clone())
+                }
+                Err(_) => (None, Default::default(), None),
+            };
+            Py::new(
+                py,
+                HuggingFaceModel {
+                    model,
+                    tools,
+                    api_key,
+                    endpoint,
+                    task,
+                    inference_provider,
+                    options,
+                },
+            )?
+            .into_any()
+        }
+        _ => Py::new(
+            py,
+            GeminiModel {
+                model,
+                tools,
+                api_key,
+                options,
+            },
+        )?
+        .into_any(),
+    };
+    Ok(new)
+}
@@ -0,0 +1,545 @@
+//! Recursive text splitting and token counting.
+
+use crate::document::Document;
+use once_cell::sync::Lazy;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use std::collections::VecDeque;
+use tiktoken_rs::tokenizer::Tokenizer;
+use tiktoken_rs::CoreBPE;
+
+/// `cl100k_base` is a close enough estimate for every supported provider.
+static BPE: Lazy<CoreBPE> =
+    Lazy::new(|| tiktoken_rs::cl100k_base().expect("failed to load cl100k_base encoding"));
+
+/// Paragraphs, then lines, then sentences, then words, then characters.
+pub(crate) const DEFAULT_SEPARATORS: &[&str] = &["\n\n", "\n", ". ", " ", ""];
+
+/// Structural boundaries for markup and source code, coarsest first. Each separator
+/// starts the piece that follows it, so headings and definitions open their chunk.
+fn language_separators(language: &str) -> Option<&'static [&'static str]> {
+    let separators: &[&str] = match language {
+        "markdown" | "md" => &[
+            "\n# ",
+            "\n## ",
+            "\n### ",
+            "\n#### ",
+            "\n##### ",
+            "\n###### ",
+            "\n```",
+            "\n---\n",
+            "\n\n",
+            "\n",
+            " ",
+            "",
+        ],
+        "python" | "py" => &[
+            "\nclass ",
+            "\ndef ",
+            "\n\tdef ",
+            "\n    def ",
+            "\n\n",
+            "\n",
+            " ",
+            "",
+        ],
+        "rust" | "rs" => &[
+            "\npub fn ",
+            "\nfn ",
+            "\nimpl ",
+            "\npub struct ",
+            "\nstruct ",
+            "\npub enum ",
+            "\nenum ",
+            "\nmod ",
+            "\n\n",
+            "\n",
+            " ",
+            "",
+        ],
+        "javascript" | "js" | "typescript" | "ts" => &[
+            "\nexport ",
+            "\nfunction ",
+            "\nclass ",
+            "\nconst ",
+            "\nlet ",
+            "\ninterface ",
+            "\n\n",
+            "\n",
+            " ",
+            "",
+        ],
+        "go" => &[
+            "\nfunc ", "\ntype ", "\nvar ", "\nconst ", "\n\n", "\n", " ", "",
+        ],
+        "java" => &[
+            "\nclass ",
+            "\npublic ",
+            "\nprotected ",
+            "\nprivate ",
+            "\n\n",
+            "\n",
+            " ",
+            "",
+        ],
+        "html" => &[
+            "<h1", "<h2", "<h3", "<section", "<article", "<div", "<p", "<br", "<li", "\n\n", "\n",
+            " ", "",
+        ],
+        _ => return None,
+    };
+    Some(separators)
+}
+
+pub(crate) fn count_tokens(text: &str) -> usize {
+    BPE.encode_with_special_tokens(text).len()
+}
+
+static O200K: Lazy<CoreBPE> =
+    Lazy::new(|| tiktoken_rs::o200k_base().expect("failed to load o200k_base encoding"));
+
+/// The encoding OpenAI's `model` uses. Models tiktoken doesn't know are assumed to be
+/// recent ones using `o200k_base`.
+pub(crate) fn tokenizer(model: &str) -> &'static CoreBPE {
+    match tiktoken_rs::tokenizer::get_tokenizer(model) {
+        Some(Tokenizer::Cl100kBase) => &BPE,
+        _ => &O200K,
+    }
+}
+
+/// The ids OpenAI's `model` tokenizes `text` into, e.g. for `logit_bias`. Models
+/// tiktoken doesn't know are assumed to be recent ones using `o200k_base`.
+#[pyfunction]
+#[pyo3(signature = (text, model="gpt-4o-mini"))]
+pub fn token_ids(py: Python, text: &str, model: &str) -> Vec<u32> {
+    let bpe = tokenizer(model);
+    py.detach(|| {
+        bpe.encode_ordinary(text)
+            .into_iter()
+            .map(|id| id as u32)
+            .collect()
+    })
+}
+
+/// How chunk sizes are measured.
+#[derive(Clone, Copy)]
+pub(crate) enum Length {
+    Chars,
+    Tokens,
+}
+
+impl Length {
+    pub(crate) fn of(self, text: &str) -> usize {
+        match self {
+            Length::Chars => text.chars().count(),
+            Length::Tokens => count_tokens(text),
+        }
+    }
+
+    fn parse(name: &str) -> PyResult<Self> {
+        match name {
+            "chars" => Ok(Length::Chars),
+            "tokens" => Ok(Length::Tokens),
+            other => Err(PyValueError::new_err(format!(
+                "length must be 'chars' or 'tokens', got '{}'",
+                other
+            ))),
+        }
+    }
+}
+
+/// Which piece a separator stays attached to.
+#[derive(Clone, Copy)]
+pub(crate) enum Keep {
+    /// End of the preceding piece, for prose ("sentence. ").
+    End,
+    /// Start of the following piece, for code and headings ("\ndef f").
+    Start,
+}
+
+impl Keep {
+    fn parse(name: &str) -> PyResult<Self> {
+        match name {
+            "end" => Ok(Keep::End),
+            "start" => Ok(Keep::Start),
+            other => Err(PyValueError::new_err(format!(
+                "keep_separator must be 'start' or 'end', got '{}'",
+                other
+            ))),
+        }
+    }
+}
+
+pub(crate) struct SplitConfig<'a> {
+    pub(crate) chunk_size: usize,
+    pub(crate) chunk_overlap: usize,
+    pub(crate) separators: &'a [String],
+    pub(crate) length: Length,
+    pub(crate) keep: Keep,
+}
+
+/// Split `text` into chunks of at most `chunk_size`, preferring the earliest separator
+/// that occurs in the text and recursing into pieces that are still too large.
+/// Adjacent pieces are merged back up to `chunk_size`, repeating up to `chunk_overlap`
+/// of the previous chunk. Separators stay attached to their piece, so no text is lost;
+/// chunks are trimmed of surrounding whitespace.
+pub(crate) fn split_text(text: &str, config: &SplitConfig) -> Vec<String> {
+    let mut chunks = Vec::new();
+    split_into(text, config.separators, config, &mut chunks);
+    chunks
+        .into_iter()
+        .map(|chunk| chunk.trim().to_string())
+        .filter(|chunk| !chunk.is_empty())
+        .collect()
+}
+
+fn split_into(text: &str, separators: &[String], config: &SplitConfig, out: &mut Vec<String>) {
+    let position = separators
+        .iter()
+        .position(|sep| sep.is_empty() || text.contains(sep.as_str()));
+    let (separator, rest) = match position {
+        Some(i) => (separators[i].as_str(), &separators[i + 1..]),
+        None => ("", &[][..]),
+    };
+
+    let pieces: Vec<&str> = if separator.is_empty() {
+        text.char_indices()
+            .map(|(i, c)| &text[i..i + c.len_utf8()])
+            .collect()
+    } else {
+        match config.keep {
+            Keep::End => text.split_inclusive(separator).collect(),
+            Keep::Start => split_before(text, separator),
+        }
+    };
+
+    let mut fitting: Vec<&str> = Vec::new();
+    for piece in pieces {
+        if config.length.of(piece) <= config.chunk_size {
+            fitting.push(piece);
+            continue;
+        }
+        merge(&fitting, config, out);
+        fitting.clear();
+        if rest.is_empty() {
+            out.push(piece.to_string());
+        } else {
+            split_into(piece, rest, config, out);
+        }
+    }
+    merge(&fitting, config, out);
+}
+
+/// Like `split_inclusive`, but each separator begins the piece after it.
+fn split_before<'t>(text: &'t str, separator: &str) -> Vec<&'t str> {
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    for (i, _) in text.match_indices(separator) {
+        if i > start {
+            pieces.push(&text[start..i]);
+            start = i;
+        }
+    }
+    pieces.push(&text[start..]);
+    pieces
+}
+
+fn merge(pieces: &[&str], config: &SplitConfig, out: &mut Vec<String>) {
+    let mut window: VecDeque<(&str, usize)> = VecDeque::new();
+    let mut total = 0;
+    for piece in pieces {
+        let len = config.length.of(piece);
+        if total + len > config.chunk_size && !window.is_empty() {
+            out.push(window.iter().map(|(p, _)| *p).collect());
+            // Keep a tail of the previous chunk as overlap, if it still leaves room.
+            while total > config.chunk_overlap || (total + len > config.chunk_size && total > 0) {
+                let (_, dropped) = window.pop_front().expect("total > 0 implies a piece");
+                total -= dropped;
+            }
+        }
+        window.push_back((piece, len));
+        total += len;
+    }
+    if !window.is_empty() {
+        out.push(window.iter().map(|(p, _)| *p).collect());
+    }
+}
+
+/// Apply `split` to every text, spreading the work over the available cores.
+fn split_many<F>(texts: &[String], split: F) -> Vec<Vec<String>>
+where
+    F: Fn(&str) -> Vec<String> + Sync,
+{
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let per_thread = texts.len().div_ceil(threads).max(1);
+    std::thread::scope(|scope| {
+        let handles: Vec<_> = texts
+            .chunks(per_thread)
+            .map(|batch| scope.spawn(|| batch.iter().map(|t| split(t)).collect::<Vec<_>>()))
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|handle| handle.join().expect("splitter thread panicked"))
+            .collect()
+    })
+}
+
+/// Split each document's text, copying its metadata onto every chunk along with the
+/// chunk's position as `"chunk"`.
+fn split_documents<F>(
+    py: Python,
+    documents: Vec<Py<Document>>,
+    split: F,
+) -> PyResult<Vec<Py<Document>>>
+where
+    F: Fn(&str) -> Vec<String> + Sync,
+{
+    let texts: Vec<String> = documents
+        .iter()
+        .map(|doc| doc.borrow(py).text.clone())
+        .collect();
+    let split = py.detach(|| split_many(&texts, &split));
+
+    let mut chunks = Vec::new();
+    for (doc, pieces) in documents.iter().zip(split) {
+        let metadata = doc.borrow(py).metadata.bind(py).copy()?;
+        for (i, piece) in pieces.into_iter().enumerate() {
+            let metadata = metadata.copy()?;
+            metadata.set_item("chunk", i)?;
+            chunks.push(Document::create(py, piece, metadata, None)?);
+        }
+    }
+    Ok(chunks)
+}
+
+fn check_sizes(chunk_size: usize, chunk_overlap: usize) -> PyResult<()> {
+    if chunk_size == 0 {
+        return Err(PyValueError::new_err("chunk_size must be at least 1"));
+    }
+    if chunk_overlap >= chunk_size {
+        return Err(PyValueError::new_err(
+            "chunk_overlap must be smaller than chunk_size",
+        ));
+    }
+    Ok(())
+}
+
+/// Splits text on a list of separators, coarsest first, recursing into pieces that are
+/// still larger than `chunk_size`.
+#[pyclass]
+pub struct RecursiveCharacterTextSplitter {
+    #[pyo3(get)]
+    chunk_size: usize,
+    #[pyo3(get)]
+    chunk_overlap: usize,
+    #[pyo3(get)]
+    separators: Vec<String>,
+    length: Length,
+    keep: Keep,
+}
+
+#[pymethods]
+impl RecursiveCharacterTextSplitter {
+    /// `length` measures chunks in `"chars"` or `"tokens"`; `keep_separator` attaches
+    /// each separator to the `"end"` of the previous piece or the `"start"` of the next.
+    #[new]
+    #[pyo3(signature = (chunk_size=1000, chunk_overlap=200, separators=None, length="chars", keep_separator="end"))]
+    fn new(
+        chunk_size: usize,
+        chunk_overlap: usize,
+        separators: Option<Vec<String>>,
+        length: &str,
+        keep_separator: &str,
+    ) -> PyResult<Self> {
+        check_sizes(chunk_size, chunk_overlap)?;
+        Ok(RecursiveCharacterTextSplitter {
+            chunk_size,
+            chunk_overlap,
+            separators: separators
+                .unwrap_or_else(|| DEFAULT_SEPARATORS.iter().map(|s| s.to_string()).collect()),
+            length: Length::parse(length)?,
+            keep: Keep::parse(keep_separator)?,
+        })
+    }
+
+    /// A splitter that cuts at the structure of `language`: `"markdown"`, `"python"`,
+    /// `"rust"`, `"javascript"`, `"typescript"`, `"go"`, `"java"` or `"html"`.
+    #[staticmethod]
+    #[pyo3(signature = (language, chunk_size=1000, chunk_overlap=200, length="chars"))]
+    fn from_language(
+        language: &str,
+        chunk_size: usize,
+        chunk_overlap: usize,
+        length: &str,
+    ) -> PyResult<Self> {
+        let separators = language_separators(&language.to_lowercase())
+            .ok_or_else(|| PyValueError::new_err(format!("unsupported language '{}'", language)))?;
+        Self::new(
+            chunk_size,
+            chunk_overlap,
+            Some(separators.iter().map(|s| s.to_string()).collect()),
+            length,
+            "start",
+        )
+    }
+
+    fn split_text(&self, py: Python, text: &str) -> Vec<String> {
+        py.detach(|| split_text(text, &self.config()))
+    }
+
+    /// Split many texts at once, in parallel.
+    fn split_texts(&self, py: Python, texts: Vec<String>) -> Vec<Vec<String>> {
+        py.detach(|| {
+            let config = self.config();
+            split_many(&texts, |text| split_text(text, &config))
+        })
+    }
+
+    /// Split documents into chunk documents that keep their metadata.
+    fn split_documents(
+        &self,
+        py: Python,
+        documents: Vec<Py<Document>>,
+    ) -> PyResult<Vec<Py<Document>>> {
+        let config = self.config();
+        split_documents(py, documents, |text| split_text(text, &config))
+    }
+}
+
+impl RecursiveCharacterTextSplitter {
+    fn config(&self) -> SplitConfig<'_> {
+        SplitConfig {
+            chunk_size: self.chunk_size,
+            chunk_overlap: self.chunk_overlap,
+            separators: &self.separators,
+            length: self.length,
+            keep: self.keep,
+        }
+    }
+}
+
+/// Splits text into windows of exactly `chunk_size` tokens (fewer for the last one),
+/// each starting `chunk_overlap` tokens before the previous one ended.
+#[pyclass]
+pub struct TokenTextSplitter {
+    #[pyo3(get)]
+    chunk_size: usize,
+    #[pyo3(get)]
+    chunk_overlap: usize,
+}
+
+#[pymethods]
+impl TokenTextSplitter {
+    #[new]
+    #[pyo3(signature = (chunk_size=512, chunk_overlap=50))]
+    fn new(chunk_size: usize, chunk_overlap: usize) -> PyResult<Self> {
+        check_sizes(chunk_size, chunk_overlap)?;
+        Ok(TokenTextSplitter {
+            chunk_size,
+            chunk_overlap,
+        })
+    }
+
+    fn split_text(&self, py: Python, text: &str) -> Vec<String> {
+        py.detach(|| self.split(text))
+    }
+
+    /// Split many texts at once, in parallel.
+    fn split_texts(&self, py: Python, texts: Vec<String>) -> Vec<Vec<String>> {
+        py.detach(|| split_many(&texts, |text| self.split(text)))
+    }
+
+    /// Split documents into chunk documents that keep their metadata.
+    fn split_documents(
+        &self,
+        py: Python,
+        documents: Vec<Py<Document>>,
+    ) -> PyResult<Vec<Py<Document>>> {
+        split_documents(py, documents, |text| self.split(text))
+    }
+
+    /// Number of tokens in `text`.
+    fn count_tokens(&self, py: Python, text: &str) -> usize {
+        py.detach(|| count_tokens(text))
+    }
+}
+
+impl TokenTextSplitter {
+    fn split(&self, text: &str) -> Vec<String> {
+        let tokens = BPE.encode_with_special_tokens(text);
+        let mut chunks = Vec::new();
+        let mut start = 0;
+        while start < tokens.len() {
+            let mut end = (start + self.chunk_size).min(tokens.len());
+            let chunk = decode_window(&tokens, &mut start, &mut end);
+            chunks.push(chunk);
+            if end == tokens.len() {
+                break;
+            }
+            start = (end.saturating_sub(self.chunk_overlap)).max(start + 1);
+        }
+        chunks
+    }
+}
+
+/// Decode `tokens[start..end]`. A window may begin or end inside a multi-byte character;
+/// nudge its edges inwards to whole characters rather than emit replacement characters.
+fn decode_window(tokens: &[usize], start: &mut usize, end: &mut usize) -> String {
+    const MAX_NUDGE: usize = 3;
+    for skip in 0..=MAX_NUDGE {
+        for trim in 0..=MAX_NUDGE {
+            let (s, e) = (*start + skip, end.saturating_sub(trim));
+            if s >= e {
+                break;
+            }
+            if let Ok(text) = String::from_utf8(BPE._decode_native(&tokens[s..e])) {
+                *start = s;
+                *end = e;
+                return text;
+            }
+        }
+    }
+    String::from_utf8_lossy(&BPE._decode_native(&tokens[*start..*end])).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(text: &str, chunk_size: usize, chunk_overlap: usize) -> Vec<String> {
+        let separators: Vec<String> = DEFAULT_SEPARATORS.iter().map(|s| s.to_string()).collect();
+        split_text(
+            text,
+            &SplitConfig {
+                chunk_size,
+                chunk_overlap,
+                separators: &separators,
+                length: Length::Chars,
+                keep: Keep::End,
+            },
+        )
+    }
+
+    #[test]
+    fn each_chunk_repeats_the_tail_of_the_last() {
+        assert_eq!(
+            split("aaaa bbbb cccc dddd", 10, 5),
+            ["aaaa bbbb", "bbbb cccc", "cccc dddd"]
+        );
+    }
+
+    #[test]
+    fn overlap_is_whole_pieces_only() {
+        // "bbbb " is five characters, one more than the overlap allows.
+        assert_eq!(
+            split("aaaa bbbb cccc dddd", 10, 4),
+            ["aaaa bbbb", "cccc dddd"]
+        );
+    }
+
+    #[test]
+    fn overlap_gives_way_to_the_chunk_size() {
+        // Repeating "bbbb " would push the chunk holding "cccccc" past ten characters.
+        assert_eq!(split("aaaa bbbb cccccc", 10, 5), ["aaaa bbbb", "cccccc"]);
+    }
+}
@@ -0,0 +1,856 @@
+//! Server-sent event parsing and the channel that carries streamed text to Python.
+
+use crate::artifacts::Artifact;
+use crate::asyncio;
+use crate::citations::Citation;
+use crate::deadline;
+use crate::error::RustedChainError;
+use crate::partial_json::JsonStream;
+use crate::pii::Redactor;
+use crate::retry::Retries;
+use crate::server_events::EventStream;
+use crate::RUNTIME;
+use pyo3::exceptions::{PyStopAsyncIteration, PyValueError};
+use pyo3::prelude::*;
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, Instant};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tokio::time::timeout;
+use unicode_segmentation::UnicodeSegmentation;
+
+const STREAM_CAPACITY: usize = 64;
+
+/// The consumer's end of a text stream. Locked for the whole of a read, so reads that
+/// overlap, such as two awaited `__anext__` calls, take chunks in turn.
+type Receiver = tokio::sync::Mutex<Reader>;
+
+struct Reader {
+    rx: mpsc::Receiver<PyResult<String>>,
+    /// The last grapheme cluster received, which the next chunk may extend.
+    held: String,
+    /// An error received while text was held, raised after the text is returned.
+    failed: Option<PyErr>,
+    /// An error the sink couldn't queue with the buffer full, raised once the chunks
+    /// before it have been read.
+    ended: Option<PyErr>,
+}
+
+/// What a sink does with a chunk when the stream's buffer is full.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum Overflow {
+    /// Wait for the consumer to make room, holding back the provider stream.
+    #[default]
+    Block,
+    /// Discard the oldest chunk not yet read.
+    DropOldest,
+    /// Fail the stream with `StreamOverflow`.
+    Error,
+}
+
+impl Overflow {
+    pub(crate) fn parse(value: &str) -> PyResult<Self> {
+        match value {
+            "block" => Ok(Self::Block),
+            "drop_oldest" => Ok(Self::DropOldest),
+            "error" => Ok(Self::Error),
+            other => Err(PyValueError::new_err(format!(
+                "on_stream_overflow must be 'block', 'drop_oldest' or 'error', got '{}'",
+                other
+            ))),
+        }
+    }
+}
+
+/// How many chunks a stream holds for its consumer, and what happens past that.
+#[derive(Clone, Copy)]
+pub(crate) struct Buffering {
+    pub(crate) capacity: usize,
+    pub(crate) overflow: Overflow,
+}
+
+impl Default for Buffering {
+    fn default() -> Self {
+        Self {
+            capacity: STREAM_CAPACITY,
+            overflow: Overflow::default(),
+        }
+    }
+}
+
+/// Incremental parser for `text/event-stream` bodies.
+/// Bytes are buffered until a full event has arrived, so multi-byte characters split
+/// across network chunks are never decoded early. A character a server splits across
+/// events, ending a JSON string in one with part of it, is carried over to the string
+/// that continues it in a later event.
+#[derive(Default)]
+pub(crate) struct SseParser {
+    buffer: Vec<u8>,
+    /// How much of `buffer` has been searched for the end of an event.
+    scanned: usize,
+    /// The start of a character that ended a string in an earlier event: incomplete
+    /// UTF-8 bytes, or the `\uXXXX` escape of a high surrogate.
+    carry: Option<Vec<u8>>,
+}
+
+impl SseParser {
+    /// Feed a network chunk, returning the `data` payload of every event it completes.
+    pub(crate) fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        // A separator may have started in the bytes searched last time.
+        let mut from = self.scanned.saturating_sub(3);
+        while let Some((end, separator)) = event_boundary(&self.buffer, from) {
+            let raw: Vec<u8> = self.buffer.drain(..end + separator).collect();
+            from = 0;
+            let raw = rejoin_characters(&raw[..end], &mut self.carry);
+            if let Some(data) = event_data(&raw) {
+                events.push(data);
+            }
+        }
+        self.scanned = self.buffer.len();
+        if !events.is_empty() {
+            deadline::started();
+        }
+        events
+    }
+}
+
+/// `raw` with characters split across events made whole. An incomplete character at
+/// the end of a JSON string is moved to `carry`, and put back at the start of the
+/// string that continues it. Surrogate escapes left without a pair become U+FFFD,
+/// since JSON parsers reject them.
+fn rejoin_characters(raw: &[u8], carry: &mut Option<Vec<u8>>) -> Vec<u8> {
+    const REPLACEMENT: &[u8] = b"\\ufffd";
+    let mut out = Vec::with_capacity(raw.len());
+    let mut in_string = false;
+    let mut i = 0;
+    while i < raw.len() {
+        let byte = raw[i];
+        if !in_string || byte == b'"' {
+            out.push(byte);
+            i += 1;
+            in_string = byte == b'"' && !in_string;
+            if in_string && continues(carry.as_deref(), raw, i) {
+                let start = carry.take().unwrap_or_default();
+                out.extend_from_slice(&start);
+                // A high surrogate goes back together with the low one it pairs with.
+                if start.starts_with(b"\\") {
+                    out.extend_from_slice(&raw[i..i + 6]);
+                    i += 6;
+                }
+            }
+            continue;
+        }
+        if byte == b'\\' {
+            let (unit, low) = (surrogate_escape(raw, i), surrogate_escape(raw, i + 6));
+            i += match (unit, low) {
+                (None, _) => {
+                    let end = (i + 2).min(raw.len());
+                    out.extend_from_slice(&raw[i..end]);
+                    end - i
+                }
+                (Some(high), Some(low)) if is_high(high) && !is_high(low) => {
+                    out.extend_from_slice(&raw[i..i + 12]);
+                    12
+                }
+                (Some(high), _) if is_high(high) && raw.get(i + 6) == Some(&b'"') => {
+                    *carry = Some(raw[i..i + 6].to_vec());
+                    6
+                }
+                (Some(_), _) => {
+                    out.extend_from_slice(REPLACEMENT);
+                    6
+                }
+            };
+            continue;
+        }
+        let width = match byte {
+            0xc0..=0xdf => 2,
+            0xe0..=0xef => 3,
+            0xf0..=0xf7 => 4,
+            _ => 1,
+        };
+        let limit = (i + width).min(raw.len());
+        let end = (i + 1..limit)
+            .find(|&at| !is_continuation(raw[at]))
+            .unwrap_or(limit);
+        if end - i < width && raw.get(end) == Some(&b'"') {
+            *carry = Some(raw[i..end].to_vec());
+        } else {
+            out.extend_from_slice(&raw[i..end]);
+        }
+        i = end;
+    }
+    out
+}
+
+/// Whether the string starting at `at` continues the character begun in `carry`.
+fn continues(carry: Option<&[u8]>, raw: &[u8], at: usize) -> bool {
+    match carry {
+        Some([b'\\', ..]) => surrogate_escape(raw, at).is_some_and(|unit| !is_high(unit)),
+        Some(_) => raw.get(at).is_some_and(|byte| is_continuation(*byte)),
+        None => false,
+    }
+}
+
+/// The code unit of a `\uXXXX` escape of a surrogate starting at `at`.
+fn surrogate_escape(raw: &[u8], at: usize) -> Option<u16> {
+    let escape = raw.get(at..at + 6)?.strip_prefix(b"\\u")?;
+    let unit = u16::from_str_radix(std::str::from_utf8(escape).ok()?, 16).ok()?;
+    (0xd800..=0xdfff).contains(&unit).then_some(unit)
+}
+
+fn is_high(unit: u16) -> bool {
+    unit < 0xdc00
+}
+
+fn is_continuation(byte: u8) -> bool {
+    byte & 0xc0 == 0x80
+}
+
+/// Where the first event ending at or after `from` ends, and the separator's length.
+fn event_boundary(buffer: &[u8], from: usize) -> Option<(usize, usize)> {
+    (from..buffer.len()).find_map(|i| {
+        if buffer[i..].starts_with(b"\r\n\r\n") {
+            Some((i, 4))
+        } else if buffer[i..].starts_with(b"\n\n") {
+            Some((i, 2))
+        } else {
+            None
+        }
+    })
+}
+
+fn event_data(raw: &[u8]) -> Option<String> {
+    let text = String::from_utf8_lossy(raw);
+    let lines: Vec<&str> = text
+        .lines()
+        .filter_map(|line| line.strip_prefix("data:"))
+        .map(|data| data.strip_prefix(' ').unwrap_or(data))
+        .collect();
+    (!lines.is_empty()).then(|| lines.join("\n"))
+}
+
+/// When text arrived, shared between a sink and its stream.
+#[derive(Default)]
+struct Timing {
+    /// First chunk of the whole stream.
+    first_chunk: Option<Instant>,
+    /// First chunk of the current request; taken after each one.
+    request_first_chunk: Option<Instant>,
+    tokens_per_second: Option<f64>,
+}
+
+/// The text sent for the current turn of a conversation, kept so that a request
+/// resumed after its stream dropped can leave out what it repeats.
+#[derive(Default)]
+struct TurnText {
+    /// Everything forwarded this turn, as the model wrote it.
+    sent: String,
+    /// Text the resumed request is expected to start with, and how much of it has
+    /// come back so far.
+    repeat: String,
+    matched: usize,
+}
+
+impl TurnText {
+    /// `text` less any part repeating what was already sent, recorded as sent.
+    fn fresh(&mut self, text: String) -> String {
+        let mut text = text;
+        if self.matched < self.repeat.len() {
+            // Providers asked to begin with the text may lead with whitespace.
+            let chunk = match self.matched {
+                0 => text.trim_start(),
+                _ => text.as_str(),
+            };
+            let common: usize = self.repeat[self.matched..]
+                .chars()
+                .zip(chunk.chars())
+                .take_while(|(expected, got)| expected == got)
+                .map(|(expected, _)| expected.len_utf8())
+                .sum();
+            if common == chunk.len() {
+                self.matched += common;
+                return String::new();
+            }
+            // New text starts here, whether the repeat is over or was never made.
+            text = chunk[common..].to_string();
+            self.repeat.clear();
+            self.matched = 0;
+        }
+        self.sent.push_str(&text);
+        text
+    }
+}
+
+/// Producer half of a text stream, handed to the provider clients.
+pub(crate) struct TextSink {
+    tx: mpsc::Sender<PyResult<String>>,
+    /// The consumer's end, to drop chunks from under `Overflow::DropOldest`. Weak, so
+    /// the channel still closes when the consumer goes away.
+    rx: Weak<Receiver>,
+    buffering: Buffering,
+    timing: Arc<Mutex<Timing>>,
+    citations: Arc<Mutex<Vec<Citation>>>,
+    retries: Arc<Mutex<Retries>>,
+    artifacts: Arc<Mutex<Vec<Artifact>>>,
+    /// Under `redact_pii`, restores placeholders in the text, holding back a possible
+    /// placeholder split across chunks.
+    restore: Option<(Arc<Redactor>, Mutex<String>)>,
+    turn: Mutex<TurnText>,
+}
+
+impl TextSink {
+    /// Restore redacted values in the text before it is forwarded.
+    pub(crate) fn restore_with(&mut self, redactor: Arc<Redactor>) {
+        self.restore = Some((redactor, Mutex::new(String::new())));
+    }
+
+    /// Forward a chunk of text, failing once the Python side has stopped listening.
+    pub(crate) async fn send(&self, text: String) -> Result<(), RustedChainError> {
+        let text = self.turn.lock().unwrap().fresh(text);
+        let text = match &self.restore {
+            Some((redactor, pending)) => {
+                let mut pending = pending.lock().unwrap();
+                redactor.restore_chunk(&mut pending, &text)
+            }
+            None => text,
+        };
+        self.forward(text).await
+    }
+
+    /// Forward text held back by `send`, at the end of a request.
+    pub(crate) async fn flush(&self) -> Result<(), RustedChainError> {
+        let held = match &self.restore {
+            Some((redactor, pending)) => {
+                let held = std::mem::take(&mut *pending.lock().unwrap());
+                redactor.restore(&held)
+            }
+            None => return Ok(()),
+        };
+        self.forward(held).await
+    }
+
+    async fn forward(&self, text: String) -> Result<(), RustedChainError> {
+        if text.is_empty() {
+            return Ok(());
+        }
+        let mut chunk = Ok(text);
+        loop {
+            chunk = match self.buffering.overflow {
+                Overflow::Block => match self.tx.send(chunk).await {
+                    Ok(()) => break,
+                    Err(_) => return Err(RustedChainError::Cancelled),
+                },
+                _ => match self.tx.try_send(chunk) {
+                    Ok(()) => break,
+                    Err(TrySendError::Closed(_)) => return Err(RustedChainError::Cancelled),
+                    Err(TrySendError::Full(chunk)) => chunk,
+                },
+            };
+            if self.buffering.overflow == Overflow::Error {
+                return Err(RustedChainError::StreamOverflow(self.buffering.capacity));
+            }
+            let rx = self.rx.upgrade().ok_or(RustedChainError::Cancelled)?;
+            // A read holds the lock while it waits for a chunk, maybe this one, so the
+            // lock is only taken when free; a read in progress makes room or is waiting.
+            let Ok(mut reader) = rx.try_lock() else {
+                tokio::task::yield_now().await;
+                continue;
+            };
+            // The buffer can't drain while the lock is held, but it may have before.
+            chunk = match self.tx.try_send(chunk) {
+                Ok(()) => break,
+                Err(TrySendError::Closed(_)) => return Err(RustedChainError::Cancelled),
+                Err(TrySendError::Full(chunk)) => chunk,
+            };
+            let _ = reader.rx.try_recv();
+        }
+        let now = Instant::now();
+        let mut timing = self.timing.lock().unwrap();
+        timing.first_chunk.get_or_insert(now);
+        timing.request_first_chunk.get_or_insert(now);
+        Ok(())
+    }
+
+    /// Start a turn of the conversation, with nothing sent yet.
+    pub(crate) fn start_turn(&self) {
+        *self.turn.lock().unwrap() = TurnText::default();
+    }
+
+    /// The text sent so far this turn, as the model wrote it.
+    pub(crate) fn turn_text(&self) -> String {
+        self.turn.lock().unwrap().sent.clone()
+    }
+
+    /// Leave `text` out where the next request starts by repeating it: it was sent
+    /// before the request that is being resumed dropped.
+    pub(crate) fn skip_repeat(&self, text: String) {
+        let mut turn = self.turn.lock().unwrap();
+        turn.repeat = text;
+        turn.matched = 0;
+    }
+
+    /// When the current request's first chunk was sent, if it sent any, resetting it
+    /// for the next request.
+    pub(crate) fn take_request_first_chunk(&self) -> Option<Instant> {
+        self.timing.lock().unwrap().request_first_chunk.take()
+    }
+
+    pub(crate) fn set_tokens_per_second(&self, rate: f64) {
+        self.timing.lock().unwrap().tokens_per_second = Some(rate);
+    }
+
+    pub(crate) fn set_citations(&self, citations: Vec<Citation>) {
+        *self.citations.lock().unwrap() = citations;
+    }
+
+    pub(crate) fn set_retries(&self, retries: Retries) {
+        *self.retries.lock().unwrap() = retries;
+    }
+
+    pub(crate) fn set_artifacts(&self, artifacts: Vec<Artifact>) {
+        *self.artifacts.lock().unwrap() = artifacts;
+    }
+
+    /// Surface an error to the consumer, after the chunks already sent. Never waits for
+    /// the consumer: with the buffer full, the error is left for the reader instead.
+    pub(crate) fn fail(&self, err: PyErr) {
+        let mut err = Err(err);
+        loop {
+            err = match self.tx.try_send(err) {
+                Ok(()) | Err(TrySendError::Closed(_)) => return,
+                Err(TrySendError::Full(err)) => err,
+            };
+            let Some(rx) = self.rx.upgrade() else {
+                return;
+            };
+            // As in `forward`, a read holding the lock is about to make room.
+            if let Ok(mut reader) = rx.try_lock() {
+                reader.ended = err.err();
+                return;
+            }
+            std::thread::yield_now();
+        }
+    }
+}
+
+/// Iterator over streamed text chunks. Chunks end on grapheme cluster boundaries, so an
+/// emoji sequence or a letter with combining accents is never split between two.
+#[pyclass]
+pub struct TextStream {
+    rx: Arc<Receiver>,
+    created: Instant,
+    timing: Arc<Mutex<Timing>>,
+    citations: Arc<Mutex<Vec<Citation>>>,
+    retries: Arc<Mutex<Retries>>,
+    artifacts: Arc<Mutex<Vec<Artifact>>>,
+}
+
+impl TextStream {
+    /// Block (with the GIL released) until the next chunk arrives or the stream ends.
+    pub(crate) fn next_chunk(&self, py: Python) -> PyResult<Option<String>> {
+        // Without a limit on the wait, the outer `None` never comes.
+        self.next_chunk_within(py, None).map(Option::flatten)
+    }
+
+    /// Like `next_chunk`, returning `None` once `wait` has passed without text arriving.
+    pub(crate) fn next_chunk_within(
+        &self,
+        py: Python,
+        wait: Option<Duration>,
+    ) -> PyResult<Option<Option<String>>> {
+        let mut reader = py.detach(|| self.rx.blocking_lock());
+        if let Some(err) = reader.failed.take() {
+            return Err(err);
+        }
+        loop {
+            let received = match wait {
+                None => py.detach(|| reader.rx.blocking_recv()),
+                Some(wait) => {
+                    let received = py.detach(|| {
+                        RUNTIME.block_on(async {
+                            Ok::<_, RustedChainError>(timeout(wait, reader.rx.recv()).await.ok())
+                        })
+                    })?;
+                    match received {
+                        Some(received) => received,
+                        None => return Ok(None),
+                    }
+                }
+            };
+            if let Some(next) = reader.take(received) {
+                return next.map(Some);
+            }
+        }
+    }
+}
+
+impl Reader {
+    /// The next chunk, or `None` at the end of the stream.
+    async fn next(&mut self) -> PyResult<Option<String>> {
+        if let Some(err) = self.failed.take() {
+            return Err(err);
+        }
+        loop {
+            let received = self.rx.recv().await;
+            if let Some(next) = self.take(received) {
+                return next;
+            }
+        }
+    }
+
+    /// Take in what the channel delivered, returning the next chunk or the end of the
+    /// stream, or `None` while the text held may still be extended.
+    fn take(&mut self, received: Option<PyResult<String>>) -> Option<PyResult<Option<String>>> {
+        let next = match received {
+            Some(Ok(text)) => {
+                self.held.push_str(&text);
+                let last = self
+                    .held
+                    .grapheme_indices(true)
+                    .next_back()
+                    .map_or(0, |(at, _)| at);
+                if last == 0 {
+                    return None;
+                }
+                let held = self.held.split_off(last);
+                Some(std::mem::replace(&mut self.held, held))
+            }
+            Some(Err(err)) if self.held.is_empty() => return Some(Err(err)),
+            Some(Err(err)) => {
+                self.failed = Some(err);
+                Some(std::mem::take(&mut self.held))
+            }
+            None if self.held.is_empty() => match self.ended.take() {
+                Some(err) => return Some(Err(err)),
+                None => None,
+            },
+            None => Some(std::mem::take(&mut self.held)),
+        };
+        Some(Ok(next))
+    }
+}
+
+#[pymethods]
+impl TextStream {
+    fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    fn __next__(&self, py: Python) -> PyResult<Option<String>> {
+        self.next_chunk(py)
+    }
+
+    fn __aiter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+
+    /// The next chunk, awaited without blocking the event loop. Overlapping awaits get
+    /// chunks in the order they started reading.
+    fn __anext__(&self, py: Python) -> PyResult<Py<PyAny>> {
+        let rx = self.rx.clone();
+        asyncio::awaitable(py, async move {
+            match rx.lock().await.next().await? {
+                Some(chunk) => Ok(chunk),
+                None => Err(PyStopAsyncIteration::new_err(())),
+            }
+        })
+    }
+
+    /// Seconds from the start of the run until the first chunk of text, or `None`
+    /// before it arrives.
+    #[getter]
+    fn time_to_first_token(&self) -> Option<f64> {
+        let timing = self.timing.lock().unwrap();
+        timing
+            .first_chunk
+            .map(|first| first.duration_since(self.created).as_secs_f64())
+    }
+
+    /// Output tokens per second of the latest streamed request, measured from its first
+    /// chunk to its end, or `None` until one has finished.
+    #[getter]
+    fn tokens_per_second(&self) -> Option<f64> {
+        self.timing.lock().unwrap().tokens_per_second
+    }
+
+    /// Sources the answer cites, for providers that return them; filled in once the
+    /// answer has finished streaming.
+    #[getter]
+    fn citations(&self) -> Vec<Citation> {
+        self.citations.lock().unwrap().clone()
+    }
+
+    /// How the run's provider requests were retried; filled in once the stream ends.
+    #[getter]
+    fn retries(&self) -> Retries {
+        self.retries.lock().unwrap().clone()
+    }
+
+    /// Files stored from tool results instead of being sent to the model; filled in
+    /// once the stream ends.
+    #[getter]
+    fn artifacts(&self) -> Vec<Artifact> {
+        self.artifacts.lock().unwrap().clone()
+    }
+
+    /// Treat the streamed text as a JSON document, yielding progressively more complete
+    /// parsed objects as fields finish.
+    fn json(slf: Py<Self>) -> JsonStream {
+        JsonStream::new(slf)
+    }
+
+    /// Format the stream as server-sent events for a streaming HTTP response: each
+    /// chunk as a `data` event, a `: keep-alive` comment after `keepalive` seconds
+    /// without text (`None` for none), then an `end` event, or an `error` event holding
+    /// the message if the run fails.
+    #[pyo3(signature = (keepalive=15.0))]
+    fn sse(slf: Py<Self>, keepalive: Option<f64>) -> PyResult<EventStream> {
+        EventStream::new(slf, keepalive, true)
+    }
+
+    /// Like `sse`, as dicts to send as WebSocket JSON messages: `{"type": "chunk",
+    /// "text": ...}`, `{"type": "keepalive"}`, then `{"type": "end"}` or `{"type":
+    /// "error", "message": ...}`.
+    #[pyo3(signature = (keepalive=15.0))]
+    fn events(slf: Py<Self>, keepalive: Option<f64>) -> PyResult<EventStream> {
+        EventStream::new(slf, keepalive, false)
+    }
+}
+
+pub(crate) fn channel(buffering: Buffering) -> (TextSink, TextStream) {
+    let (tx, rx) = mpsc::channel(buffering.capacity);
+    let rx = Arc::new(tokio::sync::Mutex::new(Reader {
+        rx,
+        held: String::new(),
+        failed: None,
+        ended: None,
+    }));
+    let timing = Arc::new(Mutex::new(Timing::default()));
+    let citations = Arc::new(Mutex::new(Vec::new()));
+    let retries = Arc::new(Mutex::new(Retries::default()));
+    let artifacts = Arc::new(Mutex::new(Vec::new()));
+    (
+        TextSink {
+            tx,
+            rx: Arc::downgrade(&rx),
+            buffering,
+            timing: timing.clone(),
+            citations: citations.clone(),
+            retries: retries.clone(),
+            artifacts: artifacts.clone(),
+            restore: None,
+            turn: Mutex::new(TurnText::default()),
+        },
+        TextStream {
+            rx,
+            created: Instant::now(),
+            timing,
+            citations,
+            retries,
+            artifacts,
+        },
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Send `chunks` through a stream holding two under `overflow`, returning what each
+    /// send did and what the consumer then reads.
+    fn overflowing(overflow: Overflow, chunks: &[&str]) -> (Vec<String>, Vec<String>) {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let buffering = Buffering {
+            capacity: 2,
+            overflow,
+        };
+        let (sink, stream) = channel(buffering);
+        runtime.block_on(async {
+            let mut sent = Vec::new();
+            for chunk in chunks {
+                let send = sink.send(chunk.to_string());
+                sent.push(match timeout(Duration::from_millis(20), send).await {
+                    Ok(Ok(())) => "sent".to_string(),
+                    Ok(Err(err)) => err.to_string(),
+                    Err(_) => "blocked".to_string(),
+                });
+            }
+            let mut reader = stream.rx.lock().await;
+            let mut read = Vec::new();
+            while let Ok(Ok(text)) = reader.rx.try_recv() {
+                read.push(text);
+            }
+            (sent, read)
+        })
+    }
+
+    #[test]
+    fn a_full_stream_blocks_the_sink() {
+        let (sent, read) = overflowing(Overflow::Block, &["a", "b", "c"]);
+        assert_eq!(sent, ["sent", "sent", "blocked"]);
+        assert_eq!(read, ["a", "b"]);
+    }
+
+    #[test]
+    fn a_full_stream_drops_its_oldest_chunk() {
+        let (sent, read) = overflowing(Overflow::DropOldest, &["a", "b", "c", "d"]);
+        assert_eq!(sent, ["sent"; 4]);
+        assert_eq!(read, ["c", "d"]);
+    }
+
+    #[test]
+    fn a_full_stream_fails_the_sink() {
+        let (sent, read) = overflowing(Overflow::Error, &["a", "b", "c"]);
+        assert_eq!(sent[..2], ["sent", "sent"]);
+        assert_eq!(sent[2], RustedChainError::StreamOverflow(2).to_string());
+        assert_eq!(read, ["a", "b"]);
+    }
+
+    /// A stream that has been sent `chunks` and closed.
+    fn sent(chunks: &[&str]) -> TextStream {
+        let (sink, stream) = channel(Buffering::default());
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        for chunk in chunks {
+            runtime.block_on(sink.send(chunk.to_string())).unwrap();
+        }
+        stream
+    }
+
+    #[test]
+    fn chunks_end_on_grapheme_boundaries() {
+        Python::initialize();
+        let stream = sent(&["cafe", "\u{301} au", " lait"]);
+        let chunks: Vec<String> =
+            Python::attach(|py| std::iter::from_fn(|| stream.next_chunk(py).unwrap()).collect());
+        assert_eq!(chunks, ["caf", "e\u{301} a", "u lai", "t"]);
+    }
+
+    #[test]
+    fn overlapping_reads_take_chunks_in_turn() {
+        Python::initialize();
+        let stream = sent(&["ab", "cd", "ef"]);
+        let mut chunks: Vec<String> = std::thread::scope(|scope| {
+            let readers: Vec<_> = (0..4)
+                .map(|_| scope.spawn(|| Python::attach(|py| stream.next_chunk(py).unwrap())))
+                .collect();
+            readers
+                .into_iter()
+                .filter_map(|reader| reader.join().unwrap())
+                .collect()
+        });
+        chunks.sort();
+        assert_eq!(chunks, ["a", "bc", "de", "f"]);
+    }
+
+    #[test]
+    fn an_error_after_a_full_buffer_follows_its_chunks() {
+        Python::initialize();
+        let (sink, stream) = channel(Buffering {
+            capacity: 2,
+            overflow: Overflow::Block,
+        });
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        for chunk in ["a", "b"] {
+            runtime.block_on(sink.send(chunk.to_string())).unwrap();
+        }
+        sink.fail(PyValueError::new_err("provider failed"));
+        drop(sink);
+        Python::attach(|py| {
+            assert_eq!(stream.next_chunk(py).unwrap().as_deref(), Some("a"));
+            assert_eq!(stream.next_chunk(py).unwrap().as_deref(), Some("b"));
+            let err = stream.next_chunk(py).unwrap_err();
+            assert_eq!(err.to_string(), "ValueError: provider failed");
+        });
+    }
+
+    #[test]
+    fn chunks_can_be_awaited() {
+        Python::initialize();
+        crate::asyncio::init();
+        let stream = sent(&["ab", "cd"]);
+        Python::attach(|py| {
+            let code = c"import asyncio
+
+async def read(stream):
+    return [chunk async for chunk in stream]
+
+def run(stream):
+    return asyncio.run(read(stream))
+";
+            let module = PyModule::from_code(py, code, c"awaited.py", c"awaited").unwrap();
+            let chunks = module.getattr("run").unwrap();
+            let chunks = chunks.call1((Py::new(py, stream).unwrap(),)).unwrap();
+            assert_eq!(chunks.extract::<Vec<String>>().unwrap(), ["a", "bc", "d"]);
+        });
+    }
+
+    #[test]
+    fn overflow_policies_parse_by_name() {
+        assert!(Overflow::parse("block").ok() == Some(Overflow::Block));
+        assert!(Overflow::parse("drop_oldest").ok() == Some(Overflow::DropOldest));
+        assert!(Overflow::parse("error").ok() == Some(Overflow::Error));
+        assert!(Overflow::parse("drop_newest").is_err());
+    }
+
+    fn parse(chunks: &[&[u8]]) -> Vec<String> {
+        let mut parser = SseParser::default();
+        chunks.iter().flat_map(|chunk| parser.push(chunk)).collect()
+    }
+
+    #[test]
+    fn events_are_split_on_blank_lines() {
+        let events = parse(&[
+            b"data: one\n\ndata: tw",
+            b"o\r\n\r\n: comment\n\nda",
+            b"ta: three\n",
+        ]);
+        assert_eq!(events, ["one", "two"]);
+    }
+
+    #[test]
+    fn data_lines_are_joined() {
+        let events = parse(&[b"event: delta\ndata: a\ndata:b\nid: 1\n\n"]);
+        assert_eq!(events, ["a\nb"]);
+    }
+
+    #[test]
+    fn separators_split_between_pushes_are_found() {
+        let text = format!("data: {}\r\n\r\ndata: end\n\n", "x".repeat(1000));
+        let bytes: Vec<&[u8]> = text.as_bytes().chunks(1).collect();
+        assert_eq!(parse(&bytes), ["x".repeat(1000), "end".to_string()]);
+    }
+
+    #[test]
+    fn characters_split_between_pushes_are_kept_whole() {
+        let text = "data: {\"t\":\"é😀\"}\n\n".as_bytes();
+        let (head, tail) = text.split_at(14);
+        assert_eq!(parse(&[head, tail]), ["{\"t\":\"é😀\"}"]);
+    }
+
+    #[test]
+    fn characters_split_between_events_move_to_the_next_string() {
+        let events = parse(&[b"data: {\"t\":\"a\xc3\"}\n\ndata: {\"t\":\"\xa9b\"}\n\n"]);
+        assert_eq!(events, ["{\"t\":\"a\"}", "{\"t\":\"éb\"}"]);
+    }
+
+    #[test]
+    fn surrogate_pairs_split_between_events_are_rejoined() {
+        let events = parse(&[b"data: {\"t\":\"\\ud83d\"}\n\ndata: {\"t\":\"\\ude00!\"}\n\n"]);
+        assert_eq!(events, ["{\"t\":\"\"}", "{\"t\":\"\\ud83d\\ude00!\"}"]);
+    }
+
+    #[test]
+    fn unpaired_surrogates_become_replacement_characters() {
+        let events = parse(&[b"data: {\"t\":\"a\\ude00b\"}\n\n"]);
+        assert_eq!(events, ["{\"t\":\"a\\ufffdb\"}"]);
+    }
+}
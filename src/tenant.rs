@@ -0,0 +1,261 @@
+//! Per-customer configuration passed to a single call: credentials, an endpoint, a
+//! spending budget and rate limits, so one process can serve many tenants.
+
+use crate::claude::Claude;
+use crate::downgrade::Spend;
+use crate::error::RustedChainError;
+use crate::estimate::Estimate;
+use crate::gemini::Gemini;
+use crate::huggingface::HuggingFace;
+use crate::keys::ApiKey;
+use crate::limits::{ToolLimiter, ToolPermit};
+use crate::openai::OpenAI;
+use crate::options::AgentOptions;
+use crate::run::RunRecord;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+const PROVIDERS: &[&str] = &["openai", "anthropic", "gemini", "huggingface"];
+
+pub(crate) struct TenantState {
+    name: String,
+    api_keys: HashMap<String, ApiKey>,
+    base_urls: HashMap<String, String>,
+    max_cost_usd: Option<f64>,
+    max_tokens: Option<u64>,
+    limiter: Option<ToolLimiter>,
+    spend: Mutex<(u64, Spend)>,
+}
+
+impl TenantState {
+    /// Refuse a run once the budget is spent, or when `planned`, the estimate of the
+    /// run's prompt, would go over what is left. The answer's spend is only known after
+    /// a run, so a run that crosses a limit with it completes.
+    pub(crate) fn check_budget(&self, planned: &Estimate) -> Result<(), RustedChainError> {
+        let (_, spend) = *self.spend.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(limit) = self.max_cost_usd {
+            if spend.cost_usd >= limit {
+                return Err(RustedChainError::BudgetExceeded(format!(
+                    "tenant '{}' has spent ${:.4} of max_cost_usd={}",
+                    self.name, spend.cost_usd, limit
+                )));
+            }
+            if let Some(cost) = planned
+                .min_cost_usd
+                .filter(|cost| spend.cost_usd + cost > limit)
+            {
+                return Err(RustedChainError::BudgetExceeded(format!(
+                    "tenant '{}' has ${:.4} left of max_cost_usd={}, less than the estimated \
+                     ${:.4} prompt",
+                    self.name,
+                    limit - spend.cost_usd,
+                    limit,
+                    cost
+                )));
+            }
+        }
+        if let Some(limit) = self.max_tokens {
+            if spend.tokens() >= limit {
+                return Err(RustedChainError::BudgetExceeded(format!(
+                    "tenant '{}' has used {} of max_tokens={}",
+                    self.name,
+                    spend.tokens(),
+                    limit
+                )));
+            }
+            if spend.tokens() + planned.prompt_tokens > limit {
+                return Err(RustedChainError::BudgetExceeded(format!(
+                    "tenant '{}' has {} left of max_tokens={}, less than the estimated {} \
+                     token prompt",
+                    self.name,
+                    limit - spend.tokens(),
+                    limit,
+                    planned.prompt_tokens
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Check the budget and wait for a slot under the tenant's limits. Call with the
+    /// GIL released; the permit is held for the whole run.
+    pub(crate) fn admit(
+        &self,
+        planned: &Estimate,
+    ) -> Result<Option<ToolPermit<'_>>, RustedChainError> {
+        self.check_budget(planned)?;
+        Ok(self.limiter.as_ref().map(ToolLimiter::acquire))
+    }
+
+    pub(crate) fn record(&self, run: &RunRecord) {
+        let mut spend = self.spend.lock().unwrap_or_else(|e| e.into_inner());
+        spend.0 += 1;
+        for (model, usage) in run.usage_by_model() {
+            spend.1.add(model, usage);
+        }
+    }
+
+    /// `metadata` with the tenant's name added under `"tenant"`, for usage records,
+    /// audit logs and trackers.
+    pub(crate) fn label(
+        &self,
+        py: Python,
+        metadata: Option<Py<PyDict>>,
+    ) -> PyResult<Option<Py<PyDict>>> {
+        let labelled = match &metadata {
+            Some(metadata) => metadata.bind(py).copy()?,
+            None => PyDict::new(py),
+        };
+        labelled.set_item("tenant", &self.name)?;
+        Ok(Some(labelled.unbind()))
+    }
+}
+
+/// Clients a tenant can hand its own credentials and endpoint to.
+pub(crate) trait Scoped: Sized {
+    fn scoped(self, tenant: &TenantState) -> Self;
+}
+
+impl Scoped for OpenAI {
+    fn scoped(mut self, tenant: &TenantState) -> Self {
+        if let Some(key) = tenant.api_keys.get("openai") {
+            self = self.with_api_key(key.clone());
+        }
+        if let Some(url) = tenant.base_urls.get("openai") {
+            self = self.with_base_url(url.clone());
+        }
+        self
+    }
+}
+
+macro_rules! scoped_by_key {
+    ($client:ty, $provider:literal) => {
+        impl Scoped for $client {
+            fn scoped(self, tenant: &TenantState) -> Self {
+                match tenant.api_keys.get($provider) {
+                    Some(key) => self.with_api_key(key.clone()),
+                    None => self,
+                }
+            }
+        }
+    };
+}
+
+scoped_by_key!(Claude, "anthropic");
+scoped_by_key!(Gemini, "gemini");
+scoped_by_key!(HuggingFace, "huggingface");
+
+/// `client` and `options` for one call, under `context` if one was given.
+pub(crate) fn scope<'a, C: Scoped>(
+    client: C,
+    options: &'a AgentOptions,
+    context: Option<&Bound<'_, Tenant>>,
+) -> (C, Cow<'a, AgentOptions>) {
+    let Some(tenant) = context else {
+        return (client, Cow::Borrowed(options));
+    };
+    let state = tenant.get().state.clone();
+    let mut options = options.clone();
+    let client = client.scoped(&state);
+    options.tenant = Some(state);
+    (client, Cow::Owned(options))
+}
+
+/// One customer's credentials, endpoint, budget and limits, passed per call as
+/// `invoke(query, context=tenant)`. Spend is tracked on the tenant across every model
+/// it is used with.
+#[pyclass(frozen)]
+pub struct Tenant {
+    state: Arc<TenantState>,
+}
+
+#[pymethods]
+impl Tenant {
+    /// `api_keys` maps a provider (`"openai"`, `"anthropic"`, `"gemini"`,
+    /// `"huggingface"`) to a key, a list of keys, a `KeyPool` or a token callback.
+    /// `base_urls` may only set `"openai"`, for OpenAI-compatible servers.
+    /// `max_concurrency` and `rate_limit_per_min` apply to the tenant's runs.
+    #[new]
+    #[pyo3(signature = (name, api_keys=None, base_urls=None, max_cost_usd=None, max_tokens=None, max_concurrency=None, rate_limit_per_min=None))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(
+        name: String,
+        api_keys: Option<HashMap<String, ApiKey>>,
+        base_urls: Option<HashMap<String, String>>,
+        max_cost_usd: Option<f64>,
+        max_tokens: Option<u64>,
+        max_concurrency: Option<usize>,
+        rate_limit_per_min: Option<usize>,
+    ) -> PyResult<Self> {
+        let api_keys = api_keys.unwrap_or_default();
+        if let Some(provider) = api_keys.keys().find(|p| !PROVIDERS.contains(&p.as_str())) {
+            return Err(PyValueError::new_err(format!(
+                "unknown provider '{}' in api_keys, expected one of {}",
+                provider,
+                PROVIDERS.join(", ")
+            )));
+        }
+        let base_urls = base_urls.unwrap_or_default();
+        if let Some(provider) = base_urls.keys().find(|p| p.as_str() != "openai") {
+            return Err(PyValueError::new_err(format!(
+                "base_urls only supports 'openai', got '{}'",
+                provider
+            )));
+        }
+        if max_concurrency == Some(0) || rate_limit_per_min == Some(0) {
+            return Err(PyValueError::new_err(
+                "max_concurrency and rate_limit_per_min must be at least 1",
+            ));
+        }
+        let limiter = (max_concurrency.is_some() || rate_limit_per_min.is_some())
+            .then(|| ToolLimiter::new(max_concurrency, rate_limit_per_min));
+        Ok(Tenant {
+            state: Arc::new(TenantState {
+                name,
+                api_keys,
+                base_urls,
+                max_cost_usd,
+                max_tokens,
+                limiter,
+                spend: Mutex::new((0, Spend::default())),
+            }),
+        })
+    }
+
+    #[getter]
+    fn name(&self) -> &str {
+        &self.state.name
+    }
+
+    /// `{"runs", "input_tokens", "output_tokens", "cost_usd"}` over every call made
+    /// with this tenant. The cost only counts models with a known price.
+    #[getter]
+    fn usage<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyDict>> {
+        let (runs, spend) = *self.state.spend.lock().unwrap_or_else(|e| e.into_inner());
+        let usage = PyDict::new(py);
+        usage.set_item("runs", runs)?;
+        usage.set_item("input_tokens", spend.usage.input_tokens)?;
+        usage.set_item("output_tokens", spend.usage.output_tokens)?;
+        usage.set_item("cost_usd", spend.cost_usd)?;
+        Ok(usage)
+    }
+
+    /// Forget the tenant's spend, e.g. at the start of a billing period.
+    fn reset_usage(&self) {
+        *self.state.spend.lock().unwrap_or_else(|e| e.into_inner()) = (0, Spend::default());
+    }
+
+    fn __repr__(&self) -> String {
+        let mut providers: Vec<&str> = self.state.api_keys.keys().map(String::as_str).collect();
+        providers.sort_unstable();
+        format!(
+            "Tenant(name='{}', api_keys=[{}])",
+            self.state.name,
+            providers.join(", ")
+        )
+    }
+}
@@ -0,0 +1,180 @@
+//! Canonical JSON for agent runs, the basis of the golden-transcript helpers in
+//! `rusted_chain.testing`.
+//!
+//! A run (an answer string, an `AgentResponse`, a `WorkflowResult`, a `Session`, or
+//! plain dicts and lists holding them) becomes JSON with sorted keys and fixed
+//! formatting, so the same behaviour always serializes to the same text. Volatile fields
+//! such as durations and ids can be masked with `ignore`.
+
+use crate::moderation::ModerationResult;
+use crate::session::Session;
+use crate::workflow::WorkflowResult;
+use crate::{AgentResponse, ResponseMetadata, ToolCall};
+use pyo3::prelude::*;
+use pyo3::types::{PyBool, PyDict, PyFloat, PyInt, PyList, PyString, PyTuple};
+use serde_json::{json, Map, Value};
+
+/// What masked values are replaced with.
+const IGNORED: &str = "<ignored>";
+
+/// The transcript of a run as plain JSON data.
+fn transcript(run: &Bound<'_, PyAny>) -> PyResult<Value> {
+    if run.is_instance_of::<PyString>() {
+        return Ok(json!({ "output": run.extract::<String>()? }));
+    }
+    to_value(run)
+}
+
+fn to_value(value: &Bound<'_, PyAny>) -> PyResult<Value> {
+    if value.is_none() {
+        return Ok(Value::Null);
+    }
+    // bool before int: Python's True is an int too.
+    if value.is_instance_of::<PyBool>() {
+        return Ok(Value::Bool(value.extract()?));
+    }
+    if value.is_instance_of::<PyInt>() {
+        return Ok(match value.extract::<i64>() {
+            Ok(n) => json!(n),
+            Err(_) => Value::String(value.str()?.to_string()),
+        });
+    }
+    if value.is_instance_of::<PyFloat>() {
+        return Ok(
+            serde_json::Number::from_f64(value.extract()?).map_or(Value::Null, Value::Number)
+        );
+    }
+    if value.is_instance_of::<PyString>() {
+        return Ok(Value::String(value.extract()?));
+    }
+    if let Ok(dict) = value.cast::<PyDict>() {
+        let mut map = Map::new();
+        for (key, item) in dict.iter() {
+            map.insert(key.str()?.to_string(), to_value(&item)?);
+        }
+        return Ok(Value::Object(map));
+    }
+    if value.is_instance_of::<PyList>() || value.is_instance_of::<PyTuple>() {
+        return value
+            .try_iter()?
+            .map(|item| to_value(&item?))
+            .collect::<PyResult<Vec<_>>>()
+            .map(Value::Array);
+    }
+    if value.is_instance_of::<AgentResponse>() {
+        return attributes(value, &["text", "tool_call", "metadata"]);
+    }
+    if let Ok(call) = value.cast::<ToolCall>() {
+        let call = call.borrow();
+        // Arguments are parsed so that key order in the model's JSON does not matter.
+        let args =
+            serde_json::from_str(&call.args).unwrap_or_else(|_| Value::String(call.args.clone()));
+        return Ok(json!({ "name": call.name, "args": args }));
+    }
+    if value.is_instance_of::<ResponseMetadata>() {
+        return attributes(value, &["detected_language", "moderation"]);
+    }
+    if value.is_instance_of::<ModerationResult>() {
+        return attributes(
+            value,
+            &["flagged", "categories", "category_scores", "matches"],
+        );
+    }
+    if value.is_instance_of::<WorkflowResult>() {
+        return attributes(value, &["output", "state", "trace"]);
+    }
+    if value.is_instance_of::<Session>() {
+        return attributes(value, &["history"]);
+    }
+    // Documents and user objects that know how to describe themselves.
+    if let Ok(to_dict) = value.getattr("to_dict") {
+        if to_dict.is_callable() {
+            return to_value(&to_dict.call0()?);
+        }
+    }
+    Ok(Value::String(value.str()?.to_string()))
+}
+
+fn attributes(value: &Bound<'_, PyAny>, names: &[&str]) -> PyResult<Value> {
+    let mut map = Map::new();
+    for name in names {
+        if let Ok(attribute) = value.getattr(*name) {
+            map.insert(name.to_string(), to_value(&attribute)?);
+        }
+    }
+    Ok(Value::Object(map))
+}
+
+/// Replace the values selected by `pattern`: a bare key such as `"duration_s"` matches at
+/// any depth; a dotted path such as `"trace.*.duration_s"` starts at the root, with `*`
+/// matching any key or index.
+fn mask(value: &mut Value, pattern: &str) {
+    if pattern.contains('.') {
+        let path: Vec<&str> = pattern.split('.').collect();
+        mask_path(value, &path);
+    } else {
+        mask_key(value, pattern);
+    }
+}
+
+fn mask_key(value: &mut Value, key: &str) {
+    match value {
+        Value::Object(map) => {
+            for (name, item) in map.iter_mut() {
+                if name == key {
+                    *item = Value::String(IGNORED.to_string());
+                } else {
+                    mask_key(item, key);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(|item| mask_key(item, key)),
+        _ => {}
+    }
+}
+
+fn mask_path(value: &mut Value, path: &[&str]) {
+    let Some((first, rest)) = path.split_first() else {
+        *value = Value::String(IGNORED.to_string());
+        return;
+    };
+    match value {
+        Value::Object(map) => {
+            for (name, item) in map.iter_mut() {
+                if *first == "*" || name == first {
+                    mask_path(item, rest);
+                }
+            }
+        }
+        Value::Array(items) => {
+            for (index, item) in items.iter_mut().enumerate() {
+                if *first == "*" || first.parse() == Ok(index) {
+                    mask_path(item, rest);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Serialize a run as canonical JSON: sorted keys, two-space indentation and a trailing
+/// newline. Fields matched by `ignore` are replaced with `"<ignored>"`, so golden files
+/// still record that they exist.
+#[pyfunction]
+#[pyo3(signature = (run, ignore=None))]
+pub fn canonical_json(run: &Bound<'_, PyAny>, ignore: Option<Vec<String>>) -> PyResult<String> {
+    let mut value = transcript(run)?;
+    for pattern in ignore.iter().flatten() {
+        mask(&mut value, pattern);
+    }
+    let mut text = serde_json::to_string_pretty(&value)
+        .map_err(|e| pyo3::exceptions::PyValueError::new_err(e.to_string()))?;
+    text.push('\n');
+    Ok(text)
+}
+
+pub(crate) fn register(parent: &Bound<'_, PyModule>) -> PyResult<()> {
+    let m = PyModule::new(parent.py(), "testing")?;
+    m.add_function(wrap_pyfunction!(canonical_json, &m)?)?;
+    parent.add_submodule(&m)
+}
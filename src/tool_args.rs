@@ -0,0 +1,350 @@
+//! Tool arguments converted to the types the tool's schema declares. Models often send
+//! `"5"` for an integer or `"2024-01-01"` for a date; the tool gets an `int` and a
+//! `datetime.date` instead of failing on a string.
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList};
+use serde_json::Value;
+
+/// How arguments that don't match their schema are handled (`coerce_tool_args`).
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum Coercion {
+    /// Arguments go to the tool as the model sent them.
+    Off,
+    /// Convert what converts cleanly and leave the rest for the tool.
+    #[default]
+    Lenient,
+    /// Convert, and send arguments that cannot be converted back to the model as an
+    /// error instead of calling the tool.
+    Strict,
+}
+
+impl Coercion {
+    pub(crate) fn parse(value: &str) -> PyResult<Self> {
+        match value {
+            "off" => Ok(Self::Off),
+            "lenient" => Ok(Self::Lenient),
+            "strict" => Ok(Self::Strict),
+            other => Err(PyValueError::new_err(format!(
+                "coerce_tool_args must be 'lenient', 'strict' or 'off', got '{}'",
+                other
+            ))),
+        }
+    }
+}
+
+/// `args` as keyword arguments for `tool`, converted to the types of its `to_dict()`
+/// parameters. With `Strict`, arguments that could not be converted are returned as
+/// the error, one line per argument.
+pub(crate) fn kwargs<'py>(
+    py: Python<'py>,
+    tool: &Bound<'py, PyAny>,
+    args: &Value,
+    coercion: Coercion,
+) -> PyResult<Result<Bound<'py, PyDict>, String>> {
+    let parameters = match coercion {
+        Coercion::Off => None,
+        _ => parameters(tool),
+    };
+    let Some(parameters) = parameters else {
+        return Ok(Ok(pythonize::pythonize(py, args)?
+            .cast_into::<PyDict>()
+            .unwrap_or_else(|_| PyDict::new(py))));
+    };
+    let mut converter = Converter {
+        py,
+        root: &parameters,
+        problems: Vec::new(),
+    };
+    let kwargs = converter.convert(&parameters, args, "")?;
+    if coercion == Coercion::Strict && !converter.problems.is_empty() {
+        return Ok(Err(converter.problems.join("\n")));
+    }
+    Ok(Ok(kwargs
+        .cast_into::<PyDict>()
+        .unwrap_or_else(|_| PyDict::new(py))))
+}
+
+/// `args` without the `null`s sent for parameters `tool` doesn't require. Under strict
+/// function calling the model has to send every argument, `null` for the ones it
+/// leaves out, so these stand for arguments that were never given.
+pub(crate) fn without_unset(tool: &Bound<'_, PyAny>, args: &Value) -> Value {
+    let Value::Object(args) = args else {
+        return args.clone();
+    };
+    let parameters = parameters(tool);
+    let required = parameters
+        .as_ref()
+        .and_then(|parameters| parameters.get("required"))
+        .and_then(Value::as_array);
+    let required = |name: &str| required.is_some_and(|names| names.iter().any(|n| n == name));
+    Value::Object(
+        args.iter()
+            .filter(|(name, value)| !value.is_null() || required(name))
+            .map(|(name, value)| (name.clone(), value.clone()))
+            .collect(),
+    )
+}
+
+/// The JSON schema of a tool's arguments, from its `to_dict()`.
+fn parameters(tool: &Bound<'_, PyAny>) -> Option<Value> {
+    let schema: Value = pythonize::depythonize(&tool.call_method0("to_dict").ok()?).ok()?;
+    match schema.get("parameters") {
+        Some(parameters @ Value::Object(_)) => Some(parameters.clone()),
+        _ => None,
+    }
+}
+
+struct Converter<'a, 'py> {
+    py: Python<'py>,
+    /// The whole parameters schema, which `$ref`s point into.
+    root: &'a Value,
+    problems: Vec<String>,
+}
+
+impl<'a, 'py> Converter<'a, 'py> {
+    /// `schema` itself, or the definition it refers to.
+    fn resolve(&self, schema: &'a Value) -> &'a Value {
+        schema
+            .get("$ref")
+            .and_then(Value::as_str)
+            .and_then(|pointer| self.root.pointer(pointer.trim_start_matches('#')))
+            .unwrap_or(schema)
+    }
+
+    fn convert(
+        &mut self,
+        schema: &'a Value,
+        value: &Value,
+        path: &str,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let schema = self.resolve(schema);
+        if value.is_null() {
+            return Ok(self.py.None().into_bound(self.py));
+        }
+        if let Some(branches) = schema
+            .get("anyOf")
+            .or_else(|| schema.get("oneOf"))
+            .and_then(Value::as_array)
+        {
+            let branches: Vec<&Value> = branches.iter().collect();
+            return self.either(&branches, value, path);
+        }
+        match schema.get("type") {
+            Some(Value::String(kind)) => self.typed(kind, schema, value, path),
+            // `"type": ["integer", "null"]` is `anyOf` over the types.
+            Some(Value::Array(kinds)) => {
+                let branches: Vec<Value> = kinds
+                    .iter()
+                    .map(|kind| {
+                        let mut branch = schema.clone();
+                        branch["type"] = kind.clone();
+                        branch
+                    })
+                    .collect();
+                let mut converter = Converter {
+                    py: self.py,
+                    root: self.root,
+                    problems: Vec::new(),
+                };
+                let branches: Vec<&Value> = branches.iter().collect();
+                let converted = converter.either(&branches, value, path)?;
+                self.problems.append(&mut converter.problems);
+                Ok(converted)
+            }
+            _ => self.unchanged(value),
+        }
+    }
+
+    /// A value for one of several allowed schemas: the first it already matches, else
+    /// the first it converts to.
+    fn either(
+        &mut self,
+        branches: &[&'a Value],
+        value: &Value,
+        path: &str,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        if let Some(branch) = branches
+            .iter()
+            .find(|branch| matches(self.resolve(branch), value))
+        {
+            return self.convert(branch, value, path);
+        }
+        let before = self.problems.len();
+        for branch in branches {
+            let converted = self.convert(branch, value, path)?;
+            if self.problems.len() == before {
+                return Ok(converted);
+            }
+            self.problems.truncate(before);
+        }
+        self.problem(path, "one of the types its schema allows", value)
+    }
+
+    fn typed(
+        &mut self,
+        kind: &str,
+        schema: &'a Value,
+        value: &Value,
+        path: &str,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let py = self.py;
+        match (kind, value) {
+            ("integer", Value::Number(n)) if n.is_i64() || n.is_u64() => self.unchanged(value),
+            ("integer", _) => match integer(value) {
+                Some(n) => Ok(n.into_pyobject(py)?.into_any()),
+                None => self.problem(path, "an integer", value),
+            },
+            ("number", Value::Number(_)) => self.unchanged(value),
+            ("number", Value::String(text)) => match text.trim().parse::<f64>() {
+                Ok(n) if n.is_finite() => Ok(n.into_pyobject(py)?.into_any()),
+                _ => self.problem(path, "a number", value),
+            },
+            ("boolean", Value::Bool(_)) => self.unchanged(value),
+            ("boolean", _) => match boolean(value) {
+                Some(flag) => Ok(flag.into_pyobject(py)?.to_owned().into_any()),
+                None => self.problem(path, "true or false", value),
+            },
+            ("string", Value::String(text)) => match schema.get("format").and_then(Value::as_str) {
+                Some(format @ ("date" | "date-time" | "time")) => {
+                    match temporal(py, format, text) {
+                        Some(parsed) => Ok(parsed),
+                        None => self.problem(path, &format!("an ISO 8601 {}", format), value),
+                    }
+                }
+                _ => self.unchanged(value),
+            },
+            ("string", Value::Number(_) | Value::Bool(_)) => {
+                Ok(value.to_string().into_pyobject(py)?.into_any())
+            }
+            ("array", Value::Array(items)) => {
+                let item_schema = schema.get("items").unwrap_or(&Value::Null);
+                let list = PyList::empty(py);
+                for (i, item) in items.iter().enumerate() {
+                    list.append(self.convert(item_schema, item, &format!("{}[{}]", path, i))?)?;
+                }
+                Ok(list.into_any())
+            }
+            // A JSON-encoded array, or a single item where a list was expected.
+            ("array", Value::String(text)) if text.trim_start().starts_with('[') => {
+                match serde_json::from_str::<Value>(text) {
+                    Ok(parsed @ Value::Array(_)) => self.typed(kind, schema, &parsed, path),
+                    _ => self.problem(path, "a list", value),
+                }
+            }
+            ("array", Value::Object(_)) => self.problem(path, "a list", value),
+            ("array", _) => self.typed(kind, schema, &Value::Array(vec![value.clone()]), path),
+            ("object", Value::Object(fields)) => {
+                let properties = schema.get("properties");
+                let additional = schema.get("additionalProperties").filter(|s| s.is_object());
+                let dict = PyDict::new(py);
+                for (key, field) in fields {
+                    let field_path = match path {
+                        "" => key.clone(),
+                        _ => format!("{}.{}", path, key),
+                    };
+                    let converted = match properties.and_then(|p| p.get(key)).or(additional) {
+                        Some(field_schema) => self.convert(field_schema, field, &field_path)?,
+                        None => self.unchanged(field)?,
+                    };
+                    dict.set_item(key, converted)?;
+                }
+                Ok(dict.into_any())
+            }
+            ("object", Value::String(text)) => match serde_json::from_str::<Value>(text) {
+                Ok(parsed @ Value::Object(_)) => self.typed(kind, schema, &parsed, path),
+                _ => self.problem(path, "an object", value),
+            },
+            ("object", _) => self.problem(path, "an object", value),
+            ("number", _) => self.problem(path, "a number", value),
+            ("string", _) => self.problem(path, "a string", value),
+            _ => self.unchanged(value),
+        }
+    }
+
+    fn unchanged(&self, value: &Value) -> PyResult<Bound<'py, PyAny>> {
+        Ok(pythonize::pythonize(self.py, value)?)
+    }
+
+    /// Note an argument that could not be converted; it is passed on unchanged.
+    fn problem(
+        &mut self,
+        path: &str,
+        expected: &str,
+        value: &Value,
+    ) -> PyResult<Bound<'py, PyAny>> {
+        let name = if path.is_empty() { "arguments" } else { path };
+        self.problems
+            .push(format!("`{}` must be {}, got {}", name, expected, value));
+        self.unchanged(value)
+    }
+}
+
+/// Whether `value` already has the JSON type `schema` asks for.
+fn matches(schema: &Value, value: &Value) -> bool {
+    match schema.get("type").and_then(Value::as_str) {
+        Some("integer") => value.is_i64() || value.is_u64(),
+        Some("number") => value.is_number(),
+        Some("boolean") => value.is_boolean(),
+        Some("string") => value.is_string(),
+        Some("array") => value.is_array(),
+        Some("object") => value.is_object(),
+        Some("null") => value.is_null(),
+        _ => false,
+    }
+}
+
+/// `5.0` and `"5"` as `5`; fractions are not integers.
+fn integer(value: &Value) -> Option<i64> {
+    let number = match value {
+        Value::Number(n) => n.as_f64()?,
+        Value::String(text) => {
+            let text = text.trim();
+            if let Ok(n) = text.parse::<i64>() {
+                return Some(n);
+            }
+            text.parse::<f64>().ok()?
+        }
+        _ => return None,
+    };
+    (number.is_finite() && number.fract() == 0.0 && number.abs() < 9.0e15).then_some(number as i64)
+}
+
+fn boolean(value: &Value) -> Option<bool> {
+    match value {
+        Value::String(text) => match text.trim().to_ascii_lowercase().as_str() {
+            "true" | "yes" | "y" | "1" => Some(true),
+            "false" | "no" | "n" | "0" => Some(false),
+            _ => None,
+        },
+        Value::Number(n) => match n.as_u64() {
+            Some(1) => Some(true),
+            Some(0) => Some(false),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// An ISO 8601 `date`, `date-time` or `time` string as a Python `datetime` object. A
+/// date-time given for a date keeps its date; a trailing `Z` means UTC.
+fn temporal<'py>(py: Python<'py>, format: &str, text: &str) -> Option<Bound<'py, PyAny>> {
+    let datetime = py.import("datetime").ok()?;
+    let text = text.trim();
+    let text = match text.strip_suffix('Z').or_else(|| text.strip_suffix('z')) {
+        Some(utc) => format!("{}+00:00", utc),
+        None => text.to_string(),
+    };
+    let parse = |class: &str| {
+        datetime
+            .getattr(class)
+            .and_then(|class| class.call_method1("fromisoformat", (&text,)))
+            .ok()
+    };
+    match format {
+        "date" => parse("date").or_else(|| parse("datetime")?.call_method0("date").ok()),
+        "date-time" => parse("datetime"),
+        _ => parse("time"),
+    }
+}
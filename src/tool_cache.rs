@@ -0,0 +1,57 @@
+//! Memoized tool results for tools added with a `cache_ttl`, shared by every run of a
+//! model.
+
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Results of one tool by normalized arguments, each kept for `ttl`.
+pub(crate) struct ToolCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<String, (Instant, Value)>>,
+}
+
+impl ToolCache {
+    pub(crate) fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The result of an earlier call with the same arguments, if it hasn't expired.
+    pub(crate) fn get(&self, args: &Value) -> Option<Value> {
+        let entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries
+            .get(&key(args))
+            .filter(|(stored, _)| stored.elapsed() < self.ttl)
+            .map(|(_, result)| result.clone())
+    }
+
+    /// Keep `result` for calls with `args`, dropping expired results on the way.
+    pub(crate) fn insert(&self, args: &Value, result: Value) {
+        let mut entries = self.entries.lock().unwrap_or_else(|e| e.into_inner());
+        entries.retain(|_, (stored, _)| stored.elapsed() < self.ttl);
+        entries.insert(key(args), (Instant::now(), result));
+    }
+}
+
+/// `args` in a form equal for equivalent calls: keys sorted (as serde_json keeps them),
+/// `null` arguments left out since they stand for the default, and strings trimmed.
+fn key(args: &Value) -> String {
+    fn normalize(value: &Value) -> Value {
+        match value {
+            Value::Object(map) => Value::Object(
+                map.iter()
+                    .filter(|(_, item)| !item.is_null())
+                    .map(|(name, item)| (name.clone(), normalize(item)))
+                    .collect::<Map<_, _>>(),
+            ),
+            Value::Array(items) => Value::Array(items.iter().map(normalize).collect()),
+            Value::String(text) => Value::String(text.trim().to_string()),
+            other => other.clone(),
+        }
+    }
+    normalize(args).to_string()
+}
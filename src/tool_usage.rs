@@ -0,0 +1,116 @@
+//! Per-tool usage statistics, kept for each run and for each agent across its runs.
+
+use pyo3::prelude::*;
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Running totals for one tool.
+#[derive(Clone, Copy, Default)]
+pub(crate) struct ToolTally {
+    calls: u64,
+    failures: u64,
+    latency: Duration,
+    /// Size of the successful results, as JSON.
+    result_bytes: u64,
+}
+
+impl ToolTally {
+    /// Count one execution; `result_bytes` is `None` when the tool raised.
+    pub(crate) fn record(&mut self, elapsed: Duration, result_bytes: Option<usize>) {
+        self.calls += 1;
+        self.latency += elapsed;
+        match result_bytes {
+            Some(bytes) => self.result_bytes += bytes as u64,
+            None => self.failures += 1,
+        }
+    }
+
+    /// The raw totals, for run exports.
+    pub(crate) fn to_json(self) -> Value {
+        json!({
+            "calls": self.calls,
+            "failures": self.failures,
+            "latency_ms": self.latency.as_millis() as u64,
+            "result_bytes": self.result_bytes,
+        })
+    }
+
+    fn usage(&self) -> ToolUsage {
+        let successes = self.calls - self.failures;
+        ToolUsage {
+            calls: self.calls,
+            failures: self.failures,
+            failure_rate: ratio(self.failures as f64, self.calls),
+            avg_latency_s: ratio(self.latency.as_secs_f64(), self.calls),
+            avg_result_bytes: ratio(self.result_bytes as f64, successes),
+        }
+    }
+}
+
+fn ratio(total: f64, count: u64) -> f64 {
+    if count == 0 {
+        0.0
+    } else {
+        total / count as f64
+    }
+}
+
+/// Tallies keyed by tool name.
+pub(crate) type Tallies = BTreeMap<String, ToolTally>;
+
+/// An agent's tallies over all its runs, shared by the copies of its options.
+pub(crate) type ToolLedger = Arc<Mutex<Tallies>>;
+
+pub(crate) fn record(ledger: &ToolLedger, name: &str, elapsed: Duration, bytes: Option<usize>) {
+    ledger
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .entry(name.to_string())
+        .or_default()
+        .record(elapsed, bytes);
+}
+
+/// The ledger's `ToolUsage` per tool, clearing it afterwards with `reset`.
+pub(crate) fn snapshot(ledger: &ToolLedger, reset: bool) -> HashMap<String, ToolUsage> {
+    let mut tallies = ledger.lock().unwrap_or_else(|e| e.into_inner());
+    let usage = summarize(&tallies);
+    if reset {
+        tallies.clear();
+    }
+    usage
+}
+
+pub(crate) fn summarize(tallies: &Tallies) -> HashMap<String, ToolUsage> {
+    tallies
+        .iter()
+        .map(|(name, tally)| (name.clone(), tally.usage()))
+        .collect()
+}
+
+/// How often a tool was called and how it fared.
+#[pyclass(get_all)]
+#[derive(Clone, Default)]
+pub struct ToolUsage {
+    /// Executions, failed ones included.
+    pub calls: u64,
+    /// Executions that raised.
+    pub failures: u64,
+    /// `failures / calls`.
+    pub failure_rate: f64,
+    /// Mean execution time in seconds.
+    pub avg_latency_s: f64,
+    /// Mean size of the successful results, serialized as JSON.
+    pub avg_result_bytes: f64,
+}
+
+#[pymethods]
+impl ToolUsage {
+    fn __repr__(&self) -> String {
+        format!(
+            "ToolUsage(calls={}, failures={}, avg_latency_s={:.3}, avg_result_bytes={:.0})",
+            self.calls, self.failures, self.avg_latency_s, self.avg_result_bytes
+        )
+    }
+}
@@ -0,0 +1,167 @@
+//! Versions of tool schemas, so a conversation that outlives a change to its tools can
+//! still be sent. Calls in the history to a tool that has since changed are migrated to
+//! its new parameters, or turned into plain text when they no longer fit.
+
+use crate::agent::ToolRequest;
+use crate::session::Entry;
+use pyo3::exceptions::PyUserWarning;
+use pyo3::prelude::*;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::CString;
+
+/// Function schemas by tool name.
+pub(crate) type ToolSchemas = BTreeMap<String, Value>;
+
+/// The schemas of a model's tools.
+pub(crate) fn schemas(py: Python, tools: &Option<Vec<Py<PyAny>>>) -> ToolSchemas {
+    crate::convert_tools(py, tools)
+        .into_iter()
+        .filter_map(|schema| Some((schema["name"].as_str()?.to_string(), schema)))
+        .collect()
+}
+
+/// A short hash of what a call has to match: the name and parameters, not the
+/// description.
+pub(crate) fn version(schema: &Value) -> String {
+    let signature = json!({ "name": schema["name"], "parameters": schema["parameters"] });
+    let digest = Sha256::digest(signature.to_string().as_bytes());
+    digest[..8]
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+/// `history`, made with the tools in `before`, with its tool calls brought in line
+/// with the tools in `after`, warning about each tool that changed. `migrate`, if
+/// given, is called as `migrate(name, args, old_schema, new_schema)` for calls to a
+/// changed tool and returns the new arguments, or `None` to keep the call as text only.
+pub(crate) fn reconcile(
+    py: Python,
+    history: &[Entry],
+    before: &ToolSchemas,
+    after: &ToolSchemas,
+    migrate: Option<&Py<PyAny>>,
+) -> PyResult<Vec<Entry>> {
+    let changed: BTreeMap<&str, Option<&Value>> = before
+        .iter()
+        .filter(|(name, old)| {
+            after
+                .get(*name)
+                .is_none_or(|new| version(new) != version(old))
+        })
+        .map(|(name, _)| (name.as_str(), after.get(name)))
+        .collect();
+    // Calls migrated and kept as text, per tool.
+    let mut counts: BTreeMap<&str, (usize, usize)> = BTreeMap::new();
+    // New arguments of calls whose result is still to come; `None` for text.
+    let mut pending: HashMap<(Option<String>, String), Option<Value>> = HashMap::new();
+    let mut out = Vec::with_capacity(history.len());
+    for entry in history {
+        let entry = match entry {
+            Entry::ToolCall(call) => match changed.get_key_value(call.name.as_str()) {
+                Some((name, new)) => {
+                    let args = match new {
+                        Some(new) => migrate_args(py, call, &before[*name], new, migrate)?,
+                        None => None,
+                    };
+                    let count = counts.entry(name).or_default();
+                    match args {
+                        Some(_) => count.0 += 1,
+                        None => count.1 += 1,
+                    }
+                    pending.insert((call.id.clone(), call.name.clone()), args.clone());
+                    match args {
+                        Some(args) => Entry::ToolCall(with_args(call, args)),
+                        // Text needs no tool to match.
+                        None => Entry::Assistant(format!(
+                            "[Called {} with {}; this tool has since changed or been removed.]",
+                            call.name, call.args
+                        )),
+                    }
+                }
+                None => entry.clone(),
+            },
+            Entry::ToolResult { call, result } => {
+                match pending.remove(&(call.id.clone(), call.name.clone())) {
+                    Some(Some(args)) => Entry::ToolResult {
+                        call: with_args(call, args),
+                        result: result.clone(),
+                    },
+                    Some(None) => Entry::User(format!("[Result of {}: {}]", call.name, result)),
+                    None => entry.clone(),
+                }
+            }
+            other => other.clone(),
+        };
+        out.push(entry);
+    }
+    for (name, (kept, dropped)) in counts {
+        let message = match changed[name] {
+            None => format!(
+                "tool '{}' is no longer available; {} earlier call(s) to it are kept as text",
+                name, dropped
+            ),
+            Some(new) => format!(
+                "the schema of tool '{}' changed ({} -> {}); {} earlier call(s) migrated, \
+                 {} kept as text",
+                name,
+                version(&before[name]),
+                version(new),
+                kept,
+                dropped
+            ),
+        };
+        let message = CString::new(message).unwrap_or_default();
+        PyErr::warn(py, &py.get_type::<PyUserWarning>(), &message, 1)?;
+    }
+    Ok(out)
+}
+
+fn with_args(call: &ToolRequest, args: Value) -> ToolRequest {
+    ToolRequest {
+        id: call.id.clone(),
+        name: call.name.clone(),
+        args,
+    }
+}
+
+/// A call's arguments under the tool's new schema: from `migrate`, or by dropping the
+/// ones the tool no longer takes. `None` if a now-required argument is missing.
+fn migrate_args(
+    py: Python,
+    call: &ToolRequest,
+    old: &Value,
+    new: &Value,
+    migrate: Option<&Py<PyAny>>,
+) -> PyResult<Option<Value>> {
+    if let Some(migrate) = migrate {
+        let args = migrate.bind(py).call1((
+            &call.name,
+            pythonize::pythonize(py, &call.args)?,
+            pythonize::pythonize(py, old)?,
+            pythonize::pythonize(py, new)?,
+        ))?;
+        if args.is_none() {
+            return Ok(None);
+        }
+        return Ok(Some(pythonize::depythonize(&args)?));
+    }
+    let parameters = &new["parameters"];
+    let args: serde_json::Map<String, Value> = call
+        .args
+        .as_object()
+        .into_iter()
+        .flatten()
+        .filter(|(key, _)| parameters["properties"].get(key.as_str()).is_some())
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    let complete = parameters["required"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .all(|required| args.contains_key(required));
+    Ok(complete.then_some(Value::Object(args)))
+}
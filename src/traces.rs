@@ -0,0 +1,266 @@
+//! A small local web UI for browsing recorded runs, in place of shipping traces to a
+//! hosted service. Runs are kept in memory once the viewer is started, and can also be
+//! read from a `run_log` file.
+
+use crate::RUNTIME;
+use crate::{export, feedback};
+use pyo3::exceptions::PyOSError;
+use pyo3::prelude::*;
+use serde_json::{json, Value};
+use std::collections::{HashSet, VecDeque};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+/// How many runs are kept in memory; older ones are dropped.
+const MAX_RUNS: usize = 1000;
+
+static SERVING: AtomicBool = AtomicBool::new(false);
+static RUNS: Mutex<VecDeque<Value>> = Mutex::new(VecDeque::new());
+
+/// Whether a viewer is running, so runs are worth exporting for it.
+pub(crate) fn serving() -> bool {
+    SERVING.load(Ordering::Relaxed)
+}
+
+/// Keep a finished run's export for the viewer, if one is running.
+pub(crate) fn record(run: &Value) {
+    if !serving() {
+        return;
+    }
+    let mut runs = RUNS.lock().unwrap_or_else(|e| e.into_inner());
+    if runs.len() == MAX_RUNS {
+        runs.pop_front();
+    }
+    runs.push_back(run.clone());
+}
+
+/// Attach feedback to a run kept for the viewer.
+pub(crate) fn add_feedback(run_id: &str, entry: &Value) {
+    let mut runs = RUNS.lock().unwrap_or_else(|e| e.into_inner());
+    if let Some(run) = runs.iter_mut().find(|run| run["run_id"] == run_id) {
+        feedback::add(run, vec![entry.clone()]);
+    }
+}
+
+/// Runs recorded in this process and in `run_log`, newest first.
+fn runs(run_log: Option<&PathBuf>) -> Vec<Value> {
+    let mut all: Vec<Value> = run_log
+        .and_then(|path| std::fs::read_to_string(path).ok())
+        .into_iter()
+        .flat_map(|text| {
+            let records = text
+                .lines()
+                .filter_map(|line| serde_json::from_str::<Value>(line).ok())
+                .collect();
+            feedback::fold(records)
+                .into_iter()
+                .filter(|record| export::check(record).is_ok())
+                .collect::<Vec<_>>()
+        })
+        .collect();
+    all.extend(
+        RUNS.lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .iter()
+            .cloned(),
+    );
+    let mut seen = HashSet::new();
+    all.reverse();
+    all.retain(|run| seen.insert(run["run_id"].as_str().unwrap_or_default().to_string()));
+    all.sort_by(|a, b| {
+        b["timings"]["started_at"]
+            .as_str()
+            .cmp(&a["timings"]["started_at"].as_str())
+    });
+    all
+}
+
+/// One line per run for the list view.
+fn summary(run: &Value) -> Value {
+    json!({
+        "run_id": run["run_id"],
+        "started_at": run["timings"]["started_at"],
+        "latency_ms": run["timings"]["latency_ms"],
+        "provider": run["provider"],
+        "model": run["model"],
+        "query": run["query"],
+        "error": run["error"],
+        "tool_calls": run["tool_calls"],
+        "tokens": run["usage"]["input_tokens"].as_u64().unwrap_or(0)
+            + run["usage"]["output_tokens"].as_u64().unwrap_or(0),
+        "tags": run["tags"],
+    })
+}
+
+fn respond(path: &str, run_log: Option<&PathBuf>) -> (&'static str, &'static str, String) {
+    const JSON: &str = "application/json";
+    match path {
+        "/" => ("200 OK", "text/html; charset=utf-8", PAGE.to_string()),
+        "/api/runs" => {
+            let list: Vec<Value> = runs(run_log).iter().map(summary).collect();
+            ("200 OK", JSON, Value::from(list).to_string())
+        }
+        _ => match path.strip_prefix("/api/runs/") {
+            Some(id) => match runs(run_log).into_iter().find(|run| run["run_id"] == id) {
+                Some(run) => ("200 OK", JSON, run.to_string()),
+                None => (
+                    "404 Not Found",
+                    JSON,
+                    json!({ "error": "no such run" }).to_string(),
+                ),
+            },
+            None => ("404 Not Found", "text/plain", String::new()),
+        },
+    }
+}
+
+/// Serve a trace viewer at `http://host:port/` from a background task. It lists the
+/// runs finished in this process from now on (the last 1000) and, with `run_log`,
+/// those in a `run_log` file, read again on every request. `/api/runs` and
+/// `/api/runs/<run_id>` serve the same data as JSON.
+#[pyfunction]
+#[pyo3(signature = (port=7465, host="127.0.0.1", run_log=None))]
+pub fn serve_traces(py: Python, port: u16, host: &str, run_log: Option<PathBuf>) -> PyResult<()> {
+    let address = format!("{}:{}", host, port);
+    let listener = py.detach(|| {
+        RUNTIME.block_on(async {
+            TcpListener::bind(&address)
+                .await
+                .map_err(|e| PyOSError::new_err(format!("cannot listen on {}: {}", address, e)))
+        })
+    })?;
+    SERVING.store(true, Ordering::Relaxed);
+
+    let run_log = Arc::new(run_log);
+    RUNTIME.spawn(async move {
+        while let Ok((mut socket, _)) = listener.accept().await {
+            let run_log = run_log.clone();
+            tokio::spawn(async move {
+                let mut request = [0u8; 1024];
+                let read = socket.read(&mut request).await.unwrap_or(0);
+                let path = std::str::from_utf8(&request[..read])
+                    .ok()
+                    .and_then(|r| r.split_whitespace().nth(1))
+                    .unwrap_or("");
+                let (status, content_type, body) = tokio::task::spawn_blocking({
+                    let path = path.to_string();
+                    move || respond(&path, run_log.as_ref().as_ref())
+                })
+                .await
+                .unwrap_or((
+                    "500 Internal Server Error",
+                    "text/plain",
+                    String::new(),
+                ));
+                let response = format!(
+                    "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+                     Connection: close\r\n\r\n{}",
+                    status,
+                    content_type,
+                    body.len(),
+                    body
+                );
+                let _ = socket.write_all(response.as_bytes()).await;
+            });
+        }
+    });
+    Ok(())
+}
+
+const PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>rusted_chain traces</title>
+<style>
+body { font-family: sans-serif; margin: 2em; }
+table { border-collapse: collapse; width: 100%; }
+th, td { text-align: left; padding: 0.3em 0.6em; border-bottom: 1px solid #ddd; vertical-align: top; }
+tr.run { cursor: pointer; }
+tr.run:hover { background: #f5f5f5; }
+tr.failed td.status { color: #dc2626; }
+details { margin: 0.3em 0 0.3em 1em; }
+summary { cursor: pointer; }
+summary .role { font-weight: bold; }
+pre { white-space: pre-wrap; word-wrap: break-word; margin: 0.3em 0; background: #fafafa; padding: 0.4em; }
+.muted { color: #6b7280; }
+</style>
+</head>
+<body>
+<h1>Runs</h1>
+<p class="muted" id="count"></p>
+<table>
+<thead><tr><th>Started</th><th>Model</th><th>Query</th><th>Tools</th><th>Tokens</th><th>Latency</th><th>Status</th></tr></thead>
+<tbody id="runs"></tbody>
+</table>
+<script>
+function el(tag, text, cls) {
+  const node = document.createElement(tag);
+  if (text !== undefined) node.textContent = text;
+  if (cls) node.className = cls;
+  return node;
+}
+function block(title, body, open) {
+  const details = el("details");
+  details.open = !!open;
+  const summary = el("summary");
+  summary.append(el("span", title, "role"));
+  details.append(summary, el("pre", typeof body === "string" ? body : JSON.stringify(body, null, 2)));
+  return details;
+}
+function tree(run) {
+  const root = el("div");
+  const messages = el("details");
+  messages.open = true;
+  messages.append(el("summary", "Messages (" + run.messages.length + ")"));
+  for (const m of run.messages) {
+    if (m.tool_call) messages.append(block("Tool call: " + m.tool_call.name, m.tool_call.args));
+    else if (m.role === "tool") messages.append(block("Tool result: " + m.name, m.content));
+    else messages.append(block(m.role, m.content, true));
+  }
+  root.append(messages);
+  if (run.error) root.append(block("Error", run.error, true));
+  root.append(block("Usage", run.usage), block("Timings", run.timings));
+  if (Object.keys(run.tool_usage || {}).length) root.append(block("Tool usage", run.tool_usage));
+  if ((run.feedback || []).length) root.append(block("Feedback", run.feedback, true));
+  root.append(block("Labels", {tags: run.tags, metadata: run.metadata}));
+  return root;
+}
+async function toggle(row, id) {
+  const next = row.nextElementSibling;
+  if (next && next.classList.contains("detail")) { next.remove(); return; }
+  const run = await (await fetch("/api/runs/" + encodeURIComponent(id))).json();
+  const detail = el("tr", undefined, "detail");
+  const cell = el("td");
+  cell.colSpan = 7;
+  cell.append(tree(run));
+  detail.append(cell);
+  row.after(detail);
+}
+async function load() {
+  const runs = await (await fetch("/api/runs")).json();
+  document.getElementById("count").textContent = runs.length + " runs";
+  const body = document.getElementById("runs");
+  body.replaceChildren();
+  for (const run of runs) {
+    const row = el("tr", undefined, run.error ? "run failed" : "run");
+    row.append(
+      el("td", (run.started_at || "").replace("T", " ").replace("Z", "")),
+      el("td", run.provider + " / " + run.model),
+      el("td", run.query),
+      el("td", String(run.tool_calls)),
+      el("td", String(run.tokens)),
+      el("td", run.latency_ms + " ms"),
+      el("td", run.error ? "error" : "ok", "status"));
+    row.onclick = () => toggle(row, run.run_id);
+    body.append(row);
+  }
+}
+load();
+</script>
+</body>
+</html>
+"#;
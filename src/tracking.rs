@@ -0,0 +1,464 @@
+//! Optional experiment-tracking integrations (Weights & Biases, MLflow).
+//!
+//! Enabled with `RUSTED_CHAIN_TRACKING=wandb`, `mlflow` or `wandb,mlflow`. Every finished
+//! run is uploaded in the background over each service's HTTP API; `flush_tracking`
+//! waits for pending uploads and is called automatically at interpreter exit. Feedback
+//! recorded on a run is added to its upload once that is done.
+
+use crate::error::RustedChainError;
+use crate::run::RunRecord;
+use crate::RUNTIME;
+use chrono::Utc;
+use futures::future::{BoxFuture, Shared};
+use futures::FutureExt;
+use once_cell::sync::Lazy;
+use pyo3::prelude::*;
+use reqwest::{Client, RequestBuilder};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::env;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, UNIX_EPOCH};
+use tokio::task::JoinHandle;
+
+/// MLflow rejects longer tag values on older servers.
+const MAX_TEXT_LEN: usize = 5000;
+
+const WANDB_UPSERT_RUN: &str = "mutation UpsertBucket($name: String, $project: String, \
+    $entity: String, $displayName: String, $jobType: String, $config: JSONString, \
+    $summaryMetrics: JSONString, $tags: [String!]) { upsertBucket(input: {name: $name, \
+    modelName: $project, entityName: $entity, displayName: $displayName, jobType: $jobType, \
+    config: $config, summaryMetrics: $summaryMetrics, tags: $tags}) { bucket { id } } }";
+
+enum Tracker {
+    Wandb {
+        base_url: String,
+        api_key: String,
+        project: String,
+        entity: Option<String>,
+    },
+    Mlflow {
+        base_url: String,
+        experiment_id: String,
+        token: Option<String>,
+        basic_auth: Option<(String, String)>,
+    },
+}
+
+/// What a tracker needs to add feedback to a run it has uploaded.
+#[derive(Clone)]
+enum Uploaded {
+    /// The run's name and summary, which an update replaces.
+    Wandb { name: String, summary: Value },
+    /// The run's id in MLflow.
+    Mlflow { run_id: String },
+}
+
+/// A run's upload to one tracker, awaited again to add feedback once it is done.
+type Upload = Shared<BoxFuture<'static, Option<Uploaded>>>;
+
+/// A run's id and its uploads, one per tracker in `TRACKERS` order.
+type RunUploads = (String, Vec<Upload>);
+
+/// How many runs' uploads are kept for feedback; feedback on older runs isn't sent.
+const MAX_UPLOADS: usize = 1000;
+
+static TRACKERS: Lazy<Vec<Tracker>> = Lazy::new(trackers_from_env);
+static PENDING: Lazy<Mutex<Vec<JoinHandle<()>>>> = Lazy::new(|| Mutex::new(Vec::new()));
+/// The latest runs' uploads, oldest first.
+static UPLOADS: Lazy<Mutex<VecDeque<RunUploads>>> = Lazy::new(|| Mutex::new(VecDeque::new()));
+static HTTP: Lazy<Client> = Lazy::new(Client::new);
+
+fn trackers_from_env() -> Vec<Tracker> {
+    dotenv::dotenv().ok();
+    let Ok(enabled) = env::var("RUSTED_CHAIN_TRACKING") else {
+        return Vec::new();
+    };
+
+    let mut trackers = Vec::new();
+    for name in enabled.split(',').map(str::trim).filter(|n| !n.is_empty()) {
+        match name {
+            "wandb" => match env::var("WANDB_API_KEY") {
+                Ok(api_key) => trackers.push(Tracker::Wandb {
+                    base_url: env::var("WANDB_BASE_URL")
+                        .unwrap_or_else(|_| "https://api.wandb.ai".to_string()),
+                    api_key,
+                    project: env::var("WANDB_PROJECT")
+                        .unwrap_or_else(|_| "rusted-chain".to_string()),
+                    entity: env::var("WANDB_ENTITY").ok(),
+                }),
+                Err(_) => log::warn!("wandb tracking needs WANDB_API_KEY; skipping"),
+            },
+            "mlflow" => match env::var("MLFLOW_TRACKING_URI") {
+                Ok(base_url) => trackers.push(Tracker::Mlflow {
+                    base_url: base_url.trim_end_matches('/').to_string(),
+                    experiment_id: env::var("MLFLOW_EXPERIMENT_ID")
+                        .unwrap_or_else(|_| "0".to_string()),
+                    token: env::var("MLFLOW_TRACKING_TOKEN").ok(),
+                    basic_auth: env::var("MLFLOW_TRACKING_USERNAME").ok().map(|user| {
+                        (
+                            user,
+                            env::var("MLFLOW_TRACKING_PASSWORD").unwrap_or_default(),
+                        )
+                    }),
+                }),
+                Err(_) => log::warn!("mlflow tracking needs MLFLOW_TRACKING_URI; skipping"),
+            },
+            other => log::warn!("unknown tracking backend '{}'", other),
+        }
+    }
+    trackers
+}
+
+/// Upload a finished run to every configured tracker without blocking the caller.
+pub(crate) fn log_run(record: RunRecord) {
+    if TRACKERS.is_empty() {
+        return;
+    }
+    let record = Arc::new(record);
+    let mut pending = PENDING.lock().unwrap_or_else(|e| e.into_inner());
+    pending.retain(|handle| !handle.is_finished());
+    let mut uploads = Vec::new();
+    for tracker in TRACKERS.iter() {
+        let record = Arc::clone(&record);
+        let upload = async move {
+            match tracker.log(&record).await {
+                Ok(uploaded) => Some(uploaded),
+                Err(err) => {
+                    log::warn!("failed to log run {}: {}", record.id, err);
+                    None
+                }
+            }
+        }
+        .boxed()
+        .shared();
+        pending.push(RUNTIME.spawn(upload.clone().map(|_| ())));
+        uploads.push(upload);
+    }
+    let mut recent = UPLOADS.lock().unwrap_or_else(|e| e.into_inner());
+    if recent.len() == MAX_UPLOADS {
+        recent.pop_front();
+    }
+    recent.push_back((record.id.clone(), uploads));
+}
+
+/// Add feedback to a run uploaded by `log_run`, once its upload is done, without
+/// blocking the caller.
+pub(crate) fn log_feedback(run_id: &str, score: f64, comment: Option<&str>) {
+    let uploads = UPLOADS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .find(|(id, _)| id == run_id)
+        .map(|(_, uploads)| uploads.clone());
+    let Some(uploads) = uploads else {
+        return;
+    };
+    // Stamped now, as feedback given in a row may reach a tracker out of order.
+    let recorded_at = Utc::now().timestamp_millis();
+    let mut pending = PENDING.lock().unwrap_or_else(|e| e.into_inner());
+    pending.retain(|handle| !handle.is_finished());
+    for (tracker, upload) in TRACKERS.iter().zip(uploads) {
+        let run_id = run_id.to_string();
+        let comment = comment.map(str::to_string);
+        pending.push(RUNTIME.spawn(async move {
+            let Some(uploaded) = upload.await else {
+                return;
+            };
+            if let Err(err) = tracker
+                .feedback(&uploaded, score, comment.as_deref(), recorded_at)
+                .await
+            {
+                log::warn!("failed to log feedback on run {}: {}", run_id, err);
+            }
+        }));
+    }
+}
+
+/// Wait up to `timeout` seconds for pending run uploads to finish.
+#[pyfunction]
+#[pyo3(signature = (timeout=10.0))]
+pub fn flush_tracking(py: Python, timeout: f64) {
+    py.detach(|| RUNTIME.block_on(drain(Duration::from_secs_f64(timeout.max(0.0)))));
+}
+
+/// Wait up to `timeout` for pending run uploads to finish.
+pub(crate) async fn drain(timeout: Duration) {
+    let handles: Vec<_> = PENDING
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .drain(..)
+        .collect();
+    let wait = async {
+        for handle in handles {
+            let _ = handle.await;
+        }
+    };
+    let _ = tokio::time::timeout(timeout, wait).await;
+}
+
+fn millis(record: &RunRecord) -> u64 {
+    record
+        .started_at
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+fn truncate(text: &str) -> String {
+    match text.char_indices().nth(MAX_TEXT_LEN) {
+        Some((end, _)) => text[..end].to_string(),
+        None => text.to_string(),
+    }
+}
+
+fn params(record: &RunRecord) -> Vec<(&'static str, String)> {
+    let mut params = vec![
+        ("provider", record.provider.to_string()),
+        ("model", record.model.clone()),
+        ("stream", record.stream.to_string()),
+        ("tools", record.tools.join(",")),
+    ];
+    if let Some(language) = &record.language {
+        params.push(("language", language.clone()));
+    }
+    params
+}
+
+fn metrics(record: &RunRecord) -> Vec<(&'static str, f64)> {
+    let mut metrics = vec![
+        ("latency_s", record.latency.as_secs_f64()),
+        ("model_calls", record.model_calls as f64),
+        ("tool_calls", record.tool_calls as f64),
+        ("retries", record.retries.retries as f64),
+        ("retry_backoff_s", record.retries.backoff_total_s()),
+        ("input_tokens", record.usage.input_tokens as f64),
+        ("output_tokens", record.usage.output_tokens as f64),
+        ("success", if record.error.is_none() { 1.0 } else { 0.0 }),
+    ];
+    if let Some(cost) = record.cost_usd() {
+        metrics.push(("cost_usd", cost));
+    }
+    if let Some(ttft) = record.time_to_first_token {
+        metrics.push(("time_to_first_token_s", ttft.as_secs_f64()));
+    }
+    if let Some(rate) = record.tokens_per_second {
+        metrics.push(("tokens_per_second", rate));
+    }
+    metrics
+}
+
+async fn send(request: RequestBuilder) -> Result<Value, RustedChainError> {
+    let response = request.send().await?;
+    let status = response.status();
+    let text = response.text().await.unwrap_or_default();
+    if !status.is_success() {
+        return Err(RustedChainError::from_response(status, None, &text));
+    }
+    Ok(serde_json::from_str(&text).unwrap_or(Value::Null))
+}
+
+impl Tracker {
+    async fn log(&self, record: &RunRecord) -> Result<Uploaded, RustedChainError> {
+        match self {
+            Tracker::Wandb { .. } => {
+                let mut config: serde_json::Map<String, Value> = params(record)
+                    .into_iter()
+                    .map(|(key, value)| (key.to_string(), json!({ "value": value })))
+                    .collect();
+                config.insert(
+                    "metadata".to_string(),
+                    json!({ "value": record.labels.metadata }),
+                );
+                let mut summary: serde_json::Map<String, Value> = metrics(record)
+                    .into_iter()
+                    .map(|(key, value)| (key.to_string(), json!(value)))
+                    .collect();
+                summary.insert("prompt".to_string(), json!(record.query));
+                summary.insert("output".to_string(), json!(record.output));
+                summary.insert("error".to_string(), json!(record.error));
+                let summary = Value::Object(summary);
+
+                self.wandb_upsert(json!({
+                    "name": record.id,
+                    "displayName": format!("{}/{}", record.provider, record.model),
+                    "jobType": "agent-run",
+                    "config": Value::Object(config).to_string(),
+                    "summaryMetrics": summary.to_string(),
+                    "tags": record.labels.tags,
+                }))
+                .await?;
+                Ok(Uploaded::Wandb {
+                    name: record.id.clone(),
+                    summary,
+                })
+            }
+            Tracker::Mlflow { experiment_id, .. } => {
+                let start = millis(record);
+                let created = send(self.mlflow_request(
+                    "runs/create",
+                    json!({
+                        "experiment_id": experiment_id,
+                        "run_name": format!("{}-{}", record.model, record.id),
+                        "start_time": start,
+                    }),
+                ))
+                .await?;
+                let run_id = created["run"]["info"]["run_id"]
+                    .as_str()
+                    .ok_or_else(|| {
+                        RustedChainError::ParseError("MLflow returned no run_id".to_string())
+                    })?
+                    .to_string();
+
+                let mut tags = vec![json!({ "key": "prompt", "value": truncate(&record.query) })];
+                if let Some(output) = &record.output {
+                    tags.push(json!({ "key": "output", "value": truncate(output) }));
+                }
+                if let Some(error) = &record.error {
+                    tags.push(json!({ "key": "error", "value": truncate(error) }));
+                }
+                if !record.labels.tags.is_empty() {
+                    tags.push(json!({ "key": "tags", "value": record.labels.tags.join(",") }));
+                }
+                for (key, value) in &record.labels.metadata {
+                    let value = match value {
+                        Value::String(text) => text.clone(),
+                        other => other.to_string(),
+                    };
+                    tags.push(
+                        json!({ "key": format!("metadata.{}", key), "value": truncate(&value) }),
+                    );
+                }
+                send(self.mlflow_request(
+                    "runs/log-batch",
+                    json!({
+                        "run_id": run_id,
+                        "params": params(record)
+                            .into_iter()
+                            .map(|(key, value)| json!({ "key": key, "value": value }))
+                            .collect::<Vec<_>>(),
+                        "metrics": metrics(record)
+                            .into_iter()
+                            .map(|(key, value)| json!({
+                                "key": key,
+                                "value": value,
+                                "timestamp": start,
+                                "step": 0,
+                            }))
+                            .collect::<Vec<_>>(),
+                        "tags": tags,
+                    }),
+                ))
+                .await?;
+
+                send(self.mlflow_request(
+                    "runs/update",
+                    json!({
+                        "run_id": run_id,
+                        "status": if record.error.is_none() { "FINISHED" } else { "FAILED" },
+                        "end_time": start + record.latency.as_millis() as u64,
+                    }),
+                ))
+                .await?;
+                Ok(Uploaded::Mlflow { run_id })
+            }
+        }
+    }
+
+    /// Add `feedback_score` (and `feedback_comment`) to an uploaded run: to the W&B
+    /// summary, or as an MLflow metric and tag.
+    async fn feedback(
+        &self,
+        uploaded: &Uploaded,
+        score: f64,
+        comment: Option<&str>,
+        recorded_at: i64,
+    ) -> Result<(), RustedChainError> {
+        match uploaded {
+            Uploaded::Wandb { name, summary } => {
+                let mut summary = summary.clone();
+                summary["feedback_score"] = json!(score);
+                summary["feedback_comment"] = json!(comment);
+                self.wandb_upsert(json!({
+                    "name": name,
+                    "summaryMetrics": summary.to_string(),
+                }))
+                .await
+            }
+            Uploaded::Mlflow { run_id } => {
+                let tags: Vec<Value> = comment
+                    .map(|comment| json!({ "key": "feedback_comment", "value": truncate(comment) }))
+                    .into_iter()
+                    .collect();
+                send(self.mlflow_request(
+                    "runs/log-batch",
+                    json!({
+                        "run_id": run_id,
+                        "metrics": [{
+                            "key": "feedback_score",
+                            "value": score,
+                            "timestamp": recorded_at,
+                            "step": 0,
+                        }],
+                        "tags": tags,
+                    }),
+                ))
+                .await
+                .map(|_| ())
+            }
+        }
+    }
+
+    /// Create or update a W&B run with `variables` for the upsert mutation.
+    async fn wandb_upsert(&self, mut variables: Value) -> Result<(), RustedChainError> {
+        let Tracker::Wandb {
+            base_url,
+            api_key,
+            project,
+            entity,
+        } = self
+        else {
+            return Ok(());
+        };
+        variables["project"] = json!(project);
+        variables["entity"] = json!(entity);
+        let body = json!({ "query": WANDB_UPSERT_RUN, "variables": variables });
+        let response = send(
+            HTTP.post(format!("{}/graphql", base_url))
+                .basic_auth("api", Some(api_key))
+                .json(&body),
+        )
+        .await?;
+        // GraphQL reports failures in the body of a 200 response.
+        if let Some(errors) = response.get("errors") {
+            return Err(RustedChainError::Api {
+                status: 200,
+                message: errors.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// A POST to the MLflow REST API at `path`, authenticated as configured.
+    fn mlflow_request(&self, path: &str, body: Value) -> RequestBuilder {
+        let Tracker::Mlflow {
+            base_url,
+            token,
+            basic_auth,
+            ..
+        } = self
+        else {
+            unreachable!("MLflow requests are only built for MLflow trackers");
+        };
+        let mut builder = HTTP
+            .post(format!("{}/api/2.0/mlflow/{}", base_url, path))
+            .json(&body);
+        if let Some(token) = token {
+            builder = builder.bearer_auth(token);
+        } else if let Some((user, password)) = basic_auth {
+            builder = builder.basic_auth(user, Some(password));
+        }
+        builder
+    }
+}
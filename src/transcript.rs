@@ -0,0 +1,104 @@
+//! A conversation rendered as a readable document, for sharing and support tickets.
+
+use crate::session::Entry;
+use serde_json::Value;
+
+/// `entries` as Markdown: a heading per message, with tool arguments and results in
+/// fenced blocks.
+pub(crate) fn to_markdown(entries: &[Entry]) -> String {
+    let mut out = String::new();
+    for entry in entries {
+        match entry {
+            Entry::User(text) => section(&mut out, "User", text),
+            Entry::Assistant(text) => section(&mut out, "Assistant", text),
+            Entry::ToolCall(call) => {
+                out.push_str(&format!("### Tool call: `{}`\n\n", call.name));
+                fenced(&mut out, "json", &pretty(&call.args));
+            }
+            Entry::ToolResult { call, result } => {
+                out.push_str(&format!("### Tool result: `{}`\n\n", call.name));
+                match result {
+                    Value::String(text) => fenced(&mut out, "", text),
+                    other => fenced(&mut out, "json", &pretty(other)),
+                }
+            }
+        }
+    }
+    out.truncate(out.trim_end().len());
+    out.push('\n');
+    out
+}
+
+/// `entries` as a self-contained HTML page. Text keeps its line breaks and is not
+/// interpreted as Markdown.
+pub(crate) fn to_html(entries: &[Entry]) -> String {
+    let mut body = String::new();
+    for entry in entries {
+        let (class, title, content) = match entry {
+            Entry::User(text) => ("user", "User".to_string(), text.clone()),
+            Entry::Assistant(text) => ("assistant", "Assistant".to_string(), text.clone()),
+            Entry::ToolCall(call) => (
+                "tool-call",
+                format!("Tool call: <code>{}</code>", escape(&call.name)),
+                pretty(&call.args),
+            ),
+            Entry::ToolResult { call, result } => (
+                "tool-result",
+                format!("Tool result: <code>{}</code>", escape(&call.name)),
+                match result {
+                    Value::String(text) => text.clone(),
+                    other => pretty(other),
+                },
+            ),
+        };
+        body.push_str(&format!(
+            "<section class=\"{}\">\n<h3>{}</h3>\n<pre>{}</pre>\n</section>\n",
+            class,
+            title,
+            escape(&content)
+        ));
+    }
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Transcript</title>\n\
+         <style>\n{}</style>\n</head>\n<body>\n{}</body>\n</html>\n",
+        STYLE, body
+    )
+}
+
+const STYLE: &str = "body { font-family: sans-serif; max-width: 48em; margin: 2em auto; }
+section { border-left: 4px solid #ccc; margin: 1em 0; padding: 0 1em; }
+section.user { border-color: #3b82f6; }
+section.assistant { border-color: #10b981; }
+section.tool-call, section.tool-result { border-color: #f59e0b; }
+h3 { font-size: 0.9em; margin: 0.5em 0; }
+pre { white-space: pre-wrap; word-wrap: break-word; margin: 0.5em 0; }
+section.user pre, section.assistant pre { font-family: inherit; }
+";
+
+fn section(out: &mut String, title: &str, text: &str) {
+    out.push_str(&format!("### {}\n\n{}\n\n", title, text.trim_end()));
+}
+
+/// A fenced block longer than any run of backticks in `text`, so it cannot end early.
+fn fenced(out: &mut String, language: &str, text: &str) {
+    let longest = text.split(|c| c != '`').map(str::len).max().unwrap_or(0);
+    let fence = "`".repeat(longest.max(2) + 1);
+    out.push_str(&format!(
+        "{}{}\n{}\n{}\n\n",
+        fence,
+        language,
+        text.trim_end(),
+        fence
+    ));
+}
+
+fn pretty(value: &Value) -> String {
+    serde_json::to_string_pretty(value).unwrap_or_else(|_| value.to_string())
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
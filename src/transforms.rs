@@ -0,0 +1,291 @@
+//! Output transforms: clean-up steps applied to a model's text answer, such as removing
+//! the Markdown fences around a JSON answer. Steps compose with `|` into one transform
+//! that can be attached to a model (`output_transform=`) or a workflow node.
+//!
+//! Also the Markdown code-block parsing behind them, `AgentResponse.code_blocks()` and
+//! the `code_only` option.
+
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::PyList;
+
+#[derive(Clone, PartialEq, Eq)]
+enum Step {
+    StripFences,
+    NormalizeWhitespace,
+    /// The first fenced code block, in `language` if given.
+    FirstCodeBlock(Option<String>),
+}
+
+impl Step {
+    fn parse(name: &str) -> PyResult<Self> {
+        match name {
+            "strip_fences" => Ok(Self::StripFences),
+            "normalize_whitespace" => Ok(Self::NormalizeWhitespace),
+            "first_code_block" => Ok(Self::FirstCodeBlock(None)),
+            other => Err(PyValueError::new_err(format!(
+                "unknown output transform '{}'; expected 'strip_fences', \
+                 'normalize_whitespace' or 'first_code_block'",
+                other
+            ))),
+        }
+    }
+
+    fn apply(&self, text: &str) -> String {
+        match self {
+            Self::StripFences => strip_fences(text),
+            Self::NormalizeWhitespace => normalize_whitespace(text),
+            Self::FirstCodeBlock(language) => code_blocks(text)
+                .into_iter()
+                .find(|block| language.is_none() || block.language == *language)
+                .map_or_else(|| text.to_string(), |block| block.code),
+        }
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Self::StripFences => "strip_fences()".to_string(),
+            Self::NormalizeWhitespace => "normalize_whitespace()".to_string(),
+            Self::FirstCodeBlock(None) => "first_code_block()".to_string(),
+            Self::FirstCodeBlock(Some(language)) => format!("first_code_block('{}')", language),
+        }
+    }
+}
+
+/// A sequence of text clean-up steps, applied in order.
+#[pyclass(frozen)]
+#[derive(Clone, Default)]
+pub struct OutputTransform {
+    steps: Vec<Step>,
+}
+
+impl OutputTransform {
+    /// An `output_transform` value: an `OutputTransform`, a step name, or a list of
+    /// either, composed in order.
+    pub(crate) fn from_py(value: &Bound<'_, PyAny>) -> PyResult<Self> {
+        if let Ok(transform) = value.cast::<OutputTransform>() {
+            return Ok(transform.get().clone());
+        }
+        if let Ok(name) = value.extract::<String>() {
+            return Ok(OutputTransform {
+                steps: vec![Step::parse(&name)?],
+            });
+        }
+        if let Ok(list) = value.cast::<PyList>() {
+            let mut steps = Vec::new();
+            for item in list.iter() {
+                steps.extend(Self::from_py(&item)?.steps);
+            }
+            return Ok(OutputTransform { steps });
+        }
+        Err(PyTypeError::new_err(
+            "output_transform must be an OutputTransform, a transform name or a list of them",
+        ))
+    }
+
+    pub(crate) fn apply(&self, text: &str) -> String {
+        self.steps
+            .iter()
+            .fold(text.to_string(), |text, step| step.apply(&text))
+    }
+}
+
+#[pymethods]
+impl OutputTransform {
+    /// Remove Markdown fence lines (```` ```json ````, `~~~`), keeping what they enclose.
+    #[staticmethod]
+    fn strip_fences() -> Self {
+        OutputTransform {
+            steps: vec![Step::StripFences],
+        }
+    }
+
+    /// Use `\n` line endings, drop trailing spaces, collapse runs of blank lines into
+    /// one, and drop blank lines at either end. Indentation is kept.
+    #[staticmethod]
+    fn normalize_whitespace() -> Self {
+        OutputTransform {
+            steps: vec![Step::NormalizeWhitespace],
+        }
+    }
+
+    /// Keep only the first fenced code block (in `language`, if given). Text without
+    /// one passes through unchanged.
+    #[staticmethod]
+    #[pyo3(signature = (language=None))]
+    fn first_code_block(language: Option<String>) -> Self {
+        OutputTransform {
+            steps: vec![Step::FirstCodeBlock(
+                language.map(|language| language.to_lowercase()),
+            )],
+        }
+    }
+
+    /// Apply this transform, then `other`.
+    fn __or__(&self, other: &Bound<'_, PyAny>) -> PyResult<Self> {
+        let mut steps = self.steps.clone();
+        steps.extend(Self::from_py(other)?.steps);
+        Ok(OutputTransform { steps })
+    }
+
+    fn __call__(&self, text: &str) -> String {
+        self.apply(text)
+    }
+
+    fn __repr__(&self) -> String {
+        let steps: Vec<String> = self.steps.iter().map(Step::name).collect();
+        format!("OutputTransform({})", steps.join(" | "))
+    }
+}
+
+/// A fenced code block: its language tag (lowercased) and contents.
+pub(crate) struct CodeBlock {
+    pub(crate) language: Option<String>,
+    pub(crate) code: String,
+}
+
+/// The fence a line opens: its character, length and info string.
+fn opening_fence(line: &str) -> Option<(char, usize, &str)> {
+    let line = line.trim_start();
+    let marker = line.chars().next().filter(|c| *c == '`' || *c == '~')?;
+    let length = line.chars().take_while(|c| *c == marker).count();
+    let info = line[length..].trim();
+    // Backtick fences cannot have backticks in their info string.
+    (length >= 3 && !(marker == '`' && info.contains('`'))).then_some((marker, length, info))
+}
+
+fn closes(line: &str, marker: char, length: usize) -> bool {
+    let line = line.trim();
+    line.chars().count() >= length && line.chars().all(|c| c == marker)
+}
+
+/// Every fenced code block in `text`, in order. A block left open runs to the end.
+pub(crate) fn code_blocks(text: &str) -> Vec<CodeBlock> {
+    let mut blocks = Vec::new();
+    let mut lines = text.lines();
+    while let Some(line) = lines.next() {
+        let Some((marker, length, info)) = opening_fence(line) else {
+            continue;
+        };
+        let language = info
+            .split_whitespace()
+            .next()
+            .map(|language| language.to_lowercase());
+        let code: Vec<&str> = lines
+            .by_ref()
+            .take_while(|line| !closes(line, marker, length))
+            .collect();
+        blocks.push(CodeBlock {
+            language,
+            code: code.join("\n"),
+        });
+    }
+    blocks
+}
+
+/// The lines of `text` without fence lines, each marked with whether it is inside a
+/// code block.
+fn fenced_lines(text: &str) -> Vec<(&str, bool)> {
+    let mut open: Option<(char, usize)> = None;
+    let mut lines = Vec::new();
+    for line in text.lines() {
+        match open {
+            Some((marker, length)) if closes(line, marker, length) => open = None,
+            Some(_) => lines.push((line, true)),
+            None => match opening_fence(line) {
+                Some((marker, length, _)) => open = Some((marker, length)),
+                None => lines.push((line, false)),
+            },
+        }
+    }
+    lines
+}
+
+pub(crate) fn strip_fences(text: &str) -> String {
+    let lines: Vec<&str> = fenced_lines(text)
+        .into_iter()
+        .map(|(line, _)| line)
+        .collect();
+    lines.join("\n").trim().to_string()
+}
+
+fn normalize_whitespace(text: &str) -> String {
+    let mut lines: Vec<&str> = Vec::new();
+    for line in text.lines().map(str::trim_end) {
+        if line.is_empty() && lines.last().is_some_and(|last| last.is_empty()) {
+            continue;
+        }
+        lines.push(line);
+    }
+    lines.join("\n").trim_matches('\n').to_string()
+}
+
+/// The `code_only` option: answers must be fenced code blocks (in `language`, if set)
+/// with no prose around them.
+#[derive(Clone)]
+pub(crate) struct CodeOnly {
+    language: Option<String>,
+}
+
+impl CodeOnly {
+    /// `True` for code in any language, a language name, or `False`/`None` for off.
+    pub(crate) fn from_py(value: &Bound<'_, PyAny>) -> PyResult<Option<Self>> {
+        if value.is_none() {
+            return Ok(None);
+        }
+        if let Ok(enabled) = value.extract::<bool>() {
+            return Ok(enabled.then_some(CodeOnly { language: None }));
+        }
+        match value.extract::<String>() {
+            Ok(language) => Ok(Some(CodeOnly {
+                language: Some(language.to_lowercase()),
+            })),
+            Err(_) => Err(PyTypeError::new_err(
+                "code_only must be a bool or a language name",
+            )),
+        }
+    }
+
+    fn block(&self) -> String {
+        match &self.language {
+            Some(language) => format!("a single fenced ```{} code block", language),
+            None => "a single fenced code block".to_string(),
+        }
+    }
+
+    /// The system-prompt line asking for code only.
+    pub(crate) fn instruction(&self) -> String {
+        format!(
+            "Answer with only {}. Do not write any explanation before or after it.",
+            self.block()
+        )
+    }
+
+    /// The follow-up sent when an answer has prose around its code.
+    pub(crate) fn reask(&self) -> String {
+        format!(
+            "Your answer must contain only {}, with no other text. Reply again with just \
+             the code.",
+            self.block()
+        )
+    }
+
+    /// Whether `text` is code only: every non-blank line is inside a code block, and
+    /// there is a block in the wanted language.
+    pub(crate) fn accepts(&self, text: &str) -> bool {
+        fenced_lines(text)
+            .iter()
+            .all(|(line, inside)| *inside || line.trim().is_empty())
+            && self.code(text).is_some()
+    }
+
+    /// The code of the blocks in the wanted language, without the prose around them.
+    pub(crate) fn code(&self, text: &str) -> Option<String> {
+        let blocks: Vec<String> = code_blocks(text)
+            .into_iter()
+            .filter(|block| self.language.is_none() || block.language == self.language)
+            .map(|block| block.code)
+            .collect();
+        (!blocks.is_empty()).then(|| blocks.join("\n\n"))
+    }
+}
@@ -0,0 +1,268 @@
+//! Usage webhooks: a usage and cost event for every provider request, delivered in
+//! batches to a URL or Python callback by a background task on the shared runtime.
+//!
+//! Registered with `add_usage_webhook`. Each webhook keeps its own queue; a batch is
+//! sent when it reaches `batch_size` events or `flush_interval` seconds after its first
+//! event, and retried with exponential backoff before it is dropped with a message on
+//! stderr. `flush_usage_webhooks` sends what is queued and is called at interpreter exit.
+
+use crate::agent::{ChatClient, Usage};
+use crate::pricing;
+use crate::run::RunRecord;
+use crate::RUNTIME;
+use chrono::{DateTime, SecondsFormat, Utc};
+use once_cell::sync::Lazy;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use reqwest::Client;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{mpsc, oneshot};
+use tokio::time::Instant;
+
+const INITIAL_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+enum Target {
+    Url {
+        url: String,
+        headers: HashMap<String, String>,
+    },
+    Callback(Arc<Py<PyAny>>),
+}
+
+enum Message {
+    Event(Value),
+    /// Send what is queued, then reply.
+    Flush(oneshot::Sender<()>),
+}
+
+static WEBHOOKS: Lazy<Mutex<Vec<mpsc::UnboundedSender<Message>>>> =
+    Lazy::new(|| Mutex::new(Vec::new()));
+static HTTP: Lazy<Client> = Lazy::new(Client::new);
+
+/// Send a usage event for every provider request to `target`: a URL, which receives
+/// each batch as a JSON array in a POST with `headers`, or a callable, which receives
+/// it as a list of dicts. Batches hold up to `batch_size` events and are sent at most
+/// `flush_interval` seconds after their first event; a failed delivery (an error
+/// status, a network error or an exception) is retried `max_retries` times.
+#[pyfunction]
+#[pyo3(signature = (target, batch_size=50, flush_interval=5.0, headers=None, max_retries=3))]
+pub fn add_usage_webhook(
+    target: &Bound<'_, PyAny>,
+    batch_size: usize,
+    flush_interval: f64,
+    headers: Option<HashMap<String, String>>,
+    max_retries: u32,
+) -> PyResult<()> {
+    if batch_size == 0 {
+        return Err(PyValueError::new_err("batch_size must be at least 1"));
+    }
+    if !(flush_interval > 0.0 && flush_interval.is_finite()) {
+        return Err(PyValueError::new_err(
+            "flush_interval must be a positive number of seconds",
+        ));
+    }
+    let target = if let Ok(url) = target.extract::<String>() {
+        if !url.starts_with("http://") && !url.starts_with("https://") {
+            return Err(PyValueError::new_err(format!(
+                "usage webhook must be an http(s) URL or a callable, got '{}'",
+                url
+            )));
+        }
+        Target::Url {
+            url,
+            headers: headers.unwrap_or_default(),
+        }
+    } else if target.is_callable() {
+        if headers.is_some() {
+            return Err(PyValueError::new_err("headers only apply to a webhook URL"));
+        }
+        Target::Callback(Arc::new(target.clone().unbind()))
+    } else {
+        return Err(PyValueError::new_err(
+            "usage webhook must be an http(s) URL or a callable",
+        ));
+    };
+    let (sender, receiver) = mpsc::unbounded_channel();
+    RUNTIME.spawn(deliver(
+        target,
+        receiver,
+        batch_size,
+        Duration::from_secs_f64(flush_interval),
+        max_retries,
+    ));
+    WEBHOOKS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(sender);
+    Ok(())
+}
+
+/// Remove every usage webhook, after sending the events already queued for it.
+#[pyfunction]
+pub fn clear_usage_webhooks() {
+    // Each delivery task sends its queue once its channel closes.
+    WEBHOOKS.lock().unwrap_or_else(|e| e.into_inner()).clear();
+}
+
+/// Send the usage events queued for every webhook now, waiting up to `timeout` seconds
+/// for the deliveries (including their retries) to finish.
+#[pyfunction]
+#[pyo3(signature = (timeout=10.0))]
+pub fn flush_usage_webhooks(py: Python, timeout: f64) {
+    py.detach(|| RUNTIME.block_on(drain(Duration::from_secs_f64(timeout.max(0.0)))));
+}
+
+/// Wait up to `timeout` for every webhook to send what is queued.
+pub(crate) async fn drain(timeout: Duration) {
+    let replies: Vec<_> = WEBHOOKS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .iter()
+        .filter_map(|webhook| {
+            let (reply, done) = oneshot::channel();
+            webhook.send(Message::Flush(reply)).ok().map(|()| done)
+        })
+        .collect();
+    let wait = async {
+        for done in replies {
+            let _ = done.await;
+        }
+    };
+    let _ = tokio::time::timeout(timeout, wait).await;
+}
+
+/// Queue the usage event for one provider request made during `run`.
+pub(crate) fn record<C: ChatClient>(
+    client: &C,
+    run: &RunRecord,
+    timestamp: DateTime<Utc>,
+    elapsed: Duration,
+    status: &str,
+    usage: Usage,
+) {
+    let mut webhooks = WEBHOOKS.lock().unwrap_or_else(|e| e.into_inner());
+    if webhooks.is_empty() {
+        return;
+    }
+    let event = json!({
+        "run_id": run.id,
+        "provider": client.provider(),
+        "model": client.model(),
+        "status": status,
+        "input_tokens": usage.input_tokens,
+        "output_tokens": usage.output_tokens,
+        "cost_usd": pricing::cost_usd(client.model(), usage),
+        "latency_s": elapsed.as_secs_f64(),
+        "timestamp": timestamp.to_rfc3339_opts(SecondsFormat::Millis, true),
+        "tags": run.labels.tags,
+        "metadata": run.labels.metadata,
+    });
+    webhooks.retain(|webhook| webhook.send(Message::Event(event.clone())).is_ok());
+}
+
+/// Batch the events arriving on `receiver` and send them to `target` until the webhook
+/// is removed.
+async fn deliver(
+    target: Target,
+    mut receiver: mpsc::UnboundedReceiver<Message>,
+    batch_size: usize,
+    flush_interval: Duration,
+    max_retries: u32,
+) {
+    let mut batch = Vec::new();
+    let mut due = Instant::now();
+    loop {
+        let message = if batch.is_empty() {
+            receiver.recv().await
+        } else {
+            match tokio::time::timeout_at(due, receiver.recv()).await {
+                Ok(message) => message,
+                Err(_) => {
+                    send(&target, std::mem::take(&mut batch), max_retries).await;
+                    continue;
+                }
+            }
+        };
+        match message {
+            Some(Message::Event(event)) => {
+                if batch.is_empty() {
+                    due = Instant::now() + flush_interval;
+                }
+                batch.push(event);
+                if batch.len() >= batch_size {
+                    send(&target, std::mem::take(&mut batch), max_retries).await;
+                }
+            }
+            Some(Message::Flush(reply)) => {
+                if !batch.is_empty() {
+                    send(&target, std::mem::take(&mut batch), max_retries).await;
+                }
+                let _ = reply.send(());
+            }
+            None => {
+                if !batch.is_empty() {
+                    send(&target, batch, max_retries).await;
+                }
+                return;
+            }
+        }
+    }
+}
+
+/// Deliver one batch, retrying failures with exponential backoff.
+async fn send(target: &Target, batch: Vec<Value>, max_retries: u32) {
+    let count = batch.len();
+    let batch = Arc::new(Value::Array(batch));
+    let mut backoff = INITIAL_BACKOFF;
+    for attempt in 0..=max_retries {
+        let error = match attempt_delivery(target, &batch).await {
+            Ok(()) => return,
+            Err(error) => error,
+        };
+        if attempt == max_retries {
+            log::warn!(
+                "dropped {} usage events after {} attempts: {}",
+                count,
+                attempt + 1,
+                error
+            );
+            return;
+        }
+        tokio::time::sleep(backoff).await;
+        backoff = (backoff * 2).min(MAX_BACKOFF);
+    }
+}
+
+async fn attempt_delivery(target: &Target, batch: &Arc<Value>) -> Result<(), String> {
+    match target {
+        Target::Url { url, headers } => {
+            let mut request = HTTP.post(url).json(batch.as_ref());
+            for (name, value) in headers {
+                request = request.header(name, value);
+            }
+            let response = request.send().await.map_err(|e| e.to_string())?;
+            let status = response.status();
+            match status.is_success() {
+                true => Ok(()),
+                false => Err(format!("{} returned HTTP {}", url, status)),
+            }
+        }
+        Target::Callback(callback) => {
+            // The callback takes the GIL, so it runs off the runtime's worker threads.
+            let (callback, batch) = (Arc::clone(callback), Arc::clone(batch));
+            tokio::task::spawn_blocking(move || {
+                Python::attach(|py| {
+                    let events = pythonize::pythonize(py, batch.as_ref())?;
+                    callback.bind(py).call1((events,)).map(drop)
+                })
+                .map_err(|e| e.to_string())
+            })
+            .await
+            .map_err(|e| e.to_string())?
+        }
+    }
+}
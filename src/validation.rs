@@ -0,0 +1,537 @@
+//! Post-hoc checks on an answer: conformance to a JSON schema, and whether the passages
+//! it quotes actually appear in the sources it was given.
+//!
+//! Schemas are checked against the common JSON Schema keywords (types, properties,
+//! required, items, enums, bounds, patterns and the `anyOf`/`oneOf`/`allOf`
+//! combinators); `$ref` and formats are not followed. Quotes are double-quoted passages
+//! and `>` block quotes of a few words or more. A quote is supported when, after
+//! normalizing case, whitespace and typographic quotes, it appears in a source, or
+//! failing that when its words or (with `embeddings`) its embedding come close enough to
+//! a sentence of one.
+
+use crate::rerank::document_text;
+use crate::transforms::strip_fences;
+use crate::vector_store::{cosine, Embedder};
+use crate::AgentResponse;
+use once_cell::sync::Lazy;
+use pyo3::prelude::*;
+use regex::Regex;
+use serde_json::Value;
+use std::collections::HashSet;
+
+/// Quoted passages shorter than this, in words, are taken for terms, not citations.
+const MIN_QUOTE_WORDS: usize = 3;
+
+/// Double-quoted passages, straight or typographic.
+static QUOTED: Lazy<Regex> = Lazy::new(|| Regex::new(r#""([^"\n]+)"|“([^”\n]+)”"#).unwrap());
+
+/// Sentence ends, for splitting sources into the passages quotes are compared with.
+static SENTENCE_END: Lazy<Regex> = Lazy::new(|| Regex::new(r"[.!?]+\s+|\n+").unwrap());
+
+/// How one quote in the answer was checked against the sources.
+#[pyclass(get_all, frozen)]
+#[derive(Clone)]
+pub struct QuoteCheck {
+    /// The quoted text, as it appears in the answer.
+    quote: String,
+    /// Whether a source backs the quote.
+    supported: bool,
+    /// Index in `sources` of the best match, if any source came close.
+    source: Option<usize>,
+    /// How close the best match is, from 0 to 1; 1 for a verbatim match.
+    score: f64,
+    /// `"exact"`, `"words"` or `"embedding"`: what the score measures.
+    method: &'static str,
+}
+
+#[pymethods]
+impl QuoteCheck {
+    fn __repr__(&self) -> String {
+        format!(
+            "QuoteCheck(quote={:?}, supported={}, source={}, score={:.2}, method='{}')",
+            self.quote,
+            if self.supported { "True" } else { "False" },
+            self.source.map_or("None".to_string(), |i| i.to_string()),
+            self.score,
+            self.method
+        )
+    }
+}
+
+/// The outcome of `validate_answer`.
+#[pyclass(get_all, frozen)]
+pub struct ValidationReport {
+    /// No schema errors and every quote supported.
+    valid: bool,
+    /// The answer parsed as JSON, when a schema or quote keys were given and it parsed.
+    parsed: Option<Py<PyAny>>,
+    /// Where the answer breaks the schema, as `"$.path: problem"`.
+    schema_errors: Vec<String>,
+    /// One check per quote found in the answer, in order.
+    quotes: Vec<QuoteCheck>,
+}
+
+#[pymethods]
+impl ValidationReport {
+    /// The quotes no source supports.
+    #[getter]
+    fn unsupported(&self) -> Vec<String> {
+        self.quotes
+            .iter()
+            .filter(|check| !check.supported)
+            .map(|check| check.quote.clone())
+            .collect()
+    }
+
+    fn __bool__(&self) -> bool {
+        self.valid
+    }
+
+    fn __repr__(&self) -> String {
+        format!(
+            "ValidationReport(valid={}, schema_errors={}, quotes={}, unsupported={})",
+            if self.valid { "True" } else { "False" },
+            self.schema_errors.len(),
+            self.quotes.len(),
+            self.quotes.iter().filter(|check| !check.supported).count()
+        )
+    }
+}
+
+/// Check `answer` (a string or `AgentResponse`) against `schema` (a JSON schema dict or
+/// a Pydantic model class) and check that each passage it quotes appears in `sources`
+/// (strings or `Document`s). In a JSON answer, the strings under `quote_keys` are quotes
+/// in full. Quotes that don't appear verbatim are matched by word overlap, or by cosine
+/// similarity with `embeddings` (an `Embeddings` instance or a callable), and count as
+/// supported at `threshold` or above.
+#[pyfunction]
+#[pyo3(signature = (answer, sources, schema=None, quote_keys=None, embeddings=None, threshold=0.85))]
+pub fn validate_answer(
+    py: Python,
+    answer: &Bound<'_, PyAny>,
+    sources: Vec<Bound<'_, PyAny>>,
+    schema: Option<&Bound<'_, PyAny>>,
+    quote_keys: Option<Vec<String>>,
+    embeddings: Option<&Bound<'_, PyAny>>,
+    threshold: f64,
+) -> PyResult<ValidationReport> {
+    let answer = answer_text(answer)?;
+    let sources = sources
+        .iter()
+        .map(document_text)
+        .collect::<PyResult<Vec<_>>>()?;
+    let embedder = embeddings.map(Embedder::from_py).transpose()?;
+
+    let mut schema_errors = Vec::new();
+    let mut parsed = None;
+    let json = (schema.is_some() || quote_keys.is_some())
+        .then(|| serde_json::from_str::<Value>(&strip_fences(&answer)));
+    let quotes = match json {
+        Some(Ok(value)) => {
+            if let Some(schema) = schema {
+                let schema: Value = if schema.hasattr("model_json_schema")? {
+                    pythonize::depythonize(&schema.call_method0("model_json_schema")?)?
+                } else {
+                    pythonize::depythonize(schema)?
+                };
+                check_schema(&value, &schema, "$", &mut schema_errors);
+            }
+            let mut quotes = Vec::new();
+            json_quotes(
+                &value,
+                quote_keys.as_deref().unwrap_or_default(),
+                &mut quotes,
+            );
+            parsed = Some(pythonize::pythonize(py, &value)?.unbind());
+            dedup(quotes)
+        }
+        Some(Err(e)) if schema.is_some() => {
+            schema_errors.push(format!("$: not valid JSON: {}", e));
+            find_quotes(&answer)
+        }
+        _ => find_quotes(&answer),
+    };
+
+    let quotes = check_quotes(py, quotes, &sources, embedder.as_ref(), threshold)?;
+    Ok(ValidationReport {
+        valid: schema_errors.is_empty() && quotes.iter().all(|check| check.supported),
+        parsed,
+        schema_errors,
+        quotes,
+    })
+}
+
+fn answer_text(answer: &Bound<'_, PyAny>) -> PyResult<String> {
+    match answer.cast::<AgentResponse>() {
+        Ok(response) => match &*response.borrow() {
+            AgentResponse::Text { text, .. } => Ok(text.clone()),
+            AgentResponse::ToolCall { .. } => Err(pyo3::exceptions::PyValueError::new_err(
+                "validate_answer needs a text answer, got a tool call",
+            )),
+        },
+        Err(_) => answer.extract(),
+    }
+}
+
+/// The quotes in the strings of a JSON answer: the whole string under one of
+/// `quote_keys`, otherwise the passages quoted within it.
+fn json_quotes(value: &Value, quote_keys: &[String], quotes: &mut Vec<String>) {
+    match value {
+        Value::String(text) => quotes.extend(find_quotes(text)),
+        Value::Array(items) => items
+            .iter()
+            .for_each(|item| json_quotes(item, quote_keys, quotes)),
+        Value::Object(map) => {
+            for (key, item) in map {
+                match item {
+                    _ if !quote_keys.contains(key) => json_quotes(item, quote_keys, quotes),
+                    Value::String(text) => quotes.push(text.clone()),
+                    Value::Array(items) => {
+                        quotes.extend(items.iter().filter_map(Value::as_str).map(String::from))
+                    }
+                    _ => json_quotes(item, quote_keys, quotes),
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// The double-quoted passages in `text`, then its block quotes, each once.
+fn find_quotes(text: &str) -> Vec<String> {
+    let mut found: Vec<String> = QUOTED
+        .captures_iter(text)
+        .filter_map(|captures| captures.get(1).or_else(|| captures.get(2)))
+        .map(|quote| quote.as_str().to_string())
+        .collect();
+    let mut block = Vec::new();
+    for line in text.lines().chain([""]) {
+        match line.trim_start().strip_prefix('>') {
+            Some(quoted) => block.push(quoted.trim()),
+            None if !block.is_empty() => found.push(std::mem::take(&mut block).join(" ")),
+            None => {}
+        }
+    }
+    dedup(
+        found
+            .into_iter()
+            .filter(|quote| quote.split_whitespace().count() >= MIN_QUOTE_WORDS)
+            .collect(),
+    )
+}
+
+/// `quotes` trimmed, without empty ones and repeats.
+fn dedup(quotes: Vec<String>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    quotes
+        .into_iter()
+        .map(|quote| quote.trim().to_string())
+        .filter(|quote| !quote.is_empty() && seen.insert(quote.clone()))
+        .collect()
+}
+
+/// Lowercased words with typographic quotes and dashes folded, for comparison.
+fn normalize(text: &str) -> String {
+    text.chars()
+        .map(|c| match c {
+            '‘' | '’' => '\'',
+            '“' | '”' => '"',
+            '–' | '—' => '-',
+            c => c,
+        })
+        .collect::<String>()
+        .to_lowercase()
+        .split_whitespace()
+        .map(|word| word.trim_matches(|c: char| !c.is_alphanumeric()))
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The share of `quote`'s words found in `passage`.
+fn word_overlap(quote: &str, passage: &str) -> f64 {
+    let passage: HashSet<&str> = passage.split(' ').collect();
+    let words: Vec<&str> = quote.split(' ').collect();
+    let found = words.iter().filter(|word| passage.contains(*word)).count();
+    found as f64 / words.len().max(1) as f64
+}
+
+fn check_quotes(
+    py: Python,
+    quotes: Vec<String>,
+    sources: &[String],
+    embedder: Option<&Embedder>,
+    threshold: f64,
+) -> PyResult<Vec<QuoteCheck>> {
+    let normalized: Vec<String> = sources.iter().map(|source| normalize(source)).collect();
+    // Sentences and adjacent pairs of sentences, tagged with their source.
+    let mut passages: Vec<(usize, String)> = Vec::new();
+    for (index, source) in sources.iter().enumerate() {
+        let sentences: Vec<&str> = SENTENCE_END
+            .split(source)
+            .map(str::trim)
+            .filter(|sentence| !sentence.is_empty())
+            .collect();
+        passages.extend(sentences.iter().map(|s| (index, s.to_string())));
+        passages.extend(
+            sentences
+                .windows(2)
+                .map(|pair| (index, format!("{} {}", pair[0], pair[1]))),
+        );
+    }
+
+    let mut checks: Vec<QuoteCheck> = quotes
+        .into_iter()
+        .map(|quote| {
+            let target = normalize(&quote);
+            if let Some(index) = normalized
+                .iter()
+                .position(|source| source.contains(&target))
+            {
+                return QuoteCheck {
+                    quote,
+                    supported: true,
+                    source: Some(index),
+                    score: 1.0,
+                    method: "exact",
+                };
+            }
+            let best = passages
+                .iter()
+                .map(|(index, passage)| (*index, word_overlap(&target, &normalize(passage))))
+                .max_by(|a, b| a.1.total_cmp(&b.1));
+            QuoteCheck {
+                quote,
+                supported: best.is_some_and(|(_, score)| score >= threshold),
+                source: best
+                    .filter(|(_, score)| *score > 0.0)
+                    .map(|(index, _)| index),
+                score: best.map_or(0.0, |(_, score)| score),
+                method: "words",
+            }
+        })
+        .collect();
+
+    let pending: Vec<usize> = (0..checks.len())
+        .filter(|&i| !checks[i].supported)
+        .collect();
+    if let Some(embedder) = embedder.filter(|_| !pending.is_empty() && !passages.is_empty()) {
+        let texts: Vec<String> = pending
+            .iter()
+            .map(|&i| checks[i].quote.clone())
+            .chain(passages.iter().map(|(_, passage)| passage.clone()))
+            .collect();
+        let vectors = embedder.embed(py, texts)?;
+        let (quote_vectors, passage_vectors) = vectors.split_at(pending.len());
+        for (&i, vector) in pending.iter().zip(quote_vectors) {
+            let (index, score) = passage_vectors
+                .iter()
+                .zip(&passages)
+                .map(|(passage, (index, _))| (*index, cosine(vector, passage) as f64))
+                .max_by(|a, b| a.1.total_cmp(&b.1))
+                .unwrap_or((0, 0.0));
+            checks[i] = QuoteCheck {
+                supported: score >= threshold,
+                source: Some(index),
+                score,
+                method: "embedding",
+                quote: std::mem::take(&mut checks[i].quote),
+            };
+        }
+    }
+    Ok(checks)
+}
+
+/// Append to `errors` each way `value` breaks `schema`, with `path` naming `value`.
+fn check_schema(value: &Value, schema: &Value, path: &str, errors: &mut Vec<String>) {
+    let Some(schema) = schema.as_object() else {
+        if schema == &Value::Bool(false) {
+            errors.push(format!("{}: not allowed", path));
+        }
+        return;
+    };
+
+    if let Some(expected) = schema.get("type") {
+        let types: Vec<&str> = match expected {
+            Value::String(name) => vec![name.as_str()],
+            Value::Array(names) => names.iter().filter_map(Value::as_str).collect(),
+            _ => Vec::new(),
+        };
+        if !types.is_empty() && !types.iter().any(|name| is_type(value, name)) {
+            errors.push(format!(
+                "{}: expected {}, got {}",
+                path,
+                types.join(" or "),
+                type_name(value)
+            ));
+            return;
+        }
+    }
+    if let Some(allowed) = schema.get("enum").and_then(Value::as_array) {
+        if !allowed.contains(value) {
+            errors.push(format!(
+                "{}: {} is not one of {}",
+                path,
+                value,
+                Value::from(allowed.clone())
+            ));
+        }
+    }
+    if let Some(constant) = schema.get("const") {
+        if constant != value {
+            errors.push(format!("{}: expected {}", path, constant));
+        }
+    }
+
+    for (keyword, key) in [("allOf", "all"), ("anyOf", "any"), ("oneOf", "one")] {
+        let Some(options) = schema.get(keyword).and_then(Value::as_array) else {
+            continue;
+        };
+        let results: Vec<Vec<String>> = options
+            .iter()
+            .map(|option| {
+                let mut found = Vec::new();
+                check_schema(value, option, path, &mut found);
+                found
+            })
+            .collect();
+        let passing = results.iter().filter(|found| found.is_empty()).count();
+        match key {
+            "all" => errors.extend(results.into_iter().flatten()),
+            "any" if passing == 0 => {
+                errors.push(format!("{}: matches none of the anyOf schemas", path))
+            }
+            "one" if passing != 1 => errors.push(format!(
+                "{}: matches {} of the oneOf schemas, expected exactly one",
+                path, passing
+            )),
+            _ => {}
+        }
+    }
+
+    match value {
+        Value::Object(map) => {
+            for name in schema
+                .get("required")
+                .and_then(Value::as_array)
+                .into_iter()
+                .flatten()
+                .filter_map(Value::as_str)
+            {
+                if !map.contains_key(name) {
+                    errors.push(format!("{}: missing required property '{}'", path, name));
+                }
+            }
+            let properties = schema.get("properties").and_then(Value::as_object);
+            for (name, item) in map {
+                let item_path = format!("{}.{}", path, name);
+                match properties.and_then(|properties| properties.get(name)) {
+                    Some(property) => check_schema(item, property, &item_path, errors),
+                    None => match schema.get("additionalProperties") {
+                        Some(Value::Bool(false)) => {
+                            errors.push(format!("{}: unexpected property '{}'", path, name))
+                        }
+                        Some(additional) => check_schema(item, additional, &item_path, errors),
+                        None => {}
+                    },
+                }
+            }
+        }
+        Value::Array(items) => {
+            if let Some(item_schema) = schema.get("items") {
+                for (i, item) in items.iter().enumerate() {
+                    check_schema(item, item_schema, &format!("{}[{}]", path, i), errors);
+                }
+            }
+            bound(
+                schema,
+                "minItems",
+                items.len() as f64,
+                path,
+                "items",
+                errors,
+            );
+            bound(
+                schema,
+                "maxItems",
+                items.len() as f64,
+                path,
+                "items",
+                errors,
+            );
+        }
+        Value::String(text) => {
+            let length = text.chars().count() as f64;
+            bound(schema, "minLength", length, path, "characters", errors);
+            bound(schema, "maxLength", length, path, "characters", errors);
+            if let Some(pattern) = schema.get("pattern").and_then(Value::as_str) {
+                match Regex::new(pattern) {
+                    Ok(regex) if !regex.is_match(text) => {
+                        errors.push(format!("{}: does not match pattern '{}'", path, pattern))
+                    }
+                    Ok(_) => {}
+                    Err(e) => {
+                        errors.push(format!("{}: invalid pattern '{}': {}", path, pattern, e))
+                    }
+                }
+            }
+        }
+        Value::Number(number) => {
+            let number = number.as_f64().unwrap_or_default();
+            for keyword in ["minimum", "maximum", "exclusiveMinimum", "exclusiveMaximum"] {
+                bound(schema, keyword, number, path, "", errors);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Check the numeric `keyword` of `schema` against `actual`.
+fn bound(
+    schema: &serde_json::Map<String, Value>,
+    keyword: &str,
+    actual: f64,
+    path: &str,
+    unit: &str,
+    errors: &mut Vec<String>,
+) {
+    let Some(limit) = schema.get(keyword).and_then(Value::as_f64) else {
+        return;
+    };
+    let (ok, relation) = match keyword {
+        "minimum" | "minItems" | "minLength" => (actual >= limit, "at least"),
+        "maximum" | "maxItems" | "maxLength" => (actual <= limit, "at most"),
+        "exclusiveMinimum" => (actual > limit, "more than"),
+        _ => (actual < limit, "less than"),
+    };
+    if !ok {
+        let unit = if unit.is_empty() {
+            String::new()
+        } else {
+            format!(" {}", unit)
+        };
+        errors.push(format!(
+            "{}: expected {} {}{}, got {}",
+            path, relation, limit, unit, actual
+        ));
+    }
+}
+
+fn is_type(value: &Value, name: &str) -> bool {
+    match name {
+        "integer" => value.as_f64().is_some_and(|n| n.fract() == 0.0),
+        "number" => value.is_number(),
+        other => type_name(value) == other,
+    }
+}
+
+fn type_name(value: &Value) -> &'static str {
+    match value {
+        Value::Null => "null",
+        Value::Bool(_) => "boolean",
+        Value::Number(_) => "number",
+        Value::String(_) => "string",
+        Value::Array(_) => "array",
+        Value::Object(_) => "object",
+    }
+}
@@ -0,0 +1,457 @@
+//! Vector stores for retrieval: in memory, Qdrant, and PostgreSQL with pgvector (behind
+//! the `pgvector` feature). Backends implement `VectorStore`; Python sees a single
+//! `VectorStore` class that embeds text and delegates storage to its backend.
+
+use crate::bm25::{self, Bm25Index};
+use crate::document::Document;
+use crate::embeddings::Embeddings;
+use crate::error::RustedChainError;
+#[cfg(feature = "pgvector")]
+use crate::pgvector::PgVector;
+use crate::qdrant::Qdrant;
+use crate::rerank::Reranker;
+use crate::RUNTIME;
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::{PyBool, PyDict};
+use serde_json::{Map, Value};
+use std::sync::RwLock;
+
+/// Candidates fetched per requested result for hybrid search and reranking.
+const FETCH_FACTOR: usize = 4;
+
+#[derive(Clone, Copy)]
+enum SearchMode {
+    Vector,
+    Keyword,
+    Hybrid,
+}
+
+impl SearchMode {
+    fn parse(mode: &str) -> PyResult<Self> {
+        match mode {
+            "vector" => Ok(SearchMode::Vector),
+            "keyword" => Ok(SearchMode::Keyword),
+            "hybrid" => Ok(SearchMode::Hybrid),
+            other => Err(PyValueError::new_err(format!(
+                "mode must be 'vector', 'keyword' or 'hybrid', got '{}'",
+                other
+            ))),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct Record {
+    pub(crate) id: String,
+    pub(crate) text: String,
+    pub(crate) metadata: Map<String, Value>,
+    pub(crate) vector: Vec<f32>,
+}
+
+/// Storage and nearest-neighbour search over embedded records. Scores are cosine
+/// similarities, higher is closer.
+pub(crate) trait VectorStore {
+    /// Insert records, replacing any with the same id.
+    async fn upsert(&self, records: Vec<Record>) -> Result<(), RustedChainError>;
+
+    /// The `k` records closest to `vector`, best first.
+    async fn search(
+        &self,
+        vector: &[f32],
+        k: usize,
+    ) -> Result<Vec<(Record, f32)>, RustedChainError>;
+
+    async fn delete(&self, ids: &[String]) -> Result<(), RustedChainError>;
+}
+
+pub(crate) fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let denominator = norm(a) * norm(b);
+    if denominator == 0.0 {
+        0.0
+    } else {
+        dot / denominator
+    }
+}
+
+/// Brute-force search over records held in process.
+#[derive(Default)]
+pub(crate) struct MemoryStore {
+    records: RwLock<Vec<Record>>,
+}
+
+impl VectorStore for MemoryStore {
+    async fn upsert(&self, records: Vec<Record>) -> Result<(), RustedChainError> {
+        let mut stored = self.records.write().expect("vector store lock poisoned");
+        if let (Some(first), Some(new)) = (stored.first(), records.first()) {
+            if first.vector.len() != new.vector.len() {
+                return Err(RustedChainError::Store(format!(
+                    "embedding has {} dimensions, the store holds {}",
+                    new.vector.len(),
+                    first.vector.len()
+                )));
+            }
+        }
+        for record in records {
+            match stored.iter_mut().find(|r| r.id == record.id) {
+                Some(existing) => *existing = record,
+                None => stored.push(record),
+            }
+        }
+        Ok(())
+    }
+
+    async fn search(
+        &self,
+        vector: &[f32],
+        k: usize,
+    ) -> Result<Vec<(Record, f32)>, RustedChainError> {
+        let stored = self.records.read().expect("vector store lock poisoned");
+        let mut scored: Vec<(&Record, f32)> = stored
+            .iter()
+            .map(|r| (r, cosine(vector, &r.vector)))
+            .collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+        Ok(scored
+            .into_iter()
+            .take(k)
+            .map(|(r, score)| (r.clone(), score))
+            .collect())
+    }
+
+    async fn delete(&self, ids: &[String]) -> Result<(), RustedChainError> {
+        self.records
+            .write()
+            .expect("vector store lock poisoned")
+            .retain(|r| !ids.contains(&r.id));
+        Ok(())
+    }
+}
+
+enum Backend {
+    Memory(MemoryStore),
+    Qdrant(Qdrant),
+    #[cfg(feature = "pgvector")]
+    PgVector(PgVector),
+}
+
+impl VectorStore for Backend {
+    async fn upsert(&self, records: Vec<Record>) -> Result<(), RustedChainError> {
+        match self {
+            Backend::Memory(store) => store.upsert(records).await,
+            Backend::Qdrant(store) => store.upsert(records).await,
+            #[cfg(feature = "pgvector")]
+            Backend::PgVector(store) => store.upsert(records).await,
+        }
+    }
+
+    async fn search(
+        &self,
+        vector: &[f32],
+        k: usize,
+    ) -> Result<Vec<(Record, f32)>, RustedChainError> {
+        match self {
+            Backend::Memory(store) => store.search(vector, k).await,
+            Backend::Qdrant(store) => store.search(vector, k).await,
+            #[cfg(feature = "pgvector")]
+            Backend::PgVector(store) => store.search(vector, k).await,
+        }
+    }
+
+    async fn delete(&self, ids: &[String]) -> Result<(), RustedChainError> {
+        match self {
+            Backend::Memory(store) => store.delete(ids).await,
+            Backend::Qdrant(store) => store.delete(ids).await,
+            #[cfg(feature = "pgvector")]
+            Backend::PgVector(store) => store.delete(ids).await,
+        }
+    }
+}
+
+/// Where vectors come from: an `Embeddings` model, or any Python callable mapping a
+/// list of strings to a list of vectors.
+pub(crate) enum Embedder {
+    Model(Py<Embeddings>),
+    Callable(Py<PyAny>),
+}
+
+impl Embedder {
+    pub(crate) fn from_py(embeddings: &Bound<'_, PyAny>) -> PyResult<Self> {
+        if let Ok(model) = embeddings.cast::<Embeddings>() {
+            Ok(Embedder::Model(model.clone().unbind()))
+        } else if embeddings.is_callable() {
+            Ok(Embedder::Callable(embeddings.clone().unbind()))
+        } else {
+            Err(PyValueError::new_err(
+                "embeddings must be an Embeddings instance or a callable",
+            ))
+        }
+    }
+
+    pub(crate) fn embed(&self, py: Python, texts: Vec<String>) -> PyResult<Vec<Vec<f32>>> {
+        let vectors: Vec<Vec<f32>> = match self {
+            Embedder::Model(model) => {
+                let model = model.borrow(py);
+                let model: &Embeddings = &model;
+                py.detach(|| RUNTIME.block_on(model.embed_all(&texts)))?
+            }
+            Embedder::Callable(f) => f.bind(py).call1((texts.clone(),))?.extract()?,
+        };
+        if vectors.len() != texts.len() {
+            return Err(PyValueError::new_err(format!(
+                "embeddings returned {} vectors for {} texts",
+                vectors.len(),
+                texts.len()
+            )));
+        }
+        Ok(vectors)
+    }
+}
+
+/// Embeds and stores documents for similarity search.
+#[pyclass(name = "VectorStore")]
+pub struct PyVectorStore {
+    backend: Backend,
+    embedder: Embedder,
+    /// BM25 index of the documents added through this store, for keyword and hybrid
+    /// search.
+    keywords: RwLock<Bm25Index>,
+}
+
+#[pymethods]
+impl PyVectorStore {
+    /// An in-memory store.
+    #[new]
+    fn new(embeddings: &Bound<'_, PyAny>) -> PyResult<Self> {
+        Ok(PyVectorStore {
+            backend: Backend::Memory(MemoryStore::default()),
+            embedder: Embedder::from_py(embeddings)?,
+            keywords: RwLock::default(),
+        })
+    }
+
+    /// A store backed by a Qdrant collection, created on first insert.
+    #[staticmethod]
+    #[pyo3(signature = (embeddings, url="http://localhost:6333", collection="rusted_chain", api_key=None))]
+    fn qdrant(
+        embeddings: &Bound<'_, PyAny>,
+        url: &str,
+        collection: &str,
+        api_key: Option<String>,
+    ) -> PyResult<Self> {
+        Ok(PyVectorStore {
+            backend: Backend::Qdrant(Qdrant::new(url, collection, api_key)),
+            embedder: Embedder::from_py(embeddings)?,
+            keywords: RwLock::default(),
+        })
+    }
+
+    /// A store backed by a PostgreSQL table using the pgvector extension, created on
+    /// first insert. Requires the `pgvector` feature.
+    #[staticmethod]
+    #[pyo3(signature = (embeddings, url, table="rusted_chain_documents"))]
+    #[allow(unused_variables)]
+    fn pgvector(embeddings: &Bound<'_, PyAny>, url: &str, table: &str) -> PyResult<Self> {
+        #[cfg(feature = "pgvector")]
+        {
+            Ok(PyVectorStore {
+                backend: Backend::PgVector(PgVector::new(url, table)?),
+                embedder: Embedder::from_py(embeddings)?,
+                keywords: RwLock::default(),
+            })
+        }
+        #[cfg(not(feature = "pgvector"))]
+        Err(pyo3::exceptions::PyImportError::new_err(
+            "rusted_chain was built without pgvector support; rebuild with `maturin build --features pgvector`",
+        ))
+    }
+
+    /// Embed and store texts, returning their ids. Ids are generated when not given.
+    #[pyo3(signature = (texts, metadatas=None, ids=None))]
+    fn add_texts(
+        &self,
+        py: Python,
+        texts: Vec<String>,
+        metadatas: Option<Vec<Bound<'_, PyDict>>>,
+        ids: Option<Vec<String>>,
+    ) -> PyResult<Vec<String>> {
+        let metadatas = match metadatas {
+            Some(metadatas) if metadatas.len() != texts.len() => {
+                return Err(PyValueError::new_err(
+                    "metadatas must have one entry per text",
+                ))
+            }
+            Some(metadatas) => metadatas
+                .iter()
+                .map(|m| pythonize::depythonize(m).map_err(PyErr::from))
+                .collect::<PyResult<Vec<Map<String, Value>>>>()?,
+            None => vec![Map::new(); texts.len()],
+        };
+        let ids = match ids {
+            Some(ids) if ids.len() != texts.len() => {
+                return Err(PyValueError::new_err("ids must have one entry per text"))
+            }
+            Some(ids) => ids,
+            None => texts.iter().map(|_| new_id()).collect(),
+        };
+        self.store(py, texts, metadatas, ids)
+    }
+
+    /// Embed and store documents, returning their ids. Documents without an id are
+    /// given one.
+    fn add_documents(&self, py: Python, documents: Vec<Py<Document>>) -> PyResult<Vec<String>> {
+        let mut texts = Vec::with_capacity(documents.len());
+        let mut metadatas = Vec::with_capacity(documents.len());
+        let mut ids = Vec::with_capacity(documents.len());
+        for doc in &documents {
+            let mut doc = doc.borrow_mut(py);
+            texts.push(doc.text.clone());
+            metadatas.push(pythonize::depythonize(doc.metadata.bind(py))?);
+            ids.push(doc.id.get_or_insert_with(new_id).clone());
+        }
+        self.store(py, texts, metadatas, ids)
+    }
+
+    /// The `k` documents most relevant to `query`. `mode` is `"vector"` (embedding
+    /// similarity), `"keyword"` (BM25) or `"hybrid"` (both, merged by reciprocal rank
+    /// fusion). With `rerank` (`True` for the local reranker, or a `Reranker`), the
+    /// candidates are reordered before the best `k` are returned. Hybrid search and
+    /// reranking consider `fetch_k` candidates (default `4 * k`).
+    #[pyo3(signature = (query, k=4, rerank=None, fetch_k=None, mode="vector"))]
+    fn similarity_search(
+        &self,
+        py: Python,
+        query: &str,
+        k: usize,
+        rerank: Option<&Bound<'_, PyAny>>,
+        fetch_k: Option<usize>,
+        mode: &str,
+    ) -> PyResult<Vec<Py<Document>>> {
+        self.similarity_search_with_score(py, query, k, rerank, fetch_k, mode)
+            .map(|hits| hits.into_iter().map(|(doc, _)| doc).collect())
+    }
+
+    /// Like `similarity_search`, paired with each document's score: cosine similarity,
+    /// BM25 score, fused rank score, or the reranker's relevance score when reranking.
+    #[pyo3(signature = (query, k=4, rerank=None, fetch_k=None, mode="vector"))]
+    fn similarity_search_with_score(
+        &self,
+        py: Python,
+        query: &str,
+        k: usize,
+        rerank: Option<&Bound<'_, PyAny>>,
+        fetch_k: Option<usize>,
+        mode: &str,
+    ) -> PyResult<Vec<(Py<Document>, f32)>> {
+        let mode = SearchMode::parse(mode)?;
+        let reranker = match rerank {
+            None => None,
+            Some(flag) if flag.is_instance_of::<PyBool>() => flag
+                .extract::<bool>()?
+                .then(|| Py::new(py, Reranker::local()))
+                .transpose()?,
+            Some(reranker) => Some(reranker.cast::<Reranker>()?.clone().unbind()),
+        };
+        let vector = match mode {
+            SearchMode::Keyword => Vec::new(),
+            _ => self.embedder.embed(py, vec![query.to_string()])?.remove(0),
+        };
+        let fetch_k = match (&reranker, mode) {
+            (None, SearchMode::Vector | SearchMode::Keyword) => k,
+            _ => fetch_k.unwrap_or(k * FETCH_FACTOR).max(k),
+        };
+        let reranker = reranker.as_ref().map(|r| r.borrow(py));
+        let reranker: Option<&Reranker> = reranker.as_deref();
+
+        let hits = py.detach(|| {
+            RUNTIME.block_on(async {
+                let candidates = match mode {
+                    SearchMode::Vector => self.backend.search(&vector, fetch_k).await?,
+                    SearchMode::Keyword => self.keyword_search(query, fetch_k),
+                    SearchMode::Hybrid => {
+                        let semantic = self.backend.search(&vector, fetch_k).await?;
+                        let lexical = self.keyword_search(query, fetch_k);
+                        bm25::reciprocal_rank_fusion(vec![semantic, lexical], fetch_k)
+                    }
+                };
+                let Some(reranker) = reranker else {
+                    return Ok::<_, RustedChainError>(
+                        candidates.into_iter().take(k).collect::<Vec<_>>(),
+                    );
+                };
+                let texts: Vec<String> = candidates.iter().map(|(r, _)| r.text.clone()).collect();
+                let ranked = reranker.rank(query, &texts, Some(k)).await?;
+                Ok(ranked
+                    .into_iter()
+                    .map(|(i, score)| (candidates[i].0.clone(), score as f32))
+                    .collect())
+            })
+        })?;
+
+        hits.into_iter()
+            .map(|(record, score)| {
+                let metadata = pythonize::pythonize(py, &record.metadata)?.cast_into::<PyDict>()?;
+                Ok((
+                    Document::create(py, record.text, metadata, Some(record.id))?,
+                    score,
+                ))
+            })
+            .collect()
+    }
+
+    /// Remove documents by id.
+    fn delete(&self, py: Python, ids: Vec<String>) -> PyResult<()> {
+        py.detach(|| RUNTIME.block_on(self.backend.delete(&ids)))?;
+        let mut keywords = self.keywords.write().expect("keyword index lock poisoned");
+        for id in &ids {
+            keywords.remove(id);
+        }
+        Ok(())
+    }
+}
+
+impl PyVectorStore {
+    fn store(
+        &self,
+        py: Python,
+        texts: Vec<String>,
+        metadatas: Vec<Map<String, Value>>,
+        ids: Vec<String>,
+    ) -> PyResult<Vec<String>> {
+        if texts.is_empty() {
+            return Ok(ids);
+        }
+        let vectors = self.embedder.embed(py, texts.clone())?;
+        let records = texts
+            .into_iter()
+            .zip(metadatas)
+            .zip(vectors)
+            .zip(&ids)
+            .map(|(((text, metadata), vector), id)| Record {
+                id: id.clone(),
+                text,
+                metadata,
+                vector,
+            })
+            .collect::<Vec<_>>();
+        py.detach(|| RUNTIME.block_on(self.backend.upsert(records.clone())))?;
+        let mut keywords = self.keywords.write().expect("keyword index lock poisoned");
+        for record in records {
+            keywords.insert(record);
+        }
+        Ok(ids)
+    }
+
+    fn keyword_search(&self, query: &str, k: usize) -> Vec<(Record, f32)> {
+        self.keywords
+            .read()
+            .expect("keyword index lock poisoned")
+            .search(query, k)
+    }
+}
+
+fn new_id() -> String {
+    uuid::Uuid::new_v4().to_string()
+}
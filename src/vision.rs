@@ -0,0 +1,168 @@
+//! Multimodal requests: prompts with images or video attached, optionally answered in
+//! the shape of a JSON schema.
+
+use crate::agent::{ChatClient, Usage};
+use crate::client::{with_client, AnyClient};
+use crate::error::RustedChainError;
+use crate::media::{Attachment, Image};
+use crate::options::AgentOptions;
+use crate::run::{Labels, RunRecord};
+use crate::{language, metrics};
+use crate::{AgentResponse, ResponseMetadata, RUNTIME};
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList, PyTuple};
+use serde_json::{json, Value};
+use std::future::Future;
+use std::time::Instant;
+
+const DEFAULT_PROMPT: &str = "Analyze the image and report what it shows.";
+
+/// OpenAI and Claude only accept object schemas; wrap anything else in a `result` field.
+fn object_schema(schema: Value) -> (Value, bool) {
+    if schema.get("type") == Some(&json!("object")) {
+        return (schema, false);
+    }
+    let wrapped = json!({
+        "type": "object",
+        "properties": { "result": schema },
+        "required": ["result"],
+    });
+    (wrapped, true)
+}
+
+/// One item or a list of them.
+fn each<T>(
+    value: &Bound<'_, PyAny>,
+    mut load: impl FnMut(&Bound<'_, PyAny>) -> PyResult<T>,
+) -> PyResult<Vec<T>> {
+    if value.is_instance_of::<PyList>() || value.is_instance_of::<PyTuple>() {
+        value.try_iter()?.map(|item| load(&item?)).collect()
+    } else {
+        Ok(vec![load(value)?])
+    }
+}
+
+/// Send one multimodal request, recorded as a run and in the metrics registry.
+async fn run_multimodal<C: ChatClient>(
+    client: &C,
+    options: &AgentOptions,
+    prompt: &str,
+    files: &[Attachment],
+    schema: Option<&Value>,
+    labels: Labels,
+) -> Result<String, RustedChainError> {
+    let request = async {
+        let (text, usage) = client.multimodal(prompt, files, schema).await?;
+        Ok((text, (), usage))
+    };
+    let (text, ()) = recorded(client, options, prompt, labels, request).await?;
+    Ok(text)
+}
+
+/// Make one tool-free request, recorded as a run and in the metrics registry.
+/// `request` yields the answer, anything else the caller wants back, and the usage.
+pub(crate) async fn recorded<C: ChatClient, T>(
+    client: &C,
+    options: &AgentOptions,
+    prompt: &str,
+    labels: Labels,
+    request: impl Future<Output = Result<(String, T, Usage), RustedChainError>>,
+) -> Result<(String, T), RustedChainError> {
+    let mut run = RunRecord::start(client, options, prompt, Vec::new(), false, labels);
+    let started = Instant::now();
+    let result = request.await;
+    let (status, usage) = match &result {
+        Ok((_, _, usage)) => ("ok", *usage),
+        Err(err) => (err.kind(), Usage::default()),
+    };
+    metrics::record_request(
+        client.provider(),
+        client.model(),
+        status,
+        started.elapsed(),
+        usage,
+    );
+    if result.is_ok() {
+        run.record_call(usage);
+    }
+    run.finish(match &result {
+        Ok((text, _, _)) => Ok(text.clone()),
+        Err(err) => Err(err.to_string()),
+    });
+    result.map(|(text, extra, _)| (text, extra))
+}
+
+/// `invoke_with_files` for every model class: a single turn with images or video
+/// attached. Tools are not offered for these requests.
+pub(crate) fn invoke_with_files<C: ChatClient + Sync>(
+    py: Python,
+    client: &C,
+    options: &AgentOptions,
+    query: &str,
+    files: &Bound<'_, PyAny>,
+    tags: Option<Vec<String>>,
+    metadata: Option<Py<PyDict>>,
+) -> PyResult<AgentResponse> {
+    let files = each(files, |file| Attachment::from_py(py, file))?;
+    let labels = Labels::from_py(py, tags, metadata.as_ref())?;
+    let text = py.detach(|| {
+        RUNTIME.block_on(run_multimodal(client, options, query, &files, None, labels))
+    })?;
+    Ok(AgentResponse::Text {
+        text,
+        metadata: ResponseMetadata {
+            detected_language: language::detect_language(query).map(str::to_string),
+            ..Default::default()
+        },
+    })
+}
+
+/// Ask `model` about one image (or a list of images) and get the answer back in the
+/// shape of `schema`: a JSON schema dict, returned as plain data, or a Pydantic model
+/// class, returned as a validated instance. Images are file paths, bytes, `http(s)://`
+/// URLs or `data:` URLs; each provider receives them in its own format.
+#[pyfunction]
+#[pyo3(signature = (image, schema, model, prompt=None))]
+pub fn analyze_image(
+    py: Python,
+    image: &Bound<'_, PyAny>,
+    schema: &Bound<'_, PyAny>,
+    model: &Bound<'_, PyAny>,
+    prompt: Option<String>,
+) -> PyResult<Py<PyAny>> {
+    let images = each(image, |image| {
+        Image::from_py(py, image).map(Attachment::Image)
+    })?;
+    let pydantic = schema.hasattr("model_json_schema")?;
+    let json_schema: Value = if pydantic {
+        pythonize::depythonize(&schema.call_method0("model_json_schema")?)?
+    } else {
+        pythonize::depythonize(schema)?
+    };
+    let (json_schema, wrapped) = object_schema(json_schema);
+    let prompt = prompt.unwrap_or_else(|| DEFAULT_PROMPT.to_string());
+    let (client, options) = AnyClient::from_model(py, model)?;
+
+    let text = py.detach(|| {
+        with_client!(&client, client => RUNTIME.block_on(run_multimodal(
+            client,
+            &options,
+            &prompt,
+            &images,
+            Some(&json_schema),
+            Labels::default(),
+        )))
+    })?;
+    let mut value: Value =
+        serde_json::from_str(&text).map_err(|e| RustedChainError::ParseError(e.to_string()))?;
+    if wrapped {
+        value = value["result"].take();
+    }
+
+    let value = pythonize::pythonize(py, &value)?;
+    if pydantic {
+        Ok(schema.call_method1("model_validate", (value,))?.unbind())
+    } else {
+        Ok(value.unbind())
+    }
+}
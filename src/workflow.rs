@@ -0,0 +1,511 @@
+//! Declarative multi-step workflows: nodes connected by (optionally conditional) edges,
+//! executed in order with a shared state dict.
+
+use crate::client::{self, AnyClient};
+use crate::prompts::PromptTemplate;
+use crate::transforms::OutputTransform;
+use crate::RUNTIME;
+use pyo3::exceptions::{PyRuntimeError, PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyDict, PyList, PyString, PyTuple};
+use std::collections::HashMap;
+use std::time::Instant;
+
+/// Target name that ends the workflow.
+pub const END: &str = "__end__";
+
+const DEFAULT_MAX_STEPS: usize = 25;
+
+enum NodeKind {
+    /// A model (anything with a `run(query)` method); its prompt is rendered from the state.
+    Agent { prompt: Option<Py<PyAny>> },
+    /// Picks the next node from the input instead of following edges.
+    Router { prompt: Option<Py<PyAny>> },
+    /// A tool adapter, called with the state entries matching its parameters.
+    Tool,
+    /// A Python callable receiving the state dict.
+    Function,
+}
+
+struct Node {
+    target: Py<PyAny>,
+    kind: NodeKind,
+    /// Applied to the node's output when it is a string.
+    transform: Option<OutputTransform>,
+}
+
+struct Edge {
+    from: String,
+    to: String,
+    condition: Option<Py<PyAny>>,
+}
+
+impl Node {
+    fn classify(target: &Bound<'_, PyAny>, prompt: Option<Py<PyAny>>) -> PyResult<NodeKind> {
+        if target.is_instance_of::<Router>() {
+            Ok(NodeKind::Router { prompt })
+        } else if target.hasattr("to_dict")? && target.is_callable() {
+            Ok(NodeKind::Tool)
+        } else if target.hasattr("run")? && !target.is_callable() {
+            Ok(NodeKind::Agent { prompt })
+        } else if target.is_callable() {
+            Ok(NodeKind::Function)
+        } else {
+            Err(PyValueError::new_err(
+                "a workflow node must be a model, a tool or a callable",
+            ))
+        }
+    }
+
+    /// Run the node and fold its result into `state`. Returns the branch chosen by a
+    /// router node, which replaces the usual edge lookup.
+    fn execute(
+        &self,
+        py: Python,
+        name: &str,
+        state: &Bound<'_, PyDict>,
+    ) -> PyResult<Option<String>> {
+        let target = self.target.bind(py);
+        let mut output = match &self.kind {
+            NodeKind::Agent { prompt } => {
+                let query = node_input(py, name, prompt.as_ref(), state)?;
+                target.call_method1("run", (query.str()?,))?
+            }
+            NodeKind::Router { prompt } => {
+                let input = node_input(py, name, prompt.as_ref(), state)?;
+                let branch = target.cast::<Router>()?.borrow().route(py, &input)?;
+                // The chosen branch is recorded, but the input flows on as the output.
+                state.set_item(name, &branch)?;
+                return Ok(Some(branch));
+            }
+            NodeKind::Tool => {
+                let schema = target.call_method0("to_dict")?;
+                let kwargs = PyDict::new(py);
+                let properties = schema
+                    .get_item("parameters")
+                    .and_then(|p| p.get_item("properties"));
+                if let Ok(properties) = properties {
+                    for key in properties.try_iter()? {
+                        let key = key?;
+                        if let Some(value) = state.get_item(&key)? {
+                            kwargs.set_item(key, value)?;
+                        }
+                    }
+                }
+                target.call((), Some(&kwargs))?
+            }
+            NodeKind::Function => {
+                let output = target.call1((state,))?;
+                // A dict is an update to the state rather than an output value.
+                if let Ok(update) = output.cast::<PyDict>() {
+                    state.update(update.as_mapping())?;
+                    return Ok(None);
+                }
+                output
+            }
+        };
+        if let (Some(transform), Ok(text)) = (&self.transform, output.extract::<String>()) {
+            output = transform.apply(&text).into_pyobject(py)?.into_any();
+        }
+        state.set_item(name, &output)?;
+        state.set_item("output", output)?;
+        Ok(None)
+    }
+}
+
+/// What a model or router node works on: its rendered prompt, else the previous
+/// output, else `state["input"]`.
+fn node_input<'py>(
+    py: Python<'py>,
+    name: &str,
+    prompt: Option<&Py<PyAny>>,
+    state: &Bound<'py, PyDict>,
+) -> PyResult<Bound<'py, PyAny>> {
+    // A str and a PromptTemplate render the same way.
+    if let Some(template) = prompt {
+        return template.bind(py).call_method("format", (), Some(state));
+    }
+    match state.get_item("output")? {
+        Some(output) => Ok(output),
+        None => state.get_item("input")?.ok_or_else(|| {
+            PyValueError::new_err(format!(
+                "node '{}' has no prompt and the state has no 'input'",
+                name
+            ))
+        }),
+    }
+}
+
+/// Sends input to one of several named branches, chosen by a Python predicate or by a
+/// short classification call to a model.
+#[pyclass]
+pub struct Router {
+    /// Branch names with optional descriptions shown to the classifier.
+    branches: Vec<(String, Option<String>)>,
+    /// One model, or a list of models when racing.
+    model: Option<Py<PyAny>>,
+    predicate: Option<Py<PyAny>>,
+    default: Option<String>,
+    /// Send the classification to every model at once and take the first answer.
+    race: bool,
+}
+
+#[pymethods]
+impl Router {
+    /// `branches` is a list of names or a dict of name -> description. Exactly one of
+    /// `model` (classifies the input) or `predicate` (returns a branch name) is required.
+    /// `default` is used when the classifier answers with no known branch. With
+    /// `race=True`, `model` is a list of models (typically from different providers)
+    /// that all classify the input concurrently; the first successful answer is used
+    /// and the slower requests are cancelled.
+    #[new]
+    #[pyo3(signature = (branches, model=None, predicate=None, default=None, race=false))]
+    fn new(
+        py: Python,
+        branches: &Bound<'_, PyAny>,
+        model: Option<Py<PyAny>>,
+        predicate: Option<Py<PyAny>>,
+        default: Option<String>,
+        race: bool,
+    ) -> PyResult<Self> {
+        let branches: Vec<(String, Option<String>)> = match branches.cast::<PyDict>() {
+            Ok(described) => described
+                .iter()
+                .map(|(name, description)| Ok((name.extract()?, description.extract()?)))
+                .collect::<PyResult<_>>()?,
+            Err(_) => branches
+                .extract::<Vec<String>>()?
+                .into_iter()
+                .map(|name| (name, None))
+                .collect(),
+        };
+        if branches.is_empty() {
+            return Err(PyValueError::new_err("a router needs at least one branch"));
+        }
+        if model.is_some() == predicate.is_some() {
+            return Err(PyValueError::new_err(
+                "pass exactly one of model or predicate",
+            ));
+        }
+        if race {
+            let models = model.as_ref().map(|m| m.bind(py));
+            let racers = match models {
+                Some(m) if m.is_instance_of::<PyList>() || m.is_instance_of::<PyTuple>() => {
+                    m.len()?
+                }
+                _ => 0,
+            };
+            if racers < 2 {
+                return Err(PyValueError::new_err(
+                    "race=True needs a list of at least two models",
+                ));
+            }
+        }
+        if let Some(default) = &default {
+            if !branches.iter().any(|(name, _)| name == default) {
+                return Err(PyValueError::new_err(format!(
+                    "default '{}' is not one of the branches",
+                    default
+                )));
+            }
+        }
+        Ok(Router {
+            branches,
+            model,
+            predicate,
+            default,
+            race,
+        })
+    }
+
+    /// Choose a branch for `input`.
+    fn route(&self, py: Python, input: &Bound<'_, PyAny>) -> PyResult<String> {
+        let answer: String = match (&self.predicate, &self.model) {
+            (Some(predicate), _) => predicate.bind(py).call1((input,))?.extract()?,
+            (None, Some(models)) if self.race => {
+                let clients = models
+                    .bind(py)
+                    .try_iter()?
+                    .map(|model| AnyClient::from_model(py, &model?))
+                    .collect::<PyResult<Vec<_>>>()?;
+                let prompt = self.classification_prompt(&input.str()?.to_string());
+                py.detach(|| RUNTIME.block_on(client::race(&clients, &prompt)))?
+            }
+            (None, Some(model)) => model
+                .bind(py)
+                .call_method1(
+                    "run",
+                    (self.classification_prompt(&input.str()?.to_string()),),
+                )?
+                .extract()?,
+            (None, None) => unreachable!("checked in Router::new"),
+        };
+        self.match_branch(&answer)
+            .or_else(|| self.default.clone())
+            .ok_or_else(|| {
+                PyValueError::new_err(format!(
+                    "router answer '{}' matches none of the branches",
+                    answer.trim()
+                ))
+            })
+    }
+
+    #[getter]
+    fn branches(&self) -> Vec<String> {
+        self.branches.iter().map(|(name, _)| name.clone()).collect()
+    }
+}
+
+impl Router {
+    fn classification_prompt(&self, input: &str) -> String {
+        let options: Vec<String> = self
+            .branches
+            .iter()
+            .map(|(name, description)| match description {
+                Some(description) => format!("- {}: {}", name, description),
+                None => format!("- {}", name),
+            })
+            .collect();
+        format!(
+            "Classify the input into exactly one of these categories:\n{}\n\n\
+             Reply with the category name only.\n\nInput:\n{}",
+            options.join("\n"),
+            input
+        )
+    }
+
+    /// Exact (case-insensitive) match first, then the first branch named in the answer.
+    fn match_branch(&self, answer: &str) -> Option<String> {
+        let answer = answer
+            .trim()
+            .trim_matches(|c: char| c == '"' || c == '\'' || c == '.');
+        let lower = answer.to_lowercase();
+        self.branches
+            .iter()
+            .find(|(name, _)| name.to_lowercase() == lower)
+            .or_else(|| {
+                self.branches
+                    .iter()
+                    .find(|(name, _)| lower.contains(&name.to_lowercase()))
+            })
+            .map(|(name, _)| name.clone())
+    }
+}
+
+/// Result of a workflow run: the final state plus the path taken through the graph.
+#[pyclass(get_all)]
+pub struct WorkflowResult {
+    /// The final state dict.
+    state: Py<PyDict>,
+    /// `state["output"]`: the last node's output, or `None`.
+    output: Py<PyAny>,
+    /// One dict per executed node: `{"node": name, "duration_s": seconds}`, plus
+    /// `"branch"` for router nodes.
+    trace: Py<PyList>,
+}
+
+#[pymethods]
+impl WorkflowResult {
+    fn __repr__(&self, py: Python) -> PyResult<String> {
+        Ok(format!(
+            "WorkflowResult(output={}, steps={})",
+            self.output.bind(py).repr()?,
+            self.trace.bind(py).len()
+        ))
+    }
+}
+
+/// A graph of agents, tools and Python functions executed step by step.
+///
+/// After each node, its outgoing edges are checked in the order they were added and the
+/// first one whose condition passes (or that has none) is followed; a `Router` node
+/// jumps straight to the branch it picks. The run ends at `END` or at a node with no
+/// matching edge.
+#[pyclass]
+pub struct Workflow {
+    nodes: HashMap<String, Node>,
+    edges: Vec<Edge>,
+    entry: Option<String>,
+    max_steps: usize,
+}
+
+#[pymethods]
+impl Workflow {
+    #[new]
+    #[pyo3(signature = (max_steps=DEFAULT_MAX_STEPS))]
+    fn new(max_steps: usize) -> Self {
+        Workflow {
+            nodes: HashMap::new(),
+            edges: Vec::new(),
+            entry: None,
+            max_steps,
+        }
+    }
+
+    /// Add a node. `prompt` is a `str.format` template (or a `PromptTemplate`) rendered
+    /// with the state for model nodes; without it a model receives the previous output (or `state["input"]`).
+    /// `transform` (an `OutputTransform` or transform name) cleans up string outputs.
+    /// The first node added is the entry point unless `set_entry` says otherwise.
+    #[pyo3(signature = (name, node, prompt=None, transform=None))]
+    fn add_node(
+        &mut self,
+        name: String,
+        node: Bound<'_, PyAny>,
+        prompt: Option<Bound<'_, PyAny>>,
+        transform: Option<Bound<'_, PyAny>>,
+    ) -> PyResult<()> {
+        if let Some(prompt) = &prompt {
+            if !prompt.is_instance_of::<PyString>() && !prompt.is_instance_of::<PromptTemplate>() {
+                return Err(PyTypeError::new_err(
+                    "prompt must be a str or a PromptTemplate",
+                ));
+            }
+        }
+        if name == END || name == "input" || name == "output" {
+            return Err(PyValueError::new_err(format!(
+                "'{}' is reserved and cannot name a node",
+                name
+            )));
+        }
+        let kind = Node::classify(&node, prompt.map(Bound::unbind))?;
+        let transform = transform
+            .map(|transform| OutputTransform::from_py(&transform))
+            .transpose()?;
+        self.entry.get_or_insert_with(|| name.clone());
+        self.nodes.insert(
+            name,
+            Node {
+                target: node.unbind(),
+                kind,
+                transform,
+            },
+        );
+        Ok(())
+    }
+
+    /// Connect two nodes. `condition` is called with the state and the edge is taken
+    /// only if it returns something truthy.
+    #[pyo3(signature = (source, target, condition=None))]
+    fn add_edge(&mut self, source: String, target: String, condition: Option<Py<PyAny>>) {
+        self.edges.push(Edge {
+            from: source,
+            to: target,
+            condition,
+        });
+    }
+
+    fn set_entry(&mut self, name: String) {
+        self.entry = Some(name);
+    }
+
+    /// Execute the workflow. `input` is either an initial state dict or a value stored
+    /// as `state["input"]`.
+    fn run(&self, py: Python, input: Bound<'_, PyAny>) -> PyResult<WorkflowResult> {
+        self.validate(py)?;
+        let state = match input.cast::<PyDict>() {
+            Ok(initial) => initial.copy()?,
+            Err(_) => {
+                let state = PyDict::new(py);
+                state.set_item("input", input)?;
+                state
+            }
+        };
+        let trace = PyList::empty(py);
+
+        let mut current = self.entry.clone();
+        let mut steps = 0;
+        while let Some(name) = current.take() {
+            if name == END {
+                break;
+            }
+            if steps == self.max_steps {
+                return Err(PyRuntimeError::new_err(format!(
+                    "workflow exceeded max_steps ({}) without reaching END",
+                    self.max_steps
+                )));
+            }
+            steps += 1;
+
+            let started = Instant::now();
+            let branch = self.nodes[&name].execute(py, &name, &state)?;
+            let step = PyDict::new(py);
+            step.set_item("node", &name)?;
+            step.set_item("duration_s", started.elapsed().as_secs_f64())?;
+            if let Some(branch) = &branch {
+                step.set_item("branch", branch)?;
+            }
+            trace.append(step)?;
+
+            current = match branch {
+                Some(branch) => Some(branch),
+                None => self.next_node(py, &name, &state)?,
+            };
+        }
+
+        let output = state
+            .get_item("output")?
+            .map_or_else(|| py.None(), |o| o.unbind());
+        Ok(WorkflowResult {
+            state: state.unbind(),
+            output,
+            trace: trace.unbind(),
+        })
+    }
+}
+
+impl Workflow {
+    fn validate(&self, py: Python) -> PyResult<()> {
+        let entry = self
+            .entry
+            .as_ref()
+            .ok_or_else(|| PyValueError::new_err("workflow has no nodes"))?;
+        let known = |name: &String| name == END || self.nodes.contains_key(name);
+        if !known(entry) {
+            return Err(PyValueError::new_err(format!(
+                "entry node '{}' does not exist",
+                entry
+            )));
+        }
+        for (name, node) in &self.nodes {
+            if let NodeKind::Router { .. } = node.kind {
+                for branch in node.target.bind(py).cast::<Router>()?.borrow().branches() {
+                    if !known(&branch) {
+                        return Err(PyValueError::new_err(format!(
+                            "router '{}' has unknown branch '{}'",
+                            name, branch
+                        )));
+                    }
+                }
+            }
+        }
+        for edge in &self.edges {
+            for name in [&edge.from, &edge.to] {
+                if !known(name) {
+                    return Err(PyValueError::new_err(format!(
+                        "edge {} -> {} refers to unknown node '{}'",
+                        edge.from, edge.to, name
+                    )));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn next_node(
+        &self,
+        py: Python,
+        from: &str,
+        state: &Bound<'_, PyDict>,
+    ) -> PyResult<Option<String>> {
+        for edge in self.edges.iter().filter(|e| e.from == from) {
+            let taken = match &edge.condition {
+                Some(condition) => condition.bind(py).call1((state,))?.is_truthy()?,
+                None => true,
+            };
+            if taken {
+                return Ok(Some(edge.to.clone()));
+            }
+        }
+        Ok(None)
+    }
+}